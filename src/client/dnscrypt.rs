@@ -0,0 +1,398 @@
+//! An upstream resolver spoken to over DNSCrypt. The provider's
+//! certificates are fetched in the clear and checked against its public
+//! key; queries are boxed under the best of them and sent over UDP, and
+//! again over TCP when the answer comes back truncated.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+use crypto::{SecretBox, random_bytes, x25519_keypair};
+use dnscrypt::{Certificate, HALF_NONCE_LEN, MAX_QUERY_LEN, MIN_QUERY_LEN, RESOLVER_MAGIC,
+               RESPONSE_HEADER_LEN, pad, padded_len, unpad};
+use protocol::{FrameReader, Header, HeaderMut, MAX_FRAME, MessageCursor, Name, QuestionMut, RData,
+               Record, tcp_frame};
+use protocol::rdata::{CLASS_IN, TYPE_TXT};
+use tsig::now;
+use super::Upstream;
+use super::stream::QUERY_TIMEOUT_MS;
+use super::udp::exchange;
+
+/// The port DNSCrypt is usually served on.
+pub const DNSCRYPT_PORT: u16 = 443;
+
+/// The client side of DNSCrypt for one provider: its certificate, the key
+/// pair queries are boxed with, and how far UDP queries are padded.
+/// Sending and receiving is up to the caller.
+pub struct DnscryptClient {
+    provider: Name,
+    provider_key: Vec<u8>,
+    secret: Vec<u8>,
+    public: Vec<u8>,
+    certificate: Option<(Certificate, SecretBox)>,
+    min_query_len: usize,
+}
+
+impl DnscryptClient {
+    /// A client for `provider`, whose certificates must be signed by the
+    /// Ed25519 `provider_key`, with a fresh key pair of its own.
+    pub fn new(provider: Name, provider_key: &[u8]) -> DnscryptClient {
+        let (secret, public) = x25519_keypair();
+        DnscryptClient {
+            provider: provider,
+            provider_key: provider_key.to_vec(),
+            secret: secret,
+            public: public,
+            certificate: None,
+            min_query_len: MIN_QUERY_LEN,
+        }
+    }
+
+    /// A query in the clear for the provider's certificates.
+    pub fn certificate_query(&self, id: u16) -> Vec<u8> {
+        let mut message = vec![0u8; 512];
+        let mut idx = MessageCursor::new(message.len());
+        HeaderMut::at(&mut idx, &mut message[..]).unwrap().make_query(id).set_qd(1);
+        QuestionMut::at(&mut idx,
+                        &mut message,
+                        &self.provider.segments(),
+                        TYPE_TXT,
+                        CLASS_IN)
+            .unwrap();
+        message.truncate(idx.tell());
+        message
+    }
+
+    /// Takes the answer to `certificate_query` and picks the certificate to
+    /// use: of those signed by the provider and good at `time`, the one for
+    /// the highest encryption system version, then the highest serial.
+    /// Returns false, keeping the certificate in use, if none will do.
+    pub fn certificates_received(&mut self, response: &[u8], time: u64) -> bool {
+        let mut best: Option<Certificate> = None;
+        for resource in Header::at(response).answers() {
+            let strings = match Record::from_resource(response, &resource) {
+                Some(Record { rdata: RData::Txt(strings), .. }) => strings,
+                _ => continue,
+            };
+            let data: Vec<u8> = strings.concat();
+            let certificate = match Certificate::parse(&data, &self.provider_key) {
+                Some(certificate) => certificate,
+                None => continue,
+            };
+            let better = best.as_ref().map_or(true, |best| {
+                (certificate.es_version(), certificate.serial) > (best.es_version(), best.serial)
+            });
+            if certificate.is_current(time) && better {
+                best = Some(certificate);
+            }
+        }
+        let certificate = match best {
+            Some(certificate) => certificate,
+            None => return false,
+        };
+        match SecretBox::between(certificate.cipher, &self.secret, &certificate.resolver_key) {
+            Some(secret_box) => {
+                self.certificate = Some((certificate, secret_box));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The certificate in use.
+    pub fn certificate(&self) -> Option<&Certificate> {
+        self.certificate.as_ref().map(|&(ref certificate, _)| certificate)
+    }
+
+    /// Whether there is a certificate good at `time`.
+    pub fn has_certificate(&self, time: u64) -> bool {
+        self.certificate().map_or(false, |certificate| certificate.is_current(time))
+    }
+
+    /// What UDP queries are padded to at least.
+    pub fn min_query_len(&self) -> usize {
+        self.min_query_len
+    }
+
+    /// Boxes a query for sending over UDP, or with less padding over TCP.
+    /// Returns the packet and the nonce its response must carry, or None
+    /// without a certificate.
+    pub fn seal(&self, query: &[u8], tcp: bool) -> Option<(Vec<u8>, Vec<u8>)> {
+        let (certificate, secret_box) = match self.certificate {
+            Some((ref certificate, ref secret_box)) => (certificate, secret_box),
+            None => return None,
+        };
+        let min_len = if tcp { 0 } else { self.min_query_len };
+        let mut nonce = random_bytes(HALF_NONCE_LEN);
+        nonce.resize(2 * HALF_NONCE_LEN, 0);
+        let mut packet = certificate.client_magic.clone();
+        packet.extend_from_slice(&self.public);
+        packet.extend_from_slice(&nonce[..HALF_NONCE_LEN]);
+        packet.extend(secret_box.seal(&nonce, &pad(query, padded_len(query.len(), min_len))));
+        Some((packet, nonce))
+    }
+
+    /// Opens the response to the query sealed with `nonce`, or returns None
+    /// if the packet is not that.
+    pub fn open(&self, packet: &[u8], nonce: &[u8]) -> Option<Vec<u8>> {
+        let secret_box = match self.certificate {
+            Some((_, ref secret_box)) => secret_box,
+            None => return None,
+        };
+        if packet.len() < RESPONSE_HEADER_LEN || !packet.starts_with(RESOLVER_MAGIC) ||
+           &packet[8..8 + HALF_NONCE_LEN] != &nonce[..HALF_NONCE_LEN] {
+            return None;
+        }
+        secret_box.open(&packet[8..RESPONSE_HEADER_LEN], &packet[RESPONSE_HEADER_LEN..])
+                  .and_then(|padded| unpad(&padded).map(|response| response.to_vec()))
+    }
+
+    /// Notes an answer that came back truncated: UDP queries are padded 64
+    /// octets longer from now on, so longer answers fit.
+    pub fn truncated(&mut self) {
+        if self.min_query_len < MAX_QUERY_LEN {
+            self.min_query_len += 64;
+        }
+    }
+}
+
+/// A DNSCrypt resolver to send queries to.
+pub struct DnscryptUpstream {
+    address: SocketAddr,
+    client: DnscryptClient,
+    timeout: Duration,
+}
+
+impl DnscryptUpstream {
+    /// The resolver at `address` for `provider`, whose certificates must be
+    /// signed by `provider_key`.
+    pub fn new(address: SocketAddr, provider: Name, provider_key: &[u8]) -> DnscryptUpstream {
+        DnscryptUpstream {
+            address: address,
+            client: DnscryptClient::new(provider, provider_key),
+            timeout: Duration::from_millis(QUERY_TIMEOUT_MS),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn client(&self) -> &DnscryptClient {
+        &self.client
+    }
+
+    /// Sends one query and returns its response, fetching the certificates
+    /// first if there is none good now.
+    pub fn query(&mut self, query: &[u8]) -> io::Result<Vec<u8>> {
+        let time = now();
+        if !self.client.has_certificate(time) {
+            let random = random_bytes(2);
+            let id = (random[0] as u16) << 8 | random[1] as u16;
+            let certificate_query = self.client.certificate_query(id);
+            let response = try!(exchange(&self.address, &certificate_query, self.timeout));
+            if !self.client.certificates_received(&response, time) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "no good certificate"));
+            }
+        }
+        let response = try!(self.query_udp(query));
+        if !Header::at(&response[..]).is_truncated() {
+            return Ok(response);
+        }
+        self.client.truncated();
+        self.query_tcp(query)
+    }
+
+    fn query_udp(&self, query: &[u8]) -> io::Result<Vec<u8>> {
+        let (packet, nonce) = self.client.seal(query, false).unwrap();
+        let local = match self.address {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = try!(UdpSocket::bind(local));
+        try!(socket.send_to(&packet, self.address));
+        let deadline = Instant::now() + self.timeout;
+        let mut buffer = vec![0u8; MAX_FRAME];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "no answer from server"));
+            }
+            try!(socket.set_read_timeout(Some(deadline - now)));
+            let (n, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                              e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            };
+            if from == self.address {
+                if let Some(response) = self.client.open(&buffer[..n], &nonce) {
+                    return Ok(response);
+                }
+            }
+        }
+    }
+
+    fn query_tcp(&self, query: &[u8]) -> io::Result<Vec<u8>> {
+        let (packet, nonce) = self.client.seal(query, true).unwrap();
+        let frame = match tcp_frame(&packet) {
+            Some(frame) => frame,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "query too long")),
+        };
+        let mut stream = try!(TcpStream::connect(self.address));
+        try!(stream.set_read_timeout(Some(self.timeout)));
+        try!(stream.set_write_timeout(Some(self.timeout)));
+        try!(stream.write_all(&frame));
+        let mut reader = FrameReader::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            if let Some(packet) = reader.next_frame() {
+                return match self.client.open(&packet, &nonce) {
+                    Some(response) => Ok(response),
+                    None => Err(io::Error::new(io::ErrorKind::InvalidData, "bad response")),
+                };
+            }
+            let n = try!(stream.read(&mut buffer));
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+            }
+            reader.push(&buffer[..n]);
+        }
+    }
+}
+
+
+impl Upstream for DnscryptUpstream {
+    /// Sends the queries one after the other.
+    fn query_all(&mut self, queries: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>> {
+        queries.iter().map(|query| self.query(query)).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, UdpSocket};
+    use std::thread;
+    use crypto::Ed25519PrivateKey;
+    use dnscrypt::{MIN_QUERY_LEN, QUERY_HEADER_LEN, max_response};
+    use protocol::{FrameReader, HeaderMut, Name, tcp_frame};
+    use server::{DnscryptRequest, DnscryptServer};
+    use test_util::{NOW, query};
+    use tsig::now;
+
+    fn response(query: &[u8], truncated: bool) -> Vec<u8> {
+        let mut response = query.to_vec();
+        HeaderMut::at_raw(&mut response[..]).set_qr(true).set_tc(truncated);
+        response
+    }
+
+    #[test]
+    fn udp_and_tcp() {
+        let provider = Name::parse("2.dnscrypt-cert.example.", None).unwrap();
+        let key = Ed25519PrivateKey::new(&[7; 32]).unwrap();
+        let server = DnscryptServer::new(provider.clone(), key, now());
+        let provider_key = server.public_key();
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = tcp.local_addr().unwrap();
+        let udp = UdpSocket::bind(address).unwrap();
+        let responder = thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            let mut lengths = Vec::new();
+            // The certificate query, then three queries, the second of them
+            // answered truncated.
+            for i in 0..4 {
+                let (n, client) = udp.recv_from(&mut buffer).unwrap();
+                lengths.push(n);
+                let packet = match server.received(&buffer[..n]) {
+                    Some(DnscryptRequest::Certificates(response)) => response,
+                    Some(DnscryptRequest::Query(query, session)) => {
+                        let sealed = session.seal(&response(&query, i == 2));
+                        assert!(sealed.len() <= n && n - QUERY_HEADER_LEN - 16 >= 256);
+                        assert!(query.len() <= max_response(n));
+                        sealed
+                    }
+                    None => panic!("dropped"),
+                };
+                udp.send_to(&packet, client).unwrap();
+                if i != 2 {
+                    continue;
+                }
+                let (mut stream, _) = tcp.accept().unwrap();
+                let mut reader = FrameReader::new();
+                let frame = loop {
+                    if let Some(frame) = reader.next_frame() {
+                        break frame;
+                    }
+                    let n = stream.read(&mut buffer).unwrap();
+                    reader.push(&buffer[..n]);
+                };
+                lengths.push(frame.len());
+                let packet = match server.received(&frame) {
+                    Some(DnscryptRequest::Query(query, session)) => {
+                        session.seal(&response(&query, false))
+                    }
+                    _ => panic!("not opened"),
+                };
+                stream.write_all(&tcp_frame(&packet).unwrap()).unwrap();
+            }
+            lengths
+        });
+
+        let mut upstream = DnscryptUpstream::new(address, provider, &provider_key);
+        assert!(upstream.client().certificate().is_none());
+        let message = query(1, "example.com.", 1);
+        assert_eq!(response(&message, false), upstream.query(&message).unwrap());
+        assert_eq!(2, upstream.client().certificate().unwrap().es_version());
+        let message = query(2, "example.com.", 1);
+        assert_eq!(response(&message, false), upstream.query(&message).unwrap());
+        assert_eq!(MIN_QUERY_LEN + 64, upstream.client().min_query_len());
+        let message = query(3, "example.com.", 1);
+        assert_eq!(response(&message, false), upstream.query(&message).unwrap());
+
+        // Queries are padded to 256 octets over UDP, then 320 after the
+        // truncated answer, and only to 64 over TCP.
+        let padding = QUERY_HEADER_LEN + 16;
+        let lengths = responder.join().unwrap();
+        assert_eq!(vec![padding + 256, padding + 256, padding + 64, padding + 320],
+                   lengths[1..].to_vec());
+    }
+
+    #[test]
+    fn certificates() {
+        let provider = Name::parse("2.dnscrypt-cert.example.", None).unwrap();
+        let key = Ed25519PrivateKey::new(&[7; 32]).unwrap();
+        let server = DnscryptServer::new(provider.clone(), key, NOW);
+        let answer = match server.received(&DnscryptClient::new(provider.clone(), &[0; 32])
+                                                .certificate_query(1)) {
+            Some(DnscryptRequest::Certificates(response)) => response,
+            _ => panic!("no certificates"),
+        };
+
+        // Signed by some other key.
+        let mut client = DnscryptClient::new(provider.clone(), &[0; 32]);
+        assert!(!client.certificates_received(&answer, NOW));
+        assert!(client.seal(&query(1, "example.com.", 1), false).is_none());
+
+        let mut client = DnscryptClient::new(provider, &server.public_key());
+        assert!(!client.certificates_received(&answer, NOW - 1));
+        assert!(client.certificates_received(&answer, NOW));
+        assert!(client.has_certificate(NOW) && !client.has_certificate(NOW + 86401));
+        let (packet, nonce) = client.seal(&query(1, "example.com.", 1), false).unwrap();
+        let session = match server.received(&packet) {
+            Some(DnscryptRequest::Query(_, session)) => session,
+            _ => panic!("not opened"),
+        };
+        let sealed = session.seal(b"response");
+        assert_eq!(Some(b"response".to_vec()), client.open(&sealed, &nonce));
+        let (_, other_nonce) = client.seal(&query(1, "example.com.", 1), false).unwrap();
+        assert_eq!(None, client.open(&sealed, &other_nonce));
+
+        for _ in 0..20 {
+            client.truncated();
+        }
+        assert_eq!(MAX_QUERY_LEN, client.min_query_len());
+    }
+}
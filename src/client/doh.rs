@@ -0,0 +1,646 @@
+//! The client side of DNS over HTTPS (RFC 8484), over HTTP/2, so queries
+//! share one connection and are answered in any order. Each is POSTed with
+//! its ID set to zero, as the RFC asks for the sake of HTTP caches, and the
+//! ID is put back in its response.
+//!
+//! Given an Oblivious DoH target, the server is taken for a proxy: queries
+//! are sealed for the target and POSTed with its host and path, and their
+//! responses opened (RFC 9230).
+//!
+//! `DohClient` only keeps the books; `DohUpstream` runs it over a blocking
+//! connection kept open for query after query.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use http::{Http2Connection, HttpError, Request, Response};
+use odoh::{ODOH_MESSAGE, OdohConfig, OdohQuery};
+use protocol::{Header, HeaderMut};
+use server::{DNS_MESSAGE, DOH_PATH};
+use tls::{ClientConfig, TlsConnection, TlsError};
+use super::Upstream;
+use super::stream::{IDLE_TIMEOUT_MS, QUERY_TIMEOUT_MS};
+
+#[derive(Debug)]
+struct Outstanding {
+    query: Vec<u8>,
+    // None until the server will take another stream.
+    stream: Option<u32>,
+    // What opens the response, if the query went to a target sealed.
+    sealed: Option<OdohQuery>,
+    deadline: Instant,
+}
+
+/// An Oblivious DoH target, reached through the server as a proxy.
+#[derive(Clone, Debug)]
+struct Target {
+    host: String,
+    path: String,
+    config: OdohConfig,
+}
+
+// The DNS message in a response, if it is one, opened if it was sealed.
+fn dns_message(response: Response, sealed: Option<&OdohQuery>) -> Option<Vec<u8>> {
+    let media_type = response.header("content-type")
+                             .map(|value| value.split(';').next().unwrap().trim().to_string());
+    let expected = if sealed.is_some() { ODOH_MESSAGE } else { DNS_MESSAGE };
+    if response.status != 200 || media_type.as_ref().map(|t| &t[..]) != Some(expected) {
+        return None;
+    }
+    let message = match sealed {
+        Some(sealed) => {
+            match sealed.open_response(&response.body) {
+                Some(message) => message,
+                None => return None,
+            }
+        }
+        None => response.body,
+    };
+    let header = Header::at(&message[..]);
+    if message.len() < header.end_offset() || !header.is_response() {
+        return None;
+    }
+    Some(message)
+}
+
+// Percent-encodes all but the unreserved characters of RFC 3986.
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Queries sent on one connection and not yet answered.
+#[derive(Debug)]
+pub struct DohClient {
+    tls: TlsConnection,
+    http: Http2Connection,
+    authority: String,
+    path: String,
+    target: Option<Target>,
+    outstanding: Vec<Outstanding>,
+    timeout: Duration,
+    idle_timeout: Duration,
+    last_active: Instant,
+}
+
+impl DohClient {
+    /// A connection to the server `name`, whose TLS `config` should offer
+    /// `h2` through ALPN, taking queries at `path`.
+    pub fn new(config: Arc<ClientConfig>,
+               name: &str,
+               path: &str,
+               now: Instant)
+        -> Result<DohClient, TlsError> {
+        let mut client = DohClient {
+            tls: try!(TlsConnection::client(config, name)),
+            http: Http2Connection::client(),
+            authority: name.to_string(),
+            path: path.to_string(),
+            target: None,
+            outstanding: Vec::new(),
+            timeout: Duration::from_millis(QUERY_TIMEOUT_MS),
+            idle_timeout: Duration::from_millis(IDLE_TIMEOUT_MS),
+            last_active: now,
+        };
+        client.flush();
+        Ok(client)
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn set_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Makes the server an Oblivious DoH proxy, through which queries go to
+    /// the target `host` at `path`, sealed for the target's `config`.
+    pub fn set_odoh_target(&mut self, host: &str, path: &str, config: OdohConfig) -> &mut Self {
+        self.target = Some(Target {
+            host: host.to_string(),
+            path: path.to_string(),
+            config: config,
+        });
+        self
+    }
+
+    /// Queues a query. Returns false, queueing nothing, if it is too short
+    /// to be one or the server is taking no more.
+    pub fn query(&mut self, query: &[u8], now: Instant) -> bool {
+        if query.len() < Header::at(query).end_offset() {
+            return false;
+        }
+        if self.http.is_closed() {
+            return false;
+        }
+        self.outstanding.push(Outstanding {
+            query: query.to_vec(),
+            stream: None,
+            sealed: None,
+            deadline: now + self.timeout,
+        });
+        self.last_active = now;
+        self.start();
+        self.flush();
+        true
+    }
+
+    // Sends the queries waiting for a stream, as many as the server takes.
+    fn start(&mut self) {
+        for outstanding in self.outstanding.iter_mut().filter(|o| o.stream.is_none()) {
+            let mut query = outstanding.query.clone();
+            HeaderMut::at_raw(&mut query[..]).set_id(0);
+            let (path, media_type, body) = match self.target {
+                Some(ref target) => {
+                    let (message, sealed) = match target.config.seal_query(&query) {
+                        Some(sealed) => sealed,
+                        None => break,
+                    };
+                    outstanding.sealed = Some(sealed);
+                    (format!("{}?targethost={}&targetpath={}",
+                             self.path,
+                             percent_encode(&target.host),
+                             percent_encode(&target.path)),
+                     ODOH_MESSAGE,
+                     message)
+                }
+                None => (self.path.clone(), DNS_MESSAGE, query),
+            };
+            let mut request = Request::new("POST", &path);
+            request.authority = Some(self.authority.clone());
+            request.headers.push(("content-type".to_string(), media_type.to_string()));
+            request.headers.push(("accept".to_string(), media_type.to_string()));
+            request.body = body;
+            outstanding.stream = self.http.request(&request);
+            if outstanding.stream.is_none() {
+                break;
+            }
+        }
+    }
+
+    // Moves what HTTP has to send into TLS.
+    fn flush(&mut self) {
+        let output = self.http.pending().to_vec();
+        self.http.written(output.len());
+        if !output.is_empty() {
+            self.tls.write(&output);
+        }
+    }
+
+    /// Takes octets read from the server and returns the queries it is
+    /// done with, each with its response, or None if the server failed it.
+    /// An error ends the connection.
+    pub fn received(&mut self,
+                    data: &[u8],
+                    now: Instant)
+        -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>, HttpError> {
+        self.last_active = now;
+        try!(self.tls.received(data));
+        let plaintext = self.tls.read();
+        let responses = self.http.received_responses(&plaintext);
+        self.flush();
+        let mut done = Vec::new();
+        for (stream, response) in try!(responses) {
+            let at = match self.outstanding.iter().position(|o| o.stream == Some(stream)) {
+                Some(at) => at,
+                None => continue,
+            };
+            let Outstanding { query, sealed, .. } = self.outstanding.remove(at);
+            let message = dns_message(response, sealed.as_ref()).map(|mut message| {
+                let id = Header::at(&query[..]).id().unwrap();
+                HeaderMut::at_raw(&mut message[..]).set_id(id);
+                message
+            });
+            done.push((query, message));
+        }
+        // Streams the server reset or never took.
+        let http = &self.http;
+        let (failed, open): (Vec<Outstanding>, Vec<Outstanding>) =
+            self.outstanding
+                .drain(..)
+                .partition(|o| o.stream.map_or(false, |s| !http.is_open(s)));
+        self.outstanding = open;
+        done.extend(failed.into_iter().map(|o| (o.query, None)));
+        self.start();
+        self.flush();
+        // If nothing is in flight, the server will never take the rest.
+        if self.outstanding.iter().all(|o| o.stream.is_none()) {
+            done.extend(self.outstanding.drain(..).map(|o| (o.query, None)));
+        }
+        Ok(done)
+    }
+
+    /// The octets waiting to be written to the server.
+    pub fn pending(&self) -> &[u8] {
+        self.tls.pending()
+    }
+
+    pub fn written(&mut self, n: usize) {
+        self.tls.written(n);
+    }
+
+    pub fn tls(&self) -> &TlsConnection {
+        &self.tls
+    }
+
+    /// How many queries are waiting for an answer.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Gives up on the queries that have waited past their timeout and
+    /// returns them. Their streams are cancelled.
+    pub fn expired(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let (expired, waiting): (Vec<Outstanding>, Vec<Outstanding>) =
+            self.outstanding.drain(..).partition(|o| o.deadline <= now);
+        self.outstanding = waiting;
+        for stream in expired.iter().filter_map(|o| o.stream) {
+            self.http.cancel(stream);
+        }
+        self.flush();
+        expired.into_iter().map(|o| o.query).collect()
+    }
+
+    /// When `expired` or `is_idle` next has something to say.
+    pub fn deadline(&self) -> Instant {
+        match self.outstanding.iter().map(|o| o.deadline).min() {
+            Some(deadline) => deadline,
+            None => self.last_active + self.idle_timeout,
+        }
+    }
+
+    /// Whether the connection has had nothing outstanding for the idle
+    /// timeout, and may be closed.
+    pub fn is_idle(&self, now: Instant) -> bool {
+        self.outstanding.is_empty() && self.pending().is_empty() &&
+        now >= self.last_active + self.idle_timeout
+    }
+
+    /// Whether the server has gone away or the connection has failed, so
+    /// no more queries can be sent on it.
+    pub fn is_closed(&self) -> bool {
+        self.http.is_closed() || self.tls.is_closed()
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "no answer from server")
+}
+
+// Sends `queries` on a connection and returns the responses in the order
+// of the queries.
+fn exchange_doh(stream: &mut TcpStream,
+                client: &mut DohClient,
+                queries: &[Vec<u8>])
+    -> io::Result<Vec<Vec<u8>>> {
+    for query in queries {
+        if !client.query(query, Instant::now()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "query cannot be sent"));
+        }
+    }
+    let mut responses: Vec<Option<Vec<u8>>> = vec![None; queries.len()];
+    let mut buffer = [0u8; 4096];
+    while client.outstanding() > 0 {
+        if !client.pending().is_empty() {
+            try!(stream.write_all(client.pending()));
+            let n = client.pending().len();
+            client.written(n);
+        }
+        if !client.expired(Instant::now()).is_empty() {
+            return Err(timed_out());
+        }
+        let n = match stream.read(&mut buffer) {
+            Ok(0) => {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                          "connection closed with queries unanswered"))
+            }
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Err(timed_out()),
+            Err(e) => return Err(e),
+        };
+        let done = match client.received(&buffer[..n], Instant::now()) {
+            Ok(done) => done,
+            Err(e) => {
+                let _ = stream.write_all(client.pending());
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+        };
+        for (query, response) in done {
+            let response = match response {
+                Some(response) => response,
+                None => return Err(io::Error::new(io::ErrorKind::Other, "query failed")),
+            };
+            let at = (0..queries.len()).find(|&i| responses[i].is_none() && queries[i] == query);
+            if let Some(i) = at {
+                responses[i] = Some(response);
+            }
+        }
+    }
+    // What is left to say, such as acknowledging the server's settings.
+    if !client.pending().is_empty() {
+        try!(stream.write_all(client.pending()));
+        let n = client.pending().len();
+        client.written(n);
+    }
+    Ok(responses.into_iter().map(|r| r.unwrap()).collect())
+}
+
+/// A DNS over HTTPS server to send queries to.
+#[derive(Debug)]
+pub struct DohUpstream {
+    address: SocketAddr,
+    name: String,
+    path: String,
+    target: Option<Target>,
+    config: Arc<ClientConfig>,
+    timeout: Duration,
+    connection: Option<(TcpStream, DohClient)>,
+}
+
+impl DohUpstream {
+    /// The server at `address`, whose certificate must be good for `name`
+    /// by `config`, which should offer `h2` through ALPN. Queries go to
+    /// `/dns-query` unless set otherwise.
+    pub fn new(address: SocketAddr, name: &str, config: Arc<ClientConfig>) -> DohUpstream {
+        DohUpstream {
+            address: address,
+            name: name.to_string(),
+            path: DOH_PATH.to_string(),
+            target: None,
+            config: config,
+            timeout: Duration::from_millis(QUERY_TIMEOUT_MS),
+            connection: None,
+        }
+    }
+
+    /// The path of the URI template (RFC 8484 Section 3), without its
+    /// `{?dns}`.
+    pub fn set_path(&mut self, path: &str) -> &mut Self {
+        self.path = path.to_string();
+        self
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends queries through the server, as a proxy, to an Oblivious DoH
+    /// target, as `DohClient::set_odoh_target`.
+    pub fn set_odoh_target(&mut self, host: &str, path: &str, config: OdohConfig) -> &mut Self {
+        self.target = Some(Target {
+            host: host.to_string(),
+            path: path.to_string(),
+            config: config,
+        });
+        self
+    }
+
+    /// Sends one query and returns its response.
+    pub fn query(&mut self, query: &[u8]) -> io::Result<Vec<u8>> {
+        self.query_all(&[query.to_vec()]).map(|mut responses| responses.remove(0))
+    }
+
+    /// Sends `queries` all at once, each on its own stream, and returns the
+    /// responses in the order of the queries. An open connection is tried
+    /// first; if it fails, the queries are sent again on a new one.
+    pub fn query_all(&mut self, queries: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>> {
+        if let Some((mut stream, mut client)) = self.connection.take() {
+            if !client.is_idle(Instant::now()) && !client.is_closed() {
+                if let Ok(responses) = exchange_doh(&mut stream, &mut client, queries) {
+                    self.connection = Some((stream, client));
+                    return Ok(responses);
+                }
+            }
+        }
+        let mut stream = try!(TcpStream::connect(self.address));
+        try!(stream.set_read_timeout(Some(self.timeout)));
+        try!(stream.set_write_timeout(Some(self.timeout)));
+        let mut client = try!(DohClient::new(self.config.clone(),
+                                             &self.name,
+                                             &self.path,
+                                             Instant::now())
+                                  .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)));
+        client.set_timeout(self.timeout);
+        if let Some(ref target) = self.target {
+            client.set_odoh_target(&target.host, &target.path, target.config.clone());
+        }
+        let responses = try!(exchange_doh(&mut stream, &mut client, queries));
+        self.connection = Some((stream, client));
+        Ok(responses)
+    }
+
+    /// Whether a connection is open.
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// Whether the open connection resumed an earlier session.
+    pub fn is_resumed(&self) -> bool {
+        match self.connection {
+            Some((_, ref client)) => client.tls().is_resumed(),
+            None => false,
+        }
+    }
+}
+
+impl Upstream for DohUpstream {
+    fn query_all(&mut self, queries: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>> {
+        DohUpstream::query_all(self, queries)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Instant;
+    use http::{Http2Connection, Response};
+    use odoh::{ODOH_MESSAGE, OdohKey};
+    use protocol::{Header, HeaderMut};
+    use server::DohConnection;
+    use test_util::query;
+    use tls::{ClientConfig, ServerConfig, TlsConnection};
+
+    fn response(query: &[u8]) -> Vec<u8> {
+        let mut response = query.to_vec();
+        HeaderMut::at_raw(&mut response[..]).set_qr(true);
+        response
+    }
+
+    // Answers `count` queries on the next connection, the last first, then
+    // hangs up. Queries for refused.example get an HTTP error. Returns
+    // whether every ID was zero and whether the session was resumed.
+    fn serve(listener: &TcpListener, config: &Arc<ServerConfig>, count: usize) -> (bool, bool) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut connection = DohConnection::with_tls(config.clone(), Instant::now());
+        let mut queries = Vec::new();
+        let mut buffer = [0u8; 4096];
+        while queries.len() < count {
+            let n = stream.read(&mut buffer).unwrap();
+            queries.extend(connection.received(&buffer[..n], Instant::now()).unwrap());
+            stream.write_all(connection.pending()).unwrap();
+            let n = connection.pending().len();
+            connection.written(n, Instant::now());
+        }
+        let zeroed = queries.iter().all(|&(_, ref query)| Header::at(&query[..]).id() == Some(0));
+        for &(handle, ref query) in queries.iter().rev() {
+            if query.windows(7).any(|w| w == b"refused") {
+                connection.refuse(handle, 500);
+            } else {
+                connection.respond(handle, &response(query));
+            }
+        }
+        stream.write_all(connection.pending()).unwrap();
+        // Let the client read it all before the connection closes.
+        let _ = stream.read(&mut buffer);
+        (zeroed, connection.tls().unwrap().is_resumed())
+    }
+
+    // An Oblivious DoH proxy for one request, taken over TLS and HTTP/2 and
+    // forwarded over HTTP/1.1 to the target at `target`. Returns the path
+    // and body of the request.
+    fn proxy(listener: &TcpListener,
+             config: &Arc<ServerConfig>,
+             target: SocketAddr)
+             -> (String, Vec<u8>) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut tls = TlsConnection::server(config.clone());
+        let mut http = Http2Connection::server();
+        let mut buffer = [0u8; 4096];
+        let mut requests = Vec::new();
+        while requests.is_empty() {
+            let n = stream.read(&mut buffer).unwrap();
+            tls.received(&buffer[..n]).unwrap();
+            requests = http.received(&tls.read()).unwrap();
+            tls.write(http.pending());
+            let n = http.pending().len();
+            http.written(n);
+            stream.write_all(tls.pending()).unwrap();
+            let n = tls.pending().len();
+            tls.written(n);
+        }
+        let (handle, request) = requests.remove(0);
+
+        let mut upstream = TcpStream::connect(target).unwrap();
+        let head = format!("POST /dns-query HTTP/1.1\r\nhost: target.example\r\n\
+                            content-type: {}\r\ncontent-length: {}\r\n\r\n",
+                           ODOH_MESSAGE,
+                           request.body.len());
+        upstream.write_all(head.as_bytes()).unwrap();
+        upstream.write_all(&request.body).unwrap();
+        let mut forwarded = Vec::new();
+        upstream.read_to_end(&mut forwarded).unwrap();
+        let at = forwarded.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        assert!(forwarded.starts_with(b"HTTP/1.1 200 OK\r\n"));
+
+        let mut response = Response::new(200);
+        response.add_header("content-type", ODOH_MESSAGE);
+        response.body = forwarded[at + 4..].to_vec();
+        http.respond(handle, &response);
+        tls.write(http.pending());
+        stream.write_all(tls.pending()).unwrap();
+        let _ = stream.read(&mut buffer);
+        (request.path, request.body)
+    }
+
+    // An Oblivious DoH target for one query, over HTTP/1.1 without TLS.
+    fn target(listener: &TcpListener, key: Arc<OdohKey>) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut connection = DohConnection::new(Instant::now());
+        connection.set_odoh_key(key);
+        let mut queries = Vec::new();
+        let mut buffer = [0u8; 4096];
+        while queries.is_empty() {
+            let n = stream.read(&mut buffer).unwrap();
+            queries = connection.received(&buffer[..n], Instant::now()).unwrap();
+        }
+        connection.respond(queries[0].0, &response(&queries[0].1));
+        stream.write_all(connection.pending()).unwrap();
+    }
+
+    fn client_config() -> Arc<ClientConfig> {
+        let mut config = ClientConfig::new();
+        config.load_roots(Path::new("testdata/tls-ca.pem")).unwrap();
+        config.add_alpn(b"h2");
+        Arc::new(config)
+    }
+
+    #[test]
+    fn multiplexing_and_reuse() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut config = ServerConfig::load(Path::new("testdata/tls-server.pem"),
+                                            Path::new("testdata/tls-server.key"))
+                             .unwrap();
+        config.add_alpn(b"h2");
+        let config = Arc::new(config);
+        let server = thread::spawn(move || {
+            (serve(&listener, &config, 1),
+             serve(&listener, &config, 3),
+             serve(&listener, &config, 1))
+        });
+
+        let mut upstream = DohUpstream::new(address, "dns.example", client_config());
+        let first = query(1, "example.com.", 1);
+        assert_eq!(response(&first), upstream.query(&first).unwrap());
+        assert!(upstream.is_connected() && !upstream.is_resumed());
+
+        // The server hung up, so these go on a new connection, answered in
+        // reverse and matched up by stream.
+        let queries = vec![query(2, "a.example.", 1),
+                           query(3, "b.example.", 1),
+                           query(2, "c.example.", 1)];
+        assert_eq!(queries.iter().map(|q| response(q)).collect::<Vec<_>>(),
+                   upstream.query_all(&queries).unwrap());
+        assert!(upstream.is_resumed());
+
+        assert!(upstream.query(&query(4, "refused.example.", 1)).is_err());
+        assert_eq!(((true, false), (true, true), (true, true)), server.join().unwrap());
+    }
+
+    #[test]
+    fn oblivious_through_proxy() {
+        let key = Arc::new(OdohKey::generate());
+        let config = key.config().clone();
+        let target_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target_address = target_listener.local_addr().unwrap();
+        let target = thread::spawn(move || self::target(&target_listener, key));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut tls_config = ServerConfig::load(Path::new("testdata/tls-server.pem"),
+                                                Path::new("testdata/tls-server.key"))
+                                 .unwrap();
+        tls_config.add_alpn(b"h2");
+        let tls_config = Arc::new(tls_config);
+        let proxy = thread::spawn(move || proxy(&listener, &tls_config, target_address));
+
+        let mut upstream = DohUpstream::new(address, "dns.example", client_config());
+        upstream.set_odoh_target("target.example", "/dns-query", config);
+        let query = query(7, "secret.example.", 1);
+        assert_eq!(response(&query), upstream.query(&query).unwrap());
+        target.join().unwrap();
+
+        // The proxy saw where the query went, but not what it was.
+        let (path, body) = proxy.join().unwrap();
+        assert_eq!("/dns-query?targethost=target.example&targetpath=%2Fdns-query", path);
+        assert!(!body.windows(6).any(|w| w == b"secret"));
+    }
+}
@@ -0,0 +1,506 @@
+//! The client side of DNS over QUIC (RFC 9250), on the QUIC of quinn: each
+//! query on a stream of its own, sent with its ID set to zero, which is put
+//! back in its response. Queries go in 0-RTT data when a session from the
+//! server allows it, QUERY requests only, as others may not be safe to
+//! replay.
+//!
+//! `DoqClient` only keeps the books; `DoqUpstream` runs it over a UDP
+//! socket, keeping the connection open for query after query.
+
+use std::cmp;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use bytes::{Bytes, BytesMut};
+use quinn_proto::{self, ConnectError, ConnectionError, ConnectionHandle, DatagramEvent, Dir,
+                  EndpointConfig, Event, StreamEvent, StreamId, TransportConfig, VarInt};
+use quinn_proto::crypto::rustls::QuicClientConfig;
+use protocol::{Header, HeaderMut, OP_QUERY, tcp_frame};
+use server::{DOQ_NO_ERROR, DOQ_REQUEST_CANCELLED, DoqRead, DoqStreams, doq_code};
+use tls::ClientConfig;
+use super::Upstream;
+use super::stream::QUERY_TIMEOUT_MS;
+
+pub const DOQ_PORT: u16 = 853;
+
+#[derive(Debug)]
+struct Outstanding {
+    query: Vec<u8>,
+    // None until the server will take another stream.
+    stream: Option<StreamId>,
+    deadline: Instant,
+}
+
+/// Queries sent on one connection and not yet answered.
+#[derive(Debug)]
+pub struct DoqClient {
+    endpoint: quinn_proto::Endpoint,
+    number: ConnectionHandle,
+    quic: quinn_proto::Connection,
+    server: SocketAddr,
+    streams: DoqStreams,
+    outstanding: Vec<Outstanding>,
+    // Why the connection was lost, once it has been.
+    error: Option<ConnectionError>,
+    timeout: Duration,
+}
+
+impl DoqClient {
+    /// A connection to the server `name` at `server`, whose TLS `config`
+    /// should offer `doq` through ALPN.
+    pub fn new(config: &ClientConfig,
+               server: SocketAddr,
+               name: &str,
+               now: Instant)
+        -> Result<DoqClient, ConnectError> {
+        let crypto = QuicClientConfig::try_from(config.rustls_config()).expect("TLS 1.3");
+        let mut config = quinn_proto::ClientConfig::new(Arc::new(crypto));
+        let mut transport = TransportConfig::default();
+        transport.max_concurrent_uni_streams(VarInt::from_u32(0));
+        config.transport_config(Arc::new(transport));
+        let mut endpoint = quinn_proto::Endpoint::new(Arc::new(EndpointConfig::default()),
+                                                      None,
+                                                      false,
+                                                      None);
+        let (number, quic) = try!(endpoint.connect(now, config, server, name));
+        Ok(DoqClient {
+            endpoint: endpoint,
+            number: number,
+            quic: quic,
+            server: server,
+            streams: DoqStreams::new(),
+            outstanding: Vec::new(),
+            error: None,
+            timeout: Duration::from_millis(QUERY_TIMEOUT_MS),
+        })
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Queues a query. Returns false, queueing nothing, if it is too short
+    /// or too long to be one, or the connection has closed.
+    pub fn query(&mut self, query: &[u8], now: Instant) -> bool {
+        if query.len() < Header::at(query).end_offset() || tcp_frame(query).is_none() ||
+           self.is_closed() {
+            return false;
+        }
+        self.outstanding.push(Outstanding {
+            query: query.to_vec(),
+            stream: None,
+            deadline: now + self.timeout,
+        });
+        self.start();
+        true
+    }
+
+    // Sends the queries waiting for a stream, as many as the server takes.
+    // Before the handshake is over, only QUERY requests go.
+    fn start(&mut self) {
+        let handshaking = self.quic.is_handshaking();
+        for outstanding in self.outstanding.iter_mut().filter(|o| o.stream.is_none()) {
+            if handshaking && Header::at(&outstanding.query[..]).op() != Some(OP_QUERY) {
+                continue;
+            }
+            outstanding.stream = self.quic.streams().open(Dir::Bi);
+            let id = match outstanding.stream {
+                Some(id) => id,
+                None => break,
+            };
+            let mut frame = tcp_frame(&outstanding.query).unwrap();
+            HeaderMut::at_raw(&mut frame[2..]).set_id(0);
+            self.streams.write(&mut self.quic, id, frame);
+        }
+    }
+
+    /// Takes a datagram from the server and returns the queries it is done
+    /// with, each with its response, or None if the server failed it. An
+    /// error ends the connection, failing every query.
+    pub fn received(&mut self,
+                    datagram: &[u8],
+                    now: Instant)
+        -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>, ConnectionError> {
+        let mut buffer = Vec::new();
+        let event = self.endpoint.handle(now,
+                                         self.server,
+                                         None,
+                                         None,
+                                         BytesMut::from(datagram),
+                                         &mut buffer);
+        if let Some(DatagramEvent::ConnectionEvent(number, event)) = event {
+            if number == self.number {
+                self.quic.handle_event(event);
+            }
+        }
+        let done = self.poll();
+        match self.error {
+            Some(ref error) => Err(error.clone()),
+            None => Ok(done),
+        }
+    }
+
+    // Acts on what has happened on the connection and returns the queries
+    // it is done with.
+    fn poll(&mut self) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        let mut done = Vec::new();
+        while let Some(event) = self.quic.poll() {
+            let (id, read) = match event {
+                Event::Stream(StreamEvent::Readable { id }) => {
+                    match self.streams.read(&mut self.quic, id) {
+                        Some(read) => (id, read),
+                        None => continue,
+                    }
+                }
+                Event::Stream(StreamEvent::Stopped { id, .. }) => (id, DoqRead::Reset),
+                Event::Stream(StreamEvent::Writable { id }) => {
+                    self.streams.writable(&mut self.quic, id);
+                    continue;
+                }
+                Event::Connected => {
+                    // Rejected 0-RTT data is gone, streams and all.
+                    if self.quic.has_0rtt() && !self.quic.accepted_0rtt() {
+                        self.streams.clear();
+                        for outstanding in &mut self.outstanding {
+                            outstanding.stream = None;
+                        }
+                    }
+                    continue;
+                }
+                Event::ConnectionLost { reason } => {
+                    self.error = Some(reason);
+                    continue;
+                }
+                _ => continue,
+            };
+            let at = match self.outstanding.iter().position(|o| o.stream == Some(id)) {
+                Some(at) => at,
+                None => continue,
+            };
+            let query = self.outstanding.remove(at).query;
+            let message = match read {
+                DoqRead::Message(ref message) if Header::at(&message[..]).is_response() => {
+                    let mut message = message.clone();
+                    let id = Header::at(&query[..]).id().unwrap();
+                    HeaderMut::at_raw(&mut message[..]).set_id(id);
+                    Some(message)
+                }
+                _ => None,
+            };
+            done.push((query, message));
+        }
+        while let Some(event) = self.quic.poll_endpoint_events() {
+            if let Some(event) = self.endpoint.handle_event(self.number, event) {
+                self.quic.handle_event(event);
+            }
+        }
+        if self.is_closed() {
+            done.extend(self.outstanding.drain(..).map(|o| (o.query, None)));
+        }
+        self.start();
+        done
+    }
+
+    /// The next datagram to send to the server, if there is one.
+    pub fn next_datagram(&mut self, now: Instant) -> Option<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let transmit = self.quic.poll_transmit(now, 1, &mut buffer);
+        while let Some(event) = self.quic.poll_endpoint_events() {
+            if let Some(event) = self.endpoint.handle_event(self.number, event) {
+                self.quic.handle_event(event);
+            }
+        }
+        transmit.map(|transmit| {
+            buffer.truncate(transmit.size);
+            buffer
+        })
+    }
+
+    /// How many queries are waiting for an answer.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Gives up on the queries that have waited past their timeout and
+    /// returns them. Their streams are cancelled.
+    pub fn expired(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let (expired, waiting): (Vec<Outstanding>, Vec<Outstanding>) =
+            self.outstanding.drain(..).partition(|o| o.deadline <= now);
+        self.outstanding = waiting;
+        for id in expired.iter().filter_map(|o| o.stream) {
+            self.streams.cancel(&mut self.quic, id, DOQ_REQUEST_CANCELLED);
+        }
+        expired.into_iter().map(|o| o.query).collect()
+    }
+
+    /// When `expired` or `timeout` next has something to do.
+    pub fn deadline(&mut self) -> Instant {
+        let queries = self.outstanding.iter().map(|o| o.deadline).min();
+        match (queries, self.quic.poll_timeout()) {
+            (Some(a), Some(b)) => cmp::min(a, b),
+            (Some(deadline), None) | (None, Some(deadline)) => deadline,
+            (None, None) => Instant::now() + self.timeout,
+        }
+    }
+
+    /// Acts on the connection's timers, and returns the queries that ends,
+    /// as `received` does.
+    pub fn timeout(&mut self, now: Instant) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        if self.quic.poll_timeout().map_or(false, |deadline| deadline <= now) {
+            self.quic.handle_timeout(now);
+        }
+        self.poll()
+    }
+
+    /// Closes the connection, once `next_datagram` has said so.
+    pub fn close(&mut self, now: Instant) {
+        self.quic.close(now, doq_code(DOQ_NO_ERROR), Bytes::new());
+    }
+
+    /// Whether the connection has ended, so no more queries can be sent on
+    /// it.
+    pub fn is_closed(&self) -> bool {
+        self.quic.is_closed() || self.error.is_some()
+    }
+
+    /// Whether the server took the queries sent in 0-RTT data.
+    pub fn is_early_data_accepted(&self) -> bool {
+        self.quic.accepted_0rtt()
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "no answer from server")
+}
+
+// Sends `queries` on a connection and returns the responses in the order
+// of the queries.
+fn exchange_doq(socket: &UdpSocket,
+                client: &mut DoqClient,
+                queries: &[Vec<u8>])
+    -> io::Result<Vec<Vec<u8>>> {
+    for query in queries {
+        if !client.query(query, Instant::now()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "query cannot be sent"));
+        }
+    }
+    let mut responses: Vec<Option<Vec<u8>>> = vec![None; queries.len()];
+    let mut buffer = [0u8; 65536];
+    loop {
+        while let Some(datagram) = client.next_datagram(Instant::now()) {
+            try!(socket.send(&datagram));
+        }
+        if client.outstanding() == 0 {
+            break;
+        }
+        if !client.expired(Instant::now()).is_empty() {
+            return Err(timed_out());
+        }
+        let now = Instant::now();
+        let wait = cmp::max(client.deadline(), now + Duration::from_millis(1)) - now;
+        try!(socket.set_read_timeout(Some(wait)));
+        let done = match socket.recv(&mut buffer) {
+            Ok(n) => {
+                match client.received(&buffer[..n], Instant::now()) {
+                    Ok(done) => done,
+                    Err(e) => {
+                        while let Some(datagram) = client.next_datagram(Instant::now()) {
+                            let _ = socket.send(&datagram);
+                        }
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                          e.kind() == io::ErrorKind::TimedOut => client.timeout(Instant::now()),
+            Err(e) => return Err(e),
+        };
+        for (query, response) in done {
+            let response = match response {
+                Some(response) => response,
+                None => return Err(io::Error::new(io::ErrorKind::Other, "query failed")),
+            };
+            let at = (0..queries.len()).find(|&i| responses[i].is_none() && queries[i] == query);
+            if let Some(i) = at {
+                responses[i] = Some(response);
+            }
+        }
+    }
+    Ok(responses.into_iter().map(|r| r.unwrap()).collect())
+}
+
+/// A DNS over QUIC server to send queries to.
+#[derive(Debug)]
+pub struct DoqUpstream {
+    address: SocketAddr,
+    name: String,
+    config: Arc<ClientConfig>,
+    timeout: Duration,
+    connection: Option<(UdpSocket, DoqClient)>,
+}
+
+impl DoqUpstream {
+    /// The server at `address`, whose certificate must be good for `name`
+    /// by `config`, which should offer `doq` through ALPN.
+    pub fn new(address: SocketAddr, name: &str, config: Arc<ClientConfig>) -> DoqUpstream {
+        DoqUpstream {
+            address: address,
+            name: name.to_string(),
+            config: config,
+            timeout: Duration::from_millis(QUERY_TIMEOUT_MS),
+            connection: None,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends one query and returns its response.
+    pub fn query(&mut self, query: &[u8]) -> io::Result<Vec<u8>> {
+        self.query_all(&[query.to_vec()]).map(|mut responses| responses.remove(0))
+    }
+
+    /// Sends `queries` all at once, each on its own stream, and returns the
+    /// responses in the order of the queries. An open connection is tried
+    /// first; if it fails, the queries are sent again on a new one.
+    pub fn query_all(&mut self, queries: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>> {
+        if let Some((socket, mut client)) = self.connection.take() {
+            client.timeout(Instant::now());
+            if !client.is_closed() {
+                if let Ok(responses) = exchange_doq(&socket, &mut client, queries) {
+                    self.connection = Some((socket, client));
+                    return Ok(responses);
+                }
+            }
+        }
+        let local = if self.address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = try!(UdpSocket::bind(local));
+        try!(socket.connect(self.address));
+        let mut client = match DoqClient::new(&self.config,
+                                              self.address,
+                                              &self.name,
+                                              Instant::now()) {
+            Ok(client) => client,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+        };
+        client.set_timeout(self.timeout);
+        let responses = try!(exchange_doq(&socket, &mut client, queries));
+        self.connection = Some((socket, client));
+        Ok(responses)
+    }
+
+    /// Closes the open connection, if any.
+    pub fn close(&mut self) {
+        if let Some((socket, mut client)) = self.connection.take() {
+            client.close(Instant::now());
+            while let Some(datagram) = client.next_datagram(Instant::now()) {
+                let _ = socket.send(&datagram);
+            }
+        }
+    }
+
+    /// Whether a connection is open.
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// Whether the open connection's queries went in 0-RTT data that the
+    /// server took, which it does only for a session resumed.
+    pub fn is_early_data_accepted(&self) -> bool {
+        match self.connection {
+            Some((_, ref client)) => client.is_early_data_accepted(),
+            None => false,
+        }
+    }
+}
+
+
+impl Upstream for DoqUpstream {
+    fn query_all(&mut self, queries: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>> {
+        DoqUpstream::query_all(self, queries)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use protocol::{Header, HeaderMut};
+    use server::{DOQ_ALPN, DoqServer};
+    use test_util::query;
+    use tls::ServerConfig;
+
+    fn response(query: &[u8]) -> Vec<u8> {
+        let mut response = query.to_vec();
+        HeaderMut::at_raw(&mut response[..]).set_qr(true);
+        response
+    }
+
+    // Answers queries until `count` have been and every connection is
+    // closed. Returns whether every ID was zero.
+    fn serve(socket: UdpSocket, config: ServerConfig, count: usize) -> bool {
+        let mut server = DoqServer::new(&config);
+        let mut answered = 0;
+        let mut zeroed = true;
+        let mut buffer = [0u8; 65536];
+        socket.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        while answered < count || server.connections() > 0 {
+            if let Ok((n, from)) = socket.recv_from(&mut buffer) {
+                for (handle, query) in server.received(&buffer[..n], from, Instant::now()) {
+                    zeroed &= Header::at(&query[..]).id() == Some(0);
+                    assert!(server.respond(handle, &response(&query)));
+                    answered += 1;
+                }
+            }
+            server.timeout(Instant::now());
+            for (datagram, to) in server.datagrams(Instant::now()) {
+                socket.send_to(&datagram, to).unwrap();
+            }
+        }
+        zeroed
+    }
+
+    #[test]
+    fn streams_reuse_and_early_data() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let mut config = ServerConfig::load(Path::new("testdata/tls-server.pem"),
+                                            Path::new("testdata/tls-server.key"))
+                             .unwrap();
+        config.add_alpn(DOQ_ALPN).set_early_data(true);
+        let server = thread::spawn(move || serve(socket, config, 5));
+
+        let mut client_config = ClientConfig::new();
+        client_config.load_roots(Path::new("testdata/tls-ca.pem")).unwrap();
+        client_config.add_alpn(DOQ_ALPN);
+        let mut upstream = DoqUpstream::new(address, "dns.example", Arc::new(client_config));
+        let first = query(1, "example.com.", 1);
+        assert_eq!(response(&first), upstream.query(&first).unwrap());
+        assert!(upstream.is_connected() && !upstream.is_early_data_accepted());
+
+        // More on the same connection, a stream each, IDs put back.
+        let queries = vec![query(2, "a.example.", 1),
+                           query(3, "b.example.", 1),
+                           query(2, "c.example.", 1)];
+        assert_eq!(queries.iter().map(|q| response(q)).collect::<Vec<_>>(),
+                   upstream.query_all(&queries).unwrap());
+
+        // A new connection resumes, with the query in 0-RTT.
+        upstream.close();
+        let last = query(4, "d.example.", 1);
+        assert_eq!(response(&last), upstream.query(&last).unwrap());
+        assert!(upstream.is_early_data_accepted());
+        upstream.close();
+        assert!(server.join().unwrap());
+    }
+}
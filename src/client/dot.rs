@@ -0,0 +1,197 @@
+//! An upstream server spoken to over DNS over TLS (RFC 7858). One
+//! connection is kept open and reused for query after query; when it has
+//! to be made again, the TLS session is resumed if the server allows.
+
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tls::ClientConfig;
+use super::Upstream;
+use super::stream::{QUERY_TIMEOUT_MS, StreamClient, exchange_stream};
+
+/// The port DNS over TLS is served on.
+pub const DOT_PORT: u16 = 853;
+
+/// A DNS over TLS server to send queries to.
+#[derive(Debug)]
+pub struct DotUpstream {
+    address: SocketAddr,
+    name: String,
+    config: Arc<ClientConfig>,
+    timeout: Duration,
+    connection: Option<(TcpStream, StreamClient)>,
+}
+
+impl DotUpstream {
+    /// The server at `address`, whose certificate must be good for `name`
+    /// by `config`. Sessions are kept in `config`, so upstreams sharing it
+    /// resume each other's.
+    pub fn new(address: SocketAddr, name: &str, config: Arc<ClientConfig>) -> DotUpstream {
+        DotUpstream {
+            address: address,
+            name: name.to_string(),
+            config: config,
+            timeout: Duration::from_millis(QUERY_TIMEOUT_MS),
+            connection: None,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends one query and returns its response.
+    pub fn query(&mut self, query: &[u8]) -> io::Result<Vec<u8>> {
+        self.query_all(&[query.to_vec()]).map(|mut responses| responses.remove(0))
+    }
+
+    /// Sends `queries` all at once and returns the responses in the order
+    /// of the queries. An open connection is tried first; if it fails, the
+    /// queries are sent again on a new one.
+    pub fn query_all(&mut self, queries: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>> {
+        if let Some((mut stream, mut client)) = self.connection.take() {
+            if !client.is_idle(Instant::now()) {
+                if let Ok(responses) = exchange_stream(&mut stream, &mut client, queries) {
+                    self.connection = Some((stream, client));
+                    return Ok(responses);
+                }
+            }
+        }
+        let mut stream = try!(TcpStream::connect(self.address));
+        try!(stream.set_read_timeout(Some(self.timeout)));
+        try!(stream.set_write_timeout(Some(self.timeout)));
+        let mut client = try!(StreamClient::with_tls(self.config.clone(),
+                                                     &self.name,
+                                                     Instant::now())
+                                  .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)));
+        client.set_timeout(self.timeout);
+        let responses = try!(exchange_stream(&mut stream, &mut client, queries));
+        self.connection = Some((stream, client));
+        Ok(responses)
+    }
+
+    /// Whether a connection is open.
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// Whether the open connection resumed an earlier session.
+    pub fn is_resumed(&self) -> bool {
+        match self.connection {
+            Some((_, ref client)) => client.tls().map_or(false, |tls| tls.is_resumed()),
+            None => false,
+        }
+    }
+}
+
+
+impl Upstream for DotUpstream {
+    fn query_all(&mut self, queries: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>> {
+        DotUpstream::query_all(self, queries)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Instant;
+    use protocol::HeaderMut;
+    use server::StreamConnection;
+    use test_util::query;
+    use tls::{ClientConfig, ServerConfig};
+
+    fn response(query: &[u8]) -> Vec<u8> {
+        let mut response = query.to_vec();
+        HeaderMut::at_raw(&mut response[..]).set_qr(true);
+        response
+    }
+
+    // Answers `count` queries on the next connection, then hangs up.
+    // Returns whether the session was resumed.
+    fn serve(listener: &TcpListener, config: &Arc<ServerConfig>, count: usize) -> bool {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut connection = StreamConnection::with_tls(config.clone(), Instant::now());
+        let mut answered = 0;
+        let mut buffer = [0u8; 4096];
+        while answered < count {
+            let n = stream.read(&mut buffer).unwrap();
+            for query in connection.received(&buffer[..n], Instant::now()).unwrap() {
+                connection.respond(&response(&query));
+                answered += 1;
+            }
+            stream.write_all(connection.pending()).unwrap();
+            let n = connection.pending().len();
+            connection.written(n, Instant::now());
+        }
+        connection.tls().unwrap().is_resumed()
+    }
+
+    #[test]
+    fn reuse_and_resumption() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = ServerConfig::load(Path::new("testdata/tls-server.pem"),
+                                        Path::new("testdata/tls-server.key"))
+                         .unwrap();
+        let config = Arc::new(config);
+        let server = thread::spawn(move || {
+            (serve(&listener, &config, 3), serve(&listener, &config, 1))
+        });
+
+        let mut client_config = ClientConfig::new();
+        client_config.load_roots(Path::new("testdata/tls-ca.pem")).unwrap();
+        let mut upstream = DotUpstream::new(address, "dns.example", Arc::new(client_config));
+        assert!(!upstream.is_connected());
+        let message = query(1, "example.com.", 1);
+        assert_eq!(response(&message), upstream.query(&message).unwrap());
+        assert!(upstream.is_connected() && !upstream.is_resumed());
+        let queries = vec![query(2, "example.com.", 1), query(3, "example.com.", 1)];
+        assert_eq!(vec![response(&queries[0]), response(&queries[1])],
+                   upstream.query_all(&queries).unwrap());
+
+        // The server has hung up, so this goes on a new connection.
+        let message = query(4, "example.com.", 1);
+        assert_eq!(response(&message), upstream.query(&message).unwrap());
+        assert!(upstream.is_resumed());
+        assert_eq!((false, true), server.join().unwrap());
+    }
+
+    #[test]
+    fn untrusted() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = ServerConfig::load(Path::new("testdata/tls-server.pem"),
+                                        Path::new("testdata/tls-server.key"))
+                         .unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut connection = StreamConnection::with_tls(Arc::new(config), Instant::now());
+            let mut buffer = [0u8; 4096];
+            loop {
+                let n = stream.read(&mut buffer).unwrap();
+                if n == 0 || connection.received(&buffer[..n], Instant::now()).is_err() {
+                    return;
+                }
+                stream.write_all(connection.pending()).unwrap();
+                let n = connection.pending().len();
+                connection.written(n, Instant::now());
+            }
+        });
+
+        // Pinned to some other key.
+        let mut client_config = ClientConfig::new();
+        client_config.add_pin("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=");
+        let mut upstream = DotUpstream::new(address, "dns.example", Arc::new(client_config));
+        assert!(upstream.query(&query(1, "example.com.", 1)).is_err());
+        assert!(!upstream.is_connected());
+        server.join().unwrap();
+    }
+}
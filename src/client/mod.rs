@@ -0,0 +1,26 @@
+//! Talking to other servers.
+
+mod dnscrypt;
+mod doh;
+mod doq;
+mod dot;
+mod stream;
+mod transfer;
+mod udp;
+
+use std::io;
+
+pub use self::dnscrypt::{DNSCRYPT_PORT, DnscryptClient, DnscryptUpstream};
+pub use self::doh::{DohClient, DohUpstream};
+pub use self::doq::{DOQ_PORT, DoqClient, DoqUpstream};
+pub use self::dot::{DOT_PORT, DotUpstream};
+pub use self::stream::{IDLE_TIMEOUT_MS, QUERY_TIMEOUT_MS, StreamClient, query_tcp};
+pub use self::transfer::{Transfer, TransferError, TransferResult, fetch, refresh};
+pub use self::udp::{exchange, query_udp};
+
+/// A server queries can be sent to over a connection kept open to it.
+pub trait Upstream {
+    /// Sends `queries` all at once and returns the responses in the order
+    /// of the queries.
+    fn query_all(&mut self, queries: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>>;
+}
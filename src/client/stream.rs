@@ -0,0 +1,347 @@
+//! The client side of a DNS connection over TCP (RFC 7766). Queries are
+//! pipelined: all may be sent before any answer is back, and the server
+//! may answer them in any order, so responses are matched up by ID.
+//!
+//! `StreamClient` only keeps the books, with TLS under the framing for DNS
+//! over TLS (RFC 7858) if asked; `query_tcp` runs it over a plain blocking
+//! connection.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use protocol::{FrameReader, FrameWriter, Header, tcp_frame};
+use tls::{ClientConfig, TlsConnection, TlsError};
+
+/// Milliseconds to wait for the answer to a query.
+pub const QUERY_TIMEOUT_MS: u64 = 5000;
+/// Milliseconds a connection with no queries outstanding is kept open for
+/// more.
+pub const IDLE_TIMEOUT_MS: u64 = 10_000;
+
+#[derive(Debug)]
+struct Outstanding {
+    query: Vec<u8>,
+    deadline: Instant,
+}
+
+/// Queries sent on one connection and not yet answered.
+#[derive(Debug)]
+pub struct StreamClient {
+    input: FrameReader,
+    output: FrameWriter,
+    tls: Option<TlsConnection>,
+    outstanding: HashMap<u16, Outstanding>,
+    timeout: Duration,
+    idle_timeout: Duration,
+    last_active: Instant,
+}
+
+impl StreamClient {
+    pub fn new(now: Instant) -> StreamClient {
+        StreamClient {
+            input: FrameReader::new(),
+            output: FrameWriter::new(),
+            tls: None,
+            outstanding: HashMap::new(),
+            timeout: Duration::from_millis(QUERY_TIMEOUT_MS),
+            idle_timeout: Duration::from_millis(IDLE_TIMEOUT_MS),
+            last_active: now,
+        }
+    }
+
+    /// A connection that starts with a TLS handshake with the server
+    /// `name`, a host name or an IP address.
+    pub fn with_tls(config: Arc<ClientConfig>,
+                    name: &str,
+                    now: Instant)
+        -> Result<StreamClient, TlsError> {
+        let mut client = StreamClient::new(now);
+        client.tls = Some(try!(TlsConnection::client(config, name)));
+        Ok(client)
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn set_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Queues a query. Returns false, queueing nothing, if its ID is
+    /// already waiting for an answer on this connection or it is too long.
+    pub fn query(&mut self, query: &[u8], now: Instant) -> bool {
+        let id = match Header::at(query).id() {
+            Some(id) => id,
+            None => return false,
+        };
+        if self.outstanding.contains_key(&id) {
+            return false;
+        }
+        let queued = match self.tls {
+            Some(ref mut tls) => tcp_frame(query).map(|frame| tls.write(&frame)).is_some(),
+            None => self.output.push(query),
+        };
+        if !queued {
+            return false;
+        }
+        self.outstanding.insert(id,
+                                Outstanding {
+                                    query: query.to_vec(),
+                                    deadline: now + self.timeout,
+                                });
+        self.last_active = now;
+        true
+    }
+
+    /// Takes octets read from the server and returns the responses they
+    /// complete, each with the query it answers. Messages answering nothing
+    /// outstanding are dropped. A TLS error ends the connection.
+    pub fn received(&mut self,
+                    data: &[u8],
+                    now: Instant)
+        -> Result<Vec<(Vec<u8>, Vec<u8>)>, TlsError> {
+        self.last_active = now;
+        match self.tls {
+            Some(ref mut tls) => {
+                try!(tls.received(data));
+                self.input.push(&tls.read());
+            }
+            None => self.input.push(data),
+        }
+        let mut answered = Vec::new();
+        while let Some(response) = self.input.next_frame() {
+            let header = Header::at(&response[..]);
+            if !header.is_response() {
+                continue;
+            }
+            let id = match header.id() {
+                Some(id) => id,
+                None => continue,
+            };
+            if let Some(outstanding) = self.outstanding.remove(&id) {
+                answered.push((outstanding.query, response));
+            }
+        }
+        Ok(answered)
+    }
+
+    /// The octets waiting to be written to the server.
+    pub fn pending(&self) -> &[u8] {
+        match self.tls {
+            Some(ref tls) => tls.pending(),
+            None => self.output.pending(),
+        }
+    }
+
+    pub fn written(&mut self, n: usize) {
+        match self.tls {
+            Some(ref mut tls) => tls.written(n),
+            None => self.output.written(n),
+        }
+    }
+
+    /// The TLS the connection runs over, if any.
+    pub fn tls(&self) -> Option<&TlsConnection> {
+        self.tls.as_ref()
+    }
+
+    /// How many queries are waiting for an answer.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Gives up on the queries that have waited past their timeout and
+    /// returns them.
+    pub fn expired(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let ids: Vec<u16> = self.outstanding
+                                .iter()
+                                .filter(|&(_, o)| o.deadline <= now)
+                                .map(|(&id, _)| id)
+                                .collect();
+        ids.iter().filter_map(|id| self.outstanding.remove(id)).map(|o| o.query).collect()
+    }
+
+    /// When `expired` or `is_idle` next has something to say.
+    pub fn deadline(&self) -> Instant {
+        match self.outstanding.values().map(|o| o.deadline).min() {
+            Some(deadline) => deadline,
+            None => self.last_active + self.idle_timeout,
+        }
+    }
+
+    /// Whether the connection has had nothing outstanding for the idle
+    /// timeout, and may be closed.
+    pub fn is_idle(&self, now: Instant) -> bool {
+        self.outstanding.is_empty() && self.pending().is_empty() &&
+        now >= self.last_active + self.idle_timeout
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "no answer from server")
+}
+
+/// Sends `queries` down one new TCP connection to `server`, all at once,
+/// and returns the responses in the order of the queries. Fails if any
+/// goes unanswered for `timeout`, or if two share an ID.
+pub fn query_tcp(server: &SocketAddr,
+                 queries: &[Vec<u8>],
+                 timeout: Duration)
+    -> io::Result<Vec<Vec<u8>>> {
+    let mut client = StreamClient::new(Instant::now());
+    client.set_timeout(timeout);
+    let mut stream = try!(TcpStream::connect(server));
+    try!(stream.set_read_timeout(Some(timeout)));
+    try!(stream.set_write_timeout(Some(timeout)));
+    exchange_stream(&mut stream, &mut client, queries)
+}
+
+/// Sends `queries` on a blocking connection run by `client`, and returns
+/// the responses in the order of the queries. The stream's timeouts should
+/// be set.
+pub fn exchange_stream(stream: &mut TcpStream,
+                       client: &mut StreamClient,
+                       queries: &[Vec<u8>])
+    -> io::Result<Vec<Vec<u8>>> {
+    for query in queries {
+        if !client.query(query, Instant::now()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "query cannot be sent"));
+        }
+    }
+    let mut responses: Vec<Option<Vec<u8>>> = vec![None; queries.len()];
+    let mut buffer = [0u8; 4096];
+    while client.outstanding() > 0 {
+        // TLS may have more to say at any point.
+        if !client.pending().is_empty() {
+            try!(stream.write_all(client.pending()));
+            let n = client.pending().len();
+            client.written(n);
+        }
+        if !client.expired(Instant::now()).is_empty() {
+            return Err(timed_out());
+        }
+        let n = match stream.read(&mut buffer) {
+            Ok(0) => {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                          "connection closed with queries unanswered"))
+            }
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Err(timed_out()),
+            Err(e) => return Err(e),
+        };
+        let answered = match client.received(&buffer[..n], Instant::now()) {
+            Ok(answered) => answered,
+            Err(e) => {
+                // Say why, if the server is still listening.
+                let _ = stream.write_all(client.pending());
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+        };
+        for (query, response) in answered {
+            if let Some(i) = queries.iter().position(|q| *q == query) {
+                responses[i] = Some(response);
+            }
+        }
+    }
+    Ok(responses.into_iter().map(|r| r.unwrap()).collect())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use protocol::{FrameReader, HeaderMut, tcp_frame};
+    use test_util::query;
+
+    fn response(query: &[u8]) -> Vec<u8> {
+        let mut response = query.to_vec();
+        HeaderMut::at_raw(&mut response[..]).set_qr(true);
+        response
+    }
+
+    #[test]
+    fn out_of_order() {
+        let start = Instant::now();
+        let mut client = StreamClient::new(start);
+        assert!(client.query(&query(1, "example.com.", 1), start));
+        assert!(client.query(&query(2, "example.com.", 1), start));
+        assert!(!client.query(&query(2, "example.com.", 1), start));
+        let mut expected = tcp_frame(&query(1, "example.com.", 1)).unwrap();
+        expected.extend(tcp_frame(&query(2, "example.com.", 1)).unwrap());
+        assert_eq!(&expected[..], client.pending());
+        client.written(expected.len());
+
+        // The second is answered first, the first arrives in pieces, and a
+        // stray answer is ignored.
+        let mut stream = tcp_frame(&response(&query(2, "example.com.", 1))).unwrap();
+        stream.extend(tcp_frame(&response(&query(9, "example.com.", 1))).unwrap());
+        stream.extend(tcp_frame(&response(&query(1, "example.com.", 1))).unwrap());
+        let split = stream.len() - 3;
+        assert_eq!(vec![(query(2, "example.com.", 1), response(&query(2, "example.com.", 1)))],
+                   client.received(&stream[..split], start).unwrap());
+        assert_eq!(1, client.outstanding());
+        assert_eq!(vec![(query(1, "example.com.", 1), response(&query(1, "example.com.", 1)))],
+                   client.received(&stream[split..], start).unwrap());
+        assert_eq!(0, client.outstanding());
+
+        // The ID is free again.
+        assert!(client.query(&query(1, "example.com.", 1), start));
+    }
+
+    #[test]
+    fn timeouts() {
+        let start = Instant::now();
+        let mut client = StreamClient::new(start);
+        client.set_timeout(Duration::from_secs(2)).set_idle_timeout(Duration::from_secs(5));
+        let (first, second) = (query(1, "example.com.", 1), query(2, "example.com.", 1));
+        client.query(&first, start);
+        client.query(&second, start + Duration::from_secs(1));
+        assert_eq!(start + Duration::from_secs(2), client.deadline());
+        assert_eq!(vec![first], client.expired(start + Duration::from_secs(2)));
+        assert!(!client.is_idle(start + Duration::from_secs(9)));
+        assert_eq!(vec![second], client.expired(start + Duration::from_secs(3)));
+        client.written(100);
+        assert_eq!(start + Duration::from_secs(6), client.deadline());
+        assert!(!client.is_idle(start + Duration::from_secs(5)));
+        assert!(client.is_idle(start + Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn blocking() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = FrameReader::new();
+            let mut queries = Vec::new();
+            let mut buffer = [0u8; 512];
+            while queries.len() < 3 {
+                match reader.next_frame() {
+                    Some(query) => queries.push(query),
+                    None => {
+                        let n = stream.read(&mut buffer).unwrap();
+                        reader.push(&buffer[..n]);
+                    }
+                }
+            }
+            // Answers last to first.
+            for query in queries.iter().rev() {
+                stream.write_all(&tcp_frame(&response(query)).unwrap()).unwrap();
+            }
+        });
+        let queries = (1..4).map(|id| query(id, "example.com.", 1)).collect::<Vec<_>>();
+        let responses = query_tcp(&address, &queries, Duration::from_secs(5)).unwrap();
+        assert_eq!(vec![response(&queries[0]), response(&queries[1]), response(&queries[2])],
+                   responses);
+        server.join().unwrap();
+    }
+}
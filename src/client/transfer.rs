@@ -0,0 +1,544 @@
+//! The receiving side of zone transfers.
+//!
+//! `Transfer` only interprets messages; moving them is up to the caller, or
+//! to `fetch` for a plain blocking TCP connection.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+use protocol::{FrameReader, Header, HeaderMut, MessageCursor, Name, Question, QuestionMut, RData,
+               Record, Serial, RC_FORMAT_ERROR, RC_NOT_IMPLEMENTED, RC_OK, tcp_frame};
+use protocol::rdata::{CLASS_IN, TYPE_AXFR, TYPE_IXFR};
+use tsig::{Key, Signer, TsigError, Verifier, now};
+use zone::Diff;
+
+#[derive(Debug)]
+pub enum TransferError {
+    /// The server answered with an error RCODE.
+    Rcode(u8),
+    /// A message that is not part of a well formed transfer.
+    Malformed(&'static str),
+    /// A response whose signature did not check out, or that the server
+    /// sent to say ours did not.
+    Tsig(TsigError),
+    Io(io::Error),
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TransferError::Rcode(rcode) => write!(fmt, "transfer refused with rcode {}", rcode),
+            TransferError::Malformed(why) => write!(fmt, "malformed transfer: {}", why),
+            TransferError::Tsig(ref e) => write!(fmt, "{}", e),
+            TransferError::Io(ref e) => write!(fmt, "{}", e),
+        }
+    }
+}
+
+impl Error for TransferError {
+    fn description(&self) -> &str {
+        match *self {
+            TransferError::Rcode(_) => "transfer refused",
+            TransferError::Malformed(why) => why,
+            TransferError::Tsig(ref e) => e.description(),
+            TransferError::Io(ref e) => e.description(),
+        }
+    }
+}
+
+impl From<io::Error> for TransferError {
+    fn from(e: io::Error) -> TransferError {
+        TransferError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransferResult {
+    /// The server has nothing newer than the serial asked about.
+    UpToDate,
+    /// A copy of the whole zone, SOA first.
+    Full(Vec<Record>),
+    /// Changes to apply in order.
+    Incremental(Vec<Diff>),
+}
+
+#[derive(Debug)]
+enum State {
+    // Waiting for the opening SOA.
+    Start,
+    // IXFR: seen the opening SOA, not yet whether the reply is incremental.
+    Opened,
+    // A whole zone, until the closing SOA.
+    Full,
+    // IXFR difference sequences.
+    Removing(Diff),
+    Adding(Diff),
+    Done,
+}
+
+fn soa_serial(record: &Record) -> Option<u32> {
+    match record.rdata {
+        RData::Soa(ref soa) => Some(soa.serial),
+        _ => None,
+    }
+}
+
+/// An AXFR or IXFR in progress.
+#[derive(Debug)]
+pub struct Transfer {
+    zone: Name,
+    id: u16,
+    // The SOA held now, for IXFR.
+    current: Option<Record>,
+    state: State,
+    serial: u32,
+    records: Vec<Record>,
+    diffs: Vec<Diff>,
+    key: Option<Key>,
+    // Checks responses once a signed query has been made.
+    verifier: Option<Verifier>,
+}
+
+impl Transfer {
+    fn new(zone: Name, id: u16, current: Option<Record>) -> Transfer {
+        Transfer {
+            zone: zone,
+            id: id,
+            current: current,
+            state: State::Start,
+            serial: 0,
+            records: Vec::new(),
+            diffs: Vec::new(),
+            key: None,
+            verifier: None,
+        }
+    }
+
+    /// Asks for a whole copy of `zone`.
+    pub fn axfr(zone: Name, id: u16) -> Transfer {
+        Transfer::new(zone, id, None)
+    }
+
+    /// Asks for the changes since the version whose SOA is `current`.
+    pub fn ixfr(zone: Name, id: u16, current: Record) -> Transfer {
+        Transfer::new(zone, id, Some(current))
+    }
+
+    pub fn zone(&self) -> &Name {
+        &self.zone
+    }
+
+    /// Signs the request with TSIG, and requires every response message to
+    /// be signed too.
+    pub fn set_key(&mut self, key: Key) -> &mut Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// The request to send.
+    pub fn query(&mut self) -> Vec<u8> {
+        let mut buffer = vec![0u8; 512];
+        let mut idx = MessageCursor::new(buffer.len());
+        HeaderMut::at(&mut idx, &mut buffer[..])
+            .unwrap()
+            .make_query(self.id)
+            .set_rd(false)
+            .set_qd(1);
+        let qtype = if self.current.is_some() {
+            TYPE_IXFR
+        } else {
+            TYPE_AXFR
+        };
+        QuestionMut::at(&mut idx, &mut buffer, &self.zone.segments(), qtype, CLASS_IN).unwrap();
+        if let Some(ref soa) = self.current {
+            soa.write_at(&mut idx, &mut buffer).unwrap();
+            HeaderMut::at_raw(&mut buffer[..]).set_ns(1);
+        }
+        buffer.truncate(idx.tell());
+        if let Some(ref key) = self.key {
+            let mac = Signer::new(key.clone(), None).sign(&mut buffer, now());
+            self.verifier = Some(Verifier::new(key.clone(), mac));
+        }
+        buffer
+    }
+
+    pub fn is_done(&self) -> bool {
+        match self.state {
+            State::Done => true,
+            _ => false,
+        }
+    }
+
+    /// Takes in the next response message. Returns true once the transfer
+    /// is complete.
+    pub fn receive(&mut self, message: &[u8]) -> Result<bool, TransferError> {
+        let unsigned;
+        let message = match self.verifier {
+            Some(ref mut verifier) => {
+                unsigned = try!(verifier.verify(message, now()).map_err(TransferError::Tsig));
+                &unsigned[..]
+            }
+            None => message,
+        };
+        let header = Header::at(message);
+        if header.id() != Some(self.id) || !header.is_response() {
+            return Err(TransferError::Malformed("not a response to the request"));
+        }
+        match header.rc() {
+            Some(RC_OK) => {}
+            Some(rcode) => return Err(TransferError::Rcode(rcode)),
+            None => return Err(TransferError::Malformed("short header")),
+        }
+        let (qd, an) = match (header.qd(), header.an()) {
+            (Some(qd), Some(an)) => (qd, an),
+            _ => return Err(TransferError::Malformed("short header")),
+        };
+        let mut next = header.end_offset();
+        for _ in 0..qd {
+            match Question::from_message(message, next) {
+                Some(question) => next = question.end_offset(),
+                None => return Err(TransferError::Malformed("bad question")),
+            }
+        }
+        let records = match Record::read_section(message, next, an) {
+            Some((records, _)) => records,
+            None => return Err(TransferError::Malformed("bad answer section")),
+        };
+        for record in records {
+            try!(self.next_record(record));
+        }
+        // A lone SOA no newer than ours is the whole answer to an IXFR.
+        if let State::Opened = self.state {
+            if !self.is_newer(self.serial) {
+                self.state = State::Done;
+            }
+        }
+        // The last message must be signed, though some before it need not.
+        if self.is_done() {
+            if let Some(ref verifier) = self.verifier {
+                try!(verifier.finish().map_err(TransferError::Tsig));
+            }
+        }
+        Ok(self.is_done())
+    }
+
+    /// Whether `serial` is newer than the version we hold.
+    fn is_newer(&self, serial: u32) -> bool {
+        match self.current.as_ref().and_then(soa_serial) {
+            Some(ours) => Serial(serial) > Serial(ours),
+            None => true,
+        }
+    }
+
+    fn next_record(&mut self, record: Record) -> Result<(), TransferError> {
+        let serial = soa_serial(&record);
+        let state = ::std::mem::replace(&mut self.state, State::Done);
+        self.state = match state {
+            State::Start => {
+                match serial {
+                    Some(serial) if record.name == self.zone => {
+                        self.serial = serial;
+                        self.records.push(record);
+                        if self.current.is_some() {
+                            State::Opened
+                        } else {
+                            State::Full
+                        }
+                    }
+                    _ => return Err(TransferError::Malformed("does not start with the SOA")),
+                }
+            }
+            State::Opened => {
+                let ours = self.current.as_ref().and_then(soa_serial);
+                match serial {
+                    // Our own SOA opens the first difference sequence.
+                    Some(serial) if Some(serial) == ours => {
+                        State::Removing(Diff {
+                            from: record.clone(),
+                            to: record,
+                            removed: Vec::new(),
+                            added: Vec::new(),
+                        })
+                    }
+                    // A zone holding nothing but its SOA.
+                    Some(_) => State::Done,
+                    None => {
+                        self.records.push(record);
+                        State::Full
+                    }
+                }
+            }
+            State::Full => {
+                match serial {
+                    Some(_) => State::Done,
+                    None => {
+                        self.records.push(record);
+                        State::Full
+                    }
+                }
+            }
+            State::Removing(mut diff) => {
+                match serial {
+                    Some(_) => {
+                        diff.to = record;
+                        State::Adding(diff)
+                    }
+                    None => {
+                        diff.removed.push(record);
+                        State::Removing(diff)
+                    }
+                }
+            }
+            State::Adding(mut diff) => {
+                match serial {
+                    Some(serial) => {
+                        let to = diff.to_serial();
+                        self.diffs.push(diff);
+                        if serial == self.serial {
+                            State::Done
+                        } else if serial == to {
+                            State::Removing(Diff {
+                                from: record.clone(),
+                                to: record,
+                                removed: Vec::new(),
+                                added: Vec::new(),
+                            })
+                        } else {
+                            return Err(TransferError::Malformed("difference sequences do not \
+                                                                 chain"));
+                        }
+                    }
+                    None => {
+                        diff.added.push(record);
+                        State::Adding(diff)
+                    }
+                }
+            }
+            State::Done => return Err(TransferError::Malformed("records after the closing SOA")),
+        };
+        Ok(())
+    }
+
+    /// What the transfer brought, once it is done.
+    pub fn result(self) -> Result<TransferResult, TransferError> {
+        match self.state {
+            State::Done if !self.diffs.is_empty() => Ok(TransferResult::Incremental(self.diffs)),
+            State::Done if self.records.len() == 1 && !self.is_newer(self.serial) => {
+                Ok(TransferResult::UpToDate)
+            }
+            State::Done => Ok(TransferResult::Full(self.records)),
+            _ => Err(TransferError::Malformed("transfer incomplete")),
+        }
+    }
+}
+
+/// Runs a transfer over a new TCP connection to `server`.
+pub fn fetch(server: &SocketAddr,
+             mut transfer: Transfer,
+             timeout: Duration)
+    -> Result<TransferResult, TransferError> {
+    let mut stream = try!(TcpStream::connect(server));
+    try!(stream.set_read_timeout(Some(timeout)));
+    try!(stream.set_write_timeout(Some(timeout)));
+    try!(stream.write_all(&tcp_frame(&transfer.query()).unwrap()));
+    let mut reader = FrameReader::new();
+    let mut buffer = [0u8; 4096];
+    while !transfer.is_done() {
+        match reader.next_frame() {
+            Some(message) => {
+                try!(transfer.receive(&message));
+            }
+            None => {
+                let n = try!(stream.read(&mut buffer));
+                if n == 0 {
+                    return Err(TransferError::Malformed("connection closed mid-transfer"));
+                }
+                reader.push(&buffer[..n]);
+            }
+        }
+    }
+    transfer.result()
+}
+
+/// Brings a copy of `zone` up to date from `server`: IXFR from `current`
+/// when there is one, falling back to AXFR if the server will not do IXFR.
+/// Requests are signed with `key` if given.
+pub fn refresh(server: &SocketAddr,
+               zone: &Name,
+               current: Option<&Record>,
+               key: Option<&Key>,
+               id: u16,
+               timeout: Duration)
+    -> Result<TransferResult, TransferError> {
+    let signed = |mut transfer: Transfer| {
+        if let Some(key) = key {
+            transfer.set_key(key.clone());
+        }
+        transfer
+    };
+    if let Some(current) = current {
+        let ixfr = signed(Transfer::ixfr(zone.clone(), id, current.clone()));
+        match fetch(server, ixfr, timeout) {
+            Err(TransferError::Rcode(RC_NOT_IMPLEMENTED)) |
+            Err(TransferError::Rcode(RC_FORMAT_ERROR)) => {}
+            result => return result,
+        }
+    }
+    fetch(server, signed(Transfer::axfr(zone.clone(), id.wrapping_add(1))), timeout)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Cursor, Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+    use std::time::Duration;
+    use protocol::{FrameReader, Name, RC_REFUSED, tcp_frame};
+    use server::{Authority, Client};
+    use tsig::{Algorithm, BADKEY, Key, TsigError};
+    use zone::{Zone, ZoneParser};
+
+    fn name(text: &str) -> Name {
+        Name::parse(text, None).unwrap()
+    }
+
+    fn zone(serial: u32, hosts: usize) -> Zone {
+        let mut text = format!("$TTL 300\n@ SOA ns hostmaster {} 1 1 1 1\n@ NS ns\n", serial);
+        for i in 0..hosts {
+            text.push_str(&format!("host{} A 192.0.2.{}\n", i, i % 250));
+        }
+        let records = ZoneParser::new(Cursor::new(text.into_bytes()),
+                                      "test.zone",
+                                      Some(name("example.com.")))
+                          .map(|r| r.unwrap());
+        Zone::from_records(name("example.com."), records)
+    }
+
+    /// A primary on a loopback port, answering over TCP until the test ends.
+    fn primary(authority: Authority) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let peer = stream.peer_addr().unwrap().ip();
+                let mut reader = FrameReader::new();
+                let mut buffer = [0u8; 4096];
+                loop {
+                    let n = stream.read(&mut buffer).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    reader.push(&buffer[..n]);
+                    while let Some(message) = reader.next_frame() {
+                        for response in authority.respond_stream(&message, &peer).unwrap() {
+                            stream.write_all(&tcp_frame(&response).unwrap()).unwrap();
+                        }
+                    }
+                }
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn loopback_transfers() {
+        let mut current = zone(1, 300);
+        current.replace(zone(2, 310));
+        current.replace(zone(3, 305));
+        let expected = current.records();
+        let mut authority = Authority::new();
+        authority.add_zone(current);
+        authority.allow_transfer(name("example.com."),
+                                 vec![Client::Address("127.0.0.1".parse().unwrap())]);
+        let address = primary(authority);
+        let timeout = Duration::from_secs(5);
+
+        // Whole zone, spread over several messages.
+        let records = match fetch(&address, Transfer::axfr(name("example.com."), 1), timeout) {
+            Ok(TransferResult::Full(records)) => records,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(expected, Zone::from_records(name("example.com."), records).records());
+
+        // Incremental, applied on top of the old version.
+        let mut secondary = zone(1, 300);
+        let ixfr = Transfer::ixfr(name("example.com."), 2, secondary.soa_record().unwrap());
+        let diffs = match fetch(&address, ixfr, timeout) {
+            Ok(TransferResult::Incremental(diffs)) => diffs,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(2, diffs.len());
+        for diff in diffs {
+            assert!(secondary.apply(diff));
+        }
+        assert_eq!(expected, secondary.records());
+
+        // Nothing new.
+        let ixfr = Transfer::ixfr(name("example.com."), 3, secondary.soa_record().unwrap());
+        assert_eq!(TransferResult::UpToDate, fetch(&address, ixfr, timeout).unwrap());
+
+        // History the primary does not have falls back to the whole zone.
+        let ixfr = Transfer::ixfr(name("example.com."),
+                                  4,
+                                  zone(0, 0).soa_record().unwrap());
+        match fetch(&address, ixfr, timeout) {
+            Ok(TransferResult::Full(records)) => assert_eq!(expected.len(), records.len()),
+            other => panic!("{:?}", other),
+        }
+
+        match fetch(&address, Transfer::axfr(name("example.org."), 5), timeout) {
+            Err(TransferError::Rcode(RC_REFUSED)) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn short_header() {
+        let mut transfer = Transfer::axfr(name("example.com."), 2);
+        match transfer.receive(&[0, 2, 128, 0, 0]) {
+            Err(TransferError::Malformed("short header")) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn signed_transfers() {
+        let key = Key {
+            name: name("transfer.example.com."),
+            algorithm: Algorithm::HmacSha256,
+            secret: b"shared with the secondaries".to_vec(),
+        };
+        let mut authority = Authority::new();
+        authority.add_zone(zone(1, 300));
+        authority.add_key(key.clone());
+        authority.allow_transfer(name("example.com."), vec![Client::Key(key.name.clone())]);
+        let address = primary(authority);
+        let timeout = Duration::from_secs(5);
+
+        // Every message of a multi-message AXFR is signed and checked.
+        let mut axfr = Transfer::axfr(name("example.com."), 1);
+        axfr.set_key(key.clone());
+        match fetch(&address, axfr, timeout) {
+            Ok(TransferResult::Full(records)) => assert_eq!(302, records.len()),
+            other => panic!("{:?}", other),
+        }
+        let result = refresh(&address, &name("example.com."), None, Some(&key), 2, timeout);
+        assert!(result.is_ok());
+
+        match fetch(&address, Transfer::axfr(name("example.com."), 3), timeout) {
+            Err(TransferError::Rcode(RC_REFUSED)) => {}
+            other => panic!("{:?}", other),
+        }
+        let mut axfr = Transfer::axfr(name("example.com."), 4);
+        axfr.set_key(Key { name: name("stranger."), ..key });
+        match fetch(&address, axfr, timeout) {
+            Err(TransferError::Tsig(TsigError::Reported(BADKEY))) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Single queries over UDP, retried over TCP when the answer comes back
+//! truncated (RFC 7766 Section 5).
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+use protocol::{Header, MAX_FRAME};
+use super::stream::query_tcp;
+
+/// Sends `query` to `server` over UDP and returns the first response to
+/// come back from it with the query's ID, truncated or not.
+pub fn query_udp(server: &SocketAddr, query: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+    let local = match *server {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+    let socket = try!(UdpSocket::bind(local));
+    try!(socket.send_to(query, server));
+    let id = Header::at(query).id();
+    let deadline = Instant::now() + timeout;
+    let mut buffer = vec![0u8; MAX_FRAME];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no answer from server"));
+        }
+        try!(socket.set_read_timeout(Some(deadline - now)));
+        let (n, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                          e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        };
+        let header = Header::at(&buffer[..n]);
+        // Anything else is stray, or someone guessing at our IDs.
+        if from == *server && header.is_response() && header.id() == id {
+            return Ok(buffer[..n].to_vec());
+        }
+    }
+}
+
+/// Asks `server` over UDP, and again over TCP if the answer was truncated,
+/// so the response returned is always complete.
+pub fn exchange(server: &SocketAddr, query: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+    let response = try!(query_udp(server, query, timeout));
+    if !Header::at(&response[..]).is_truncated() {
+        return Ok(response);
+    }
+    let mut responses = try!(query_tcp(server, &[query.to_vec()], timeout));
+    Ok(responses.remove(0))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, UdpSocket};
+    use std::thread;
+    use std::time::Duration;
+    use protocol::{FrameReader, Header, HeaderMut, tcp_frame};
+    use test_util::query;
+
+    #[test]
+    fn truncated_retry() {
+        // The same port number for both, as a real server has.
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = tcp.local_addr().unwrap();
+        let udp = UdpSocket::bind(server).unwrap();
+        let responder = thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            let (n, client) = udp.recv_from(&mut buffer).unwrap();
+            let mut stray = buffer[..n].to_vec();
+            HeaderMut::at_raw(&mut stray[..]).set_qr(true).set_id(99);
+            udp.send_to(&stray, client).unwrap();
+            let mut truncated = buffer[..n].to_vec();
+            HeaderMut::at_raw(&mut truncated[..]).set_qr(true).set_tc(true);
+            udp.send_to(&truncated, client).unwrap();
+
+            let (mut stream, _) = tcp.accept().unwrap();
+            let mut reader = FrameReader::new();
+            let mut queries = Vec::new();
+            while queries.is_empty() {
+                let n = stream.read(&mut buffer).unwrap();
+                reader.push(&buffer[..n]);
+                queries.extend(reader.next_frame());
+            }
+            let mut complete = queries[0].clone();
+            HeaderMut::at_raw(&mut complete[..]).set_qr(true).set_an(0);
+            complete.extend(b"whole answer".iter().cloned());
+            stream.write_all(&tcp_frame(&complete).unwrap()).unwrap();
+        });
+        let message = query(5, "example.com.", 1);
+        let response = exchange(&server, &message, Duration::from_secs(5)).unwrap();
+        let header = Header::at(&response[..]);
+        assert_eq!((Some(5), Some(false)), (header.id(), header.tc()));
+        assert!(response.ends_with(b"whole answer"));
+        responder.join().unwrap();
+    }
+}
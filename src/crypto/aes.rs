@@ -0,0 +1,78 @@
+//! AES-GCM (NIST SP 800-38D), on ring.
+
+use ring::aead::{AES_128_GCM, AES_256_GCM, Aad, LessSafeKey, Nonce, UnboundKey};
+use super::Aead;
+
+/// AES-GCM with a 16 or 32 octet key, 12 octet nonces and 16 octet tags.
+#[derive(Clone)]
+pub struct AesGcm {
+    key: LessSafeKey,
+}
+
+impl AesGcm {
+    pub fn new(key: &[u8]) -> AesGcm {
+        let algorithm = if key.len() == 16 {
+            &AES_128_GCM
+        } else {
+            &AES_256_GCM
+        };
+        AesGcm { key: LessSafeKey::new(UnboundKey::new(algorithm, key).expect("AES key size")) }
+    }
+}
+
+impl Aead for AesGcm {
+    fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let nonce = Nonce::try_assume_unique_for_key(nonce).expect("GCM nonce size");
+        let mut sealed = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(nonce, Aad::from(aad), &mut sealed)
+            .expect("GCM plaintext size");
+        sealed
+    }
+
+    fn open(&self, nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        let nonce = match Nonce::try_assume_unique_for_key(nonce) {
+            Ok(nonce) => nonce,
+            Err(_) => return None,
+        };
+        let mut plaintext = sealed.to_vec();
+        let len = match self.key.open_in_place(nonce, Aad::from(aad), &mut plaintext) {
+            Ok(opened) => opened.len(),
+            Err(_) => return None,
+        };
+        plaintext.truncate(len);
+        Some(plaintext)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::Aead;
+    use protocol::{hex_decode, hex_encode};
+
+    // The GCM specification's test cases 4 and 16.
+    #[test]
+    fn gcm() {
+        let plaintext = hex_decode("d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a\
+                                    318a721c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba63\
+                                    7b39")
+                            .unwrap();
+        let aad = hex_decode("feedfacedeadbeeffeedfacedeadbeefabaddad2").unwrap();
+        let nonce = hex_decode("cafebabefacedbaddecaf888").unwrap();
+        let aead = AesGcm::new(&hex_decode("feffe9928665731c6d6a8f9467308308").unwrap());
+        let sealed = aead.seal(&nonce, &aad, &plaintext);
+        assert_eq!("42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e21d514b2\
+                    5466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091\
+                    5bc94fbc3221a5db94fae95ae7121a47",
+                   hex_encode(&sealed));
+        assert_eq!(Some(plaintext.clone()), aead.open(&nonce, &aad, &sealed));
+        assert_eq!(None, aead.open(&nonce, &aad[1..], &sealed));
+
+        let key = hex_decode("feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308")
+                      .unwrap();
+        let sealed = AesGcm::new(&key).seal(&nonce, &aad, &plaintext);
+        assert_eq!("76fc6ece0f4e1768cddf8853bb2d551b", hex_encode(&sealed[sealed.len() - 16..]));
+    }
+}
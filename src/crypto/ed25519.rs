@@ -0,0 +1,89 @@
+//! Ed25519 signatures (RFC 8032 Section 5.1), on ring.
+
+use std::sync::Arc;
+use ring::signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+
+/// A private key: the 32 octet seed of RFC 8032.
+#[derive(Clone)]
+pub struct Ed25519PrivateKey {
+    pair: Arc<Ed25519KeyPair>,
+}
+
+impl Ed25519PrivateKey {
+    pub fn new(seed: &[u8]) -> Option<Ed25519PrivateKey> {
+        Ed25519KeyPair::from_seed_unchecked(seed)
+            .ok()
+            .map(|pair| Ed25519PrivateKey { pair: Arc::new(pair) })
+    }
+
+    /// Reads a PKCS #8 private key (RFC 8410 Section 7), with or without
+    /// the public key after it.
+    pub fn from_pkcs8(der: &[u8]) -> Option<Ed25519PrivateKey> {
+        Ed25519KeyPair::from_pkcs8_maybe_unchecked(der)
+            .ok()
+            .map(|pair| Ed25519PrivateKey { pair: Arc::new(pair) })
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        self.pair.public_key().as_ref().to_vec()
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        self.pair.sign(data).as_ref().to_vec()
+    }
+}
+
+/// Checks a 64 octet signature against a 32 octet public key.
+pub fn ed25519_verify(public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    UnparsedPublicKey::new(&ED25519, public_key).verify(data, signature).is_ok()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::{hex_decode, hex_encode};
+
+    // RFC 8032 Section 7.1, tests 1 and 2.
+    #[test]
+    fn rfc8032() {
+        let key = Ed25519PrivateKey::new(&hex_decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b3\
+                                                      26919703bac031cae7f60")
+                                              .unwrap())
+                      .unwrap();
+        let public = key.public_key();
+        assert_eq!("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                   hex_encode(&public));
+        let signature = key.sign(b"");
+        assert_eq!("e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590\
+                    a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+                   hex_encode(&signature));
+        assert!(ed25519_verify(&public, b"", &signature));
+        assert!(!ed25519_verify(&public, b"x", &signature));
+
+        let key = Ed25519PrivateKey::new(&hex_decode("4ccd089b28ff96da9db6c346ec114e0f5b8a319f35a\
+                                                      ba624da8cf6ed4fb8a6fb")
+                                              .unwrap())
+                      .unwrap();
+        let signature = key.sign(&[0x72]);
+        assert_eq!("92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e\
+                    15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+                   hex_encode(&signature));
+        assert!(ed25519_verify(&key.public_key(), &[0x72], &signature));
+    }
+
+    #[test]
+    fn pkcs8() {
+        // testdata/tls-ed25519.key.
+        let der = hex_decode("302e020100300506032b6570042204209d21f6da855f850547c6bb636f96c3d298\
+                              2f2e1b6a5cbc683cb7396d5fab8c45")
+                      .unwrap();
+        let key = Ed25519PrivateKey::from_pkcs8(&der).unwrap();
+        assert_eq!("8cf698b6c23ecacf64c6c3dfe257eedff4396c8c27fc269d445ea3fc251c6312",
+                   hex_encode(&key.public_key()));
+        assert!(Ed25519PrivateKey::from_pkcs8(&der[..47]).is_none());
+        let mut other = der.clone();
+        other[15] = 0x21;
+        assert!(Ed25519PrivateKey::from_pkcs8(&other).is_none());
+    }
+}
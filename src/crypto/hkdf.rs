@@ -0,0 +1,72 @@
+//! HKDF (RFC 5869), the extract-then-expand key derivation of HPKE, with
+//! ring doing the expanding.
+
+use ring::hkdf::{self, HKDF_SHA256, HKDF_SHA384, HKDF_SHA512, KeyType, Prk};
+use super::hmac::Hmac;
+use super::sha2::Digest;
+
+// How many octets to expand to, as ring takes it.
+struct Len(usize);
+
+impl KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+/// Concentrates the entropy of `ikm` into a pseudorandom key. ring keeps
+/// its keys opaque, and this one is needed in the open, so it is taken as
+/// the HMAC it is defined to be.
+pub fn hkdf_extract<D: Digest>(salt: &[u8], ikm: &[u8]) -> Vec<u8> {
+    let zeros;
+    let salt = if salt.is_empty() {
+        zeros = vec![0u8; D::output_size()];
+        &zeros[..]
+    } else {
+        salt
+    };
+    Hmac::<D>::mac(salt, ikm)
+}
+
+/// Stretches a pseudorandom key into `len` octets bound to `info`.
+pub fn hkdf_expand<D: Digest>(prk: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    let algorithm: hkdf::Algorithm = match D::output_size() {
+        32 => HKDF_SHA256,
+        48 => HKDF_SHA384,
+        _ => HKDF_SHA512,
+    };
+    let info = [info];
+    let mut output = vec![0; len];
+    Prk::new_less_safe(algorithm, prk)
+        .expand(&info, Len(len))
+        .and_then(|okm| okm.fill(&mut output))
+        .expect("HKDF output too long");
+    output
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::Sha256;
+    use protocol::{hex_decode, hex_encode};
+
+    // RFC 5869 Appendix A, test cases 1 and 3.
+    #[test]
+    fn rfc5869() {
+        let ikm = vec![0x0b; 22];
+        let salt = hex_decode("000102030405060708090a0b0c").unwrap();
+        let info = hex_decode("f0f1f2f3f4f5f6f7f8f9").unwrap();
+        let prk = hkdf_extract::<Sha256>(&salt, &ikm);
+        assert_eq!("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5",
+                   hex_encode(&prk));
+        assert_eq!("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf\
+                    34007208d5b887185865",
+                   hex_encode(&hkdf_expand::<Sha256>(&prk, &info, 42)));
+
+        let prk = hkdf_extract::<Sha256>(&[], &ikm);
+        assert_eq!("8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d\
+                    9d201395faa4b61a96c8",
+                   hex_encode(&hkdf_expand::<Sha256>(&prk, &[], 42)));
+    }
+}
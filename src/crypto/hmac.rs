@@ -0,0 +1,85 @@
+//! HMAC (RFC 2104) over any of the SHA-2 digests, on ring.
+
+use std::marker::PhantomData;
+use ring::hmac::{self, Context, HMAC_SHA256, HMAC_SHA384, HMAC_SHA512, Key};
+use super::sha2::Digest;
+
+// The ring algorithm for the digest of each output size.
+fn algorithm(output_size: usize) -> hmac::Algorithm {
+    match output_size {
+        32 => HMAC_SHA256,
+        48 => HMAC_SHA384,
+        _ => HMAC_SHA512,
+    }
+}
+
+/// An HMAC computation in progress.
+pub struct Hmac<D: Digest> {
+    context: Context,
+    digest: PhantomData<D>,
+}
+
+impl<D: Digest> Hmac<D> {
+    pub fn new(key: &[u8]) -> Hmac<D> {
+        Hmac {
+            context: Context::with_key(&Key::new(algorithm(D::output_size()), key)),
+            digest: PhantomData,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.context.update(data);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.context.sign().as_ref().to_vec()
+    }
+
+    pub fn mac(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut hmac = Hmac::<D>::new(key);
+        hmac.update(data);
+        hmac.finish()
+    }
+}
+
+/// Compares two MACs in time that depends only on their length.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::{Sha256, Sha384, Sha512};
+    use protocol::hex_encode;
+
+    // RFC 4231 test cases 2 and 6.
+    #[test]
+    fn rfc4231() {
+        let data = b"what do ya want for nothing?";
+        assert_eq!("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+                   hex_encode(&Hmac::<Sha256>::mac(b"Jefe", data)));
+        assert_eq!("af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e\
+                    8e2240ca5e69e2c78b3239ecfab21649",
+                   hex_encode(&Hmac::<Sha384>::mac(b"Jefe", data)));
+        assert_eq!("164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+                    9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+                   hex_encode(&Hmac::<Sha512>::mac(b"Jefe", data)));
+
+        let key = vec![0xaa; 131];
+        let data = b"Test Using Larger Than Block-Size Key - Hash Key First";
+        assert_eq!("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+                   hex_encode(&Hmac::<Sha256>::mac(&key, data)));
+    }
+
+    #[test]
+    fn comparison() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
@@ -0,0 +1,179 @@
+//! HPKE (RFC 9180) in base mode, for the one suite Oblivious DoH requires:
+//! DHKEM(X25519, HKDF-SHA256), HKDF-SHA256 and AES-128-GCM.
+
+use super::Aead;
+use super::aes::AesGcm;
+use super::hkdf::{hkdf_expand, hkdf_extract};
+use super::sha2::Sha256;
+use super::x25519::{X25519_BASE, x25519, x25519_keypair};
+
+/// The identifiers of the suite, as HPKE's registries number them.
+pub const KEM_X25519_SHA256: u16 = 0x0020;
+pub const KDF_HKDF_SHA256: u16 = 0x0001;
+pub const AEAD_AES_128_GCM: u16 = 0x0001;
+
+const KEY_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+fn be16(value: u16) -> [u8; 2] {
+    [(value >> 8) as u8, value as u8]
+}
+
+// LabeledExtract and LabeledExpand (RFC 9180 Section 4).
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> Vec<u8> {
+    let mut labeled = b"HPKE-v1".to_vec();
+    labeled.extend_from_slice(suite_id);
+    labeled.extend_from_slice(label);
+    labeled.extend_from_slice(ikm);
+    hkdf_extract::<Sha256>(salt, &labeled)
+}
+
+fn labeled_expand(suite_id: &[u8], prk: &[u8], label: &[u8], info: &[u8], len: usize)
+    -> Vec<u8> {
+    let mut labeled = be16(len as u16).to_vec();
+    labeled.extend_from_slice(b"HPKE-v1");
+    labeled.extend_from_slice(suite_id);
+    labeled.extend_from_slice(label);
+    labeled.extend_from_slice(info);
+    hkdf_expand::<Sha256>(prk, &labeled, len)
+}
+
+// The shared secret of DHKEM (Section 4.1), or None if the public key is of
+// small order.
+fn kem_shared_secret(secret: &[u8], public: &[u8], enc: &[u8], recipient: &[u8])
+    -> Option<Vec<u8>> {
+    let dh = x25519(secret, public);
+    if dh.iter().all(|&b| b == 0) {
+        return None;
+    }
+    let mut suite_id = b"KEM".to_vec();
+    suite_id.extend_from_slice(&be16(KEM_X25519_SHA256));
+    let mut kem_context = enc.to_vec();
+    kem_context.extend_from_slice(recipient);
+    let prk = labeled_extract(&suite_id, &[], b"eae_prk", &dh);
+    Some(labeled_expand(&suite_id, &prk, b"shared_secret", &kem_context, 32))
+}
+
+/// One end of an HPKE exchange: messages sealed or opened in turn, and
+/// secrets exported.
+pub struct HpkeContext {
+    aead: AesGcm,
+    base_nonce: Vec<u8>,
+    exporter_secret: Vec<u8>,
+    suite_id: Vec<u8>,
+    seq: u64,
+}
+
+impl HpkeContext {
+    /// Sets up the sender for the recipient's public key (SetupBaseS).
+    /// Returns the encapsulated key to send along, or None if the public
+    /// key is no good.
+    pub fn sender(public: &[u8], info: &[u8]) -> Option<(Vec<u8>, HpkeContext)> {
+        let (secret, enc) = x25519_keypair();
+        let shared_secret = match kem_shared_secret(&secret, public, &enc, public) {
+            Some(shared_secret) => shared_secret,
+            None => return None,
+        };
+        Some((enc, HpkeContext::key_schedule(&shared_secret, info)))
+    }
+
+    /// Sets up the recipient from the encapsulated key (SetupBaseR).
+    pub fn recipient(enc: &[u8], secret: &[u8], info: &[u8]) -> Option<HpkeContext> {
+        if enc.len() != 32 {
+            return None;
+        }
+        let public = x25519(secret, &X25519_BASE);
+        kem_shared_secret(secret, enc, enc, &public)
+            .map(|shared_secret| HpkeContext::key_schedule(&shared_secret, info))
+    }
+
+    fn key_schedule(shared_secret: &[u8], info: &[u8]) -> HpkeContext {
+        let mut suite_id = b"HPKE".to_vec();
+        suite_id.extend_from_slice(&be16(KEM_X25519_SHA256));
+        suite_id.extend_from_slice(&be16(KDF_HKDF_SHA256));
+        suite_id.extend_from_slice(&be16(AEAD_AES_128_GCM));
+        // Mode base: no pre-shared key.
+        let mut context = vec![0];
+        context.extend(labeled_extract(&suite_id, &[], b"psk_id_hash", &[]));
+        context.extend(labeled_extract(&suite_id, &[], b"info_hash", info));
+        let secret = labeled_extract(&suite_id, shared_secret, b"secret", &[]);
+        let key = labeled_expand(&suite_id, &secret, b"key", &context, KEY_LEN);
+        HpkeContext {
+            aead: AesGcm::new(&key),
+            base_nonce: labeled_expand(&suite_id, &secret, b"base_nonce", &context, NONCE_LEN),
+            exporter_secret: labeled_expand(&suite_id, &secret, b"exp", &context, 32),
+            suite_id: suite_id,
+            seq: 0,
+        }
+    }
+
+    fn nonce(&self) -> Vec<u8> {
+        let mut nonce = self.base_nonce.clone();
+        for i in 0..8 {
+            nonce[NONCE_LEN - 1 - i] ^= (self.seq >> (8 * i)) as u8;
+        }
+        nonce
+    }
+
+    pub fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let sealed = self.aead.seal(&self.nonce(), aad, plaintext);
+        self.seq += 1;
+        sealed
+    }
+
+    /// The plaintext, or None if the ciphertext or associated data were
+    /// tampered with.
+    pub fn open(&mut self, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        let plaintext = self.aead.open(&self.nonce(), aad, sealed);
+        if plaintext.is_some() {
+            self.seq += 1;
+        }
+        plaintext
+    }
+
+    /// A secret of `len` octets both ends derive for `exporter_context`.
+    pub fn export(&self, exporter_context: &[u8], len: usize) -> Vec<u8> {
+        labeled_expand(&self.suite_id,
+                       &self.exporter_secret,
+                       b"sec",
+                       exporter_context,
+                       len)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::hex_decode;
+
+    #[test]
+    fn seal_and_open() {
+        let secret: Vec<u8> = (0..32).collect();
+        let public = x25519(&secret, &X25519_BASE);
+
+        // Sealed by another implementation for this key.
+        let message = hex_decode("9aa69ed9185385532ab7d3ef3edeeff431858182b1cdad66f59d960b8c1c81\
+                                  53abf73c3235efbdd91343538ae088324150b5c723b7e56fdacc")
+                          .unwrap();
+        let mut recipient = HpkeContext::recipient(&message[..32], &secret, b"odoh query")
+                                .unwrap();
+        assert_eq!(Some(b"odoh test".to_vec()), recipient.open(&[], &message[32..]));
+        let mut recipient = HpkeContext::recipient(&message[..32], &secret, b"other").unwrap();
+        assert_eq!(None, recipient.open(&[], &message[32..]));
+
+        let (enc, mut sender) = HpkeContext::sender(&public, b"info").unwrap();
+        let mut recipient = HpkeContext::recipient(&enc, &secret, b"info").unwrap();
+        let first = sender.seal(b"aad", b"first");
+        let second = sender.seal(b"aad", b"second");
+        assert_eq!(None, recipient.open(b"aad", &second));
+        assert_eq!(None, recipient.open(b"other", &first));
+        assert_eq!(Some(b"first".to_vec()), recipient.open(b"aad", &first));
+        assert_eq!(Some(b"second".to_vec()), recipient.open(b"aad", &second));
+        assert_eq!(sender.export(b"context", 16), recipient.export(b"context", 16));
+        assert!(sender.export(b"context", 16) != sender.export(b"other", 16));
+
+        assert!(HpkeContext::sender(&[0; 32], b"info").is_none());
+        assert!(HpkeContext::recipient(&enc[..31], &secret, b"info").is_none());
+    }
+}
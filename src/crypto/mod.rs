@@ -0,0 +1,36 @@
+//! The cryptographic primitives DNS message authentication and the
+//! encrypted transports need, behind one small interface: hashes, MACs,
+//! RSA and Ed25519 signatures and AES-GCM on ring, X25519 on x25519-dalek
+//! and NaCl's boxes on crypto_secretbox. HKDF and HPKE are built here from
+//! those.
+
+mod aes;
+mod ed25519;
+mod hkdf;
+mod hmac;
+mod hpke;
+mod random;
+mod rsa;
+mod secretbox;
+mod sha2;
+mod x25519;
+
+pub use self::aes::AesGcm;
+pub use self::ed25519::{Ed25519PrivateKey, ed25519_verify};
+pub use self::hkdf::{hkdf_expand, hkdf_extract};
+pub use self::hmac::{Hmac, constant_time_eq};
+pub use self::hpke::{AEAD_AES_128_GCM, HpkeContext, KDF_HKDF_SHA256, KEM_X25519_SHA256};
+pub use self::random::{fill_random, random_bytes};
+pub use self::rsa::{RsaPrivateKey, RsaPublicKey};
+pub use self::secretbox::{BoxCipher, SecretBox};
+pub use self::sha2::{Digest, Sha256, Sha384, Sha512};
+pub use self::x25519::{X25519_BASE, x25519, x25519_keypair};
+
+/// Authenticated encryption with associated data, as TLS 1.3 and its
+/// relatives use it: the tag is appended to the ciphertext.
+pub trait Aead {
+    fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8>;
+    /// The plaintext, or None if the ciphertext or associated data were
+    /// tampered with.
+    fn open(&self, nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>>;
+}
@@ -0,0 +1,19 @@
+//! Random octets for keys and nonces, from the operating system by way of
+//! ring.
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Fills `buffer` with random octets. Panics if the system source cannot
+/// be read, as nothing secure can be done without it.
+pub fn fill_random(buffer: &mut [u8]) {
+    if SystemRandom::new().fill(buffer).is_err() {
+        panic!("cannot read the system's random source");
+    }
+}
+
+/// `len` random octets.
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut buffer = vec![0u8; len];
+    fill_random(&mut buffer);
+    buffer
+}
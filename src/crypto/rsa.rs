@@ -0,0 +1,227 @@
+//! RSASSA-PKCS1-v1_5 signatures (RFC 8017 Section 8.2) over SHA-2, as
+//! DNSSEC and SIG(0) use them, on ring.
+
+use std::sync::Arc;
+use ring::rand::SystemRandom;
+use ring::rsa::KeyPairComponents;
+use ring::signature::{self, RsaKeyPair, RsaPublicKeyComponents};
+use super::sha2::Digest;
+
+// Big endian integers as ring takes them: without leading zeros.
+fn trim(value: &[u8]) -> &[u8] {
+    let zeros = value.iter().take_while(|&&b| b == 0).count();
+    &value[zeros..]
+}
+
+#[derive(Clone, Debug)]
+pub struct RsaPublicKey {
+    modulus: Vec<u8>,
+    exponent: Vec<u8>,
+}
+
+impl RsaPublicKey {
+    /// Takes big endian modulus and public exponent. Returns None for an
+    /// even modulus, which no real key has.
+    pub fn new(modulus: &[u8], exponent: &[u8]) -> Option<RsaPublicKey> {
+        let modulus = trim(modulus);
+        if modulus.last().map_or(true, |&b| b & 1 == 0) {
+            return None;
+        }
+        Some(RsaPublicKey {
+            modulus: modulus.to_vec(),
+            exponent: trim(exponent).to_vec(),
+        })
+    }
+
+    /// Reads the DNSKEY and KEY public key field (RFC 3110 Section 2).
+    pub fn from_dns(data: &[u8]) -> Option<RsaPublicKey> {
+        let (exponent_len, at) = match data.get(0) {
+            Some(&0) if data.len() > 3 => (((data[1] as usize) << 8) | data[2] as usize, 3),
+            Some(&len) => (len as usize, 1),
+            None => return None,
+        };
+        if data.len() <= at + exponent_len {
+            return None;
+        }
+        RsaPublicKey::new(&data[at + exponent_len..], &data[at..at + exponent_len])
+    }
+
+    pub fn to_dns(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        if self.exponent.len() > 255 {
+            data.push(0);
+            data.push((self.exponent.len() >> 8) as u8);
+        }
+        data.push(self.exponent.len() as u8);
+        data.extend_from_slice(&self.exponent);
+        data.extend_from_slice(&self.modulus);
+        data
+    }
+
+    /// The modulus size in octets, which is also the signature size.
+    pub fn len(&self) -> usize {
+        self.modulus.len()
+    }
+
+    /// Checks a signature. Keys down to 1024 bits are taken, as DNSSEC
+    /// still has them.
+    pub fn verify<D: Digest>(&self, data: &[u8], signature: &[u8]) -> bool {
+        let parameters = match D::output_size() {
+            32 => &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+            48 => &signature::RSA_PKCS1_2048_8192_SHA384,
+            _ => &signature::RSA_PKCS1_1024_8192_SHA512_FOR_LEGACY_USE_ONLY,
+        };
+        let key = RsaPublicKeyComponents {
+            n: &self.modulus,
+            e: &self.exponent,
+        };
+        key.verify(parameters, data, signature).is_ok()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RsaPrivateKey {
+    public: RsaPublicKey,
+    pair: Arc<RsaKeyPair>,
+}
+
+impl RsaPrivateKey {
+    /// Takes the private exponent, the primes p and q, the exponents d mod
+    /// (p - 1) and d mod (q - 1), and the coefficient q^-1 mod p, all big
+    /// endian. Returns None if they do not make a key of 2048 to 4096 bits,
+    /// the sizes ring signs with.
+    pub fn new(public: RsaPublicKey,
+               private_exponent: &[u8],
+               primes: (&[u8], &[u8]),
+               exponents: (&[u8], &[u8]),
+               coefficient: &[u8])
+        -> Option<RsaPrivateKey> {
+        let components = KeyPairComponents {
+            public_key: RsaPublicKeyComponents {
+                n: &public.modulus[..],
+                e: &public.exponent[..],
+            },
+            d: trim(private_exponent),
+            p: trim(primes.0),
+            q: trim(primes.1),
+            dP: trim(exponents.0),
+            dQ: trim(exponents.1),
+            qInv: trim(coefficient),
+        };
+        match RsaKeyPair::from_components(&components) {
+            Ok(pair) => {
+                Some(RsaPrivateKey {
+                    public: public,
+                    pair: Arc::new(pair),
+                })
+            }
+            Err(_) => None,
+        }
+    }
+
+    pub fn public(&self) -> &RsaPublicKey {
+        &self.public
+    }
+
+    /// Signs `data`; None if the key is too small for the digest.
+    pub fn sign<D: Digest>(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let padding = match D::output_size() {
+            32 => &signature::RSA_PKCS1_SHA256,
+            48 => &signature::RSA_PKCS1_SHA384,
+            _ => &signature::RSA_PKCS1_SHA512,
+        };
+        let mut signature = vec![0; self.pair.public().modulus_len()];
+        match self.pair.sign(padding, &SystemRandom::new(), data, &mut signature) {
+            Ok(()) => Some(signature),
+            Err(_) => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use std::io::Read;
+    use crypto::{Sha256, Sha512};
+    use protocol::{base64_decode, hex_decode};
+
+    // Made with `openssl dgst -sha256 -sign` and the key in testdata.
+    const SIGNATURE: &'static str = "1676e7db9a8f05ce2e50c3a663f6189992a0e0c3b527a6bf700986bf1f897\
+                                     185a9d974a3b70c38b4a55fc347b20ba94553e27f7652fead63b0cdf0ef4a\
+                                     5e6d8e32e52e19384513af7ca534f2ba23f8b4296604cff011c7401807138\
+                                     d63a0fc26ee891d169b86577f4d509f2f8560fb67f5359ea2a03b64ab8c3b\
+                                     2f397806fbbd9f5061cf62293c20694646d9b296b86ac3b27700ca0b98604\
+                                     c603fe85bbb10ed30c07909a83c4c1bbf6ad801d2d5eb1813d217e4b86a5d\
+                                     dae46ea934d05a49449c6da0c73e9e642d8fbb22bfea7344bb8eeab561527\
+                                     9149d168c412ac915b7a050c9b2620ccf265c5b5b68da3927e830dc827053\
+                                     249da3830373cf20250b0004";
+
+    // A 1024 bit key and a signature it made with `openssl dgst -sha256
+    // -sign`: too small for ring to sign with, but still to be checked.
+    const MODULUS: &'static str = "d35d86afd46e7eb3d348dd8f7fc789796f813b26ff4bde1b1b90baa3d514d0\
+                                   11bb0f1555780a8cee4b3c370a6e9f9cefd69ee354702443334c0c354676\
+                                   6a9de457772effda31151a3a486fa0ae04257f1d477c08778a32bc93d01e\
+                                   f5bb060587e1e8188f4c601633d53ecd01925f8d01198b97b1f3c1232e59\
+                                   13806ab79680eb";
+    const LEGACY_SIGNATURE: &'static str = "181409fa3f9af01d150d95ff9391846249cca7820eea6eeaa1\
+                                            0d3ed544a2a080aa48c026651d832bb1ad1e7def0ba9d634e9\
+                                            4093c96b4695407fd79ccefb19073b6f5714a32e1f6d77c57b\
+                                            2989ee4f38f8b21c9885ced0c76f4db6b3cade5e7de087d83d\
+                                            ab720fe7fc7d0bbc0152e99e16cc36e9affe84fb7c5dd44cce\
+                                            89b362";
+
+    // The fields of the key in testdata, which is in BIND's format.
+    fn fields() -> Vec<(String, Vec<u8>)> {
+        let mut text = String::new();
+        File::open("testdata/Kupdate.example.com.+008+55112.private")
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .unwrap();
+        text.lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ": ");
+                let name = parts.next().unwrap().to_string();
+                parts.next().and_then(base64_decode).map(|value| (name, value))
+            })
+            .collect()
+    }
+
+    fn key(primes: (&str, &str)) -> Option<RsaPrivateKey> {
+        let fields = fields();
+        let field = |name: &str| &fields.iter().find(|&&(ref n, _)| n == name).unwrap().1[..];
+        let public = RsaPublicKey::new(field("Modulus"), field("PublicExponent")).unwrap();
+        RsaPrivateKey::new(public,
+                           field("PrivateExponent"),
+                           (field(primes.0), field(primes.1)),
+                           (field("Exponent1"), field("Exponent2")),
+                           field("Coefficient"))
+    }
+
+    #[test]
+    fn pkcs1_signatures() {
+        let private = key(("Prime1", "Prime2")).unwrap();
+        let public = private.public().clone();
+        assert_eq!(256, public.len());
+        let data = b"SIG(0) test data";
+        let signature = hex_decode(SIGNATURE).unwrap();
+        assert_eq!(Some(signature.clone()), private.sign::<Sha256>(data));
+        assert!(public.verify::<Sha256>(data, &signature));
+        assert!(!public.verify::<Sha256>(b"other data", &signature));
+        assert!(!public.verify::<Sha512>(data, &signature));
+
+        let signature = private.sign::<Sha512>(data).unwrap();
+        assert!(public.verify::<Sha512>(data, &signature));
+
+        let dns = public.to_dns();
+        assert_eq!(&[3, 1, 0, 1, 0x9c], &dns[..5]);
+        assert!(RsaPublicKey::from_dns(&dns).unwrap().verify::<Sha512>(data, &signature));
+        assert!(RsaPublicKey::from_dns(&[3, 1, 0]).is_none());
+
+        let legacy = RsaPublicKey::new(&hex_decode(MODULUS).unwrap(), &[1, 0, 1]).unwrap();
+        assert!(legacy.verify::<Sha256>(data, &hex_decode(LEGACY_SIGNATURE).unwrap()));
+
+        // Parts that do not go together make no key.
+        assert!(key(("Prime2", "Prime1")).is_none());
+    }
+}
@@ -0,0 +1,142 @@
+//! NaCl's boxes: XSalsa20-Poly1305 as crypto_secretbox makes it, and the
+//! XChaCha20 variant of libsodium, on the crypto_secretbox crate, with keys
+//! agreed by X25519.
+
+use crypto_secretbox::{Kdf, Key, KeyInit, Nonce, XChaCha20Poly1305, XSalsa20Poly1305};
+use crypto_secretbox::aead::Aead;
+use super::x25519::x25519;
+
+// The key and nonce types of crypto_secretbox, which panics on the wrong
+// sizes as they would.
+fn key(data: &[u8]) -> Key {
+    let mut key = [0u8; 32];
+    key.copy_from_slice(data);
+    Key::from(key)
+}
+
+fn nonce(data: &[u8]) -> Nonce {
+    let mut nonce = [0u8; 24];
+    nonce.copy_from_slice(data);
+    Nonce::from(nonce)
+}
+
+/// The stream cipher a box is sealed with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoxCipher {
+    XSalsa20,
+    XChaCha20,
+}
+
+/// A box keyed with 32 octets, taking 24 octet nonces; sealed boxes are
+/// the Poly1305 tag followed by the ciphertext.
+#[derive(Clone)]
+pub struct SecretBox {
+    cipher: BoxCipher,
+    key: Key,
+}
+
+impl SecretBox {
+    pub fn new(cipher: BoxCipher, key: &[u8]) -> SecretBox {
+        SecretBox {
+            cipher: cipher,
+            key: self::key(key),
+        }
+    }
+
+    /// The box a secret and a public X25519 key share (crypto_box_beforenm),
+    /// or None if the public key is of small order.
+    pub fn between(cipher: BoxCipher, secret: &[u8], public: &[u8]) -> Option<SecretBox> {
+        let shared = x25519(secret, public);
+        if shared.iter().all(|&b| b == 0) {
+            return None;
+        }
+        let (shared, zeros) = (key(&shared), Default::default());
+        let key = match cipher {
+            BoxCipher::XSalsa20 => XSalsa20Poly1305::kdf(&shared, &zeros),
+            BoxCipher::XChaCha20 => XChaCha20Poly1305::kdf(&shared, &zeros),
+        };
+        Some(SecretBox {
+            cipher: cipher,
+            key: key,
+        })
+    }
+
+    pub fn cipher(&self) -> BoxCipher {
+        self.cipher
+    }
+
+    pub fn seal(&self, nonce: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let nonce = self::nonce(nonce);
+        match self.cipher {
+            BoxCipher::XSalsa20 => XSalsa20Poly1305::new(&self.key).encrypt(&nonce, plaintext),
+            BoxCipher::XChaCha20 => XChaCha20Poly1305::new(&self.key).encrypt(&nonce, plaintext),
+        }
+        .expect("boxes take any plaintext")
+    }
+
+    /// The plaintext, or None if the box was tampered with.
+    pub fn open(&self, nonce: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        let nonce = self::nonce(nonce);
+        match self.cipher {
+            BoxCipher::XSalsa20 => XSalsa20Poly1305::new(&self.key).decrypt(&nonce, sealed),
+            BoxCipher::XChaCha20 => XChaCha20Poly1305::new(&self.key).decrypt(&nonce, sealed),
+        }
+        .ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::X25519_BASE;
+    use protocol::{hex_decode, hex_encode};
+
+    const SUNSCREEN: &'static [u8] = b"Ladies and Gentlemen of the class of '99: If I could \
+                                      offer you only one tip for the future, sunscreen would \
+                                      be it.";
+
+    // Checked against libsodium's crypto_secretbox_easy,
+    // crypto_secretbox_xchacha20poly1305_easy and their beforenm.
+    #[test]
+    fn libsodium() {
+        let key = hex_decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+                      .unwrap();
+        let nonce = hex_decode("404142434445464748494a4b4c4d4e4f5051525354555657").unwrap();
+        let cases = [(BoxCipher::XSalsa20,
+                      "e58aaa24bccf2e2e581422691c64399f067631105f229b4ba83d51260de72e9d\
+                       ab5a233a4d2c39e890bd47ea9ab5e227a7140677ce90ff3d636c551f11bb7a4c\
+                       efc14f0987e1d620eda09bcb6802bedaf1a0c18534a1999d2f8de125eb217713\
+                       262a153e99696d43ed029761a243bbb5e0835a2dd33e5292182519187ca72d7f\
+                       f209"),
+                     (BoxCipher::XChaCha20,
+                      "4d8a5e79ec0d04f42e62339148e9611d73d660e72d12b88420dc31d25e9ea81a\
+                       c20accbcb15bbcc8d9014039ccbbd7aba7196a16f0c7402fb5d4ced88fd545f1\
+                       fdcfc1a0333635fc0f43dd3c667103ff78957b4e3afce1308bdb846c6cb5a8d1\
+                       ef0318c809f0c0e9c49924d460c7222739599d177a9332a7f06fd4dfbe1cbc86\
+                       82e9")];
+        for &(cipher, expected) in &cases {
+            let secret_box = SecretBox::new(cipher, &key);
+            let sealed = secret_box.seal(&nonce, SUNSCREEN);
+            assert_eq!(expected, hex_encode(&sealed));
+            assert_eq!(Some(SUNSCREEN.to_vec()), secret_box.open(&nonce, &sealed));
+            assert_eq!(Some(vec![]), secret_box.open(&nonce, &secret_box.seal(&nonce, b"")));
+            let mut forged = sealed.clone();
+            forged[20] ^= 1;
+            assert_eq!(None, secret_box.open(&nonce, &forged));
+            assert_eq!(None, secret_box.open(&nonce, &sealed[..15]));
+        }
+
+        let secret = hex_decode("a0a1a2a3a4a5a6a7a8a9aaabacadaeafb0b1b2b3b4b5b6b7b8b9babbbcbdbebf")
+                         .unwrap();
+        let public = x25519(&secret, &X25519_BASE);
+        assert_eq!("605a725d2a4adfeeb1a29e17edd621c1b7593ee8cdbc44ac6c4ab6e2f805d23c",
+                   hex_encode(&public));
+        let salsa = SecretBox::between(BoxCipher::XSalsa20, &secret, &public).unwrap();
+        assert_eq!("98be7c07b27e7a7487d8668b1a4cc21b09bf92683b9d1aa18d1288405ae8fb17",
+                   hex_encode(&salsa.key));
+        let chacha = SecretBox::between(BoxCipher::XChaCha20, &secret, &public).unwrap();
+        assert_eq!("022a7f61a81b6c92367c280f8fc30496d13456b8f7cafea90e1ebaaeddb9b3bc",
+                   hex_encode(&chacha.key));
+        assert!(SecretBox::between(BoxCipher::XSalsa20, &secret, &[0; 32]).is_none());
+    }
+}
@@ -0,0 +1,118 @@
+//! SHA-256, SHA-384 and SHA-512 (FIPS 180-4), on ring.
+
+use ring::digest::{Context, SHA256, SHA384, SHA512};
+
+/// An incremental hash function.
+pub trait Digest {
+    fn new() -> Self;
+    fn update(&mut self, data: &[u8]);
+    fn finish(self) -> Vec<u8>;
+    fn output_size() -> usize;
+
+    fn digest(data: &[u8]) -> Vec<u8>
+        where Self: Sized
+    {
+        let mut hash = Self::new();
+        hash.update(data);
+        hash.finish()
+    }
+}
+
+pub struct Sha256(Context);
+
+impl Digest for Sha256 {
+    fn new() -> Sha256 {
+        Sha256(Context::new(&SHA256))
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.0.finish().as_ref().to_vec()
+    }
+
+    fn output_size() -> usize {
+        32
+    }
+}
+
+pub struct Sha384(Context);
+
+impl Digest for Sha384 {
+    fn new() -> Sha384 {
+        Sha384(Context::new(&SHA384))
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.0.finish().as_ref().to_vec()
+    }
+
+    fn output_size() -> usize {
+        48
+    }
+}
+
+pub struct Sha512(Context);
+
+impl Digest for Sha512 {
+    fn new() -> Sha512 {
+        Sha512(Context::new(&SHA512))
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.0.finish().as_ref().to_vec()
+    }
+
+    fn output_size() -> usize {
+        64
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::hex_encode;
+
+    const TWO_BLOCKS: &'static [u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+
+    #[test]
+    fn sha256() {
+        assert_eq!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+                   hex_encode(&Sha256::digest(b"abc")));
+        assert_eq!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                   hex_encode(&Sha256::digest(b"")));
+        assert_eq!("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+                   hex_encode(&Sha256::digest(TWO_BLOCKS)));
+        // Fed in pieces that straddle the block boundary.
+        let mut hash = Sha256::new();
+        for chunk in TWO_BLOCKS.chunks(7) {
+            hash.update(chunk);
+        }
+        assert_eq!(Sha256::digest(TWO_BLOCKS), hash.finish());
+    }
+
+    #[test]
+    fn sha512_and_sha384() {
+        assert_eq!("ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                    2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+                   hex_encode(&Sha512::digest(b"abc")));
+        assert_eq!("cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed\
+                    8086072ba1e7cc2358baeca134c825a7",
+                   hex_encode(&Sha384::digest(b"abc")));
+        let million = vec![b'a'; 1000000];
+        assert_eq!("e718483d0ce769644e2e42c7bc15b4638e1f98b13b2044285632a803afa973eb\
+                    de0ff244877ea60a4cb0432ce577c31beb009c5c2c49aa2e4eadb217ad8cc09b",
+                   hex_encode(&Sha512::digest(&million)));
+    }
+}
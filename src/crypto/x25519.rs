@@ -0,0 +1,60 @@
+//! X25519 Diffie-Hellman (RFC 7748 Section 5), on x25519-dalek.
+
+use x25519_dalek;
+use super::random::random_bytes;
+
+/// The u coordinate of the base point.
+pub const X25519_BASE: [u8; 32] = x25519_dalek::X25519_BASEPOINT_BYTES;
+
+// The first 32 octets of `data`.
+fn array(data: &[u8]) -> [u8; 32] {
+    let mut array = [0u8; 32];
+    array.copy_from_slice(&data[..32]);
+    array
+}
+
+/// Multiplies the point with u coordinate `u` by the scalar `k`, both 32
+/// octets little endian.
+pub fn x25519(k: &[u8], u: &[u8]) -> Vec<u8> {
+    x25519_dalek::x25519(array(k), array(u)).to_vec()
+}
+
+/// A fresh key pair: the private scalar and the public u coordinate.
+pub fn x25519_keypair() -> (Vec<u8>, Vec<u8>) {
+    let private = random_bytes(32);
+    let public = x25519(&private, &X25519_BASE);
+    (private, public)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::{hex_decode, hex_encode};
+
+    // RFC 7748 Sections 5.2 and 6.1.
+    #[test]
+    fn rfc7748() {
+        let k = hex_decode("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4")
+                    .unwrap();
+        let u = hex_decode("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c")
+                    .unwrap();
+        assert_eq!("c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552",
+                   hex_encode(&x25519(&k, &u)));
+
+        let alice = hex_decode("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a")
+                        .unwrap();
+        let bob = hex_decode("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb")
+                      .unwrap();
+        let alice_public = x25519(&alice, &X25519_BASE);
+        assert_eq!("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a",
+                   hex_encode(&alice_public));
+        let shared = x25519(&bob, &alice_public);
+        assert_eq!("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742",
+                   hex_encode(&shared));
+        assert_eq!(shared, x25519(&alice, &x25519(&bob, &X25519_BASE)));
+
+        let (private, public) = x25519_keypair();
+        assert_eq!(public, x25519(&private, &X25519_BASE));
+    }
+}
@@ -0,0 +1,157 @@
+//! Resolver certificates: a resolver public key and the client magic that
+//! selects it, good for a span of time, signed by the provider.
+
+use crypto::{BoxCipher, Ed25519PrivateKey, ed25519_verify};
+
+pub const CERT_MAGIC: &'static [u8] = b"DNSC";
+
+/// The encryption system versions: the box cipher a certificate's key is
+/// used with.
+pub const ES_XSALSA20: u16 = 1;
+pub const ES_XCHACHA20: u16 = 2;
+
+/// Octets of a certificate without extensions.
+pub const CERT_SIZE: usize = 124;
+
+// Where the signed part starts, after the magic, versions and signature.
+const SIGNED_START: usize = 72;
+
+fn be32(data: &[u8]) -> u32 {
+    (data[0] as u32) << 24 | (data[1] as u32) << 16 | (data[2] as u32) << 8 | data[3] as u32
+}
+
+fn push_be32(wire: &mut Vec<u8>, value: u32) {
+    wire.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8,
+                             value as u8]);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Certificate {
+    pub cipher: BoxCipher,
+    /// The resolver's X25519 public key.
+    pub resolver_key: Vec<u8>,
+    /// What queries boxed for this key start with.
+    pub client_magic: Vec<u8>,
+    pub serial: u32,
+    /// Seconds since the epoch the certificate is good from and until.
+    pub valid_from: u32,
+    pub valid_until: u32,
+    signature: Vec<u8>,
+}
+
+impl Certificate {
+    /// Signs a certificate for a resolver key with the provider's key. The
+    /// client magic is the start of the resolver key.
+    pub fn sign(key: &Ed25519PrivateKey,
+                cipher: BoxCipher,
+                resolver_key: &[u8],
+                serial: u32,
+                valid_from: u32,
+                valid_until: u32)
+        -> Certificate {
+        let mut certificate = Certificate {
+            cipher: cipher,
+            resolver_key: resolver_key.to_vec(),
+            client_magic: resolver_key[..8].to_vec(),
+            serial: serial,
+            valid_from: valid_from,
+            valid_until: valid_until,
+            signature: Vec::new(),
+        };
+        certificate.signature = key.sign(&certificate.to_wire()[SIGNED_START..]);
+        certificate
+    }
+
+    /// Reads a certificate and checks its signature by the provider's
+    /// public key. Returns None for anything malformed, forged, or for an
+    /// unknown encryption system.
+    pub fn parse(data: &[u8], provider_key: &[u8]) -> Option<Certificate> {
+        if data.len() < CERT_SIZE || &data[..4] != CERT_MAGIC || data[6..8] != [0, 0] {
+            return None;
+        }
+        let cipher = match (data[4] as u16) << 8 | data[5] as u16 {
+            ES_XSALSA20 => BoxCipher::XSalsa20,
+            ES_XCHACHA20 => BoxCipher::XChaCha20,
+            _ => return None,
+        };
+        if !ed25519_verify(provider_key, &data[SIGNED_START..], &data[8..SIGNED_START]) {
+            return None;
+        }
+        Some(Certificate {
+            cipher: cipher,
+            resolver_key: data[72..104].to_vec(),
+            client_magic: data[104..112].to_vec(),
+            serial: be32(&data[112..]),
+            valid_from: be32(&data[116..]),
+            valid_until: be32(&data[120..]),
+            signature: data[8..SIGNED_START].to_vec(),
+        })
+    }
+
+    pub fn es_version(&self) -> u16 {
+        match self.cipher {
+            BoxCipher::XSalsa20 => ES_XSALSA20,
+            BoxCipher::XChaCha20 => ES_XCHACHA20,
+        }
+    }
+
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut wire = Vec::with_capacity(CERT_SIZE);
+        wire.extend_from_slice(CERT_MAGIC);
+        let version = self.es_version();
+        wire.extend_from_slice(&[(version >> 8) as u8, version as u8, 0, 0]);
+        // Zeros while signing.
+        wire.extend_from_slice(&self.signature);
+        wire.resize(SIGNED_START, 0);
+        wire.extend_from_slice(&self.resolver_key);
+        wire.extend_from_slice(&self.client_magic);
+        push_be32(&mut wire, self.serial);
+        push_be32(&mut wire, self.valid_from);
+        push_be32(&mut wire, self.valid_until);
+        wire
+    }
+
+    /// Whether the certificate is good at `time`, in seconds since the
+    /// epoch.
+    pub fn is_current(&self, time: u64) -> bool {
+        time >= self.valid_from as u64 && time <= self.valid_until as u64
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::{BoxCipher, Ed25519PrivateKey};
+    use test_util::NOW;
+
+    #[test]
+    fn sign_and_parse() {
+        let key = Ed25519PrivateKey::new(&[7; 32]).unwrap();
+        let resolver_key: Vec<u8> = (0..32).collect();
+        let certificate = Certificate::sign(&key,
+                                            BoxCipher::XChaCha20,
+                                            &resolver_key,
+                                            3,
+                                            NOW as u32,
+                                            NOW as u32 + 86400);
+        let wire = certificate.to_wire();
+        assert_eq!(CERT_SIZE, wire.len());
+        assert_eq!(b"DNSC\x00\x02\x00\x00", &wire[..8]);
+        assert_eq!(&resolver_key[..8], &wire[104..112]);
+        assert_eq!(b"\x00\x00\x00\x03\x6a\xd4\x0c\x00\x6a\xd5\x5d\x80", &wire[112..]);
+        assert_eq!(Some(certificate.clone()), Certificate::parse(&wire, &key.public_key()));
+        assert!(certificate.is_current(NOW) && certificate.is_current(NOW + 86400));
+        assert!(!certificate.is_current(NOW - 1) && !certificate.is_current(NOW + 86401));
+
+        let other = Ed25519PrivateKey::new(&[8; 32]).unwrap();
+        assert_eq!(None, Certificate::parse(&wire, &other.public_key()));
+        let mut forged = wire.clone();
+        forged[115] = 4;
+        assert_eq!(None, Certificate::parse(&forged, &key.public_key()));
+        let mut unknown = wire.clone();
+        unknown[5] = 3;
+        assert_eq!(None, Certificate::parse(&unknown, &key.public_key()));
+        assert_eq!(None, Certificate::parse(&wire[..100], &key.public_key()));
+    }
+}
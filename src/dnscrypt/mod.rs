@@ -0,0 +1,98 @@
+//! DNSCrypt version 2: queries boxed with X25519 and XSalsa20-Poly1305 or
+//! XChaCha20-Poly1305 under a resolver key, which the provider vouches for
+//! with certificates signed by its Ed25519 key and served in the clear as
+//! TXT records.
+//!
+//! A query on the wire is the client magic of the certificate it was made
+//! for, the client's public key, half a nonce and the boxed query; the
+//! response is the resolver magic, the whole nonce and the boxed response.
+//! Both are padded to a multiple of 64 octets before boxing.
+
+mod certificate;
+mod stamp;
+
+pub use self::certificate::{CERT_MAGIC, CERT_SIZE, Certificate, ES_XCHACHA20, ES_XSALSA20};
+pub use self::stamp::{PROP_DNSSEC, PROP_NO_FILTER, PROP_NO_LOGS, Stamp};
+
+/// The start of every response.
+pub const RESOLVER_MAGIC: &'static [u8] = b"r6fnvWj8";
+
+/// Octets of the client magic, and of the client's half of the nonce.
+pub const CLIENT_MAGIC_LEN: usize = 8;
+pub const HALF_NONCE_LEN: usize = 12;
+
+/// The shortest a padded query sent over UDP may be, so responses to
+/// forged sources can be no bigger than the queries.
+pub const MIN_QUERY_LEN: usize = 256;
+
+/// The most a client pads UDP queries to as answers keep coming back
+/// truncated: the packet still fits in the IPv6 minimum MTU.
+pub const MAX_QUERY_LEN: usize = 1152;
+
+/// What a box adds to the padded message: the tag.
+pub const TAG_LEN: usize = 16;
+
+/// Octets a query packet has before its box.
+pub const QUERY_HEADER_LEN: usize = CLIENT_MAGIC_LEN + 32 + HALF_NONCE_LEN;
+
+/// Octets a response packet has before its box.
+pub const RESPONSE_HEADER_LEN: usize = 8 + 2 * HALF_NONCE_LEN;
+
+/// The length `len` octets are padded to: the next multiple of 64 with
+/// room for the 0x80 marker, and no less than `min_len`.
+pub fn padded_len(len: usize, min_len: usize) -> usize {
+    let padded = (len + 64) / 64 * 64;
+    if padded < min_len { (min_len + 63) / 64 * 64 } else { padded }
+}
+
+/// Pads a message ISO/IEC 7816-4 style to `len`: 0x80, then zeros.
+pub fn pad(message: &[u8], len: usize) -> Vec<u8> {
+    let mut padded = Vec::with_capacity(len);
+    padded.extend_from_slice(message);
+    padded.push(0x80);
+    padded.resize(len, 0);
+    padded
+}
+
+/// The message inside its padding, or None if the padding is bad.
+pub fn unpad(padded: &[u8]) -> Option<&[u8]> {
+    match padded.iter().rposition(|&b| b != 0) {
+        Some(end) if padded[end] == 0x80 => Some(&padded[..end]),
+        _ => None,
+    }
+}
+
+/// The longest response that seals into at most `len` octets. Over UDP
+/// that is the length of the query, which responses may not exceed.
+pub fn max_response(len: usize) -> usize {
+    let room = len.saturating_sub(RESPONSE_HEADER_LEN + TAG_LEN) / 64 * 64;
+    room.saturating_sub(1)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn padding() {
+        assert_eq!(64, padded_len(0, 0));
+        assert_eq!(64, padded_len(63, 0));
+        assert_eq!(128, padded_len(64, 0));
+        assert_eq!(256, padded_len(40, MIN_QUERY_LEN));
+        assert_eq!(320, padded_len(40, 300));
+
+        let padded = pad(b"query\x80\x00", 64);
+        assert_eq!(64, padded.len());
+        assert_eq!(Some(&b"query\x80\x00"[..]), unpad(&padded));
+        assert_eq!(None, unpad(&[0; 64]));
+        assert_eq!(None, unpad(b"query\x81\x00"));
+
+        // The smallest UDP query leaves room for 255 octets of response.
+        let query_len = QUERY_HEADER_LEN + MIN_QUERY_LEN + TAG_LEN;
+        assert_eq!(255, max_response(query_len));
+        let response_len = RESPONSE_HEADER_LEN + padded_len(255, 0) + TAG_LEN;
+        assert!(response_len <= query_len);
+        assert_eq!(0, max_response(20));
+    }
+}
@@ -0,0 +1,155 @@
+//! DNS stamps for DNSCrypt resolvers, the `sdns://` URIs resolver lists
+//! give them as: base64url of the protocol, the properties the resolver
+//! claims, its address, the provider's public key and the provider name,
+//! each of the last three after its length.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str;
+use client::DNSCRYPT_PORT;
+use protocol::{Name, base64url_decode, base64url_encode};
+
+// The protocol of DNSCrypt stamps; other values are for other transports.
+const PROTOCOL_DNSCRYPT: u8 = 0x01;
+
+/// Properties a resolver claims: it validates DNSSEC, keeps no logs, and
+/// filters nothing.
+pub const PROP_DNSSEC: u64 = 1;
+pub const PROP_NO_LOGS: u64 = 2;
+pub const PROP_NO_FILTER: u64 = 4;
+
+/// A DNSCrypt resolver as its stamp gives it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stamp {
+    pub props: u64,
+    pub address: SocketAddr,
+    /// The Ed25519 key the provider signs certificates with.
+    pub provider_key: Vec<u8>,
+    pub provider: Name,
+}
+
+// The field at `at` after its length, moving `at` past it.
+fn field<'a>(data: &'a [u8], at: &mut usize) -> Option<&'a [u8]> {
+    let len = match data.get(*at) {
+        Some(&len) => len as usize,
+        None => return None,
+    };
+    if data.len() < *at + 1 + len {
+        return None;
+    }
+    let field = &data[*at + 1..*at + 1 + len];
+    *at += 1 + len;
+    Some(field)
+}
+
+// An address with an optional port, IPv6 in brackets, port 443 if none is
+// given.
+fn parse_address(text: &str) -> Option<SocketAddr> {
+    if let Ok(address) = text.parse() {
+        return Some(address);
+    }
+    let ip = if text.starts_with('[') && text.ends_with(']') {
+        &text[1..text.len() - 1]
+    } else {
+        text
+    };
+    ip.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, DNSCRYPT_PORT))
+}
+
+impl Stamp {
+    /// Reads an `sdns://` stamp. None unless it is a DNSCrypt one with an
+    /// address, a key of 32 octets and a provider name.
+    pub fn parse(text: &str) -> Option<Stamp> {
+        if !text.starts_with("sdns://") {
+            return None;
+        }
+        let data = match base64url_decode(&text[7..]) {
+            Some(data) => data,
+            None => return None,
+        };
+        if data.len() < 9 || data[0] != PROTOCOL_DNSCRYPT {
+            return None;
+        }
+        let props = data[1..9].iter().rev().fold(0, |props, &b| props << 8 | b as u64);
+        let mut at = 9;
+        let address = field(&data, &mut at)
+                          .and_then(|address| str::from_utf8(address).ok())
+                          .and_then(parse_address);
+        let provider_key = field(&data, &mut at);
+        let provider = field(&data, &mut at)
+                           .and_then(|provider| str::from_utf8(provider).ok())
+                           .and_then(|provider| Name::parse(provider, Some(&Name::root())));
+        match (address, provider_key, provider) {
+            (Some(address), Some(key), Some(provider)) if key.len() == 32 &&
+                                                          at == data.len() => {
+                Some(Stamp {
+                    props: props,
+                    address: address,
+                    provider_key: key.to_vec(),
+                    provider: provider,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Stamp {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut data = vec![PROTOCOL_DNSCRYPT];
+        data.extend((0..8).map(|i| (self.props >> (8 * i)) as u8));
+        let address = match self.address {
+            SocketAddr::V4(ref address) if address.port() == DNSCRYPT_PORT => {
+                address.ip().to_string()
+            }
+            SocketAddr::V6(ref address) if address.port() == DNSCRYPT_PORT => {
+                format!("[{}]", address.ip())
+            }
+            address => address.to_string(),
+        };
+        let provider = self.provider.to_string();
+        let provider = provider.trim_right_matches('.');
+        for field in &[address.as_bytes(), &self.provider_key[..], provider.as_bytes()] {
+            data.push(field.len() as u8);
+            data.extend_from_slice(field);
+        }
+        write!(fmt, "sdns://{}", base64url_encode(&data))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::Name;
+
+    const STAMP: &'static str = "sdns://AQcAAAAAAAAACjE5Mi4wLjIuNTMgAQIDBAUGBwgJCgsMDQ4PEBESExQVF\
+                                 hcYGRobHB0eHyAbMi5kbnNjcnlwdC1jZXJ0LmV4YW1wbGUuY29t";
+
+    const IPV6_STAMP: &'static str = "sdns://AQEAAAAAAAAAE1syMDAxOmRiODo6NTNdOjg0NDMgAQIDBAUGBwgJC\
+                                      gsMDQ4PEBESExQVFhcYGRobHB0eHyAbMi5kbnNjcnlwdC1jZXJ0LmV4YW1w\
+                                      bGUuY29t";
+
+    #[test]
+    fn parse_and_write() {
+        let stamp = Stamp::parse(STAMP).unwrap();
+        assert_eq!(Stamp {
+                       props: PROP_DNSSEC | PROP_NO_LOGS | PROP_NO_FILTER,
+                       address: "192.0.2.53:443".parse().unwrap(),
+                       provider_key: (1..33).collect(),
+                       provider: Name::parse("2.dnscrypt-cert.example.com.", None).unwrap(),
+                   },
+                   stamp);
+        assert_eq!(STAMP, stamp.to_string());
+
+        let stamp = Stamp::parse(IPV6_STAMP).unwrap();
+        assert_eq!(("[2001:db8::53]:8443".parse().unwrap(), PROP_DNSSEC),
+                   (stamp.address, stamp.props));
+        assert_eq!(IPV6_STAMP, stamp.to_string());
+
+        // DNS over HTTPS, cut short, and not a stamp.
+        assert_eq!(None, Stamp::parse("sdns://AgAAAAAAAAAA"));
+        assert_eq!(None, Stamp::parse(&STAMP[..STAMP.len() - 4]));
+        assert_eq!(None, Stamp::parse(&STAMP[1..]));
+    }
+}
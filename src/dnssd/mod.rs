@@ -0,0 +1,435 @@
+//! DNS-based service discovery (RFC 6763), over multicast DNS on the link
+//! or unicast DNS alike.
+//!
+//! The PTR records of a service type, such as `_ipp._tcp.local.`, list its
+//! instances, such as `Office Printer._ipp._tcp.local.`: the instance
+//! label is free text, shown to people as it is. Each instance has an SRV
+//! record naming its host and port, and a TXT record of attributes.
+
+mod txt;
+
+pub use self::txt::{parse_txt, txt_strings, txt_value};
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use client::exchange;
+use crypto::random_bytes;
+use mdns::{MdnsSocket, Querier, Responder};
+use protocol::{Header, HeaderMut, MessageCursor, Name, QuestionMut, RData, Record};
+use protocol::rdata::{CLASS_IN, TYPE_A, TYPE_AAAA, TYPE_PTR, TYPE_SRV, TYPE_TXT};
+
+/// The name, under a domain, whose PTR records list the service types
+/// found there (Section 9).
+pub const SERVICE_TYPES: &'static str = "_services._dns-sd._udp";
+
+/// TTLs for SRV records, which name a host, and for the rest (RFC 6762
+/// Section 10).
+pub const HOST_TTL: u32 = 120;
+pub const OTHER_TTL: u32 = 4500;
+
+/// A service instance, resolved.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceInstance {
+    /// The instance label, service type and domain.
+    pub name: Name,
+    pub host: Name,
+    pub port: u16,
+    pub priority: u16,
+    pub weight: u16,
+    /// The attributes of its TXT record.
+    pub txt: Vec<(String, Option<Vec<u8>>)>,
+    /// The host's addresses, if they were found.
+    pub addresses: Vec<IpAddr>,
+}
+
+impl ServiceInstance {
+    /// The instance named `name` as `records` describe it, or None if they
+    /// hold no SRV record for it.
+    pub fn from_records(name: &Name, records: &[Record]) -> Option<ServiceInstance> {
+        let mut instance = None;
+        let mut txt = None;
+        for record in records.iter().filter(|r| r.name == *name) {
+            match record.rdata {
+                RData::Srv { priority, weight, port, ref target } if instance.is_none() => {
+                    instance = Some(ServiceInstance {
+                        name: name.clone(),
+                        host: target.clone(),
+                        port: port,
+                        priority: priority,
+                        weight: weight,
+                        txt: Vec::new(),
+                        addresses: Vec::new(),
+                    });
+                }
+                RData::Txt(ref strings) if txt.is_none() => txt = Some(parse_txt(strings)),
+                _ => {}
+            }
+        }
+        let mut instance = match instance {
+            Some(instance) => instance,
+            None => return None,
+        };
+        instance.txt = txt.unwrap_or(Vec::new());
+        let host = instance.host.clone();
+        for record in records.iter().filter(|r| r.name == host) {
+            let address = match record.rdata {
+                RData::A(address) => IpAddr::V4(address),
+                RData::Aaaa(address) => IpAddr::V6(address),
+                _ => continue,
+            };
+            if !instance.addresses.contains(&address) {
+                instance.addresses.push(address);
+            }
+        }
+        Some(instance)
+    }
+
+    /// The instance label, as people see it.
+    pub fn instance(&self) -> String {
+        instance_label(&self.name)
+    }
+}
+
+/// The name of the instance `instance` of `service`, or None if the label
+/// is too long.
+pub fn instance_name(instance: &str, service: &Name) -> Option<Name> {
+    service.prepend(instance.as_bytes())
+}
+
+/// The instance label of an instance name, unescaped.
+pub fn instance_label(name: &Name) -> String {
+    match name.labels().first() {
+        Some(label) => String::from_utf8_lossy(label).into_owned(),
+        None => String::new(),
+    }
+}
+
+/// The instances of `service` the PTR records in `records` list.
+pub fn instances(service: &Name, records: &[Record]) -> Vec<Name> {
+    let mut instances: Vec<Name> = Vec::new();
+    for record in records.iter().filter(|r| r.name == *service) {
+        if let RData::Ptr(ref instance) = record.rdata {
+            if record.ttl > 0 && !instances.contains(instance) {
+                instances.push(instance.clone());
+            }
+        }
+    }
+    instances
+}
+
+/// The records that make `instance` an instance of its service on `host`
+/// and `port`, and whether each is unique to us. Returns None if the name
+/// is not under a service type and domain.
+pub fn service_records(instance: &Name,
+                       host: &Name,
+                       port: u16,
+                       txt: &[(String, Option<Vec<u8>>)])
+                       -> Option<Vec<(Record, bool)>> {
+    if instance.label_count() < 4 {
+        return None;
+    }
+    let service = instance.parent().unwrap();
+    let domain = service.parent().and_then(|n| n.parent()).unwrap();
+    let types = match Name::parse(SERVICE_TYPES, Some(&domain)) {
+        Some(types) => types,
+        None => return None,
+    };
+    let srv = RData::Srv {
+        priority: 0,
+        weight: 0,
+        port: port,
+        target: host.clone(),
+    };
+    Some(vec![(Record::new(service.clone(), CLASS_IN, OTHER_TTL, RData::Ptr(instance.clone())),
+               false),
+              (Record::new(instance.clone(), CLASS_IN, HOST_TTL, srv), true),
+              (Record::new(instance.clone(), CLASS_IN, OTHER_TTL, RData::Txt(txt_strings(txt))),
+               true),
+              (Record::new(types, CLASS_IN, OTHER_TTL, RData::Ptr(service)), false)])
+}
+
+/// Registers `instance` with a multicast DNS responder. Should another
+/// host have the name, the responder numbers it; `registered` tells what
+/// it became.
+pub fn register(responder: &mut Responder,
+                instance: &Name,
+                host: &Name,
+                port: u16,
+                txt: &[(String, Option<Vec<u8>>)],
+                now: Instant)
+                -> bool {
+    match service_records(instance, host, port, txt) {
+        Some(records) => {
+            for (record, unique) in records {
+                responder.publish(record, unique, now);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// The name instances registered with `responder` for `service` ended up
+/// with.
+pub fn registered(responder: &Responder, service: &Name) -> Vec<Name> {
+    let records: Vec<Record> = responder.records().into_iter().cloned().collect();
+    instances(service, &records)
+}
+
+/// Where service records come from.
+pub trait Lookup {
+    /// Asks for `name` and `qtype`, and returns the records learned: the
+    /// answers, and whatever else came with them.
+    fn lookup(&mut self, name: &Name, qtype: u16) -> io::Result<Vec<Record>>;
+}
+
+/// Looks up over multicast DNS, caching what the link says.
+#[derive(Debug)]
+pub struct MdnsLookup {
+    socket: MdnsSocket,
+    querier: Querier,
+    timeout: Duration,
+}
+
+impl MdnsLookup {
+    /// Asks over `socket`, waiting up to `timeout` for answers.
+    pub fn new(socket: MdnsSocket, timeout: Duration) -> MdnsLookup {
+        MdnsLookup {
+            socket: socket,
+            querier: Querier::new(),
+            timeout: timeout,
+        }
+    }
+}
+
+impl Lookup for MdnsLookup {
+    fn lookup(&mut self, name: &Name, qtype: u16) -> io::Result<Vec<Record>> {
+        let now = Instant::now();
+        let deadline = now + self.timeout;
+        self.querier.expire(now);
+        if qtype == TYPE_PTR {
+            // Every host with an instance answers, so all are waited for.
+            try!(self.socket.send(&self.querier.query(name, qtype, false, now), None));
+            try!(self.querier.listen(&self.socket, deadline));
+        } else if self.querier.lookup(name, qtype, now).is_empty() {
+            try!(self.querier.ask(&self.socket, name, qtype, false, deadline));
+        }
+        Ok(self.querier.records(Instant::now()))
+    }
+}
+
+/// Looks up with a unicast DNS server, for service discovery in domains
+/// other than `local.`.
+#[derive(Debug)]
+pub struct UnicastLookup {
+    server: SocketAddr,
+    timeout: Duration,
+}
+
+impl UnicastLookup {
+    pub fn new(server: SocketAddr, timeout: Duration) -> UnicastLookup {
+        UnicastLookup {
+            server: server,
+            timeout: timeout,
+        }
+    }
+}
+
+impl Lookup for UnicastLookup {
+    fn lookup(&mut self, name: &Name, qtype: u16) -> io::Result<Vec<Record>> {
+        let id = random_bytes(2);
+        let mut query = vec![0u8; name.wire_len() + 16];
+        let mut idx = MessageCursor::new(query.len());
+        HeaderMut::at(&mut idx, &mut query[..])
+            .unwrap()
+            .make_query((id[0] as u16) << 8 | id[1] as u16)
+            .set_qd(1);
+        QuestionMut::at(&mut idx, &mut query, &name.segments(), qtype, CLASS_IN).unwrap();
+        query.truncate(idx.tell());
+        let response = try!(exchange(&self.server, &query, self.timeout));
+
+        let header = Header::at(&response[..]);
+        let mut records = Vec::new();
+        let mut at = header.questions().end_offset();
+        for count in vec![header.an(), header.ns(), header.ar()] {
+            match (at, count) {
+                (Some(start), Some(count)) => {
+                    at = Record::read_section(&response, start, count).map(|(section, end)| {
+                        records.extend(section);
+                        end
+                    })
+                }
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed response")),
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// The instances of `service`.
+pub fn browse<L: Lookup>(lookup: &mut L, service: &Name) -> io::Result<Vec<Name>> {
+    let records = try!(lookup.lookup(service, TYPE_PTR));
+    Ok(instances(service, &records))
+}
+
+/// The host, port, attributes and addresses of `instance`, or None if it
+/// has no SRV record.
+pub fn resolve<L: Lookup>(lookup: &mut L, instance: &Name) -> io::Result<Option<ServiceInstance>> {
+    let mut records = try!(lookup.lookup(instance, TYPE_SRV));
+    let has_txt = records.iter().any(|r| r.name == *instance && r.rtype() == TYPE_TXT);
+    if !has_txt {
+        records.extend(try!(lookup.lookup(instance, TYPE_TXT)));
+    }
+    let resolved = match ServiceInstance::from_records(instance, &records) {
+        Some(resolved) => resolved,
+        None => return Ok(None),
+    };
+    if !resolved.addresses.is_empty() {
+        return Ok(Some(resolved));
+    }
+    records.extend(try!(lookup.lookup(&resolved.host, TYPE_A)));
+    if !records.iter().any(|r| r.name == resolved.host && r.rtype() == TYPE_A) {
+        records.extend(try!(lookup.lookup(&resolved.host, TYPE_AAAA)));
+    }
+    Ok(ServiceInstance::from_records(instance, &records))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{IpAddr, UdpSocket};
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use mdns::{MDNS_IPV4, MdnsMessage, MdnsSocket, Responder};
+    use protocol::{Name, RData, Record};
+    use protocol::rdata::{CLASS_IN, TYPE_SRV};
+
+    fn name(text: &str) -> Name {
+        Name::parse(text, None).unwrap()
+    }
+
+    fn printer() -> Name {
+        instance_name("Office Printer", &name("_ipp._tcp.local.")).unwrap()
+    }
+
+    fn attributes() -> Vec<(String, Option<Vec<u8>>)> {
+        vec![("rp".to_string(), Some(b"ipp/print".to_vec())), ("color".to_string(), None)]
+    }
+
+    fn address() -> Record {
+        Record::new(name("printer.local."), CLASS_IN, 120, RData::A("192.0.2.5".parse().unwrap()))
+    }
+
+    fn resolved() -> ServiceInstance {
+        ServiceInstance {
+            name: printer(),
+            host: name("printer.local."),
+            port: 631,
+            priority: 0,
+            weight: 0,
+            txt: attributes(),
+            addresses: vec!["192.0.2.5".parse().unwrap()],
+        }
+    }
+
+    #[test]
+    fn records() {
+        assert_eq!("Office Printer", instance_label(&printer()));
+        assert_eq!(b"Office Printer", &printer().labels()[0][..]);
+        assert_eq!(None, instance_name(&"x".repeat(64), &name("_ipp._tcp.local.")));
+
+        let records = service_records(&printer(), &name("printer.local."), 631, &attributes())
+                          .unwrap();
+        assert_eq!(4, records.len());
+        assert_eq!((Record::new(name("_ipp._tcp.local."),
+                                CLASS_IN,
+                                OTHER_TTL,
+                                RData::Ptr(printer())),
+                    false),
+                   records[0]);
+        assert_eq!((TYPE_SRV, HOST_TTL, true),
+                   (records[1].0.rtype(), records[1].0.ttl, records[1].1));
+        assert_eq!(RData::Txt(vec![b"rp=ipp/print".to_vec(), b"color".to_vec()]),
+                   records[2].0.rdata);
+        assert_eq!(name("_services._dns-sd._udp.local."), records[3].0.name);
+        assert_eq!(RData::Ptr(name("_ipp._tcp.local.")), records[3].0.rdata);
+        assert_eq!(None, service_records(&name("_ipp._tcp.local."), &printer(), 631, &[]));
+
+        let mut published: Vec<Record> = records.into_iter().map(|r| r.0).collect();
+        published.push(address());
+        published.push(address());
+        assert_eq!(Some(resolved()), ServiceInstance::from_records(&printer(), &published));
+        assert_eq!(None, ServiceInstance::from_records(&name("printer.local."), &published));
+
+        let mut goodbye = published[0].clone();
+        goodbye.rdata = RData::Ptr(name("Scanner._ipp._tcp.local."));
+        goodbye.ttl = 0;
+        published.push(goodbye);
+        assert_eq!(vec![printer()], instances(&name("_ipp._tcp.local."), &published));
+
+        let mut responder = Responder::new();
+        assert!(register(&mut responder,
+                         &printer(),
+                         &name("printer.local."),
+                         631,
+                         &[],
+                         Instant::now()));
+        assert_eq!(vec![printer()], registered(&responder, &name("_ipp._tcp.local.")));
+    }
+
+    #[test]
+    fn over_mdns() {
+        let port = UdpSocket::bind("0.0.0.0:0").unwrap().local_addr().unwrap().port();
+        let group = IpAddr::V4(MDNS_IPV4);
+        let socket = MdnsSocket::join(group, port).unwrap();
+        let (claimed, rx) = channel();
+        let thread = thread::spawn(move || {
+            let mut responder = Responder::new();
+            register(&mut responder,
+                     &printer(),
+                     &name("printer.local."),
+                     631,
+                     &attributes(),
+                     Instant::now());
+            responder.publish(address(), true, Instant::now());
+            while !responder.is_claimed(&printer()) {
+                responder.run(&socket, Some(Instant::now() + Duration::from_millis(100))).unwrap();
+            }
+            claimed.send(()).unwrap();
+            responder.run(&socket, Some(Instant::now() + Duration::from_secs(2))).unwrap();
+        });
+        rx.recv().unwrap();
+
+        let socket = MdnsSocket::one_shot(group, port).unwrap();
+        let mut lookup = MdnsLookup::new(socket, Duration::from_millis(500));
+        assert_eq!(vec![printer()], browse(&mut lookup, &name("_ipp._tcp.local.")).unwrap());
+        assert_eq!(Some(resolved()), resolve(&mut lookup, &printer()).unwrap());
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn over_unicast() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let local = server.local_addr().unwrap();
+        let thread = thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            let (n, from) = server.recv_from(&mut buffer).unwrap();
+            let query = MdnsMessage::parse(&buffer[..n]).unwrap();
+            assert_eq!(vec![(printer(), TYPE_SRV, CLASS_IN)], query.questions);
+            let mut response = MdnsMessage::new(query.id, true);
+            response.questions = query.questions;
+            let records = service_records(&printer(), &name("printer.local."), 631, &attributes())
+                              .unwrap();
+            response.answers.push(records[1].0.clone());
+            response.additionals.push(records[2].0.clone());
+            response.additionals.push(address());
+            server.send_to(&response.to_wire(), from).unwrap();
+        });
+        let mut lookup = UnicastLookup::new(local, Duration::from_secs(2));
+        assert_eq!(Some(resolved()), resolve(&mut lookup, &printer()).unwrap());
+        thread.join().unwrap();
+    }
+}
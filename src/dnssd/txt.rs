@@ -0,0 +1,84 @@
+//! The key/value pairs of a service's TXT record (RFC 6763 Section 6).
+
+/// The attributes in a TXT record's strings, in order: a key alone is a
+/// boolean attribute, with no value, and `key=` has an empty value. Keys
+/// are lowercased; strings with no key, and repeats of a key, are ignored.
+pub fn parse_txt(strings: &[Vec<u8>]) -> Vec<(String, Option<Vec<u8>>)> {
+    let mut attributes: Vec<(String, Option<Vec<u8>>)> = Vec::new();
+    for string in strings {
+        let (key, value) = match string.iter().position(|&b| b == b'=') {
+            Some(at) => (&string[..at], Some(string[at + 1..].to_vec())),
+            None => (&string[..], None),
+        };
+        if key.is_empty() || key.iter().any(|&b| b < 0x20 || b > 0x7e) {
+            continue;
+        }
+        let key = String::from_utf8_lossy(key).to_lowercase();
+        if !attributes.iter().any(|a| a.0 == key) {
+            attributes.push((key, value));
+        }
+    }
+    attributes
+}
+
+/// The strings of a TXT record for `attributes`. A record with none has
+/// one empty string, as TXT records must have at least one.
+pub fn txt_strings(attributes: &[(String, Option<Vec<u8>>)]) -> Vec<Vec<u8>> {
+    if attributes.is_empty() {
+        return vec![Vec::new()];
+    }
+    attributes.iter()
+              .map(|&(ref key, ref value)| {
+                  let mut string = key.as_bytes().to_vec();
+                  if let Some(ref value) = *value {
+                      string.push(b'=');
+                      string.extend_from_slice(value);
+                  }
+                  string
+              })
+              .collect()
+}
+
+/// The value of `key` in `attributes`: None if it is absent, Some(None) if
+/// it is there without a value.
+pub fn txt_value<'a>(attributes: &'a [(String, Option<Vec<u8>>)], key: &str)
+    -> Option<Option<&'a [u8]>> {
+    let key = key.to_lowercase();
+    attributes.iter()
+              .find(|a| a.0 == key)
+              .map(|a| a.1.as_ref().map(|v| &v[..]))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn attributes() {
+        let strings: Vec<Vec<u8>> = vec![b"txtvers=1".to_vec(),
+                                         b"PaperSize=A4".to_vec(),
+                                         b"color".to_vec(),
+                                         b"note=".to_vec(),
+                                         b"papersize=Letter".to_vec(),
+                                         b"=nokey".to_vec(),
+                                         Vec::new(),
+                                         b"bin=\x00=\xff".to_vec()];
+        let attributes = parse_txt(&strings);
+        assert_eq!(vec![("txtvers".to_string(), Some(b"1".to_vec())),
+                        ("papersize".to_string(), Some(b"A4".to_vec())),
+                        ("color".to_string(), None),
+                        ("note".to_string(), Some(Vec::new())),
+                        ("bin".to_string(), Some(b"\x00=\xff".to_vec()))],
+                   attributes);
+        assert_eq!(Some(Some(&b"A4"[..])), txt_value(&attributes, "PAPERSIZE"));
+        assert_eq!(Some(None), txt_value(&attributes, "color"));
+        assert_eq!(Some(Some(&b""[..])), txt_value(&attributes, "note"));
+        assert_eq!(None, txt_value(&attributes, "duplex"));
+
+        assert_eq!(attributes, parse_txt(&txt_strings(&attributes)));
+        assert_eq!(b"color".to_vec(), txt_strings(&attributes)[2]);
+        assert_eq!(vec![Vec::<u8>::new()], txt_strings(&[]));
+        assert!(parse_txt(&txt_strings(&[])).is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+//! The DNSSEC signature algorithms supported (RFC 8624 Section 3.1 lists
+//! the ones worth having).
+
+use crypto::{RsaPublicKey, Sha256, Sha512, ed25519_verify};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    RsaSha256,
+    RsaSha512,
+    Ed25519,
+}
+
+const ALGORITHMS: &'static [(Algorithm, u8, &'static str)] =
+    &[(Algorithm::RsaSha256, 8, "RSASHA256"),
+      (Algorithm::RsaSha512, 10, "RSASHA512"),
+      (Algorithm::Ed25519, 15, "ED25519")];
+
+impl Algorithm {
+    pub fn from_number(number: u8) -> Option<Algorithm> {
+        ALGORITHMS.iter().find(|&&(_, n, _)| n == number).map(|&(a, _, _)| a)
+    }
+
+    /// The number in DNSKEY, KEY and SIG records.
+    pub fn number(&self) -> u8 {
+        ALGORITHMS.iter().find(|&&(a, _, _)| a == *self).unwrap().1
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        ALGORITHMS.iter().find(|&&(a, _, _)| a == *self).unwrap().2
+    }
+
+    /// Checks `signature` over `data` against a public key in the form it
+    /// takes in DNSKEY and KEY records.
+    pub fn verify(&self, public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+        match *self {
+            Algorithm::RsaSha256 => {
+                RsaPublicKey::from_dns(public_key)
+                    .map_or(false, |key| key.verify::<Sha256>(data, signature))
+            }
+            Algorithm::RsaSha512 => {
+                RsaPublicKey::from_dns(public_key)
+                    .map_or(false, |key| key.verify::<Sha512>(data, signature))
+            }
+            Algorithm::Ed25519 => ed25519_verify(public_key, data, signature),
+        }
+    }
+}
+
+/// The key tag of DNSKEY or KEY rdata (RFC 4034 Appendix B).
+pub fn key_tag(rdata: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    for (i, &b) in rdata.iter().enumerate() {
+        sum += if i % 2 == 0 {
+            (b as u32) << 8
+        } else {
+            b as u32
+        };
+    }
+    sum += sum >> 16;
+    sum as u16
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::{RData, base64_decode};
+
+    #[test]
+    fn key_tags() {
+        // The example.com. KSK of RFC 4034 Section 5.4.
+        let rdata = RData::Dnskey {
+            flags: 256,
+            protocol: 3,
+            algorithm: 5,
+            public_key: base64_decode("AQOeiiR0GOMYkDshWoSKz9XzfwJr1AYtsmx3TGkJaNXVbfi/2pHm822\
+                                       aJ5iI9BMzNXxeYCmZDRD99WYwYqUSdjMmmAphXdvxegXd/M5+X7OrzKB\
+                                       aMbCVdFLUUh6DhweJBjEVv5f2wwjM9XzcnOf+EPbtG9DMBmADjFDc2w/\
+                                       rljwvFw==")
+                            .unwrap(),
+        };
+        assert_eq!(60485, key_tag(&rdata.to_wire()));
+        assert_eq!(Some(Algorithm::Ed25519), Algorithm::from_number(15));
+        assert_eq!(None, Algorithm::from_number(5));
+        assert_eq!((10, "RSASHA512"),
+                   (Algorithm::RsaSha512.number(), Algorithm::RsaSha512.mnemonic()));
+    }
+}
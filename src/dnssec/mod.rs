@@ -0,0 +1,11 @@
+//! Public key signatures as DNSSEC defines them: algorithms, key tags and
+//! private key files, and SIG(0) message signatures (RFC 2931) made with
+//! them.
+
+mod algorithm;
+mod private;
+mod sig0;
+
+pub use self::algorithm::{Algorithm, key_tag};
+pub use self::private::{KeyError, PrivateKey, load_private_key, parse_private_key};
+pub use self::sig0::{SIG0_VALIDITY, Sig0Error, sign_message, verify_message};
@@ -0,0 +1,272 @@
+//! Private keys in the file format of BIND's dnssec-keygen
+//! (`K<name>.+<alg>+<tag>.private`):
+//!
+//! ```text
+//! Private-key-format: v1.3
+//! Algorithm: 15 (ED25519)
+//! PrivateKey: ODIyNjAzODQ2MjgwODAxMjI2NDUxOTAyMDQxNDIyNjI=
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use crypto::{Ed25519PrivateKey, RsaPrivateKey, RsaPublicKey, Sha256, Sha512};
+use protocol::{Name, RData, Record, base64_decode};
+use protocol::rdata::CLASS_IN;
+use super::algorithm::{Algorithm, key_tag};
+
+/// KEY flags for a key that belongs to a host (RFC 2535 Section 3.1.2).
+const HOST_KEY_FLAGS: u16 = 0x0200;
+const PROTOCOL_DNSSEC: u8 = 3;
+
+#[derive(Clone)]
+enum Secret {
+    Rsa(RsaPrivateKey),
+    Ed25519(Ed25519PrivateKey),
+}
+
+/// A key to sign with.
+#[derive(Clone)]
+pub struct PrivateKey {
+    algorithm: Algorithm,
+    secret: Secret,
+    // In DNSKEY and KEY form.
+    public_key: Vec<u8>,
+}
+
+impl PrivateKey {
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// The public key as it appears in DNSKEY and KEY records.
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    /// The KEY record that publishes the public half at `owner`, so that
+    /// servers can check SIG(0) signatures made with it.
+    pub fn key_record(&self, owner: Name, ttl: u32) -> Record {
+        Record::new(owner,
+                    CLASS_IN,
+                    ttl,
+                    RData::Key {
+                        flags: HOST_KEY_FLAGS,
+                        protocol: PROTOCOL_DNSSEC,
+                        algorithm: self.algorithm.number(),
+                        public_key: self.public_key.clone(),
+                    })
+    }
+
+    /// The key tag of the KEY record.
+    pub fn key_tag(&self) -> u16 {
+        key_tag(&self.key_record(Name::root(), 0).rdata.to_wire())
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        match (self.algorithm, &self.secret) {
+            (Algorithm::RsaSha256, &Secret::Rsa(ref key)) => key.sign::<Sha256>(data),
+            (Algorithm::RsaSha512, &Secret::Rsa(ref key)) => key.sign::<Sha512>(data),
+            (_, &Secret::Ed25519(ref key)) => Some(key.sign(data)),
+            _ => None,
+        }
+        .unwrap_or(Vec::new())
+    }
+}
+
+// Keeps secrets out of logs.
+impl fmt::Debug for PrivateKey {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("PrivateKey")
+           .field("algorithm", &self.algorithm)
+           .field("key_tag", &self.key_tag())
+           .finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyError {
+    /// Zero when the file could not be read at all, or when the problem is
+    /// a missing field.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            return write!(fmt, "{}", self.message);
+        }
+        write!(fmt, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for KeyError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+/// Reads the text of a private key file.
+pub fn parse_private_key(text: &str) -> Result<PrivateKey, KeyError> {
+    let mut algorithm = None;
+    let mut fields = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: String| {
+            KeyError {
+                line: i + 1,
+                message: message,
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (field, value) = match line.find(':') {
+            Some(at) => (&line[..at], line[at + 1..].trim()),
+            None => return Err(error("expected Field: value".to_string())),
+        };
+        match field {
+            "Private-key-format" => {
+                if !value.starts_with("v1.") {
+                    return Err(error(format!("unsupported format {}", value)));
+                }
+            }
+            "Algorithm" => {
+                let number = value.split_whitespace().next().and_then(|n| n.parse().ok());
+                match number.and_then(Algorithm::from_number) {
+                    Some(a) => algorithm = Some(a),
+                    None => return Err(error(format!("unsupported algorithm {}", value))),
+                }
+            }
+            "Modulus" | "PublicExponent" | "PrivateExponent" | "Prime1" | "Prime2" |
+            "Exponent1" | "Exponent2" | "Coefficient" | "PrivateKey" => {
+                match base64_decode(value) {
+                    Some(data) => fields.push((field, data)),
+                    None => return Err(error(format!("{} is not base64", field))),
+                }
+            }
+            // Timing metadata.
+            _ => {}
+        }
+    }
+    let field = |name: &str| {
+        match fields.iter().find(|&&(f, _)| f == name) {
+            Some(&(_, ref data)) => Ok(data),
+            None => {
+                Err(KeyError {
+                    line: 0,
+                    message: format!("missing {}", name),
+                })
+            }
+        }
+    };
+    let algorithm = match algorithm {
+        Some(algorithm) => algorithm,
+        None => {
+            return Err(KeyError {
+                line: 0,
+                message: "missing Algorithm".to_string(),
+            })
+        }
+    };
+    let (secret, public_key) = if algorithm == Algorithm::Ed25519 {
+        match Ed25519PrivateKey::new(try!(field("PrivateKey"))) {
+            Some(key) => {
+                let public = key.public_key();
+                (Secret::Ed25519(key), public)
+            }
+            None => {
+                return Err(KeyError {
+                    line: 0,
+                    message: "Ed25519 keys are 32 octets".to_string(),
+                })
+            }
+        }
+    } else {
+        let public = match RsaPublicKey::new(try!(field("Modulus")),
+                                             try!(field("PublicExponent"))) {
+            Some(public) => public,
+            None => {
+                return Err(KeyError {
+                    line: 0,
+                    message: "bad RSA modulus".to_string(),
+                })
+            }
+        };
+        let dns = public.to_dns();
+        let private = RsaPrivateKey::new(public,
+                                         try!(field("PrivateExponent")),
+                                         (try!(field("Prime1")), try!(field("Prime2"))),
+                                         (try!(field("Exponent1")), try!(field("Exponent2"))),
+                                         try!(field("Coefficient")));
+        match private {
+            Some(private) => (Secret::Rsa(private), dns),
+            None => {
+                return Err(KeyError {
+                    line: 0,
+                    message: "RSA keys to sign with are 2048 to 4096 bits".to_string(),
+                })
+            }
+        }
+    };
+    Ok(PrivateKey {
+        algorithm: algorithm,
+        secret: secret,
+        public_key: public_key,
+    })
+}
+
+/// Reads a private key file.
+pub fn load_private_key<P: AsRef<Path>>(path: P) -> Result<PrivateKey, KeyError> {
+    let path = path.as_ref();
+    let mut text = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+        return Err(KeyError {
+            line: 0,
+            message: format!("{}: {}", path.display(), e),
+        });
+    }
+    parse_private_key(&text)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dnssec::Algorithm;
+    use protocol::Name;
+
+    #[test]
+    fn key_files() {
+        let key = load_private_key("testdata/Kupdate.example.com.+008+55112.private").unwrap();
+        assert_eq!(Algorithm::RsaSha256, key.algorithm());
+        assert_eq!(55112, key.key_tag());
+        let signature = key.sign(b"data");
+        assert!(key.algorithm().verify(key.public_key(), b"data", &signature));
+
+        let text = "Private-key-format: v1.3\n\
+                    Algorithm: 15 (ED25519)\n\
+                    PrivateKey: nWGxne/9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A=\n\
+                    Created: 20240101000000\n";
+        let key = parse_private_key(text).unwrap();
+        assert_eq!(Algorithm::Ed25519, key.algorithm());
+        let record = key.key_record(Name::parse("host.example.com.", None).unwrap(), 300);
+        assert_eq!("host.example.com. 300 IN KEY 512 3 15 \
+                    11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=",
+                   record.to_string());
+    }
+
+    #[test]
+    fn key_file_errors() {
+        assert_eq!(2,
+                   parse_private_key("Private-key-format: v1.3\nAlgorithm: 5 (RSASHA1)\n")
+                       .unwrap_err()
+                       .line);
+        assert_eq!("missing PrivateKey",
+                   parse_private_key("Algorithm: 15\n").unwrap_err().message);
+        assert_eq!("missing Algorithm", parse_private_key("").unwrap_err().message);
+        assert_eq!(1, parse_private_key("nonsense\n").unwrap_err().line);
+    }
+}
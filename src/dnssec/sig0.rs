@@ -0,0 +1,188 @@
+//! SIG(0) message signatures (RFC 2931): a signature over the whole
+//! message by a private key whose public half is a KEY record in the DNS.
+//!
+//! Unlike TSIG there is no shared secret, so a server only needs to find
+//! the signer's KEY record, usually in the zone being updated.
+
+use std::error::Error;
+use std::fmt;
+use protocol::{Name, RData, Serial, SigRecord, RC_FORMAT_ERROR, RC_NOT_AUTH};
+use super::algorithm::{Algorithm, key_tag};
+use super::private::PrivateKey;
+
+/// Seconds either side of the signing time within which a signature is
+/// valid.
+pub const SIG0_VALIDITY: u32 = 300;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sig0Error {
+    /// A SIG record that cannot be used at all; answered with FORMERR.
+    Malformed,
+    /// No signature where one is required.
+    Unsigned,
+    /// No KEY record of the signer matches the signature.
+    BadKey,
+    BadSig,
+    BadTime,
+}
+
+impl Sig0Error {
+    /// The RCODE of a response reporting the error.
+    pub fn rcode(&self) -> u8 {
+        match *self {
+            Sig0Error::Malformed => RC_FORMAT_ERROR,
+            _ => RC_NOT_AUTH,
+        }
+    }
+}
+
+impl fmt::Display for Sig0Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.description())
+    }
+}
+
+impl Error for Sig0Error {
+    fn description(&self) -> &str {
+        match *self {
+            Sig0Error::Malformed => "malformed SIG(0) record",
+            Sig0Error::Unsigned => "message is not signed",
+            Sig0Error::BadKey => "no KEY record for the SIG(0) signer",
+            Sig0Error::BadSig => "SIG(0) signature does not match",
+            Sig0Error::BadTime => "SIG(0) signature expired or not yet valid",
+        }
+    }
+}
+
+// RFC 2931 Section 3.1: the SIG rdata, then for a response the request it
+// answers, then the message itself.
+fn signed_data(sig: &SigRecord, request: Option<&[u8]>, message: &[u8]) -> Vec<u8> {
+    let mut data = sig.signed_fields();
+    if let Some(request) = request {
+        data.extend(request.iter().cloned());
+    }
+    data.extend(message.iter().cloned());
+    data
+}
+
+// The public key of KEY rdata that may have made `sig`.
+fn public_key<'a>(rdata: &'a RData, sig: &SigRecord) -> Option<&'a [u8]> {
+    match *rdata {
+        RData::Key { algorithm, ref public_key, .. } if algorithm == sig.algorithm => {
+            if key_tag(&rdata.to_wire()) == sig.key_tag {
+                Some(public_key)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Appends a SIG(0) record made with `key` to `message`. A response is
+/// signed together with the request it answers, exactly as received.
+pub fn sign_message(message: &mut Vec<u8>,
+                    request: Option<&[u8]>,
+                    signer: &Name,
+                    key: &PrivateKey,
+                    now: u64) {
+    let now = now as u32;
+    let mut sig = SigRecord {
+        algorithm: key.algorithm().number(),
+        expiration: now.wrapping_add(SIG0_VALIDITY),
+        inception: now.wrapping_sub(SIG0_VALIDITY),
+        key_tag: key.key_tag(),
+        signer: signer.clone(),
+        signature: Vec::new(),
+    };
+    sig.signature = key.sign(&signed_data(&sig, request, message));
+    sig.append_to(message);
+}
+
+/// Checks the SIG(0) signature of a message against `keys`, the KEY
+/// records of the signer. Returns the signer's name.
+pub fn verify_message(message: &[u8],
+                      request: Option<&[u8]>,
+                      keys: &[RData],
+                      now: u64)
+    -> Result<Name, Sig0Error> {
+    let (sig, start) = match SigRecord::find(message) {
+        Ok(Some(found)) => found,
+        Ok(None) => return Err(Sig0Error::Unsigned),
+        Err(()) => return Err(Sig0Error::Malformed),
+    };
+    let algorithm = match Algorithm::from_number(sig.algorithm) {
+        Some(algorithm) => algorithm,
+        None => return Err(Sig0Error::BadKey),
+    };
+    let candidates: Vec<&[u8]> = keys.iter()
+                                     .filter_map(|rdata| public_key(rdata, &sig))
+                                     .collect();
+    if candidates.is_empty() {
+        return Err(Sig0Error::BadKey);
+    }
+    let now = Serial(now as u32);
+    if now < Serial(sig.inception) || now > Serial(sig.expiration) {
+        return Err(Sig0Error::BadTime);
+    }
+    let data = signed_data(&sig, request, &SigRecord::unsigned(message, start));
+    // Key tags can collide, so any key with the tag may have signed.
+    if candidates.iter().any(|key| algorithm.verify(key, &data, &sig.signature)) {
+        Ok(sig.signer)
+    } else {
+        Err(Sig0Error::BadSig)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dnssec::load_private_key;
+    use protocol::{HeaderMut, MessageCursor, Name, QuestionMut, RData};
+
+    fn message(id: u16) -> Vec<u8> {
+        let mut message = vec![0u8; 512];
+        let mut idx = MessageCursor::new(message.len());
+        HeaderMut::at(&mut idx, &mut message[..]).unwrap().make_query(id).set_qd(1);
+        QuestionMut::at(&mut idx,
+                        &mut message,
+                        &Name::parse("example.com.", None).unwrap().segments(),
+                        6,
+                        1)
+            .unwrap();
+        message.truncate(idx.tell());
+        message
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let now = 1_500_000_000;
+        let key = load_private_key("testdata/Kupdate.example.com.+008+55112.private").unwrap();
+        let signer = Name::parse("update.example.com.", None).unwrap();
+        let keys = vec![RData::Txt(vec![b"not a key".to_vec()]),
+                        key.key_record(signer.clone(), 300).rdata];
+
+        let mut request = message(7);
+        sign_message(&mut request, None, &signer, &key, now);
+        assert_eq!(Ok(signer.clone()), verify_message(&request, None, &keys, now + 299));
+        assert_eq!(Err(Sig0Error::BadTime),
+                   verify_message(&request, None, &keys, now + 301));
+        assert_eq!(Err(Sig0Error::BadKey), verify_message(&request, None, &keys[..1], now));
+        assert_eq!(Err(Sig0Error::Unsigned), verify_message(&message(7), None, &keys, now));
+
+        // Changing the message breaks the signature.
+        let mut altered = request.clone();
+        altered[1] = 8;
+        assert_eq!(Err(Sig0Error::BadSig), verify_message(&altered, None, &keys, now));
+
+        // A response covers the request too.
+        let mut response = message(7);
+        HeaderMut::at_raw(&mut response[..]).set_qr(true);
+        sign_message(&mut response, Some(&request), &signer, &key, now);
+        assert_eq!(Ok(signer.clone()),
+                   verify_message(&response, Some(&request), &keys, now));
+        assert_eq!(Err(Sig0Error::BadSig),
+                   verify_message(&response, Some(&altered), &keys, now));
+    }
+}
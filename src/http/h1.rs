@@ -0,0 +1,264 @@
+//! The server side of an HTTP/1.1 connection (RFC 9112). Requests may be
+//! pipelined; responses go back in the order of the requests whatever
+//! order they are made in.
+
+use std::collections::BTreeMap;
+use super::{MAX_BODY, Request, Response};
+
+/// The longest request line and header section taken.
+const MAX_HEAD: usize = 8192;
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+fn serialize(response: &Response, close: bool) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", response.status, reason(response.status));
+    for &(ref name, ref value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!("content-length: {}\r\n", response.body.len()));
+    if close {
+        head.push_str("connection: close\r\n");
+    }
+    head.push_str("\r\n");
+    let mut out = head.into_bytes();
+    out.extend(response.body.iter().cloned());
+    out
+}
+
+// The request line and header fields, or the status to refuse them with.
+fn parse_head(head: &str) -> Result<(Request, bool), u16> {
+    let mut lines = head.split("\r\n");
+    let parts: Vec<&str> = lines.next().unwrap_or("").split(' ').collect();
+    if parts.len() != 3 || parts[0].is_empty() || parts[1].is_empty() {
+        return Err(400);
+    }
+    let http10 = match parts[2] {
+        "HTTP/1.1" => false,
+        "HTTP/1.0" => true,
+        _ => return Err(505),
+    };
+    let mut request = Request::new(parts[0], parts[1]);
+    for line in lines {
+        let colon = match line.find(':') {
+            Some(colon) if colon > 0 => colon,
+            _ => return Err(400),
+        };
+        let name = &line[..colon];
+        if name.contains(|c: char| c.is_whitespace()) {
+            return Err(400);
+        }
+        request.headers.push((name.to_lowercase(), line[colon + 1..].trim().to_string()));
+    }
+    request.authority = request.header("host").map(|host| host.to_string());
+    let connection = request.header("connection").unwrap_or("").to_lowercase();
+    let keep_alive = if http10 {
+        connection == "keep-alive"
+    } else {
+        connection != "close"
+    };
+    Ok((request, keep_alive))
+}
+
+/// Requests read from one client and responses waiting to go back.
+#[derive(Debug)]
+pub struct Http1Server {
+    input: Vec<u8>,
+    output: Vec<u8>,
+    next_request: u32,
+    next_response: u32,
+    waiting: BTreeMap<u32, Vec<u8>>,
+    // The request after which nothing more is read, and the connection
+    // closes once it is answered.
+    last: Option<u32>,
+}
+
+impl Http1Server {
+    pub fn new() -> Http1Server {
+        Http1Server {
+            input: Vec::new(),
+            output: Vec::new(),
+            next_request: 0,
+            next_response: 0,
+            waiting: BTreeMap::new(),
+            last: None,
+        }
+    }
+
+    /// Takes octets read from the client and returns the requests they
+    /// complete, each with the handle to respond to it by. Requests that
+    /// cannot be read are answered here, and end the connection.
+    pub fn received(&mut self, data: &[u8]) -> Vec<(u32, Request)> {
+        if self.last.is_some() {
+            return Vec::new();
+        }
+        self.input.extend(data.iter().cloned());
+        let mut requests = Vec::new();
+        while self.last.is_none() {
+            let end = match self.input.windows(4).position(|w| w == b"\r\n\r\n") {
+                Some(end) => end,
+                None => {
+                    if self.input.len() > MAX_HEAD {
+                        self.refuse(431);
+                    }
+                    break;
+                }
+            };
+            if end > MAX_HEAD {
+                self.refuse(431);
+                break;
+            }
+            let parsed = match String::from_utf8(self.input[..end].to_vec()) {
+                Ok(head) => parse_head(&head),
+                Err(_) => Err(400),
+            };
+            let (mut request, keep_alive) = match parsed {
+                Ok(parsed) => parsed,
+                Err(status) => {
+                    self.refuse(status);
+                    break;
+                }
+            };
+            if request.header("transfer-encoding").is_some() {
+                self.refuse(501);
+                break;
+            }
+            let length = match request.header("content-length").map(|l| l.parse::<usize>()) {
+                None => 0,
+                Some(Ok(length)) if length <= MAX_BODY => length,
+                Some(Ok(_)) => {
+                    self.refuse(413);
+                    break;
+                }
+                Some(Err(_)) => {
+                    self.refuse(400);
+                    break;
+                }
+            };
+            if self.input.len() < end + 4 + length {
+                break;
+            }
+            request.body = self.input[end + 4..end + 4 + length].to_vec();
+            self.input.drain(..end + 4 + length);
+            let handle = self.next_request;
+            self.next_request += 1;
+            if !keep_alive {
+                self.last = Some(handle);
+            }
+            requests.push((handle, request));
+        }
+        requests
+    }
+
+    // Answers a request that could not be read with `status`, and reads no
+    // more.
+    fn refuse(&mut self, status: u16) {
+        let handle = self.next_request;
+        self.next_request += 1;
+        self.last = Some(handle);
+        self.respond(handle, &Response::new(status));
+    }
+
+    /// Queues the response to a request.
+    pub fn respond(&mut self, handle: u32, response: &Response) {
+        if handle < self.next_response || handle >= self.next_request {
+            return;
+        }
+        let close = self.last == Some(handle);
+        self.waiting.insert(handle, serialize(response, close));
+        while let Some(response) = self.waiting.remove(&self.next_response) {
+            self.output.extend(response);
+            self.next_response += 1;
+        }
+    }
+
+    /// The octets waiting to be written to the client.
+    pub fn pending(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn written(&mut self, n: usize) {
+        self.output.drain(..n);
+    }
+
+    /// Whether the last request has been answered, so the connection
+    /// should close once `pending` is written.
+    pub fn is_closed(&self) -> bool {
+        self.last.map_or(false, |last| self.next_response > last)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use http::Response;
+
+    fn ok(body: &[u8]) -> Response {
+        let mut response = Response::new(200);
+        response.add_header("content-type", "text/plain").body = body.to_vec();
+        response
+    }
+
+    #[test]
+    fn pipelining() {
+        let mut server = Http1Server::new();
+        let stream = b"GET /one HTTP/1.1\r\nHost: dns.example\r\n\r\n\
+                       POST /two HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+                       GET /three HTTP/1.1\r\nConnection: close\r\n\r\n";
+        let mut requests = server.received(&stream[..60]);
+        requests.extend(server.received(&stream[60..]));
+        assert_eq!(3, requests.len());
+        assert_eq!(("GET", "/one", Some("dns.example")),
+                   (&requests[0].1.method[..],
+                    &requests[0].1.path[..],
+                    requests[0].1.authority.as_ref().map(|a| &a[..])));
+        assert_eq!(b"hello".to_vec(), requests[1].1.body);
+
+        // Answered out of order, sent in order.
+        server.respond(requests[1].0, &ok(b"2"));
+        assert!(server.pending().is_empty());
+        server.respond(requests[0].0, &ok(b"1"));
+        server.respond(requests[2].0, &ok(b"3"));
+        let text = String::from_utf8(server.pending().to_vec()).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\n\
+                                  content-length: 1\r\n\r\n1"));
+        assert!(text.find("\r\n\r\n1").unwrap() < text.find("\r\n\r\n2").unwrap());
+        assert!(text.ends_with("connection: close\r\n\r\n3"));
+        assert!(server.is_closed());
+        assert!(server.received(b"GET / HTTP/1.1\r\n\r\n").is_empty());
+    }
+
+    #[test]
+    fn refusals() {
+        let cases: [(&[u8], &str); 5] = [(b"GET /\r\n\r\n", "400"),
+                                         (b"GET / HTTP/2.0\r\n\r\n", "505"),
+                                         (b"POST / HTTP/1.1\r\nContent-Length: 99999\r\n\r\n",
+                                          "413"),
+                                         (b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+                                          "501"),
+                                         (b"GET / HTTP/1.1\r\nBad Header\r\n\r\n", "400")];
+        for &(stream, status) in cases.iter() {
+            let mut server = Http1Server::new();
+            assert!(server.received(stream).is_empty());
+            assert!(server.is_closed());
+            assert_eq!(status.as_bytes(), &server.pending()[9..12]);
+        }
+        let mut server = Http1Server::new();
+        assert!(server.received(&vec![b'a'; 9000]).is_empty());
+        assert_eq!(b"431", &server.pending()[9..12]);
+    }
+}
@@ -0,0 +1,1031 @@
+//! One HTTP/2 connection (RFC 9113), from either end: frames, streams, and
+//! flow control. Requests and responses arrive whole once their stream
+//! ends, and go out as the peer's flow control windows allow.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use super::hpack::{self, Decoder, Field};
+use super::{CANCEL, COMPRESSION_ERROR, ENHANCE_YOUR_CALM, FLOW_CONTROL_ERROR, FRAME_SIZE_ERROR,
+            HttpError, MAX_BODY, NO_ERROR, PROTOCOL_ERROR, REFUSED_STREAM, Request, Response,
+            STREAM_CLOSED};
+
+/// What a client sends before anything else.
+pub const PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// How many streams a peer may have open at once.
+pub const MAX_STREAMS: u32 = 100;
+
+// The highest stream a client may open.
+const MAX_STREAM_ID: u32 = 0x7fff_ffff;
+
+const FRAME_DATA: u8 = 0;
+const FRAME_HEADERS: u8 = 1;
+const FRAME_PRIORITY: u8 = 2;
+const FRAME_RST_STREAM: u8 = 3;
+const FRAME_SETTINGS: u8 = 4;
+const FRAME_PUSH_PROMISE: u8 = 5;
+const FRAME_PING: u8 = 6;
+const FRAME_GOAWAY: u8 = 7;
+const FRAME_WINDOW_UPDATE: u8 = 8;
+const FRAME_CONTINUATION: u8 = 9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+const SETTINGS_ENABLE_PUSH: u16 = 2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 5;
+
+const DEFAULT_WINDOW: i64 = 65535;
+const MAX_WINDOW: i64 = 0x7fff_ffff;
+const DEFAULT_FRAME_SIZE: usize = 16384;
+const MAX_FRAME_SIZE: usize = 0xff_ffff;
+const MAX_HEADER_BLOCK: usize = 65536;
+
+fn u32_at(data: &[u8]) -> u32 {
+    ((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) |
+    data[3] as u32
+}
+
+fn put_frame(out: &mut Vec<u8>, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+    let len = payload.len();
+    out.extend(&[(len >> 16) as u8, (len >> 8) as u8, len as u8, kind, flags]);
+    out.extend(&[(stream >> 24) as u8, (stream >> 16) as u8, (stream >> 8) as u8, stream as u8]);
+    out.extend(payload.iter().cloned());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+// The payload without its padding.
+fn unpad(flags: u8, payload: &[u8]) -> Result<&[u8], HttpError> {
+    if flags & FLAG_PADDED == 0 {
+        return Ok(payload);
+    }
+    match payload.first() {
+        Some(&pad) if payload.len() > pad as usize => Ok(&payload[1..payload.len() - pad as usize]),
+        _ => Err(HttpError::Local(PROTOCOL_ERROR, "bad padding")),
+    }
+}
+
+// A request from its header fields, or None if they are malformed
+// (RFC 9113 Section 8.1.1).
+fn request_head(fields: Vec<Field>) -> Option<Request> {
+    let (mut method, mut scheme, mut path, mut authority) = (None, None, None, None);
+    let mut headers = Vec::new();
+    for (name, value) in fields {
+        if name.chars().any(|c| c.is_uppercase()) {
+            return None;
+        }
+        let slot = match &name[..] {
+            ":method" => &mut method,
+            ":scheme" => &mut scheme,
+            ":path" => &mut path,
+            ":authority" => &mut authority,
+            "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" |
+            "upgrade" => return None,
+            _ if name.starts_with(':') => return None,
+            _ => {
+                headers.push((name, value));
+                continue;
+            }
+        };
+        if slot.is_some() || !headers.is_empty() {
+            return None;
+        }
+        *slot = Some(value);
+    }
+    match (method, scheme, path) {
+        (Some(method), Some(_), Some(path)) if !path.is_empty() => {
+            Some(Request {
+                method: method,
+                path: path,
+                authority: authority,
+                headers: headers,
+                body: Vec::new(),
+            })
+        }
+        _ => None,
+    }
+}
+
+// A response from its header fields, or None if they are malformed.
+fn response_head(fields: Vec<Field>) -> Option<Response> {
+    let mut status = None;
+    let mut headers = Vec::new();
+    for (name, value) in fields {
+        if name.chars().any(|c| c.is_uppercase()) {
+            return None;
+        }
+        match &name[..] {
+            ":status" if status.is_none() && headers.is_empty() => {
+                status = match value.parse::<u16>() {
+                    Ok(status) if value.len() == 3 && status >= 100 => Some(status),
+                    _ => return None,
+                };
+            }
+            "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" |
+            "upgrade" => return None,
+            _ if name.starts_with(':') => return None,
+            _ => headers.push((name, value)),
+        }
+    }
+    status.map(|status| {
+        Response {
+            status: status,
+            headers: headers,
+            body: Vec::new(),
+        }
+    })
+}
+
+#[derive(Debug)]
+enum Message {
+    Request(Request),
+    Response(Response),
+}
+
+impl Message {
+    fn body(&mut self) -> &mut Vec<u8> {
+        match *self {
+            Message::Request(ref mut request) => &mut request.body,
+            Message::Response(ref mut response) => &mut response.body,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Stream {
+    // What the peer is sending, from its header fields until the stream
+    // ends and it is handed on.
+    message: Option<Message>,
+    head_received: bool,
+    received_end: bool,
+    sent_end: bool,
+    send_window: i64,
+    // Body octets waiting for flow control to let them go.
+    outgoing: Vec<u8>,
+}
+
+impl Stream {
+    fn new(send_window: i64) -> Stream {
+        Stream {
+            message: None,
+            head_received: false,
+            received_end: false,
+            sent_end: false,
+            send_window: send_window,
+            outgoing: Vec::new(),
+        }
+    }
+}
+
+/// One connection, as the client or the server.
+#[derive(Debug)]
+pub struct Http2Connection {
+    client: bool,
+    // Octets of the client preface still to come.
+    preface: usize,
+    settings_received: bool,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    decoder: Decoder,
+    streams: BTreeMap<u32, Stream>,
+    last_peer_stream: u32,
+    // The stream a client opens next.
+    next_stream: u32,
+    peer_max_streams: u32,
+    send_window: i64,
+    initial_window: i64,
+    max_frame: usize,
+    // A header block waiting for CONTINUATION frames: its stream, the flags
+    // of its HEADERS frame, and the fragments so far.
+    header_block: Option<(u32, u8, Vec<u8>)>,
+    goaway: bool,
+    error: Option<HttpError>,
+}
+
+impl Http2Connection {
+    /// A connection a client has opened, which starts with the preface.
+    pub fn server() -> Http2Connection {
+        let mut connection = Http2Connection::new(false);
+        let mut settings = vec![0, SETTINGS_MAX_CONCURRENT_STREAMS as u8];
+        put_u32(&mut settings, MAX_STREAMS);
+        put_frame(&mut connection.output, FRAME_SETTINGS, 0, 0, &settings);
+        connection
+    }
+
+    /// A connection to a server, which starts with the preface. Requests
+    /// may be made at once, before the server has said anything.
+    pub fn client() -> Http2Connection {
+        let mut connection = Http2Connection::new(true);
+        connection.preface = 0;
+        connection.output.extend(PREFACE.iter().cloned());
+        let mut settings = vec![0, SETTINGS_ENABLE_PUSH as u8];
+        put_u32(&mut settings, 0);
+        put_frame(&mut connection.output, FRAME_SETTINGS, 0, 0, &settings);
+        connection
+    }
+
+    fn new(client: bool) -> Http2Connection {
+        Http2Connection {
+            client: client,
+            preface: PREFACE.len(),
+            settings_received: false,
+            input: Vec::new(),
+            output: Vec::new(),
+            decoder: Decoder::new(),
+            streams: BTreeMap::new(),
+            last_peer_stream: 0,
+            next_stream: 1,
+            peer_max_streams: ::std::u32::MAX,
+            send_window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame: DEFAULT_FRAME_SIZE,
+            header_block: None,
+            goaway: false,
+            error: None,
+        }
+    }
+
+    /// Takes octets read from the client and returns the requests they
+    /// complete, each with its stream. An error ends the connection, with
+    /// a GOAWAY saying why left in `pending`.
+    pub fn received(&mut self, data: &[u8]) -> Result<Vec<(u32, Request)>, HttpError> {
+        let messages = try!(self.receive(data));
+        Ok(messages.into_iter()
+                   .filter_map(|(stream, message)| {
+                       match message {
+                           Message::Request(request) => Some((stream, request)),
+                           Message::Response(_) => None,
+                       }
+                   })
+                   .collect())
+    }
+
+    /// Takes octets read from the server and returns the responses they
+    /// complete, each with the stream of its request. A request whose
+    /// stream is no longer open, with no response, has failed.
+    pub fn received_responses(&mut self, data: &[u8]) -> Result<Vec<(u32, Response)>, HttpError> {
+        let messages = try!(self.receive(data));
+        Ok(messages.into_iter()
+                   .filter_map(|(stream, message)| {
+                       match message {
+                           Message::Response(response) => Some((stream, response)),
+                           Message::Request(_) => None,
+                       }
+                   })
+                   .collect())
+    }
+
+    fn receive(&mut self, data: &[u8]) -> Result<Vec<(u32, Message)>, HttpError> {
+        if let Some(ref error) = self.error {
+            return Err(error.clone());
+        }
+        self.input.extend(data.iter().cloned());
+        let mut messages = Vec::new();
+        match self.frames(&mut messages) {
+            Ok(()) => Ok(messages),
+            Err(error) => {
+                if let HttpError::Local(code, _) = error {
+                    self.send_goaway(code);
+                }
+                self.error = Some(error.clone());
+                Err(error)
+            }
+        }
+    }
+
+    fn frames(&mut self, messages: &mut Vec<(u32, Message)>) -> Result<(), HttpError> {
+        if self.preface > 0 {
+            let n = cmp::min(self.preface, self.input.len());
+            let expected = &PREFACE[PREFACE.len() - self.preface..][..n];
+            if self.input[..n] != *expected {
+                return Err(HttpError::Local(PROTOCOL_ERROR, "bad connection preface"));
+            }
+            self.input.drain(..n);
+            self.preface -= n;
+        }
+        while self.preface == 0 && self.input.len() >= 9 {
+            let len = ((self.input[0] as usize) << 16) | ((self.input[1] as usize) << 8) |
+                      self.input[2] as usize;
+            if len > DEFAULT_FRAME_SIZE {
+                return Err(HttpError::Local(FRAME_SIZE_ERROR, "frame too long"));
+            }
+            if self.input.len() < 9 + len {
+                break;
+            }
+            let frame: Vec<u8> = self.input.drain(..9 + len).collect();
+            let stream = u32_at(&frame[5..9]) & 0x7fff_ffff;
+            try!(self.frame(frame[3], frame[4], stream, &frame[9..], messages));
+        }
+        Ok(())
+    }
+
+    fn frame(&mut self,
+             kind: u8,
+             flags: u8,
+             stream: u32,
+             payload: &[u8],
+             messages: &mut Vec<(u32, Message)>)
+        -> Result<(), HttpError> {
+        if !self.settings_received && kind != FRAME_SETTINGS {
+            return Err(HttpError::Local(PROTOCOL_ERROR, "expected SETTINGS"));
+        }
+        if let Some((id, _, _)) = self.header_block {
+            if kind != FRAME_CONTINUATION || stream != id {
+                return Err(HttpError::Local(PROTOCOL_ERROR, "expected CONTINUATION"));
+            }
+        }
+        let connection_frame = kind == FRAME_SETTINGS || kind == FRAME_PING ||
+                               kind == FRAME_GOAWAY;
+        let stream_frame = kind != FRAME_WINDOW_UPDATE && !connection_frame && kind <= 9;
+        if (connection_frame && stream != 0) || (stream_frame && stream == 0) {
+            return Err(HttpError::Local(PROTOCOL_ERROR, "frame on the wrong stream"));
+        }
+        match kind {
+            FRAME_DATA => self.data(flags, stream, payload, messages),
+            FRAME_HEADERS => {
+                let mut block = try!(unpad(flags, payload));
+                if flags & FLAG_PRIORITY != 0 {
+                    if block.len() < 5 {
+                        return Err(HttpError::Local(FRAME_SIZE_ERROR, "short HEADERS"));
+                    }
+                    block = &block[5..];
+                }
+                if flags & FLAG_END_HEADERS != 0 {
+                    return self.headers(stream, flags, block.to_vec(), messages);
+                }
+                self.header_block = Some((stream, flags, block.to_vec()));
+                Ok(())
+            }
+            FRAME_CONTINUATION => {
+                let (id, first_flags, mut block) = match self.header_block.take() {
+                    Some(pending) => pending,
+                    None => return Err(HttpError::Local(PROTOCOL_ERROR, "stray CONTINUATION")),
+                };
+                block.extend(payload.iter().cloned());
+                if block.len() > MAX_HEADER_BLOCK {
+                    return Err(HttpError::Local(ENHANCE_YOUR_CALM, "header block too long"));
+                }
+                if flags & FLAG_END_HEADERS != 0 {
+                    return self.headers(id, first_flags, block, messages);
+                }
+                self.header_block = Some((id, first_flags, block));
+                Ok(())
+            }
+            FRAME_PRIORITY if payload.len() != 5 => {
+                Err(HttpError::Local(FRAME_SIZE_ERROR, "bad PRIORITY"))
+            }
+            FRAME_RST_STREAM => {
+                if payload.len() != 4 {
+                    return Err(HttpError::Local(FRAME_SIZE_ERROR, "bad RST_STREAM"));
+                }
+                if self.is_idle(stream) {
+                    return Err(HttpError::Local(PROTOCOL_ERROR, "RST_STREAM on idle stream"));
+                }
+                self.streams.remove(&stream);
+                Ok(())
+            }
+            FRAME_SETTINGS => self.settings(flags, payload),
+            FRAME_PUSH_PROMISE => Err(HttpError::Local(PROTOCOL_ERROR, "PUSH_PROMISE")),
+            FRAME_PING => {
+                if payload.len() != 8 {
+                    return Err(HttpError::Local(FRAME_SIZE_ERROR, "bad PING"));
+                }
+                if flags & FLAG_ACK == 0 {
+                    put_frame(&mut self.output, FRAME_PING, FLAG_ACK, 0, payload);
+                }
+                Ok(())
+            }
+            FRAME_GOAWAY => {
+                if payload.len() < 8 {
+                    return Err(HttpError::Local(FRAME_SIZE_ERROR, "bad GOAWAY"));
+                }
+                self.goaway = true;
+                // Streams past the last the peer took were never seen, and
+                // may be tried again elsewhere.
+                let last = u32_at(payload) & 0x7fff_ffff;
+                let unseen: Vec<u32> = self.streams
+                                           .keys()
+                                           .cloned()
+                                           .filter(|&id| id > last)
+                                           .collect();
+                for id in unseen {
+                    self.streams.remove(&id);
+                }
+                match u32_at(&payload[4..8]) {
+                    NO_ERROR => Ok(()),
+                    code => Err(HttpError::Remote(code)),
+                }
+            }
+            FRAME_WINDOW_UPDATE => self.window_update(stream, payload),
+            // Unknown frames, and PRIORITY, which is ignored.
+            _ => Ok(()),
+        }
+    }
+
+    // Whether the stream has not been opened yet.
+    fn is_idle(&self, stream: u32) -> bool {
+        if self.client {
+            stream % 2 == 0 || stream >= self.next_stream
+        } else {
+            stream > self.last_peer_stream
+        }
+    }
+
+    fn data(&mut self,
+            flags: u8,
+            stream: u32,
+            payload: &[u8],
+            messages: &mut Vec<(u32, Message)>)
+        -> Result<(), HttpError> {
+        // What is read is consumed at once, so the windows open again.
+        if !payload.is_empty() {
+            self.send_window_update(0, payload.len());
+        }
+        let body = try!(unpad(flags, payload));
+        let end = flags & FLAG_END_STREAM != 0;
+        let too_long = match self.streams.get_mut(&stream) {
+            Some(ref mut open) if open.head_received && !open.received_end => {
+                let message = open.message.as_mut().unwrap();
+                message.body().extend(body.iter().cloned());
+                open.received_end = end;
+                message.body().len() > MAX_BODY
+            }
+            _ => {
+                if self.is_idle(stream) {
+                    return Err(HttpError::Local(PROTOCOL_ERROR, "DATA on idle stream"));
+                }
+                self.reset(stream, STREAM_CLOSED);
+                return Ok(());
+            }
+        };
+        if too_long {
+            self.reset(stream, ENHANCE_YOUR_CALM);
+        } else if end {
+            self.complete(stream, messages);
+        } else if !payload.is_empty() {
+            self.send_window_update(stream, payload.len());
+        }
+        Ok(())
+    }
+
+    fn headers(&mut self,
+               stream: u32,
+               flags: u8,
+               block: Vec<u8>,
+               messages: &mut Vec<(u32, Message)>)
+        -> Result<(), HttpError> {
+        let fields = match self.decoder.decode(&block) {
+            Some(fields) => fields,
+            None => return Err(HttpError::Local(COMPRESSION_ERROR, "bad header block")),
+        };
+        let end = flags & FLAG_END_STREAM != 0;
+        let state = self.streams.get(&stream).map(|open| (open.head_received, open.received_end));
+        match state {
+            // Trailers, which must end the stream and are otherwise ignored.
+            Some((true, received_end)) => {
+                if received_end || !end {
+                    self.reset(stream, PROTOCOL_ERROR);
+                } else {
+                    self.streams.get_mut(&stream).unwrap().received_end = true;
+                    self.complete(stream, messages);
+                }
+                return Ok(());
+            }
+            // The response to a request of ours.
+            Some((false, _)) => {
+                match response_head(fields) {
+                    // Informational; the final response is still to come.
+                    Some(ref response) if response.status < 200 => {}
+                    Some(response) => {
+                        {
+                            let open = self.streams.get_mut(&stream).unwrap();
+                            open.message = Some(Message::Response(response));
+                            open.head_received = true;
+                            open.received_end = end;
+                        }
+                        if end {
+                            self.complete(stream, messages);
+                        }
+                    }
+                    None => self.reset(stream, PROTOCOL_ERROR),
+                }
+                return Ok(());
+            }
+            None => {}
+        }
+        if self.client {
+            if self.is_idle(stream) {
+                return Err(HttpError::Local(PROTOCOL_ERROR, "HEADERS on idle stream"));
+            }
+            // On a stream given up on.
+            return Ok(());
+        }
+        if stream % 2 == 0 || stream <= self.last_peer_stream {
+            return Err(HttpError::Local(PROTOCOL_ERROR, "bad stream for a request"));
+        }
+        self.last_peer_stream = stream;
+        if self.streams.len() >= MAX_STREAMS as usize {
+            self.reset(stream, REFUSED_STREAM);
+            return Ok(());
+        }
+        let request = match request_head(fields) {
+            Some(request) => request,
+            None => {
+                self.reset(stream, PROTOCOL_ERROR);
+                return Ok(());
+            }
+        };
+        let mut open = Stream::new(self.initial_window);
+        open.message = Some(Message::Request(request));
+        open.head_received = true;
+        open.received_end = end;
+        self.streams.insert(stream, open);
+        if end {
+            self.complete(stream, messages);
+        }
+        Ok(())
+    }
+
+    // Hands on a message whose stream has ended.
+    fn complete(&mut self, stream: u32, messages: &mut Vec<(u32, Message)>) {
+        let (message, done) = match self.streams.get_mut(&stream) {
+            Some(open) => (open.message.take(), open.sent_end),
+            None => return,
+        };
+        if let Some(message) = message {
+            messages.push((stream, message));
+        }
+        if done {
+            self.streams.remove(&stream);
+        }
+    }
+
+    fn settings(&mut self, flags: u8, payload: &[u8]) -> Result<(), HttpError> {
+        if flags & FLAG_ACK != 0 {
+            if !payload.is_empty() {
+                return Err(HttpError::Local(FRAME_SIZE_ERROR, "SETTINGS ACK with settings"));
+            }
+            return Ok(());
+        }
+        if payload.len() % 6 != 0 {
+            return Err(HttpError::Local(FRAME_SIZE_ERROR, "bad SETTINGS"));
+        }
+        for setting in payload.chunks(6) {
+            let id = ((setting[0] as u16) << 8) | setting[1] as u16;
+            let value = u32_at(&setting[2..]);
+            match id {
+                SETTINGS_ENABLE_PUSH if value > 1 => {
+                    return Err(HttpError::Local(PROTOCOL_ERROR, "bad ENABLE_PUSH"));
+                }
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value as i64 > MAX_WINDOW {
+                        return Err(HttpError::Local(FLOW_CONTROL_ERROR, "window too large"));
+                    }
+                    let delta = value as i64 - self.initial_window;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                    }
+                    self.initial_window = value as i64;
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if (value as usize) < DEFAULT_FRAME_SIZE || value as usize > MAX_FRAME_SIZE {
+                        return Err(HttpError::Local(PROTOCOL_ERROR, "bad MAX_FRAME_SIZE"));
+                    }
+                    self.max_frame = value as usize;
+                }
+                SETTINGS_MAX_CONCURRENT_STREAMS => self.peer_max_streams = value,
+                _ => {}
+            }
+        }
+        self.settings_received = true;
+        put_frame(&mut self.output, FRAME_SETTINGS, FLAG_ACK, 0, &[]);
+        self.flush();
+        Ok(())
+    }
+
+    fn window_update(&mut self, stream: u32, payload: &[u8]) -> Result<(), HttpError> {
+        if payload.len() != 4 {
+            return Err(HttpError::Local(FRAME_SIZE_ERROR, "bad WINDOW_UPDATE"));
+        }
+        let increment = (u32_at(payload) & 0x7fff_ffff) as i64;
+        if stream == 0 {
+            if increment == 0 {
+                return Err(HttpError::Local(PROTOCOL_ERROR, "empty WINDOW_UPDATE"));
+            }
+            self.send_window += increment;
+            if self.send_window > MAX_WINDOW {
+                return Err(HttpError::Local(FLOW_CONTROL_ERROR, "window too large"));
+            }
+        } else {
+            let error = match self.streams.get_mut(&stream) {
+                Some(open) => {
+                    open.send_window += increment;
+                    if increment == 0 {
+                        Some(PROTOCOL_ERROR)
+                    } else if open.send_window > MAX_WINDOW {
+                        Some(FLOW_CONTROL_ERROR)
+                    } else {
+                        None
+                    }
+                }
+                None => None,
+            };
+            if let Some(code) = error {
+                self.reset(stream, code);
+            }
+        }
+        self.flush();
+        Ok(())
+    }
+
+    fn send_window_update(&mut self, stream: u32, increment: usize) {
+        let mut payload = Vec::with_capacity(4);
+        put_u32(&mut payload, increment as u32);
+        put_frame(&mut self.output, FRAME_WINDOW_UPDATE, 0, stream, &payload);
+    }
+
+    fn send_goaway(&mut self, code: u32) {
+        let mut payload = Vec::with_capacity(8);
+        put_u32(&mut payload, self.last_peer_stream);
+        put_u32(&mut payload, code);
+        put_frame(&mut self.output, FRAME_GOAWAY, 0, 0, &payload);
+    }
+
+    // Ends a stream with an error.
+    fn reset(&mut self, stream: u32, code: u32) {
+        let mut payload = Vec::with_capacity(4);
+        put_u32(&mut payload, code);
+        put_frame(&mut self.output, FRAME_RST_STREAM, 0, stream, &payload);
+        self.streams.remove(&stream);
+    }
+
+    // Sends what flow control allows of the bodies waiting.
+    fn flush(&mut self) {
+        let waiting: Vec<u32> = self.streams
+                                    .iter()
+                                    .filter(|&(_, s)| !s.outgoing.is_empty())
+                                    .map(|(&id, _)| id)
+                                    .collect();
+        for id in waiting {
+            let done = {
+                let stream = self.streams.get_mut(&id).unwrap();
+                loop {
+                    let window = cmp::min(self.send_window, stream.send_window);
+                    let n = cmp::min(stream.outgoing.len(), self.max_frame);
+                    let n = cmp::min(n as i64, cmp::max(window, 0)) as usize;
+                    if n == 0 {
+                        break false;
+                    }
+                    let chunk: Vec<u8> = stream.outgoing.drain(..n).collect();
+                    self.send_window -= n as i64;
+                    stream.send_window -= n as i64;
+                    let end = stream.outgoing.is_empty();
+                    let flags = if end { FLAG_END_STREAM } else { 0 };
+                    put_frame(&mut self.output, FRAME_DATA, flags, id, &chunk);
+                    if end {
+                        break true;
+                    }
+                }
+            };
+            if done {
+                self.sent_end(id);
+            }
+        }
+    }
+
+    // Notes that everything has been sent on a stream, which is done with
+    // if the peer has finished too.
+    fn sent_end(&mut self, stream: u32) {
+        let done = match self.streams.get_mut(&stream) {
+            Some(open) => {
+                open.sent_end = true;
+                open.received_end
+            }
+            None => return,
+        };
+        if done {
+            self.streams.remove(&stream);
+        }
+    }
+
+    // Sends a header block and then the body on an open stream.
+    fn send(&mut self, stream: u32, fields: &[(&str, &str)], body: &[u8]) {
+        let block = hpack::encode(fields);
+        let end = if body.is_empty() { FLAG_END_STREAM } else { 0 };
+        let mut chunks = block.chunks(self.max_frame).peekable();
+        let mut kind = FRAME_HEADERS;
+        let mut flags = end;
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= FLAG_END_HEADERS;
+            }
+            put_frame(&mut self.output, kind, flags, stream, chunk);
+            kind = FRAME_CONTINUATION;
+            flags = 0;
+        }
+        if body.is_empty() {
+            self.sent_end(stream);
+        } else {
+            self.streams.get_mut(&stream).unwrap().outgoing = body.to_vec();
+            self.flush();
+        }
+    }
+
+    /// Queues the response on a stream. Streams the client has reset are
+    /// passed over.
+    pub fn respond(&mut self, stream: u32, response: &Response) {
+        if self.client || self.error.is_some() || !self.streams.contains_key(&stream) {
+            return;
+        }
+        let status = response.status.to_string();
+        let length = response.body.len().to_string();
+        let mut fields = vec![(":status", &status[..])];
+        for &(ref name, ref value) in &response.headers {
+            fields.push((&name[..], &value[..]));
+        }
+        fields.push(("content-length", &length[..]));
+        self.send(stream, &fields, &response.body);
+    }
+
+    /// Queues a request on a new stream over `https`, and returns the
+    /// stream. None if the server will take no more streams for now, or
+    /// none at all.
+    pub fn request(&mut self, request: &Request) -> Option<u32> {
+        if !self.client || self.goaway || self.error.is_some() ||
+           self.streams.len() >= self.peer_max_streams as usize ||
+           self.next_stream > MAX_STREAM_ID {
+            return None;
+        }
+        let stream = self.next_stream;
+        self.next_stream += 2;
+        self.streams.insert(stream, Stream::new(self.initial_window));
+        let length = request.body.len().to_string();
+        let mut fields = vec![(":method", &request.method[..]),
+                              (":scheme", "https"),
+                              (":path", &request.path[..])];
+        if let Some(ref authority) = request.authority {
+            fields.push((":authority", &authority[..]));
+        }
+        for &(ref name, ref value) in &request.headers {
+            fields.push((&name[..], &value[..]));
+        }
+        if !request.body.is_empty() {
+            fields.push(("content-length", &length[..]));
+        }
+        self.send(stream, &fields, &request.body);
+        Some(stream)
+    }
+
+    /// Gives up on a stream, as when its response has been waited on too
+    /// long.
+    pub fn cancel(&mut self, stream: u32) {
+        if self.error.is_none() && self.streams.contains_key(&stream) {
+            self.reset(stream, CANCEL);
+        }
+    }
+
+    /// Whether a stream is still open, waiting on either end.
+    pub fn is_open(&self, stream: u32) -> bool {
+        self.streams.contains_key(&stream)
+    }
+
+    /// The octets waiting to be written to the peer.
+    pub fn pending(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn written(&mut self, n: usize) {
+        self.output.drain(..n);
+    }
+
+    /// Tells the peer no more streams will be taken; those open are still
+    /// seen through.
+    pub fn close(&mut self) {
+        if !self.goaway {
+            self.send_goaway(NO_ERROR);
+            self.goaway = true;
+        }
+    }
+
+    /// Whether the connection has failed, or is going away with nothing
+    /// left to answer, so it should close once `pending` is written.
+    pub fn is_closed(&self) -> bool {
+        self.error.is_some() || (self.goaway && self.streams.is_empty())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use http::{CANCEL, Request, Response};
+    use http::hpack::{Decoder, encode};
+
+    // Splits what the server sent into frames.
+    fn frames(data: &[u8]) -> Vec<(u8, u8, u32, Vec<u8>)> {
+        let mut frames = Vec::new();
+        let mut at = 0;
+        while at < data.len() {
+            let len = ((data[at] as usize) << 16) | ((data[at + 1] as usize) << 8) |
+                      data[at + 2] as usize;
+            frames.push((data[at + 3],
+                         data[at + 4],
+                         u32_at(&data[at + 5..]),
+                         data[at + 9..at + 9 + len].to_vec()));
+            at += 9 + len;
+        }
+        frames
+    }
+
+    fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_frame(&mut out, kind, flags, stream, payload);
+        out
+    }
+
+    fn request_headers(method: &str, path: &str) -> Vec<u8> {
+        encode(&[(":method", method),
+                 (":scheme", "https"),
+                 (":path", path),
+                 (":authority", "dns.example"),
+                 ("accept", "application/dns-message")])
+    }
+
+    // A server that has been through the preface and settings exchange.
+    fn server() -> Http2Connection {
+        let mut server = Http2Connection::server();
+        let mut client = PREFACE.to_vec();
+        client.extend(frame(FRAME_SETTINGS, 0, 0, &[0, 4, 0, 0, 0, 10]));
+        assert!(server.received(&client).unwrap().is_empty());
+        let sent = frames(server.pending());
+        assert_eq!(vec![(FRAME_SETTINGS, 0, 0, vec![0, 3, 0, 0, 0, 100]),
+                        (FRAME_SETTINGS, FLAG_ACK, 0, Vec::new())],
+                   sent);
+        let n = server.pending().len();
+        server.written(n);
+        server
+    }
+
+    #[test]
+    fn requests_and_flow_control() {
+        let mut server = server();
+        let mut data = frame(FRAME_HEADERS,
+                             FLAG_END_HEADERS | FLAG_END_STREAM,
+                             1,
+                             &request_headers("GET", "/dns-query?dns=AAAB"));
+        // A POST split into HEADERS, CONTINUATION and padded DATA.
+        let block = request_headers("POST", "/dns-query");
+        data.extend(frame(FRAME_HEADERS, 0, 3, &block[..4]));
+        data.extend(frame(FRAME_CONTINUATION, FLAG_END_HEADERS, 3, &block[4..]));
+        data.extend(frame(FRAME_DATA, FLAG_PADDED | FLAG_END_STREAM, 3, &[2, 9, 8, 0, 0]));
+        data.extend(frame(FRAME_PING, 0, 0, b"pingpong"));
+        let requests = server.received(&data).unwrap();
+        assert_eq!(2, requests.len());
+        let mut expected = Request::new("GET", "/dns-query?dns=AAAB");
+        expected.authority = Some("dns.example".to_string());
+        expected.headers.push(("accept".to_string(), "application/dns-message".to_string()));
+        assert_eq!((1, expected), requests[0]);
+        assert_eq!((3, vec![9, 8]), (requests[1].0, requests[1].1.body.clone()));
+        assert_eq!(vec![(FRAME_WINDOW_UPDATE, 0, 0, vec![0, 0, 0, 5]),
+                        (FRAME_PING, FLAG_ACK, 0, b"pingpong".to_vec())],
+                   frames(server.pending()));
+        let n = server.pending().len();
+        server.written(n);
+
+        // The client's window of ten octets holds the body back.
+        let mut response = Response::new(200);
+        response.add_header("content-type", "application/dns-message").body = vec![7; 25];
+        server.respond(3, &response);
+        let sent = frames(server.pending());
+        assert_eq!(2, sent.len());
+        assert_eq!((FRAME_HEADERS, FLAG_END_HEADERS, 3), (sent[0].0, sent[0].1, sent[0].2));
+        assert_eq!(Some(vec![(":status".to_string(), "200".to_string()),
+                             ("content-type".to_string(), "application/dns-message".to_string()),
+                             ("content-length".to_string(), "25".to_string())]),
+                   Decoder::new().decode(&sent[0].3));
+        assert_eq!((FRAME_DATA, 0, 3, vec![7; 10]), sent[1]);
+        let n = server.pending().len();
+        server.written(n);
+        server.received(&frame(FRAME_WINDOW_UPDATE, 0, 3, &[0, 0, 0, 100])).unwrap();
+        assert_eq!(vec![(FRAME_DATA, FLAG_END_STREAM, 3, vec![7; 15])],
+                   frames(server.pending()));
+        let n = server.pending().len();
+        server.written(n);
+
+        // Stream 1 was reset, so its response goes nowhere.
+        let mut cancel = Vec::new();
+        put_u32(&mut cancel, CANCEL);
+        server.received(&frame(FRAME_RST_STREAM, 0, 1, &cancel)).unwrap();
+        server.respond(1, &Response::new(200));
+        assert!(server.pending().is_empty());
+
+        server.received(&frame(FRAME_GOAWAY, 0, 0, &[0; 8])).unwrap();
+        assert!(server.is_closed());
+    }
+
+    #[test]
+    fn client() {
+        let mut client = Http2Connection::client();
+        let mut server = Http2Connection::server();
+        let mut get = Request::new("GET", "/dns-query?dns=AAAB");
+        get.authority = Some("dns.example".to_string());
+        let mut post = Request::new("POST", "/dns-query");
+        post.body = vec![1; 20];
+        assert_eq!((Some(1), Some(3)), (client.request(&get), client.request(&post)));
+
+        let requests = server.received(client.pending()).unwrap();
+        let n = client.pending().len();
+        client.written(n);
+        assert_eq!(vec![1, 3], requests.iter().map(|&(id, _)| id).collect::<Vec<_>>());
+        assert_eq!((get.clone(), vec![1; 20]), (requests[0].1.clone(), requests[1].1.body.clone()));
+        let mut response = Response::new(200);
+        response.body = vec![2; 5];
+        server.respond(3, &response);
+        server.respond(1, &Response::new(404));
+        let responses = client.received_responses(server.pending()).unwrap();
+        assert_eq!(vec![(3, vec![2; 5]), (1, Vec::new())],
+                   responses.iter().map(|&(id, ref r)| (id, r.body.clone())).collect::<Vec<_>>());
+        assert_eq!(404, responses[1].1.status);
+        assert!(!client.is_open(1) && !client.is_open(3));
+
+        // The server goes away having seen stream 5 and not 7.
+        assert_eq!((Some(5), Some(7)), (client.request(&get), client.request(&get)));
+        let mut goaway = Vec::new();
+        put_u32(&mut goaway, 5);
+        put_u32(&mut goaway, NO_ERROR);
+        client.received_responses(&frame(FRAME_GOAWAY, 0, 0, &goaway)).unwrap();
+        assert!(client.is_open(5) && !client.is_open(7));
+        assert_eq!(None, client.request(&get));
+        assert!(!client.is_closed());
+        let mut reset = Vec::new();
+        put_u32(&mut reset, REFUSED_STREAM);
+        client.received_responses(&frame(FRAME_RST_STREAM, 0, 5, &reset)).unwrap();
+        assert!(client.is_closed());
+        assert!(client.received_responses(&frame(FRAME_DATA, 0, 9, b"idle")).is_err());
+    }
+
+    #[test]
+    fn stream_errors() {
+        let mut server = server();
+        // Missing :path, and a pseudo header after a regular one.
+        let mut data = frame(FRAME_HEADERS,
+                             FLAG_END_HEADERS | FLAG_END_STREAM,
+                             1,
+                             &encode(&[(":method", "GET"), (":scheme", "https")]));
+        data.extend(frame(FRAME_HEADERS,
+                          FLAG_END_HEADERS | FLAG_END_STREAM,
+                          3,
+                          &encode(&[(":method", "GET"),
+                                    (":scheme", "https"),
+                                    ("accept", "*/*"),
+                                    (":path", "/")])));
+        // DATA after the stream has ended.
+        data.extend(frame(FRAME_DATA, FLAG_END_STREAM, 3, b"late"));
+        assert!(server.received(&data).unwrap().is_empty());
+        let sent = frames(server.pending());
+        assert_eq!((FRAME_RST_STREAM, 1, vec![0, 0, 0, PROTOCOL_ERROR as u8]),
+                   (sent[0].0, sent[0].2, sent[0].3.clone()));
+        assert_eq!((FRAME_RST_STREAM, 3), (sent[1].0, sent[1].2));
+        assert_eq!((FRAME_RST_STREAM, 3, vec![0, 0, 0, STREAM_CLOSED as u8]),
+                   (sent[3].0, sent[3].2, sent[3].3.clone()));
+        assert!(!server.is_closed());
+    }
+
+    #[test]
+    fn connection_errors() {
+        let cases = [frame(FRAME_DATA, 0, 5, b"idle"),
+                     frame(FRAME_HEADERS, FLAG_END_HEADERS, 2, &request_headers("GET", "/")),
+                     frame(FRAME_HEADERS, FLAG_END_HEADERS, 1, &[0xff]),
+                     frame(FRAME_PING, 0, 1, b"pingpong"),
+                     frame(FRAME_WINDOW_UPDATE, 0, 0, &[0, 0, 0, 0]),
+                     frame(FRAME_SETTINGS, 0, 0, &[0, 5, 0, 0, 0, 1]),
+                     frame(FRAME_HEADERS, 0, 1, &[]),
+                     vec![0, 0x40, 1, FRAME_DATA, 0, 0, 0, 0, 1]];
+        for (i, case) in cases.iter().enumerate() {
+            let mut server = server();
+            let mut data = case.clone();
+            if i == 6 {
+                data.extend(frame(FRAME_PING, 0, 0, b"pingpong"));
+            }
+            assert!(server.received(&data).is_err(), "case {}", i);
+            let sent = frames(server.pending());
+            assert_eq!(FRAME_GOAWAY, sent.last().unwrap().0);
+            assert!(server.is_closed());
+            assert!(server.received(&[]).is_err());
+        }
+        let mut server = Http2Connection::server();
+        assert!(server.received(b"GET / HTTP/1.1\r\n").is_err());
+        let mut server = Http2Connection::server();
+        let mut data = PREFACE.to_vec();
+        data.extend(frame(FRAME_PING, 0, 0, b"pingpong"));
+        assert!(server.received(&data).is_err());
+    }
+}
@@ -0,0 +1,328 @@
+//! HPACK header compression (RFC 7541). Decoding keeps the dynamic table
+//! the peer builds; encoding never adds to ours, so it needs no state.
+
+use std::collections::VecDeque;
+use super::huffman;
+
+/// The size of the dynamic table a peer may use, unless settings say
+/// otherwise.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+// Each entry costs this much over its name and value (Section 4.1).
+const ENTRY_OVERHEAD: usize = 32;
+
+const STATIC_TABLE: [(&'static [u8], &'static [u8]); 61] = [
+    (b":authority", b""),
+    (b":method", b"GET"),
+    (b":method", b"POST"),
+    (b":path", b"/"),
+    (b":path", b"/index.html"),
+    (b":scheme", b"http"),
+    (b":scheme", b"https"),
+    (b":status", b"200"),
+    (b":status", b"204"),
+    (b":status", b"206"),
+    (b":status", b"304"),
+    (b":status", b"400"),
+    (b":status", b"404"),
+    (b":status", b"500"),
+    (b"accept-charset", b""),
+    (b"accept-encoding", b"gzip, deflate"),
+    (b"accept-language", b""),
+    (b"accept-ranges", b""),
+    (b"accept", b""),
+    (b"access-control-allow-origin", b""),
+    (b"age", b""),
+    (b"allow", b""),
+    (b"authorization", b""),
+    (b"cache-control", b""),
+    (b"content-disposition", b""),
+    (b"content-encoding", b""),
+    (b"content-language", b""),
+    (b"content-length", b""),
+    (b"content-location", b""),
+    (b"content-range", b""),
+    (b"content-type", b""),
+    (b"cookie", b""),
+    (b"date", b""),
+    (b"etag", b""),
+    (b"expect", b""),
+    (b"expires", b""),
+    (b"from", b""),
+    (b"host", b""),
+    (b"if-match", b""),
+    (b"if-modified-since", b""),
+    (b"if-none-match", b""),
+    (b"if-range", b""),
+    (b"if-unmodified-since", b""),
+    (b"last-modified", b""),
+    (b"link", b""),
+    (b"location", b""),
+    (b"max-forwards", b""),
+    (b"proxy-authenticate", b""),
+    (b"proxy-authorization", b""),
+    (b"range", b""),
+    (b"referer", b""),
+    (b"refresh", b""),
+    (b"retry-after", b""),
+    (b"server", b""),
+    (b"set-cookie", b""),
+    (b"strict-transport-security", b""),
+    (b"transfer-encoding", b""),
+    (b"user-agent", b""),
+    (b"vary", b""),
+    (b"via", b""),
+    (b"www-authenticate", b""),
+];
+
+/// A header field, its name in lower case.
+pub type Field = (String, String);
+
+fn integer(data: &[u8], at: &mut usize, prefix: u8) -> Option<usize> {
+    let max = (1usize << prefix) - 1;
+    let mut value = match data.get(*at) {
+        Some(&first) => first as usize & max,
+        None => return None,
+    };
+    *at += 1;
+    if value < max {
+        return Some(value);
+    }
+    let mut shift = 0;
+    loop {
+        let byte = match data.get(*at) {
+            Some(&byte) => byte as usize,
+            None => return None,
+        };
+        *at += 1;
+        if shift > 21 {
+            return None;
+        }
+        value += (byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+}
+
+fn string(data: &[u8], at: &mut usize) -> Option<String> {
+    let huffman = match data.get(*at) {
+        Some(&first) => first & 0x80 != 0,
+        None => return None,
+    };
+    let len = match integer(data, at, 7) {
+        Some(len) if *at + len <= data.len() => len,
+        _ => return None,
+    };
+    let raw = &data[*at..*at + len];
+    *at += len;
+    let octets = if huffman {
+        match huffman::decode(raw) {
+            Some(octets) => octets,
+            None => return None,
+        }
+    } else {
+        raw.to_vec()
+    };
+    Some(String::from_utf8_lossy(&octets).into_owned())
+}
+
+/// The decoding side of one connection's header compression.
+#[derive(Debug)]
+pub struct Decoder {
+    table: VecDeque<Field>,
+    size: usize,
+    capacity: usize,
+    max_capacity: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            capacity: DEFAULT_TABLE_SIZE,
+            max_capacity: DEFAULT_TABLE_SIZE,
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.capacity {
+            let (name, value) = self.table.pop_back().unwrap();
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+
+    fn field(&self, index: usize) -> Option<Field> {
+        if index == 0 {
+            return None;
+        }
+        if index <= STATIC_TABLE.len() {
+            let (name, value) = STATIC_TABLE[index - 1];
+            return Some((String::from_utf8_lossy(name).into_owned(),
+                         String::from_utf8_lossy(value).into_owned()));
+        }
+        self.table.get(index - STATIC_TABLE.len() - 1).cloned()
+    }
+
+    /// Decodes a complete header block. Returns None if it is malformed,
+    /// after which the connection cannot go on.
+    pub fn decode(&mut self, block: &[u8]) -> Option<Vec<Field>> {
+        let mut fields = Vec::new();
+        let mut at = 0;
+        while at < block.len() {
+            let first = block[at];
+            if first & 0x80 != 0 {
+                match integer(block, &mut at, 7).and_then(|index| self.field(index)) {
+                    Some(field) => fields.push(field),
+                    None => return None,
+                }
+                continue;
+            }
+            if first & 0xe0 == 0x20 {
+                // A table size update, only allowed before any field.
+                match integer(block, &mut at, 5) {
+                    Some(capacity) if capacity <= self.max_capacity && fields.is_empty() => {
+                        self.capacity = capacity;
+                        self.evict();
+                    }
+                    _ => return None,
+                }
+                continue;
+            }
+            let indexing = first & 0xc0 == 0x40;
+            let prefix = if indexing { 6 } else { 4 };
+            let name = match integer(block, &mut at, prefix) {
+                Some(0) => string(block, &mut at),
+                Some(index) => self.field(index).map(|(name, _)| name),
+                None => None,
+            };
+            let field = match (name, string(block, &mut at)) {
+                (Some(name), Some(value)) => (name, value),
+                _ => return None,
+            };
+            if indexing {
+                self.size += field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+                self.table.push_front(field.clone());
+                self.evict();
+            }
+            fields.push(field);
+        }
+        Some(fields)
+    }
+}
+
+fn put_integer(out: &mut Vec<u8>, flags: u8, prefix: u8, value: usize) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    let mut value = value - max;
+    while value >= 0x80 {
+        out.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_string(out: &mut Vec<u8>, text: &str) {
+    put_integer(out, 0, 7, text.len());
+    out.extend(text.as_bytes().iter().cloned());
+}
+
+/// Encodes header fields, names already in lower case. Fields in the
+/// static table are referred to there; the rest are literals that are not
+/// indexed.
+pub fn encode(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for &(name, value) in fields {
+        let exact = STATIC_TABLE.iter()
+                                .position(|&(n, v)| n == name.as_bytes() && v == value.as_bytes());
+        if let Some(index) = exact {
+            put_integer(&mut out, 0x80, 7, index + 1);
+            continue;
+        }
+        match STATIC_TABLE.iter().position(|&(n, _)| n == name.as_bytes()) {
+            Some(index) => put_integer(&mut out, 0, 4, index + 1),
+            None => {
+                out.push(0);
+                put_string(&mut out, name);
+            }
+        }
+        put_string(&mut out, value);
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<Field> {
+        fields.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn requests_with_huffman() {
+        // RFC 7541 Appendix C.4: three requests sharing a dynamic table.
+        let mut decoder = Decoder::new();
+        let first = [0x82, 0x86, 0x84, 0x41, 0x8c, 0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b,
+                     0xa0, 0xab, 0x90, 0xf4, 0xff];
+        assert_eq!(Some(fields(&[(":method", "GET"),
+                                 (":scheme", "http"),
+                                 (":path", "/"),
+                                 (":authority", "www.example.com")])),
+                   decoder.decode(&first));
+        let second = [0x82, 0x86, 0x84, 0xbe, 0x58, 0x86, 0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf];
+        assert_eq!(Some(fields(&[(":method", "GET"),
+                                 (":scheme", "http"),
+                                 (":path", "/"),
+                                 (":authority", "www.example.com"),
+                                 ("cache-control", "no-cache")])),
+                   decoder.decode(&second));
+        let third = [0x82, 0x87, 0x85, 0xbf, 0x40, 0x88, 0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xa9,
+                     0x7d, 0x7f, 0x89, 0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xb8, 0xe8, 0xb4, 0xbf];
+        assert_eq!(Some(fields(&[(":method", "GET"),
+                                 (":scheme", "https"),
+                                 (":path", "/index.html"),
+                                 (":authority", "www.example.com"),
+                                 ("custom-key", "custom-value")])),
+                   decoder.decode(&third));
+        assert_eq!(164, decoder.size);
+    }
+
+    #[test]
+    fn eviction_and_errors() {
+        let mut decoder = Decoder::new();
+        // Shrink the table to fit one entry, then add two.
+        let mut block = vec![0x3f, 0x06];
+        for value in &["aaaa", "bbbb"] {
+            block.extend(&[0x40, 0x01, b'x', 0x04]);
+            block.extend(value.as_bytes());
+        }
+        block.push(0xbe);
+        assert_eq!(Some(fields(&[("x", "aaaa"), ("x", "bbbb"), ("x", "bbbb")])),
+                   decoder.decode(&block));
+        assert_eq!(1, decoder.table.len());
+        assert_eq!(None, decoder.decode(&[0xbf]));
+        assert_eq!(None, decoder.decode(&[0x80]));
+        assert_eq!(None, decoder.decode(&[0x3f, 0xe2, 0x1f]));
+        assert_eq!(None, decoder.decode(&[0x04, 0x85, b'/']));
+    }
+
+    #[test]
+    fn round_trip() {
+        let block = encode(&[(":status", "200"),
+                             ("content-type", "application/dns-message"),
+                             ("x-long", &"v".repeat(200))]);
+        assert_eq!(0x88, block[0]);
+        assert_eq!(Some(fields(&[(":status", "200"),
+                                 ("content-type", "application/dns-message"),
+                                 ("x-long", &"v".repeat(200))])),
+                   Decoder::new().decode(&block));
+    }
+}
@@ -0,0 +1,97 @@
+//! The Huffman code HPACK compresses strings with (RFC 7541 Appendix B).
+//! The code is canonical, so the length of each symbol's code is all it
+//! takes to rebuild it.
+
+/// The length in bits of the code for each octet, and for end of string.
+const CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+    6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6,
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10,
+    13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6,
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5,
+    6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28,
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+    30,
+];
+
+const EOS: usize = 256;
+const MAX_LENGTH: usize = 30;
+
+/// Decodes a Huffman coded string. Returns None if it holds the end of
+/// string symbol or is padded with anything but the start of one.
+pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    // How many codes there are of each length, and the symbols in order of
+    // their codes.
+    let mut counts = [0usize; MAX_LENGTH + 1];
+    for &len in CODE_LENGTHS.iter() {
+        counts[len as usize] += 1;
+    }
+    let mut symbols: Vec<usize> = (0..CODE_LENGTHS.len()).collect();
+    symbols.sort_by_key(|&symbol| CODE_LENGTHS[symbol]);
+
+    let mut result = Vec::with_capacity(data.len() * 8 / 5);
+    let (mut code, mut first, mut index, mut len) = (0usize, 0usize, 0usize, 0usize);
+    for &byte in data {
+        for bit in (0..8).rev() {
+            code |= ((byte >> bit) & 1) as usize;
+            len += 1;
+            let count = counts[len];
+            if code < first + count {
+                let symbol = symbols[index + code - first];
+                if symbol == EOS {
+                    return None;
+                }
+                result.push(symbol as u8);
+                code = 0;
+                first = 0;
+                index = 0;
+                len = 0;
+                continue;
+            }
+            if len == MAX_LENGTH {
+                return None;
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+    }
+    // Padding is the most significant bits of EOS, which are all ones. The
+    // bits left over were shifted up one ready for the next.
+    if len > 7 || code >> 1 != (1 << len) - 1 {
+        return None;
+    }
+    Some(result)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strings() {
+        // RFC 7541 Appendix C.4 and C.6.
+        assert_eq!(Some(b"www.example.com".to_vec()),
+                   decode(&[0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4,
+                            0xff]));
+        assert_eq!(Some(b"no-cache".to_vec()),
+                   decode(&[0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf]));
+        assert_eq!(Some(b"302".to_vec()), decode(&[0x64, 0x02]));
+        assert_eq!(Some(b"a".to_vec()), decode(&[0x1f]));
+        assert_eq!(Some(Vec::new()), decode(&[]));
+        // Padding too long, padding of zeros, and EOS itself.
+        assert_eq!(None, decode(&[0x64, 0x02, 0xff]));
+        assert_eq!(None, decode(&[0x18]));
+        assert_eq!(None, decode(&[0xff, 0xff, 0xff, 0xff]));
+    }
+}
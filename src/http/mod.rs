@@ -0,0 +1,126 @@
+//! HTTP as DNS over HTTPS uses it: HTTP/1.1 (RFC 9112) and HTTP/2
+//! (RFC 9113), each request and response whole, bodies and all, since DNS
+//! messages are small. Like `tls`, nothing here touches a socket.
+
+mod h1;
+mod h2;
+mod hpack;
+mod huffman;
+
+pub use self::h1::Http1Server;
+pub use self::h2::{Http2Connection, MAX_STREAMS, PREFACE};
+
+use std::error::Error;
+use std::fmt;
+use tls::TlsError;
+
+/// The longest request or response body taken.
+pub const MAX_BODY: usize = 65535;
+
+// HTTP/2 error codes (RFC 9113 Section 7).
+pub const NO_ERROR: u32 = 0;
+pub const PROTOCOL_ERROR: u32 = 1;
+pub const INTERNAL_ERROR: u32 = 2;
+pub const FLOW_CONTROL_ERROR: u32 = 3;
+pub const STREAM_CLOSED: u32 = 5;
+pub const FRAME_SIZE_ERROR: u32 = 6;
+pub const REFUSED_STREAM: u32 = 7;
+pub const CANCEL: u32 = 8;
+pub const COMPRESSION_ERROR: u32 = 9;
+pub const ENHANCE_YOUR_CALM: u32 = 11;
+
+/// Why a connection failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HttpError {
+    /// The peer broke the protocol; the HTTP/2 error code sent back.
+    Local(u32, &'static str),
+    /// The peer gave up on the connection with this HTTP/2 error code.
+    Remote(u32),
+    Tls(TlsError),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HttpError::Remote(code) => write!(fmt, "HTTP/2 error {} from peer", code),
+            HttpError::Tls(ref error) => write!(fmt, "{}", error),
+            _ => write!(fmt, "{}", self.description()),
+        }
+    }
+}
+
+impl Error for HttpError {
+    fn description(&self) -> &str {
+        match *self {
+            HttpError::Local(_, reason) => reason,
+            HttpError::Remote(_) => "HTTP/2 error from peer",
+            HttpError::Tls(ref error) => error.description(),
+        }
+    }
+}
+
+impl From<TlsError> for HttpError {
+    fn from(error: TlsError) -> HttpError {
+        HttpError::Tls(error)
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref value)| &value[..])
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    /// The path and query.
+    pub path: String,
+    pub authority: Option<String>,
+    /// Other header fields, names in lower case.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: &str, path: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            authority: None,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// The value of a header field, named in lower case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    /// Header fields, names in lower case, not counting Content-Length.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status: status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn add_header(&mut self, name: &str, value: &str) -> &mut Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The value of a header field, named in lower case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
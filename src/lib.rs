@@ -1,3 +1,28 @@
+extern crate bytes;
+extern crate crypto_secretbox;
+extern crate quinn_proto;
+extern crate ring;
+extern crate rustls;
+extern crate socket2;
 extern crate url;
+extern crate webpki;
+extern crate webpki_roots;
+extern crate x25519_dalek;
 
+pub mod client;
+pub mod crypto;
+pub mod dnscrypt;
+pub mod dnssd;
+pub mod dnssec;
+pub mod http;
+pub mod llmnr;
+pub mod mdns;
+pub mod odoh;
 pub mod protocol;
+pub mod server;
+pub mod tls;
+pub mod tsig;
+pub mod zone;
+
+#[cfg(test)]
+mod test_util;
@@ -0,0 +1,231 @@
+//! Link-Local Multicast Name Resolution (RFC 4795): single-label names
+//! asked for over UDP to 224.0.0.252 and ff02::1:3 on port 5355, and
+//! answered unicast by the hosts that own them.
+//!
+//! The header has its own flags: C in a query says the querier heard more
+//! than one answer, and in a response that the name is not unique; T says
+//! the responder has not yet checked that no one else has the name. Both
+//! ends only keep the books, with `MdnsSocket` joined to LLMNR's group and
+//! port carrying their messages.
+
+mod querier;
+mod responder;
+
+pub use self::querier::{LlmnrQuery, resolve};
+pub use self::responder::{LlmnrResponder, VERIFY_COUNT};
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use protocol::{Header, HeaderMut, LLMNR_LAYOUT, MessageCursor, Name, QuestionMut, Record};
+
+pub const LLMNR_PORT: u16 = 5355;
+pub const LLMNR_IPV4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 252);
+pub const LLMNR_IPV6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 3);
+
+/// Milliseconds to wait for responses, and between the queries that check
+/// a name is unique.
+pub const LLMNR_TIMEOUT_MS: u64 = 1000;
+
+/// The longest message sent over UDP; responses that would be longer are
+/// cut short and marked truncated.
+pub const MAX_UDP_MESSAGE: usize = 512;
+
+/// A message as the responder and querier read and write it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LlmnrMessage {
+    pub id: u16,
+    pub is_response: bool,
+    pub conflict: bool,
+    pub truncated: bool,
+    pub tentative: bool,
+    pub rc: u8,
+    /// Name, type and class.
+    pub questions: Vec<(Name, u16, u16)>,
+    pub answers: Vec<Record>,
+    pub authorities: Vec<Record>,
+    pub additionals: Vec<Record>,
+}
+
+impl LlmnrMessage {
+    pub fn new(id: u16, is_response: bool) -> LlmnrMessage {
+        LlmnrMessage {
+            id: id,
+            is_response: is_response,
+            conflict: false,
+            truncated: false,
+            tentative: false,
+            rc: 0,
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        }
+    }
+
+    /// Reads a message. Returns None for anything malformed, and for
+    /// messages with an opcode, which are ignored.
+    pub fn parse(message: &[u8]) -> Option<LlmnrMessage> {
+        let header = Header::at(message).with_layout(&LLMNR_LAYOUT);
+        if message.len() < header.end_offset() || header.op() != Some(0) {
+            return None;
+        }
+        let mut parsed = LlmnrMessage::new(header.id().unwrap(), header.is_response());
+        parsed.conflict = header.c().unwrap();
+        parsed.truncated = header.is_truncated();
+        parsed.tentative = header.t().unwrap();
+        parsed.rc = header.rc().unwrap();
+        for question in header.questions() {
+            let name = question.name().and_then(|n| Name::from_domain_name(message, n));
+            match (name, question.qtype(), question.qclass()) {
+                (Some(name), Some(qtype), Some(qclass)) => {
+                    parsed.questions.push((name, qtype, qclass))
+                }
+                _ => return None,
+            }
+        }
+        let mut at = match header.questions().end_offset() {
+            Some(at) => at,
+            None => return None,
+        };
+        let counts = [header.an().unwrap(), header.ns().unwrap(), header.ar().unwrap()];
+        for (i, &count) in counts.iter().enumerate() {
+            let (records, next) = match Record::read_section(message, at, count) {
+                Some(section) => section,
+                None => return None,
+            };
+            match i {
+                0 => parsed.answers = records,
+                1 => parsed.authorities = records,
+                _ => parsed.additionals = records,
+            }
+            at = next;
+        }
+        Some(parsed)
+    }
+
+    /// The message on the wire, no longer than `MAX_UDP_MESSAGE`: records
+    /// that do not fit are left out, and the message marked truncated.
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut message = vec![0u8; MAX_UDP_MESSAGE];
+        let mut idx = MessageCursor::new(message.len());
+        HeaderMut::at(&mut idx, &mut message[..])
+            .unwrap()
+            .with_layout(&LLMNR_LAYOUT)
+            .set_id(self.id)
+            .set_qr(self.is_response)
+            .set_c(self.conflict)
+            .set_t(self.tentative)
+            .set_rc(self.rc);
+        let mut qd = 0;
+        for &(ref name, qtype, qclass) in &self.questions {
+            if QuestionMut::at(&mut idx, &mut message, &name.segments(), qtype, qclass).is_some() {
+                qd += 1;
+            }
+        }
+        let mut counts = [0u16; 3];
+        let mut truncated = self.truncated || qd < self.questions.len() as u16;
+        for (i, section) in [&self.answers, &self.authorities, &self.additionals]
+                                .iter()
+                                .enumerate() {
+            for record in section.iter() {
+                if truncated {
+                    break;
+                }
+                let at = idx.tell();
+                match record.write_at(&mut idx, &mut message) {
+                    Some(_) => counts[i] += 1,
+                    None => {
+                        idx.rewind(at);
+                        truncated = true;
+                    }
+                }
+            }
+        }
+        HeaderMut::at_raw(&mut message[..])
+            .set_tc(truncated)
+            .set_qd(qd)
+            .set_an(counts[0])
+            .set_ns(counts[1])
+            .set_ar(counts[2]);
+        message.truncate(idx.tell());
+        message
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{IpAddr, UdpSocket};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use mdns::MdnsSocket;
+    use protocol::{Name, RData, Record};
+    use protocol::rdata::{CLASS_IN, TYPE_A};
+
+    #[test]
+    fn messages() {
+        let name = Name::parse("printer.", None).unwrap();
+        let mut message = LlmnrMessage::new(0x1234, false);
+        message.conflict = true;
+        message.questions.push((name.clone(), TYPE_A, CLASS_IN));
+        let wire = message.to_wire();
+        // C where AA is, and no RD.
+        assert_eq!(&[0x12, 0x34, 0x04, 0, 0, 1, 0, 0, 0, 0, 0, 0], &wire[..12]);
+        assert_eq!(Some(message.clone()), LlmnrMessage::parse(&wire));
+
+        message.is_response = true;
+        message.conflict = false;
+        message.tentative = true;
+        let address = Record::new(name, CLASS_IN, 30, RData::A("192.0.2.5".parse().unwrap()));
+        message.answers.push(address.clone());
+        let wire = message.to_wire();
+        assert_eq!(&[0x12, 0x34, 0x81, 0, 0, 1, 0, 1, 0, 0, 0, 0], &wire[..12]);
+        assert_eq!(Some(message.clone()), LlmnrMessage::parse(&wire));
+
+        // Too many answers for one datagram.
+        for _ in 0..40 {
+            message.answers.push(address.clone());
+        }
+        let wire = message.to_wire();
+        assert!(wire.len() <= MAX_UDP_MESSAGE);
+        let parsed = LlmnrMessage::parse(&wire).unwrap();
+        assert!(parsed.truncated);
+        assert!(parsed.answers.len() < 41);
+
+        let mut update = wire.clone();
+        update[2] |= 0x28;
+        assert_eq!(None, LlmnrMessage::parse(&update));
+        assert_eq!(None, LlmnrMessage::parse(&wire[..wire.len() - 1]));
+    }
+
+    #[test]
+    fn loopback() {
+        // A port of our own, rather than the one other hosts use.
+        let port = UdpSocket::bind("0.0.0.0:0").unwrap().local_addr().unwrap().port();
+        let group = IpAddr::V4(LLMNR_IPV4);
+        let name = Name::parse("printer.", None).unwrap();
+        let printer = |address: &str| {
+            Record::new(name.clone(), CLASS_IN, 30, RData::A(address.parse().unwrap()))
+        };
+
+        let socket = MdnsSocket::join(group, port).unwrap();
+        let mut responder = LlmnrResponder::new();
+        responder.publish(printer("192.0.2.5"), Instant::now());
+        let thread = thread::spawn(move || {
+            responder.run(&socket, Some(Instant::now() + Duration::from_millis(2500))).unwrap();
+        });
+        thread::sleep(Duration::from_millis(100));
+
+        // Answered tentatively while the name is being checked.
+        let socket = MdnsSocket::one_shot(group, port).unwrap();
+        assert_eq!(vec![printer("192.0.2.5")], resolve(&socket, &name, TYPE_A).unwrap());
+
+        // Another host wanting the name finds it taken.
+        let socket = MdnsSocket::one_shot(group, port).unwrap();
+        let mut other = LlmnrResponder::new();
+        other.publish(printer("192.0.2.6"), Instant::now());
+        other.run(&socket, Some(Instant::now() + Duration::from_millis(500))).unwrap();
+        assert!(other.is_conflicted(&name));
+        thread.join().unwrap();
+    }
+}
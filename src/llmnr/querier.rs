@@ -0,0 +1,150 @@
+//! The querier (RFC 4795 Sections 2.1.1, 2.4 and 4.2): asks the link, and
+//! weighs the answers of every host that responds.
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use crypto::random_bytes;
+use mdns::MdnsSocket;
+use protocol::{Name, Record};
+use protocol::rdata::CLASS_IN;
+use super::{LLMNR_TIMEOUT_MS, LlmnrMessage};
+
+/// One question put to the link, and the responses to it.
+#[derive(Debug)]
+pub struct LlmnrQuery {
+    id: u16,
+    name: Name,
+    qtype: u16,
+    // Who responded, whether tentatively, and with what.
+    responses: Vec<(SocketAddr, bool, Vec<Record>)>,
+}
+
+impl LlmnrQuery {
+    pub fn new(name: &Name, qtype: u16) -> LlmnrQuery {
+        let id = random_bytes(2);
+        LlmnrQuery {
+            id: (id[0] as u16) << 8 | id[1] as u16,
+            name: name.clone(),
+            qtype: qtype,
+            responses: Vec::new(),
+        }
+    }
+
+    /// The query, with C set if it tells responders of a conflict.
+    pub fn to_wire(&self, conflict: bool) -> Vec<u8> {
+        let mut query = LlmnrMessage::new(self.id, false);
+        query.conflict = conflict;
+        query.questions.push((self.name.clone(), self.qtype, CLASS_IN));
+        query.to_wire()
+    }
+
+    /// Takes a message from `from`, keeping it if it responds to this
+    /// query without error.
+    pub fn received(&mut self, message: &[u8], from: SocketAddr) {
+        let response = match LlmnrMessage::parse(message) {
+            Some(response) => response,
+            None => return,
+        };
+        if !response.is_response || response.id != self.id || response.rc != 0 ||
+           response.questions != vec![(self.name.clone(), self.qtype, CLASS_IN)] ||
+           self.responses.iter().any(|r| r.0 == from) {
+            return;
+        }
+        self.responses.push((from, response.tentative, response.answers));
+    }
+
+    /// Whether more than one host answered as the name's sole owner.
+    pub fn is_conflicted(&self) -> bool {
+        self.responses.iter().filter(|r| !r.1).count() > 1
+    }
+
+    /// The answers of the first host to answer as the name's owner, or
+    /// else those of a lone tentative responder (Section 4.1).
+    pub fn answers(&self) -> Vec<Record> {
+        if let Some(response) = self.responses.iter().find(|r| !r.1) {
+            return response.2.clone();
+        }
+        match self.responses.len() {
+            1 => self.responses[0].2.clone(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Asks the link for `name` and `qtype` over `socket`, waits for every
+/// host's response, and returns the answers. Should more than one host
+/// claim the name, they are told so.
+pub fn resolve(socket: &MdnsSocket, name: &Name, qtype: u16) -> io::Result<Vec<Record>> {
+    let mut query = LlmnrQuery::new(name, qtype);
+    try!(socket.send(&query.to_wire(false), None));
+    let deadline = Instant::now() + Duration::from_millis(LLMNR_TIMEOUT_MS);
+    while let Some((message, from)) = try!(socket.receive(deadline)) {
+        query.received(&message, from);
+    }
+    if query.is_conflicted() {
+        try!(socket.send(&query.to_wire(true), None));
+    }
+    Ok(query.answers())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::SocketAddr;
+    use llmnr::LlmnrMessage;
+    use protocol::{Name, RData, Record};
+    use protocol::rdata::{CLASS_IN, TYPE_A};
+
+    fn response(query: &LlmnrQuery, tentative: bool, address: &str) -> Vec<u8> {
+        let query = LlmnrMessage::parse(&query.to_wire(false)).unwrap();
+        let mut response = LlmnrMessage::new(query.id, true);
+        response.tentative = tentative;
+        response.answers.push(Record::new(query.questions[0].0.clone(),
+                                          CLASS_IN,
+                                          30,
+                                          RData::A(address.parse().unwrap())));
+        response.questions = query.questions;
+        response.to_wire()
+    }
+
+    fn host(n: u8) -> SocketAddr {
+        SocketAddr::new(format!("192.0.2.{}", n).parse().unwrap(), 5355)
+    }
+
+    #[test]
+    fn answers() {
+        let name = Name::parse("printer.", None).unwrap();
+        let mut query = LlmnrQuery::new(&name, TYPE_A);
+        let sent = LlmnrMessage::parse(&query.to_wire(true)).unwrap();
+        assert!(sent.conflict && !sent.is_response);
+        assert_eq!(vec![(name.clone(), TYPE_A, CLASS_IN)], sent.questions);
+        assert!(query.answers().is_empty());
+
+        // A lone tentative answer will do.
+        query.received(&response(&query, true, "192.0.2.5"), host(5));
+        assert_eq!(RData::A("192.0.2.5".parse().unwrap()), query.answers()[0].rdata);
+        query.received(&response(&query, true, "192.0.2.7"), host(7));
+        assert!(query.answers().is_empty());
+
+        // One that is sure beats those that are not.
+        query.received(&response(&query, false, "192.0.2.6"), host(6));
+        query.received(&response(&query, false, "192.0.2.8"), host(6));
+        assert_eq!(RData::A("192.0.2.6".parse().unwrap()), query.answers()[0].rdata);
+        assert!(!query.is_conflicted());
+        query.received(&response(&query, false, "192.0.2.8"), host(8));
+        assert!(query.is_conflicted());
+        assert_eq!(RData::A("192.0.2.6".parse().unwrap()), query.answers()[0].rdata);
+
+        // Responses to something else are ignored.
+        let other = LlmnrQuery::new(&name, TYPE_A);
+        let mut error = LlmnrMessage::parse(&response(&query, false, "192.0.2.9")).unwrap();
+        error.rc = 3;
+        let mut fresh = LlmnrQuery::new(&name, TYPE_A);
+        fresh.received(&response(&other, false, "192.0.2.9"), host(9));
+        fresh.received(&error.to_wire(), host(9));
+        fresh.received(&fresh.to_wire(false), host(9));
+        assert!(fresh.answers().is_empty());
+    }
+}
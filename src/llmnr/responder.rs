@@ -0,0 +1,330 @@
+//! The responder (RFC 4795 Sections 2.1.1, 2.2 and 4): answers for the
+//! names this host owns, once it has checked no other host owns them.
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use crypto::random_bytes;
+use mdns::MdnsSocket;
+use protocol::{Name, Record};
+use protocol::rdata::{CLASS_ANY, CLASS_IN, TYPE_ANY};
+use super::{LLMNR_TIMEOUT_MS, LlmnrMessage};
+
+/// Queries sent to check a name is unique before it is answered for
+/// without the T bit.
+pub const VERIFY_COUNT: u32 = 3;
+
+// How long to wait for messages when nothing is due.
+const IDLE_WAIT_MS: u64 = 60000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Verifying,
+    Unique,
+    // Another host answered for the name; it is no longer answered for.
+    Conflicted,
+}
+
+#[derive(Debug)]
+struct Owned {
+    name: Name,
+    state: State,
+    // Queries sent while verifying, the ID they had, and when the next is
+    // due.
+    sent: u32,
+    id: u16,
+    next: Instant,
+}
+
+/// The names this host owns and the records it answers with. What comes in
+/// goes to `received`; what is to be sent comes out of `poll`, with None
+/// for the group or the address to send to.
+#[derive(Debug)]
+pub struct LlmnrResponder {
+    owned: Vec<Owned>,
+    records: Vec<Record>,
+    // Responses waiting to go out, and where to.
+    queued: Vec<(Instant, Vec<u8>, Option<SocketAddr>)>,
+}
+
+impl LlmnrResponder {
+    pub fn new() -> LlmnrResponder {
+        LlmnrResponder {
+            owned: Vec::new(),
+            records: Vec::new(),
+            queued: Vec::new(),
+        }
+    }
+
+    /// Answers with `record`, checking first that its name is unique if it
+    /// is new.
+    pub fn publish(&mut self, record: Record, now: Instant) {
+        if !self.owned.iter().any(|o| o.name == record.name) {
+            self.owned.push(Owned {
+                name: record.name.clone(),
+                state: State::Verifying,
+                sent: 0,
+                id: 0,
+                next: now,
+            });
+        }
+        self.records.push(record);
+    }
+
+    /// Whether `name` has been found to be this host's alone.
+    pub fn is_unique(&self, name: &Name) -> bool {
+        self.owned.iter().any(|o| o.name == *name && o.state == State::Unique)
+    }
+
+    /// Whether another host turned out to own `name`.
+    pub fn is_conflicted(&self, name: &Name) -> bool {
+        self.owned.iter().any(|o| o.name == *name && o.state == State::Conflicted)
+    }
+
+    /// The messages due by `now`: queries checking names are unique, and
+    /// responses.
+    pub fn poll(&mut self, now: Instant) -> Vec<(Vec<u8>, Option<SocketAddr>)> {
+        let mut messages: Vec<(Vec<u8>, Option<SocketAddr>)> =
+            self.queued.drain(..).map(|(_, message, to)| (message, to)).collect();
+        for owned in self.owned.iter_mut() {
+            if owned.state != State::Verifying || owned.next > now {
+                continue;
+            }
+            if owned.sent == VERIFY_COUNT {
+                // No one answered.
+                owned.state = State::Unique;
+                continue;
+            }
+            let id = random_bytes(2);
+            owned.id = (id[0] as u16) << 8 | id[1] as u16;
+            owned.sent += 1;
+            owned.next = now + Duration::from_millis(LLMNR_TIMEOUT_MS);
+            let mut query = LlmnrMessage::new(owned.id, false);
+            query.questions.push((owned.name.clone(), TYPE_ANY, CLASS_IN));
+            messages.push((query.to_wire(), None));
+        }
+        messages
+    }
+
+    /// When `poll` next has something to send, if ever.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.owned
+            .iter()
+            .filter(|o| o.state == State::Verifying)
+            .map(|o| o.next)
+            .chain(self.queued.iter().map(|q| q.0))
+            .min()
+    }
+
+    /// Takes a message from `from`: a query to answer, or a response to a
+    /// query checking a name is unique.
+    pub fn received(&mut self, message: &[u8], from: SocketAddr, now: Instant) {
+        let message = match LlmnrMessage::parse(message) {
+            Some(message) => message,
+            None => return,
+        };
+        if message.is_response {
+            self.check_conflict(&message);
+        } else {
+            self.answer(&message, from, now);
+        }
+    }
+
+    // A response to one of our checks from another host with the name
+    // (Section 4.1).
+    fn check_conflict(&mut self, response: &LlmnrMessage) {
+        let records = &self.records;
+        let owned = self.owned.iter_mut().find(|o| {
+            o.state == State::Verifying && o.sent > 0 && o.id == response.id
+        });
+        if let Some(owned) = owned {
+            let theirs = response.answers.iter().any(|answer| {
+                answer.name == owned.name &&
+                !records.iter().any(|r| r.name == answer.name && r.rdata == answer.rdata)
+            });
+            if theirs {
+                owned.state = State::Conflicted;
+            }
+        }
+    }
+
+    // Answers a query for a name we own, unicast to whoever asked (Section
+    // 2.2), with T set while the name is not known to be ours alone.
+    fn answer(&mut self, query: &LlmnrMessage, from: SocketAddr, now: Instant) {
+        if query.questions.len() != 1 || !query.answers.is_empty() ||
+           !query.authorities.is_empty() {
+            return;
+        }
+        let (ref name, qtype, qclass) = query.questions[0];
+        let owned = match self.owned.iter_mut().find(|o| o.name == *name) {
+            Some(owned) => owned,
+            None => return,
+        };
+        // Our own checks coming back.
+        if owned.state == State::Verifying && owned.sent > 0 && owned.id == query.id {
+            return;
+        }
+        match owned.state {
+            State::Conflicted => return,
+            // The querier heard more than one answer: check again
+            // (Section 4.2).
+            State::Unique if query.conflict => {
+                owned.state = State::Verifying;
+                owned.sent = 0;
+                owned.next = now;
+            }
+            _ => {}
+        }
+        let mut response = LlmnrMessage::new(query.id, true);
+        response.tentative = owned.state != State::Unique;
+        response.questions = query.questions.clone();
+        response.answers = self.records
+                               .iter()
+                               .filter(|r| {
+                                   r.name == *name &&
+                                   (qtype == TYPE_ANY || qtype == r.rtype()) &&
+                                   (qclass == CLASS_ANY || qclass == r.class)
+                               })
+                               .cloned()
+                               .collect();
+        self.queued.push((now, response.to_wire(), Some(from)));
+    }
+
+    /// Runs the responder on `socket` until `until`, or for good.
+    pub fn run(&mut self, socket: &MdnsSocket, until: Option<Instant>) -> io::Result<()> {
+        loop {
+            let now = Instant::now();
+            for (message, to) in self.poll(now) {
+                try!(socket.send(&message, to));
+            }
+            let mut deadline = self.deadline()
+                                   .unwrap_or(now + Duration::from_millis(IDLE_WAIT_MS));
+            match until {
+                Some(until) if until <= now => return Ok(()),
+                Some(until) if until < deadline => deadline = until,
+                _ => {}
+            }
+            if let Some((message, from)) = try!(socket.receive(deadline)) {
+                self.received(&message, from, Instant::now());
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+    use llmnr::{LLMNR_TIMEOUT_MS, LlmnrMessage};
+    use protocol::{HeaderMut, Name, RData, Record};
+    use protocol::rdata::{CLASS_IN, TYPE_A, TYPE_AAAA, TYPE_ANY};
+    use test_util;
+
+    fn printer(address: &str) -> Record {
+        Record::new(Name::parse("printer.", None).unwrap(),
+                    CLASS_IN,
+                    30,
+                    RData::A(address.parse().unwrap()))
+    }
+
+    // A query from test_util without the recursion desired bit, which is
+    // the tentative bit in LLMNR.
+    fn query(id: u16, qtype: u16) -> LlmnrMessage {
+        let mut query = test_util::query(id, "printer.", qtype);
+        HeaderMut::at_raw(&mut query[..]).set_rd(false);
+        LlmnrMessage::parse(&query).unwrap()
+    }
+
+    fn from() -> SocketAddr {
+        "192.0.2.9:49152".parse().unwrap()
+    }
+
+    #[test]
+    fn verifying_and_answering() {
+        let name = Name::parse("printer.", None).unwrap();
+        let start = Instant::now();
+        let mut responder = LlmnrResponder::new();
+        responder.publish(printer("192.0.2.5"), start);
+        assert_eq!(Some(start), responder.deadline());
+
+        let mut now = start;
+        for _ in 0..VERIFY_COUNT {
+            let sent = responder.poll(now);
+            assert_eq!(1, sent.len());
+            assert_eq!(None, sent[0].1);
+            let check = LlmnrMessage::parse(&sent[0].0).unwrap();
+            assert_eq!(vec![(name.clone(), TYPE_ANY, CLASS_IN)], check.questions);
+            assert!(!check.is_response && !check.conflict && !check.tentative);
+            // Which comes back to us, and is no query to answer.
+            responder.received(&sent[0].0, from(), now);
+            assert!(!responder.is_unique(&name));
+
+            // Answered for, tentatively, in the meantime.
+            responder.received(&query(7, TYPE_A).to_wire(), from(), now);
+            let sent = responder.poll(now);
+            assert_eq!(1, sent.len());
+            assert_eq!(Some(from()), sent[0].1);
+            let response = LlmnrMessage::parse(&sent[0].0).unwrap();
+            assert!(response.is_response && response.tentative && !response.conflict);
+            assert_eq!((7, query(7, TYPE_A).questions), (response.id, response.questions));
+            assert_eq!(vec![printer("192.0.2.5")], response.answers);
+
+            now = now + Duration::from_millis(LLMNR_TIMEOUT_MS);
+            assert_eq!(Some(now), responder.deadline());
+        }
+        assert!(responder.poll(now).is_empty());
+        assert!(responder.is_unique(&name));
+        assert_eq!(None, responder.deadline());
+
+        responder.received(&query(8, TYPE_AAAA).to_wire(), from(), now);
+        let response = LlmnrMessage::parse(&responder.poll(now)[0].0).unwrap();
+        assert!(!response.tentative);
+        assert!(response.answers.is_empty());
+
+        // Not our name, or not a question we answer.
+        let mut other = query(9, TYPE_A);
+        other.questions[0].0 = Name::parse("scanner.", None).unwrap();
+        responder.received(&other.to_wire(), from(), now);
+        let mut two = query(9, TYPE_A);
+        two.questions.push(other.questions[0].clone());
+        responder.received(&two.to_wire(), from(), now);
+        let mut known = query(9, TYPE_A);
+        known.answers.push(printer("192.0.2.5"));
+        responder.received(&known.to_wire(), from(), now);
+        assert!(responder.poll(now).is_empty());
+
+        // A querier heard from more than one of us.
+        let mut conflict = query(10, TYPE_A);
+        conflict.conflict = true;
+        responder.received(&conflict.to_wire(), from(), now);
+        assert!(!responder.is_unique(&name));
+        assert_eq!(2, responder.poll(now).len());
+    }
+
+    #[test]
+    fn conflicts() {
+        let name = Name::parse("printer.", None).unwrap();
+        let now = Instant::now();
+        let mut responder = LlmnrResponder::new();
+        responder.publish(printer("192.0.2.5"), now);
+        let check = LlmnrMessage::parse(&responder.poll(now)[0].0).unwrap();
+
+        let mut response = LlmnrMessage::new(check.id, true);
+        response.questions = check.questions.clone();
+        response.answers.push(printer("192.0.2.5"));
+        responder.received(&response.to_wire(), from(), now);
+        response.id ^= 1;
+        response.answers.push(printer("192.0.2.6"));
+        responder.received(&response.to_wire(), from(), now);
+        assert!(!responder.is_conflicted(&name));
+
+        response.id ^= 1;
+        responder.received(&response.to_wire(), from(), now);
+        assert!(responder.is_conflicted(&name));
+        responder.received(&query(7, TYPE_A).to_wire(), from(), now);
+        assert!(responder.poll(now).is_empty());
+        assert_eq!(None, responder.deadline());
+    }
+}
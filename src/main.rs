@@ -1,138 +1,1737 @@
 extern crate bueller;
 extern crate mio;
 
-use bueller::protocol::{Header, HeaderMut};
-use bueller::protocol::MessageCursor;
-use bueller::protocol::{Question, QuestionMut};
-use bueller::protocol::Resource;
-use bueller::protocol::encode_dotted_name;
+use bueller::client::{self, TransferError, TransferResult, Upstream};
+use bueller::crypto::Ed25519PrivateKey;
+use bueller::dnscrypt::max_response;
+use bueller::dnssd::{Lookup, MdnsLookup, UnicastLookup, browse, instance_label, instance_name,
+                     parse_txt, register, registered, resolve};
+use bueller::llmnr::{LLMNR_IPV4, LLMNR_IPV6, LLMNR_PORT, LlmnrResponder};
+use bueller::mdns::{MDNS_IPV4, MDNS_IPV6, MDNS_PORT, MdnsSocket, Responder};
+use bueller::odoh::OdohKey;
+use bueller::protocol::{Header, MAX_FRAME, Name, OP_QUERY, Record, udp_payload_size};
+use bueller::protocol::rdata::TYPE_PTR;
+use bueller::server::{ApplyError, Authority, CatalogChange, Catalogs, Config, DOQ_ALPN,
+                      DnscryptRequest, DnscryptServer, DnscryptSession, DohConnection, DoqHandle,
+                      DoqServer, EDNS_PAYLOAD, EXPIRE_INTERVAL_MS, Forwarder, GroupConfig,
+                      HIGH_WATER_MARK, IDLE_TIMEOUT_MS, KEY_ROTATION_MS, MAX_TCP_RESPONSE,
+                      Member, NOTIFY_INTERVAL_MS, NotifyConfig, Notifier, RELAY_TIMEOUT_MS,
+                      Relay, Relayed, Request, Responses, Secondary, StreamConnection,
+                      apply_transfer, catalog_members, notify_is_news, parse_notify};
+use bueller::tls::{ServerConfig, load_private_key};
+use bueller::tsig::{Key, load_keys, now};
+use mio::{TryRead, TryWrite};
+use mio::tcp::{TcpListener, TcpStream};
 use mio::udp::UdpSocket;
-use std::io::Read;
+use std::cmp;
+use std::collections::HashMap;
+use std::env;
 use std::io;
-use std::iter;
-use std::net::SocketAddr;
-use std::net::SocketAddrV4;
-use std::net::Ipv4Addr;
-use std::sync::Arc;
+use std::net::{IpAddr, SocketAddr};
+use std::process;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
 
-const RESPONSE: mio::Token = mio::Token(0);
-const QUERY: mio::Token = mio::Token(1);
+const DEFAULT_CONFIG: &'static str = "bueller.conf";
 
+// Milliseconds service discovery waits for each answer.
+const DISCOVERY_TIMEOUT_MS: u64 = 1000;
 
+// Seconds a zone transfer may stall before it is abandoned.
+const TRANSFER_TIMEOUT: u64 = 30;
 
-struct Recurse {
-    server: UdpSocket,
-    upstream: SocketAddr,
+// UDP sockets and TCP listeners take the first tokens, one pair per listen
+// address, then the TLS, HTTPS, HTTP and DNSCrypt listeners, the QUIC
+// sockets and the DNSCrypt sockets; connections, and the sockets queries
+// are relayed upstream on, are numbered from here up.
+const FIRST_CONNECTION: usize = 1 << 16;
+
+/// DNS over TCP or TLS, DNS over HTTPS, or DNSCrypt over TCP.
+enum Handler {
+    Dns(StreamConnection),
+    Doh(DohConnection),
+    Dnscrypt(StreamConnection),
 }
 
-impl Recurse {
-    fn new(server: UdpSocket) -> Recurse {
-        let upstream = "8.8.8.8:53".parse().unwrap();
-        Recurse {
-            server: server,
-            upstream: upstream,
+impl Handler {
+    fn pending(&self) -> &[u8] {
+        match *self {
+            Handler::Dns(ref handler) | Handler::Dnscrypt(ref handler) => handler.pending(),
+            Handler::Doh(ref handler) => handler.pending(),
+        }
+    }
+
+    fn written(&mut self, n: usize, now: Instant) {
+        match *self {
+            Handler::Dns(ref mut handler) | Handler::Dnscrypt(ref mut handler) => {
+                handler.written(n, now)
+            }
+            Handler::Doh(ref mut handler) => handler.written(n, now),
+        }
+    }
+
+    fn deadline(&self) -> Instant {
+        match *self {
+            Handler::Dns(ref handler) | Handler::Dnscrypt(ref handler) => handler.deadline(),
+            Handler::Doh(ref handler) => handler.deadline(),
+        }
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        match *self {
+            Handler::Dns(ref handler) | Handler::Dnscrypt(ref handler) => handler.is_idle(now),
+            Handler::Doh(ref handler) => handler.is_idle(now),
+        }
+    }
+
+    /// Whether the connection is done once `pending` is written.
+    fn is_closed(&self) -> bool {
+        match *self {
+            Handler::Dns(_) | Handler::Dnscrypt(_) => false,
+            Handler::Doh(ref handler) => handler.is_closed(),
         }
     }
 }
 
+struct Connection {
+    stream: TcpStream,
+    peer: SocketAddr,
+    handler: Handler,
+}
 
-impl mio::Handler for Recurse {
-    type Timeout = ();
-    type Message = Vec<u8>;
+impl Connection {
+    fn new(stream: TcpStream, peer: SocketAddr, handler: Handler) -> Connection {
+        Connection {
+            stream: stream,
+            peer: peer,
+            handler: handler,
+        }
+    }
 
-    fn ready(&mut self,
-             event_loop: &mut mio::EventLoop<Recurse>,
-             token: mio::Token,
-             events: mio::EventSet) {
-        println!("ready...");
-        match token {
-            RESPONSE => {
-                if (events.is_readable()) {
-                    let mut recv_buf = Vec::with_capacity(1024);
-                    match self.server.recv_from(&mut recv_buf) {
-                        Ok(addr) => {
-                            let msg = &recv_buf[..];
-                            println!("Got a response from {:?}", addr);
-                            println!("{:?}", recv_buf);
-                            let header = Header::at(msg);
-                            println!("Header {:?}", &header);
-                            let mut next = header.end_offset();
-                            for qdcount in header.qd() {
-                                for q in 0..qdcount {
-                                    if let Some(query) = Question::from_message(msg, next) {
-                                        next = query.end_offset();
-                                        println!("Question {}: {:?}", q, &query);
-                                    }
+    /// Reading stops while the output is over the high-water mark.
+    fn interest(&self) -> mio::EventSet {
+        let pending = self.handler.pending().len();
+        if pending == 0 {
+            mio::EventSet::readable()
+        } else if pending >= HIGH_WATER_MARK {
+            mio::EventSet::writable()
+        } else {
+            mio::EventSet::readable() | mio::EventSet::writable()
+        }
+    }
+
+    /// Writes what it can of `pending`. Returns false once the connection
+    /// should be closed.
+    fn flush(&mut self) -> bool {
+        if !self.handler.pending().is_empty() {
+            match self.stream.try_write(self.handler.pending()) {
+                Ok(Some(n)) => self.handler.written(n, Instant::now()),
+                Ok(None) => {}
+                Err(_) => return false,
+            }
+        }
+        !self.handler.is_closed() || !self.handler.pending().is_empty()
+    }
+}
+
+/// Where a relayed query came from, for its answer to go back to.
+enum RelayClient {
+    /// A client's address and the UDP socket it asked on.
+    Udp(usize, SocketAddr),
+    /// A DNS over TCP or TLS connection.
+    Stream(mio::Token),
+    /// A DNS over HTTPS request on a connection.
+    Doh(mio::Token, u32),
+    /// A DNS over QUIC stream on the QUIC socket at an index.
+    Doq(usize, DoqHandle),
+    /// A client's address and the DNSCrypt socket it asked on, and what
+    /// seals the answer.
+    Dnscrypt(usize, SocketAddr, DnscryptSession),
+    /// A DNSCrypt over TCP connection, and what seals the answer.
+    DnscryptStream(mio::Token, DnscryptSession),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Timer {
+    /// Time to check a secondary zone with its primaries.
+    Refresh(usize),
+    /// A secondary zone may have gone too long without a refresh.
+    Expire(usize),
+    /// A NOTIFY has not been answered yet.
+    NotifyRetry(u16),
+    /// A TCP connection may have gone quiet.
+    Idle(mio::Token),
+    /// The QUIC connections on a socket have a timer due.
+    Quic(usize),
+    /// Time for new DNSCrypt resolver keys.
+    DnscryptRotate,
+    /// A query relayed upstream may have gone unanswered.
+    Relay(u16),
+    /// Time to sweep expired answers out of the relay's cache.
+    RelayExpire,
+}
+
+/// The outcome of a transfer run on its own thread for a secondary zone.
+struct Transferred {
+    secondary: usize,
+    primary: SocketAddr,
+    result: Result<TransferResult, TransferError>,
+}
+
+/// What threads report back to the event loop.
+enum Message {
+    Transferred(Transferred),
+    /// The answers from an upstream to relayed queries, asked over TCP or
+    /// over a connection kept open to it.
+    Relayed(Forwarder, io::Result<Vec<Vec<u8>>>),
+}
+
+/// Reports the outcome of a transfer thread, and its failure if the thread
+/// panics before it has one, so the secondary goes on to retry.
+struct TransferReport {
+    channel: Option<mio::Sender<Message>>,
+    secondary: usize,
+    primary: SocketAddr,
+}
+
+impl TransferReport {
+    fn send(&mut self, result: Result<TransferResult, TransferError>) {
+        if let Some(channel) = self.channel.take() {
+            let _ = channel.send(Message::Transferred(Transferred {
+                secondary: self.secondary,
+                primary: self.primary,
+                result: result,
+            }));
+        }
+    }
+}
+
+impl Drop for TransferReport {
+    fn drop(&mut self) {
+        let panicked = io::Error::new(io::ErrorKind::Other, "transfer thread panicked");
+        self.send(Err(TransferError::Io(panicked)));
+    }
+}
+
+/// The zone named by an UPDATE request.
+fn updated_zone(message: &[u8]) -> Option<Name> {
+    Request::parse(message).and_then(|request| request.query).map(|query| query.name)
+}
+
+/// The loaded TSIG key of this name. Exits if there is none.
+fn find_key(authority: &Authority, name: Option<&Name>) -> Option<Key> {
+    name.map(|name| {
+        match authority.key(name) {
+            Some(key) => key.clone(),
+            None => {
+                println!("unknown key {}", name);
+                process::exit(1);
+            }
+        }
+    })
+}
+
+/// Whether `query` is a standard query for a name outside the loaded zones,
+/// for the relay to forward.
+fn is_foreign(authority: &Authority, query: &[u8]) -> bool {
+    match Request::parse(query) {
+        Some(Request { opcode, query: Some(ref query), .. }) if opcode == OP_QUERY => {
+            authority.zone_for(&query.name).is_none()
+        }
+        _ => false,
+    }
+}
+
+/// Answers a query or UPDATE the way UDP does, in at most `max_size`
+/// octets. Zones changed by UPDATE requests are added to `updated`.
+fn answer(authority: &mut Authority,
+          query: &[u8],
+          from: &IpAddr,
+          max_size: usize,
+          updated: &mut Vec<Name>)
+    -> Option<Vec<u8>> {
+    match authority.update(query, from, max_size) {
+        Some((response, changed)) => {
+            if changed {
+                updated.extend(updated_zone(query));
+            }
+            Some(response)
+        }
+        None => authority.respond(query, max_size),
+    }
+}
+
+/// The certificate and key for TLS. Exits if they cannot be loaded.
+fn load_tls(config: &Config) -> ServerConfig {
+    let certificate = config.tls_certificate.as_ref().unwrap();
+    let key = config.tls_key.as_ref().unwrap();
+    match ServerConfig::load(certificate, key) {
+        Ok(tls_config) => tls_config,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// Publishes records over multicast DNS to `group`, from a thread of its own.
+fn publish_mdns(records: &[Record], group: IpAddr) -> io::Result<()> {
+    let socket = try!(MdnsSocket::join(group, MDNS_PORT));
+    println!("Publishing over multicast DNS to {}", socket.group());
+    let records = records.to_vec();
+    thread::spawn(move || {
+        let mut responder = Responder::new();
+        for record in records {
+            let unique = record.rtype() != TYPE_PTR;
+            responder.publish(record, unique, Instant::now());
+        }
+        if let Err(e) = responder.run(&socket, None) {
+            println!("Multicast DNS stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+// Asks `upstream` the queries sent down the channel returned, from a thread
+// of its own, those that wait while it is busy all at once, and reports
+// their answers back to the event loop.
+fn start_upstream(forwarder: Forwarder,
+                  mut upstream: Box<Upstream + Send>,
+                  channel: mio::Sender<Message>)
+    -> mpsc::Sender<Vec<u8>> {
+    let (sender, queries) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(query) = queries.recv() {
+            let mut batch = vec![query];
+            batch.extend(queries.try_iter());
+            let result = upstream.query_all(&batch);
+            if channel.send(Message::Relayed(forwarder.clone(), result)).is_err() {
+                return;
+            }
+        }
+    });
+    sender
+}
+
+// Answers for records over LLMNR on `group`, from a thread of its own.
+fn publish_llmnr(records: &[Record], group: IpAddr) -> io::Result<()> {
+    let socket = try!(MdnsSocket::join(group, LLMNR_PORT));
+    println!("Answering over LLMNR on {}", socket.group());
+    let records = records.to_vec();
+    thread::spawn(move || {
+        let mut responder = LlmnrResponder::new();
+        for record in records {
+            responder.publish(record, Instant::now());
+        }
+        if let Err(e) = responder.run(&socket, None) {
+            println!("LLMNR stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000
+}
+
+struct Server {
+    authority: Authority,
+    udp: Vec<UdpSocket>,
+    tcp: Vec<TcpListener>,
+    tls: Vec<TcpListener>,
+    https: Vec<TcpListener>,
+    http: Vec<TcpListener>,
+    // Each QUIC socket with the DNS over QUIC connections on it, and their
+    // next timer.
+    quic: Vec<(UdpSocket, DoqServer)>,
+    quic_timers: Vec<Option<mio::Timeout>>,
+    // DNSCrypt over UDP and TCP, answered for the one provider.
+    dnscrypt: Vec<UdpSocket>,
+    dnscrypt_tcp: Vec<TcpListener>,
+    dnscrypt_server: Option<DnscryptServer>,
+    tls_config: Option<Arc<ServerConfig>>,
+    https_config: Option<Arc<ServerConfig>>,
+    // Makes the HTTPS and HTTP connections Oblivious DoH targets too.
+    odoh_key: Option<Arc<OdohKey>>,
+    connections: HashMap<mio::Token, Connection>,
+    next_connection: usize,
+    max_connections: usize,
+    // Secondaries from the configuration file come first, then members of
+    // catalogs. A member dropped from its catalog keeps its place with no
+    // primaries, so the indices in timers stay good.
+    secondaries: Vec<Secondary>,
+    configured: usize,
+    // The TSIG key each secondary signs its transfer requests with.
+    secondary_keys: Vec<Option<Key>>,
+    catalogs: Catalogs,
+    catalog_groups: Vec<(GroupConfig, Option<Key>)>,
+    // The pending refresh and expire timer of each secondary.
+    refresh_timers: Vec<Option<mio::Timeout>>,
+    expire_timers: Vec<Option<mio::Timeout>>,
+    notify: Vec<NotifyConfig>,
+    notifier: Notifier,
+    next_id: u16,
+    // Forwards queries outside the loaded zones, each from a socket of its
+    // own on a port the system picks at random (RFC 5452 Section 9.2),
+    // kept with the ID of the query until it is answered.
+    relay: Option<Relay<RelayClient>>,
+    upstream: HashMap<mio::Token, (u16, UdpSocket)>,
+    // The channels to the threads asking the encrypted upstreams.
+    upstream_threads: Vec<(Forwarder, mpsc::Sender<Vec<u8>>)>,
+}
+
+impl Server {
+    fn udp_ready(&mut self, event_loop: &mut mio::EventLoop<Server>, index: usize) {
+        let mut refreshes = Vec::new();
+        let mut updated = Vec::new();
+        let mut foreign = Vec::new();
+        {
+            let socket = &self.udp[index];
+            loop {
+                let mut query = Vec::with_capacity(MAX_TCP_RESPONSE);
+                let from = match socket.recv_from(&mut query) {
+                    Ok(Some(from)) => from,
+                    Ok(None) => break,
+                    Err(e) => {
+                        println!("recv_from() failed: {}", e);
+                        break;
+                    }
+                };
+                if Header::at(&query[..]).is_response() {
+                    self.notifier.acknowledge(&query, &from);
+                    continue;
+                }
+                if let Some((zone, serial)) = parse_notify(&query) {
+                    let current = self.authority
+                                      .zones()
+                                      .iter()
+                                      .find(|z| *z.origin() == zone)
+                                      .and_then(|z| z.serial());
+                    let secondary = self.secondaries.iter().position(|s| {
+                        *s.origin() == zone && s.primaries().iter().any(|p| p.ip() == from.ip())
+                    });
+                    if let Some(secondary) = secondary {
+                        if notify_is_news(serial, current) {
+                            refreshes.push(secondary);
+                        }
+                    }
+                }
+                if self.relay.is_some() && is_foreign(&self.authority, &query) {
+                    foreign.push((query, from));
+                    continue;
+                }
+                let max_len = cmp::min(udp_payload_size(&query), EDNS_PAYLOAD);
+                let response = answer(&mut self.authority,
+                                      &query,
+                                      &from.ip(),
+                                      max_len,
+                                      &mut updated);
+                if let Some(response) = response {
+                    if let Err(e) = socket.send_to(&mut io::Cursor::new(response), &from) {
+                        println!("send_to({}) failed: {}", from, e);
+                    }
+                }
+            }
+        }
+        for (query, from) in foreign {
+            let max_len = cmp::min(udp_payload_size(&query), EDNS_PAYLOAD);
+            self.relay_query(event_loop, &query, RelayClient::Udp(index, from), max_len);
+        }
+        for secondary in refreshes {
+            self.start_refresh(event_loop, secondary);
+        }
+        for zone in updated {
+            self.send_notify(event_loop, &zone);
+        }
+    }
+
+    /// Answers `query` from the relay's cache in at most `max_len` octets,
+    /// or forwards it upstream for the answer to go back to `client`.
+    fn relay_query(&mut self,
+                   event_loop: &mut mio::EventLoop<Server>,
+                   query: &[u8],
+                   client: RelayClient,
+                   max_len: usize) {
+        let relayed = match self.relay {
+            Some(ref mut relay) => relay.query(query, client, max_len, Instant::now()),
+            None => None,
+        };
+        self.relayed(event_loop, relayed);
+    }
+
+    /// Does what the relay calls for: sends an answer to its client, or a
+    /// query upstream, setting the timer for its answer. Plain DNS goes over
+    /// UDP, or over TCP from a thread of its own, and encrypted upstreams
+    /// are asked by their threads; those report back through the event
+    /// loop's channel.
+    fn relayed(&mut self,
+               event_loop: &mut mio::EventLoop<Server>,
+               relayed: Option<Relayed<RelayClient>>) {
+        match relayed {
+            Some(Relayed::Answer(client, response)) => {
+                self.send_relayed(event_loop, client, response)
+            }
+            Some(Relayed::Forward(id, Forwarder::Udp(upstream), message)) => {
+                // Left unsent, the query fails when its time is up.
+                let _ = event_loop.timeout_ms(Timer::Relay(id), RELAY_TIMEOUT_MS);
+                let any: IpAddr = if upstream.is_ipv4() {
+                    "0.0.0.0".parse().unwrap()
+                } else {
+                    "::".parse().unwrap()
+                };
+                let socket = match UdpSocket::bound(&SocketAddr::new(any, 0)) {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        println!("bind() failed: {}", e);
+                        return;
+                    }
+                };
+                if let Err(e) = socket.send_to(&mut io::Cursor::new(message), &upstream) {
+                    println!("send_to({}) failed: {}", upstream, e);
+                    return;
+                }
+                let token = mio::Token(self.next_connection);
+                self.next_connection += 1;
+                if let Err(e) = event_loop.register(&socket,
+                                                    token,
+                                                    mio::EventSet::readable(),
+                                                    mio::PollOpt::level()) {
+                    println!("register() failed: {}", e);
+                    return;
+                }
+                self.upstream.insert(token, (id, socket));
+            }
+            Some(Relayed::Forward(id, upstream, message)) => {
+                let _ = event_loop.timeout_ms(Timer::Relay(id), RELAY_TIMEOUT_MS);
+                if let Some(&(_, ref queries)) = self.upstream_threads
+                                                     .iter()
+                                                     .find(|&&(ref f, _)| *f == upstream) {
+                    let _ = queries.send(message);
+                }
+            }
+            Some(Relayed::Retry(_, upstream, message)) => {
+                // The timer already set finds the query's new deadline.
+                let channel = event_loop.channel();
+                thread::spawn(move || {
+                    let timeout = Duration::from_millis(RELAY_TIMEOUT_MS);
+                    let result = client::query_tcp(&upstream, &[message], timeout);
+                    let _ = channel.send(Message::Relayed(Forwarder::Udp(upstream), result));
+                });
+            }
+            None => {}
+        }
+    }
+
+    /// Reads the answers waiting on an upstream socket, and passes the one
+    /// to the query it was opened for back to the client that asked, once
+    /// the relay has matched its server and question too.
+    fn upstream_ready(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token) {
+        loop {
+            let mut message = Vec::with_capacity(MAX_TCP_RESPONSE);
+            let (id, from) = match self.upstream.get(&token) {
+                Some(&(id, ref socket)) => {
+                    match socket.recv_from(&mut message) {
+                        Ok(Some(from)) => (id, Forwarder::Udp(from)),
+                        Ok(None) => return,
+                        Err(e) => {
+                            println!("recv_from() failed: {}", e);
+                            return;
+                        }
+                    }
+                }
+                None => return,
+            };
+            if Header::at(&message).id() != Some(id) {
+                continue;
+            }
+            let relayed = match self.relay {
+                Some(ref mut relay) => relay.response(&message, &from, Instant::now()),
+                None => None,
+            };
+            if relayed.is_some() {
+                // Answered, or to be asked again over TCP.
+                self.close_upstream(event_loop, id);
+            }
+            self.relayed(event_loop, relayed);
+        }
+    }
+
+    /// Closes the socket the query under `id` went upstream on, if it is
+    /// still open.
+    fn close_upstream(&mut self, event_loop: &mut mio::EventLoop<Server>, id: u16) {
+        let token = self.upstream
+                        .iter()
+                        .find(|&(_, &(pending, _))| pending == id)
+                        .map(|(&token, _)| token);
+        if let Some((_, socket)) = token.and_then(|token| self.upstream.remove(&token)) {
+            let _ = event_loop.deregister(&socket);
+        }
+    }
+
+    /// Sends a relayed answer back the way its query came.
+    fn send_relayed(&mut self,
+                    event_loop: &mut mio::EventLoop<Server>,
+                    client: RelayClient,
+                    response: Vec<u8>) {
+        match client {
+            RelayClient::Udp(index, to) => {
+                if let Err(e) = self.udp[index].send_to(&mut io::Cursor::new(response), &to) {
+                    println!("send_to({}) failed: {}", to, e);
+                }
+            }
+            // The connection may have closed meanwhile.
+            RelayClient::Stream(token) => {
+                if let Some(&mut Connection { handler: Handler::Dns(ref mut handler), .. }) =
+                       self.connections.get_mut(&token) {
+                    handler.respond(&response);
+                }
+                self.connection_flush(event_loop, token);
+            }
+            RelayClient::Doh(token, handle) => {
+                if let Some(&mut Connection { handler: Handler::Doh(ref mut handler), .. }) =
+                       self.connections.get_mut(&token) {
+                    handler.respond(handle, &response);
+                }
+                self.connection_flush(event_loop, token);
+            }
+            RelayClient::Doq(index, handle) => {
+                self.quic[index].1.respond(handle, &response);
+                self.quic_flush(event_loop, index);
+            }
+            RelayClient::Dnscrypt(index, to, session) => {
+                let sealed = session.seal(&response);
+                if let Err(e) = self.dnscrypt[index].send_to(&mut io::Cursor::new(sealed), &to) {
+                    println!("send_to({}) failed: {}", to, e);
+                }
+            }
+            RelayClient::DnscryptStream(token, session) => {
+                if let Some(&mut Connection { handler: Handler::Dnscrypt(ref mut handler), .. }) =
+                       self.connections.get_mut(&token) {
+                    handler.respond(&session.seal(&response));
+                }
+                self.connection_flush(event_loop, token);
+            }
+        }
+    }
+
+    // Transfer query IDs go in twos, as a refresh falling back to AXFR uses
+    // the one after its IXFR's.
+    fn next_id(&mut self) -> u16 {
+        self.next_id = self.next_id.wrapping_add(2);
+        self.next_id
+    }
+
+    fn send_udp(&self, message: Vec<u8>, to: &SocketAddr) {
+        if let Err(e) = self.udp[0].send_to(&mut io::Cursor::new(message), to) {
+            println!("send_to({}) failed: {}", to, e);
+        }
+    }
+
+    /// Reads and answers the datagrams waiting on the QUIC socket at
+    /// `index`.
+    fn quic_ready(&mut self, event_loop: &mut mio::EventLoop<Server>, index: usize) {
+        let mut updated = Vec::new();
+        let mut foreign = Vec::new();
+        {
+            let relaying = self.relay.is_some();
+            let authority = &mut self.authority;
+            let (ref socket, ref mut server) = self.quic[index];
+            loop {
+                let mut datagram = Vec::with_capacity(MAX_TCP_RESPONSE);
+                let from = match socket.recv_from(&mut datagram) {
+                    Ok(Some(from)) => from,
+                    Ok(None) => break,
+                    Err(e) => {
+                        println!("recv_from() failed: {}", e);
+                        break;
+                    }
+                };
+                for (handle, query) in server.received(&datagram, from, Instant::now()) {
+                    if relaying && is_foreign(authority, &query) {
+                        foreign.push((query, RelayClient::Doq(index, handle)));
+                        continue;
+                    }
+                    match answer(authority, &query, &from.ip(), MAX_TCP_RESPONSE, &mut updated) {
+                        Some(response) => {
+                            server.respond(handle, &response);
+                        }
+                        None => server.fail(handle),
+                    }
+                }
+            }
+        }
+        self.quic_flush(event_loop, index);
+        for (query, client) in foreign {
+            self.relay_query(event_loop, &query, client, MAX_TCP_RESPONSE);
+        }
+        for zone in updated {
+            self.send_notify(event_loop, &zone);
+        }
+    }
+
+    /// Sends what the QUIC connections at `index` have to send, and sets
+    /// the timer for their next timeout.
+    fn quic_flush(&mut self, event_loop: &mut mio::EventLoop<Server>, index: usize) {
+        let (ref socket, ref mut server) = self.quic[index];
+        for (datagram, to) in server.datagrams(Instant::now()) {
+            if let Err(e) = socket.send_to(&mut io::Cursor::new(datagram), &to) {
+                println!("send_to({}) failed: {}", to, e);
+            }
+        }
+        if let Some(timeout) = self.quic_timers[index].take() {
+            event_loop.clear_timeout(timeout);
+        }
+        if let Some(deadline) = server.deadline() {
+            let now = Instant::now();
+            let delay = if deadline > now { millis(deadline - now) + 1 } else { 1 };
+            self.quic_timers[index] = event_loop.timeout_ms(Timer::Quic(index), delay).ok();
+        }
+    }
+
+    /// Reads and answers the packets waiting on the DNSCrypt socket at
+    /// `index`.
+    fn dnscrypt_ready(&mut self, event_loop: &mut mio::EventLoop<Server>, index: usize) {
+        let mut updated = Vec::new();
+        let mut foreign = Vec::new();
+        {
+            let relaying = self.relay.is_some();
+            let server = match self.dnscrypt_server {
+                Some(ref server) => server,
+                None => return,
+            };
+            let socket = &self.dnscrypt[index];
+            loop {
+                let mut packet = Vec::with_capacity(MAX_TCP_RESPONSE);
+                let from = match socket.recv_from(&mut packet) {
+                    Ok(Some(from)) => from,
+                    Ok(None) => break,
+                    Err(e) => {
+                        println!("recv_from() failed: {}", e);
+                        break;
+                    }
+                };
+                let response = match server.received_datagram(&packet) {
+                    Some(DnscryptRequest::Certificates(response)) => response,
+                    Some(DnscryptRequest::Query(query, session)) => {
+                        let max_size = max_response(packet.len());
+                        if relaying && is_foreign(&self.authority, &query) {
+                            let client = RelayClient::Dnscrypt(index, from, session);
+                            foreign.push((query, client, max_size));
+                            continue;
+                        }
+                        match answer(&mut self.authority,
+                                     &query,
+                                     &from.ip(),
+                                     max_size,
+                                     &mut updated) {
+                            Some(response) => session.seal(&response),
+                            None => continue,
+                        }
+                    }
+                    None => continue,
+                };
+                if let Err(e) = socket.send_to(&mut io::Cursor::new(response), &from) {
+                    println!("send_to({}) failed: {}", from, e);
+                }
+            }
+        }
+        for (query, client, max_len) in foreign {
+            self.relay_query(event_loop, &query, client, max_len);
+        }
+        for zone in updated {
+            self.send_notify(event_loop, &zone);
+        }
+    }
+
+    /// Tells the configured servers that `zone` has changed.
+    fn send_notify(&mut self, event_loop: &mut mio::EventLoop<Server>, zone: &Name) {
+        let targets: Vec<SocketAddr> = self.notify
+                                           .iter()
+                                           .filter(|n| n.origin == *zone)
+                                           .flat_map(|n| n.targets.iter().cloned())
+                                           .collect();
+        let soa = self.authority
+                      .zones()
+                      .iter()
+                      .find(|z| z.origin() == zone)
+                      .and_then(|z| z.soa_record());
+        for (id, target, message) in self.notifier.notify(zone, soa.as_ref(), &targets) {
+            self.send_udp(message, &target);
+            let _ = event_loop.timeout_ms(Timer::NotifyRetry(id), NOTIFY_INTERVAL_MS);
+        }
+    }
+
+    /// Starts checking a secondary zone with its primaries, unless a check
+    /// is already under way.
+    fn start_refresh(&mut self, event_loop: &mut mio::EventLoop<Server>, secondary: usize) {
+        if let Some(timeout) = self.refresh_timers[secondary].take() {
+            event_loop.clear_timeout(timeout);
+        }
+        if let Some(primary) = self.secondaries[secondary].start_refresh() {
+            self.spawn_transfer(event_loop, secondary, primary, true);
+        }
+    }
+
+    /// Runs a transfer on its own thread, reporting back through the event
+    /// loop's channel. Asks for IXFR when `incremental` and the zone has an
+    /// SOA to start from.
+    fn spawn_transfer(&mut self,
+                      event_loop: &mut mio::EventLoop<Server>,
+                      secondary: usize,
+                      primary: SocketAddr,
+                      incremental: bool) {
+        let origin = self.secondaries[secondary].origin().clone();
+        let current = if incremental {
+            self.authority
+                .zones()
+                .iter()
+                .find(|z| *z.origin() == origin)
+                .and_then(|z| z.soa_record())
+        } else {
+            None
+        };
+        let key = self.secondary_keys[secondary].clone();
+        let id = self.next_id();
+        let mut report = TransferReport {
+            channel: Some(event_loop.channel()),
+            secondary: secondary,
+            primary: primary,
+        };
+        thread::spawn(move || {
+            let timeout = Duration::from_secs(TRANSFER_TIMEOUT);
+            let result = client::refresh(&primary,
+                                         &origin,
+                                         current.as_ref(),
+                                         key.as_ref(),
+                                         id,
+                                         timeout);
+            report.send(result);
+        });
+    }
+
+    fn transferred(&mut self, event_loop: &mut mio::EventLoop<Server>, done: Transferred) {
+        let index = done.secondary;
+        let origin = self.secondaries[index].origin().clone();
+        let mut changed = false;
+        let success = match done.result {
+            Ok(result) => {
+                // The zone may have left its catalog meanwhile.
+                let zone = match self.authority.zone_mut(&origin) {
+                    Some(zone) => zone,
+                    None => return,
+                };
+                match apply_transfer(zone, result) {
+                    Ok(applied) => {
+                        changed = applied;
+                        true
+                    }
+                    Err(ApplyError::Misfit) => {
+                        println!("{}: {}, trying AXFR from {}",
+                                 origin,
+                                 ApplyError::Misfit,
+                                 done.primary);
+                        self.spawn_transfer(event_loop, index, done.primary, false);
+                        return;
+                    }
+                    Err(e) => {
+                        println!("{}: transfer from {} refused: {}", origin, done.primary, e);
+                        false
+                    }
+                }
+            }
+            Err(e) => {
+                println!("{}: transfer from {} failed: {}", origin, done.primary, e);
+                false
+            }
+        };
+        let soa = self.authority
+                      .zones()
+                      .iter()
+                      .find(|z| *z.origin() == origin)
+                      .and_then(|z| z.soa());
+        let delay = self.secondaries[index].refreshed(Instant::now(), success, soa.as_ref());
+        self.refresh_timers[index] = event_loop.timeout_ms(Timer::Refresh(index), millis(delay))
+                                               .ok();
+        if success {
+            self.authority.set_expired(&origin, false);
+            if let Some(timeout) = self.expire_timers[index].take() {
+                event_loop.clear_timeout(timeout);
+            }
+            if let Some(expire) = self.secondaries[index].expire_after() {
+                self.expire_timers[index] = event_loop.timeout_ms(Timer::Expire(index),
+                                                                  millis(expire))
+                                                      .ok();
+            }
+        }
+        if changed {
+            if let Some(serial) = soa.map(|soa| soa.serial) {
+                println!("Transferred {} serial {}", origin, serial);
+            }
+            self.send_notify(event_loop, &origin);
+            if self.catalogs.is_catalog(&origin) {
+                self.catalog_changed(event_loop, &origin);
+            }
+        }
+    }
+
+    fn secondary_index(&self, origin: &Name) -> Option<usize> {
+        self.secondaries.iter().position(|s| s.origin() == origin)
+    }
+
+    fn clear_timers(&mut self, event_loop: &mut mio::EventLoop<Server>, index: usize) {
+        if let Some(timeout) = self.refresh_timers[index].take() {
+            event_loop.clear_timeout(timeout);
+        }
+        if let Some(timeout) = self.expire_timers[index].take() {
+            event_loop.clear_timeout(timeout);
+        }
+    }
+
+    /// Brings the member zones served in line with a catalog zone that has
+    /// just changed.
+    fn catalog_changed(&mut self, event_loop: &mut mio::EventLoop<Server>, catalog: &Name) {
+        let members = match self.authority.zones().iter().find(|z| z.origin() == catalog) {
+            Some(zone) => catalog_members(zone),
+            None => return,
+        };
+        let members = match members {
+            Ok(members) => members,
+            Err(e) => {
+                println!("{}: {}", catalog, e);
+                return;
+            }
+        };
+        for change in self.catalogs.update(catalog, members) {
+            match change {
+                CatalogChange::Added(member) => {
+                    println!("{} adds {}", catalog, member.zone);
+                    self.add_member(event_loop, &member, true);
+                }
+                CatalogChange::Reset(member) => {
+                    println!("{} resets {}", member.catalog, member.zone);
+                    self.add_member(event_loop, &member, true);
+                }
+                CatalogChange::Changed(member) => self.add_member(event_loop, &member, false),
+                CatalogChange::Refused(member) => {
+                    println!("{}: already configured, not taken from {}",
+                             member.zone,
+                             member.catalog);
+                }
+                CatalogChange::Removed(zone) => {
+                    println!("{} removes {}", catalog, zone);
+                    self.remove_member(event_loop, &zone);
+                }
+            }
+        }
+    }
+
+    /// Where a member zone is transferred from: its group's primaries, or
+    /// else its catalog's.
+    fn member_source(&self, member: &Member) -> (Vec<SocketAddr>, Option<Key>) {
+        for &(ref group, ref key) in &self.catalog_groups {
+            if member.groups.contains(&group.group) {
+                return (group.primaries.clone(), key.clone());
+            }
+        }
+        match self.secondary_index(&member.catalog) {
+            Some(index) => {
+                (self.secondaries[index].primaries().to_vec(), self.secondary_keys[index].clone())
+            }
+            None => (Vec::new(), None),
+        }
+    }
+
+    /// Whether a zone is configured here, as a primary or a secondary, and
+    /// so no catalog's to add or remove.
+    fn is_local(&self, zone: &Name) -> bool {
+        let configured = self.secondary_index(zone).map_or(false, |index| index < self.configured);
+        let primary = self.authority.zones().iter().any(|z| z.origin() == zone) &&
+                      !self.authority.is_secondary(zone);
+        configured || primary
+    }
+
+    /// Serves a member zone of a catalog as a secondary, starting over from
+    /// an empty copy when `fresh`.
+    fn add_member(&mut self,
+                  event_loop: &mut mio::EventLoop<Server>,
+                  member: &Member,
+                  fresh: bool) {
+        if self.is_local(&member.zone) {
+            println!("{}: already configured, not taken from {}", member.zone, member.catalog);
+            return;
+        }
+        let index = match self.secondary_index(&member.zone) {
+            Some(index) => index,
+            None => {
+                self.secondaries.push(Secondary::new(member.zone.clone(), Vec::new()));
+                self.secondary_keys.push(None);
+                self.refresh_timers.push(None);
+                self.expire_timers.push(None);
+                self.secondaries.len() - 1
+            }
+        };
+        let (primaries, key) = self.member_source(member);
+        self.secondary_keys[index] = key;
+        if fresh || !self.authority.is_secondary(&member.zone) {
+            self.clear_timers(event_loop, index);
+            self.secondaries[index] = Secondary::new(member.zone.clone(), primaries);
+            self.authority.add_secondary(member.zone.clone());
+            self.start_refresh(event_loop, index);
+        } else {
+            self.secondaries[index].set_primaries(primaries);
+        }
+    }
+
+    fn remove_member(&mut self, event_loop: &mut mio::EventLoop<Server>, zone: &Name) {
+        if self.is_local(zone) {
+            return;
+        }
+        if let Some(index) = self.secondary_index(zone) {
+            self.clear_timers(event_loop, index);
+            self.secondaries[index] = Secondary::new(zone.clone(), Vec::new());
+            self.secondary_keys[index] = None;
+        }
+        self.authority.remove_zone(zone);
+    }
+
+    /// The TCP listeners, then the TLS, HTTPS, HTTP and DNSCrypt ones.
+    fn listeners(&self) -> Vec<&TcpListener> {
+        self.tcp
+            .iter()
+            .chain(&self.tls)
+            .chain(&self.https)
+            .chain(&self.http)
+            .chain(&self.dnscrypt_tcp)
+            .collect()
+    }
+
+    /// Accepts connections on the listener at `index` in `listeners`.
+    fn accept(&mut self, event_loop: &mut mio::EventLoop<Server>, index: usize) {
+        // Where the TLS, HTTPS, HTTP and DNSCrypt listeners start.
+        let tls = self.tcp.len();
+        let https = tls + self.tls.len();
+        let http = https + self.https.len();
+        let dnscrypt = http + self.http.len();
+        loop {
+            let accepted = self.listeners()[index].accept();
+            let (stream, peer) = match accepted {
+                Ok(Some(accepted)) => accepted,
+                Ok(None) => return,
+                Err(e) => {
+                    println!("accept() failed: {}", e);
+                    return;
+                }
+            };
+            // Past the cap, the stream is dropped and so closed.
+            if self.connections.len() >= self.max_connections && !self.close_idlest(event_loop) {
+                continue;
+            }
+            let token = mio::Token(self.next_connection);
+            self.next_connection += 1;
+            if let Err(e) = event_loop.register(&stream,
+                                                token,
+                                                mio::EventSet::readable(),
+                                                mio::PollOpt::level()) {
+                println!("register() failed: {}", e);
+                continue;
+            }
+            let start = Instant::now();
+            let mut handler = match (&self.tls_config, &self.https_config) {
+                (&Some(ref config), _) if index >= tls && index < https => {
+                    Handler::Dns(StreamConnection::with_tls(config.clone(), start))
+                }
+                (_, &Some(ref config)) if index >= https && index < http => {
+                    Handler::Doh(DohConnection::with_tls(config.clone(), start))
+                }
+                _ if index >= dnscrypt => Handler::Dnscrypt(StreamConnection::new(start)),
+                _ if index >= http => Handler::Doh(DohConnection::new(start)),
+                _ => Handler::Dns(StreamConnection::new(start)),
+            };
+            if let (&mut Handler::Doh(ref mut handler), &Some(ref key)) = (&mut handler,
+                                                                          &self.odoh_key) {
+                handler.set_odoh_key(key.clone());
+            }
+            self.connections.insert(token, Connection::new(stream, peer, handler));
+            let _ = event_loop.timeout_ms(Timer::Idle(token), IDLE_TIMEOUT_MS);
+        }
+    }
+
+    /// Closes the connection that has gone longest without reading or
+    /// writing, of those with nothing left to write, to make room for
+    /// another. Returns false if there is none.
+    fn close_idlest(&mut self, event_loop: &mut mio::EventLoop<Server>) -> bool {
+        let idlest = self.connections
+                         .iter()
+                         .filter(|&(_, c)| c.handler.pending().is_empty())
+                         .min_by_key(|&(_, c)| c.handler.deadline())
+                         .map(|(&token, _)| token);
+        match idlest.and_then(|token| self.connections.remove(&token)) {
+            Some(connection) => {
+                let _ = event_loop.deregister(&connection.stream);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reads and answers whatever the connection has sent, and writes out
+    /// what it can. Returns false once the connection should be closed.
+    /// Zones changed by UPDATE requests are added to `updated`, and queries
+    /// for the relay to `foreign`.
+    fn connection_ready(&mut self,
+                        token: mio::Token,
+                        events: mio::EventSet,
+                        updated: &mut Vec<Name>,
+                        foreign: &mut Vec<(Vec<u8>, RelayClient, usize)>)
+        -> bool {
+        let relaying = self.relay.is_some();
+        let authority = &mut self.authority;
+        let dnscrypt = &self.dnscrypt_server;
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return false,
+        };
+        if events.is_readable() {
+            let mut buffer = [0u8; 4096];
+            let n = match connection.stream.try_read(&mut buffer) {
+                Ok(Some(0)) => return false,
+                Ok(Some(n)) => n,
+                Ok(None) => 0,
+                Err(_) => return false,
+            };
+            let peer = connection.peer.ip();
+            match connection.handler {
+                _ if n == 0 => {}
+                Handler::Dns(ref mut handler) => {
+                    let queries = match handler.received(&buffer[..n], Instant::now()) {
+                        Ok(queries) => queries,
+                        Err(e) => {
+                            println!("{}: {}", connection.peer, e);
+                            let _ = connection.stream.try_write(handler.pending());
+                            return false;
+                        }
+                    };
+                    for query in queries {
+                        if relaying && is_foreign(authority, &query) {
+                            foreign.push((query, RelayClient::Stream(token), MAX_TCP_RESPONSE));
+                            continue;
+                        }
+                        let responses = match authority.update(&query, &peer, MAX_TCP_RESPONSE) {
+                            Some((response, changed)) => {
+                                if changed {
+                                    updated.extend(updated_zone(&query));
                                 }
+                                Responses::single(response)
                             }
-                            for ancount in header.an() {
-                                for a in 0..ancount {
-                                    if let Some(answer) = Resource::from_message(msg, next) {
-                                        next = answer.end_offset();
-                                        println!("Answer {}: {:?}", a, &answer);
-                                    }
+                            None => {
+                                match authority.respond_stream(&query, &peer) {
+                                    Some(responses) => responses,
+                                    None => return false,
                                 }
                             }
-                            event_loop.shutdown();
-                        }
+                        };
+                        handler.respond_all(responses);
+                    }
+                }
+                Handler::Doh(ref mut handler) => {
+                    let queries = match handler.received(&buffer[..n], Instant::now()) {
+                        Ok(queries) => queries,
                         Err(e) => {
-                            println!("recv_from() failed: {}", e);
-                            event_loop.shutdown();
+                            println!("{}: {}", connection.peer, e);
+                            let _ = connection.stream.try_write(handler.pending());
+                            return false;
+                        }
+                    };
+                    for (handle, query) in queries {
+                        if relaying && is_foreign(authority, &query) {
+                            let client = RelayClient::Doh(token, handle);
+                            foreign.push((query, client, MAX_TCP_RESPONSE));
+                            continue;
+                        }
+                        match answer(authority, &query, &peer, MAX_TCP_RESPONSE, updated) {
+                            Some(response) => handler.respond(handle, &response),
+                            None => handler.refuse(handle, 400),
                         }
                     }
                 }
+                Handler::Dnscrypt(ref mut handler) => {
+                    let server = match *dnscrypt {
+                        Some(ref server) => server,
+                        None => return false,
+                    };
+                    let packets = match handler.received(&buffer[..n], Instant::now()) {
+                        Ok(packets) => packets,
+                        Err(_) => return false,
+                    };
+                    for packet in packets {
+                        let response = match server.received(&packet) {
+                            Some(DnscryptRequest::Certificates(response)) => response,
+                            Some(DnscryptRequest::Query(query, session)) => {
+                                let max_size = max_response(MAX_FRAME);
+                                if relaying && is_foreign(authority, &query) {
+                                    let client = RelayClient::DnscryptStream(token, session);
+                                    foreign.push((query, client, max_size));
+                                    continue;
+                                }
+                                match answer(authority, &query, &peer, max_size, updated) {
+                                    Some(response) => session.seal(&response),
+                                    None => continue,
+                                }
+                            }
+                            None => return false,
+                        };
+                        handler.respond(&response);
+                    }
+                }
             }
-            t => {
-                panic!("Invalid token: {:?}", t);
+        }
+        connection.flush() && !events.is_hup() && !events.is_error()
+    }
+
+    /// Writes out what the connection has waiting, then waits for what it
+    /// needs next, or closes it.
+    fn connection_flush(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token) {
+        let open = match self.connections.get_mut(&token) {
+            Some(connection) => connection.flush(),
+            None => return,
+        };
+        self.rearm(event_loop, token, open);
+    }
+
+    /// Waits for what the connection needs next if it is still `open`, or
+    /// else closes it.
+    fn rearm(&mut self, event_loop: &mut mio::EventLoop<Server>, token: mio::Token, open: bool) {
+        if open {
+            let connection = &self.connections[&token];
+            if let Err(e) = event_loop.reregister(&connection.stream,
+                                                  token,
+                                                  connection.interest(),
+                                                  mio::PollOpt::level()) {
+                println!("reregister() failed: {}", e);
             }
+        } else if let Some(connection) = self.connections.remove(&token) {
+            let _ = event_loop.deregister(&connection.stream);
+        }
+    }
+}
+
+impl mio::Handler for Server {
+    type Timeout = Timer;
+    type Message = Message;
+
+    fn ready(&mut self,
+             event_loop: &mut mio::EventLoop<Server>,
+             token: mio::Token,
+             events: mio::EventSet) {
+        let mio::Token(index) = token;
+        let first_dnscrypt = self.udp.len() + self.listeners().len() + self.quic.len();
+        if index < self.udp.len() {
+            self.udp_ready(event_loop, index);
+        } else if index < self.udp.len() + self.listeners().len() {
+            let listener = index - self.udp.len();
+            self.accept(event_loop, listener);
+        } else if index < first_dnscrypt {
+            let socket = index - self.udp.len() - self.listeners().len();
+            self.quic_ready(event_loop, socket);
+        } else if index < FIRST_CONNECTION {
+            self.dnscrypt_ready(event_loop, index - first_dnscrypt);
+        } else if self.upstream.contains_key(&token) {
+            self.upstream_ready(event_loop, token);
+        } else {
+            let mut updated = Vec::new();
+            let mut foreign = Vec::new();
+            let open = self.connection_ready(token, events, &mut updated, &mut foreign);
+            self.rearm(event_loop, token, open);
+            for (query, client, max_len) in foreign {
+                self.relay_query(event_loop, &query, client, max_len);
+            }
+            for zone in updated {
+                self.send_notify(event_loop, &zone);
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Server>, timer: Timer) {
+        match timer {
+            Timer::Refresh(secondary) => {
+                self.refresh_timers[secondary] = None;
+                self.start_refresh(event_loop, secondary);
+            }
+            Timer::Expire(secondary) => {
+                self.expire_timers[secondary] = None;
+                let secondary = &self.secondaries[secondary];
+                if secondary.is_expired(Instant::now()) {
+                    println!("{} expired", secondary.origin());
+                    self.authority.set_expired(secondary.origin(), true);
+                }
+            }
+            Timer::NotifyRetry(id) => {
+                if let Some((target, message)) = self.notifier.retry(id) {
+                    self.send_udp(message, &target);
+                    let _ = event_loop.timeout_ms(Timer::NotifyRetry(id), NOTIFY_INTERVAL_MS);
+                }
+            }
+            Timer::Idle(token) => {
+                let now = Instant::now();
+                let idle = match self.connections.get(&token) {
+                    Some(connection) => connection.handler.is_idle(now),
+                    None => return,
+                };
+                if idle {
+                    if let Some(connection) = self.connections.remove(&token) {
+                        let _ = event_loop.deregister(&connection.stream);
+                    }
+                    return;
+                }
+                // Still busy writing, or heard from since the timer was set.
+                let deadline = self.connections[&token].handler.deadline();
+                let delay = if deadline > now {
+                    millis(deadline - now)
+                } else {
+                    IDLE_TIMEOUT_MS
+                };
+                let _ = event_loop.timeout_ms(Timer::Idle(token), delay);
+            }
+            Timer::Quic(index) => {
+                self.quic_timers[index] = None;
+                self.quic[index].1.timeout(Instant::now());
+                self.quic_flush(event_loop, index);
+            }
+            Timer::DnscryptRotate => {
+                if let Some(ref mut server) = self.dnscrypt_server {
+                    server.rotate(now());
+                }
+                let _ = event_loop.timeout_ms(Timer::DnscryptRotate, KEY_ROTATION_MS);
+            }
+            Timer::Relay(id) => {
+                let now = Instant::now();
+                let (failed, deadline) = match self.relay {
+                    Some(ref mut relay) => (relay.timeout(id, now), relay.deadline(id)),
+                    None => return,
+                };
+                if let Some((client, failure)) = failed {
+                    self.close_upstream(event_loop, id);
+                    self.send_relayed(event_loop, client, failure);
+                } else if let Some(deadline) = deadline {
+                    // Set for an earlier query that had the same ID.
+                    let delay = if deadline > now {
+                        millis(deadline - now)
+                    } else {
+                        0
+                    };
+                    let _ = event_loop.timeout_ms(Timer::Relay(id), delay);
+                }
+            }
+            Timer::RelayExpire => {
+                if let Some(ref mut relay) = self.relay {
+                    relay.expire(Instant::now());
+                }
+                let _ = event_loop.timeout_ms(Timer::RelayExpire, EXPIRE_INTERVAL_MS);
+            }
+        }
+    }
+
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Server>, message: Message) {
+        match message {
+            Message::Transferred(done) => self.transferred(event_loop, done),
+            Message::Relayed(upstream, Ok(responses)) => {
+                for response in responses {
+                    let relayed = match self.relay {
+                        Some(ref mut relay) => {
+                            relay.response(&response, &upstream, Instant::now())
+                        }
+                        None => None,
+                    };
+                    self.relayed(event_loop, relayed);
+                }
+            }
+            Message::Relayed(upstream, Err(e)) => {
+                println!("relaying to {} failed: {}", upstream, e);
+            }
+        }
+    }
+}
+
+// Prints the instances of `service`, resolved.
+fn browse_with<L: Lookup>(lookup: &mut L, service: &Name) -> io::Result<()> {
+    for instance in try!(browse(lookup, service)) {
+        let found = match try!(resolve(lookup, &instance)) {
+            Some(found) => found,
+            None => {
+                println!("{}", instance_label(&instance));
+                continue;
+            }
+        };
+        let addresses: Vec<String> = found.addresses.iter().map(|a| a.to_string()).collect();
+        let txt: Vec<String> = found.txt
+                                    .iter()
+                                    .map(|&(ref key, ref value)| {
+                                        match *value {
+                                            Some(ref value) => {
+                                                format!("{}={}",
+                                                        key,
+                                                        String::from_utf8_lossy(value))
+                                            }
+                                            None => key.clone(),
+                                        }
+                                    })
+                                    .collect();
+        println!("{}\t{}:{}\t{}\t{}",
+                 found.instance(),
+                 found.host,
+                 found.port,
+                 addresses.join(" "),
+                 txt.join(" "));
+    }
+    Ok(())
+}
+
+// `bueller browse SERVICE [SERVER]` lists the instances of a service, over
+// multicast DNS or from a unicast server; `bueller register INSTANCE
+// SERVICE HOST PORT [KEY=VALUE...]` publishes one over multicast DNS until
+// killed. Names are relative to `local.`. Returns the exit status.
+fn service_discovery(command: &str, args: &[String]) -> i32 {
+    let local = Name::parse("local.", None).unwrap();
+    let timeout = Duration::from_millis(DISCOVERY_TIMEOUT_MS);
+    let group = IpAddr::V4(MDNS_IPV4);
+    if command == "browse" && (args.len() == 1 || args.len() == 2) {
+        let service = match Name::parse(&args[0], Some(&local)) {
+            Some(service) => service,
+            None => {
+                println!("bad service name {}", args[0]);
+                return 1;
+            }
+        };
+        let server = args.get(1).map(|server| {
+            server.parse::<SocketAddr>()
+                  .ok()
+                  .or(server.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 53)))
+        });
+        let result = match server {
+            Some(Some(server)) => browse_with(&mut UnicastLookup::new(server, timeout), &service),
+            Some(None) => {
+                println!("bad address {}", args[1]);
+                return 1;
+            }
+            None => {
+                MdnsSocket::one_shot(group, MDNS_PORT).and_then(|socket| {
+                    browse_with(&mut MdnsLookup::new(socket, timeout), &service)
+                })
+            }
+        };
+        return match result {
+            Ok(()) => 0,
+            Err(e) => {
+                println!("{}", e);
+                1
+            }
+        };
+    }
+    if command != "register" || args.len() < 4 {
+        println!("usage: bueller browse SERVICE [SERVER]");
+        println!("       bueller register INSTANCE SERVICE HOST PORT [KEY=VALUE...]");
+        return 2;
+    }
+    let service = Name::parse(&args[1], Some(&local));
+    let instance = service.as_ref().and_then(|service| instance_name(&args[0], service));
+    let host = Name::parse(&args[2], Some(&local));
+    let port = args[3].parse::<u16>().ok();
+    let (service, instance, host, port) = match (service, instance, host, port) {
+        (Some(service), Some(instance), Some(host), Some(port)) => (service, instance, host, port),
+        _ => {
+            println!("bad instance, service, host or port");
+            return 1;
+        }
+    };
+    let strings: Vec<Vec<u8>> = args[4..].iter().map(|a| a.as_bytes().to_vec()).collect();
+    let mut responder = Responder::new();
+    if !register(&mut responder, &instance, &host, port, &parse_txt(&strings), Instant::now()) {
+        println!("bad service name {}", service);
+        return 1;
+    }
+    let socket = match MdnsSocket::join(group, MDNS_PORT) {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    loop {
+        let claimed = registered(&responder, &service);
+        if claimed.iter().any(|name| responder.is_claimed(name)) {
+            println!("Registered {}", instance_label(&claimed[0]));
+            break;
+        }
+        let until = Instant::now() + Duration::from_millis(100);
+        if let Err(e) = responder.run(&socket, Some(until)) {
+            println!("{}", e);
+            return 1;
+        }
+    }
+    match responder.run(&socket, None) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("{}", e);
+            1
         }
     }
 }
 
 fn main() {
-    println!("Init...");
+    if let Some(command) = env::args().nth(1) {
+        if command == "browse" || command == "register" {
+            let args: Vec<String> = env::args().skip(2).collect();
+            process::exit(service_discovery(&command, &args));
+        }
+    }
+    let path = env::args().nth(1).unwrap_or(DEFAULT_CONFIG.to_string());
+    let config = match Config::load(&path) {
+        Ok(config) => config,
+        Err(ref e) if e.line == 0 => {
+            println!("{}", e);
+            process::exit(1);
+        }
+        Err(e) => {
+            println!("{}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    let mut authority = match Authority::from_config(&config) {
+        Ok(authority) => authority,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+    for path in &config.key_files {
+        match load_keys(path) {
+            Ok(keys) => {
+                for key in keys {
+                    authority.add_key(key);
+                }
+            }
+            Err(ref e) if e.line == 0 => {
+                println!("{}", e);
+                process::exit(1);
+            }
+            Err(e) => {
+                println!("{}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+    let secondary_keys: Vec<Option<Key>> = config.secondaries
+                                                 .iter()
+                                                 .map(|s| find_key(&authority, s.key.as_ref()))
+                                                 .collect();
+    let catalog_groups: Vec<(GroupConfig, Option<Key>)> =
+        config.catalog_groups
+              .iter()
+              .map(|g| (g.clone(), find_key(&authority, g.key.as_ref())))
+              .collect();
+    let mut catalogs = Catalogs::new();
+    for catalog in &config.catalogs {
+        catalogs.add_catalog(catalog.clone());
+    }
+    for zone in authority.zones() {
+        catalogs.add_local(zone.origin().clone());
+    }
+    for zone in authority.zones() {
+        if !authority.is_secondary(zone.origin()) {
+            println!("Loaded {} serial {}", zone.origin(), zone.serial().unwrap_or(0));
+        }
+    }
+    let secondaries: Vec<Secondary> = config.secondaries
+                                            .iter()
+                                            .map(|s| {
+                                                Secondary::new(s.origin.clone(),
+                                                               s.primaries.clone())
+                                            })
+                                            .collect();
 
     let mut event_loop = mio::EventLoop::new().unwrap();
-
-    println!("Binding socket...");
-    let address = "0.0.0.0:5300".parse().unwrap();
-    let listener = UdpSocket::bound(&address).unwrap();
-    event_loop.register(&listener,
-                        RESPONSE,
-                        mio::EventSet::readable(),
-                        mio::PollOpt::level());
-
-    println!("Sending query ...");
-
-    let mut buffer = iter::repeat(0u8).take(512).collect::<Vec<u8>>();
-    let mut idx = MessageCursor::new(buffer.len());
-    HeaderMut::at(&mut idx, &mut buffer)
-        .unwrap()
-        .make_query(1)
-        .set_qd(1);
-    let qname = encode_dotted_name("github.com").unwrap();
-    let mut qref = Vec::with_capacity(qname.len());
-    for i in 0..qname.len() {
-        qref.push(&qname[i][..]);
-    }
-    // qref == vec![&[0x67u8, 0x69, 0x74, 0x68, 0x75, 0x62][..],
-    //             &[0x63, 0x6f, 0x6d][..]];
-    QuestionMut::at(&mut idx,
-                    &mut buffer,
-                    // github, com, ""
-                    &qref[..],
-                    1, // 0xff, // QTYPE_ALL
-                    1 /* QCLASS_IN */)
-        .unwrap();
-    buffer.truncate(idx.tell());
-    println!("Request: {:?}", buffer);
-    println!("header: {:?}", Header::at(&buffer));
-    println!("question: {:?}", Question::from_message(&buffer, 12));
-
-
-    let upstream = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(8, 8, 8, 8), 53));
-    listener.send_to(&mut io::Cursor::new(buffer), &upstream);
-    let mut server = Recurse::new(listener);
-    println!("Running...");
-    event_loop.run(&mut server);
+    let mut server = Server {
+        authority: authority,
+        udp: Vec::new(),
+        tcp: Vec::new(),
+        tls: Vec::new(),
+        https: Vec::new(),
+        http: Vec::new(),
+        quic: Vec::new(),
+        quic_timers: Vec::new(),
+        dnscrypt: Vec::new(),
+        dnscrypt_tcp: Vec::new(),
+        dnscrypt_server: None,
+        tls_config: None,
+        https_config: None,
+        odoh_key: None,
+        connections: HashMap::new(),
+        next_connection: FIRST_CONNECTION,
+        max_connections: config.max_connections,
+        refresh_timers: secondaries.iter().map(|_| None).collect(),
+        expire_timers: secondaries.iter().map(|_| None).collect(),
+        configured: secondaries.len(),
+        secondaries: secondaries,
+        secondary_keys: secondary_keys,
+        catalogs: catalogs,
+        catalog_groups: catalog_groups,
+        notify: config.notify.clone(),
+        notifier: Notifier::new(1),
+        next_id: 0,
+        relay: None,
+        upstream: HashMap::new(),
+        upstream_threads: Vec::new(),
+    };
+    for address in &config.listen {
+        println!("Listening on {}", address);
+        server.udp.push(UdpSocket::bound(address).unwrap());
+        server.tcp.push(TcpListener::bind(address).unwrap());
+    }
+    if !config.tls_listen.is_empty() {
+        server.tls_config = Some(Arc::new(load_tls(&config)));
+    }
+    if !config.https_listen.is_empty() {
+        let mut https_config = load_tls(&config);
+        https_config.add_alpn(b"h2").add_alpn(b"http/1.1");
+        server.https_config = Some(Arc::new(https_config));
+    }
+    if let Some(ref path) = config.odoh_key {
+        match OdohKey::load(path) {
+            Ok(key) => server.odoh_key = Some(Arc::new(key)),
+            Err(e) => {
+                println!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+    for address in &config.tls_listen {
+        println!("Listening for TLS on {}", address);
+        server.tls.push(TcpListener::bind(address).unwrap());
+    }
+    for address in &config.https_listen {
+        println!("Listening for HTTPS on {}", address);
+        server.https.push(TcpListener::bind(address).unwrap());
+    }
+    for address in &config.http_listen {
+        println!("Listening for HTTP on {}", address);
+        server.http.push(TcpListener::bind(address).unwrap());
+    }
+    if !config.quic_listen.is_empty() {
+        let mut quic_config = load_tls(&config);
+        quic_config.add_alpn(DOQ_ALPN).set_early_data(true);
+        for address in &config.quic_listen {
+            println!("Listening for QUIC on {}", address);
+            let socket = UdpSocket::bound(address).unwrap();
+            let mut doq = DoqServer::new(&quic_config);
+            doq.set_max_connections(config.max_connections);
+            server.quic.push((socket, doq));
+            server.quic_timers.push(None);
+        }
+    }
+    if let Some((ref provider, ref path)) = config.dnscrypt_provider {
+        let key = load_private_key(path).map(|key| Ed25519PrivateKey::from_pkcs8(key.secret_der()));
+        let key = match key {
+            Ok(Some(key)) => key,
+            Ok(None) => {
+                println!("{}: not an Ed25519 key", path.display());
+                process::exit(1);
+            }
+            Err(e) => {
+                println!("{}", e);
+                process::exit(1);
+            }
+        };
+        server.dnscrypt_server = Some(DnscryptServer::new(provider.clone(), key, now()));
+    }
+    for address in &config.dnscrypt_listen {
+        println!("Listening for DNSCrypt on {}", address);
+        server.dnscrypt.push(UdpSocket::bound(address).unwrap());
+        server.dnscrypt_tcp.push(TcpListener::bind(address).unwrap());
+    }
+    if !config.forwarders.is_empty() {
+        for forwarder in &config.forwarders {
+            match forwarder.upstream() {
+                Ok(Some(upstream)) => {
+                    let queries = start_upstream(forwarder.clone(), upstream, event_loop.channel());
+                    server.upstream_threads.push((forwarder.clone(), queries));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    println!("{}: {}", forwarder, e);
+                    process::exit(1);
+                }
+            }
+            println!("Relaying to {}", forwarder);
+        }
+        server.relay = Some(Relay::new(config.forwarders.clone()));
+    }
+    if !config.mdns_records.is_empty() {
+        if let Err(e) = publish_mdns(&config.mdns_records, IpAddr::V4(MDNS_IPV4)) {
+            println!("Multicast DNS: {}", e);
+            process::exit(1);
+        }
+        // Only where the host has IPv6.
+        if let Err(e) = publish_mdns(&config.mdns_records, IpAddr::V6(MDNS_IPV6)) {
+            println!("Multicast DNS over IPv6: {}", e);
+        }
+    }
+    if !config.llmnr_records.is_empty() {
+        if let Err(e) = publish_llmnr(&config.llmnr_records, IpAddr::V4(LLMNR_IPV4)) {
+            println!("LLMNR: {}", e);
+            process::exit(1);
+        }
+        if let Err(e) = publish_llmnr(&config.llmnr_records, IpAddr::V6(LLMNR_IPV6)) {
+            println!("LLMNR over IPv6: {}", e);
+        }
+    }
+    for (i, socket) in server.udp.iter().enumerate() {
+        event_loop.register(socket,
+                            mio::Token(i),
+                            mio::EventSet::readable(),
+                            mio::PollOpt::level())
+                  .unwrap();
+    }
+    for (i, listener) in server.listeners().into_iter().enumerate() {
+        event_loop.register(listener,
+                            mio::Token(server.udp.len() + i),
+                            mio::EventSet::readable(),
+                            mio::PollOpt::level())
+                  .unwrap();
+    }
+    let first_quic = server.udp.len() + server.listeners().len();
+    for (i, &(ref socket, _)) in server.quic.iter().enumerate() {
+        event_loop.register(socket,
+                            mio::Token(first_quic + i),
+                            mio::EventSet::readable(),
+                            mio::PollOpt::level())
+                  .unwrap();
+    }
+    let first_dnscrypt = first_quic + server.quic.len();
+    for (i, socket) in server.dnscrypt.iter().enumerate() {
+        event_loop.register(socket,
+                            mio::Token(first_dnscrypt + i),
+                            mio::EventSet::readable(),
+                            mio::PollOpt::level())
+                  .unwrap();
+    }
+    if server.dnscrypt_server.is_some() {
+        event_loop.timeout_ms(Timer::DnscryptRotate, KEY_ROTATION_MS).unwrap();
+    }
+    if server.relay.is_some() {
+        event_loop.timeout_ms(Timer::RelayExpire, EXPIRE_INTERVAL_MS).unwrap();
+    }
+    for secondary in 0..server.secondaries.len() {
+        server.start_refresh(&mut event_loop, secondary);
+    }
+    let primaries: Vec<Name> = server.authority
+                                     .zones()
+                                     .iter()
+                                     .map(|z| z.origin().clone())
+                                     .filter(|origin| !server.authority.is_secondary(origin))
+                                     .collect();
+    for origin in &primaries {
+        server.send_notify(&mut event_loop, origin);
+    }
+    event_loop.run(&mut server).unwrap();
 }
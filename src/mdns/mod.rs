@@ -0,0 +1,261 @@
+//! Multicast DNS (RFC 6762): names under `local.` answered by the hosts
+//! that own them, over UDP to 224.0.0.251 and ff02::fb on port 5353.
+//!
+//! `Responder` claims and answers for the records this host publishes, and
+//! `Querier` asks for and caches those of other hosts. Both only keep the
+//! books; `MdnsSocket` carries their messages to and from the group.
+//!
+//! The top bit of a question's class asks for a unicast response, and the
+//! top bit of a record's class says the record is the whole set for its
+//! name and type, so caches flush any others.
+
+mod querier;
+mod responder;
+mod socket;
+
+pub use self::querier::Querier;
+pub use self::responder::{ANNOUNCE_COUNT, ANNOUNCE_INTERVAL_MS, LEGACY_TTL, PROBE_COUNT,
+                          PROBE_DEFER_MS, PROBE_INTERVAL_MS, Responder};
+pub use self::socket::MdnsSocket;
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use protocol::{Header, HeaderMut, MessageCursor, Name, QuestionMut, Record};
+
+pub const MDNS_PORT: u16 = 5353;
+pub const MDNS_IPV4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const MDNS_IPV6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+/// The top bit of a question's class: the querier wants the answer
+/// unicast.
+pub const UNICAST_RESPONSE: u16 = 0x8000;
+
+/// The top bit of a record's class: the record replaces what caches hold
+/// for its name and type.
+pub const CACHE_FLUSH: u16 = 0x8000;
+
+/// The longest message sent, as on an Ethernet with jumbo frames.
+pub const MAX_MESSAGE: usize = 9000;
+
+/// A message as the responder and querier read and write it. Classes keep
+/// their top bit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MdnsMessage {
+    pub id: u16,
+    pub is_response: bool,
+    /// Name, type and class.
+    pub questions: Vec<(Name, u16, u16)>,
+    pub answers: Vec<Record>,
+    pub authorities: Vec<Record>,
+    pub additionals: Vec<Record>,
+}
+
+impl MdnsMessage {
+    pub fn new(id: u16, is_response: bool) -> MdnsMessage {
+        MdnsMessage {
+            id: id,
+            is_response: is_response,
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        }
+    }
+
+    /// Reads a message. Returns None for anything malformed, and for
+    /// messages with an opcode or response code, which are ignored.
+    pub fn parse(message: &[u8]) -> Option<MdnsMessage> {
+        let header = Header::at(message);
+        if message.len() < header.end_offset() || header.op() != Some(0) ||
+           header.rc() != Some(0) {
+            return None;
+        }
+        let mut parsed = MdnsMessage::new(header.id().unwrap(), header.is_response());
+        for question in header.questions() {
+            let name = question.name().and_then(|n| Name::from_domain_name(message, n));
+            match (name, question.qtype(), question.qclass()) {
+                (Some(name), Some(qtype), Some(qclass)) => {
+                    parsed.questions.push((name, qtype, qclass))
+                }
+                _ => return None,
+            }
+        }
+        let mut at = match header.questions().end_offset() {
+            Some(at) => at,
+            None => return None,
+        };
+        let counts = [header.an().unwrap(), header.ns().unwrap(), header.ar().unwrap()];
+        for (i, &count) in counts.iter().enumerate() {
+            let (records, next) = match Record::read_section(message, at, count) {
+                Some(section) => section,
+                None => return None,
+            };
+            match i {
+                0 => parsed.answers = records,
+                1 => parsed.authorities = records,
+                _ => parsed.additionals = records,
+            }
+            at = next;
+        }
+        Some(parsed)
+    }
+
+    /// The message on the wire, responses marked authoritative. Records
+    /// that do not fit in `MAX_MESSAGE` octets are left out.
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut message = vec![0u8; MAX_MESSAGE];
+        let mut idx = MessageCursor::new(message.len());
+        HeaderMut::at(&mut idx, &mut message[..])
+            .unwrap()
+            .set_id(self.id)
+            .set_qr(self.is_response)
+            .set_aa(self.is_response);
+        let mut qd = 0;
+        for &(ref name, qtype, qclass) in &self.questions {
+            if QuestionMut::at(&mut idx, &mut message, &name.segments(), qtype, qclass).is_some() {
+                qd += 1;
+            }
+        }
+        let mut counts = [0u16; 3];
+        for (i, section) in [&self.answers, &self.authorities, &self.additionals]
+                                .iter()
+                                .enumerate() {
+            for record in section.iter() {
+                let at = idx.tell();
+                match record.write_at(&mut idx, &mut message) {
+                    Some(_) => counts[i] += 1,
+                    None => idx.rewind(at),
+                }
+            }
+        }
+        HeaderMut::at_raw(&mut message[..])
+            .set_qd(qd)
+            .set_an(counts[0])
+            .set_ns(counts[1])
+            .set_ar(counts[2]);
+        message.truncate(idx.tell());
+        message
+    }
+}
+
+// Whether two records are the same but for their TTLs and cache-flush
+// bits.
+fn same_record(a: &Record, b: &Record) -> bool {
+    a.name == b.name && a.class & !CACHE_FLUSH == b.class & !CACHE_FLUSH && a.rdata == b.rdata
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+    use std::net::{IpAddr, UdpSocket};
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use protocol::{Name, RData, Record};
+    use protocol::rdata::{CLASS_IN, TYPE_A, TYPE_ANY};
+
+    #[test]
+    fn messages() {
+        let name = Name::parse("printer.local.", None).unwrap();
+        let mut message = MdnsMessage::new(0, false);
+        message.questions.push((name.clone(), TYPE_ANY, CLASS_IN | UNICAST_RESPONSE));
+        message.authorities.push(Record::new(name.clone(),
+                                             CLASS_IN,
+                                             120,
+                                             RData::A("192.0.2.5".parse().unwrap())));
+        let wire = message.to_wire();
+        assert_eq!(&[0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0], &wire[..12]);
+        assert_eq!(Some(message.clone()), MdnsMessage::parse(&wire));
+
+        message.is_response = true;
+        message.questions.clear();
+        message.answers.push(Record::new(name.clone(),
+                                         CLASS_IN | CACHE_FLUSH,
+                                         120,
+                                         RData::A("192.0.2.6".parse().unwrap())));
+        let wire = message.to_wire();
+        assert_eq!(&[0, 0, 0x84, 0, 0, 0, 0, 1, 0, 1, 0, 0], &wire[..12]);
+        let parsed = MdnsMessage::parse(&wire).unwrap();
+        assert_eq!(CLASS_IN | CACHE_FLUSH, parsed.answers[0].class);
+        let flushed = Record::new(name, CLASS_IN, 0, RData::A("192.0.2.6".parse().unwrap()));
+        assert!(same_record(&parsed.answers[0], &flushed));
+
+        // Records that do not fit are left out whole.
+        let big = Record::new(Name::parse("big.local.", None).unwrap(),
+                              CLASS_IN,
+                              120,
+                              RData::Txt(vec![vec![b'x'; 255]; 40]));
+        message.answers.push(big);
+        let parsed = MdnsMessage::parse(&message.to_wire()).unwrap();
+        assert_eq!((1, 1), (parsed.answers.len(), parsed.authorities.len()));
+
+        // Other opcodes are ignored.
+        let mut update = wire.clone();
+        update[2] |= 0x28;
+        assert_eq!(None, MdnsMessage::parse(&update));
+        assert_eq!(None, MdnsMessage::parse(&wire[..wire.len() - 1]));
+    }
+
+    #[test]
+    fn shared_port() {
+        let port = UdpSocket::bind("0.0.0.0:0").unwrap().local_addr().unwrap().port();
+        let _first = MdnsSocket::join(IpAddr::V4(MDNS_IPV4), port).unwrap();
+        let _second = MdnsSocket::join(IpAddr::V4(MDNS_IPV4), port).unwrap();
+        // IPv6 beside IPv4 on the same port, where the host has IPv6.
+        if let Err(e) = MdnsSocket::join(IpAddr::V6(MDNS_IPV6), port) {
+            assert!(e.kind() != io::ErrorKind::AddrInUse, "{}", e);
+        }
+    }
+
+    #[test]
+    fn loopback() {
+        // A port of our own, rather than the one other hosts use.
+        let port = UdpSocket::bind("0.0.0.0:0").unwrap().local_addr().unwrap().port();
+        let group = IpAddr::V4(MDNS_IPV4);
+        let name = Name::parse("printer.local.", None).unwrap();
+        let printer = |address: &str| {
+            Record::new(name.clone(), CLASS_IN, 120, RData::A(address.parse().unwrap()))
+        };
+
+        let socket = MdnsSocket::join(group, port).unwrap();
+        let mut responder = Responder::new();
+        responder.publish(printer("192.0.2.5"), true, Instant::now());
+        let (claimed, rx) = channel();
+        let owner = name.clone();
+        let thread = thread::spawn(move || {
+            while !responder.is_claimed(&owner) {
+                responder.run(&socket, Some(Instant::now() + Duration::from_millis(100))).unwrap();
+            }
+            claimed.send(()).unwrap();
+            responder.run(&socket, Some(Instant::now() + Duration::from_secs(3))).unwrap();
+        });
+        rx.recv().unwrap();
+
+        // Another host wanting the name gets the next one.
+        let socket = MdnsSocket::one_shot(group, port).unwrap();
+        let mut other = Responder::new();
+        other.publish(printer("192.0.2.6"), true, Instant::now());
+        let deadline = Instant::now() + Duration::from_secs(4);
+        let renamed = Name::parse("printer-2.local.", None).unwrap();
+        while !other.is_claimed(&renamed) && Instant::now() < deadline {
+            other.run(&socket, Some(Instant::now() + Duration::from_millis(100))).unwrap();
+        }
+        assert_eq!(renamed, other.records()[0].name);
+        assert!(other.is_claimed(&renamed));
+
+        let socket = MdnsSocket::one_shot(group, port).unwrap();
+        let mut querier = Querier::new();
+        let answers = querier.ask(&socket,
+                                  &name,
+                                  TYPE_A,
+                                  true,
+                                  Instant::now() + Duration::from_secs(2))
+                             .unwrap();
+        // Legacy, from a port other than the group's.
+        let mut expected = printer("192.0.2.5");
+        expected.ttl = LEGACY_TTL;
+        assert_eq!(vec![expected], answers);
+        thread.join().unwrap();
+    }
+}
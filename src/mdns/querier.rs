@@ -0,0 +1,284 @@
+//! The querier (RFC 6762 Sections 5 to 10): asks the link, and caches what
+//! every response tells it.
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use protocol::{Name, Record};
+use protocol::rdata::{CLASS_IN, TYPE_ANY};
+use super::{CACHE_FLUSH, MdnsMessage, UNICAST_RESPONSE, same_record};
+use super::socket::MdnsSocket;
+
+// How long records flushed or said goodbye to linger (RFC 6762 Sections
+// 10.1 and 10.2), in case the message that ended them was out of date.
+const LINGER_MS: u64 = 1000;
+
+#[derive(Debug)]
+struct Cached {
+    // Its class without the cache-flush bit.
+    record: Record,
+    received: Instant,
+    expires: Instant,
+}
+
+/// The records heard from the link, until their TTLs run out.
+#[derive(Debug)]
+pub struct Querier {
+    cache: Vec<Cached>,
+}
+
+impl Querier {
+    pub fn new() -> Querier {
+        Querier { cache: Vec::new() }
+    }
+
+    /// A query for `name` and `qtype`, asking for a unicast response if
+    /// `unicast`, with the answers already cached that have over half their
+    /// TTL left so responders need not repeat them.
+    pub fn query(&self, name: &Name, qtype: u16, unicast: bool, now: Instant) -> Vec<u8> {
+        let mut message = MdnsMessage::new(0, false);
+        let class = if unicast {
+            CLASS_IN | UNICAST_RESPONSE
+        } else {
+            CLASS_IN
+        };
+        message.questions.push((name.clone(), qtype, class));
+        for cached in &self.cache {
+            let record = &cached.record;
+            if record.name != *name || qtype != TYPE_ANY && qtype != record.rtype() {
+                continue;
+            }
+            let left = remaining(cached, now);
+            if left > record.ttl / 2 {
+                let mut known = record.clone();
+                known.ttl = left;
+                message.answers.push(known);
+            }
+        }
+        message.to_wire()
+    }
+
+    /// Caches the records of a response, and returns them with their
+    /// classes' top bits cleared. Queries are ignored.
+    pub fn received(&mut self, message: &[u8], now: Instant) -> Vec<Record> {
+        let message = match MdnsMessage::parse(message) {
+            Some(ref message) if message.is_response => message.clone(),
+            _ => return Vec::new(),
+        };
+        let linger = now + Duration::from_millis(LINGER_MS);
+        let mut records = Vec::new();
+        let sections = message.answers
+                              .into_iter()
+                              .chain(message.authorities)
+                              .chain(message.additionals);
+        for mut record in sections {
+            let flush = record.class & CACHE_FLUSH != 0;
+            record.class &= !CACHE_FLUSH;
+            if flush {
+                // The record is the whole set: the rest go, unless they
+                // came in the last second, in the same burst of responses.
+                for cached in self.cache.iter_mut() {
+                    if cached.record.name == record.name &&
+                       cached.record.rtype() == record.rtype() &&
+                       cached.record.class == record.class &&
+                       cached.record.rdata != record.rdata &&
+                       cached.received + Duration::from_millis(LINGER_MS) <= now &&
+                       cached.expires > linger {
+                        cached.expires = linger;
+                    }
+                }
+            }
+            let expires = if record.ttl == 0 {
+                linger
+            } else {
+                now + Duration::from_secs(record.ttl as u64)
+            };
+            match self.cache.iter_mut().position(|c| same_record(&c.record, &record)) {
+                Some(at) => {
+                    let cached = &mut self.cache[at];
+                    if record.ttl == 0 && cached.expires < linger {
+                        // Already on the way out.
+                    } else {
+                        cached.record.ttl = record.ttl;
+                        cached.received = now;
+                        cached.expires = expires;
+                    }
+                }
+                None if record.ttl == 0 => {}
+                None => {
+                    self.cache.push(Cached {
+                        record: record.clone(),
+                        received: now,
+                        expires: expires,
+                    })
+                }
+            }
+            records.push(record);
+        }
+        records
+    }
+
+    /// The records cached for `name` and `qtype`, with the TTLs they have
+    /// left.
+    pub fn lookup(&self, name: &Name, qtype: u16, now: Instant) -> Vec<Record> {
+        self.cache
+            .iter()
+            .filter(|c| {
+                c.expires > now && c.record.name == *name &&
+                (qtype == TYPE_ANY || qtype == c.record.rtype())
+            })
+            .map(|c| {
+                let mut record = c.record.clone();
+                record.ttl = remaining(c, now);
+                record
+            })
+            .collect()
+    }
+
+    /// Every record cached, with the TTLs they have left.
+    pub fn records(&self, now: Instant) -> Vec<Record> {
+        self.cache
+            .iter()
+            .filter(|c| c.expires > now)
+            .map(|c| {
+                let mut record = c.record.clone();
+                record.ttl = remaining(c, now);
+                record
+            })
+            .collect()
+    }
+
+    /// Drops what has expired by `now`.
+    pub fn expire(&mut self, now: Instant) {
+        self.cache.retain(|c| c.expires > now);
+    }
+
+    /// Asks the link for `name` and `qtype` over `socket`, and returns the
+    /// answers once any come, or what is cached if none come by `deadline`.
+    pub fn ask(&mut self,
+               socket: &MdnsSocket,
+               name: &Name,
+               qtype: u16,
+               unicast: bool,
+               deadline: Instant)
+               -> io::Result<Vec<Record>> {
+        let now = Instant::now();
+        self.expire(now);
+        try!(socket.send(&self.query(name, qtype, unicast, now), None));
+        loop {
+            let (message, _): (Vec<u8>, SocketAddr) = match try!(socket.receive(deadline)) {
+                Some(received) => received,
+                None => return Ok(self.lookup(name, qtype, Instant::now())),
+            };
+            let now = Instant::now();
+            let answered = self.received(&message, now).iter().any(|r| {
+                r.ttl > 0 && r.name == *name && (qtype == TYPE_ANY || qtype == r.rtype())
+            });
+            if answered {
+                return Ok(self.lookup(name, qtype, now));
+            }
+        }
+    }
+
+    /// Caches every response `socket` receives until `deadline`, for
+    /// questions many hosts answer.
+    pub fn listen(&mut self, socket: &MdnsSocket, deadline: Instant) -> io::Result<()> {
+        while let Some((message, _)) = try!(socket.receive(deadline)) {
+            self.received(&message, Instant::now());
+        }
+        Ok(())
+    }
+}
+
+// Seconds of its TTL a cached record has left.
+fn remaining(cached: &Cached, now: Instant) -> u32 {
+    if cached.expires <= now {
+        0
+    } else {
+        (cached.expires - now).as_secs() as u32
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use mdns::{CACHE_FLUSH, MdnsMessage, UNICAST_RESPONSE};
+    use protocol::{Name, RData, Record};
+    use protocol::rdata::{CLASS_IN, TYPE_A, TYPE_TXT};
+
+    fn address(address: &str, ttl: u32, flush: bool) -> Record {
+        let class = if flush {
+            CLASS_IN | CACHE_FLUSH
+        } else {
+            CLASS_IN
+        };
+        Record::new(Name::parse("printer.local.", None).unwrap(),
+                    class,
+                    ttl,
+                    RData::A(address.parse().unwrap()))
+    }
+
+    fn response(records: Vec<Record>) -> Vec<u8> {
+        let mut response = MdnsMessage::new(0, true);
+        response.answers = records;
+        response.to_wire()
+    }
+
+    #[test]
+    fn caching() {
+        let name = Name::parse("printer.local.", None).unwrap();
+        let start = Instant::now();
+        let mut querier = Querier::new();
+
+        let query = MdnsMessage::parse(&querier.query(&name, TYPE_A, true, start)).unwrap();
+        assert_eq!(vec![(name.clone(), TYPE_A, CLASS_IN | UNICAST_RESPONSE)], query.questions);
+        assert!(query.answers.is_empty());
+
+        let received = querier.received(&response(vec![address("192.0.2.5", 120, false),
+                                                       address("192.0.2.6", 120, false)]),
+                                        start);
+        assert_eq!(vec![address("192.0.2.5", 120, false), address("192.0.2.6", 120, false)],
+                   received);
+        assert_eq!(2, querier.lookup(&name, TYPE_A, start).len());
+        assert!(querier.lookup(&name, TYPE_TXT, start).is_empty());
+
+        // Known answers go along while they have over half their TTL left.
+        let query = MdnsMessage::parse(&querier.query(&name, TYPE_A, false, start)).unwrap();
+        assert_eq!(CLASS_IN, query.questions[0].2);
+        assert_eq!(2, query.answers.len());
+        let later = start + Duration::from_secs(61);
+        assert!(MdnsMessage::parse(&querier.query(&name, TYPE_A, false, later))
+                    .unwrap()
+                    .answers
+                    .is_empty());
+        assert_eq!(vec![address("192.0.2.5", 59, false), address("192.0.2.6", 59, false)],
+                   querier.lookup(&name, TYPE_A, later));
+
+        // A flushing record replaces the rest after a second.
+        let now = start + Duration::from_secs(5);
+        let received = querier.received(&response(vec![address("192.0.2.6", 120, true)]), now);
+        assert_eq!(vec![address("192.0.2.6", 120, false)], received);
+        assert_eq!(2, querier.lookup(&name, TYPE_A, now).len());
+        let now = now + Duration::from_millis(1000);
+        assert_eq!(vec![address("192.0.2.6", 119, false)], querier.lookup(&name, TYPE_A, now));
+
+        // Except what came in the second before.
+        querier.received(&response(vec![address("192.0.2.7", 120, true)]), now);
+        querier.received(&response(vec![address("192.0.2.8", 120, true)]), now);
+        assert_eq!(2, querier.lookup(&name, TYPE_A, now + Duration::from_secs(2)).len());
+
+        // Goodbyes too.
+        querier.received(&response(vec![address("192.0.2.7", 0, false)]), now);
+        assert_eq!(3, querier.lookup(&name, TYPE_A, now).len());
+        let now = now + Duration::from_secs(1);
+        assert_eq!(vec![address("192.0.2.8", 119, false)], querier.lookup(&name, TYPE_A, now));
+        querier.expire(now);
+        assert_eq!(1, querier.cache.len());
+
+        // Queries are no answers.
+        let query = querier.query(&name, TYPE_A, false, now);
+        assert!(querier.received(&query, now).is_empty());
+    }
+}
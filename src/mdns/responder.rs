@@ -0,0 +1,704 @@
+//! The responder (RFC 6762 Sections 6 to 10): records this host publishes,
+//! claimed by probing, announced, defended against other hosts, and given
+//! out in answer to queries.
+//!
+//! Unique records, such as a host's addresses, are probed for before they
+//! are used; if another host answers for the name, or probes for it at the
+//! same time with data that sorts later, the name is numbered (`printer`
+//! becomes `printer-2`) and probed for again. Shared records, such as the
+//! PTR records of service browsing, are only announced.
+
+use std::cmp;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use crypto::random_bytes;
+use protocol::{Name, RData, Record};
+use protocol::rdata::{CLASS_ANY, TYPE_ANY};
+use super::{CACHE_FLUSH, MDNS_PORT, MdnsMessage, UNICAST_RESPONSE, same_record};
+use super::socket::MdnsSocket;
+
+/// Milliseconds between probes, and the most the first waits.
+pub const PROBE_INTERVAL_MS: u64 = 250;
+pub const PROBE_COUNT: u32 = 3;
+
+/// Milliseconds between announcements.
+pub const ANNOUNCE_INTERVAL_MS: u64 = 1000;
+pub const ANNOUNCE_COUNT: u32 = 2;
+
+/// Milliseconds the loser of simultaneous probes waits to probe again.
+pub const PROBE_DEFER_MS: u64 = 1000;
+
+/// The longest TTL given to legacy queriers, which do not hear of changes.
+pub const LEGACY_TTL: u32 = 10;
+
+// Answers with shared records wait between these many milliseconds, so
+// the answers of several hosts go out together.
+const MIN_SHARED_DELAY_MS: u64 = 20;
+const MAX_SHARED_DELAY_MS: u64 = 120;
+
+// How long to wait for messages when nothing is due.
+const IDLE_WAIT_MS: u64 = 60000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Probing,
+    Announcing,
+    Announced,
+}
+
+#[derive(Debug)]
+struct Published {
+    // Its class without the cache-flush bit.
+    record: Record,
+    unique: bool,
+    state: State,
+    // Probes or announcements sent in this state.
+    sent: u32,
+    next: Instant,
+}
+
+fn random_ms(min: u64, max: u64) -> Duration {
+    let bytes = random_bytes(2);
+    let random = (bytes[0] as u64) << 8 | bytes[1] as u64;
+    Duration::from_millis(min + random % (max - min + 1))
+}
+
+// The name with its first label numbered, or its number raised: `printer`
+// becomes `printer-2`, and `printer-2` becomes `printer-3`.
+fn next_name(name: &Name) -> Name {
+    let first = String::from_utf8_lossy(&name.labels()[0]).into_owned();
+    let number = first.rfind('-').map(|at| (&first[..at], first[at + 1..].parse::<u32>()));
+    let numbered = match number {
+        Some((base, Ok(n))) if n >= 2 => format!("{}-{}", base, n + 1),
+        _ => format!("{}-2", first),
+    };
+    let mut labels = name.labels().to_vec();
+    labels[0] = numbered.into_bytes();
+    Name::from_labels(labels).unwrap_or(name.clone())
+}
+
+// The rdata with names that point at `from` pointed at `to`.
+fn renamed(rdata: &RData, from: &Name, to: &Name) -> RData {
+    match *rdata {
+        RData::Ptr(ref name) if name == from => RData::Ptr(to.clone()),
+        RData::Cname(ref name) if name == from => RData::Cname(to.clone()),
+        RData::Srv { priority, weight, port, ref target } if target == from => {
+            RData::Srv {
+                priority: priority,
+                weight: weight,
+                port: port,
+                target: to.clone(),
+            }
+        }
+        ref other => other.clone(),
+    }
+}
+
+// The name rdata points at, whose records go along as additionals.
+fn target(rdata: &RData) -> Option<&Name> {
+    match *rdata {
+        RData::Ptr(ref name) | RData::Cname(ref name) => Some(name),
+        RData::Srv { ref target, .. } => Some(target),
+        _ => None,
+    }
+}
+
+// The records for a name as simultaneous probes compare them (RFC 6762
+// Section 8.2): sorted by class, type and rdata.
+fn probe_order(records: Vec<&Record>) -> Vec<(u16, u16, Vec<u8>)> {
+    let mut order: Vec<(u16, u16, Vec<u8>)> =
+        records.iter()
+               .map(|r| (r.class & !CACHE_FLUSH, r.rtype(), r.rdata.to_canonical_wire()))
+               .collect();
+    order.sort();
+    order
+}
+
+/// The records this host publishes, and how far each has come to being its
+/// own. What comes in goes to `received`; what is to be sent comes out of
+/// `poll`, with None for the group or the address to send to.
+#[derive(Debug)]
+pub struct Responder {
+    published: Vec<Published>,
+    port: u16,
+    // Responses waiting to go out, and where to.
+    queued: Vec<(Instant, Vec<u8>, Option<SocketAddr>)>,
+}
+
+impl Responder {
+    pub fn new() -> Responder {
+        Responder {
+            published: Vec::new(),
+            port: MDNS_PORT,
+            queued: Vec::new(),
+        }
+    }
+
+    /// The port of the group. Queries from any other port come from legacy
+    /// resolvers, and are answered the way they expect.
+    pub fn set_port(&mut self, port: u16) -> &mut Self {
+        self.port = port;
+        self
+    }
+
+    /// Publishes a record: `unique` if this host alone has records of its
+    /// name and type, so it is probed for first, or shared.
+    pub fn publish(&mut self, mut record: Record, unique: bool, now: Instant) {
+        record.class &= !CACHE_FLUSH;
+        let owned = self.published.iter().any(|p| {
+            p.unique && p.record.name == record.name && p.state != State::Probing
+        });
+        let probing = self.published
+                          .iter()
+                          .find(|p| {
+                              p.record.name == record.name && p.state == State::Probing &&
+                              p.sent == 0
+                          })
+                          .map(|p| p.next);
+        let (state, next) = match probing {
+            _ if !unique || owned => (State::Announcing, now),
+            // Probed for together with the others of its name.
+            Some(next) => (State::Probing, next),
+            None => (State::Probing, now + random_ms(0, PROBE_INTERVAL_MS)),
+        };
+        self.published.push(Published {
+            record: record,
+            unique: unique,
+            state: state,
+            sent: 0,
+            next: next,
+        });
+    }
+
+    /// Every record published, under the names they ended up with.
+    pub fn records(&self) -> Vec<&Record> {
+        self.published.iter().map(|p| &p.record).collect()
+    }
+
+    /// Whether probing for `name` is over and it is this host's.
+    pub fn is_claimed(&self, name: &Name) -> bool {
+        let mut records = self.published.iter().filter(|p| p.record.name == *name).peekable();
+        records.peek().is_some() && records.all(|p| p.state != State::Probing)
+    }
+
+    /// The messages due by `now`: probes, announcements and responses.
+    pub fn poll(&mut self, now: Instant) -> Vec<(Vec<u8>, Option<SocketAddr>)> {
+        let mut messages = Vec::new();
+        let mut probe = MdnsMessage::new(0, false);
+        let mut announcement = MdnsMessage::new(0, true);
+        for published in self.published.iter_mut().filter(|p| p.next <= now) {
+            if published.state == State::Probing && published.sent < PROBE_COUNT {
+                let name = published.record.name.clone();
+                if !probe.questions.iter().any(|q| q.0 == name) {
+                    probe.questions.push((name, TYPE_ANY, CLASS_ANY | UNICAST_RESPONSE));
+                }
+                probe.authorities.push(published.record.clone());
+                published.sent += 1;
+                published.next = now + Duration::from_millis(PROBE_INTERVAL_MS);
+                continue;
+            }
+            if published.state == State::Probing {
+                // No one objected in time.
+                published.state = State::Announcing;
+                published.sent = 0;
+            }
+            if published.state == State::Announcing {
+                let mut record = published.record.clone();
+                if published.unique {
+                    record.class |= CACHE_FLUSH;
+                }
+                announcement.answers.push(record);
+                published.sent += 1;
+                published.next = now + Duration::from_millis(ANNOUNCE_INTERVAL_MS);
+                if published.sent == ANNOUNCE_COUNT {
+                    published.state = State::Announced;
+                }
+            }
+        }
+        if !probe.authorities.is_empty() {
+            messages.push((probe.to_wire(), None));
+        }
+        if !announcement.answers.is_empty() {
+            messages.push((announcement.to_wire(), None));
+        }
+        let (due, waiting): (Vec<_>, Vec<_>) = self.queued.drain(..).partition(|q| q.0 <= now);
+        self.queued = waiting;
+        messages.extend(due.into_iter().map(|(_, message, to)| (message, to)));
+        messages
+    }
+
+    /// When `poll` next has something to send, if ever.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.published
+            .iter()
+            .filter(|p| p.state != State::Announced)
+            .map(|p| p.next)
+            .chain(self.queued.iter().map(|q| q.0))
+            .min()
+    }
+
+    /// Takes a message from `from`: a query to answer or a probe to weigh
+    /// against ours, or a response that may conflict with our records.
+    pub fn received(&mut self, message: &[u8], from: SocketAddr, now: Instant) {
+        let message = match MdnsMessage::parse(message) {
+            Some(message) => message,
+            None => return,
+        };
+        if message.is_response {
+            self.check_conflicts(&message, now);
+        } else {
+            self.check_probes(&message, now);
+            self.answer(&message, from, now);
+        }
+    }
+
+    // A response with other data for one of our unique records: while
+    // probing, the name is someone else's and we take another; later, we
+    // probe for it again to find out (RFC 6762 Section 9).
+    fn check_conflicts(&mut self, message: &MdnsMessage, now: Instant) {
+        let mut conflicts: Vec<(Name, bool)> = Vec::new();
+        for record in message.answers.iter().chain(&message.additionals) {
+            if record.ttl == 0 || self.published.iter().any(|p| same_record(&p.record, record)) {
+                continue;
+            }
+            let conflict = self.published.iter().find(|p| {
+                p.unique && p.record.name == record.name &&
+                (p.state == State::Probing ||
+                 p.record.rtype() == record.rtype() &&
+                 p.record.class == record.class & !CACHE_FLUSH)
+            });
+            if let Some(published) = conflict {
+                if !conflicts.iter().any(|c| c.0 == published.record.name) {
+                    conflicts.push((published.record.name.clone(),
+                                    published.state == State::Probing));
+                }
+            }
+        }
+        for (name, probing) in conflicts {
+            if probing {
+                self.rename(&name, now);
+            } else {
+                self.reprobe(&name, now);
+            }
+        }
+    }
+
+    // A probe for a name we are probing for too: the one with the data that
+    // sorts earlier waits and tries again (RFC 6762 Section 8.2).
+    fn check_probes(&mut self, message: &MdnsMessage, now: Instant) {
+        let mut lost = Vec::new();
+        for &(ref name, _, _) in &message.questions {
+            if !self.published.iter().any(|p| p.record.name == *name && p.state == State::Probing) {
+                continue;
+            }
+            let theirs = probe_order(message.authorities
+                                            .iter()
+                                            .filter(|r| r.name == *name)
+                                            .collect());
+            let ours = probe_order(self.published
+                                       .iter()
+                                       .filter(|p| p.unique && p.record.name == *name)
+                                       .map(|p| &p.record)
+                                       .collect());
+            if !theirs.is_empty() && ours < theirs {
+                lost.push(name.clone());
+            }
+        }
+        for name in lost {
+            self.reprobe(&name, now + Duration::from_millis(PROBE_DEFER_MS));
+        }
+    }
+
+    fn reprobe(&mut self, name: &Name, at: Instant) {
+        for published in self.published.iter_mut() {
+            if published.unique && published.record.name == *name {
+                published.state = State::Probing;
+                published.sent = 0;
+                published.next = at;
+            }
+        }
+    }
+
+    // Takes the next name after `name` for the records that have it, and
+    // points the records that refer to it there.
+    fn rename(&mut self, name: &Name, now: Instant) {
+        let mut new_name = next_name(name);
+        while self.published.iter().any(|p| p.record.name == new_name) {
+            new_name = next_name(&new_name);
+        }
+        for published in self.published.iter_mut() {
+            let rdata = renamed(&published.record.rdata, name, &new_name);
+            if published.record.name == *name {
+                published.record.name = new_name.clone();
+                published.state = if published.unique {
+                    State::Probing
+                } else {
+                    State::Announcing
+                };
+            } else if rdata != published.record.rdata {
+                if published.state == State::Announced {
+                    published.state = State::Announcing;
+                }
+            } else {
+                continue;
+            }
+            published.record.rdata = rdata;
+            published.sent = 0;
+            published.next = now;
+        }
+    }
+
+    // Answers a query with the records we own that it asks for, less those
+    // it already knows, and the records they point to (RFC 6762 Section 6).
+    fn answer(&mut self, query: &MdnsMessage, from: SocketAddr, now: Instant) {
+        let legacy = from.port() != self.port;
+        let mut unicast = legacy;
+        let mut answers: Vec<&Published> = Vec::new();
+        for &(ref name, qtype, qclass) in &query.questions {
+            unicast |= qclass & UNICAST_RESPONSE != 0;
+            let qclass = qclass & !UNICAST_RESPONSE;
+            for published in &self.published {
+                let record = &published.record;
+                if published.state == State::Probing || record.name != *name ||
+                   qtype != TYPE_ANY && qtype != record.rtype() ||
+                   qclass != CLASS_ANY && qclass != record.class {
+                    continue;
+                }
+                // Known-answer suppression (RFC 6762 Section 7.1).
+                if query.answers.iter().any(|r| same_record(r, record) && r.ttl >= record.ttl / 2) {
+                    continue;
+                }
+                if !answers.iter().any(|p| same_record(&p.record, record)) {
+                    answers.push(published);
+                }
+            }
+        }
+        if answers.is_empty() {
+            return;
+        }
+        let mut additionals: Vec<&Published> = Vec::new();
+        let mut targets: Vec<&Name> = answers.iter()
+                                             .filter_map(|p| target(&p.record.rdata))
+                                             .collect();
+        while let Some(name) = targets.pop() {
+            for published in &self.published {
+                let record = &published.record;
+                if published.state == State::Probing || record.name != *name ||
+                   answers.iter().chain(&additionals).any(|p| same_record(&p.record, record)) {
+                    continue;
+                }
+                additionals.push(published);
+                targets.extend(target(&published.record.rdata));
+            }
+        }
+
+        let record = |published: &&Published| {
+            let mut record = published.record.clone();
+            if legacy {
+                record.ttl = cmp::min(record.ttl, LEGACY_TTL);
+            } else if published.unique {
+                record.class |= CACHE_FLUSH;
+            }
+            record
+        };
+        let mut response = MdnsMessage::new(0, true);
+        if legacy {
+            response.id = query.id;
+            response.questions = query.questions.clone();
+        }
+        response.answers = answers.iter().map(&record).collect();
+        response.additionals = additionals.iter().map(&record).collect();
+        let at = if unicast || answers.iter().all(|p| p.unique) {
+            now
+        } else {
+            now + random_ms(MIN_SHARED_DELAY_MS, MAX_SHARED_DELAY_MS)
+        };
+        self.queued.push((at, response.to_wire(), if unicast { Some(from) } else { None }));
+    }
+
+    /// The goodbye for every record claimed: each with a TTL of zero, for
+    /// caches to drop them (RFC 6762 Section 10.1).
+    pub fn goodbye(&self) -> Vec<u8> {
+        let mut message = MdnsMessage::new(0, true);
+        for published in self.published.iter().filter(|p| p.state != State::Probing) {
+            let mut record = published.record.clone();
+            record.ttl = 0;
+            message.answers.push(record);
+        }
+        message.to_wire()
+    }
+
+    /// Runs the responder on `socket` until `until`, or for good.
+    pub fn run(&mut self, socket: &MdnsSocket, until: Option<Instant>) -> io::Result<()> {
+        self.port = socket.group().port();
+        loop {
+            let now = Instant::now();
+            for (message, to) in self.poll(now) {
+                try!(socket.send(&message, to));
+            }
+            let mut deadline = self.deadline()
+                                   .unwrap_or(now + Duration::from_millis(IDLE_WAIT_MS));
+            match until {
+                Some(until) if until <= now => return Ok(()),
+                Some(until) if until < deadline => deadline = until,
+                _ => {}
+            }
+            if let Some((message, from)) = try!(socket.receive(deadline)) {
+                self.received(&message, from, Instant::now());
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+    use mdns::{CACHE_FLUSH, MDNS_PORT, MdnsMessage, UNICAST_RESPONSE};
+    use protocol::{Name, RData, Record};
+    use protocol::rdata::{CLASS_ANY, CLASS_IN, TYPE_A, TYPE_ANY, TYPE_PTR};
+    use test_util::class_query;
+
+    fn name(text: &str) -> Name {
+        Name::parse(text, None).unwrap()
+    }
+
+    fn address(owner: &str, address: &str) -> Record {
+        Record::new(name(owner), CLASS_IN, 120, RData::A(address.parse().unwrap()))
+    }
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    // Publishes a printer's address and service, and polls until it is
+    // announced. Returns when that was.
+    fn printer(start: Instant) -> (Responder, Instant) {
+        let mut responder = Responder::new();
+        responder.publish(address("printer.local.", "192.0.2.5"), true, start);
+        responder.publish(Record::new(name("printer._ipp._tcp.local."),
+                                      CLASS_IN,
+                                      120,
+                                      RData::Srv {
+                                          priority: 0,
+                                          weight: 0,
+                                          port: 631,
+                                          target: name("printer.local."),
+                                      }),
+                          true,
+                          start);
+        responder.publish(Record::new(name("_ipp._tcp.local."),
+                                      CLASS_IN,
+                                      4500,
+                                      RData::Ptr(name("printer._ipp._tcp.local."))),
+                          false,
+                          start);
+        let mut now = start;
+        while responder.deadline().is_some() {
+            now = responder.deadline().unwrap();
+            responder.poll(now);
+        }
+        (responder, now)
+    }
+
+    fn query(qname: &str, qtype: u16, qclass: u16) -> MdnsMessage {
+        MdnsMessage::parse(&class_query(0, qname, qtype, qclass)).unwrap()
+    }
+
+    fn group() -> SocketAddr {
+        SocketAddr::new("192.0.2.9".parse().unwrap(), MDNS_PORT)
+    }
+
+    #[test]
+    fn probing_and_announcing() {
+        let start = Instant::now();
+        let mut responder = Responder::new();
+        responder.publish(address("printer.local.", "192.0.2.5"), true, start);
+        responder.publish(Record::new(name("printer.local."),
+                                      CLASS_IN,
+                                      120,
+                                      RData::Txt(vec![b"ok".to_vec()])),
+                          true,
+                          start);
+        let first = responder.deadline().unwrap();
+        assert!(first <= ms(start, PROBE_INTERVAL_MS));
+
+        for i in 0..PROBE_COUNT as u64 {
+            let now = first + Duration::from_millis(i * PROBE_INTERVAL_MS);
+            let sent = responder.poll(now);
+            assert_eq!(1, sent.len());
+            let probe = MdnsMessage::parse(&sent[0].0).unwrap();
+            assert_eq!(None, sent[0].1);
+            assert!(!probe.is_response);
+            assert_eq!(vec![(name("printer.local."), TYPE_ANY, CLASS_ANY | UNICAST_RESPONSE)],
+                       probe.questions);
+            assert_eq!(2, probe.authorities.len());
+            assert!(!responder.is_claimed(&name("printer.local.")));
+            assert!(responder.poll(now).is_empty());
+        }
+
+        let now = first + Duration::from_millis(PROBE_COUNT as u64 * PROBE_INTERVAL_MS);
+        for i in 0..ANNOUNCE_COUNT as u64 {
+            assert_eq!(Some(now + Duration::from_millis(i * ANNOUNCE_INTERVAL_MS)),
+                       responder.deadline());
+            let sent = responder.poll(now + Duration::from_millis(i * ANNOUNCE_INTERVAL_MS));
+            assert_eq!(1, sent.len());
+            let announcement = MdnsMessage::parse(&sent[0].0).unwrap();
+            assert!(announcement.is_response);
+            assert_eq!(2, announcement.answers.len());
+            assert!(announcement.answers.iter().all(|r| r.class == CLASS_IN | CACHE_FLUSH));
+            assert!(responder.is_claimed(&name("printer.local.")));
+        }
+        assert_eq!(None, responder.deadline());
+
+        // Shared records are announced straight away.
+        responder.publish(Record::new(name("_ipp._tcp.local."),
+                                      CLASS_IN,
+                                      4500,
+                                      RData::Ptr(name("printer._ipp._tcp.local."))),
+                          false,
+                          now);
+        let sent = responder.poll(now);
+        let announcement = MdnsMessage::parse(&sent[0].0).unwrap();
+        assert_eq!(CLASS_IN, announcement.answers[0].class);
+
+        let goodbye = MdnsMessage::parse(&responder.goodbye()).unwrap();
+        assert_eq!(3, goodbye.answers.len());
+        assert!(goodbye.answers.iter().all(|r| r.ttl == 0));
+    }
+
+    #[test]
+    fn conflicts() {
+        let start = Instant::now();
+        let mut responder = Responder::new();
+        responder.publish(address("printer.local.", "192.0.2.5"), true, start);
+        responder.publish(Record::new(name("_ipp._tcp.local."),
+                                      CLASS_IN,
+                                      4500,
+                                      RData::Ptr(name("printer.local."))),
+                          false,
+                          start);
+        let now = responder.deadline().unwrap();
+        responder.poll(now);
+
+        // Someone answers for the name while we probe: we take another.
+        let mut response = MdnsMessage::new(0, true);
+        response.answers.push(address("printer.local.", "192.0.2.9"));
+        responder.received(&response.to_wire(), group(), now);
+        let records = responder.records();
+        assert_eq!(name("printer-2.local."), records[0].name);
+        assert_eq!(RData::Ptr(name("printer-2.local.")), records[1].rdata);
+        let sent = responder.poll(now);
+        let probe = MdnsMessage::parse(&sent[0].0).unwrap();
+        assert_eq!(name("printer-2.local."), probe.questions[0].0);
+        // The shared record goes out again pointing at the new name.
+        let announcement = MdnsMessage::parse(&sent[1].0).unwrap();
+        assert_eq!(RData::Ptr(name("printer-2.local.")), announcement.answers[0].rdata);
+
+        response.answers[0] = address("printer-2.local.", "192.0.2.9");
+        responder.received(&response.to_wire(), group(), now);
+        assert_eq!(name("printer-3.local."), responder.records()[0].name);
+
+        // Our own records coming back are no conflict, nor are goodbyes.
+        let (mut responder, now) = printer(start);
+        let goodbye = responder.goodbye();
+        responder.received(&goodbye, group(), now);
+        response.answers[0] = address("printer.local.", "192.0.2.5");
+        responder.received(&response.to_wire(), group(), now);
+        assert!(responder.is_claimed(&name("printer.local.")));
+
+        // Once claimed, other data means probing again under the same name.
+        response.answers[0] = address("printer.local.", "192.0.2.9");
+        responder.received(&response.to_wire(), group(), now);
+        assert!(!responder.is_claimed(&name("printer.local.")));
+        assert_eq!(name("printer.local."), responder.records()[0].name);
+        assert_eq!(Some(now), responder.deadline());
+    }
+
+    #[test]
+    fn simultaneous_probes() {
+        let start = Instant::now();
+        let mut responder = Responder::new();
+        responder.publish(address("printer.local.", "192.0.2.5"), true, start);
+        let now = responder.deadline().unwrap();
+        responder.poll(now);
+        let next = responder.deadline();
+
+        let mut probe = query("printer.local.", TYPE_ANY, CLASS_ANY | UNICAST_RESPONSE);
+        probe.authorities.push(address("printer.local.", "192.0.2.1"));
+        responder.received(&probe.to_wire(), group(), now);
+        assert_eq!(next, responder.deadline());
+
+        // Theirs sorts later: we wait and try again.
+        probe.authorities[0] = address("printer.local.", "192.0.2.9");
+        responder.received(&probe.to_wire(), group(), now);
+        assert_eq!(Some(ms(now, PROBE_DEFER_MS)), responder.deadline());
+        assert_eq!(1, responder.poll(ms(now, PROBE_DEFER_MS)).len());
+        assert_eq!(name("printer.local."), responder.records()[0].name);
+    }
+
+    #[test]
+    fn answers() {
+        let start = Instant::now();
+        let (mut responder, now) = printer(start);
+
+        // Unique records go out at once, to the group.
+        let a = query("printer.local.", TYPE_A, CLASS_IN);
+        responder.received(&a.to_wire(), group(), now);
+        let sent = responder.poll(now);
+        assert_eq!(None, sent[0].1);
+        let response = MdnsMessage::parse(&sent[0].0).unwrap();
+        assert!(response.questions.is_empty());
+        assert_eq!(1, response.answers.len());
+        assert_eq!(RData::A("192.0.2.5".parse().unwrap()), response.answers[0].rdata);
+        assert_eq!(CLASS_IN | CACHE_FLUSH, response.answers[0].class);
+
+        // Nothing for what the querier already knows, unless it is stale.
+        let mut known = a.clone();
+        known.answers.push(address("printer.local.", "192.0.2.5"));
+        responder.received(&known.to_wire(), group(), now);
+        assert!(responder.poll(now).is_empty());
+        known.answers[0].ttl = 59;
+        responder.received(&known.to_wire(), group(), now);
+        assert_eq!(1, responder.poll(now).len());
+
+        // Unicast when asked.
+        let qu = query("printer.local.", TYPE_A, CLASS_IN | UNICAST_RESPONSE);
+        responder.received(&qu.to_wire(), group(), now);
+        assert_eq!(Some(group()), responder.poll(now)[0].1);
+
+        // Legacy resolvers get what they expect.
+        let legacy = SocketAddr::new("192.0.2.9".parse().unwrap(), 40000);
+        let mut plain = query("printer.local.", TYPE_A, CLASS_IN);
+        plain.id = 0x1234;
+        responder.received(&plain.to_wire(), legacy, now);
+        let sent = responder.poll(now);
+        assert_eq!(Some(legacy), sent[0].1);
+        let response = MdnsMessage::parse(&sent[0].0).unwrap();
+        assert_eq!(0x1234, response.id);
+        assert_eq!(plain.questions, response.questions);
+        assert_eq!(LEGACY_TTL, response.answers[0].ttl);
+        assert_eq!(CLASS_IN, response.answers[0].class);
+
+        // Shared records wait a little, and bring what they point to.
+        let ptr = query("_ipp._tcp.local.", TYPE_PTR, CLASS_IN);
+        responder.received(&ptr.to_wire(), group(), now);
+        assert!(responder.poll(now).is_empty());
+        assert!(responder.deadline().unwrap() >= ms(now, 20));
+        let sent = responder.poll(ms(now, 120));
+        let response = MdnsMessage::parse(&sent[0].0).unwrap();
+        assert_eq!(1, response.answers.len());
+        assert_eq!(2, response.additionals.len());
+        assert_eq!(name("printer._ipp._tcp.local."), response.additionals[0].name);
+        assert_eq!(name("printer.local."), response.additionals[1].name);
+
+        // Nothing while probing, or for names of others.
+        responder.received(&query("scanner.local.", TYPE_ANY, CLASS_IN).to_wire(), group(), now);
+        responder.publish(address("scanner.local.", "192.0.2.6"), true, now);
+        responder.received(&query("scanner.local.", TYPE_ANY, CLASS_IN).to_wire(), group(), now);
+        assert!(responder.queued.is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+//! A UDP socket on the link's multicast DNS group.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Instant;
+use socket2::{Domain, Protocol, Socket, Type};
+use super::MAX_MESSAGE;
+
+/// A socket that sends to the group, or to one address, and receives with
+/// a deadline.
+#[derive(Debug)]
+pub struct MdnsSocket {
+    socket: UdpSocket,
+    group: SocketAddr,
+}
+
+impl MdnsSocket {
+    /// A socket bound to `port` in `group`, usually `MDNS_IPV4` or
+    /// `MDNS_IPV6`, as a responder or querier needs, taking the group's
+    /// traffic on the default interface. What it sends comes back to it.
+    /// Other responders and queriers on the host may share the port (RFC
+    /// 6762 Section 15), and the IPv6 socket leaves IPv4 to the other.
+    pub fn join(group: IpAddr, port: u16) -> io::Result<MdnsSocket> {
+        let socket = match group {
+            IpAddr::V4(ref group) => {
+                let socket = try!(bind_shared(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port));
+                try!(socket.join_multicast_v4(group, &Ipv4Addr::new(0, 0, 0, 0)));
+                try!(socket.set_multicast_loop_v4(true));
+                // Packets from off the link have a TTL below this.
+                try!(socket.set_multicast_ttl_v4(255));
+                socket
+            }
+            IpAddr::V6(ref group) => {
+                let any = Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0);
+                let socket = try!(bind_shared(IpAddr::V6(any), port));
+                try!(socket.join_multicast_v6(group, 0));
+                try!(socket.set_multicast_loop_v6(true));
+                socket
+            }
+        };
+        Ok(MdnsSocket {
+            socket: socket,
+            group: SocketAddr::new(group, port),
+        })
+    }
+
+    /// A socket on a port of its own that sends to the group on `port`
+    /// without joining it, for one-shot queries: answers come back unicast.
+    pub fn one_shot(group: IpAddr, port: u16) -> io::Result<MdnsSocket> {
+        let local = match group {
+            IpAddr::V4(_) => "0.0.0.0:0",
+            IpAddr::V6(_) => "[::]:0",
+        };
+        let socket = try!(UdpSocket::bind(local));
+        Ok(MdnsSocket {
+            socket: socket,
+            group: SocketAddr::new(group, port),
+        })
+    }
+
+    /// The group and port messages go to.
+    pub fn group(&self) -> SocketAddr {
+        self.group
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Sends to `to`, or to the group if None.
+    pub fn send(&self, message: &[u8], to: Option<SocketAddr>) -> io::Result<()> {
+        self.socket.send_to(message, to.unwrap_or(self.group)).map(|_| ())
+    }
+
+    /// The next message and where it came from, or None if nothing comes
+    /// before `deadline`.
+    pub fn receive(&self, deadline: Instant) -> io::Result<Option<(Vec<u8>, SocketAddr)>> {
+        let mut buffer = vec![0u8; MAX_MESSAGE];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            try!(self.socket.set_read_timeout(Some(deadline - now)));
+            match self.socket.recv_from(&mut buffer) {
+                Ok((n, from)) => return Ok(Some((buffer[..n].to_vec(), from))),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                              e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+// A UDP socket bound to `port` on `any`, with the address and port reused
+// and, for IPv6, IPv6 only.
+fn bind_shared(any: IpAddr, port: u16) -> io::Result<UdpSocket> {
+    let domain = match any {
+        IpAddr::V4(_) => Domain::IPV4,
+        IpAddr::V6(_) => Domain::IPV6,
+    };
+    let socket = try!(Socket::new(domain, Type::DGRAM, Some(Protocol::UDP)));
+    try!(socket.set_reuse_address(true));
+    #[cfg(unix)]
+    {
+        try!(socket.set_reuse_port(true));
+    }
+    if any.is_ipv6() {
+        try!(socket.set_only_v6(true));
+    }
+    try!(socket.bind(&SocketAddr::new(any, port).into()));
+    Ok(socket.into())
+}
@@ -0,0 +1,386 @@
+//! Oblivious DNS over HTTPS (RFC 9230): queries sealed with HPKE for a
+//! target's public key and POSTed through a proxy, so the proxy learns who
+//! asks but not what, and the target what but not who.
+//!
+//! Targets publish their keys as ObliviousDoHConfigs at
+//! `/.well-known/odohconfigs`. A response is sealed under a key both ends
+//! export from the HPKE context of its query, with a fresh nonce from the
+//! target. Plaintexts are padded with zeros to a multiple of the block
+//! lengths of RFC 8467.
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use crypto::{AEAD_AES_128_GCM, Aead, AesGcm, HpkeContext, KDF_HKDF_SHA256, KEM_X25519_SHA256,
+             Sha256, X25519_BASE, hkdf_expand, hkdf_extract, random_bytes, x25519,
+             x25519_keypair};
+use tls::{TlsError, pem_decode};
+
+pub const ODOH_MESSAGE: &'static str = "application/oblivious-dns-message";
+pub const ODOH_CONFIGS_PATH: &'static str = "/.well-known/odohconfigs";
+pub const ODOH_VERSION: u16 = 0x0001;
+
+/// The message types.
+pub const ODOH_QUERY: u8 = 1;
+pub const ODOH_RESPONSE: u8 = 2;
+
+/// Plaintexts are padded to multiples of these.
+pub const QUERY_BLOCK: usize = 128;
+pub const RESPONSE_BLOCK: usize = 468;
+
+const KEY_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+// What a PKCS #8 X25519 private key has before the key itself.
+const PKCS8_X25519: &'static [u8] = &[0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03,
+                                      0x2b, 0x65, 0x6e, 0x04, 0x22, 0x04, 0x20];
+
+fn be16(data: &[u8]) -> usize {
+    (data[0] as usize) << 8 | data[1] as usize
+}
+
+fn push_vec16(wire: &mut Vec<u8>, data: &[u8]) {
+    wire.extend_from_slice(&[(data.len() >> 8) as u8, data.len() as u8]);
+    wire.extend_from_slice(data);
+}
+
+// The next field with a two octet length, and what follows it.
+fn read_vec16(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    if data.len() < 2 || data.len() < 2 + be16(data) {
+        return None;
+    }
+    let end = 2 + be16(data);
+    Some((&data[2..end], &data[end..]))
+}
+
+// ObliviousDoHMessagePlaintext: the message, then zeros up to `block`.
+fn pad(message: &[u8], block: usize) -> Vec<u8> {
+    let padded = (message.len() + 4 + block - 1) / block * block;
+    let mut plaintext = Vec::with_capacity(padded);
+    push_vec16(&mut plaintext, message);
+    push_vec16(&mut plaintext, &vec![0; padded - message.len() - 4]);
+    plaintext
+}
+
+// The message in a plaintext, or None if it is malformed or its padding is
+// not all zeros.
+fn unpad(plaintext: &[u8]) -> Option<Vec<u8>> {
+    match read_vec16(plaintext).and_then(|(message, rest)| {
+        read_vec16(rest).map(|(padding, rest)| (message, padding, rest))
+    }) {
+        Some((message, padding, rest)) if rest.is_empty() && padding.iter().all(|&b| b == 0) => {
+            Some(message.to_vec())
+        }
+        _ => None,
+    }
+}
+
+// ObliviousDoHMessage: the type, the key ID or response nonce, and the
+// sealed plaintext.
+fn odoh_message(message_type: u8, key_id: &[u8], encrypted: &[u8]) -> Vec<u8> {
+    let mut message = vec![message_type];
+    push_vec16(&mut message, key_id);
+    push_vec16(&mut message, encrypted);
+    message
+}
+
+fn parse_odoh_message(message_type: u8, message: &[u8]) -> Option<(&[u8], &[u8])> {
+    if message.first() != Some(&message_type) {
+        return None;
+    }
+    match read_vec16(&message[1..]).and_then(|(key_id, rest)| {
+        read_vec16(rest).map(|(encrypted, rest)| (key_id, encrypted, rest))
+    }) {
+        Some((key_id, encrypted, rest)) if rest.is_empty() => Some((key_id, encrypted)),
+        _ => None,
+    }
+}
+
+// The associated data a message is sealed with.
+fn aad(message_type: u8, key_id: &[u8]) -> Vec<u8> {
+    let mut aad = vec![message_type];
+    push_vec16(&mut aad, key_id);
+    aad
+}
+
+// The AEAD a response is sealed with, from the query's context and
+// plaintext and the response nonce (RFC 9230 Section 6.4).
+fn response_aead(context: &HpkeContext, query: &[u8], nonce: &[u8]) -> (AesGcm, Vec<u8>) {
+    let secret = context.export(b"odoh response", KEY_LEN);
+    let mut salt = query.to_vec();
+    push_vec16(&mut salt, nonce);
+    let prk = hkdf_extract::<Sha256>(&salt, &secret);
+    (AesGcm::new(&hkdf_expand::<Sha256>(&prk, b"odoh key", KEY_LEN)),
+     hkdf_expand::<Sha256>(&prk, b"odoh nonce", NONCE_LEN))
+}
+
+/// A target's public key, in the one suite supported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OdohConfig {
+    pub public_key: Vec<u8>,
+}
+
+impl OdohConfig {
+    pub fn new(public_key: &[u8]) -> OdohConfig {
+        OdohConfig { public_key: public_key.to_vec() }
+    }
+
+    // ObliviousDoHConfigContents.
+    fn contents(&self) -> Vec<u8> {
+        let mut contents = Vec::new();
+        for &id in &[KEM_X25519_SHA256, KDF_HKDF_SHA256, AEAD_AES_128_GCM] {
+            contents.extend_from_slice(&[(id >> 8) as u8, id as u8]);
+        }
+        push_vec16(&mut contents, &self.public_key);
+        contents
+    }
+
+    /// The ObliviousDoHConfig: the version, then the contents.
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut wire = vec![(ODOH_VERSION >> 8) as u8, ODOH_VERSION as u8];
+        push_vec16(&mut wire, &self.contents());
+        wire
+    }
+
+    /// What queries sealed for this key name it by.
+    pub fn key_id(&self) -> Vec<u8> {
+        let prk = hkdf_extract::<Sha256>(&[], &self.contents());
+        hkdf_expand::<Sha256>(&prk, b"odoh key id", 32)
+    }
+
+    /// Reads ObliviousDoHConfigs, skipping configs of other versions or
+    /// suites. None if the list is malformed.
+    pub fn parse_list(data: &[u8]) -> Option<Vec<OdohConfig>> {
+        let mut rest = match read_vec16(data) {
+            Some((list, rest)) if rest.is_empty() => list,
+            _ => return None,
+        };
+        let mut configs = Vec::new();
+        while !rest.is_empty() {
+            if rest.len() < 2 {
+                return None;
+            }
+            let (contents, next) = match read_vec16(&rest[2..]) {
+                Some(config) => config,
+                None => return None,
+            };
+            if be16(rest) == ODOH_VERSION as usize && contents.len() >= 6 {
+                let config = read_vec16(&contents[6..]).map(|(key, _)| OdohConfig::new(key));
+                match config {
+                    Some(config) if config.contents() == contents => configs.push(config),
+                    _ => {}
+                }
+            }
+            rest = next;
+        }
+        Some(configs)
+    }
+
+    pub fn list_to_wire(configs: &[OdohConfig]) -> Vec<u8> {
+        let mut list = Vec::new();
+        for config in configs {
+            list.extend(config.to_wire());
+        }
+        let mut wire = Vec::new();
+        push_vec16(&mut wire, &list);
+        wire
+    }
+
+    /// Seals a query for this key. Returns the message to send and what
+    /// opens its response, or None if the key is no good.
+    pub fn seal_query(&self, query: &[u8]) -> Option<(Vec<u8>, OdohQuery)> {
+        let (enc, mut context) = match HpkeContext::sender(&self.public_key, b"odoh query") {
+            Some(sender) => sender,
+            None => return None,
+        };
+        let key_id = self.key_id();
+        let plaintext = pad(query, QUERY_BLOCK);
+        let mut encrypted = enc;
+        encrypted.extend(context.seal(&aad(ODOH_QUERY, &key_id), &plaintext));
+        let query = OdohQuery {
+            context: context,
+            plaintext: plaintext,
+        };
+        Some((odoh_message(ODOH_QUERY, &key_id, &encrypted), query))
+    }
+}
+
+/// A query sent, kept to open its response with.
+pub struct OdohQuery {
+    context: HpkeContext,
+    plaintext: Vec<u8>,
+}
+
+// Keeps secrets out of logs.
+impl fmt::Debug for OdohQuery {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("OdohQuery").finish()
+    }
+}
+
+impl OdohQuery {
+    /// The DNS response in a response message, or None if it is not one
+    /// sealed for this query.
+    pub fn open_response(&self, response: &[u8]) -> Option<Vec<u8>> {
+        let (nonce, encrypted) = match parse_odoh_message(ODOH_RESPONSE, response) {
+            Some(parts) => parts,
+            None => return None,
+        };
+        let (aead, aead_nonce) = response_aead(&self.context, &self.plaintext, nonce);
+        aead.open(&aead_nonce, &aad(ODOH_RESPONSE, nonce), encrypted)
+            .and_then(|plaintext| unpad(&plaintext))
+    }
+}
+
+/// A target's private key.
+pub struct OdohKey {
+    secret: Vec<u8>,
+    config: OdohConfig,
+}
+
+impl fmt::Debug for OdohKey {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("OdohKey").field("config", &self.config).finish()
+    }
+}
+
+impl OdohKey {
+    pub fn new(secret: &[u8]) -> OdohKey {
+        OdohKey {
+            secret: secret.to_vec(),
+            config: OdohConfig::new(&x25519(secret, &X25519_BASE)),
+        }
+    }
+
+    pub fn generate() -> OdohKey {
+        let (secret, _) = x25519_keypair();
+        OdohKey::new(&secret)
+    }
+
+    /// Reads an X25519 key from a PKCS #8 PEM file, as `openssl genpkey
+    /// -algorithm X25519` makes them.
+    pub fn load(path: &Path) -> Result<OdohKey, TlsError> {
+        let mut text = String::new();
+        try!(File::open(path)
+                 .and_then(|mut file| file.read_to_string(&mut text))
+                 .map_err(|e| TlsError::File(format!("{}: {}", path.display(), e))));
+        let blocks = pem_decode(&text).unwrap_or(Vec::new());
+        match blocks.iter().find(|&&(ref label, _)| label == "PRIVATE KEY") {
+            Some(&(_, ref der)) if der.len() == 48 && der.starts_with(PKCS8_X25519) => {
+                Ok(OdohKey::new(&der[16..]))
+            }
+            _ => Err(TlsError::File(format!("{}: no X25519 private key", path.display()))),
+        }
+    }
+
+    pub fn config(&self) -> &OdohConfig {
+        &self.config
+    }
+
+    /// Opens a query message sealed for this key. Returns the DNS query and
+    /// what seals its response, or None if it is malformed, for another
+    /// key, or tampered with.
+    pub fn open_query(&self, message: &[u8]) -> Option<(Vec<u8>, OdohSession)> {
+        let (key_id, encrypted) = match parse_odoh_message(ODOH_QUERY, message) {
+            Some((key_id, encrypted)) if encrypted.len() > 32 => (key_id, encrypted),
+            _ => return None,
+        };
+        if key_id != &self.config.key_id()[..] {
+            return None;
+        }
+        let mut context = match HpkeContext::recipient(&encrypted[..32],
+                                                       &self.secret,
+                                                       b"odoh query") {
+            Some(context) => context,
+            None => return None,
+        };
+        let plaintext = match context.open(&aad(ODOH_QUERY, key_id), &encrypted[32..]) {
+            Some(plaintext) => plaintext,
+            None => return None,
+        };
+        unpad(&plaintext).map(|query| {
+            let session = OdohSession {
+                context: context,
+                plaintext: plaintext,
+            };
+            (query, session)
+        })
+    }
+}
+
+/// What a response to one query is sealed with.
+pub struct OdohSession {
+    context: HpkeContext,
+    plaintext: Vec<u8>,
+}
+
+impl fmt::Debug for OdohSession {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("OdohSession").finish()
+    }
+}
+
+impl OdohSession {
+    pub fn seal(&self, response: &[u8]) -> Vec<u8> {
+        let nonce = random_bytes(KEY_LEN);
+        let (aead, aead_nonce) = response_aead(&self.context, &self.plaintext, &nonce);
+        let encrypted = aead.seal(&aead_nonce,
+                                  &aad(ODOH_RESPONSE, &nonce),
+                                  &pad(response, RESPONSE_BLOCK));
+        odoh_message(ODOH_RESPONSE, &nonce, &encrypted)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn configs() {
+        let key = OdohKey::new(&[3; 32]);
+        let wire = OdohConfig::list_to_wire(&[key.config().clone()]);
+        assert_eq!(&[0, 44, 0, 1, 0, 40, 0, 0x20, 0, 1, 0, 1, 0, 32], &wire[..14]);
+        assert_eq!(Some(vec![key.config().clone()]), OdohConfig::parse_list(&wire));
+
+        // Other versions and suites are skipped.
+        let config = key.config().to_wire();
+        let mut version = config.clone();
+        version[1] = 2;
+        let mut suite = config.clone();
+        suite[5] = 0x21;
+        let mut list = vec![0, 3 * 44];
+        list.extend(version);
+        list.extend(suite);
+        list.extend(config);
+        assert_eq!(Some(vec![key.config().clone()]), OdohConfig::parse_list(&list));
+        assert_eq!(None, OdohConfig::parse_list(&wire[..20]));
+
+        let loaded = OdohKey::load(Path::new("testdata/odoh.key")).unwrap();
+        assert_eq!(32, loaded.config().key_id().len());
+        assert!(OdohKey::load(Path::new("testdata/tls-ed25519.key")).is_err());
+    }
+
+    #[test]
+    fn query_and_response() {
+        let key = OdohKey::generate();
+        let (message, query) = key.config().seal_query(b"query").unwrap();
+        assert_eq!(1 + 2 + 32 + 2 + 32 + QUERY_BLOCK + 16, message.len());
+        let (opened, session) = key.open_query(&message).unwrap();
+        assert_eq!(b"query".to_vec(), opened);
+
+        let response = session.seal(b"response");
+        assert_eq!(1 + 2 + 16 + 2 + RESPONSE_BLOCK + 16, response.len());
+        assert_eq!(Some(b"response".to_vec()), query.open_response(&response));
+        let (_, other) = key.config().seal_query(b"query").unwrap();
+        assert_eq!(None, other.open_response(&response));
+        assert_eq!(None, query.open_response(&message));
+
+        let mut forged = message.clone();
+        forged[80] ^= 1;
+        assert!(key.open_query(&forged).is_none());
+        assert!(OdohKey::generate().open_query(&message).is_none());
+    }
+}
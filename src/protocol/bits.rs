@@ -179,12 +179,43 @@ impl BEU32Field {
     }
 }
 
+/// A 48 bit big-endian integer, such as the TSIG time signed (RFC 8945
+/// Section 4.2).
+pub struct BEU48Field {
+    pub index: usize,
+}
+
+impl BEU48Field {
+    #[inline]
+    pub fn get<T: BitData + ?Sized>(&self, data: &T) -> Option<u64> {
+        if let Some(split) = data.get_range(self.index..self.index + 6) {
+            let mut value = 0u64;
+            for i in 0..6 {
+                value = (value << 8) | split[i] as u64;
+            }
+            return Some(value);
+        }
+        None
+    }
+
+    /// Stores the low 48 bits of `value`.
+    #[inline]
+    pub fn set<T: BitDataMut + ?Sized>(&self, data: &mut T, value: u64) {
+        if let Some(split) = data.get_mut_range(self.index..self.index + 6) {
+            for i in 0..6 {
+                split[i] = (value >> (40 - 8 * i)) as u8;
+            }
+        }
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::BitField;
     use super::BEU16Field;
     use super::BEU32Field;
+    use super::BEU48Field;
 
     #[test]
     fn u8_extract0() {
@@ -281,6 +312,15 @@ mod tests {
         assert_eq!(Some(0xabcdef01), field.get(view));
     }
 
+    #[test]
+    fn u48_extract() {
+        let data = [0x00, 0x00, 0x01, 0x23, 0x45, 0x67, 0x89];
+        let view: &[u8] = &data[..];
+        let field = BEU48Field { index: 1 };
+        assert_eq!(Some(0x000123456789), field.get(view));
+        assert_eq!(None, BEU48Field { index: 2 }.get(view));
+    }
+
     #[test]
     fn u32_invalid_address() {
         let data = [0xab, 0xcd];
@@ -423,6 +463,18 @@ mod tests {
         assert_eq!(0x01, view[4]);
     }
 
+    #[test]
+    fn u48_write() {
+        let mut data = [0x11, 0, 0, 0, 0, 0, 0];
+        let view: &mut [u8] = &mut data[..];
+        let field = BEU48Field { index: 1 };
+        field.set(view, 0xffff_ab00_0000_cdef);
+        assert_eq!([0x11, 0xab, 0x00, 0x00, 0x00, 0xcd, 0xef], *view);
+        // Out of range writes are ignored.
+        BEU48Field { index: 2 }.set(view, 0);
+        assert_eq!(0xab, view[1]);
+    }
+
     #[test]
     fn u32_write_invalid_address() {
         let mut data = [0x11, 0x22];
@@ -0,0 +1,486 @@
+//! JSON forms of DNS messages: the representation of RFC 8427, and the
+//! smaller shape served as `application/dns-json` by the common resolver
+//! APIs.
+
+use super::encoding::{hex_decode, hex_encode};
+use super::header::{Header, HeaderMut};
+use super::json::Json;
+use super::message::MessageCursor;
+use super::name::Name;
+use super::question::QuestionMut;
+use super::rdata::{CLASS_IN, RData, TYPE_OPT, class_from_name, class_name, type_from_name,
+                   type_name};
+use super::record::Record;
+use super::resource::Resource;
+
+/// The largest message composed from JSON.
+const MAX_MESSAGE: usize = 65535;
+
+fn number(value: u64) -> Json {
+    Json::Number(value as f64)
+}
+
+fn flag(value: bool) -> Json {
+    number(value as u64)
+}
+
+fn members(members: Vec<(&str, Json)>) -> Json {
+    Json::Object(members.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+/// The RFC 8427 object for one resource record.
+fn resource_json(message: &[u8], resource: &Resource) -> Option<Json> {
+    let name = match resource.name().and_then(|n| Name::from_domain_name(message, n)) {
+        Some(name) => name,
+        None => return None,
+    };
+    let (rtype, class) = match (resource.rtype(), resource.rclass()) {
+        (Some(rtype), Some(class)) => (rtype, class),
+        _ => return None,
+    };
+    let payload = match resource.payload(message) {
+        Some(payload) => payload,
+        None => return None,
+    };
+    let mut object = vec![("NAME", Json::String(name.to_string())),
+                          ("TYPE", number(rtype as u64)),
+                          ("TYPEname", Json::String(type_name(rtype))),
+                          ("CLASS", number(class as u64)),
+                          ("CLASSname", Json::String(class_name(class))),
+                          ("TTL", number(resource.ttl().unwrap_or(0) as u64)),
+                          ("RDLENGTH", number(payload.len() as u64)),
+                          ("RDATAHEX", Json::String(hex_encode(payload).to_uppercase()))];
+    let key = format!("rdata{}", type_name(rtype));
+    match Record::from_resource(message, resource) {
+        Some(Record { rdata: RData::Unknown { .. }, .. }) | None => {}
+        Some(record) => object.push((&key, Json::String(record.rdata.to_string()))),
+    }
+    Some(members(object))
+}
+
+/// The RFC 8427 representation of a message, or None if it cannot be read.
+pub fn message_to_json(message: &[u8]) -> Option<Json> {
+    let header = Header::at(message);
+    if header.additionals().end_offset().is_none() {
+        return None;
+    }
+    let mut object = vec![("ID", number(header.id().unwrap_or(0) as u64)),
+                          ("QR", flag(header.is_response())),
+                          ("Opcode", number(header.op().unwrap_or(0) as u64)),
+                          ("AA", flag(header.aa() == Some(true))),
+                          ("TC", flag(header.is_truncated())),
+                          ("RD", flag(header.rd() == Some(true))),
+                          ("RA", flag(header.ra() == Some(true))),
+                          ("AD", flag(header.ad() == Some(true))),
+                          ("CD", flag(header.cd() == Some(true))),
+                          ("RCODE", number(header.rc().unwrap_or(0) as u64)),
+                          ("QDCOUNT", number(header.qd().unwrap_or(0) as u64)),
+                          ("ANCOUNT", number(header.an().unwrap_or(0) as u64)),
+                          ("NSCOUNT", number(header.ns().unwrap_or(0) as u64)),
+                          ("ARCOUNT", number(header.ar().unwrap_or(0) as u64))];
+    let mut questions = Vec::new();
+    for question in header.questions() {
+        let name = match question.name().and_then(|n| Name::from_domain_name(message, n)) {
+            Some(name) => name,
+            None => return None,
+        };
+        let qtype = question.qtype().unwrap_or(0);
+        let qclass = question.qclass().unwrap_or(0);
+        questions.push(vec![("NAME", Json::String(name.to_string())),
+                            ("TYPE", number(qtype as u64)),
+                            ("TYPEname", Json::String(type_name(qtype))),
+                            ("CLASS", number(qclass as u64)),
+                            ("CLASSname", Json::String(class_name(qclass)))]);
+    }
+    // The first question goes in the message object itself; a list is
+    // added only when there are more.
+    if let Some(first) = questions.first() {
+        let names = ["QNAME", "QTYPE", "QTYPEname", "QCLASS", "QCLASSname"];
+        for (&name, &(_, ref value)) in names.iter().zip(first.iter()) {
+            object.push((name, value.clone()));
+        }
+    }
+    if questions.len() > 1 {
+        object.push(("questionRRs",
+                     Json::Array(questions.into_iter().map(members).collect())));
+    }
+    let sections = vec![("answerRRs", header.answers()),
+                        ("authorityRRs", header.nameservers()),
+                        ("additionalRRs", header.additionals())];
+    for (key, section) in sections {
+        let mut records = Vec::new();
+        for resource in section {
+            match resource_json(message, &resource) {
+                Some(record) => records.push(record),
+                None => return None,
+            }
+        }
+        if !records.is_empty() {
+            object.push((key, Json::Array(records)));
+        }
+    }
+    Some(members(object))
+}
+
+// A header field given as a number or, for the flags, a boolean.
+fn field(json: &Json, key: &str, max: u64) -> Result<u64, String> {
+    match json.get(key) {
+        None => Ok(0),
+        Some(&Json::Bool(value)) if max == 1 => Ok(value as u64),
+        Some(value) => value.as_u64(max).ok_or(format!("invalid {}", key)),
+    }
+}
+
+fn name_field(json: &Json, key: &str) -> Result<Name, String> {
+    match json.get(key).and_then(|name| name.as_str()).and_then(|name| Name::parse(name, None)) {
+        Some(name) => Ok(name),
+        None => Err(format!("invalid {}", key)),
+    }
+}
+
+// A type or class given by number, or by mnemonic under `key` + "name".
+fn code_field(json: &Json,
+              key: &str,
+              from_name: fn(&str) -> Option<u16>,
+              default: Option<u16>)
+    -> Result<u16, String> {
+    if let Some(value) = json.get(key) {
+        return value.as_u64(0xffff).map(|v| v as u16).ok_or(format!("invalid {}", key));
+    }
+    match json.get(&format!("{}name", key)).and_then(|name| name.as_str()) {
+        Some(name) => from_name(name).ok_or(format!("invalid {}name", key)),
+        None => default.ok_or(format!("missing {}", key)),
+    }
+}
+
+/// Splits presentation rdata into fields, removing quotes but leaving
+/// escapes for `RData::parse`.
+fn rdata_fields(text: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                if quoted {
+                    fields.push(current.take().unwrap_or(String::new()));
+                } else if let Some(field) = current.take() {
+                    fields.push(field);
+                }
+                quoted = !quoted;
+                if quoted {
+                    current = Some(String::new());
+                }
+            }
+            ' ' | '\t' if !quoted => {
+                if let Some(field) = current.take() {
+                    fields.push(field);
+                }
+            }
+            _ => {
+                let field = current.get_or_insert(String::new());
+                field.push(c);
+                if c == '\\' {
+                    if let Some(escaped) = chars.next() {
+                        field.push(escaped);
+                    }
+                }
+            }
+        }
+    }
+    if let Some(field) = current {
+        fields.push(field);
+    }
+    fields
+}
+
+fn parse_rdata(rtype: u16, text: &str) -> Result<RData, String> {
+    let fields = rdata_fields(text);
+    let fields: Vec<&str> = fields.iter().map(|f| &f[..]).collect();
+    RData::parse(rtype, &fields, None)
+}
+
+/// Reads one resource record object of RFC 8427.
+fn record_from_json(json: &Json) -> Result<Record, String> {
+    let name = try!(name_field(json, "NAME"));
+    let rtype = try!(code_field(json, "TYPE", type_from_name, None));
+    let class = try!(code_field(json, "CLASS", class_from_name, Some(CLASS_IN)));
+    let ttl = try!(field(json, "TTL", 0xffffffff)) as u32;
+    let rdata = if let Some(hex) = json.get("RDATAHEX") {
+        let data = match hex.as_str().and_then(hex_decode) {
+            Some(data) => data,
+            None => return Err("invalid RDATAHEX".to_string()),
+        };
+        match RData::from_message(rtype, &data, 0, data.len()) {
+            Some(rdata) => rdata,
+            None => return Err("invalid RDATAHEX".to_string()),
+        }
+    } else {
+        let key = format!("rdata{}", type_name(rtype));
+        match json.get(&key).and_then(|text| text.as_str()) {
+            Some(text) => try!(parse_rdata(rtype, text)),
+            None => return Err(format!("missing RDATAHEX or {}", key)),
+        }
+    };
+    Ok(Record::new(name, class, ttl, rdata))
+}
+
+// The members of `json` under `key`, which must be an array if present.
+fn list<'j>(json: &'j Json, key: &str) -> Result<&'j [Json], String> {
+    match json.get(key) {
+        None => Ok(&[]),
+        Some(value) => value.as_array().ok_or(format!("{} is not an array", key)),
+    }
+}
+
+/// The parts of a message as read from either JSON form.
+struct Parts {
+    id: u16,
+    qr: bool,
+    opcode: u8,
+    flags: [bool; 6],
+    rcode: u8,
+    questions: Vec<(Name, u16, u16)>,
+    sections: [Vec<Record>; 3],
+}
+
+fn compose(parts: &Parts) -> Result<Vec<u8>, String> {
+    let too_long = || "message too long".to_string();
+    let mut message = vec![0; MAX_MESSAGE];
+    let mut idx = MessageCursor::new(message.len());
+    {
+        let mut header = try!(HeaderMut::at(&mut idx, &mut message[..]).ok_or_else(too_long));
+        let [aa, tc, rd, ra, ad, cd] = parts.flags;
+        header.set_id(parts.id)
+            .set_qr(parts.qr)
+            .set_op(parts.opcode)
+            .set_aa(aa)
+            .set_tc(tc)
+            .set_rd(rd)
+            .set_ra(ra)
+            .set_ad(ad)
+            .set_cd(cd)
+            .set_rc(parts.rcode)
+            .set_qd(parts.questions.len() as u16)
+            .set_an(parts.sections[0].len() as u16)
+            .set_ns(parts.sections[1].len() as u16)
+            .set_ar(parts.sections[2].len() as u16);
+    }
+    for &(ref name, qtype, qclass) in &parts.questions {
+        try!(QuestionMut::at(&mut idx, &mut message, &name.segments(), qtype, qclass)
+            .ok_or_else(too_long));
+    }
+    for record in parts.sections.iter().flat_map(|section| section.iter()) {
+        try!(record.write_at(&mut idx, &mut message).ok_or_else(too_long));
+    }
+    message.truncate(idx.tell());
+    Ok(message)
+}
+
+/// Composes a message from its RFC 8427 representation. Counts are taken
+/// from the sections given rather than from the count members.
+pub fn message_from_json(json: &Json) -> Result<Vec<u8>, String> {
+    let mut questions = Vec::new();
+    if json.get("QNAME").is_some() {
+        questions.push((try!(name_field(json, "QNAME")),
+                        try!(code_field(json, "QTYPE", type_from_name, None)),
+                        try!(code_field(json, "QCLASS", class_from_name, Some(CLASS_IN)))));
+    }
+    for (i, question) in try!(list(json, "questionRRs")).iter().enumerate() {
+        let question = (try!(name_field(question, "NAME")),
+                        try!(code_field(question, "TYPE", type_from_name, None)),
+                        try!(code_field(question, "CLASS", class_from_name, Some(CLASS_IN))));
+        // The list repeats the first question given by QNAME.
+        if i > 0 || questions.is_empty() {
+            questions.push(question);
+        }
+    }
+    let mut sections = [Vec::new(), Vec::new(), Vec::new()];
+    for (i, &key) in ["answerRRs", "authorityRRs", "additionalRRs"].iter().enumerate() {
+        for record in try!(list(json, key)) {
+            sections[i].push(try!(record_from_json(record)));
+        }
+    }
+    let mut flags = [false; 6];
+    for (i, &key) in ["AA", "TC", "RD", "RA", "AD", "CD"].iter().enumerate() {
+        flags[i] = try!(field(json, key, 1)) == 1;
+    }
+    compose(&Parts {
+        id: try!(field(json, "ID", 0xffff)) as u16,
+        qr: try!(field(json, "QR", 1)) == 1,
+        opcode: try!(field(json, "Opcode", 15)) as u8,
+        flags: flags,
+        rcode: try!(field(json, "RCODE", 15)) as u8,
+        questions: questions,
+        sections: sections,
+    })
+}
+
+/// The `application/dns-json` form of a response: the status, flags,
+/// questions and records with their rdata in presentation format. OPT
+/// records are left out, as are empty sections.
+pub fn message_to_dns_json(message: &[u8]) -> Option<Json> {
+    let header = Header::at(message);
+    if header.additionals().end_offset().is_none() {
+        return None;
+    }
+    let mut object = vec![("Status", number(header.rc().unwrap_or(0) as u64)),
+                          ("TC", Json::Bool(header.is_truncated())),
+                          ("RD", Json::Bool(header.rd() == Some(true))),
+                          ("RA", Json::Bool(header.ra() == Some(true))),
+                          ("AD", Json::Bool(header.ad() == Some(true))),
+                          ("CD", Json::Bool(header.cd() == Some(true)))];
+    let mut questions = Vec::new();
+    for question in header.questions() {
+        let name = match question.name().and_then(|n| Name::from_domain_name(message, n)) {
+            Some(name) => name,
+            None => return None,
+        };
+        questions.push(members(vec![("name", Json::String(name.to_string())),
+                                    ("type", number(question.qtype().unwrap_or(0) as u64))]));
+    }
+    object.push(("Question", Json::Array(questions)));
+    let sections = vec![("Answer", header.answers()),
+                        ("Authority", header.nameservers()),
+                        ("Additional", header.additionals())];
+    for (key, section) in sections {
+        let mut records = Vec::new();
+        for resource in section {
+            if resource.rtype() == Some(TYPE_OPT) {
+                continue;
+            }
+            let record = match Record::from_resource(message, &resource) {
+                Some(record) => record,
+                None => return None,
+            };
+            records.push(members(vec![("name", Json::String(record.name.to_string())),
+                                      ("type", number(record.rtype() as u64)),
+                                      ("TTL", number(record.ttl as u64)),
+                                      ("data", Json::String(record.rdata.to_string()))]));
+        }
+        if !records.is_empty() {
+            object.push((key, Json::Array(records)));
+        }
+    }
+    Some(members(object))
+}
+
+/// Composes a response from the `application/dns-json` form. The class of
+/// every question and record is taken to be IN.
+pub fn message_from_dns_json(json: &Json) -> Result<Vec<u8>, String> {
+    let mut questions = Vec::new();
+    for question in try!(list(json, "Question")) {
+        questions.push((try!(name_field(question, "name")),
+                        try!(field(question, "type", 0xffff)) as u16,
+                        CLASS_IN));
+    }
+    let mut sections = [Vec::new(), Vec::new(), Vec::new()];
+    for (i, &key) in ["Answer", "Authority", "Additional"].iter().enumerate() {
+        for record in try!(list(json, key)) {
+            let rtype = try!(field(record, "type", 0xffff)) as u16;
+            let data = match record.get("data").and_then(|data| data.as_str()) {
+                Some(data) => data,
+                None => return Err("missing data".to_string()),
+            };
+            sections[i].push(Record::new(try!(name_field(record, "name")),
+                                         CLASS_IN,
+                                         try!(field(record, "TTL", 0xffffffff)) as u32,
+                                         try!(parse_rdata(rtype, data))));
+        }
+    }
+    let mut flags = [false; 6];
+    for (i, &key) in ["AA", "TC", "RD", "RA", "AD", "CD"].iter().enumerate() {
+        flags[i] = try!(field(json, key, 1)) == 1;
+    }
+    compose(&Parts {
+        id: 0,
+        qr: true,
+        opcode: 0,
+        flags: flags,
+        rcode: try!(field(json, "Status", 15)) as u8,
+        questions: questions,
+        sections: sections,
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::{Header, Json};
+
+    fn response() -> Vec<u8> {
+        let text = r#"{"ID": 4660, "QR": 1, "Opcode": 0, "AA": true, "RD": 1,
+                       "QNAME": "example.com.", "QTYPEname": "MX",
+                       "answerRRs": [{"NAME": "example.com.", "TYPE": 15, "TTL": 300,
+                                      "rdataMX": "10 mail.example.com."},
+                                     {"NAME": "example.com.", "TYPEname": "TXT", "TTL": 300,
+                                      "rdataTXT": "\"v=spf1 -all\" \"a\\\"b\""}],
+                       "additionalRRs": [{"NAME": "mail.example.com.", "TYPE": 1,
+                                          "CLASS": 1, "TTL": 60, "RDATAHEX": "C0000201"},
+                                         {"NAME": ".", "TYPE": 41, "CLASS": 1232,
+                                          "RDATAHEX": ""}]}"#;
+        message_from_json(&Json::parse(text).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn rfc8427() {
+        let message = response();
+        let header = Header::at(&message[..]);
+        assert_eq!((Some(4660), true, Some(true), Some(1), Some(2), Some(2)),
+                   (header.id(), header.is_response(), header.aa(), header.qd(), header.an(),
+                    header.ar()));
+
+        let json = message_to_json(&message).unwrap();
+        assert_eq!(Some("MX"), json.get("QTYPEname").and_then(|t| t.as_str()));
+        assert_eq!(Some(1), json.get("AA").and_then(|f| f.as_u64(1)));
+        assert_eq!(None, json.get("authorityRRs"));
+        let answers = json.get("answerRRs").and_then(|a| a.as_array()).unwrap();
+        assert_eq!(Some("10 mail.example.com."),
+                   answers[0].get("rdataMX").and_then(|r| r.as_str()));
+        let additionals = json.get("additionalRRs").and_then(|a| a.as_array()).unwrap();
+        assert_eq!(Some("C0000201"), additionals[0].get("RDATAHEX").and_then(|r| r.as_str()));
+        assert_eq!(Some("192.0.2.1"), additionals[0].get("rdataA").and_then(|r| r.as_str()));
+        assert_eq!((Some("OPT"), None),
+                   (additionals[1].get("TYPEname").and_then(|t| t.as_str()),
+                    additionals[1].get("rdataOPT")));
+
+        // Back through text to the same message.
+        let text = json.to_string();
+        assert_eq!(Ok(message), message_from_json(&Json::parse(&text).unwrap()));
+    }
+
+    #[test]
+    fn dns_json() {
+        let message = response();
+        let json = message_to_dns_json(&message).unwrap();
+        assert_eq!("{\"Status\":0,\"TC\":false,\"RD\":true,\"RA\":false,\"AD\":false,\
+                    \"CD\":false,\"Question\":[{\"name\":\"example.com.\",\"type\":15}],\
+                    \"Answer\":[{\"name\":\"example.com.\",\"type\":15,\"TTL\":300,\
+                    \"data\":\"10 mail.example.com.\"},{\"name\":\"example.com.\",\
+                    \"type\":16,\"TTL\":300,\"data\":\"\\\"v=spf1 -all\\\" \
+                    \\\"a\\\\\\\"b\\\"\"}],\"Additional\":[{\
+                    \"name\":\"mail.example.com.\",\"type\":1,\"TTL\":60,\
+                    \"data\":\"192.0.2.1\"}]}",
+                   json.to_string());
+
+        let parsed = message_from_dns_json(&json).unwrap();
+        assert_eq!(Some(json), message_to_dns_json(&parsed));
+    }
+
+    #[test]
+    fn invalid() {
+        let cases = [r#"{"QNAME": "example.com."}"#,
+                     r#"{"ID": 65536}"#,
+                     r#"{"answerRRs": {}}"#,
+                     r#"{"answerRRs": [{"NAME": "a.", "TYPE": 1, "RDATAHEX": "C000"}]}"#,
+                     r#"{"answerRRs": [{"NAME": "a.", "TYPE": 1, "rdataA": "bad"}]}"#,
+                     r#"{"answerRRs": [{"NAME": "a.", "TYPE": 1}]}"#];
+        for text in cases.iter() {
+            assert!(message_from_json(&Json::parse(text).unwrap()).is_err(), "{}", text);
+        }
+        assert_eq!(None, message_to_json(&response()[..40]));
+        assert_eq!(None, message_to_dns_json(&[0, 0, 0, 0, 0, 1]));
+    }
+}
@@ -55,7 +55,9 @@ impl DomainName {
         None
     }
 
-    fn segments<'d, D: 'd + ?Sized + BitData>(&self,
+    /// Returns the labels of this name, following compression pointers. The
+    /// last segment is the empty root label.
+    pub fn segments<'d, D: 'd + ?Sized + BitData>(&self,
                                               message: &'d D)
         -> Option<Vec<&'d <D as BitData>::Slice>> {
         // Allow at most 63 pointers. RFC: unbounded, but more pointers than segments
@@ -162,6 +164,7 @@ impl DomainName {
         };
 
         let start = idx.tell();
+        let mut compressed = false;
         for i in 0..name_len {
             let suffix = &name[i..name_len];
             match idx.lookup_name_suffix(data, suffix) {
@@ -170,6 +173,7 @@ impl DomainName {
                     if let Some(ptr_idx) = idx.alloc(2) {
                         offset |= (POINTER_TAG as u16) << 8;
                         BEU16Field { index: ptr_idx.start }.set(data, offset);
+                        compressed = true;
                         break;
                     } else {
                         // No more space in the buffer.
@@ -197,13 +201,15 @@ impl DomainName {
                 }
             }
         }
-        // Append a root segment
-        if let Some(segment_idx) = idx.alloc(1) {
-            if let Some(ref mut segment) = data.get_mut_range(segment_idx) {
-                segment[0] = 0;
+        // Append a root segment, unless a pointer already ended the name.
+        if !compressed {
+            if let Some(segment_idx) = idx.alloc(1) {
+                if let Some(ref mut segment) = data.get_mut_range(segment_idx) {
+                    segment[0] = 0;
+                }
+            } else {
+                return None;
             }
-        } else {
-            return None;
         }
         return DomainName::from_message(data, start);
     }
@@ -385,7 +391,21 @@ mod test {
         assert_eq!(&vec![0u8, 2, 1, 2, 1, 3, 0, 0], buffer);
     }
 
-    // TODO: test write_at with compression, once MessageCursor supports it.
+    #[test]
+    fn write_at_compressed() {
+        let buffer = &mut repeat(0u8).take(16).collect::<Vec<u8>>();
+        let idx = &mut MessageCursor::new(buffer.len());
+        DomainName::write_at(idx, buffer, &[&b"a"[..], &b"bc"[..]][..]).unwrap();
+        let second = DomainName::write_at(idx, buffer, &[&b"x"[..], &b"a"[..], &b"bc"[..]][..])
+                         .unwrap();
+        assert_eq!(&vec![1u8, b'a', 2, b'b', b'c', 0, 1, b'x', 0xc0, 0, 0, 0, 0, 0, 0, 0],
+                   buffer);
+        assert_eq!(10, second.end_offset());
+        let v = second.segments(&buffer[..]).unwrap();
+        assert_eq!(4, v.len());
+        assert_eq!(&[b'x'], v[0]);
+        assert_eq!(&[b'b', b'c'], v[2]);
+    }
 
 
     #[test]
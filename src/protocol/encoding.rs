@@ -0,0 +1,191 @@
+//! Text encodings used by the presentation format of resource data.
+
+const HEX_DIGITS: &'static [u8] = b"0123456789abcdef";
+const BASE64_ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes as lowercase hexadecimal.
+pub fn hex_encode(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len() * 2);
+    for byte in data {
+        result.push(HEX_DIGITS[(byte >> 4) as usize] as char);
+        result.push(HEX_DIGITS[(byte & 0xf) as usize] as char);
+    }
+    result
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'...b'9' => Some(digit - b'0'),
+        b'a'...b'f' => Some(digit - b'a' + 10),
+        b'A'...b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decodes hexadecimal of either case. Returns None on odd length or bad digits.
+pub fn hex_decode(text: &str) -> Option<Vec<u8>> {
+    let digits = text.as_bytes();
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    let mut result = Vec::with_capacity(digits.len() / 2);
+    for pair in digits.chunks(2) {
+        match (hex_value(pair[0]), hex_value(pair[1])) {
+            (Some(high), Some(low)) => result.push((high << 4) | low),
+            _ => return None,
+        }
+    }
+    Some(result)
+}
+
+/// Encodes bytes as padded base64 (RFC 4648 Section 4).
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut result = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = if chunk.len() > 1 { chunk[1] as u32 } else { 0 };
+        let b2 = if chunk.len() > 2 { chunk[2] as u32 } else { 0 };
+        let triple = (b0 << 16) | (b1 << 8) | b2;
+        result.push(BASE64_ALPHABET[((triple >> 18) & 0x3f) as usize] as char);
+        result.push(BASE64_ALPHABET[((triple >> 12) & 0x3f) as usize] as char);
+        if chunk.len() > 1 {
+            result.push(BASE64_ALPHABET[((triple >> 6) & 0x3f) as usize] as char);
+        } else {
+            result.push('=');
+        }
+        if chunk.len() > 2 {
+            result.push(BASE64_ALPHABET[(triple & 0x3f) as usize] as char);
+        } else {
+            result.push('=');
+        }
+    }
+    result
+}
+
+fn base64_value(digit: u8) -> Option<u32> {
+    match digit {
+        b'A'...b'Z' => Some((digit - b'A') as u32),
+        b'a'...b'z' => Some((digit - b'a') as u32 + 26),
+        b'0'...b'9' => Some((digit - b'0') as u32 + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// Decodes padded base64. Whitespace is not permitted; callers join tokens first.
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let digits = text.as_bytes();
+    if digits.len() % 4 != 0 {
+        return None;
+    }
+    let mut result = Vec::with_capacity(digits.len() / 4 * 3);
+    for (n, quad) in digits.chunks(4).enumerate() {
+        let last = n + 1 == digits.len() / 4;
+        let padding = quad.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut triple = 0u32;
+        for &digit in &quad[..4 - padding] {
+            match base64_value(digit) {
+                Some(value) => triple = (triple << 6) | value,
+                None => return None,
+            }
+        }
+        triple <<= 6 * padding as u32;
+        result.push((triple >> 16) as u8);
+        if padding < 2 {
+            result.push((triple >> 8) as u8);
+        }
+        if padding < 1 {
+            result.push(triple as u8);
+        }
+    }
+    Some(result)
+}
+
+/// Encodes bytes as unpadded base64url (RFC 4648 Section 5), as URLs carry
+/// it.
+pub fn base64url_encode(data: &[u8]) -> String {
+    base64_encode(data)
+        .trim_right_matches('=')
+        .chars()
+        .map(|c| {
+            match c {
+                '+' => '-',
+                '/' => '_',
+                c => c,
+            }
+        })
+        .collect()
+}
+
+/// Decodes base64url, padded or not.
+pub fn base64url_decode(text: &str) -> Option<Vec<u8>> {
+    if text.contains('+') || text.contains('/') {
+        return None;
+    }
+    let mut padded: String = text.chars()
+                                 .map(|c| {
+                                     match c {
+                                         '-' => '+',
+                                         '_' => '/',
+                                         c => c,
+                                     }
+                                 })
+                                 .collect();
+    if !padded.ends_with('=') {
+        while padded.len() % 4 != 0 {
+            padded.push('=');
+        }
+    }
+    base64_decode(&padded)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hex_round_trip() {
+        assert_eq!("00ff7a", hex_encode(&[0x00, 0xff, 0x7a]));
+        assert_eq!(Some(vec![0x00, 0xff, 0x7a]), hex_decode("00FF7a"));
+        assert_eq!(None, hex_decode("0"));
+        assert_eq!(None, hex_decode("zz"));
+    }
+
+    #[test]
+    fn base64_round_trip() {
+        // RFC 4648 Section 10 test vectors.
+        let vectors = [("", ""),
+                       ("f", "Zg=="),
+                       ("fo", "Zm8="),
+                       ("foo", "Zm9v"),
+                       ("foob", "Zm9vYg=="),
+                       ("fooba", "Zm9vYmE="),
+                       ("foobar", "Zm9vYmFy")];
+        for &(plain, encoded) in vectors.iter() {
+            assert_eq!(encoded, base64_encode(plain.as_bytes()));
+            assert_eq!(Some(plain.as_bytes().to_vec()), base64_decode(encoded));
+        }
+    }
+
+    #[test]
+    fn base64_invalid() {
+        assert_eq!(None, base64_decode("Zg="));
+        assert_eq!(None, base64_decode("Zg==Zg=="));
+        assert_eq!(None, base64_decode("Z!=="));
+    }
+
+    #[test]
+    fn base64url() {
+        assert_eq!("-_8", base64url_encode(&[0xfb, 0xff]));
+        assert_eq!(Some(vec![0xfb, 0xff]), base64url_decode("-_8"));
+        assert_eq!(Some(vec![0xfb, 0xff]), base64url_decode("-_8="));
+        assert_eq!(Some(b"foobar".to_vec()), base64url_decode("Zm9vYmFy"));
+        assert_eq!(None, base64url_decode("+/8"));
+        assert_eq!(None, base64url_decode("Z"));
+    }
+}
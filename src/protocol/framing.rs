@@ -0,0 +1,130 @@
+//! Message framing for stream transports: each message is preceded by its
+//! length as a two octet big-endian integer (RFC 1035 Section 4.2.2).
+
+/// Largest message a two octet length prefix can describe.
+pub const MAX_FRAME: usize = 0xffff;
+
+/// Prefixes a message with its length. Returns None if it is too long.
+pub fn tcp_frame(message: &[u8]) -> Option<Vec<u8>> {
+    if message.len() > MAX_FRAME {
+        return None;
+    }
+    let mut frame = Vec::with_capacity(message.len() + 2);
+    frame.push((message.len() >> 8) as u8);
+    frame.push(message.len() as u8);
+    frame.extend(message.iter().cloned());
+    Some(frame)
+}
+
+/// Collects stream input and splits it into messages as they complete.
+#[derive(Debug, Default)]
+pub struct FrameReader {
+    buffer: Vec<u8>,
+}
+
+impl FrameReader {
+    pub fn new() -> FrameReader {
+        FrameReader { buffer: Vec::new() }
+    }
+
+    /// Adds octets read from the stream.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend(data.iter().cloned());
+    }
+
+    /// Octets held that do not yet make a whole message.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Removes and returns the next complete message, if there is one.
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        if self.buffer.len() < 2 {
+            return None;
+        }
+        let len = ((self.buffer[0] as usize) << 8) | self.buffer[1] as usize;
+        if self.buffer.len() < 2 + len {
+            return None;
+        }
+        let message = self.buffer[2..2 + len].to_vec();
+        self.buffer.drain(..2 + len);
+        Some(message)
+    }
+}
+
+/// Queues messages for a stream, framed, until they have been written.
+#[derive(Debug, Default)]
+pub struct FrameWriter {
+    buffer: Vec<u8>,
+}
+
+impl FrameWriter {
+    pub fn new() -> FrameWriter {
+        FrameWriter { buffer: Vec::new() }
+    }
+
+    /// Queues a message. Returns false, queueing nothing, if it is too long
+    /// to frame.
+    pub fn push(&mut self, message: &[u8]) -> bool {
+        match tcp_frame(message) {
+            Some(frame) => {
+                self.buffer.extend(frame);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The octets still to be written.
+    pub fn pending(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Drops the first `n` octets once the stream has taken them.
+    pub fn written(&mut self, n: usize) {
+        let n = n.min(self.buffer.len());
+        self.buffer.drain(..n);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn frames() {
+        let mut reader = FrameReader::new();
+        let mut stream = tcp_frame(b"first").unwrap();
+        stream.extend(tcp_frame(b"").unwrap());
+        stream.extend(tcp_frame(b"third").unwrap());
+        // Arrives in awkward pieces.
+        reader.push(&stream[..1]);
+        assert_eq!(None, reader.next_frame());
+        reader.push(&stream[1..8]);
+        assert_eq!(Some(b"first".to_vec()), reader.next_frame());
+        assert_eq!(None, reader.next_frame());
+        reader.push(&stream[8..]);
+        assert_eq!(Some(Vec::new()), reader.next_frame());
+        assert_eq!(Some(b"third".to_vec()), reader.next_frame());
+        assert_eq!(0, reader.buffered());
+
+        assert_eq!(None, tcp_frame(&vec![0u8; MAX_FRAME + 1]));
+    }
+
+    #[test]
+    fn writing() {
+        let mut writer = FrameWriter::new();
+        assert!(writer.push(b"one"));
+        assert!(writer.push(b"two"));
+        assert!(!writer.push(&vec![0u8; MAX_FRAME + 1]));
+        writer.written(3);
+        assert_eq!(b"ne\x00\x03two", writer.pending());
+        writer.written(100);
+        assert!(writer.is_empty());
+    }
+}
@@ -5,6 +5,7 @@ use super::bits::BEU16Field;
 use std::ops::Deref;
 use std::fmt;
 use super::message::MessageCursor;
+use super::section::{Questions, Resources};
 
 const ID: BEU16Field = BEU16Field { index: 0 };
 
@@ -37,11 +38,35 @@ const Z: BitField = BitField {
     index: 3,
     mask: 0b0111_0000u8,
 };
+// The two low bits of Z given meaning by DNSSEC (RFC 4035 Section 3.2).
+const AD: BitField = BitField {
+    index: 3,
+    mask: 0b0010_0000u8,
+};
+const CD: BitField = BitField {
+    index: 3,
+    mask: 0b0001_0000u8,
+};
 const RC: BitField = BitField {
     index: 3,
     mask: 0b0000_1111u8,
 };
 
+// LLMNR's flags (RFC 4795 Section 2.1.1): C and T where AA and RD are, and
+// all four bits ahead of RCODE zero.
+const C: BitField = BitField {
+    index: 2,
+    mask: 0b0000_0100u8,
+};
+const T: BitField = BitField {
+    index: 2,
+    mask: 0b0000_0001u8,
+};
+const LLMNR_Z: BitField = BitField {
+    index: 3,
+    mask: 0b1111_0000u8,
+};
+
 const QD: BEU16Field = BEU16Field { index: 4 };
 const AN: BEU16Field = BEU16Field { index: 6 };
 const NS: BEU16Field = BEU16Field { index: 8 };
@@ -55,20 +80,67 @@ pub const RC_SERVER_ERROR: u8 = 2;
 pub const RC_NAME_ERROR: u8 = 3;
 pub const RC_NOT_IMPLEMENTED: u8 = 4;
 pub const RC_REFUSED: u8 = 5;
+pub const RC_YX_DOMAIN: u8 = 6;
+pub const RC_YX_RRSET: u8 = 7;
+pub const RC_NX_RRSET: u8 = 8;
+pub const RC_NOT_AUTH: u8 = 9;
+pub const RC_NOT_ZONE: u8 = 10;
 
 pub const OP_QUERY: u8 = 0;
 pub const OP_IQUERY: u8 = 1;
 pub const OP_STATUS: u8 = 2;
+pub const OP_NOTIFY: u8 = 4;
+pub const OP_UPDATE: u8 = 5;
+
+/// Where the flags that differ between protocols sharing the header sit.
+/// Accessors for flags a layout lacks return None, and setters do nothing.
+pub struct HeaderLayout {
+    aa: Option<BitField>,
+    rd: Option<BitField>,
+    ra: Option<BitField>,
+    ad: Option<BitField>,
+    cd: Option<BitField>,
+    c: Option<BitField>,
+    t: Option<BitField>,
+    z: BitField,
+}
+
+/// DNS, the layout headers have unless given another.
+pub const DNS_LAYOUT: HeaderLayout = HeaderLayout {
+    aa: Some(AA),
+    rd: Some(RD),
+    ra: Some(RA),
+    ad: Some(AD),
+    cd: Some(CD),
+    c: None,
+    t: None,
+    z: Z,
+};
+
+/// Link-Local Multicast Name Resolution (RFC 4795): the conflict and
+/// tentative bits in place of AA and RD, and no RA, AD or CD.
+pub const LLMNR_LAYOUT: HeaderLayout = HeaderLayout {
+    aa: None,
+    rd: None,
+    ra: None,
+    ad: None,
+    cd: None,
+    c: Some(C),
+    t: Some(T),
+    z: LLMNR_Z,
+};
 
 #[derive(Copy,Clone)]
 pub struct Header<'d, D: 'd + ?Sized> {
     start: usize,
     data: &'d D,
+    layout: &'static HeaderLayout,
 }
 
 pub struct HeaderMut<'d, D: 'd + ?Sized> {
     start: usize,
     data: &'d mut D,
+    layout: &'static HeaderLayout,
 }
 
 
@@ -78,9 +150,15 @@ impl<'d, D: 'd + ?Sized> Header<'d, D> where D: BitData {
         Header {
             start: 0,
             data: data,
+            layout: &DNS_LAYOUT,
         }
     }
 
+    /// The header read with the flags where `layout` has them.
+    pub fn with_layout(self, layout: &'static HeaderLayout) -> Header<'d, D> {
+        Header { layout: layout, ..self }
+    }
+
     pub fn id(&self) -> Option<u16> {
         ID.get(self.data)
     }
@@ -91,19 +169,31 @@ impl<'d, D: 'd + ?Sized> Header<'d, D> where D: BitData {
         OP.get(self.data)
     }
     pub fn aa(&self) -> Option<bool> {
-        AA.nonzero(self.data)
+        self.layout.aa.as_ref().and_then(|field| field.nonzero(self.data))
     }
     pub fn tc(&self) -> Option<bool> {
         TC.nonzero(self.data)
     }
     pub fn rd(&self) -> Option<bool> {
-        RD.nonzero(self.data)
+        self.layout.rd.as_ref().and_then(|field| field.nonzero(self.data))
     }
     pub fn ra(&self) -> Option<bool> {
-        RA.nonzero(self.data)
+        self.layout.ra.as_ref().and_then(|field| field.nonzero(self.data))
     }
     pub fn z(&self) -> Option<u8> {
-        Z.get(self.data)
+        self.layout.z.get(self.data)
+    }
+    pub fn c(&self) -> Option<bool> {
+        self.layout.c.as_ref().and_then(|field| field.nonzero(self.data))
+    }
+    pub fn t(&self) -> Option<bool> {
+        self.layout.t.as_ref().and_then(|field| field.nonzero(self.data))
+    }
+    pub fn ad(&self) -> Option<bool> {
+        self.layout.ad.as_ref().and_then(|field| field.nonzero(self.data))
+    }
+    pub fn cd(&self) -> Option<bool> {
+        self.layout.cd.as_ref().and_then(|field| field.nonzero(self.data))
     }
     pub fn rc(&self) -> Option<u8> {
         RC.get(self.data)
@@ -131,21 +221,32 @@ impl<'d, D: 'd + ?Sized> Header<'d, D> where D: BitData {
         self.tc() == Some(true)
     }
 
-    // TODO questions iterator
-    // TODO answers iterator
-    // TODO nameservers iterator
-    // TODO additional records iterator
-
     pub fn end_offset(&self) -> usize {
         self.start + SIZE
     }
 }
 
+impl<'d, D: 'd + ?Sized> Header<'d, D> where D: BitData<Slice = [u8]> {
+    pub fn questions(&self) -> Questions<'d, D> {
+        Questions::new(self.data, self.end_offset(), self.qd().unwrap_or(0))
+    }
+    pub fn answers(&self) -> Resources<'d, D> {
+        Resources::new(self.data, self.questions().end_offset(), self.an().unwrap_or(0))
+    }
+    pub fn nameservers(&self) -> Resources<'d, D> {
+        Resources::new(self.data, self.answers().end_offset(), self.ns().unwrap_or(0))
+    }
+    pub fn additionals(&self) -> Resources<'d, D> {
+        Resources::new(self.data, self.nameservers().end_offset(), self.ar().unwrap_or(0))
+    }
+}
+
 impl<'d, D: 'd + ?Sized> HeaderMut<'d, D> where D: BitDataMut {
     pub fn at_raw(data: &'d mut D) -> HeaderMut<'d, D> {
         HeaderMut {
             start: 0,
             data: data,
+            layout: &DNS_LAYOUT,
         }
     }
     pub fn at(idx: &mut MessageCursor, data: &'d mut D) -> Option<HeaderMut<'d, D>> {
@@ -153,11 +254,17 @@ impl<'d, D: 'd + ?Sized> HeaderMut<'d, D> where D: BitDataMut {
             return Some(HeaderMut {
                 start: at.start,
                 data: data,
+                layout: &DNS_LAYOUT,
             });
         }
         None
     }
 
+    /// The header written with the flags where `layout` has them.
+    pub fn with_layout(self, layout: &'static HeaderLayout) -> HeaderMut<'d, D> {
+        HeaderMut { layout: layout, ..self }
+    }
+
     pub fn set_id(&mut self, val: u16) -> &mut Self {
         ID.set(self.data, val);
         self
@@ -171,7 +278,9 @@ impl<'d, D: 'd + ?Sized> HeaderMut<'d, D> where D: BitDataMut {
         self
     }
     pub fn set_aa(&mut self, val: bool) -> &mut Self {
-        AA.set(self.data, val as u8);
+        if let Some(ref field) = self.layout.aa {
+            field.set(self.data, val as u8);
+        }
         self
     }
     pub fn set_tc(&mut self, val: bool) -> &mut Self {
@@ -179,11 +288,39 @@ impl<'d, D: 'd + ?Sized> HeaderMut<'d, D> where D: BitDataMut {
         self
     }
     pub fn set_rd(&mut self, val: bool) -> &mut Self {
-        RD.set(self.data, val as u8);
+        if let Some(ref field) = self.layout.rd {
+            field.set(self.data, val as u8);
+        }
         self
     }
     pub fn set_ra(&mut self, val: bool) -> &mut Self {
-        RA.set(self.data, val as u8);
+        if let Some(ref field) = self.layout.ra {
+            field.set(self.data, val as u8);
+        }
+        self
+    }
+    pub fn set_ad(&mut self, val: bool) -> &mut Self {
+        if let Some(ref field) = self.layout.ad {
+            field.set(self.data, val as u8);
+        }
+        self
+    }
+    pub fn set_cd(&mut self, val: bool) -> &mut Self {
+        if let Some(ref field) = self.layout.cd {
+            field.set(self.data, val as u8);
+        }
+        self
+    }
+    pub fn set_c(&mut self, val: bool) -> &mut Self {
+        if let Some(ref field) = self.layout.c {
+            field.set(self.data, val as u8);
+        }
+        self
+    }
+    pub fn set_t(&mut self, val: bool) -> &mut Self {
+        if let Some(ref field) = self.layout.t {
+            field.set(self.data, val as u8);
+        }
         self
     }
     pub fn set_rc(&mut self, val: u8) -> &mut Self {
@@ -255,6 +392,7 @@ impl<'d, D: 'd> HeaderMut<'d, D> where D: BitDataMut {
         Header {
             start: self.start,
             data: readonly,
+            layout: self.layout,
         }
     }
 }
@@ -556,4 +694,67 @@ mod tests {
         let h = Header::at(data);
         assert_eq!(Some(0xabcd), h.ar());
     }
+
+    #[test]
+    fn set_ad_cd() {
+        let data: &mut Vec<u8> = &mut vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        HeaderMut::at_raw(data).set_ad(true).set_cd(true);
+        let h = Header::at(data);
+        assert_eq!((Some(true), Some(true), Some(3)), (h.ad(), h.cd(), h.z()));
+    }
+
+    #[test]
+    fn llmnr_layout() {
+        let data: &mut Vec<u8> = &mut vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        HeaderMut::at_raw(data)
+            .with_layout(&LLMNR_LAYOUT)
+            .set_qr(true)
+            .set_c(true)
+            .set_tc(true)
+            .set_t(true)
+            .set_aa(true)
+            .set_ra(true)
+            .set_rc(RC_NAME_ERROR);
+        assert_eq!(&[0, 0, 0x87, 0x03], &data[..4]);
+        let h = Header::at(data).with_layout(&LLMNR_LAYOUT);
+        assert_eq!((Some(true), Some(true), Some(true)), (h.c(), h.tc(), h.t()));
+        assert_eq!((None, None, None), (h.aa(), h.rd(), h.ra()));
+        assert_eq!(Some(0), h.z());
+
+        // The same bits, read as DNS.
+        let h = Header::at(data);
+        assert_eq!((Some(true), Some(true), None), (h.aa(), h.rd(), h.c()));
+        HeaderMut::at_raw(data).set_t(true).set_c(false);
+        assert_eq!(0x87, data[2]);
+    }
+
+    #[test]
+    fn sections() {
+        use protocol::{Name, QuestionMut, RData, Record};
+        let mut message = vec![0u8; 512];
+        let mut idx = MessageCursor::new(message.len());
+        HeaderMut::at(&mut idx, &mut message[..]).unwrap().set_qd(1).set_an(2).set_ar(1);
+        let name = Name::parse("example.com.", None).unwrap();
+        QuestionMut::at(&mut idx, &mut message, &name.segments(), 1, 1).unwrap();
+        for ttl in 1..4 {
+            Record::new(name.clone(), 1, ttl, RData::A("192.0.2.1".parse().unwrap()))
+                .write_at(&mut idx, &mut message)
+                .unwrap();
+        }
+        message.truncate(idx.tell());
+
+        let h = Header::at(&message[..]);
+        assert_eq!(Some(1), h.questions().next().and_then(|q| q.qtype()));
+        assert_eq!(vec![Some(1), Some(2)], h.answers().map(|r| r.ttl()).collect::<Vec<_>>());
+        assert_eq!(0, h.nameservers().count());
+        assert_eq!(vec![Some(3)], h.additionals().map(|r| r.ttl()).collect::<Vec<_>>());
+        assert_eq!(Some(message.len()), h.additionals().end_offset());
+
+        // Cut off in the middle of the second answer.
+        let short = &message[..message.len() - 20];
+        let h = Header::at(short);
+        assert_eq!(1, h.answers().count());
+        assert_eq!(None, h.answers().end_offset());
+        assert_eq!((0, None), (h.additionals().count(), h.additionals().end_offset()));
+    }
 }
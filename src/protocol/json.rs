@@ -0,0 +1,354 @@
+//! A small JSON value (RFC 8259), enough to read and write the JSON forms
+//! of DNS messages.
+
+use std::fmt;
+
+/// How deeply arrays and objects may nest when parsing.
+const MAX_DEPTH: usize = 32;
+
+/// A parsed JSON value. Object members keep their order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parses a complete JSON text, or returns None if it is not one.
+    pub fn parse(text: &str) -> Option<Json> {
+        let mut parser = Parser {
+            text: text.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value(0);
+        parser.skip_space();
+        if parser.pos != parser.text.len() {
+            return None;
+        }
+        value
+    }
+
+    /// The member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => {
+                members.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Json::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// The value of a number that is a whole number no larger than `max`.
+    pub fn as_u64(&self, max: u64) -> Option<u64> {
+        match *self {
+            Json::Number(n) if n >= 0.0 && n <= max as f64 && n.fract() == 0.0 => Some(n as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match *self {
+            Json::Array(ref items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+fn write_string(fmt: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    try!(write!(fmt, "\""));
+    for c in value.chars() {
+        match c {
+            '"' => try!(write!(fmt, "\\\"")),
+            '\\' => try!(write!(fmt, "\\\\")),
+            '\n' => try!(write!(fmt, "\\n")),
+            '\r' => try!(write!(fmt, "\\r")),
+            '\t' => try!(write!(fmt, "\\t")),
+            '\u{0}'...'\u{1f}' => try!(write!(fmt, "\\u{:04x}", c as u32)),
+            _ => try!(write!(fmt, "{}", c)),
+        }
+    }
+    write!(fmt, "\"")
+}
+
+/// Writes the value compactly, with whole numbers written without a
+/// fraction.
+impl fmt::Display for Json {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(fmt, "null"),
+            Json::Bool(b) => write!(fmt, "{}", b),
+            Json::Number(n) if !n.is_finite() => write!(fmt, "null"),
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(fmt, "{}", n as i64),
+            Json::Number(n) => write!(fmt, "{}", n),
+            Json::String(ref s) => write_string(fmt, s),
+            Json::Array(ref items) => {
+                try!(write!(fmt, "["));
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(fmt, ","));
+                    }
+                    try!(write!(fmt, "{}", item));
+                }
+                write!(fmt, "]")
+            }
+            Json::Object(ref members) => {
+                try!(write!(fmt, "{{"));
+                for (i, &(ref key, ref value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(fmt, ","));
+                    }
+                    try!(write_string(fmt, key));
+                    try!(write!(fmt, ":{}", value));
+                }
+                write!(fmt, "}}")
+            }
+        }
+    }
+}
+
+/// Recursive descent over the text.
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_space(&mut self) {
+        while self.pos < self.text.len() {
+            match self.text[self.pos] {
+                b' ' | b'\t' | b'\n' | b'\r' => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_space();
+        self.text.get(self.pos).cloned()
+    }
+
+    fn literal(&mut self, word: &[u8], value: Json) -> Option<Json> {
+        if !self.text[self.pos..].starts_with(word) {
+            return None;
+        }
+        self.pos += word.len();
+        Some(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Option<Json> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        match self.peek() {
+            Some(b'n') => self.literal(b"null", Json::Null),
+            Some(b't') => self.literal(b"true", Json::Bool(true)),
+            Some(b'f') => self.literal(b"false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => self.array(depth),
+            Some(b'{') => self.object(depth),
+            Some(b'-') | Some(b'0'...b'9') => self.number(),
+            _ => None,
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Option<Json> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Some(Json::Array(items));
+        }
+        loop {
+            match self.value(depth + 1) {
+                Some(item) => items.push(item),
+                None => return None,
+            }
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Some(Json::Array(items));
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Option<Json> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Some(Json::Object(members));
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return None;
+            }
+            let key = match self.string() {
+                Some(key) => key,
+                None => return None,
+            };
+            if self.peek() != Some(b':') {
+                return None;
+            }
+            self.pos += 1;
+            match self.value(depth + 1) {
+                Some(value) => members.push((key, value)),
+                None => return None,
+            }
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Some(Json::Object(members));
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    fn number(&mut self) -> Option<Json> {
+        let start = self.pos;
+        while self.pos < self.text.len() {
+            match self.text[self.pos] {
+                b'0'...b'9' | b'-' | b'+' | b'.' | b'e' | b'E' => self.pos += 1,
+                _ => break,
+            }
+        }
+        let text = String::from_utf8_lossy(&self.text[start..self.pos]);
+        // Rust reads forms JSON does not have, such as "1." and ".5".
+        let digits = if text.starts_with('-') { &text[1..] } else { &text[..] }.as_bytes();
+        let leading_zero = digits.len() > 1 && digits[0] == b'0' && digits[1].is_ascii_digit();
+        let bare_point = text.contains(".e") || text.contains(".E") || text.ends_with('.');
+        if digits.first() == Some(&b'.') || leading_zero || bare_point {
+            return None;
+        }
+        text.parse::<f64>().ok().map(Json::Number)
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        if self.pos + 4 > self.text.len() {
+            return None;
+        }
+        let digits = &self.text[self.pos..self.pos + 4];
+        self.pos += 4;
+        if !digits.iter().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        u32::from_str_radix(&String::from_utf8_lossy(digits), 16).ok()
+    }
+
+    // The code point of a \u escape, joining a surrogate pair.
+    fn unicode_escape(&mut self) -> Option<char> {
+        let mut code = match self.hex4() {
+            Some(code) => code,
+            None => return None,
+        };
+        if code >= 0xd800 && code < 0xdc00 {
+            if !self.text[self.pos..].starts_with(b"\\u") {
+                return None;
+            }
+            self.pos += 2;
+            match self.hex4() {
+                Some(low) if low >= 0xdc00 && low < 0xe000 => {
+                    code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                }
+                _ => return None,
+            }
+        }
+        ::std::char::from_u32(code)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        while self.pos < self.text.len() {
+            let c = self.text[self.pos];
+            self.pos += 1;
+            if c != b'\\' {
+                match c {
+                    b'"' => return String::from_utf8(bytes).ok(),
+                    0...0x1f => return None,
+                    _ => bytes.push(c),
+                }
+                continue;
+            }
+            let escape = match self.text.get(self.pos) {
+                Some(&escape) => escape,
+                None => return None,
+            };
+            self.pos += 1;
+            let decoded = match escape {
+                b'"' => '"',
+                b'\\' => '\\',
+                b'/' => '/',
+                b'b' => '\u{8}',
+                b'f' => '\u{c}',
+                b'n' => '\n',
+                b'r' => '\r',
+                b't' => '\t',
+                b'u' => {
+                    match self.unicode_escape() {
+                        Some(decoded) => decoded,
+                        None => return None,
+                    }
+                }
+                _ => return None,
+            };
+            let mut buffer = [0; 4];
+            bytes.extend(decoded.encode_utf8(&mut buffer).as_bytes());
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_write() {
+        let text = " { \"a\" : [1, -2.5, 1e3, true, false, null], \
+                    \"b\\n\": \"x\\u00e9\\ud83d\\ude00\" } ";
+        let value = Json::parse(text).unwrap();
+        let a = value.get("a").and_then(|a| a.as_array()).unwrap();
+        assert_eq!((Some(1000), Some(false)), (a[2].as_u64(9999), a[4].as_bool()));
+        assert_eq!(Some("x\u{e9}\u{1f600}"), value.get("b\n").and_then(|b| b.as_str()));
+        assert_eq!("{\"a\":[1,-2.5,1000,true,false,null],\"b\\n\":\"x\u{e9}\u{1f600}\"}",
+                   value.to_string());
+        assert_eq!(Some(value.clone()), Json::parse(&value.to_string()));
+    }
+
+    #[test]
+    fn rejects() {
+        let bad = ["", "{", "[1,]", "{\"a\" 1}", "01", "1.", ".5", "\"\\x\"", "\"\\ud800\"",
+                   "\"a\nb\"", "[] []", "tru"];
+        for text in bad.iter() {
+            assert_eq!(None, Json::parse(text), "{}", text);
+        }
+        let deep = format!("{}{}", "[".repeat(40), "]".repeat(40));
+        assert_eq!(None, Json::parse(&deep));
+        assert_eq!(None, Json::Number(1.5).as_u64(10));
+        assert_eq!(None, Json::Number(11.0).as_u64(10));
+    }
+}
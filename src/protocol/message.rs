@@ -0,0 +1,109 @@
+use std::ops::Range;
+use super::bits::BitData;
+
+// Compression pointers have 14 bits of offset.
+const MAX_POINTER_OFFSET: usize = 0x3fff;
+
+/// Tracks the write position while composing a message, and the names
+/// already written so later ones can be compressed against them.
+#[derive(Debug)]
+pub struct MessageCursor {
+    pos: usize,
+    end: usize,
+    // Name suffixes written so far and the offsets they start at.
+    names: Vec<(Vec<Vec<u8>>, u16)>,
+}
+
+impl MessageCursor {
+    /// A cursor over a message buffer of `len` octets.
+    pub fn new(len: usize) -> MessageCursor {
+        MessageCursor {
+            pos: 0,
+            end: len,
+            names: Vec::new(),
+        }
+    }
+
+    /// Current write offset, which is also the length composed so far.
+    pub fn tell(&self) -> usize {
+        self.pos
+    }
+
+    /// Octets left before the end of the buffer.
+    pub fn remaining(&self) -> usize {
+        self.end - self.pos
+    }
+
+    /// Reserves the next `size` octets, or returns None if they do not fit.
+    pub fn alloc(&mut self, size: usize) -> Option<Range<usize>> {
+        if size > self.end - self.pos {
+            return None;
+        }
+        let start = self.pos;
+        self.pos += size;
+        Some(Range {
+            start: start,
+            end: self.pos,
+        })
+    }
+
+    /// Moves the write position back to `at`, forgetting any names written
+    /// past it. Used to drop a partly written record that did not fit.
+    pub fn rewind(&mut self, at: usize) {
+        if at < self.pos {
+            self.pos = at;
+            self.names.retain(|&(_, offset)| (offset as usize) < at);
+        }
+    }
+
+    /// Offset of an earlier occurrence of `suffix`, for use as a compression
+    /// pointer.
+    pub fn lookup_name_suffix<D: ?Sized + BitData>(&self,
+                                                   _data: &D,
+                                                   suffix: &[&[u8]])
+        -> Option<u16> {
+        for &(ref name, offset) in &self.names {
+            if name.len() == suffix.len() &&
+               name.iter().zip(suffix.iter()).all(|(a, b)| &a[..] == *b) {
+                return Some(offset);
+            }
+        }
+        None
+    }
+
+    /// Records that `suffix` was written starting at offset `at`.
+    pub fn register_name_suffix(&mut self, at: usize, suffix: &[&[u8]]) {
+        if at > MAX_POINTER_OFFSET || suffix.is_empty() {
+            return;
+        }
+        self.names.push((suffix.iter().map(|s| s.to_vec()).collect(), at as u16));
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn alloc() {
+        let mut idx = MessageCursor::new(4);
+        assert_eq!(Some(0..3), idx.alloc(3));
+        assert_eq!(None, idx.alloc(2));
+        assert_eq!(Some(3..4), idx.alloc(1));
+        assert_eq!(0, idx.remaining());
+    }
+
+    #[test]
+    fn rewind_forgets_names() {
+        let data = [0u8; 32];
+        let mut idx = MessageCursor::new(32);
+        idx.register_name_suffix(0, &[&b"a"[..], &b"b"[..]]);
+        idx.alloc(20);
+        idx.register_name_suffix(12, &[&b"c"[..]]);
+        idx.rewind(10);
+        assert_eq!(10, idx.tell());
+        assert_eq!(Some(0), idx.lookup_name_suffix(&data[..], &[&b"a"[..], &b"b"[..]]));
+        assert_eq!(None, idx.lookup_name_suffix(&data[..], &[&b"c"[..]]));
+    }
+}
@@ -2,12 +2,44 @@ mod bits;
 mod header;
 mod question;
 mod domain_name;
+mod dns_json;
 mod resource;
 mod message;
+mod encoding;
+mod framing;
+mod json;
+mod name;
+pub mod rdata;
+mod record;
+mod serial;
+mod section;
+mod sig;
+mod tsig;
+mod udp;
 
-pub use self::header::{Header, HeaderMut};
+pub use self::bits::BEU48Field;
+pub use self::header::{DNS_LAYOUT, Header, HeaderLayout, HeaderMut, LLMNR_LAYOUT};
+pub use self::header::{OP_IQUERY, OP_NOTIFY, OP_QUERY, OP_STATUS, OP_UPDATE};
+pub use self::header::{RC_FORMAT_ERROR, RC_NAME_ERROR, RC_NOT_AUTH, RC_NOT_IMPLEMENTED,
+                       RC_NOT_ZONE, RC_NX_RRSET, RC_OK, RC_REFUSED, RC_SERVER_ERROR,
+                       RC_YX_DOMAIN, RC_YX_RRSET};
 pub use self::question::{Question, QuestionMut};
+pub use self::dns_json::{message_from_dns_json, message_from_json, message_to_dns_json,
+                         message_to_json};
 pub use self::domain_name::encode_dotted_name;
 pub use self::domain_name::DomainName;
-pub use self::resource::Resource;
+pub use self::resource::{Resource, ResourceMut};
 pub use self::message::MessageCursor;
+pub use self::encoding::{base64_decode, base64_encode, base64url_decode, base64url_encode,
+                         hex_decode, hex_encode};
+pub use self::framing::{FrameReader, FrameWriter, MAX_FRAME, tcp_frame};
+pub use self::json::Json;
+pub use self::name::Name;
+pub use self::rdata::{RData, Soa};
+pub use self::record::Record;
+pub use self::serial::Serial;
+pub use self::section::{Questions, Resources};
+pub use self::sig::SigRecord;
+pub use self::tsig::TsigRecord;
+pub use self::udp::{MIN_UDP_PAYLOAD, OPT_LEN, append_opt, has_edns, response_ttl, truncate_for_udp,
+                    udp_payload_size};
@@ -0,0 +1,400 @@
+use std::ascii::AsciiExt;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use super::bits::BitData;
+use super::domain_name::DomainName;
+
+const MAX_LABEL_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 255;
+
+/// An owned, absolute domain name.
+///
+/// Unlike `DomainName`, which is a view into a message, a `Name` carries its
+/// own labels so it can outlive the message it was read from. Comparisons are
+/// ASCII case-insensitive and `Ord` is the canonical ordering of RFC 4034
+/// Section 6.1.
+#[derive(Clone)]
+pub struct Name {
+    // Leftmost label first. The root label is implied.
+    labels: Vec<Vec<u8>>,
+}
+
+impl Name {
+    pub fn root() -> Name {
+        Name { labels: Vec::new() }
+    }
+
+    /// Builds a name from its labels, leftmost first. A trailing empty (root)
+    /// label is accepted and dropped.
+    pub fn from_labels(mut labels: Vec<Vec<u8>>) -> Option<Name> {
+        if labels.last().map(|l| l.is_empty()).unwrap_or(false) {
+            labels.pop();
+        }
+        let name = Name { labels: labels };
+        if name.labels.iter().any(|l| l.is_empty() || l.len() > MAX_LABEL_LEN) ||
+           name.wire_len() > MAX_NAME_LEN {
+            return None;
+        }
+        Some(name)
+    }
+
+    /// Reads a (possibly compressed) name out of a message.
+    pub fn from_message<D: ?Sized + BitData<Slice = [u8]>>(message: &D,
+                                                          at: usize)
+        -> Option<Name> {
+        if let Some(name) = DomainName::from_message(message, at) {
+            return Name::from_domain_name(message, &name);
+        }
+        None
+    }
+
+    pub fn from_domain_name<D: ?Sized + BitData<Slice = [u8]>>(message: &D,
+                                                              name: &DomainName)
+        -> Option<Name> {
+        if let Some(segments) = name.segments(message) {
+            return Name::from_labels(segments.iter().map(|s| s.to_vec()).collect());
+        }
+        None
+    }
+
+    /// Parses a name in master file presentation format.
+    ///
+    /// `@` stands for the origin and names without a trailing dot are
+    /// relative to it. Escapes are `\X` for a literal character and `\DDD`
+    /// for a decimal octet.
+    pub fn parse(text: &str, origin: Option<&Name>) -> Option<Name> {
+        if text == "@" {
+            return origin.cloned();
+        }
+        if text == "." {
+            return Some(Name::root());
+        }
+        let bytes = text.as_bytes();
+        let mut labels = Vec::new();
+        let mut label = Vec::new();
+        let mut absolute = false;
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => {
+                    if i + 3 < bytes.len() &&
+                       bytes[i + 1..i + 4].iter().all(|c| c.is_ascii_digit()) {
+                        match text[i + 1..i + 4].parse::<u16>() {
+                            Ok(value) if value <= 255 => label.push(value as u8),
+                            _ => return None,
+                        }
+                        i += 4;
+                    } else if i + 1 < bytes.len() && !bytes[i + 1].is_ascii_digit() {
+                        label.push(bytes[i + 1]);
+                        i += 2;
+                    } else {
+                        return None;
+                    }
+                    continue;
+                }
+                b'.' => {
+                    if label.is_empty() {
+                        return None;
+                    }
+                    labels.push(label);
+                    label = Vec::new();
+                    if i + 1 == bytes.len() {
+                        absolute = true;
+                    }
+                }
+                c => label.push(c),
+            }
+            i += 1;
+        }
+        if !label.is_empty() {
+            labels.push(label);
+        }
+        if !absolute {
+            match origin {
+                Some(origin) => labels.extend(origin.labels.iter().cloned()),
+                None => return None,
+            }
+        }
+        Name::from_labels(labels)
+    }
+
+    /// Labels from leftmost to rightmost, excluding the root.
+    pub fn labels(&self) -> &[Vec<u8>] {
+        &self.labels
+    }
+
+    /// Number of labels, excluding the root.
+    pub fn label_count(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.labels.first().map(|l| &l[..] == b"*").unwrap_or(false)
+    }
+
+    /// Length of the uncompressed wire encoding, including the root octet.
+    pub fn wire_len(&self) -> usize {
+        self.labels.iter().fold(1, |len, l| len + 1 + l.len())
+    }
+
+    /// The name with its leftmost label removed, or None for the root.
+    pub fn parent(&self) -> Option<Name> {
+        if self.labels.is_empty() {
+            return None;
+        }
+        Some(Name { labels: self.labels[1..].to_vec() })
+    }
+
+    /// Prepends a label, as in building `www` + `example.com`.
+    pub fn prepend(&self, label: &[u8]) -> Option<Name> {
+        let mut labels = Vec::with_capacity(self.labels.len() + 1);
+        labels.push(label.to_vec());
+        labels.extend(self.labels.iter().cloned());
+        Name::from_labels(labels)
+    }
+
+    /// Whether this name is equal to or below `other`.
+    pub fn is_subdomain_of(&self, other: &Name) -> bool {
+        if other.labels.len() > self.labels.len() {
+            return false;
+        }
+        let skip = self.labels.len() - other.labels.len();
+        self.labels[skip..]
+            .iter()
+            .zip(other.labels.iter())
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    /// Slices suitable for `DomainName::write_at`, root label included.
+    pub fn segments(&self) -> Vec<&[u8]> {
+        let mut segments: Vec<&[u8]> = self.labels.iter().map(|l| &l[..]).collect();
+        segments.push(&[]);
+        segments
+    }
+
+    /// Formats the name relative to `origin` when it lies at or below it:
+    /// `@` for the origin itself, otherwise the leading labels without a
+    /// trailing dot. Other names are formatted absolute.
+    pub fn relative<'a>(&'a self, origin: Option<&'a Name>) -> RelativeName<'a> {
+        RelativeName {
+            name: self,
+            origin: origin,
+        }
+    }
+
+    /// Uncompressed wire encoding, preserving case.
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut wire = Vec::with_capacity(self.wire_len());
+        for label in &self.labels {
+            wire.push(label.len() as u8);
+            wire.extend(label.iter().cloned());
+        }
+        wire.push(0);
+        wire
+    }
+
+    /// Uncompressed wire encoding in lowercase (RFC 4034 Section 6.2).
+    pub fn to_canonical_wire(&self) -> Vec<u8> {
+        let mut wire = self.to_wire();
+        wire.make_ascii_lowercase();
+        wire
+    }
+}
+
+/// Writes a label in presentation format, escaping the characters that are
+/// special in master files.
+pub fn write_label(fmt: &mut fmt::Formatter, label: &[u8]) -> fmt::Result {
+    for &c in label {
+        match c {
+            b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                try!(write!(fmt, "\\{}", c as char));
+            }
+            0x21...0x7e => try!(write!(fmt, "{}", c as char)),
+            _ => try!(write!(fmt, "\\{:03}", c)),
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.labels.is_empty() {
+            return write!(fmt, ".");
+        }
+        for label in &self.labels {
+            try!(write_label(fmt, label));
+            try!(write!(fmt, "."));
+        }
+        Ok(())
+    }
+}
+
+/// Display adapter returned by `Name::relative`.
+pub struct RelativeName<'a> {
+    name: &'a Name,
+    origin: Option<&'a Name>,
+}
+
+impl<'a> fmt::Display for RelativeName<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let origin = match self.origin {
+            Some(origin) if self.name.is_subdomain_of(origin) => origin,
+            _ => return write!(fmt, "{}", self.name),
+        };
+        let keep = self.name.labels.len() - origin.labels.len();
+        if keep == 0 {
+            return write!(fmt, "@");
+        }
+        for (i, label) in self.name.labels[..keep].iter().enumerate() {
+            if i > 0 {
+                try!(write!(fmt, "."));
+            }
+            try!(write_label(fmt, label));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Name({})", self)
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Name) -> bool {
+        self.labels.len() == other.labels.len() &&
+        self.labels.iter().zip(other.labels.iter()).all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for label in &self.labels {
+            state.write_usize(label.len());
+            for c in label {
+                state.write_u8(c.to_ascii_lowercase());
+            }
+        }
+    }
+}
+
+fn compare_labels(a: &[u8], b: &[u8]) -> Ordering {
+    for (x, y) in a.iter().zip(b.iter()) {
+        match x.to_ascii_lowercase().cmp(&y.to_ascii_lowercase()) {
+            Ordering::Equal => continue,
+            unequal => return unequal,
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+impl Ord for Name {
+    fn cmp(&self, other: &Name) -> Ordering {
+        for (a, b) in self.labels.iter().rev().zip(other.labels.iter().rev()) {
+            match compare_labels(a, b) {
+                Ordering::Equal => continue,
+                unequal => return unequal,
+            }
+        }
+        self.labels.len().cmp(&other.labels.len())
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Name) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn name(text: &str) -> Name {
+        Name::parse(text, None).unwrap()
+    }
+
+    #[test]
+    fn parse_absolute() {
+        let n = name("www.Example.com.");
+        assert_eq!(3, n.label_count());
+        assert_eq!(b"Example", &n.labels()[1][..]);
+        assert_eq!("www.Example.com.", n.to_string());
+    }
+
+    #[test]
+    fn parse_relative() {
+        let origin = name("example.com.");
+        assert_eq!(name("www.example.com."), Name::parse("www", Some(&origin)).unwrap());
+        assert_eq!(origin, Name::parse("@", Some(&origin)).unwrap());
+        assert!(Name::parse("www", None).is_none());
+    }
+
+    #[test]
+    fn parse_escapes() {
+        let n = name("a\\.b.c\\032d.");
+        assert_eq!(2, n.label_count());
+        assert_eq!(b"a.b", &n.labels()[0][..]);
+        assert_eq!(b"c d", &n.labels()[1][..]);
+        assert_eq!("a\\.b.c\\032d.", n.to_string());
+        assert!(Name::parse("a\\256.", None).is_none());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(Name::parse("a..b.", None).is_none());
+        assert!(Name::parse(&format!("{}.", "x".repeat(64)), None).is_none());
+        assert!(Name::parse(&format!("{}.", vec!["abcdefg"; 40].join(".")), None).is_none());
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert_eq!(name("WWW.example.COM."), name("www.EXAMPLE.com."));
+        assert!(name("a.b.Example.").is_subdomain_of(&name("example.")));
+        assert!(!name("example.").is_subdomain_of(&name("a.example.")));
+    }
+
+    #[test]
+    fn canonical_order() {
+        // RFC 4034 Section 6.1 example.
+        let ordered = ["example.",
+                       "a.example.",
+                       "yljkjljk.a.example.",
+                       "Z.a.example.",
+                       "zABC.a.EXAMPLE.",
+                       "z.example.",
+                       "\\001.z.example.",
+                       "*.z.example.",
+                       "\\200.z.example."];
+        let names: Vec<Name> = ordered.iter().map(|n| name(n)).collect();
+        for pair in names.windows(2) {
+            assert!(pair[0] < pair[1], "{} < {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn relative() {
+        let origin = name("example.com.");
+        assert_eq!("@", origin.relative(Some(&origin)).to_string());
+        assert_eq!("a.www", name("a.www.Example.com.").relative(Some(&origin)).to_string());
+        assert_eq!("example.net.", name("example.net.").relative(Some(&origin)).to_string());
+        assert_eq!("www.example.com.", name("www.example.com.").relative(None).to_string());
+    }
+
+    #[test]
+    fn wire() {
+        let n = name("Ab.c.");
+        assert_eq!(vec![2, b'A', b'b', 1, b'c', 0], n.to_wire());
+        assert_eq!(vec![2, b'a', b'b', 1, b'c', 0], n.to_canonical_wire());
+        assert_eq!(Some(n), Name::from_message(&[0xff, 2, b'A', b'b', 1, b'c', 0][..], 1));
+    }
+}
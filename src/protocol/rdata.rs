@@ -0,0 +1,1190 @@
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use super::domain_name::DomainName;
+use super::encoding::{base64_decode, base64_encode, hex_decode, hex_encode};
+use super::name::Name;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_HINFO: u16 = 13;
+pub const TYPE_MX: u16 = 15;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_SIG: u16 = 24;
+pub const TYPE_KEY: u16 = 25;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_DNAME: u16 = 39;
+pub const TYPE_OPT: u16 = 41;
+pub const TYPE_DS: u16 = 43;
+pub const TYPE_SSHFP: u16 = 44;
+pub const TYPE_RRSIG: u16 = 46;
+pub const TYPE_NSEC: u16 = 47;
+pub const TYPE_DNSKEY: u16 = 48;
+pub const TYPE_TLSA: u16 = 52;
+pub const TYPE_ZONEMD: u16 = 63;
+pub const TYPE_TSIG: u16 = 250;
+pub const TYPE_IXFR: u16 = 251;
+pub const TYPE_AXFR: u16 = 252;
+pub const TYPE_ANY: u16 = 255;
+pub const TYPE_CAA: u16 = 257;
+
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_CH: u16 = 3;
+pub const CLASS_HS: u16 = 4;
+pub const CLASS_NONE: u16 = 254;
+pub const CLASS_ANY: u16 = 255;
+
+const TYPE_NAMES: &'static [(u16, &'static str)] = &[(TYPE_A, "A"),
+                                                     (TYPE_NS, "NS"),
+                                                     (TYPE_CNAME, "CNAME"),
+                                                     (TYPE_SOA, "SOA"),
+                                                     (TYPE_PTR, "PTR"),
+                                                     (TYPE_HINFO, "HINFO"),
+                                                     (TYPE_MX, "MX"),
+                                                     (TYPE_TXT, "TXT"),
+                                                     (TYPE_SIG, "SIG"),
+                                                     (TYPE_KEY, "KEY"),
+                                                     (TYPE_AAAA, "AAAA"),
+                                                     (TYPE_SRV, "SRV"),
+                                                     (TYPE_DNAME, "DNAME"),
+                                                     (TYPE_OPT, "OPT"),
+                                                     (TYPE_DS, "DS"),
+                                                     (TYPE_SSHFP, "SSHFP"),
+                                                     (TYPE_RRSIG, "RRSIG"),
+                                                     (TYPE_NSEC, "NSEC"),
+                                                     (TYPE_DNSKEY, "DNSKEY"),
+                                                     (TYPE_TLSA, "TLSA"),
+                                                     (TYPE_ZONEMD, "ZONEMD"),
+                                                     (TYPE_TSIG, "TSIG"),
+                                                     (TYPE_IXFR, "IXFR"),
+                                                     (TYPE_AXFR, "AXFR"),
+                                                     (TYPE_ANY, "ANY"),
+                                                     (TYPE_CAA, "CAA")];
+
+const CLASS_NAMES: &'static [(u16, &'static str)] = &[(CLASS_IN, "IN"),
+                                                      (CLASS_CH, "CH"),
+                                                      (CLASS_HS, "HS"),
+                                                      (CLASS_NONE, "NONE"),
+                                                      (CLASS_ANY, "ANY")];
+
+fn mnemonic(table: &[(u16, &'static str)], value: u16, generic: &str) -> String {
+    for &(v, name) in table {
+        if v == value {
+            return name.to_string();
+        }
+    }
+    format!("{}{}", generic, value)
+}
+
+fn from_mnemonic(table: &[(u16, &'static str)], text: &str, generic: &str) -> Option<u16> {
+    for &(v, name) in table {
+        if name.eq_ignore_ascii_case(text) {
+            return Some(v);
+        }
+    }
+    if text.len() > generic.len() && text[..generic.len()].eq_ignore_ascii_case(generic) {
+        return text[generic.len()..].parse::<u16>().ok();
+    }
+    None
+}
+
+/// Mnemonic for a record type, or the RFC 3597 `TYPEnnn` form.
+pub fn type_name(rtype: u16) -> String {
+    mnemonic(TYPE_NAMES, rtype, "TYPE")
+}
+
+pub fn type_from_name(text: &str) -> Option<u16> {
+    from_mnemonic(TYPE_NAMES, text, "TYPE")
+}
+
+/// Mnemonic for a class, or the RFC 3597 `CLASSnnn` form.
+pub fn class_name(class: u16) -> String {
+    mnemonic(CLASS_NAMES, class, "CLASS")
+}
+
+pub fn class_from_name(text: &str) -> Option<u16> {
+    from_mnemonic(CLASS_NAMES, text, "CLASS")
+}
+
+/// Parses a TTL, either as plain seconds or with BIND style unit suffixes
+/// such as `1h30m` or `2W`. Values above 2^31 - 1 are rejected (RFC 2181
+/// Section 8).
+pub fn parse_ttl(text: &str) -> Option<u32> {
+    if text.is_empty() {
+        return None;
+    }
+    let mut total: u64 = 0;
+    let mut current: Option<u64> = None;
+    for c in text.chars() {
+        match c {
+            '0'...'9' => {
+                let digit = c as u64 - '0' as u64;
+                current = Some(current.unwrap_or(0) * 10 + digit);
+                if current.unwrap() > 0x7fffffff {
+                    return None;
+                }
+            }
+            _ => {
+                let unit = match c.to_ascii_lowercase() {
+                    's' => 1,
+                    'm' => 60,
+                    'h' => 3600,
+                    'd' => 86400,
+                    'w' => 604800,
+                    _ => return None,
+                };
+                match current.take() {
+                    Some(value) => total += value * unit,
+                    None => return None,
+                }
+            }
+        }
+        if total > 0x7fffffff {
+            return None;
+        }
+    }
+    total += current.unwrap_or(0);
+    if total > 0x7fffffff {
+        return None;
+    }
+    Some(total as u32)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Soa {
+    pub mname: Name,
+    pub rname: Name,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+/// Typed resource data.
+///
+/// Types without a dedicated variant are kept as `Unknown` with their raw
+/// wire bytes and are presented in the RFC 3597 `\#` form.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ns(Name),
+    Cname(Name),
+    Ptr(Name),
+    Dname(Name),
+    Soa(Soa),
+    Mx {
+        preference: u16,
+        exchange: Name,
+    },
+    Txt(Vec<Vec<u8>>),
+    Hinfo {
+        cpu: Vec<u8>,
+        os: Vec<u8>,
+    },
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: Name,
+    },
+    Ds {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+    },
+    Sshfp {
+        algorithm: u8,
+        fp_type: u8,
+        fingerprint: Vec<u8>,
+    },
+    Dnskey {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+    },
+    /// The public key record of RFC 2535, now used only for SIG(0)
+    /// (RFC 3445); laid out like DNSKEY.
+    Key {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+    },
+    Tlsa {
+        usage: u8,
+        selector: u8,
+        matching_type: u8,
+        data: Vec<u8>,
+    },
+    Caa {
+        flags: u8,
+        tag: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// A digest of the whole zone (RFC 8976).
+    Zonemd {
+        serial: u32,
+        scheme: u8,
+        hash_algorithm: u8,
+        digest: Vec<u8>,
+    },
+    Unknown {
+        rtype: u16,
+        data: Vec<u8>,
+    },
+}
+
+/// Sequential reader over the rdata of one record in a message.
+struct Reader<'d> {
+    message: &'d [u8],
+    pos: usize,
+    end: usize,
+}
+
+impl<'d> Reader<'d> {
+    fn u8(&mut self) -> Option<u8> {
+        if self.pos + 1 > self.end {
+            return None;
+        }
+        self.pos += 1;
+        Some(self.message[self.pos - 1])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        match (self.u8(), self.u8()) {
+            (Some(high), Some(low)) => Some(((high as u16) << 8) | low as u16),
+            _ => None,
+        }
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        match (self.u16(), self.u16()) {
+            (Some(high), Some(low)) => Some(((high as u32) << 16) | low as u32),
+            _ => None,
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<Vec<u8>> {
+        if self.pos + len > self.end {
+            return None;
+        }
+        self.pos += len;
+        Some(self.message[self.pos - len..self.pos].to_vec())
+    }
+
+    fn rest(&mut self) -> Vec<u8> {
+        let rest = self.message[self.pos..self.end].to_vec();
+        self.pos = self.end;
+        rest
+    }
+
+    fn char_string(&mut self) -> Option<Vec<u8>> {
+        match self.u8() {
+            Some(len) => self.bytes(len as usize),
+            None => None,
+        }
+    }
+
+    fn name(&mut self) -> Option<Name> {
+        if let Some(domain_name) = DomainName::from_message(self.message, self.pos) {
+            if domain_name.end_offset() <= self.end {
+                self.pos = domain_name.end_offset();
+                return Name::from_domain_name(self.message, &domain_name);
+            }
+        }
+        None
+    }
+
+    fn done(&self) -> bool {
+        self.pos == self.end
+    }
+}
+
+fn push_u16(wire: &mut Vec<u8>, value: u16) {
+    wire.push((value >> 8) as u8);
+    wire.push(value as u8);
+}
+
+fn push_u32(wire: &mut Vec<u8>, value: u32) {
+    push_u16(wire, (value >> 16) as u16);
+    push_u16(wire, value as u16);
+}
+
+fn push_char_string(wire: &mut Vec<u8>, value: &[u8]) {
+    wire.push(value.len() as u8);
+    wire.extend(value.iter().cloned());
+}
+
+impl RData {
+    pub fn rtype(&self) -> u16 {
+        match *self {
+            RData::A(_) => TYPE_A,
+            RData::Aaaa(_) => TYPE_AAAA,
+            RData::Ns(_) => TYPE_NS,
+            RData::Cname(_) => TYPE_CNAME,
+            RData::Ptr(_) => TYPE_PTR,
+            RData::Dname(_) => TYPE_DNAME,
+            RData::Soa(_) => TYPE_SOA,
+            RData::Mx { .. } => TYPE_MX,
+            RData::Txt(_) => TYPE_TXT,
+            RData::Hinfo { .. } => TYPE_HINFO,
+            RData::Srv { .. } => TYPE_SRV,
+            RData::Ds { .. } => TYPE_DS,
+            RData::Sshfp { .. } => TYPE_SSHFP,
+            RData::Dnskey { .. } => TYPE_DNSKEY,
+            RData::Key { .. } => TYPE_KEY,
+            RData::Tlsa { .. } => TYPE_TLSA,
+            RData::Caa { .. } => TYPE_CAA,
+            RData::Zonemd { .. } => TYPE_ZONEMD,
+            RData::Unknown { rtype, .. } => rtype,
+        }
+    }
+
+    /// Decodes the rdata found at `start..end` of `message`. Embedded names
+    /// may use compression pointers into the rest of the message.
+    pub fn from_message(rtype: u16, message: &[u8], start: usize, end: usize) -> Option<RData> {
+        if start > end || end > message.len() {
+            return None;
+        }
+        let mut r = Reader {
+            message: message,
+            pos: start,
+            end: end,
+        };
+        let rdata = match rtype {
+            TYPE_A => {
+                match r.bytes(4) {
+                    Some(b) => Some(RData::A(Ipv4Addr::new(b[0], b[1], b[2], b[3]))),
+                    None => None,
+                }
+            }
+            TYPE_AAAA => {
+                match r.bytes(16) {
+                    Some(b) => {
+                        let mut segments = [0u16; 8];
+                        for i in 0..8 {
+                            segments[i] = ((b[2 * i] as u16) << 8) | b[2 * i + 1] as u16;
+                        }
+                        Some(RData::Aaaa(Ipv6Addr::new(segments[0],
+                                                       segments[1],
+                                                       segments[2],
+                                                       segments[3],
+                                                       segments[4],
+                                                       segments[5],
+                                                       segments[6],
+                                                       segments[7])))
+                    }
+                    None => None,
+                }
+            }
+            TYPE_NS => r.name().map(RData::Ns),
+            TYPE_CNAME => r.name().map(RData::Cname),
+            TYPE_PTR => r.name().map(RData::Ptr),
+            TYPE_DNAME => r.name().map(RData::Dname),
+            TYPE_SOA => {
+                match (r.name(), r.name(), r.u32(), r.u32(), r.u32(), r.u32(), r.u32()) {
+                    (Some(mname),
+                     Some(rname),
+                     Some(serial),
+                     Some(refresh),
+                     Some(retry),
+                     Some(expire),
+                     Some(minimum)) => {
+                        Some(RData::Soa(Soa {
+                            mname: mname,
+                            rname: rname,
+                            serial: serial,
+                            refresh: refresh,
+                            retry: retry,
+                            expire: expire,
+                            minimum: minimum,
+                        }))
+                    }
+                    _ => None,
+                }
+            }
+            TYPE_MX => {
+                match (r.u16(), r.name()) {
+                    (Some(preference), Some(exchange)) => {
+                        Some(RData::Mx {
+                            preference: preference,
+                            exchange: exchange,
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            TYPE_TXT => {
+                let mut strings = Vec::new();
+                while !r.done() {
+                    match r.char_string() {
+                        Some(s) => strings.push(s),
+                        None => return None,
+                    }
+                }
+                if strings.is_empty() {
+                    return None;
+                }
+                Some(RData::Txt(strings))
+            }
+            TYPE_HINFO => {
+                match (r.char_string(), r.char_string()) {
+                    (Some(cpu), Some(os)) => {
+                        Some(RData::Hinfo {
+                            cpu: cpu,
+                            os: os,
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            TYPE_SRV => {
+                match (r.u16(), r.u16(), r.u16(), r.name()) {
+                    (Some(priority), Some(weight), Some(port), Some(target)) => {
+                        Some(RData::Srv {
+                            priority: priority,
+                            weight: weight,
+                            port: port,
+                            target: target,
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            TYPE_DS => {
+                match (r.u16(), r.u8(), r.u8()) {
+                    (Some(key_tag), Some(algorithm), Some(digest_type)) => {
+                        Some(RData::Ds {
+                            key_tag: key_tag,
+                            algorithm: algorithm,
+                            digest_type: digest_type,
+                            digest: r.rest(),
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            TYPE_SSHFP => {
+                match (r.u8(), r.u8()) {
+                    (Some(algorithm), Some(fp_type)) => {
+                        Some(RData::Sshfp {
+                            algorithm: algorithm,
+                            fp_type: fp_type,
+                            fingerprint: r.rest(),
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            TYPE_DNSKEY | TYPE_KEY => {
+                match (r.u16(), r.u8(), r.u8()) {
+                    (Some(flags), Some(protocol), Some(algorithm)) if rtype == TYPE_KEY => {
+                        Some(RData::Key {
+                            flags: flags,
+                            protocol: protocol,
+                            algorithm: algorithm,
+                            public_key: r.rest(),
+                        })
+                    }
+                    (Some(flags), Some(protocol), Some(algorithm)) => {
+                        Some(RData::Dnskey {
+                            flags: flags,
+                            protocol: protocol,
+                            algorithm: algorithm,
+                            public_key: r.rest(),
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            TYPE_TLSA => {
+                match (r.u8(), r.u8(), r.u8()) {
+                    (Some(usage), Some(selector), Some(matching_type)) => {
+                        Some(RData::Tlsa {
+                            usage: usage,
+                            selector: selector,
+                            matching_type: matching_type,
+                            data: r.rest(),
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            TYPE_CAA => {
+                match (r.u8(), r.char_string()) {
+                    (Some(flags), Some(tag)) => {
+                        Some(RData::Caa {
+                            flags: flags,
+                            tag: tag,
+                            value: r.rest(),
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            TYPE_ZONEMD => {
+                match (r.u32(), r.u8(), r.u8()) {
+                    (Some(serial), Some(scheme), Some(hash_algorithm)) => {
+                        Some(RData::Zonemd {
+                            serial: serial,
+                            scheme: scheme,
+                            hash_algorithm: hash_algorithm,
+                            digest: r.rest(),
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            _ => {
+                Some(RData::Unknown {
+                    rtype: rtype,
+                    data: r.rest(),
+                })
+            }
+        };
+        if !r.done() {
+            return None;
+        }
+        rdata
+    }
+
+    fn encode(&self, canonical: bool) -> Vec<u8> {
+        let name = |n: &Name| {
+            if canonical {
+                n.to_canonical_wire()
+            } else {
+                n.to_wire()
+            }
+        };
+        let mut wire = Vec::new();
+        match *self {
+            RData::A(ref addr) => wire.extend(addr.octets().iter().cloned()),
+            RData::Aaaa(ref addr) => wire.extend(addr.octets().iter().cloned()),
+            RData::Ns(ref n) |
+            RData::Cname(ref n) |
+            RData::Ptr(ref n) |
+            RData::Dname(ref n) => wire.extend(name(n)),
+            RData::Soa(ref soa) => {
+                wire.extend(name(&soa.mname));
+                wire.extend(name(&soa.rname));
+                push_u32(&mut wire, soa.serial);
+                push_u32(&mut wire, soa.refresh);
+                push_u32(&mut wire, soa.retry);
+                push_u32(&mut wire, soa.expire);
+                push_u32(&mut wire, soa.minimum);
+            }
+            RData::Mx { preference, ref exchange } => {
+                push_u16(&mut wire, preference);
+                wire.extend(name(exchange));
+            }
+            RData::Txt(ref strings) => {
+                for s in strings {
+                    push_char_string(&mut wire, s);
+                }
+            }
+            RData::Hinfo { ref cpu, ref os } => {
+                push_char_string(&mut wire, cpu);
+                push_char_string(&mut wire, os);
+            }
+            RData::Srv { priority, weight, port, ref target } => {
+                push_u16(&mut wire, priority);
+                push_u16(&mut wire, weight);
+                push_u16(&mut wire, port);
+                wire.extend(name(target));
+            }
+            RData::Ds { key_tag, algorithm, digest_type, ref digest } => {
+                push_u16(&mut wire, key_tag);
+                wire.push(algorithm);
+                wire.push(digest_type);
+                wire.extend(digest.iter().cloned());
+            }
+            RData::Sshfp { algorithm, fp_type, ref fingerprint } => {
+                wire.push(algorithm);
+                wire.push(fp_type);
+                wire.extend(fingerprint.iter().cloned());
+            }
+            RData::Dnskey { flags, protocol, algorithm, ref public_key } |
+            RData::Key { flags, protocol, algorithm, ref public_key } => {
+                push_u16(&mut wire, flags);
+                wire.push(protocol);
+                wire.push(algorithm);
+                wire.extend(public_key.iter().cloned());
+            }
+            RData::Tlsa { usage, selector, matching_type, ref data } => {
+                wire.push(usage);
+                wire.push(selector);
+                wire.push(matching_type);
+                wire.extend(data.iter().cloned());
+            }
+            RData::Caa { flags, ref tag, ref value } => {
+                wire.push(flags);
+                push_char_string(&mut wire, tag);
+                wire.extend(value.iter().cloned());
+            }
+            RData::Zonemd { serial, scheme, hash_algorithm, ref digest } => {
+                push_u32(&mut wire, serial);
+                wire.push(scheme);
+                wire.push(hash_algorithm);
+                wire.extend(digest.iter().cloned());
+            }
+            RData::Unknown { ref data, .. } => wire.extend(data.iter().cloned()),
+        }
+        wire
+    }
+
+    /// Uncompressed wire encoding.
+    pub fn to_wire(&self) -> Vec<u8> {
+        self.encode(false)
+    }
+
+    /// Wire encoding with embedded names lowercased (RFC 4034 Section 6.2).
+    pub fn to_canonical_wire(&self) -> Vec<u8> {
+        self.encode(true)
+    }
+
+    /// Parses the rdata fields of a master file entry.
+    ///
+    /// `fields` are the raw tokens following the type, with quotes removed
+    /// but escapes intact. Relative names are completed with `origin`.
+    pub fn parse(rtype: u16, fields: &[&str], origin: Option<&Name>) -> Result<RData, String> {
+        if fields.first() == Some(&"\\#") {
+            return RData::parse_generic(rtype, &fields[1..]);
+        }
+        let mut f = Fields {
+            fields: fields,
+            pos: 0,
+            origin: origin,
+        };
+        let rdata = match rtype {
+            TYPE_A => {
+                match try!(f.next("address")).parse::<Ipv4Addr>() {
+                    Ok(addr) => RData::A(addr),
+                    Err(_) => return Err("invalid IPv4 address".to_string()),
+                }
+            }
+            TYPE_AAAA => {
+                match try!(f.next("address")).parse::<Ipv6Addr>() {
+                    Ok(addr) => RData::Aaaa(addr),
+                    Err(_) => return Err("invalid IPv6 address".to_string()),
+                }
+            }
+            TYPE_NS => RData::Ns(try!(f.name("name server"))),
+            TYPE_CNAME => RData::Cname(try!(f.name("canonical name"))),
+            TYPE_PTR => RData::Ptr(try!(f.name("pointer"))),
+            TYPE_DNAME => RData::Dname(try!(f.name("target"))),
+            TYPE_SOA => {
+                RData::Soa(Soa {
+                    mname: try!(f.name("primary name server")),
+                    rname: try!(f.name("responsible mailbox")),
+                    serial: try!(f.number("serial", 0xffffffff)) as u32,
+                    refresh: try!(f.ttl("refresh")),
+                    retry: try!(f.ttl("retry")),
+                    expire: try!(f.ttl("expire")),
+                    minimum: try!(f.ttl("minimum")),
+                })
+            }
+            TYPE_MX => {
+                RData::Mx {
+                    preference: try!(f.number("preference", 0xffff)) as u16,
+                    exchange: try!(f.name("exchange")),
+                }
+            }
+            TYPE_TXT => {
+                let mut strings = Vec::new();
+                while !f.done() {
+                    strings.push(try!(f.char_string("text")));
+                }
+                if strings.is_empty() {
+                    return Err("missing text".to_string());
+                }
+                RData::Txt(strings)
+            }
+            TYPE_HINFO => {
+                RData::Hinfo {
+                    cpu: try!(f.char_string("cpu")),
+                    os: try!(f.char_string("os")),
+                }
+            }
+            TYPE_SRV => {
+                RData::Srv {
+                    priority: try!(f.number("priority", 0xffff)) as u16,
+                    weight: try!(f.number("weight", 0xffff)) as u16,
+                    port: try!(f.number("port", 0xffff)) as u16,
+                    target: try!(f.name("target")),
+                }
+            }
+            TYPE_DS => {
+                RData::Ds {
+                    key_tag: try!(f.number("key tag", 0xffff)) as u16,
+                    algorithm: try!(f.number("algorithm", 0xff)) as u8,
+                    digest_type: try!(f.number("digest type", 0xff)) as u8,
+                    digest: try!(f.hex_rest("digest")),
+                }
+            }
+            TYPE_SSHFP => {
+                RData::Sshfp {
+                    algorithm: try!(f.number("algorithm", 0xff)) as u8,
+                    fp_type: try!(f.number("fingerprint type", 0xff)) as u8,
+                    fingerprint: try!(f.hex_rest("fingerprint")),
+                }
+            }
+            TYPE_DNSKEY | TYPE_KEY => {
+                let flags = try!(f.number("flags", 0xffff)) as u16;
+                let protocol = try!(f.number("protocol", 0xff)) as u8;
+                let algorithm = try!(f.number("algorithm", 0xff)) as u8;
+                let public_key = try!(f.base64_rest("public key"));
+                if rtype == TYPE_KEY {
+                    RData::Key {
+                        flags: flags,
+                        protocol: protocol,
+                        algorithm: algorithm,
+                        public_key: public_key,
+                    }
+                } else {
+                    RData::Dnskey {
+                        flags: flags,
+                        protocol: protocol,
+                        algorithm: algorithm,
+                        public_key: public_key,
+                    }
+                }
+            }
+            TYPE_TLSA => {
+                RData::Tlsa {
+                    usage: try!(f.number("usage", 0xff)) as u8,
+                    selector: try!(f.number("selector", 0xff)) as u8,
+                    matching_type: try!(f.number("matching type", 0xff)) as u8,
+                    data: try!(f.hex_rest("certificate data")),
+                }
+            }
+            TYPE_CAA => {
+                let flags = try!(f.number("flags", 0xff)) as u8;
+                let tag = try!(f.next("tag"));
+                if tag.is_empty() || !tag.bytes().all(|c| c.is_ascii_alphanumeric()) {
+                    return Err("invalid CAA tag".to_string());
+                }
+                RData::Caa {
+                    flags: flags,
+                    tag: tag.as_bytes().to_vec(),
+                    value: try!(f.char_string("value")),
+                }
+            }
+            TYPE_ZONEMD => {
+                RData::Zonemd {
+                    serial: try!(f.number("serial", 0xffffffff)) as u32,
+                    scheme: try!(f.number("scheme", 0xff)) as u8,
+                    hash_algorithm: try!(f.number("hash algorithm", 0xff)) as u8,
+                    digest: try!(f.hex_rest("digest")),
+                }
+            }
+            _ => {
+                return Err(format!("type {} requires the \\# generic syntax",
+                                   type_name(rtype)))
+            }
+        };
+        if !f.done() {
+            return Err("trailing data after rdata".to_string());
+        }
+        Ok(rdata)
+    }
+
+    /// Parses RFC 3597 generic rdata: a length followed by hex digits.
+    fn parse_generic(rtype: u16, fields: &[&str]) -> Result<RData, String> {
+        let len = match fields.first().map(|l| l.parse::<u16>()) {
+            Some(Ok(len)) => len as usize,
+            _ => return Err("invalid \\# rdata length".to_string()),
+        };
+        let hex = fields[1..].concat();
+        let data = match hex_decode(&hex) {
+            Some(data) => data,
+            None => return Err("invalid hex in \\# rdata".to_string()),
+        };
+        if data.len() != len {
+            return Err(format!("\\# rdata length {} does not match {} octets of data",
+                               len,
+                               data.len()));
+        }
+        match RData::from_message(rtype, &data, 0, data.len()) {
+            Some(rdata) => Ok(rdata),
+            None => Err(format!("malformed {} rdata", type_name(rtype))),
+        }
+    }
+}
+
+/// Cursor over the presentation fields of one record.
+struct Fields<'a, 'b: 'a> {
+    fields: &'a [&'b str],
+    pos: usize,
+    origin: Option<&'a Name>,
+}
+
+impl<'a, 'b> Fields<'a, 'b> {
+    fn done(&self) -> bool {
+        self.pos >= self.fields.len()
+    }
+
+    fn next(&mut self, what: &str) -> Result<&'b str, String> {
+        if self.done() {
+            return Err(format!("missing {}", what));
+        }
+        self.pos += 1;
+        Ok(self.fields[self.pos - 1])
+    }
+
+    fn number(&mut self, what: &str, max: u64) -> Result<u64, String> {
+        match try!(self.next(what)).parse::<u64>() {
+            Ok(value) if value <= max => Ok(value),
+            _ => Err(format!("invalid {}", what)),
+        }
+    }
+
+    fn ttl(&mut self, what: &str) -> Result<u32, String> {
+        match parse_ttl(try!(self.next(what))) {
+            Some(value) => Ok(value),
+            None => Err(format!("invalid {}", what)),
+        }
+    }
+
+    fn name(&mut self, what: &str) -> Result<Name, String> {
+        let text = try!(self.next(what));
+        match Name::parse(text, self.origin) {
+            Some(name) => Ok(name),
+            None => Err(format!("invalid {} '{}'", what, text)),
+        }
+    }
+
+    fn char_string(&mut self, what: &str) -> Result<Vec<u8>, String> {
+        match parse_char_string(try!(self.next(what))) {
+            Some(value) => Ok(value),
+            None => Err(format!("invalid {}", what)),
+        }
+    }
+
+    fn rest(&mut self, what: &str) -> Result<String, String> {
+        if self.done() {
+            return Err(format!("missing {}", what));
+        }
+        let rest = self.fields[self.pos..].concat();
+        self.pos = self.fields.len();
+        Ok(rest)
+    }
+
+    fn hex_rest(&mut self, what: &str) -> Result<Vec<u8>, String> {
+        match hex_decode(&try!(self.rest(what))) {
+            Some(value) => Ok(value),
+            None => Err(format!("invalid hex in {}", what)),
+        }
+    }
+
+    fn base64_rest(&mut self, what: &str) -> Result<Vec<u8>, String> {
+        match base64_decode(&try!(self.rest(what))) {
+            Some(value) => Ok(value),
+            None => Err(format!("invalid base64 in {}", what)),
+        }
+    }
+}
+
+/// Unescapes a character-string (RFC 1035 Section 5.1). The result may be
+/// at most 255 octets.
+pub fn parse_char_string(text: &str) -> Option<Vec<u8>> {
+    let bytes = text.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            result.push(bytes[i]);
+            i += 1;
+        } else if i + 3 < bytes.len() && bytes[i + 1..i + 4].iter().all(|c| c.is_ascii_digit()) {
+            match text[i + 1..i + 4].parse::<u16>() {
+                Ok(value) if value <= 255 => result.push(value as u8),
+                _ => return None,
+            }
+            i += 4;
+        } else if i + 1 < bytes.len() {
+            result.push(bytes[i + 1]);
+            i += 2;
+        } else {
+            return None;
+        }
+    }
+    if result.len() > 255 {
+        return None;
+    }
+    Some(result)
+}
+
+/// Writes a character-string quoted, escaping quotes, backslashes and
+/// non-printable octets.
+pub fn write_char_string(fmt: &mut fmt::Formatter, value: &[u8]) -> fmt::Result {
+    try!(write!(fmt, "\""));
+    for &c in value {
+        match c {
+            b'"' | b'\\' => try!(write!(fmt, "\\{}", c as char)),
+            0x20...0x7e => try!(write!(fmt, "{}", c as char)),
+            _ => try!(write!(fmt, "\\{:03}", c)),
+        }
+    }
+    write!(fmt, "\"")
+}
+
+/// Display adapter returned by `RData::presentation`.
+pub struct Presentation<'a> {
+    rdata: &'a RData,
+    origin: Option<&'a Name>,
+}
+
+impl RData {
+    /// Formats the rdata in master file syntax, writing embedded names
+    /// relative to `origin` where possible.
+    pub fn presentation<'a>(&'a self, origin: Option<&'a Name>) -> Presentation<'a> {
+        Presentation {
+            rdata: self,
+            origin: origin,
+        }
+    }
+}
+
+impl fmt::Display for RData {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.presentation(None).fmt(fmt)
+    }
+}
+
+impl<'a> fmt::Display for Presentation<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let origin = self.origin;
+        match *self.rdata {
+            RData::A(ref addr) => write!(fmt, "{}", addr),
+            RData::Aaaa(ref addr) => write!(fmt, "{}", addr),
+            RData::Ns(ref n) |
+            RData::Cname(ref n) |
+            RData::Ptr(ref n) |
+            RData::Dname(ref n) => write!(fmt, "{}", n.relative(origin)),
+            RData::Soa(ref soa) => {
+                write!(fmt,
+                       "{} {} {} {} {} {} {}",
+                       soa.mname.relative(origin),
+                       soa.rname.relative(origin),
+                       soa.serial,
+                       soa.refresh,
+                       soa.retry,
+                       soa.expire,
+                       soa.minimum)
+            }
+            RData::Mx { preference, ref exchange } => {
+                write!(fmt, "{} {}", preference, exchange.relative(origin))
+            }
+            RData::Txt(ref strings) => {
+                for (i, s) in strings.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(fmt, " "));
+                    }
+                    try!(write_char_string(fmt, s));
+                }
+                Ok(())
+            }
+            RData::Hinfo { ref cpu, ref os } => {
+                try!(write_char_string(fmt, cpu));
+                try!(write!(fmt, " "));
+                write_char_string(fmt, os)
+            }
+            RData::Srv { priority, weight, port, ref target } => {
+                write!(fmt, "{} {} {} {}", priority, weight, port, target.relative(origin))
+            }
+            RData::Ds { key_tag, algorithm, digest_type, ref digest } => {
+                write!(fmt,
+                       "{} {} {} {}",
+                       key_tag,
+                       algorithm,
+                       digest_type,
+                       hex_encode(digest).to_uppercase())
+            }
+            RData::Sshfp { algorithm, fp_type, ref fingerprint } => {
+                write!(fmt, "{} {} {}", algorithm, fp_type, hex_encode(fingerprint))
+            }
+            RData::Dnskey { flags, protocol, algorithm, ref public_key } |
+            RData::Key { flags, protocol, algorithm, ref public_key } => {
+                write!(fmt,
+                       "{} {} {} {}",
+                       flags,
+                       protocol,
+                       algorithm,
+                       base64_encode(public_key))
+            }
+            RData::Tlsa { usage, selector, matching_type, ref data } => {
+                write!(fmt,
+                       "{} {} {} {}",
+                       usage,
+                       selector,
+                       matching_type,
+                       hex_encode(data))
+            }
+            RData::Caa { flags, ref tag, ref value } => {
+                try!(write!(fmt, "{} {} ", flags, String::from_utf8_lossy(tag)));
+                write_char_string(fmt, value)
+            }
+            RData::Zonemd { serial, scheme, hash_algorithm, ref digest } => {
+                write!(fmt,
+                       "{} {} {} {}",
+                       serial,
+                       scheme,
+                       hash_algorithm,
+                       hex_encode(digest))
+            }
+            RData::Unknown { ref data, .. } => {
+                try!(write!(fmt, "\\# {}", data.len()));
+                if !data.is_empty() {
+                    try!(write!(fmt, " {}", hex_encode(data)));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::name::Name;
+
+    fn name(text: &str) -> Name {
+        Name::parse(text, None).unwrap()
+    }
+
+    fn round_trip(rdata: RData) {
+        let wire = rdata.to_wire();
+        assert_eq!(Some(rdata.clone()),
+                   RData::from_message(rdata.rtype(), &wire, 0, wire.len()));
+        let text = rdata.to_string();
+        let fields: Vec<&str> = text.split(' ').map(|f| f.trim_matches('"')).collect();
+        assert_eq!(Ok(rdata.clone()), RData::parse(rdata.rtype(), &fields, None));
+    }
+
+    #[test]
+    fn mnemonics() {
+        assert_eq!(Some(TYPE_AAAA), type_from_name("aaaa"));
+        assert_eq!(Some(65280), type_from_name("TYPE65280"));
+        assert_eq!(None, type_from_name("BOGUS"));
+        assert_eq!("TYPE65280", type_name(65280));
+        assert_eq!(Some(CLASS_CH), class_from_name("ch"));
+        assert_eq!("CLASS7", class_name(7));
+    }
+
+    #[test]
+    fn ttls() {
+        assert_eq!(Some(3600), parse_ttl("3600"));
+        assert_eq!(Some(5400), parse_ttl("1h30m"));
+        assert_eq!(Some(1209600), parse_ttl("2W"));
+        assert_eq!(Some(90061), parse_ttl("1d1h1m1"));
+        assert_eq!(None, parse_ttl("h"));
+        assert_eq!(None, parse_ttl("1x"));
+        assert_eq!(None, parse_ttl("2147483648"));
+    }
+
+    #[test]
+    fn wire_and_text_round_trips() {
+        round_trip(RData::A("192.0.2.1".parse().unwrap()));
+        round_trip(RData::Aaaa("2001:db8::1".parse().unwrap()));
+        round_trip(RData::Ns(name("ns1.example.com.")));
+        round_trip(RData::Mx {
+            preference: 10,
+            exchange: name("mail.example.com."),
+        });
+        round_trip(RData::Soa(Soa {
+            mname: name("ns1.example.com."),
+            rname: name("hostmaster.example.com."),
+            serial: 2015110801,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+        }));
+        round_trip(RData::Srv {
+            priority: 0,
+            weight: 5,
+            port: 5060,
+            target: name("sip.example.com."),
+        });
+        round_trip(RData::Ds {
+            key_tag: 60485,
+            algorithm: 5,
+            digest_type: 1,
+            digest: vec![0x2b, 0xb1, 0x83, 0xaf],
+        });
+        round_trip(RData::Key {
+            flags: 512,
+            protocol: 3,
+            algorithm: 15,
+            public_key: vec![0x4d, 0x1c, 0x80],
+        });
+        round_trip(RData::Zonemd {
+            serial: 2018031900,
+            scheme: 1,
+            hash_algorithm: 1,
+            digest: vec![0xc6, 0x80, 0x90, 0xd9],
+        });
+        round_trip(RData::Unknown {
+            rtype: 65280,
+            data: vec![1, 2, 3],
+        });
+    }
+
+    #[test]
+    fn parse_txt() {
+        let rdata = RData::parse(TYPE_TXT, &["hello world", "a\\\"b", "\\255"], None).unwrap();
+        assert_eq!(RData::Txt(vec![b"hello world".to_vec(), b"a\"b".to_vec(), vec![255]]),
+                   rdata);
+        assert_eq!("\"hello world\" \"a\\\"b\" \"\\255\"", rdata.to_string());
+    }
+
+    #[test]
+    fn parse_relative_names() {
+        let origin = name("example.com.");
+        assert_eq!(Ok(RData::Cname(name("www.example.com."))),
+                   RData::parse(TYPE_CNAME, &["www"], Some(&origin)));
+        assert!(RData::parse(TYPE_CNAME, &["www"], None).is_err());
+    }
+
+    #[test]
+    fn parse_generic() {
+        assert_eq!(Ok(RData::A("10.0.0.1".parse().unwrap())),
+                   RData::parse(TYPE_A, &["\\#", "4", "0A000001"], None));
+        assert_eq!(Ok(RData::Unknown {
+                       rtype: 731,
+                       data: vec![0xab, 0xcd, 0xef],
+                   }),
+                   RData::parse(731, &["\\#", "3", "abcd", "ef"], None));
+        assert!(RData::parse(731, &["\\#", "2", "abcdef"], None).is_err());
+        assert!(RData::parse(731, &["abcdef"], None).is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(RData::parse(TYPE_A, &["192.0.2.256"], None).is_err());
+        assert!(RData::parse(TYPE_MX, &["10"], None).is_err());
+        assert!(RData::parse(TYPE_MX, &["70000", "mx."], None).is_err());
+        assert!(RData::parse(TYPE_A, &["192.0.2.1", "extra"], None).is_err());
+    }
+
+    #[test]
+    fn compressed_names() {
+        // An MX record whose exchange points back at an earlier name.
+        let message = [3, b'c', b'o', b'm', 0, 0, 10, 2, b'm', b'x', 0xc0, 0];
+        assert_eq!(Some(RData::Mx {
+                       preference: 10,
+                       exchange: name("mx.com."),
+                   }),
+                   RData::from_message(TYPE_MX, &message, 5, message.len()));
+        // The exchange must not run past the rdata.
+        assert_eq!(None, RData::from_message(TYPE_MX, &message, 5, message.len() - 1));
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+use super::message::MessageCursor;
+use super::name::Name;
+use super::rdata::{RData, class_name, type_name};
+use super::resource::{Resource, ResourceMut};
+
+/// An owned resource record with typed rdata.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Record {
+    pub name: Name,
+    pub class: u16,
+    pub ttl: u32,
+    pub rdata: RData,
+}
+
+impl Record {
+    pub fn new(name: Name, class: u16, ttl: u32, rdata: RData) -> Record {
+        Record {
+            name: name,
+            class: class,
+            ttl: ttl,
+            rdata: rdata,
+        }
+    }
+
+    pub fn rtype(&self) -> u16 {
+        self.rdata.rtype()
+    }
+
+    /// Copies a resource out of a message.
+    pub fn from_resource(message: &[u8], resource: &Resource) -> Option<Record> {
+        let name = match resource.name().and_then(|n| Name::from_domain_name(message, n)) {
+            Some(name) => name,
+            None => return None,
+        };
+        match (resource.rtype(), resource.rclass(), resource.ttl(), resource.payload_range()) {
+            (Some(rtype), Some(class), Some(ttl), Some(range)) => {
+                RData::from_message(rtype, message, range.start, range.end)
+                    .map(|rdata| Record::new(name, class, ttl, rdata))
+            }
+            _ => None,
+        }
+    }
+
+    /// Copies `count` consecutive resources starting at `at`, returning them
+    /// with the offset just past the last one.
+    pub fn read_section(message: &[u8], at: usize, count: u16) -> Option<(Vec<Record>, usize)> {
+        let mut records = Vec::with_capacity(count as usize);
+        let mut next = at;
+        for _ in 0..count {
+            let resource = match Resource::from_message(message, next) {
+                Some(resource) => resource,
+                None => return None,
+            };
+            match Record::from_resource(message, &resource) {
+                Some(record) => records.push(record),
+                None => return None,
+            }
+            next = resource.end_offset();
+        }
+        Some((records, next))
+    }
+
+    /// Composes the record into a message at the cursor. The owner name is
+    /// compressed against names already in the message.
+    pub fn write_at<'d>(&self,
+                        idx: &mut MessageCursor,
+                        data: &'d mut [u8])
+        -> Option<ResourceMut<'d>> {
+        ResourceMut::at(idx,
+                        data,
+                        &self.name.segments(),
+                        self.rtype(),
+                        self.class,
+                        self.ttl,
+                        &self.rdata.to_wire())
+    }
+
+    /// Uncompressed wire encoding of the whole record.
+    pub fn to_wire(&self) -> Vec<u8> {
+        let rdata = self.rdata.to_wire();
+        let mut wire = self.name.to_wire();
+        let rtype = self.rtype();
+        wire.extend([(rtype >> 8) as u8,
+                     rtype as u8,
+                     (self.class >> 8) as u8,
+                     self.class as u8,
+                     (self.ttl >> 24) as u8,
+                     (self.ttl >> 16) as u8,
+                     (self.ttl >> 8) as u8,
+                     self.ttl as u8,
+                     (rdata.len() >> 8) as u8,
+                     rdata.len() as u8]
+                        .iter()
+                        .cloned());
+        wire.extend(rdata);
+        wire
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "{} {} {} {} {}",
+               self.name,
+               self.ttl,
+               class_name(self.class),
+               type_name(self.rtype()),
+               self.rdata)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::name::Name;
+    use super::super::rdata::{CLASS_IN, RData};
+    use super::super::resource::Resource;
+
+    #[test]
+    fn wire_round_trip() {
+        let record = Record::new(Name::parse("www.example.", None).unwrap(),
+                                 CLASS_IN,
+                                 300,
+                                 RData::A("192.0.2.7".parse().unwrap()));
+        let wire = record.to_wire();
+        let resource = Resource::from_message(&wire[..], 0).unwrap();
+        assert_eq!(wire.len(), resource.end_offset());
+        assert_eq!(Some(record.clone()), Record::from_resource(&wire, &resource));
+        assert_eq!("www.example. 300 IN A 192.0.2.7", record.to_string());
+
+        let mut two = wire.clone();
+        two.extend(wire.iter().cloned());
+        assert_eq!(Some((vec![record.clone(), record], two.len())),
+                   Record::read_section(&two, 0, 2));
+        assert_eq!(None, Record::read_section(&wire, 0, 2));
+    }
+}
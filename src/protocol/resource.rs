@@ -3,6 +3,7 @@ use super::bits::BitData;
 use super::bits::BEU16Field;
 use super::bits::BEU32Field;
 use super::domain_name::DomainName;
+use super::message::MessageCursor;
 use std::ops::Range;
 
 const TYPE: BEU16Field = BEU16Field { index: 0 };
@@ -84,10 +85,68 @@ impl<'d> fmt::Debug for Resource<'d> {
     }
 }
 
+#[derive(Debug)]
+pub struct ResourceMut<'d> {
+    name: DomainName,
+    end: usize,
+    data: &'d mut [u8],
+}
+
+impl<'d> ResourceMut<'d> {
+    /// Writes a resource with already encoded rdata at the cursor.
+    pub fn at<'a, 'b, 'c>(idx: &'a mut MessageCursor,
+                          data: &'d mut [u8],
+                          name: &'b [&'c [u8]],
+                          rtype: u16,
+                          rclass: u16,
+                          ttl: u32,
+                          rdata: &[u8])
+        -> Option<ResourceMut<'d>> {
+        if rdata.len() > 0xffff {
+            return None;
+        }
+        if let Some(name) = DomainName::write_at(idx, data, name) {
+            if let Some(footer_idx) = idx.alloc(SIZE + rdata.len()) {
+                let end = footer_idx.end;
+                {
+                    let footer = &mut data[footer_idx];
+                    TYPE.set(footer, rtype);
+                    CLASS.set(footer, rclass);
+                    TTL.set(footer, ttl);
+                    LENGTH.set(footer, rdata.len() as u16);
+                    footer[SIZE..].clone_from_slice(rdata);
+                }
+                return Some(ResourceMut {
+                    name: name,
+                    end: end,
+                    data: data,
+                });
+            }
+        }
+        None
+    }
+
+    pub fn name(&self) -> &DomainName {
+        &self.name
+    }
+
+    pub fn end_offset(&self) -> usize {
+        self.end
+    }
+
+    /// Rewrites the TTL, as when aging a cached record in place.
+    pub fn set_ttl(&mut self, ttl: u32) -> &mut Self {
+        let at = self.name.end_offset();
+        TTL.set(&mut self.data[at..], ttl);
+        self
+    }
+}
+
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use super::super::message::MessageCursor;
 
     #[test]
     fn payload() {
@@ -117,4 +176,24 @@ mod test {
         assert!(r.payload(data).is_none());
     }
 
+    #[test]
+    fn write_resource() {
+        let buffer = &mut [0u8; 16][..];
+        let idx = &mut MessageCursor::new(buffer.len());
+        let end = ResourceMut::at(idx, buffer, &[&b"x"[..]], 1, 2, 3, &[0xaa, 0xab])
+                      .unwrap()
+                      .set_ttl(4)
+                      .end_offset();
+        assert_eq!(15, end);
+        let r = Resource::from_message(&buffer[..], 0).unwrap();
+        assert_eq!(Some(1), r.rtype());
+        assert_eq!(Some(2), r.rclass());
+        assert_eq!(Some(4), r.ttl());
+        assert_eq!(&[0xaa, 0xab], r.payload(&buffer[..]).unwrap());
+
+        // Does not fit:
+        let idx = &mut MessageCursor::new(14);
+        assert!(ResourceMut::at(idx, buffer, &[&b"x"[..]], 1, 2, 3, &[0xaa, 0xab]).is_none());
+    }
+
 }
@@ -0,0 +1,113 @@
+//! Iterators over the sections of a message, as `Header` hands them out.
+//! Each stops at the count in the header, or early at the first entry that
+//! runs past the end of the message; `end_offset` tells the two apart.
+
+use super::bits::BitData;
+use super::question::Question;
+use super::resource::Resource;
+
+/// The entries of the question section.
+pub struct Questions<'d, D: 'd + ?Sized> {
+    message: &'d D,
+    at: usize,
+    remaining: u16,
+    failed: bool,
+}
+
+impl<'d, D: 'd + ?Sized> Questions<'d, D> where D: BitData<Slice = [u8]> {
+    pub fn new(message: &'d D, at: usize, count: u16) -> Questions<'d, D> {
+        Questions {
+            message: message,
+            at: at,
+            remaining: count,
+            failed: false,
+        }
+    }
+
+    /// Reads the rest of the section and returns the offset just past it,
+    /// or None if it is cut short.
+    pub fn end_offset(mut self) -> Option<usize> {
+        while self.next().is_some() {}
+        if self.failed {
+            return None;
+        }
+        Some(self.at)
+    }
+}
+
+impl<'d, D: 'd + ?Sized> Iterator for Questions<'d, D> where D: BitData<Slice = [u8]> {
+    type Item = Question<'d>;
+
+    fn next(&mut self) -> Option<Question<'d>> {
+        if self.remaining == 0 || self.failed {
+            return None;
+        }
+        match Question::from_message(self.message, self.at) {
+            Some(question) => {
+                self.at = question.end_offset();
+                self.remaining -= 1;
+                Some(question)
+            }
+            None => {
+                self.failed = true;
+                None
+            }
+        }
+    }
+}
+
+/// The resource records of the answer, authority or additional section.
+pub struct Resources<'d, D: 'd + ?Sized> {
+    message: &'d D,
+    at: usize,
+    remaining: u16,
+    failed: bool,
+}
+
+impl<'d, D: 'd + ?Sized> Resources<'d, D> where D: BitData<Slice = [u8]> {
+    /// The section of `count` records at `at`, or an empty one that counts
+    /// as cut short if the sections before it were.
+    pub fn new(message: &'d D, at: Option<usize>, count: u16) -> Resources<'d, D> {
+        Resources {
+            message: message,
+            at: at.unwrap_or(0),
+            remaining: count,
+            failed: at.is_none(),
+        }
+    }
+
+    /// Reads the rest of the section and returns the offset just past it,
+    /// or None if it is cut short.
+    pub fn end_offset(mut self) -> Option<usize> {
+        while self.next().is_some() {}
+        if self.failed {
+            return None;
+        }
+        Some(self.at)
+    }
+}
+
+impl<'d, D: 'd + ?Sized> Iterator for Resources<'d, D> where D: BitData<Slice = [u8]> {
+    type Item = Resource<'d>;
+
+    fn next(&mut self) -> Option<Resource<'d>> {
+        if self.remaining == 0 || self.failed {
+            return None;
+        }
+        match Resource::from_message(self.message, self.at) {
+            Some(resource) => {
+                if resource.payload(self.message).is_none() {
+                    self.failed = true;
+                    return None;
+                }
+                self.at = resource.end_offset();
+                self.remaining -= 1;
+                Some(resource)
+            }
+            None => {
+                self.failed = true;
+                None
+            }
+        }
+    }
+}
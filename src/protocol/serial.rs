@@ -0,0 +1,60 @@
+//! Serial number arithmetic (RFC 1982), as used for SOA serials.
+
+use std::cmp::Ordering;
+
+const HALF: u32 = 0x8000_0000;
+
+/// An SOA serial. Comparison wraps around: a serial is greater than the
+/// 2^31 - 1 values behind it, and serials exactly 2^31 apart are unordered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Serial(pub u32);
+
+impl Serial {
+    /// Adds `n`, which may be at most 2^31 - 1 (RFC 1982 Section 3.1).
+    pub fn add(self, n: u32) -> Option<Serial> {
+        if n >= HALF {
+            return None;
+        }
+        Some(Serial(self.0.wrapping_add(n)))
+    }
+}
+
+impl PartialOrd for Serial {
+    fn partial_cmp(&self, other: &Serial) -> Option<Ordering> {
+        let distance = other.0.wrapping_sub(self.0);
+        if distance == 0 {
+            Some(Ordering::Equal)
+        } else if distance < HALF {
+            Some(Ordering::Less)
+        } else if distance > HALF {
+            Some(Ordering::Greater)
+        } else {
+            None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ordering() {
+        assert!(Serial(1) < Serial(2));
+        assert!(Serial(0xffff_ffff) < Serial(0));
+        assert!(Serial(0) > Serial(0xffff_ffff));
+        assert!(Serial(0x7fff_ffff) > Serial(0));
+        assert!(Serial(5) <= Serial(5));
+        // Exactly half way round is undefined.
+        assert_eq!(None, Serial(0).partial_cmp(&Serial(0x8000_0000)));
+        assert!(!(Serial(0) < Serial(0x8000_0000)) && !(Serial(0) > Serial(0x8000_0000)));
+    }
+
+    #[test]
+    fn addition() {
+        assert_eq!(Some(Serial(1)), Serial(0xffff_ffff).add(2));
+        assert_eq!(None, Serial(0).add(0x8000_0000));
+        assert!(Serial(7).add(0x7fff_ffff).unwrap() > Serial(7));
+    }
+}
@@ -0,0 +1,145 @@
+//! The SIG record in its SIG(0) form (RFC 2931 Section 3): a public key
+//! signature over a whole message, carried like TSIG as the last record of
+//! the additional section.
+
+use super::bits::{BEU16Field, BEU32Field};
+use super::header::{Header, HeaderMut};
+use super::name::Name;
+use super::rdata::{CLASS_ANY, TYPE_SIG};
+use super::tsig::{find_last, push_u16, remove_last};
+
+// Offsets into the rdata.
+const TYPE_COVERED: BEU16Field = BEU16Field { index: 0 };
+const ORIGINAL_TTL: BEU32Field = BEU32Field { index: 4 };
+const EXPIRATION: BEU32Field = BEU32Field { index: 8 };
+const INCEPTION: BEU32Field = BEU32Field { index: 12 };
+const KEY_TAG: BEU16Field = BEU16Field { index: 16 };
+const SIGNER: usize = 18;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SigRecord {
+    pub algorithm: u8,
+    /// Seconds since the epoch, in serial number arithmetic.
+    pub expiration: u32,
+    pub inception: u32,
+    pub key_tag: u16,
+    /// The owner of the KEY record that checks the signature.
+    pub signer: Name,
+    pub signature: Vec<u8>,
+}
+
+impl SigRecord {
+    fn from_rdata(rdata: &[u8]) -> Option<SigRecord> {
+        // Type covered, labels and original TTL are all zero in SIG(0).
+        if rdata.len() <= SIGNER || TYPE_COVERED.get(rdata) != Some(0) || rdata[3] != 0 ||
+           ORIGINAL_TTL.get(rdata) != Some(0) {
+            return None;
+        }
+        let signer = match Name::from_message(&rdata[SIGNER..], 0) {
+            Some(signer) => signer,
+            None => return None,
+        };
+        Some(SigRecord {
+            algorithm: rdata[2],
+            expiration: EXPIRATION.get(rdata).unwrap(),
+            inception: INCEPTION.get(rdata).unwrap(),
+            key_tag: KEY_TAG.get(rdata).unwrap(),
+            signature: rdata[SIGNER + signer.wire_len()..].to_vec(),
+            signer: signer,
+        })
+    }
+
+    /// Finds the SIG(0) record of a message. Returns it with the offset
+    /// where it starts, None if the message is unsigned, or Err if it is
+    /// malformed or not last.
+    pub fn find(message: &[u8]) -> Result<Option<(SigRecord, usize)>, ()> {
+        match try!(find_last(message, TYPE_SIG)) {
+            Some((ref owner, rdata, start)) if owner.is_root() => {
+                match SigRecord::from_rdata(rdata) {
+                    Some(sig) => Ok(Some((sig, start))),
+                    None => Err(()),
+                }
+            }
+            Some(_) => Err(()),
+            None => Ok(None),
+        }
+    }
+
+    /// The message as it was before the record at `start` was added.
+    pub fn unsigned(message: &[u8], start: usize) -> Vec<u8> {
+        remove_last(message, start)
+    }
+
+    /// The rdata without the signature, which starts the signed data.
+    pub fn signed_fields(&self) -> Vec<u8> {
+        let mut rdata = vec![0u8; SIGNER];
+        rdata[2] = self.algorithm;
+        EXPIRATION.set(&mut rdata[..], self.expiration);
+        INCEPTION.set(&mut rdata[..], self.inception);
+        KEY_TAG.set(&mut rdata[..], self.key_tag);
+        rdata.extend(self.signer.to_canonical_wire());
+        rdata
+    }
+
+    /// The whole record, ready to append to a message.
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut rdata = self.signed_fields();
+        rdata.extend(self.signature.iter().cloned());
+        let mut wire = Name::root().to_canonical_wire();
+        push_u16(&mut wire, TYPE_SIG);
+        push_u16(&mut wire, CLASS_ANY);
+        wire.extend([0u8; 4].iter().cloned());
+        push_u16(&mut wire, rdata.len() as u16);
+        wire.extend(rdata);
+        wire
+    }
+
+    /// Appends the record to a message, counting it in ARCOUNT.
+    pub fn append_to(&self, message: &mut Vec<u8>) {
+        let ar = Header::at(&message[..]).ar().unwrap_or(0);
+        HeaderMut::at_raw(&mut message[..]).set_ar(ar + 1);
+        message.extend(self.to_wire());
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::{HeaderMut, MessageCursor, Name, QuestionMut, TsigRecord};
+
+    #[test]
+    fn round_trip() {
+        let mut message = vec![0u8; 512];
+        let mut idx = MessageCursor::new(message.len());
+        HeaderMut::at(&mut idx, &mut message[..]).unwrap().make_query(7).set_qd(1);
+        QuestionMut::at(&mut idx,
+                        &mut message,
+                        &Name::parse("example.com.", None).unwrap().segments(),
+                        6,
+                        1)
+            .unwrap();
+        message.truncate(idx.tell());
+        let unsigned = message.clone();
+
+        let sig = SigRecord {
+            algorithm: 15,
+            expiration: 1_500_000_300,
+            inception: 1_499_999_700,
+            key_tag: 12345,
+            signer: Name::parse("Host.Example.com.", None).unwrap(),
+            signature: vec![9; 64],
+        };
+        sig.append_to(&mut message);
+        let (found, start) = SigRecord::find(&message).unwrap().unwrap();
+        assert_eq!(sig, found);
+        assert_eq!(unsigned, SigRecord::unsigned(&message, start));
+        assert_eq!(&sig.to_wire()[11..11 + 18 + 18], &sig.signed_fields()[..]);
+
+        assert_eq!(None, SigRecord::find(&unsigned).unwrap());
+        assert_eq!(None, TsigRecord::find(&message).unwrap());
+        let mut twice = message.clone();
+        sig.append_to(&mut twice);
+        assert!(SigRecord::find(&twice).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! The TSIG meta-record (RFC 8945 Section 4.2), which carries a message's
+//! transaction signature as the last record of the additional section.
+
+use super::bits::{BEU16Field, BEU48Field};
+use super::header::{Header, HeaderMut};
+use super::name::Name;
+use super::question::Question;
+use super::rdata::{CLASS_ANY, TYPE_TSIG};
+use super::resource::Resource;
+
+// Offsets into the rdata past the algorithm name.
+const TIME_SIGNED: BEU48Field = BEU48Field { index: 0 };
+const FUDGE: BEU16Field = BEU16Field { index: 6 };
+const MAC_SIZE: BEU16Field = BEU16Field { index: 8 };
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TsigRecord {
+    pub key_name: Name,
+    pub algorithm: Name,
+    /// Seconds since the epoch; only 48 bits go on the wire.
+    pub time_signed: u64,
+    pub fudge: u16,
+    pub mac: Vec<u8>,
+    pub original_id: u16,
+    pub error: u16,
+    pub other: Vec<u8>,
+}
+
+pub fn push_u16(wire: &mut Vec<u8>, value: u16) {
+    wire.push((value >> 8) as u8);
+    wire.push(value as u8);
+}
+
+/// Finds a meta-record of type `rtype`, which may only be the last record
+/// of the additional section and of class ANY, as TSIG and SIG(0) are.
+/// Returns its owner, rdata and the offset where it starts.
+pub fn find_last(message: &[u8], rtype: u16) -> Result<Option<(Name, &[u8], usize)>, ()> {
+    let header = Header::at(message);
+    let mut next = header.end_offset();
+    for _ in 0..header.qd().unwrap_or(0) {
+        match Question::from_message(message, next) {
+            Some(question) => next = question.end_offset(),
+            None => return Err(()),
+        }
+    }
+    let records = header.an().unwrap_or(0) as usize + header.ns().unwrap_or(0) as usize +
+                  header.ar().unwrap_or(0) as usize;
+    for i in 0..records {
+        let resource = match Resource::from_message(message, next) {
+            Some(resource) => resource,
+            None => return Err(()),
+        };
+        let start = next;
+        next = resource.end_offset();
+        if resource.rtype() != Some(rtype) {
+            continue;
+        }
+        if i + 1 != records || header.ar() == Some(0) || resource.rclass() != Some(CLASS_ANY) {
+            return Err(());
+        }
+        let name = resource.name().and_then(|n| Name::from_domain_name(message, n));
+        return match (name, resource.payload(message)) {
+            (Some(name), Some(rdata)) => Ok(Some((name, rdata, start))),
+            _ => Err(()),
+        };
+    }
+    Ok(None)
+}
+
+/// The message as it was before the meta-record at `start` was added: the
+/// record dropped and ARCOUNT decreased.
+pub fn remove_last(message: &[u8], start: usize) -> Vec<u8> {
+    let mut unsigned = message[..start].to_vec();
+    let ar = Header::at(message).ar().unwrap();
+    HeaderMut::at_raw(&mut unsigned[..]).set_ar(ar - 1);
+    unsigned
+}
+
+impl TsigRecord {
+    fn from_rdata(key_name: Name, rdata: &[u8]) -> Option<TsigRecord> {
+        let algorithm = match Name::from_message(rdata, 0) {
+            Some(algorithm) => algorithm,
+            None => return None,
+        };
+        let fixed = &rdata[algorithm.wire_len()..];
+        let (time_signed, fudge, mac_size) = match (TIME_SIGNED.get(fixed),
+                                                    FUDGE.get(fixed),
+                                                    MAC_SIZE.get(fixed)) {
+            (Some(time), Some(fudge), Some(size)) => (time, fudge, size as usize),
+            _ => return None,
+        };
+        let rest = &fixed[10..];
+        if rest.len() < mac_size + 6 {
+            return None;
+        }
+        let mac = rest[..mac_size].to_vec();
+        let rest = &rest[mac_size..];
+        let field = |at: usize| BEU16Field { index: at }.get(rest).unwrap();
+        let other_len = field(4) as usize;
+        if rest.len() != 6 + other_len {
+            return None;
+        }
+        Some(TsigRecord {
+            key_name: key_name,
+            algorithm: algorithm,
+            time_signed: time_signed,
+            fudge: fudge,
+            mac: mac,
+            original_id: field(0),
+            error: field(2),
+            other: rest[6..].to_vec(),
+        })
+    }
+
+    /// Finds the TSIG record of a message. Returns it with the offset where
+    /// it starts, None if the message is unsigned, or Err if the message is
+    /// malformed or has a TSIG anywhere but last.
+    pub fn find(message: &[u8]) -> Result<Option<(TsigRecord, usize)>, ()> {
+        match try!(find_last(message, TYPE_TSIG)) {
+            Some((name, rdata, start)) => {
+                match TsigRecord::from_rdata(name, rdata) {
+                    Some(tsig) => Ok(Some((tsig, start))),
+                    None => Err(()),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// The message as it was before `tsig`, found at `start`, was added:
+    /// the record dropped, ARCOUNT decreased and the original ID restored.
+    pub fn unsigned(message: &[u8], tsig: &TsigRecord, start: usize) -> Vec<u8> {
+        let mut unsigned = remove_last(message, start);
+        HeaderMut::at_raw(&mut unsigned[..]).set_id(tsig.original_id);
+        unsigned
+    }
+
+    /// The rdata fields covered by the MAC (RFC 8945 Section 4.3.3), or
+    /// only the time and fudge for later messages of a stream.
+    pub fn variables(&self, timers_only: bool) -> Vec<u8> {
+        let mut wire = Vec::new();
+        if !timers_only {
+            wire.extend(self.key_name.to_canonical_wire());
+            push_u16(&mut wire, CLASS_ANY);
+            wire.extend([0u8; 4].iter().cloned());
+            wire.extend(self.algorithm.to_canonical_wire());
+        }
+        let mut timers = [0u8; 8];
+        TIME_SIGNED.set(&mut timers[..], self.time_signed);
+        FUDGE.set(&mut timers[..], self.fudge);
+        wire.extend(timers.iter().cloned());
+        if !timers_only {
+            push_u16(&mut wire, self.error);
+            push_u16(&mut wire, self.other.len() as u16);
+            wire.extend(self.other.iter().cloned());
+        }
+        wire
+    }
+
+    /// The whole record, uncompressed, ready to append to a message.
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut rdata = self.algorithm.to_canonical_wire();
+        let mut fixed = [0u8; 10];
+        TIME_SIGNED.set(&mut fixed[..], self.time_signed);
+        FUDGE.set(&mut fixed[..], self.fudge);
+        MAC_SIZE.set(&mut fixed[..], self.mac.len() as u16);
+        rdata.extend(fixed.iter().cloned());
+        rdata.extend(self.mac.iter().cloned());
+        push_u16(&mut rdata, self.original_id);
+        push_u16(&mut rdata, self.error);
+        push_u16(&mut rdata, self.other.len() as u16);
+        rdata.extend(self.other.iter().cloned());
+
+        let mut wire = self.key_name.to_canonical_wire();
+        push_u16(&mut wire, TYPE_TSIG);
+        push_u16(&mut wire, CLASS_ANY);
+        wire.extend([0u8; 4].iter().cloned());
+        push_u16(&mut wire, rdata.len() as u16);
+        wire.extend(rdata);
+        wire
+    }
+
+    /// Appends the record to a message, counting it in ARCOUNT.
+    pub fn append_to(&self, message: &mut Vec<u8>) {
+        let ar = Header::at(&message[..]).ar().unwrap_or(0);
+        HeaderMut::at_raw(&mut message[..]).set_ar(ar + 1);
+        message.extend(self.to_wire());
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::{HeaderMut, MessageCursor, Name, QuestionMut};
+
+    #[test]
+    fn round_trip() {
+        let mut message = vec![0u8; 512];
+        let mut idx = MessageCursor::new(message.len());
+        HeaderMut::at(&mut idx, &mut message[..]).unwrap().make_query(0x4242).set_qd(1);
+        QuestionMut::at(&mut idx,
+                        &mut message,
+                        &Name::parse("example.com.", None).unwrap().segments(),
+                        1,
+                        1)
+            .unwrap();
+        message.truncate(idx.tell());
+        let unsigned = message.clone();
+
+        let tsig = TsigRecord {
+            key_name: Name::parse("key.example.", None).unwrap(),
+            algorithm: Name::parse("hmac-sha256.", None).unwrap(),
+            time_signed: 0x0001_0203_0405,
+            fudge: 300,
+            mac: vec![7; 32],
+            original_id: 0x4242,
+            error: 0,
+            other: Vec::new(),
+        };
+        tsig.append_to(&mut message);
+        // The ID may change in transit; the original is in the record.
+        HeaderMut::at_raw(&mut message[..]).set_id(1);
+        let (found, start) = TsigRecord::find(&message).unwrap().unwrap();
+        assert_eq!(tsig, found);
+        assert_eq!(unsigned.len(), start);
+        assert_eq!(unsigned, TsigRecord::unsigned(&message, &found, start));
+
+        assert_eq!(None, TsigRecord::find(&unsigned).unwrap());
+        // Only ever last.
+        let mut twice = message.clone();
+        tsig.append_to(&mut twice);
+        assert!(TsigRecord::find(&twice).is_err());
+        assert!(TsigRecord::find(&message[..message.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+//! Fitting messages into UDP datagrams: the size a client accepts, from
+//! its EDNS OPT record (RFC 6891 Section 6.2.3), and cutting a response
+//! down to that size with TC set (RFC 2181 Section 9). Also how long a
+//! response may be cached, which DNS over HTTPS tells HTTP caches.
+
+use super::header::{Header, HeaderMut};
+use super::question::Question;
+use super::rdata::TYPE_OPT;
+use super::resource::Resource;
+
+/// Largest UDP message every client takes, and the least an OPT record
+/// can advertise (RFC 1035 Section 4.2.1).
+pub const MIN_UDP_PAYLOAD: usize = 512;
+
+// The end of the question section and the extent and type of each record,
+// or None for a malformed message.
+fn layout(message: &[u8]) -> Option<(usize, Vec<(usize, usize, u16)>)> {
+    let header = Header::at(message);
+    if message.len() < header.end_offset() {
+        return None;
+    }
+    let mut next = header.end_offset();
+    for _ in 0..header.qd().unwrap_or(0) {
+        match Question::from_message(message, next) {
+            Some(question) => next = question.end_offset(),
+            None => return None,
+        }
+    }
+    let question_end = next;
+    let count = header.an().unwrap_or(0) as usize + header.ns().unwrap_or(0) as usize +
+                header.ar().unwrap_or(0) as usize;
+    let mut records = Vec::with_capacity(count);
+    for _ in 0..count {
+        let resource = match Resource::from_message(message, next) {
+            Some(resource) => resource,
+            None => return None,
+        };
+        if resource.end_offset() > message.len() {
+            return None;
+        }
+        records.push((next, resource.end_offset(), resource.rtype().unwrap()));
+        next = resource.end_offset();
+    }
+    Some((question_end, records))
+}
+
+/// Octets of an OPT record with no options.
+pub const OPT_LEN: usize = 11;
+
+// The payload size in the OPT record of `query`, if it has one.
+fn opt_size(query: &[u8]) -> Option<usize> {
+    let header = Header::at(query);
+    let (_, records) = match layout(query) {
+        Some(layout) => layout,
+        None => return None,
+    };
+    let first_additional = header.an().unwrap_or(0) as usize + header.ns().unwrap_or(0) as usize;
+    for &(start, _, rtype) in &records[first_additional..] {
+        if rtype == TYPE_OPT {
+            let resource = Resource::from_message(query, start).unwrap();
+            return Some(resource.rclass().unwrap() as usize);
+        }
+    }
+    None
+}
+
+/// The largest UDP response the sender of `query` accepts.
+pub fn udp_payload_size(query: &[u8]) -> usize {
+    opt_size(query).map_or(MIN_UDP_PAYLOAD, |size| size.max(MIN_UDP_PAYLOAD))
+}
+
+/// Whether `query` was sent with EDNS, and so expects an OPT record back
+/// (RFC 6891 Section 7).
+pub fn has_edns(query: &[u8]) -> bool {
+    opt_size(query).is_some()
+}
+
+/// Appends an OPT record advertising `payload_size` to the additional
+/// section of `message`.
+pub fn append_opt(message: &mut Vec<u8>, payload_size: u16) {
+    let ar = Header::at(&message[..]).ar().unwrap_or(0);
+    HeaderMut::at_raw(&mut message[..]).set_ar(ar + 1);
+    message.push(0);
+    message.extend_from_slice(&[(TYPE_OPT >> 8) as u8, TYPE_OPT as u8]);
+    message.extend_from_slice(&[(payload_size >> 8) as u8, payload_size as u8]);
+    message.extend_from_slice(&[0; 6]);
+}
+
+/// Cuts a response down to at most `max_len` octets. Additional records
+/// are dropped first, which needs no TC; if the answer and authority
+/// sections do not fit either, only the question is left and TC is set.
+/// The OPT record is kept throughout. Returns None for a malformed message.
+pub fn truncate_for_udp(response: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    if response.len() <= max_len {
+        return Some(response.to_vec());
+    }
+    let (question_end, records) = match layout(response) {
+        Some(layout) => layout,
+        None => return None,
+    };
+    let header = Header::at(response);
+    let first_additional = header.an().unwrap_or(0) as usize + header.ns().unwrap_or(0) as usize;
+    // Names in a record only point back into the message, so any prefix
+    // of the records is still well formed; the OPT owner is the root and
+    // points nowhere.
+    let opt = records[first_additional..]
+                  .iter()
+                  .find(|&&(_, _, rtype)| rtype == TYPE_OPT)
+                  .map(|&(start, end, _)| &response[start..end]);
+    let opt_len = opt.map_or(0, |opt| opt.len());
+    let sections_end = match records.get(first_additional) {
+        Some(&(start, _, _)) => start,
+        None => response.len(),
+    };
+
+    let mut truncated;
+    if sections_end + opt_len <= max_len {
+        let mut end = sections_end;
+        let mut ar = 0;
+        for &(start, record_end, rtype) in &records[first_additional..] {
+            if rtype == TYPE_OPT {
+                continue;
+            }
+            if start != end || record_end + opt_len > max_len {
+                break;
+            }
+            end = record_end;
+            ar += 1;
+        }
+        truncated = response[..end].to_vec();
+        HeaderMut::at_raw(&mut truncated[..]).set_ar(ar);
+    } else {
+        truncated = response[..question_end].to_vec();
+        HeaderMut::at_raw(&mut truncated[..]).set_tc(true).set_an(0).set_ns(0).set_ar(0);
+    }
+    if let Some(opt) = opt {
+        let ar = Header::at(&truncated[..]).ar().unwrap();
+        HeaderMut::at_raw(&mut truncated[..]).set_ar(ar + 1);
+        truncated.extend(opt.iter().cloned());
+    }
+    Some(truncated)
+}
+
+/// The smallest TTL in the answer and authority sections, which bounds how
+/// long the whole response may be cached (RFC 8484 Section 5.1). None if
+/// the sections are empty or the message is malformed.
+pub fn response_ttl(response: &[u8]) -> Option<u32> {
+    let header = Header::at(response);
+    let (_, records) = match layout(response) {
+        Some(layout) => layout,
+        None => return None,
+    };
+    let count = header.an().unwrap_or(0) as usize + header.ns().unwrap_or(0) as usize;
+    records[..count]
+        .iter()
+        .filter_map(|&(start, _, _)| Resource::from_message(response, start).unwrap().ttl())
+        .min()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::{Header, HeaderMut, MessageCursor, Name, QuestionMut, RData, Record};
+    use protocol::rdata::{CLASS_IN, TYPE_A};
+
+    fn opt(size: u16) -> Record {
+        Record::new(Name::root(),
+                    size,
+                    0,
+                    RData::Unknown {
+                        rtype: TYPE_OPT,
+                        data: Vec::new(),
+                    })
+    }
+
+    // A response with `an` answers, `ar` additional A records, and an OPT
+    // record if `edns` is given.
+    fn message(an: u16, ar: u16, edns: Option<u16>) -> Vec<u8> {
+        let mut buffer = vec![0u8; 65535];
+        let mut idx = MessageCursor::new(buffer.len());
+        HeaderMut::at(&mut idx, &mut buffer[..]).unwrap().make_query(7).set_qr(true).set_qd(1);
+        let name = Name::parse("www.example.com.", None).unwrap();
+        QuestionMut::at(&mut idx, &mut buffer, &name.segments(), TYPE_A, CLASS_IN).unwrap();
+        for i in 0..an + ar {
+            let address = format!("192.0.2.{}", i % 250);
+            Record::new(name.clone(), CLASS_IN, 300, RData::A(address.parse().unwrap()))
+                .write_at(&mut idx, &mut buffer)
+                .unwrap();
+        }
+        let mut extra = ar;
+        if let Some(size) = edns {
+            opt(size).write_at(&mut idx, &mut buffer).unwrap();
+            extra += 1;
+        }
+        HeaderMut::at_raw(&mut buffer[..]).set_an(an).set_ar(extra);
+        buffer.truncate(idx.tell());
+        buffer
+    }
+
+    #[test]
+    fn payload_size() {
+        assert_eq!(512, udp_payload_size(&message(0, 0, None)));
+        assert_eq!(1232, udp_payload_size(&message(0, 2, Some(1232))));
+        assert_eq!(512, udp_payload_size(&message(0, 0, Some(100))));
+        assert_eq!(512, udp_payload_size(b"short"));
+        assert!(has_edns(&message(0, 0, Some(100))));
+        assert!(!has_edns(&message(2, 1, None)));
+    }
+
+    #[test]
+    fn opt_appended() {
+        let mut response = message(1, 1, None);
+        let len = response.len();
+        append_opt(&mut response, 1232);
+        assert_eq!(len + OPT_LEN, response.len());
+        assert_eq!(Some(2), Header::at(&response[..]).ar());
+        assert_eq!(message(1, 1, Some(1232)), response);
+    }
+
+    #[test]
+    fn truncation() {
+        // Small enough already.
+        let small = message(2, 0, Some(1232));
+        assert_eq!(Some(small.clone()), truncate_for_udp(&small, 512));
+
+        // Additional records go quietly, the OPT record stays.
+        let response = message(2, 40, Some(1232));
+        let cut = truncate_for_udp(&response, 512).unwrap();
+        assert!(cut.len() <= 512);
+        let header = Header::at(&cut[..]);
+        assert_eq!(Some(false), header.tc());
+        assert_eq!(Some(2), header.an());
+        assert_eq!(1232, udp_payload_size(&cut));
+        assert!(header.ar().unwrap() > 1);
+
+        // Answers that do not fit leave only the question, with TC.
+        let response = message(60, 0, Some(1232));
+        let cut = truncate_for_udp(&response, 512).unwrap();
+        let header = Header::at(&cut[..]);
+        assert_eq!(Some(true), header.tc());
+        assert_eq!((Some(1), Some(0), Some(0), Some(1)),
+                   (header.qd(), header.an(), header.ns(), header.ar()));
+        assert_eq!(1232, udp_payload_size(&cut));
+        let cut = truncate_for_udp(&message(60, 0, None), 512).unwrap();
+        assert_eq!(Some(0), Header::at(&cut[..]).ar());
+        assert_eq!(33, cut.len());
+
+        assert_eq!(None, truncate_for_udp(&response[..600], 512));
+    }
+
+    #[test]
+    fn ttl() {
+        let mut response = message(2, 1, Some(1232));
+        assert_eq!(Some(300), response_ttl(&response));
+        // The second answer's TTL, just before its RDLENGTH and address.
+        let at = response.len() - 11 - 4 - 16 - 6;
+        response[at..at + 4].copy_from_slice(&[0, 0, 0, 60]);
+        assert_eq!(Some(60), response_ttl(&response));
+        assert_eq!(None, response_ttl(&message(0, 1, None)));
+        assert_eq!(None, response_ttl(b"short"));
+    }
+}
@@ -0,0 +1,840 @@
+use std::net::IpAddr;
+use dnssec::{Sig0Error, verify_message};
+use protocol::{HeaderMut, Name, OPT_LEN, SigRecord, OP_NOTIFY, OP_QUERY, OP_UPDATE, RC_FORMAT_ERROR,
+               RC_NOT_AUTH, RC_NOT_IMPLEMENTED, RC_OK, RC_REFUSED, RC_SERVER_ERROR, append_opt,
+               has_edns};
+use protocol::rdata::{CLASS_ANY, TYPE_ANY, TYPE_AXFR, TYPE_IXFR, TYPE_KEY, TYPE_SOA};
+use tsig::{Key, SignedRequest, now, verify_request};
+use zone::{Location, Lookup, ParseError, Zone, apply_update, set_zonemd, verify_zonemd};
+use super::config::{Client, Config};
+use super::request::Request;
+use super::transfer::{self, Responses};
+use super::update::Update;
+
+/// Largest response sent over UDP to clients without EDNS (RFC 1035
+/// Section 4.2.1).
+pub const MAX_UDP_RESPONSE: usize = 512;
+/// Largest response sent over UDP to clients with EDNS, however much more
+/// they take: what fits the IPv6 minimum MTU without fragmenting.
+pub const EDNS_PAYLOAD: usize = 1232;
+/// Largest message that fits the two octet TCP length prefix.
+pub const MAX_TCP_RESPONSE: usize = 65535;
+
+/// Answers queries from a set of zones loaded in memory.
+pub struct Authority {
+    zones: Vec<Zone>,
+    // Zones copied from a primary, which accept NOTIFY.
+    secondaries: Vec<Name>,
+    // Zones that must not be answered from, such as secondaries past
+    // their SOA expire time.
+    expired: Vec<Name>,
+    // Zones open to dynamic update, with the clients allowed to send them.
+    update_clients: Vec<(Name, Vec<Client>)>,
+    // Zones only some clients may transfer; the rest are open to all.
+    transfer_clients: Vec<(Name, Vec<Client>)>,
+    // TSIG keys requests may be signed with.
+    keys: Vec<Key>,
+}
+
+impl Authority {
+    pub fn new() -> Authority {
+        Authority {
+            zones: Vec::new(),
+            secondaries: Vec::new(),
+            expired: Vec::new(),
+            update_clients: Vec::new(),
+            transfer_clients: Vec::new(),
+            keys: Vec::new(),
+        }
+    }
+
+    /// Loads every zone named in the configuration. Secondary zones start
+    /// out empty and expired until their first transfer. Keys are left to
+    /// the caller to load from `config.key_files`.
+    pub fn from_config(config: &Config) -> Result<Authority, ParseError> {
+        let mut authority = Authority::new();
+        for zone in &config.zones {
+            let mut loaded = try!(Zone::load(&zone.path, &zone.origin));
+            match config.digests.iter().find(|d| d.origin == zone.origin) {
+                Some(digest) => set_zonemd(&mut loaded, &digest.hash_algorithms),
+                None => {
+                    if let Err(e) = verify_zonemd(&loaded) {
+                        return Err(ParseError {
+                            location: Location {
+                                file: zone.path.to_string_lossy().into_owned(),
+                                line: 0,
+                                column: 0,
+                            },
+                            message: format!("{}: {}", zone.origin, e),
+                        });
+                    }
+                }
+            }
+            authority.add_zone(loaded);
+        }
+        for secondary in &config.secondaries {
+            authority.add_secondary(secondary.origin.clone());
+        }
+        for update in &config.updates {
+            authority.allow_update(update.origin.clone(), update.clients.clone());
+        }
+        for transfer in &config.transfers {
+            authority.allow_transfer(transfer.origin.clone(), transfer.clients.clone());
+        }
+        // Zones without an allow list go to the secondaries they notify.
+        for notify in &config.notify {
+            if config.transfers.iter().any(|t| t.origin == notify.origin) {
+                continue;
+            }
+            let mut clients = authority.transfer_clients
+                                       .iter()
+                                       .find(|&&(ref o, _)| *o == notify.origin)
+                                       .map_or(Vec::new(), |&(_, ref clients)| clients.clone());
+            clients.extend(notify.targets.iter().map(|t| Client::Address(t.ip())));
+            authority.allow_transfer(notify.origin.clone(), clients);
+        }
+        Ok(authority)
+    }
+
+    /// Adds a TSIG key, replacing any of the same name.
+    pub fn add_key(&mut self, key: Key) {
+        self.keys.retain(|k| k.name != key.name);
+        self.keys.push(key);
+    }
+
+    pub fn key(&self, name: &Name) -> Option<&Key> {
+        self.keys.iter().find(|k| k.name == *name)
+    }
+
+    /// Lets `clients` change the zone at `origin` with dynamic UPDATE.
+    pub fn allow_update(&mut self, origin: Name, clients: Vec<Client>) {
+        self.update_clients.retain(|&(ref o, _)| *o != origin);
+        self.update_clients.push((origin, clients));
+    }
+
+    fn may_update(&self, origin: &Name, client: &IpAddr, key: Option<&Name>) -> bool {
+        self.update_clients
+            .iter()
+            .any(|&(ref o, ref clients)| {
+                o == origin && clients.iter().any(|c| c.matches(client, key))
+            })
+    }
+
+    /// Lets `clients` transfer the zone at `origin`. Zones without clients
+    /// may not be transferred at all.
+    pub fn allow_transfer(&mut self, origin: Name, clients: Vec<Client>) {
+        self.transfer_clients.retain(|&(ref o, _)| *o != origin);
+        self.transfer_clients.push((origin, clients));
+    }
+
+    fn may_transfer(&self, origin: &Name, client: &IpAddr, key: Option<&Name>) -> bool {
+        match self.transfer_clients.iter().find(|&&(ref o, _)| o == origin) {
+            Some(&(_, ref clients)) => clients.iter().any(|c| c.matches(client, key)),
+            None => false,
+        }
+    }
+
+    /// Adds an empty, expired secondary zone to be filled by transfers.
+    pub fn add_secondary(&mut self, origin: Name) {
+        self.add_zone(Zone::new(origin.clone()));
+        self.set_expired(&origin, true);
+        self.secondaries.retain(|o| *o != origin);
+        self.secondaries.push(origin);
+    }
+
+    /// Stops serving a zone, forgetting whatever was set for it.
+    pub fn remove_zone(&mut self, origin: &Name) {
+        self.zones.retain(|z| z.origin() != origin);
+        self.secondaries.retain(|o| o != origin);
+        self.expired.retain(|o| o != origin);
+        self.update_clients.retain(|&(ref o, _)| o != origin);
+        self.transfer_clients.retain(|&(ref o, _)| o != origin);
+    }
+
+    pub fn is_secondary(&self, origin: &Name) -> bool {
+        self.secondaries.contains(origin)
+    }
+
+    /// Stops or resumes answering from a zone.
+    pub fn set_expired(&mut self, origin: &Name, expired: bool) {
+        self.expired.retain(|o| o != origin);
+        if expired {
+            self.expired.push(origin.clone());
+        }
+    }
+
+    pub fn is_expired(&self, origin: &Name) -> bool {
+        self.expired.contains(origin)
+    }
+
+    /// Adds a zone, replacing any already loaded with the same origin.
+    pub fn add_zone(&mut self, zone: Zone) {
+        self.zones.retain(|z| z.origin() != zone.origin());
+        self.zones.push(zone);
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    /// The zone with exactly this origin.
+    pub fn zone_mut(&mut self, origin: &Name) -> Option<&mut Zone> {
+        self.zones.iter_mut().find(|z| z.origin() == origin)
+    }
+
+    /// The most specific zone containing `name`.
+    pub fn zone_for(&self, name: &Name) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .filter(|z| name.is_subdomain_of(z.origin()))
+            .max_by_key(|z| z.origin().label_count())
+    }
+
+    /// Processes a dynamic UPDATE (RFC 2136) from `client`. Returns None if
+    /// the message is not an UPDATE request; otherwise the response, and
+    /// whether the zone named in it changed.
+    ///
+    /// The request may be signed with TSIG or with SIG(0), whose signer
+    /// must have a KEY record in a zone served here. Responses to SIG(0)
+    /// requests go unsigned, since the server holds no private key.
+    pub fn update(&mut self,
+                  message: &[u8],
+                  client: &IpAddr,
+                  max_len: usize)
+        -> Option<(Vec<u8>, bool)> {
+        let request = match Request::parse(message) {
+            Some(request) => request,
+            None => return None,
+        };
+        if request.opcode != OP_UPDATE {
+            return None;
+        }
+        match self.authenticate_sig0(message) {
+            Ok(Some((unsigned, signer))) => {
+                return Some(self.apply(&unsigned, client, Some(&signer), max_len));
+            }
+            Ok(None) => {}
+            Err(error) => return Some((request.error(error.rcode(), max_len), false)),
+        }
+        let signed = match authenticate(&self.keys, message, max_len) {
+            Ok(signed) => signed,
+            Err(response) => return response.map(|r| (r, false)),
+        };
+        let max_len = max_len.saturating_sub(overhead(&signed));
+        let (response, changed) = self.apply(unsigned(&signed, message),
+                                             client,
+                                             signed.as_ref().map(|s| &s.key.name),
+                                             max_len);
+        let mut responses = vec![response];
+        sign(&signed, &mut responses);
+        Some((responses.pop().unwrap(), changed))
+    }
+
+    // Checks the SIG(0) signature of a request, if it has one, against the
+    // signer's KEY records. Gives the request as it was before signing.
+    fn authenticate_sig0(&self, message: &[u8]) -> Result<Option<(Vec<u8>, Name)>, Sig0Error> {
+        let (sig, start) = match SigRecord::find(message) {
+            Ok(Some(found)) => found,
+            Ok(None) => return Ok(None),
+            Err(()) => return Err(Sig0Error::Malformed),
+        };
+        let keys = self.zone_for(&sig.signer)
+                       .and_then(|zone| zone.rrset(&sig.signer, TYPE_KEY))
+                       .map_or(Vec::new(), |rrset| rrset.rdatas.clone());
+        let signer = try!(verify_message(message, None, &keys, now()));
+        Ok(Some((SigRecord::unsigned(message, start), signer)))
+    }
+
+    fn apply(&mut self,
+             message: &[u8],
+             client: &IpAddr,
+             key: Option<&Name>,
+             max_len: usize)
+        -> (Vec<u8>, bool) {
+        let request = Request::parse(message).unwrap();
+        let update = match Update::parse(message, &request) {
+            Ok(update) => update,
+            Err(rcode) => return (request.error(rcode, max_len), false),
+        };
+        let known = self.zones
+                        .iter()
+                        .any(|z| *z.origin() == update.zone && z.class() == update.class);
+        let rcode = if !known {
+            RC_NOT_AUTH
+        } else if self.is_secondary(&update.zone) ||
+                  !self.may_update(&update.zone, client, key) {
+            RC_REFUSED
+        } else if self.is_expired(&update.zone) {
+            RC_SERVER_ERROR
+        } else {
+            let zone = self.zone_mut(&update.zone).unwrap();
+            match apply_update(zone, &update.prerequisites, &update.updates) {
+                Ok(changed) => return (request.error(RC_OK, max_len), changed),
+                Err(rcode) => rcode,
+            }
+        };
+        (request.error(rcode, max_len), false)
+    }
+
+    /// Builds the responses to a message received over a stream transport
+    /// from `client`, which may be many for a zone transfer. Returns None if
+    /// the message should be dropped without an answer.
+    pub fn respond_stream(&self, message: &[u8], client: &IpAddr) -> Option<Responses> {
+        let signed = match authenticate(&self.keys, message, MAX_TCP_RESPONSE) {
+            Ok(signed) => signed,
+            Err(response) => return response.map(Responses::single),
+        };
+        let max_len = MAX_TCP_RESPONSE.saturating_sub(overhead(&signed));
+        let key = signed.as_ref().map(|s| &s.key.name);
+        let mut responses = match self.answer_stream(unsigned(&signed, message),
+                                                     client,
+                                                     key,
+                                                     max_len) {
+            Some(responses) => responses,
+            None => return None,
+        };
+        if let Some(ref signed) = signed {
+            responses.sign_with(signed.signer());
+        }
+        Some(responses)
+    }
+
+    fn answer_stream(&self,
+                     message: &[u8],
+                     client: &IpAddr,
+                     key: Option<&Name>,
+                     max_len: usize)
+        -> Option<Responses> {
+        let request = match Request::parse(message) {
+            Some(request) => request,
+            None => return None,
+        };
+        let zone = match request.query {
+            Some(ref query) if request.opcode == OP_QUERY &&
+                               (query.qtype == TYPE_AXFR || query.qtype == TYPE_IXFR) => {
+                self.zone_for(&query.name).and_then(|zone| {
+                    if zone.origin() == &query.name && zone.class() == query.qclass {
+                        Some(zone)
+                    } else {
+                        None
+                    }
+                })
+            }
+            _ => return self.answer(message, max_len).map(Responses::single),
+        };
+        Some(match zone {
+            Some(zone) if !self.may_transfer(zone.origin(), client, key) => {
+                Responses::single(request.error(RC_REFUSED, max_len))
+            }
+            Some(zone) if self.is_expired(zone.origin()) => {
+                Responses::single(request.error(RC_SERVER_ERROR, max_len))
+            }
+            Some(zone) => transfer::transfer(message, &request, zone, max_len),
+            None => Responses::single(request.error(RC_REFUSED, max_len)),
+        })
+    }
+
+    /// Builds the response to `message`, at most `max_len` octets long.
+    /// Returns None if the message should be dropped without an answer.
+    ///
+    /// Zone transfers need a stream transport, except that IXFR over UDP is
+    /// answered with the current SOA alone so the client knows whether to
+    /// retry over TCP (RFC 1995 Section 2).
+    ///
+    /// Requests signed with TSIG get signed responses; those whose signature
+    /// fails are answered with the TSIG error (RFC 8945 Section 5.2).
+    pub fn respond(&self, message: &[u8], max_len: usize) -> Option<Vec<u8>> {
+        let signed = match authenticate(&self.keys, message, max_len) {
+            Ok(signed) => signed,
+            Err(response) => return response,
+        };
+        let response = self.answer(unsigned(&signed, message),
+                                   max_len.saturating_sub(overhead(&signed)));
+        let mut responses: Vec<Vec<u8>> = response.into_iter().collect();
+        sign(&signed, &mut responses);
+        responses.pop()
+    }
+
+    // Queries with EDNS get an OPT record back (RFC 6891 Section 7).
+    fn answer(&self, message: &[u8], max_len: usize) -> Option<Vec<u8>> {
+        if !has_edns(message) {
+            return self.answer_query(message, max_len);
+        }
+        let mut response = self.answer_query(message, max_len.saturating_sub(OPT_LEN));
+        if let Some(ref mut response) = response {
+            append_opt(response, EDNS_PAYLOAD as u16);
+        }
+        response
+    }
+
+    fn answer_query(&self, message: &[u8], max_len: usize) -> Option<Vec<u8>> {
+        let request = match Request::parse(message) {
+            Some(request) => request,
+            None => return None,
+        };
+        if request.opcode != OP_QUERY && request.opcode != OP_NOTIFY {
+            return Some(request.error(RC_NOT_IMPLEMENTED, max_len));
+        }
+        let query = match request.query {
+            Some(ref query) => query,
+            None => return Some(request.error(RC_FORMAT_ERROR, max_len)),
+        };
+        if request.opcode == OP_NOTIFY {
+            // Acknowledged for our secondary zones only (RFC 1996 Section
+            // 3.10); acting on it is up to the caller.
+            if query.qtype != TYPE_SOA || !self.is_secondary(&query.name) {
+                return Some(request.error(RC_REFUSED, max_len));
+            }
+            let (mut buffer, idx) = request.response(max_len);
+            HeaderMut::at_raw(&mut buffer[..]).set_aa(true);
+            buffer.truncate(idx.tell());
+            return Some(buffer);
+        }
+        if query.qtype == TYPE_AXFR {
+            return Some(request.error(RC_NOT_IMPLEMENTED, max_len));
+        }
+        let zone = match self.zone_for(&query.name) {
+            Some(zone) if query.qclass == zone.class() || query.qclass == CLASS_ANY => zone,
+            _ => return Some(request.error(RC_REFUSED, max_len)),
+        };
+        if self.is_expired(zone.origin()) {
+            return Some(request.error(RC_SERVER_ERROR, max_len));
+        }
+
+        let mut lookup = if query.qtype == TYPE_IXFR {
+            if zone.origin() != &query.name {
+                return Some(request.error(RC_REFUSED, max_len));
+            }
+            zone.lookup(&query.name, TYPE_SOA)
+        } else {
+            zone.lookup(&query.name, query.qtype)
+        };
+        if query.qtype == TYPE_ANY {
+            minimal_any(&mut lookup);
+        }
+        let (mut buffer, mut idx) = request.response(max_len);
+        lookup.write_sections(&mut idx, &mut buffer);
+        buffer.truncate(idx.tell());
+        Some(buffer)
+    }
+}
+
+// Checks the TSIG of a request, if it has one. On failure, gives the
+// response reporting the error, unless the message is not to be answered.
+fn authenticate(keys: &[Key],
+                message: &[u8],
+                max_len: usize)
+    -> Result<Option<SignedRequest>, Option<Vec<u8>>> {
+    match verify_request(message, keys, now()) {
+        Ok(signed) => Ok(signed),
+        Err(error) => {
+            Err(Request::parse(message).map(|request| {
+                let mut response = request.error(error.error.rcode(), max_len);
+                error.sign_response(&mut response, now());
+                response
+            }))
+        }
+    }
+}
+
+// The request as it was before signing.
+fn unsigned<'a>(signed: &'a Option<SignedRequest>, message: &'a [u8]) -> &'a [u8] {
+    signed.as_ref().map_or(message, |s| &s.message[..])
+}
+
+// Room to leave in responses for their signature.
+fn overhead(signed: &Option<SignedRequest>) -> usize {
+    signed.as_ref().map_or(0, |s| s.signer().overhead())
+}
+
+// Signs the responses to a signed request, in order.
+fn sign(signed: &Option<SignedRequest>, responses: &mut [Vec<u8>]) {
+    if let Some(ref signed) = *signed {
+        let mut signer = signed.signer();
+        let now = now();
+        for response in responses {
+            signer.sign(response, now);
+        }
+    }
+}
+
+/// Answers ANY with a single RRset rather than everything at the name
+/// (RFC 8482 Section 4.1).
+fn minimal_any(lookup: &mut Lookup) {
+    if let Some((owner, rtype)) = lookup.answer.first().map(|r| (r.name.clone(), r.rtype())) {
+        lookup.answer.retain(|r| r.name == owner && r.rtype() == rtype);
+        lookup.additional.clear();
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::{Header, HeaderMut, Name, RData, Record, Resource, append_opt, has_edns,
+                   udp_payload_size};
+    use dnssec::{load_private_key, sign_message};
+    use protocol::{OP_NOTIFY, OP_STATUS, RC_FORMAT_ERROR, RC_NAME_ERROR, RC_NOT_AUTH,
+                   RC_NOT_IMPLEMENTED, RC_NX_RRSET, RC_OK, RC_REFUSED, RC_SERVER_ERROR};
+    use protocol::rdata::*;
+    use server::{Client, Config, Update};
+    use tsig::{Algorithm, BADSIG, Key, Signer, TsigError, Verifier, now};
+    use test_util::{class_query, query};
+    use zone::{Zone, verify_zonemd};
+
+    fn authority() -> Authority {
+        let mut authority = Authority::new();
+        authority.add_zone(Zone::load("testdata/example.com.zone",
+                                      &Name::parse("example.com.", None).unwrap())
+                               .unwrap());
+        authority
+    }
+
+    fn respond_stream(authority: &Authority, message: &[u8], client: &IpAddr) -> Vec<Vec<u8>> {
+        authority.respond_stream(message, client).unwrap().collect()
+    }
+
+    fn answers(response: &[u8]) -> Vec<Record> {
+        let header = Header::at(response);
+        let mut next = header.end_offset();
+        for _ in 0..header.qd().unwrap() {
+            next = ::protocol::Question::from_message(response, next).unwrap().end_offset();
+        }
+        let mut records = Vec::new();
+        for _ in 0..header.an().unwrap() {
+            let resource = Resource::from_message(response, next).unwrap();
+            next = resource.end_offset();
+            records.push(Record::from_resource(response, &resource).unwrap());
+        }
+        records
+    }
+
+    #[test]
+    fn authoritative_answer() {
+        let response = authority().respond(&query(0x1234, "www.example.com.", TYPE_A), 512)
+                                  .unwrap();
+        let header = Header::at(&response[..]);
+        assert_eq!(Some(0x1234), header.id());
+        assert!(header.is_response());
+        assert_eq!(Some(true), header.aa());
+        assert_eq!(Some(true), header.rd());
+        assert_eq!(Some(false), header.ra());
+        assert_eq!(Some(RC_OK), header.rc());
+        assert_eq!(Some(1), header.qd());
+        assert_eq!(vec!["www.example.com. 3600 IN A 192.0.2.80".to_string()],
+                   answers(&response).iter().map(|r| r.to_string()).collect::<Vec<_>>());
+
+        let response = authority().respond(&query(0x1234, "nope.example.com.", TYPE_A), 512)
+                                  .unwrap();
+        let header = Header::at(&response[..]);
+        assert_eq!(Some(RC_NAME_ERROR), header.rc());
+        assert_eq!(Some(true), header.aa());
+        assert_eq!(Some(1), header.ns());
+    }
+
+    #[test]
+    fn edns() {
+        let mut message = query(0x1234, "www.example.com.", TYPE_A);
+        append_opt(&mut message, 4096);
+        let response = authority().respond(&message, 4096).unwrap();
+        assert_eq!(Some(1), Header::at(&response[..]).ar());
+        assert_eq!(EDNS_PAYLOAD, udp_payload_size(&response));
+        assert_eq!(1, answers(&response).len());
+
+        // The OPT record is left room for however little there is.
+        let response = authority().respond(&message, 48).unwrap();
+        let header = Header::at(&response[..]);
+        assert!(response.len() <= 48);
+        assert_eq!((Some(true), Some(0), Some(1)), (header.tc(), header.an(), header.ar()));
+        assert!(has_edns(&response));
+
+        let response = authority().respond(&query(0x1234, "www.example.com.", TYPE_A), 512)
+                                  .unwrap();
+        assert!(!has_edns(&response));
+    }
+
+    #[test]
+    fn refused() {
+        let response = authority().respond(&query(0x1234, "example.org.", TYPE_A), 512)
+                                  .unwrap();
+        let header = Header::at(&response[..]);
+        assert_eq!(Some(RC_REFUSED), header.rc());
+        assert_eq!(Some(false), header.aa());
+        assert_eq!(Some(1), header.qd());
+
+        let chaos = class_query(0x1234, "example.com.", TYPE_A, CLASS_CH);
+        let response = authority().respond(&chaos, 512).unwrap();
+        assert_eq!(Some(RC_REFUSED), Header::at(&response[..]).rc());
+    }
+
+    #[test]
+    fn errors() {
+        let mut message = query(0x1234, "example.com.", TYPE_A);
+        HeaderMut::at_raw(&mut message[..]).set_op(OP_STATUS);
+        let response = authority().respond(&message, 512).unwrap();
+        let header = Header::at(&response[..]);
+        assert_eq!(Some(RC_NOT_IMPLEMENTED), header.rc());
+        assert_eq!(Some(OP_STATUS), header.op());
+
+        let mut message = query(0x1234, "example.com.", TYPE_A);
+        HeaderMut::at_raw(&mut message[..]).set_qd(2);
+        let response = authority().respond(&message, 512).unwrap();
+        assert_eq!(Some(RC_FORMAT_ERROR), Header::at(&response[..]).rc());
+
+        // Responses and runts are dropped.
+        let mut message = query(0x1234, "example.com.", TYPE_A);
+        HeaderMut::at_raw(&mut message[..]).set_qr(true);
+        assert_eq!(None, authority().respond(&message, 512));
+        assert_eq!(None, authority().respond(&message[..5], 512));
+    }
+
+    #[test]
+    fn minimal_any() {
+        let response = authority().respond(&query(0x1234, "www.example.com.", TYPE_ANY), 512)
+                                  .unwrap();
+        let answers = answers(&response);
+        assert_eq!(1, answers.len());
+        assert_eq!(TYPE_A, answers[0].rtype());
+        assert_eq!(Some(0), Header::at(&response[..]).ar());
+    }
+
+    #[test]
+    fn transfers() {
+        let mut authority = authority();
+        let client = "192.0.2.9".parse().unwrap();
+        let origin = Name::parse("example.com.", None).unwrap();
+        // Not without an allow list.
+        let messages = respond_stream(&authority,
+                                      &query(0x1234, "example.com.", TYPE_AXFR),
+                                      &client);
+        assert_eq!(Some(RC_REFUSED), Header::at(&messages[0][..]).rc());
+        authority.allow_transfer(origin, vec![Client::Address(client)]);
+        let messages = respond_stream(&authority,
+                                      &query(0x1234, "example.com.", TYPE_AXFR),
+                                      &client);
+        assert_eq!(1, messages.len());
+        assert_eq!(11, answers(&messages[0]).len());
+
+        let messages = respond_stream(&authority,
+                                      &query(0x1234, "www.example.com.", TYPE_AXFR),
+                                      &client);
+        assert_eq!(Some(RC_REFUSED), Header::at(&messages[0][..]).rc());
+
+        // Not over UDP, apart from the IXFR SOA hint.
+        let response = authority.respond(&query(0x1234, "example.com.", TYPE_AXFR), 512)
+                                .unwrap();
+        assert_eq!(Some(RC_NOT_IMPLEMENTED), Header::at(&response[..]).rc());
+        let response = authority.respond(&query(0x1234, "example.com.", TYPE_IXFR), 512)
+                                .unwrap();
+        let answers = answers(&response);
+        assert_eq!(1, answers.len());
+        assert_eq!(TYPE_SOA, answers[0].rtype());
+
+        // Ordinary queries over TCP get a single response.
+        let messages = respond_stream(&authority,
+                                      &query(0x1234, "www.example.com.", TYPE_A),
+                                      &client);
+        assert_eq!(1, messages.len());
+    }
+
+    #[test]
+    fn secondaries() {
+        let mut authority = authority();
+        let origin = Name::parse("example.net.", None).unwrap();
+        authority.add_secondary(origin.clone());
+        let response = authority.respond(&query(0x1234, "www.example.net.", TYPE_A), 512)
+                                .unwrap();
+        assert_eq!(Some(RC_SERVER_ERROR), Header::at(&response[..]).rc());
+
+        let notify = ::server::notify_message(&origin, 3, None);
+        let response = authority.respond(&notify, 512).unwrap();
+        let header = Header::at(&response[..]);
+        assert_eq!(Some(RC_OK), header.rc());
+        assert_eq!(Some(OP_NOTIFY), header.op());
+        assert_eq!(Some(true), header.aa());
+        assert!(header.is_response());
+
+        // Not for zones we are primary for.
+        let notify = ::server::notify_message(&Name::parse("example.com.", None).unwrap(),
+                                              4,
+                                              None);
+        assert_eq!(Some(RC_REFUSED),
+                   Header::at(&authority.respond(&notify, 512).unwrap()[..]).rc());
+
+        // A zone dropped from a catalog is no longer served at all.
+        authority.remove_zone(&origin);
+        assert!(!authority.is_secondary(&origin));
+        let response = authority.respond(&query(0x1234, "www.example.net.", TYPE_A), 512)
+                                .unwrap();
+        assert_eq!(Some(RC_REFUSED), Header::at(&response[..]).rc());
+    }
+
+    #[test]
+    fn updates() {
+        let mut authority = authority();
+        let client = "192.0.2.9".parse().unwrap();
+        let mut update = Update {
+            zone: Name::parse("example.com.", None).unwrap(),
+            class: CLASS_IN,
+            prerequisites: Vec::new(),
+            updates: vec![Record::new(Name::parse("new.example.com.", None).unwrap(),
+                                      CLASS_IN,
+                                      300,
+                                      RData::A("192.0.2.1".parse().unwrap()))],
+        };
+        let rcode = |response: Option<(Vec<u8>, bool)>| {
+            let (response, changed) = response.unwrap();
+            (Header::at(&response[..]).rc().unwrap(), changed)
+        };
+        // Not until the client is allowed to.
+        assert_eq!((RC_REFUSED, false),
+                   rcode(authority.update(&update.to_message(1).unwrap(), &client, 512)));
+        authority.allow_update(update.zone.clone(), vec![Client::Address(client)]);
+        assert_eq!((RC_OK, true),
+                   rcode(authority.update(&update.to_message(2).unwrap(), &client, 512)));
+        let response = authority.respond(&query(0x1234, "new.example.com.", TYPE_A), 512)
+                                .unwrap();
+        assert_eq!(Some(1), Header::at(&response[..]).an());
+
+        update.prerequisites = vec![Record::new(Name::parse("gone.example.com.", None).unwrap(),
+                                                CLASS_ANY,
+                                                0,
+                                                RData::Unknown {
+                                                    rtype: TYPE_A,
+                                                    data: Vec::new(),
+                                                })];
+        assert_eq!((RC_NX_RRSET, false),
+                   rcode(authority.update(&update.to_message(3).unwrap(), &client, 512)));
+
+        update.zone = Name::parse("example.org.", None).unwrap();
+        assert_eq!((RC_NOT_AUTH, false),
+                   rcode(authority.update(&update.to_message(4).unwrap(), &client, 512)));
+        // Other opcodes are left to respond().
+        assert_eq!(None,
+                   authority.update(&query(0x1234, "www.example.com.", TYPE_A), &client, 512));
+    }
+
+    #[test]
+    fn signed_requests() {
+        let mut authority = authority();
+        let key = Key {
+            name: Name::parse("update.example.com.", None).unwrap(),
+            algorithm: Algorithm::HmacSha512,
+            secret: b"update secret".to_vec(),
+        };
+        authority.add_key(key.clone());
+        let mut message = query(0x1234, "www.example.com.", TYPE_A);
+        let mac = Signer::new(key.clone(), None).sign(&mut message, now());
+        let response = authority.respond(&message, 512).unwrap();
+        let response = Verifier::new(key.clone(), mac).verify(&response, now()).unwrap();
+        assert_eq!(1, answers(&response).len());
+
+        // A bad signature gets NOTAUTH and the TSIG error.
+        let other = Key { secret: b"guessed".to_vec(), ..key.clone() };
+        let mut message = query(0x1234, "www.example.com.", TYPE_A);
+        let mac = Signer::new(other, None).sign(&mut message, now());
+        let response = authority.respond(&message, 512).unwrap();
+        assert_eq!(Some(RC_NOT_AUTH), Header::at(&response[..]).rc());
+        assert_eq!(Err(TsigError::Reported(BADSIG)),
+                   Verifier::new(key.clone(), mac).verify(&response, now()));
+
+        // Updates allowed by key, from any address.
+        let update = Update {
+            zone: Name::parse("example.com.", None).unwrap(),
+            class: CLASS_IN,
+            prerequisites: Vec::new(),
+            updates: vec![Record::new(Name::parse("new.example.com.", None).unwrap(),
+                                      CLASS_IN,
+                                      300,
+                                      RData::A("192.0.2.1".parse().unwrap()))],
+        };
+        authority.allow_update(update.zone.clone(), vec![Client::Key(key.name.clone())]);
+        let client = "192.0.2.9".parse().unwrap();
+        let (response, changed) = authority.update(&update.to_message(1).unwrap(), &client, 512)
+                                           .unwrap();
+        assert_eq!((Some(RC_REFUSED), false), (Header::at(&response[..]).rc(), changed));
+        let mut message = update.to_message(2).unwrap();
+        let mac = Signer::new(key.clone(), None).sign(&mut message, now());
+        let (response, changed) = authority.update(&message, &client, 512).unwrap();
+        let response = Verifier::new(key, mac).verify(&response, now()).unwrap();
+        assert_eq!((Some(RC_OK), true), (Header::at(&response[..]).rc(), changed));
+    }
+
+    #[test]
+    fn sig0_updates() {
+        let mut authority = authority();
+        let key = load_private_key("testdata/Kupdate.example.com.+008+55112.private").unwrap();
+        let signer = Name::parse("update.example.com.", None).unwrap();
+        let origin = Name::parse("example.com.", None).unwrap();
+        authority.zone_mut(&origin).unwrap().insert(key.key_record(signer.clone(), 300));
+        authority.allow_update(origin.clone(), vec![Client::Key(signer.clone())]);
+        let update = Update {
+            zone: origin,
+            class: CLASS_IN,
+            prerequisites: Vec::new(),
+            updates: vec![Record::new(Name::parse("new.example.com.", None).unwrap(),
+                                      CLASS_IN,
+                                      300,
+                                      RData::A("192.0.2.1".parse().unwrap()))],
+        };
+        let client = "192.0.2.9".parse().unwrap();
+        let mut update_with = |signer: &Name, id: u16| {
+            let mut message = update.to_message(id).unwrap();
+            sign_message(&mut message, None, signer, &key, now());
+            let (response, changed) = authority.update(&message, &client, 512).unwrap();
+            (Header::at(&response[..]).rc().unwrap(), changed)
+        };
+        assert_eq!((RC_OK, true), update_with(&signer, 1));
+        // No KEY record for this signer.
+        assert_eq!((RC_NOT_AUTH, false),
+                   update_with(&Name::parse("other.example.com.", None).unwrap(), 2));
+    }
+
+    #[test]
+    fn truncation() {
+        let response = authority().respond(&query(0x1234, "example.com.", TYPE_NS), 40)
+                                  .unwrap();
+        let header = Header::at(&response[..]);
+        assert_eq!(Some(true), header.tc());
+        assert!(response.len() <= 40);
+    }
+
+    #[test]
+    fn zone_digests() {
+        let text = "zone example.com. testdata/example.com.zone\n\
+                    zonemd example.com. sha384\n";
+        let config = Config::parse(text, None).unwrap();
+        let authority = Authority::from_config(&config).unwrap();
+        let zone = &authority.zones()[0];
+        assert_eq!(1, zone.rrset(zone.origin(), TYPE_ZONEMD).unwrap().rdatas.len());
+        assert_eq!(Ok(true), verify_zonemd(zone));
+
+        // Without the directive the zone has no digest to check.
+        let config = Config::parse("zone example.com. testdata/example.com.zone\n", None)
+                         .unwrap();
+        let authority = Authority::from_config(&config).unwrap();
+        assert!(authority.zones()[0].rrset(zone.origin(), TYPE_ZONEMD).is_none());
+    }
+
+    #[test]
+    fn transfers_default_to_notify_targets() {
+        let text = "zone example.com. testdata/example.com.zone\n\
+                    notify example.com. 192.0.2.1 192.0.2.2:5300\n";
+        let authority = Authority::from_config(&Config::parse(text, None).unwrap()).unwrap();
+        let axfr = query(0x1234, "example.com.", TYPE_AXFR);
+        for (client, rc) in vec![("192.0.2.2", RC_OK), ("192.0.2.3", RC_REFUSED)] {
+            let messages = respond_stream(&authority, &axfr, &client.parse().unwrap());
+            assert_eq!(Some(rc), Header::at(&messages[0][..]).rc());
+        }
+
+        // An allow list replaces them.
+        let text = "zone example.com. testdata/example.com.zone\n\
+                    notify example.com. 192.0.2.1\n\
+                    allow-transfer example.com. 192.0.2.3\n";
+        let authority = Authority::from_config(&Config::parse(text, None).unwrap()).unwrap();
+        for (client, rc) in vec![("192.0.2.1", RC_REFUSED), ("192.0.2.3", RC_OK)] {
+            let messages = respond_stream(&authority, &axfr, &client.parse().unwrap());
+            assert_eq!(Some(rc), Header::at(&messages[0][..]).rc());
+        }
+    }
+}
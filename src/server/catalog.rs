@@ -0,0 +1,389 @@
+//! Catalog zones (RFC 9432): a zone whose records list the member zones a
+//! secondary should serve, so that adding or removing one is a change to
+//! the catalog rather than to every server's configuration.
+//!
+//! ```text
+//! version.catalog.example.        TXT "2"
+//! a1.zones.catalog.example.       PTR example.com.
+//! group.a1.zones.catalog.example. TXT "signed"
+//! coo.a1.zones.catalog.example.   PTR other-catalog.example.
+//! ```
+
+use protocol::{Name, RData};
+use protocol::rdata::{TYPE_PTR, TYPE_TXT};
+use zone::Zone;
+
+/// The catalog zone schema version understood.
+pub const CATALOG_VERSION: &'static [u8] = b"2";
+
+/// A zone listed in a catalog, with its properties.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    pub zone: Name,
+    /// The catalog listing it.
+    pub catalog: Name,
+    /// The unique label of its member node. A new one asks for the zone to
+    /// be started over.
+    pub id: Name,
+    /// The `group` property, which picks how the zone is served.
+    pub groups: Vec<String>,
+    /// The `coo` (change of ownership) property: the catalog the zone is
+    /// moving to.
+    pub coo: Option<Name>,
+}
+
+/// Reads the members of a catalog zone. Fails if the catalog is of a
+/// version not understood. Member nodes without exactly one PTR record are
+/// broken and left out, as is any later member listing a zone again.
+pub fn catalog_members(zone: &Zone) -> Result<Vec<Member>, &'static str> {
+    let origin = zone.origin();
+    let version = origin.prepend(b"version").and_then(|name| zone.rrset(&name, TYPE_TXT));
+    let supported = version.map_or(false, |rrset| {
+        rrset.rdatas.iter().any(|rdata| {
+            match *rdata {
+                RData::Txt(ref strings) => strings.iter().any(|s| &s[..] == CATALOG_VERSION),
+                _ => false,
+            }
+        })
+    });
+    if !supported {
+        return Err("catalog zone version is not 2");
+    }
+    let zones = origin.prepend(b"zones").unwrap();
+    let mut members: Vec<Member> = Vec::new();
+    for record in zone.records() {
+        if record.rtype() != TYPE_PTR || record.name.parent().as_ref() != Some(&zones) {
+            continue;
+        }
+        let member = match zone.rrset(&record.name, TYPE_PTR) {
+            Some(rrset) if rrset.rdatas.len() == 1 => {
+                match rrset.rdatas[0] {
+                    RData::Ptr(ref target) => target.clone(),
+                    _ => continue,
+                }
+            }
+            _ => continue,
+        };
+        if members.iter().any(|m| m.zone == member) {
+            continue;
+        }
+        let property = |label: &[u8], rtype: u16| {
+            record.name
+                  .prepend(label)
+                  .and_then(|name| zone.rrset(&name, rtype))
+                  .map_or(Vec::new(), |rrset| rrset.rdatas.clone())
+        };
+        let groups = property(b"group", TYPE_TXT)
+                         .into_iter()
+                         .filter_map(|rdata| {
+                             match rdata {
+                                 RData::Txt(strings) => {
+                                     Some(strings.iter()
+                                                 .map(|s| String::from_utf8_lossy(s))
+                                                 .collect())
+                                 }
+                                 _ => None,
+                             }
+                         })
+                         .collect();
+        let coo = property(b"coo", TYPE_PTR).into_iter().filter_map(|rdata| {
+            match rdata {
+                RData::Ptr(target) => Some(target),
+                _ => None,
+            }
+        });
+        members.push(Member {
+            zone: member,
+            catalog: origin.clone(),
+            id: Name::from_labels(vec![record.name.labels()[0].clone()]).unwrap(),
+            groups: groups,
+            coo: coo.last(),
+        });
+    }
+    Ok(members)
+}
+
+/// What to do about a member zone after a catalog changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CatalogChange {
+    /// Start serving a zone as a secondary.
+    Added(Member),
+    /// Stop serving a zone.
+    Removed(Name),
+    /// Throw away the copy of a zone and transfer it afresh.
+    Reset(Member),
+    /// Keep the copy of a zone, but serve it as its new groups or owner
+    /// say.
+    Changed(Member),
+    /// Leave alone a zone configured locally, which no catalog may take.
+    Refused(Member),
+}
+
+/// The catalogs consumed, and which of them owns each member zone. A zone
+/// listed by several catalogs belongs to the first to list it, until its
+/// `coo` property hands it to another. Zones configured locally belong to
+/// none.
+#[derive(Debug)]
+pub struct Catalogs {
+    catalogs: Vec<(Name, Vec<Member>)>,
+    // Member zones, with the catalog owning them.
+    owners: Vec<(Name, Name)>,
+    local: Vec<Name>,
+}
+
+impl Catalogs {
+    pub fn new() -> Catalogs {
+        Catalogs {
+            catalogs: Vec::new(),
+            owners: Vec::new(),
+            local: Vec::new(),
+        }
+    }
+
+    /// Keeps a zone configured locally out of every catalog's hands: it is
+    /// neither added nor removed by them.
+    pub fn add_local(&mut self, zone: Name) {
+        if !self.local.contains(&zone) {
+            self.local.push(zone);
+        }
+    }
+
+    /// Consumes the catalog zone at `origin`. Its members are known once
+    /// `update` is first called.
+    pub fn add_catalog(&mut self, origin: Name) {
+        if !self.is_catalog(&origin) {
+            self.catalogs.push((origin, Vec::new()));
+        }
+    }
+
+    pub fn is_catalog(&self, origin: &Name) -> bool {
+        self.catalogs.iter().any(|&(ref o, _)| o == origin)
+    }
+
+    /// The catalog a member zone belongs to.
+    pub fn owner(&self, zone: &Name) -> Option<&Name> {
+        self.owners.iter().find(|&&(ref z, _)| z == zone).map(|&(_, ref catalog)| catalog)
+    }
+
+    fn members(&self, catalog: &Name) -> &[Member] {
+        self.catalogs
+            .iter()
+            .find(|&&(ref o, _)| o == catalog)
+            .map_or(&[], |&(_, ref members)| &members[..])
+    }
+
+    fn set_owner(&mut self, zone: &Name, catalog: Option<&Name>) {
+        self.owners.retain(|&(ref z, _)| z != zone);
+        if let Some(catalog) = catalog {
+            self.owners.push((zone.clone(), catalog.clone()));
+        }
+    }
+
+    /// Takes in the new member list of a catalog, as `catalog_members`
+    /// read it, and returns the changes to make to the zones served.
+    pub fn update(&mut self, catalog: &Name, members: Vec<Member>) -> Vec<CatalogChange> {
+        let old = self.members(catalog).to_vec();
+        self.catalogs.retain(|&(ref o, _)| o != catalog);
+        self.catalogs.push((catalog.clone(), members.clone()));
+        let mut changes = Vec::new();
+        for member in &members {
+            if self.local.contains(&member.zone) {
+                if !old.iter().any(|m| m.zone == member.zone) {
+                    changes.push(CatalogChange::Refused(member.clone()));
+                }
+                continue;
+            }
+            let owner = self.owner(&member.zone).cloned();
+            match owner {
+                None => {
+                    self.set_owner(&member.zone, Some(catalog));
+                    changes.push(CatalogChange::Added(member.clone()));
+                }
+                Some(ref owner) if owner == catalog => {
+                    match old.iter().find(|m| m.zone == member.zone) {
+                        Some(previous) if previous.id != member.id => {
+                            changes.push(CatalogChange::Reset(member.clone()));
+                        }
+                        Some(previous) if previous.groups != member.groups => {
+                            changes.push(CatalogChange::Changed(member.clone()));
+                        }
+                        _ => {}
+                    }
+                }
+                Some(owner) => {
+                    let handed_over = self.members(&owner)
+                                          .iter()
+                                          .find(|m| m.zone == member.zone)
+                                          .map(|m| m.coo.as_ref() == Some(catalog));
+                    if handed_over == Some(true) {
+                        changes.push(self.migrate(&owner, member));
+                    }
+                }
+            }
+        }
+        for previous in &old {
+            if self.owner(&previous.zone) != Some(catalog) {
+                continue;
+            }
+            let member = members.iter().find(|m| m.zone == previous.zone);
+            // A zone handed to a catalog that already lists it moves now;
+            // otherwise the new owner takes it when it lists it.
+            let successor = member.and_then(|m| m.coo.as_ref())
+                                  .and_then(|coo| {
+                                      self.members(coo)
+                                          .iter()
+                                          .find(|m| m.zone == previous.zone)
+                                          .cloned()
+                                  });
+            match (member, successor) {
+                (_, Some(successor)) => changes.push(self.migrate(catalog, &successor)),
+                (None, None) => {
+                    self.set_owner(&previous.zone, None);
+                    changes.push(CatalogChange::Removed(previous.zone.clone()));
+                }
+                _ => {}
+            }
+        }
+        changes
+    }
+
+    // Moves a zone from `from` to the catalog of `member`. The copy is kept
+    // if the member keeps its unique label (RFC 9432 Section 5.6).
+    fn migrate(&mut self, from: &Name, member: &Member) -> CatalogChange {
+        let same_id = self.members(from)
+                          .iter()
+                          .find(|m| m.zone == member.zone)
+                          .map_or(false, |m| m.id == member.id);
+        self.set_owner(&member.zone, Some(&member.catalog));
+        if same_id {
+            CatalogChange::Changed(member.clone())
+        } else {
+            CatalogChange::Reset(member.clone())
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use protocol::Name;
+    use zone::{Zone, ZoneParser};
+
+    fn name(text: &str) -> Name {
+        Name::parse(text, None).unwrap()
+    }
+
+    fn catalog(origin: &str, members: &str) -> Zone {
+        let text = format!("@ 0 SOA invalid. invalid. 1 3600 600 86400 0\n\
+                            @ 0 NS invalid.\n\
+                            version 0 TXT \"2\"\n{}",
+                           members);
+        let origin = name(origin);
+        let records = ZoneParser::new(Cursor::new(text.into_bytes()),
+                                      "catalog.zone",
+                                      Some(origin.clone()))
+                          .map(|r| r.unwrap());
+        Zone::from_records(origin, records)
+    }
+
+    fn members(origin: &str, text: &str) -> Vec<Member> {
+        catalog_members(&catalog(origin, text)).unwrap()
+    }
+
+    #[test]
+    fn members_and_properties() {
+        let found = members("catalog.example.",
+                            "a1.zones 0 PTR example.com.\n\
+                             group.a1.zones 0 TXT \"signed\"\n\
+                             coo.a1.zones 0 PTR new.example.\n\
+                             b2.zones 0 PTR example.net.\n\
+                             dup.zones 0 PTR example.net.\n\
+                             two.zones 0 PTR example.org.\n\
+                             two.zones 0 PTR example.info.\n\
+                             other 0 PTR example.edu.\n");
+        assert_eq!(vec![Member {
+                            zone: name("example.com."),
+                            catalog: name("catalog.example."),
+                            id: name("a1."),
+                            groups: vec!["signed".to_string()],
+                            coo: Some(name("new.example.")),
+                        },
+                        Member {
+                            zone: name("example.net."),
+                            catalog: name("catalog.example."),
+                            id: name("b2."),
+                            groups: Vec::new(),
+                            coo: None,
+                        }],
+                   found);
+
+        let mut zone = catalog("catalog.example.", "");
+        zone.remove_rrset(&name("version.catalog.example."), TYPE_TXT);
+        assert!(catalog_members(&zone).is_err());
+    }
+
+    #[test]
+    fn changes() {
+        let mut catalogs = Catalogs::new();
+        let (old, new) = (name("catalog.example."), name("new.example."));
+        catalogs.add_catalog(old.clone());
+        catalogs.add_catalog(new.clone());
+        let first = members("catalog.example.",
+                            "a.zones 0 PTR example.com.\nb.zones 0 PTR example.net.\n");
+        assert_eq!(vec![CatalogChange::Added(first[0].clone()),
+                        CatalogChange::Added(first[1].clone())],
+                   catalogs.update(&old, first.clone()));
+        assert_eq!(Some(&old), catalogs.owner(&name("example.com.")));
+        assert_eq!(Vec::<CatalogChange>::new(), catalogs.update(&old, first.clone()));
+
+        // A new unique label resets the zone, a new group only changes it.
+        let second = members("catalog.example.",
+                             "c.zones 0 PTR example.com.\n\
+                              b.zones 0 PTR example.net.\n\
+                              group.b.zones 0 TXT \"slow\"\n");
+        assert_eq!(vec![CatalogChange::Changed(second[0].clone()),
+                        CatalogChange::Reset(second[1].clone())],
+                   catalogs.update(&old, second.clone()));
+
+        // Another catalog cannot take a zone without a change of ownership.
+        let claim = members("new.example.", "c.zones 0 PTR example.net.\n");
+        assert_eq!(Vec::<CatalogChange>::new(), catalogs.update(&new, claim.clone()));
+        let handover = members("catalog.example.",
+                               "c.zones 0 PTR example.com.\n\
+                                b.zones 0 PTR example.net.\n\
+                                group.b.zones 0 TXT \"slow\"\n\
+                                coo.b.zones 0 PTR new.example.\n");
+        assert_eq!(vec![CatalogChange::Reset(claim[0].clone())],
+                   catalogs.update(&old, handover));
+        assert_eq!(Some(&new), catalogs.owner(&name("example.net.")));
+
+        // Dropping a member the old catalog no longer owns leaves it be.
+        let last = members("catalog.example.", "");
+        assert_eq!(vec![CatalogChange::Removed(name("example.com."))],
+                   catalogs.update(&old, last));
+        assert_eq!(None, catalogs.owner(&name("example.com.")));
+    }
+
+    #[test]
+    fn local_zones() {
+        let mut catalogs = Catalogs::new();
+        let origin = name("catalog.example.");
+        catalogs.add_catalog(origin.clone());
+        catalogs.add_local(name("example.com."));
+        let listed = members("catalog.example.",
+                             "a.zones 0 PTR example.com.
+b.zones 0 PTR example.net.
+");
+        assert_eq!(vec![CatalogChange::Refused(listed[0].clone()),
+                        CatalogChange::Added(listed[1].clone())],
+                   catalogs.update(&origin, listed.clone()));
+        assert_eq!(None, catalogs.owner(&name("example.com.")));
+        assert_eq!(Vec::<CatalogChange>::new(), catalogs.update(&origin, listed));
+
+        // Nor is it removed when the catalog drops it.
+        assert_eq!(vec![CatalogChange::Removed(name("example.net."))],
+                   catalogs.update(&origin, Vec::new()));
+    }
+}
@@ -0,0 +1,1092 @@
+//! The server configuration file.
+//!
+//! One directive per line, a `#` starting a word starts a comment:
+//!
+//! ```text
+//! listen 127.0.0.1:5300
+//! zone example.com. zones/example.com.zone
+//! notify example.com. 192.0.2.53
+//! secondary example.net. key transfer.example.net. 192.0.2.1 192.0.2.2:5300
+//! catalog catalog.example. 192.0.2.1
+//! catalog-group signed key transfer.example.net. 192.0.2.2
+//! zonemd example.com. sha384
+//! key-file keys.conf
+//! allow-update example.com. 192.0.2.67 key update.example.com.
+//! allow-transfer example.com. key transfer.example.com.
+//! tls-listen 127.0.0.1:8530
+//! quic-listen 127.0.0.1:8530
+//! https-listen 127.0.0.1:8443
+//! tls-certificate dns.example.pem
+//! tls-key dns.example.key
+//! odoh-key odoh.key
+//! dnscrypt-listen 127.0.0.1:8443
+//! dnscrypt-provider 2.dnscrypt-cert.example.com. provider.key
+//! mdns-publish printer.local. 120 A 192.0.2.5
+//! llmnr-publish printer. 30 A 192.0.2.5
+//! forward 192.0.2.53 198.51.100.53:5353 https://dns.example/dns-query
+//! forward tls://192.0.2.54#dns.example pin pOdRQ0V2YZ1ftJm7kCk1GJnn+Ig8LhGUh5Lm8yfCvV4=
+//! max-connections 512
+//! ```
+//!
+//! Relative zone and key file paths are taken relative to the configuration
+//! file. Addresses of primaries and notify targets default to port 53.
+//! Allow lists name client addresses, or TSIG keys after `key`. Zones
+//! without `allow-transfer` may be transferred only by their `notify`
+//! targets, and by no one if they have none.
+//!
+//! A `catalog` is a secondary zone listing more secondary zones (RFC 9432).
+//! Its members are transferred from the catalog's own primaries, unless
+//! they are in a group with a `catalog-group` line.
+//!
+//! A `zonemd` line gives a zone ZONEMD digests (RFC 8976) made with the
+//! named hash algorithms whenever it is loaded or updated. Other zones are
+//! checked against any digests they already have.
+//!
+//! `tls-listen` serves DNS over TLS (RFC 7858) on an address, port 853 if
+//! none is given, with the PEM certificate chain and private key named by
+//! `tls-certificate` and `tls-key`. `https-listen` serves DNS over HTTPS
+//! (RFC 8484) with the same certificate, port 443 if none is given, and
+//! `http-listen` serves it without TLS, port 80 if none is given, for a
+//! proxy in front to terminate TLS. `quic-listen` serves DNS over QUIC
+//! (RFC 9250) with the same certificate, on UDP port 853 if none is given.
+//! With `odoh-key`, the HTTPS and HTTP listeners are Oblivious DoH targets
+//! (RFC 9230) as well, for the X25519 private key in the PEM file named.
+//!
+//! `dnscrypt-listen` serves DNSCrypt version 2 over UDP and TCP, port 443
+//! if none is given, as the provider named by `dnscrypt-provider` with the
+//! Ed25519 private key in the PEM file after the name. Resolver keys and
+//! their certificates are made as the server runs.
+//!
+//! `mdns-publish` lines give records, in zone file syntax with names
+//! relative to `local.`, to publish over multicast DNS (RFC 6762). PTR
+//! records are shared; the rest are unique to this host, and claimed
+//! before use.
+//!
+//! `llmnr-publish` lines likewise give records, with names relative to the
+//! root, to answer for over LLMNR (RFC 4795) once their names are found
+//! to be unique on the link.
+//!
+//! `forward` gives upstream resolvers to relay queries for names outside
+//! the loaded zones to, caching what they answer. Without it such queries
+//! are refused. An address, port 53 if none is given, is asked over plain
+//! UDP, and TCP for truncated answers. A URI `tls://host[:port]`, port 853
+//! if none is given, is asked over DNS over TLS, one `quic://host[:port]`,
+//! UDP port 853 if none is given, over DNS over QUIC, and one
+//! `https://host[:port]/path`, the path `/dns-query` if none is given,
+//! over DNS over HTTPS. The host may be a name or an address, IPv6 in
+//! brackets, and the server's certificate must be good for it, or for the
+//! name after a `#` at the end. It is checked against the roots of the web
+//! PKI, or those in the PEM file after a `ca` that follows the URI, and
+//! must have the SPKI pin after each `pin` that follows it: with pins
+//! alone, a pin match is enough. After an `https://` URI, `target` and an
+//! `https://host[:port]/path` URI make it an Oblivious DoH proxy (RFC
+//! 9230) for that target, to which queries are sealed for the X25519
+//! public key in base64 after `target-key`. A DNS stamp, `sdns://` as
+//! resolver lists give them, is asked over DNSCrypt.
+//!
+//! `max-connections` caps the TCP, TLS and HTTPS connections open at once
+//! (RFC 7766 Section 6.2.2), `DEFAULT_MAX_CONNECTIONS` if not given. A
+//! connection past the cap closes the longest idle one that has nothing
+//! left to write, or is itself closed if there is none. It caps the QUIC
+//! connections on each `quic-listen` address too, past which new ones are
+//! refused.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use protocol::{Name, RData, Record};
+use protocol::rdata::{CLASS_IN, parse_ttl, type_from_name};
+use client::{DNSCRYPT_PORT, DOQ_PORT, DOT_PORT};
+use dnscrypt::Stamp;
+use protocol::base64_decode;
+use zone::hash_algorithm_from_name;
+use super::doh::DOH_PATH;
+use super::relay::{Forwarder, OdohTarget, TlsForwarder};
+
+pub const DEFAULT_LISTEN: &'static str = "0.0.0.0:5300";
+pub const HTTPS_PORT: u16 = 443;
+pub const HTTP_PORT: u16 = 80;
+pub const DEFAULT_MAX_CONNECTIONS: usize = 512;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Zero when the file could not be read at all.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            return write!(fmt, "{}", self.message);
+        }
+        write!(fmt, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ConfigError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+/// A zone served with authority.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZoneConfig {
+    pub origin: Name,
+    pub path: PathBuf,
+}
+
+/// A zone copied from primary servers by zone transfer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecondaryConfig {
+    pub origin: Name,
+    pub primaries: Vec<SocketAddr>,
+    /// The TSIG key to sign transfer requests with.
+    pub key: Option<Name>,
+}
+
+/// Where the member zones of a catalog group are transferred from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupConfig {
+    pub group: String,
+    pub primaries: Vec<SocketAddr>,
+    pub key: Option<Name>,
+}
+
+/// Hash algorithms to digest a zone with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DigestConfig {
+    pub origin: Name,
+    pub hash_algorithms: Vec<u8>,
+}
+
+/// Servers to send NOTIFY to when a zone changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NotifyConfig {
+    pub origin: Name,
+    pub targets: Vec<SocketAddr>,
+}
+
+/// A client in an allow list: an address, or anyone signing with a key,
+/// either a TSIG key of that name or a SIG(0) key whose KEY record is there.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Client {
+    Address(IpAddr),
+    Key(Name),
+}
+
+impl Client {
+    /// Whether a request from `address`, signed with `key` if any, is this
+    /// client's.
+    pub fn matches(&self, address: &IpAddr, key: Option<&Name>) -> bool {
+        match *self {
+            Client::Address(ref ip) => ip == address,
+            Client::Key(ref name) => Some(name) == key,
+        }
+    }
+}
+
+/// Clients allowed to change a zone with dynamic UPDATE.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdateConfig {
+    pub origin: Name,
+    pub clients: Vec<Client>,
+}
+
+/// Clients allowed to transfer a zone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransferConfig {
+    pub origin: Name,
+    pub clients: Vec<Client>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Addresses to serve on, over both UDP and TCP.
+    pub listen: Vec<SocketAddr>,
+    pub zones: Vec<ZoneConfig>,
+    pub secondaries: Vec<SecondaryConfig>,
+    /// Secondary zones that are catalogs.
+    pub catalogs: Vec<Name>,
+    pub catalog_groups: Vec<GroupConfig>,
+    pub digests: Vec<DigestConfig>,
+    pub notify: Vec<NotifyConfig>,
+    pub updates: Vec<UpdateConfig>,
+    pub transfers: Vec<TransferConfig>,
+    /// BIND style files of TSIG keys.
+    pub key_files: Vec<PathBuf>,
+    /// Addresses to serve DNS over TLS on.
+    pub tls_listen: Vec<SocketAddr>,
+    /// Addresses to serve DNS over HTTPS on, with and without TLS.
+    pub https_listen: Vec<SocketAddr>,
+    pub http_listen: Vec<SocketAddr>,
+    /// Addresses to serve DNS over QUIC on.
+    pub quic_listen: Vec<SocketAddr>,
+    /// PEM files of the certificate chain and private key for TLS.
+    pub tls_certificate: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// The PEM file of the X25519 key for Oblivious DoH.
+    pub odoh_key: Option<PathBuf>,
+    /// Addresses to serve DNSCrypt on.
+    pub dnscrypt_listen: Vec<SocketAddr>,
+    /// The DNSCrypt provider name, and the PEM file of its Ed25519 key.
+    pub dnscrypt_provider: Option<(Name, PathBuf)>,
+    /// Records to publish over multicast DNS.
+    pub mdns_records: Vec<Record>,
+    /// Records to answer for over LLMNR.
+    pub llmnr_records: Vec<Record>,
+    /// Resolvers to relay queries outside the loaded zones to.
+    pub forwarders: Vec<Forwarder>,
+    /// Stream connections open at once, over every listener.
+    pub max_connections: usize,
+}
+
+// An address with an optional port, which defaults to `port`.
+fn parse_address(text: &str, port: u16) -> Option<SocketAddr> {
+    if let Ok(addr) = text.parse() {
+        return Some(addr);
+    }
+    match text.parse::<IpAddr>() {
+        Ok(ip) => Some(SocketAddr::new(ip, port)),
+        Err(_) => None,
+    }
+}
+
+fn parse_server(text: &str) -> Option<SocketAddr> {
+    parse_address(text, 53)
+}
+
+// Servers to transfer from or notify, after an optional TSIG key when
+// `keyed`.
+fn parse_servers(fields: &[&str],
+                 keyed: bool)
+    -> Result<(Option<Name>, Vec<SocketAddr>), String> {
+    let mut key = None;
+    let mut fields = fields;
+    if keyed && fields[0] == "key" && fields.len() > 2 {
+        match Name::parse(fields[1], Some(&Name::root())) {
+            Some(name) => key = Some(name),
+            None => return Err(format!("bad key name {}", fields[1])),
+        }
+        fields = &fields[2..];
+    }
+    let mut servers = Vec::new();
+    for field in fields {
+        match parse_server(field) {
+            Some(addr) => servers.push(addr),
+            None => return Err(format!("bad address {}", field)),
+        }
+    }
+    Ok((key, servers))
+}
+
+// A server in a URI, after the scheme: a name or an address, IPv6 in
+// brackets, with an optional port, which defaults to `port`, and a name
+// for its certificate after a `#` at the end. Returns the server and the
+// path between, if any.
+fn parse_tls_server(text: &str, port: u16) -> Option<(TlsForwarder, &str)> {
+    let (text, name) = match text.find('#') {
+        Some(at) => (&text[..at], Some(&text[at + 1..])),
+        None => (text, None),
+    };
+    let (authority, path) = match text.find('/') {
+        Some(at) => (&text[..at], &text[at..]),
+        None => (text, ""),
+    };
+    let (host, port) = if authority.starts_with('[') {
+        let end = match authority.find(']') {
+            Some(end) => end,
+            None => return None,
+        };
+        let host = &authority[1..end];
+        if host.parse::<IpAddr>().is_err() {
+            return None;
+        }
+        match &authority[end + 1..] {
+            "" => (host, Some(port)),
+            rest if rest.starts_with(':') => (host, rest[1..].parse().ok()),
+            _ => return None,
+        }
+    } else {
+        match authority.find(':') {
+            Some(at) => (&authority[..at], authority[at + 1..].parse().ok()),
+            None => (authority, Some(port)),
+        }
+    };
+    let name = name.unwrap_or(host);
+    match port {
+        Some(port) if !host.is_empty() && !name.is_empty() => {
+            let server = TlsForwarder {
+                host: host.to_string(),
+                port: port,
+                name: name.to_string(),
+                roots: None,
+                pins: Vec::new(),
+            };
+            Some((server, path))
+        }
+        _ => None,
+    }
+}
+
+// An Oblivious DoH target, `https://host[:port]/path`, the path
+// `/dns-query` if none is given, its key to come.
+fn parse_odoh_target(text: &str) -> Option<OdohTarget> {
+    if !text.starts_with("https://") || text.contains('#') {
+        return None;
+    }
+    let (host, path) = match text[8..].find('/') {
+        Some(at) => (&text[8..8 + at], &text[8 + at..]),
+        None => (&text[8..], DOH_PATH),
+    };
+    parse_tls_server(host, HTTPS_PORT).map(|_| {
+        OdohTarget {
+            host: host.to_string(),
+            path: path.to_string(),
+            public_key: Vec::new(),
+        }
+    })
+}
+
+// Sets an option of the upstream it follows: the roots or a pin of one
+// over TLS, or the Oblivious DoH target a DNS over HTTPS one is a proxy
+// for, or that target's key.
+fn set_option(forwarder: Option<&mut Forwarder>,
+              option: &str,
+              value: &str,
+              dir: Option<&Path>)
+    -> Result<(), String> {
+    let forwarder = match forwarder {
+        Some(forwarder) => forwarder,
+        None => return Err(format!("{} follows no upstream", option)),
+    };
+    if option == "target" {
+        let target = match parse_odoh_target(value) {
+            Some(target) => target,
+            None => return Err(format!("bad target {}", value)),
+        };
+        let odoh = match *forwarder {
+            Forwarder::Https(ref server, ref path) => {
+                Forwarder::Odoh(server.clone(), path.clone(), target)
+            }
+            _ => return Err("target follows no upstream over HTTPS".to_string()),
+        };
+        *forwarder = odoh;
+        return Ok(());
+    }
+    if option == "target-key" {
+        let target = match *forwarder {
+            Forwarder::Odoh(_, _, ref mut target) => target,
+            _ => return Err("target-key follows no target".to_string()),
+        };
+        return match base64_decode(value) {
+            Some(ref key) if key.len() == 32 => {
+                target.public_key = key.clone();
+                Ok(())
+            }
+            _ => Err(format!("bad target key {}", value)),
+        };
+    }
+    let server = match *forwarder {
+        Forwarder::Tls(ref mut server) |
+        Forwarder::Https(ref mut server, _) |
+        Forwarder::Quic(ref mut server) |
+        Forwarder::Odoh(ref mut server, _, _) => server,
+        _ => return Err(format!("{} follows no upstream over TLS", option)),
+    };
+    if option == "ca" {
+        server.roots = Some(resolve(value, dir));
+    } else if base64_decode(value).map_or(false, |pin| pin.len() == 32) {
+        server.pins.push(value.to_string());
+    } else {
+        return Err(format!("bad pin {}", value));
+    }
+    Ok(())
+}
+
+// Upstream resolvers, each an address or a URI, the URIs followed by their
+// options.
+fn parse_forwarders(fields: &[&str], dir: Option<&Path>) -> Result<Vec<Forwarder>, String> {
+    let mut forwarders = Vec::new();
+    let mut fields = fields.iter();
+    while let Some(field) = fields.next() {
+        if ["ca", "pin", "target", "target-key"].contains(field) {
+            let value = match fields.next() {
+                Some(value) => value,
+                None => return Err(format!("expected a value after {}", field)),
+            };
+            try!(set_option(forwarders.last_mut(), field, value, dir));
+            continue;
+        }
+        if field.starts_with("tls://") || field.starts_with("quic://") {
+            let quic = field.starts_with("quic://");
+            let (rest, port) = if quic {
+                (&field[7..], DOQ_PORT)
+            } else {
+                (&field[6..], DOT_PORT)
+            };
+            match parse_tls_server(rest, port) {
+                Some((server, "")) if quic => forwarders.push(Forwarder::Quic(server)),
+                Some((server, "")) => forwarders.push(Forwarder::Tls(server)),
+                _ => return Err(format!("bad URI {}", field)),
+            }
+            continue;
+        }
+        if field.starts_with("sdns://") {
+            match Stamp::parse(field) {
+                Some(stamp) => forwarders.push(Forwarder::Dnscrypt(stamp)),
+                None => return Err(format!("bad DNSCrypt stamp {}", field)),
+            }
+            continue;
+        }
+        if field.starts_with("https://") {
+            match parse_tls_server(&field[8..], HTTPS_PORT) {
+                Some((server, "")) => {
+                    forwarders.push(Forwarder::Https(server, DOH_PATH.to_string()))
+                }
+                Some((server, path)) => forwarders.push(Forwarder::Https(server, path.to_string())),
+                None => return Err(format!("bad URI {}", field)),
+            }
+            continue;
+        }
+        match parse_server(field) {
+            Some(addr) => forwarders.push(Forwarder::Udp(addr)),
+            None => return Err(format!("bad address {}", field)),
+        }
+    }
+    for forwarder in &forwarders {
+        if let Forwarder::Odoh(_, _, ref target) = *forwarder {
+            if target.public_key.is_empty() {
+                return Err(format!("no target-key for {}", target.host));
+            }
+        }
+    }
+    Ok(forwarders)
+}
+
+// The arguments of an allow list.
+fn parse_clients(fields: &[&str]) -> Result<Vec<Client>, String> {
+    let mut clients = Vec::new();
+    let mut fields = fields.iter();
+    while let Some(field) = fields.next() {
+        if *field == "key" {
+            match fields.next().and_then(|f| Name::parse(f, Some(&Name::root()))) {
+                Some(name) => clients.push(Client::Key(name)),
+                None => return Err("expected a key name after key".to_string()),
+            }
+            continue;
+        }
+        match field.parse() {
+            Ok(ip) => clients.push(Client::Address(ip)),
+            Err(_) => return Err(format!("bad address {}", field)),
+        }
+    }
+    Ok(clients)
+}
+
+// A path from the configuration file, relative to its directory.
+fn resolve(path: &str, dir: Option<&Path>) -> PathBuf {
+    let mut path = PathBuf::from(path);
+    if let Some(dir) = dir {
+        if path.is_relative() {
+            path = dir.join(path);
+        }
+    }
+    path
+}
+
+impl Config {
+    /// Reads a configuration file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+            return Err(ConfigError {
+                line: 0,
+                message: format!("{}: {}", path.display(), e),
+            });
+        }
+        Config::parse(&text, path.parent())
+    }
+
+    /// Parses configuration text, resolving relative paths against `dir`.
+    pub fn parse(text: &str, dir: Option<&Path>) -> Result<Config, ConfigError> {
+        let mut config = Config {
+            listen: Vec::new(),
+            zones: Vec::new(),
+            secondaries: Vec::new(),
+            catalogs: Vec::new(),
+            catalog_groups: Vec::new(),
+            digests: Vec::new(),
+            notify: Vec::new(),
+            updates: Vec::new(),
+            transfers: Vec::new(),
+            key_files: Vec::new(),
+            tls_listen: Vec::new(),
+            https_listen: Vec::new(),
+            http_listen: Vec::new(),
+            quic_listen: Vec::new(),
+            tls_certificate: None,
+            tls_key: None,
+            odoh_key: None,
+            dnscrypt_listen: Vec::new(),
+            dnscrypt_provider: None,
+            mdns_records: Vec::new(),
+            llmnr_records: Vec::new(),
+            forwarders: Vec::new(),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        };
+        // The first line that needs a certificate, and its directive.
+        let mut tls_line = (0, "");
+        let mut dnscrypt_line = 0;
+        for (i, line) in text.lines().enumerate() {
+            // Only at the start of a word, so a URI may end in `#name`.
+            let comment = line.match_indices('#').map(|(at, _)| at).find(|&at| {
+                line[..at].chars().next_back().map_or(true, char::is_whitespace)
+            });
+            let line = match comment {
+                Some(at) => &line[..at],
+                None => line,
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let error = |message: String| {
+                ConfigError {
+                    line: i + 1,
+                    message: message,
+                }
+            };
+            match (fields[0], fields.len()) {
+                ("listen", 2) => {
+                    match fields[1].parse() {
+                        Ok(addr) => config.listen.push(addr),
+                        Err(_) => return Err(error(format!("bad address {}", fields[1]))),
+                    }
+                }
+                ("zone", 3) => {
+                    let origin = match Name::parse(fields[1], Some(&Name::root())) {
+                        Some(origin) => origin,
+                        None => return Err(error(format!("bad zone name {}", fields[1]))),
+                    };
+                    config.zones.push(ZoneConfig {
+                        origin: origin,
+                        path: resolve(fields[2], dir),
+                    });
+                }
+                ("key-file", 2) => config.key_files.push(resolve(fields[1], dir)),
+                ("tls-listen", 2) | ("https-listen", 2) | ("http-listen", 2) |
+                ("quic-listen", 2) => {
+                    let (list, port) = match fields[0] {
+                        "tls-listen" => (&mut config.tls_listen, DOT_PORT),
+                        "https-listen" => (&mut config.https_listen, HTTPS_PORT),
+                        "quic-listen" => (&mut config.quic_listen, DOQ_PORT),
+                        _ => (&mut config.http_listen, HTTP_PORT),
+                    };
+                    match parse_address(fields[1], port) {
+                        Some(addr) => list.push(addr),
+                        None => return Err(error(format!("bad address {}", fields[1]))),
+                    }
+                    if tls_line.0 == 0 && fields[0] != "http-listen" {
+                        tls_line = (i + 1, fields[0]);
+                    }
+                }
+                ("tls-certificate", 2) => config.tls_certificate = Some(resolve(fields[1], dir)),
+                ("tls-key", 2) => config.tls_key = Some(resolve(fields[1], dir)),
+                ("odoh-key", 2) => config.odoh_key = Some(resolve(fields[1], dir)),
+                ("max-connections", 2) => {
+                    match fields[1].parse() {
+                        Ok(n) if n > 0 => config.max_connections = n,
+                        _ => return Err(error(format!("bad connection count {}", fields[1]))),
+                    }
+                }
+                ("dnscrypt-listen", 2) => {
+                    match parse_address(fields[1], DNSCRYPT_PORT) {
+                        Some(addr) => config.dnscrypt_listen.push(addr),
+                        None => return Err(error(format!("bad address {}", fields[1]))),
+                    }
+                    if dnscrypt_line == 0 {
+                        dnscrypt_line = i + 1;
+                    }
+                }
+                ("dnscrypt-provider", 3) => {
+                    let name = match Name::parse(fields[1], Some(&Name::root())) {
+                        Some(name) => name,
+                        None => return Err(error(format!("bad provider name {}", fields[1]))),
+                    };
+                    config.dnscrypt_provider = Some((name, resolve(fields[2], dir)));
+                }
+                ("mdns-publish", n) | ("llmnr-publish", n) if n > 4 => {
+                    let origin = match fields[0] {
+                        "mdns-publish" => Name::parse("local.", None).unwrap(),
+                        _ => Name::root(),
+                    };
+                    let name = match Name::parse(fields[1], Some(&origin)) {
+                        Some(name) => name,
+                        None => return Err(error(format!("bad name {}", fields[1]))),
+                    };
+                    let ttl = match parse_ttl(fields[2]) {
+                        Some(ttl) => ttl,
+                        None => return Err(error(format!("bad TTL {}", fields[2]))),
+                    };
+                    let rtype = match type_from_name(fields[3]) {
+                        Some(rtype) => rtype,
+                        None => return Err(error(format!("unknown type {}", fields[3]))),
+                    };
+                    let rdata = try!(RData::parse(rtype, &fields[4..], Some(&origin))
+                                         .map_err(&error));
+                    let record = Record::new(name, CLASS_IN, ttl, rdata);
+                    match fields[0] {
+                        "mdns-publish" => config.mdns_records.push(record),
+                        _ => config.llmnr_records.push(record),
+                    }
+                }
+                ("forward", n) if n > 1 => {
+                    let forwarders = try!(parse_forwarders(&fields[1..], dir).map_err(&error));
+                    config.forwarders.extend(forwarders);
+                }
+                ("secondary", n) | ("catalog", n) | ("notify", n) if n > 2 => {
+                    let origin = match Name::parse(fields[1], Some(&Name::root())) {
+                        Some(origin) => origin,
+                        None => return Err(error(format!("bad zone name {}", fields[1]))),
+                    };
+                    let keyed = fields[0] != "notify";
+                    let (key, servers) = try!(parse_servers(&fields[2..], keyed).map_err(&error));
+                    if fields[0] == "notify" {
+                        config.notify.push(NotifyConfig {
+                            origin: origin,
+                            targets: servers,
+                        });
+                        continue;
+                    }
+                    if fields[0] == "catalog" {
+                        config.catalogs.push(origin.clone());
+                    }
+                    config.secondaries.push(SecondaryConfig {
+                        origin: origin,
+                        primaries: servers,
+                        key: key,
+                    });
+                }
+                ("catalog-group", n) if n > 2 => {
+                    let (key, servers) = try!(parse_servers(&fields[2..], true).map_err(&error));
+                    config.catalog_groups.push(GroupConfig {
+                        group: fields[1].to_string(),
+                        primaries: servers,
+                        key: key,
+                    });
+                }
+                ("zonemd", n) if n > 2 => {
+                    let origin = match Name::parse(fields[1], Some(&Name::root())) {
+                        Some(origin) => origin,
+                        None => return Err(error(format!("bad zone name {}", fields[1]))),
+                    };
+                    let mut hash_algorithms = Vec::new();
+                    for field in &fields[2..] {
+                        match hash_algorithm_from_name(field) {
+                            Some(n) => hash_algorithms.push(n),
+                            None => return Err(error(format!("unknown hash algorithm {}", field))),
+                        }
+                    }
+                    config.digests.push(DigestConfig {
+                        origin: origin,
+                        hash_algorithms: hash_algorithms,
+                    });
+                }
+                ("allow-update", n) | ("allow-transfer", n) if n > 2 => {
+                    let origin = match Name::parse(fields[1], Some(&Name::root())) {
+                        Some(origin) => origin,
+                        None => return Err(error(format!("bad zone name {}", fields[1]))),
+                    };
+                    let clients = try!(parse_clients(&fields[2..]).map_err(&error));
+                    if fields[0] == "allow-update" {
+                        config.updates.push(UpdateConfig {
+                            origin: origin,
+                            clients: clients,
+                        });
+                    } else {
+                        config.transfers.push(TransferConfig {
+                            origin: origin,
+                            clients: clients,
+                        });
+                    }
+                }
+                ("listen", _) | ("zone", _) | ("key-file", _) | ("secondary", _) |
+                ("catalog", _) | ("catalog-group", _) | ("notify", _) | ("allow-update", _) |
+                ("allow-transfer", _) | ("zonemd", _) | ("tls-listen", _) |
+                ("https-listen", _) | ("http-listen", _) | ("quic-listen", _) |
+                ("tls-certificate", _) | ("tls-key", _) | ("odoh-key", _) |
+                ("dnscrypt-listen", _) | ("dnscrypt-provider", _) | ("mdns-publish", _) |
+                ("llmnr-publish", _) | ("forward", _) | ("max-connections", _) => {
+                    return Err(error(format!("wrong number of arguments to {}", fields[0])));
+                }
+                (other, _) => return Err(error(format!("unknown directive {}", other))),
+            }
+        }
+        if tls_line.0 > 0 && (config.tls_certificate.is_none() || config.tls_key.is_none()) {
+            return Err(ConfigError {
+                line: tls_line.0,
+                message: format!("{} needs tls-certificate and tls-key", tls_line.1),
+            });
+        }
+        if dnscrypt_line > 0 && config.dnscrypt_provider.is_none() {
+            return Err(ConfigError {
+                line: dnscrypt_line,
+                message: "dnscrypt-listen needs dnscrypt-provider".to_string(),
+            });
+        }
+        if config.listen.is_empty() {
+            config.listen.push(DEFAULT_LISTEN.parse().unwrap());
+        }
+        Ok(config)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::path::{Path, PathBuf};
+    use protocol::{Name, RData, Record};
+    use protocol::rdata::CLASS_IN;
+    use zone::{HASH_SHA384, HASH_SHA512};
+
+    #[test]
+    fn parse() {
+        let text = "# authoritative\n\
+                    listen 127.0.0.1:53\n\
+                    listen [::1]:53 # and v6\n\
+                    \n\
+                    zone example.com zones/example.com.zone\n\
+                    zone example.net. /etc/example.net.zone\n";
+        let config = Config::parse(text, Some(Path::new("/srv"))).unwrap();
+        let listen: Vec<SocketAddr> = vec!["127.0.0.1:53".parse().unwrap(),
+                                           "[::1]:53".parse().unwrap()];
+        assert_eq!(listen, config.listen);
+        assert_eq!(vec![ZoneConfig {
+                            origin: Name::parse("example.com.", None).unwrap(),
+                            path: PathBuf::from("/srv/zones/example.com.zone"),
+                        },
+                        ZoneConfig {
+                            origin: Name::parse("example.net.", None).unwrap(),
+                            path: PathBuf::from("/etc/example.net.zone"),
+                        }],
+                   config.zones);
+    }
+
+    #[test]
+    fn defaults_and_errors() {
+        let config = Config::parse("", None).unwrap();
+        assert_eq!(vec![DEFAULT_LISTEN.parse::<SocketAddr>().unwrap()], config.listen);
+
+        assert_eq!(2, Config::parse("\nlisten nowhere\n", None).unwrap_err().line);
+        assert_eq!(1, Config::parse("zone example.com.\n", None).unwrap_err().line);
+        assert_eq!(1, Config::parse("frobnicate\n", None).unwrap_err().line);
+        assert_eq!(1, Config::parse("secondary example.com.\n", None).unwrap_err().line);
+        assert_eq!(1, Config::parse("notify example.com. nowhere\n", None).unwrap_err().line);
+    }
+
+    #[test]
+    fn secondaries() {
+        let text = "secondary example.net 192.0.2.1 [2001:db8::1]:5300\n\
+                    notify example.com. 192.0.2.53\n\
+                    allow-update example.com. 192.0.2.67 2001:db8::67\n";
+        let config = Config::parse(text, None).unwrap();
+        assert_eq!(vec![SecondaryConfig {
+                            origin: Name::parse("example.net.", None).unwrap(),
+                            primaries: vec!["192.0.2.1:53".parse().unwrap(),
+                                            "[2001:db8::1]:5300".parse().unwrap()],
+                            key: None,
+                        }],
+                   config.secondaries);
+        assert_eq!(vec![NotifyConfig {
+                            origin: Name::parse("example.com.", None).unwrap(),
+                            targets: vec!["192.0.2.53:53".parse().unwrap()],
+                        }],
+                   config.notify);
+        assert_eq!(vec![UpdateConfig {
+                            origin: Name::parse("example.com.", None).unwrap(),
+                            clients: vec![Client::Address("192.0.2.67".parse().unwrap()),
+                                          Client::Address("2001:db8::67".parse().unwrap())],
+                        }],
+                   config.updates);
+        assert_eq!(1,
+                   Config::parse("allow-update example.com. 192.0.2.67:53\n", None)
+                       .unwrap_err()
+                       .line);
+    }
+
+    #[test]
+    fn keys() {
+        let text = "key-file keys.conf
+                    secondary example.net. key xfer. 192.0.2.1
+                    allow-transfer example.com. key xfer. 192.0.2.2
+";
+        let config = Config::parse(text, Some(Path::new("/srv"))).unwrap();
+        let xfer = Name::parse("xfer.", None).unwrap();
+        assert_eq!(vec![PathBuf::from("/srv/keys.conf")], config.key_files);
+        assert_eq!(Some(xfer.clone()), config.secondaries[0].key);
+        assert_eq!(vec!["192.0.2.1:53".parse::<SocketAddr>().unwrap()],
+                   config.secondaries[0].primaries);
+        let clients = &config.transfers[0].clients;
+        assert_eq!(vec![Client::Key(xfer.clone()),
+                        Client::Address("192.0.2.2".parse().unwrap())],
+                   *clients);
+        assert!(clients[0].matches(&"192.0.2.9".parse().unwrap(), Some(&xfer)));
+        assert!(!clients[0].matches(&"192.0.2.9".parse().unwrap(), None));
+        assert!(clients[1].matches(&"192.0.2.2".parse().unwrap(), None));
+        assert_eq!(1,
+                   Config::parse("allow-transfer example.com. key
+", None).unwrap_err().line);
+    }
+
+    #[test]
+    fn catalogs() {
+        let text = "catalog catalog.example. 192.0.2.1\n\
+                    catalog-group signed key xfer. 192.0.2.2 192.0.2.3\n";
+        let config = Config::parse(text, None).unwrap();
+        let catalog = Name::parse("catalog.example.", None).unwrap();
+        assert_eq!(vec![catalog.clone()], config.catalogs);
+        assert_eq!(catalog, config.secondaries[0].origin);
+        assert_eq!(vec![GroupConfig {
+                            group: "signed".to_string(),
+                            primaries: vec!["192.0.2.2:53".parse().unwrap(),
+                                            "192.0.2.3:53".parse().unwrap()],
+                            key: Some(Name::parse("xfer.", None).unwrap()),
+                        }],
+                   config.catalog_groups);
+        assert_eq!(1, Config::parse("catalog-group signed\n", None).unwrap_err().line);
+    }
+
+    #[test]
+    fn digests() {
+        let config = Config::parse("zonemd example.com. sha384 SHA512\n", None).unwrap();
+        assert_eq!(vec![DigestConfig {
+                            origin: Name::parse("example.com.", None).unwrap(),
+                            hash_algorithms: vec![HASH_SHA384, HASH_SHA512],
+                        }],
+                   config.digests);
+        assert_eq!("unknown hash algorithm sha1",
+                   Config::parse("zonemd example.com. sha1\n", None).unwrap_err().message);
+        assert_eq!(1, Config::parse("zonemd example.com.\n", None).unwrap_err().line);
+    }
+
+    #[test]
+    fn tls() {
+        let text = "tls-listen 127.0.0.1
+                    tls-listen [::1]:8530
+                    tls-certificate certs/dns.pem
+                    tls-key /etc/dns.key
+";
+        let config = Config::parse(text, Some(Path::new("/srv"))).unwrap();
+        assert_eq!(vec!["127.0.0.1:853".parse::<SocketAddr>().unwrap(),
+                        "[::1]:8530".parse().unwrap()],
+                   config.tls_listen);
+        assert_eq!(Some(PathBuf::from("/srv/certs/dns.pem")), config.tls_certificate);
+        assert_eq!(Some(PathBuf::from("/etc/dns.key")), config.tls_key);
+        assert_eq!(2,
+                   Config::parse("
+tls-listen 127.0.0.1
+tls-key dns.key
+", None)
+                       .unwrap_err()
+                       .line);
+    }
+
+    #[test]
+    fn https() {
+        let text = "https-listen 127.0.0.1
+                    http-listen [::1]:8080
+                    tls-certificate dns.pem
+                    tls-key dns.key
+                    odoh-key /etc/odoh.key
+";
+        let config = Config::parse(text, None).unwrap();
+        assert_eq!(vec!["127.0.0.1:443".parse::<SocketAddr>().unwrap()], config.https_listen);
+        assert_eq!(vec!["[::1]:8080".parse::<SocketAddr>().unwrap()], config.http_listen);
+        assert_eq!(Some(PathBuf::from("/etc/odoh.key")), config.odoh_key);
+        assert!(config.tls_listen.is_empty());
+
+        // Only https-listen needs a certificate.
+        assert!(Config::parse("http-listen 127.0.0.1\n", None).is_ok());
+        let error = Config::parse("http-listen 127.0.0.1\nhttps-listen 127.0.0.1\n", None)
+                        .unwrap_err();
+        assert_eq!((2, "https-listen needs tls-certificate and tls-key"),
+                   (error.line, &error.message[..]));
+    }
+
+    #[test]
+    fn quic() {
+        let text = "quic-listen 127.0.0.1
+                    quic-listen [::1]:8853
+                    tls-certificate dns.pem
+                    tls-key dns.key
+";
+        let config = Config::parse(text, None).unwrap();
+        assert_eq!(vec!["127.0.0.1:853".parse::<SocketAddr>().unwrap(),
+                        "[::1]:8853".parse().unwrap()],
+                   config.quic_listen);
+        let error = Config::parse("quic-listen 127.0.0.1\n", None).unwrap_err();
+        assert_eq!((1, "quic-listen needs tls-certificate and tls-key"),
+                   (error.line, &error.message[..]));
+    }
+    #[test]
+    fn dnscrypt() {
+        let text = "dnscrypt-listen 127.0.0.1
+                    dnscrypt-listen [::1]:8443
+                    dnscrypt-provider 2.dnscrypt-cert.example.com provider.key
+";
+        let config = Config::parse(text, Some(Path::new("/srv"))).unwrap();
+        assert_eq!(vec!["127.0.0.1:443".parse::<SocketAddr>().unwrap(),
+                        "[::1]:8443".parse().unwrap()],
+                   config.dnscrypt_listen);
+        assert_eq!(Some((Name::parse("2.dnscrypt-cert.example.com.", None).unwrap(),
+                         PathBuf::from("/srv/provider.key"))),
+                   config.dnscrypt_provider);
+        let error = Config::parse("dnscrypt-listen 127.0.0.1\n", None).unwrap_err();
+        assert_eq!((1, "dnscrypt-listen needs dnscrypt-provider"),
+                   (error.line, &error.message[..]));
+        assert_eq!(1,
+                   Config::parse("dnscrypt-provider example.com.\n", None).unwrap_err().line);
+    }
+
+    #[test]
+    fn mdns() {
+        let text = "mdns-publish printer 120 A 192.0.2.5
+                    mdns-publish _ipp._tcp 4500 PTR printer._ipp._tcp
+";
+        let config = Config::parse(text, None).unwrap();
+        let printer = Name::parse("printer._ipp._tcp.local.", None).unwrap();
+        assert_eq!(vec![Record::new(Name::parse("printer.local.", None).unwrap(),
+                                    CLASS_IN,
+                                    120,
+                                    RData::A("192.0.2.5".parse().unwrap())),
+                        Record::new(Name::parse("_ipp._tcp.local.", None).unwrap(),
+                                    CLASS_IN,
+                                    4500,
+                                    RData::Ptr(printer))],
+                   config.mdns_records);
+        assert_eq!(1, Config::parse("mdns-publish printer 120 A\n", None).unwrap_err().line);
+        assert_eq!(1,
+                   Config::parse("mdns-publish printer 120 A nowhere\n", None)
+                       .unwrap_err()
+                       .line);
+    }
+
+    #[test]
+    fn llmnr() {
+        let config = Config::parse("llmnr-publish printer 30 A 192.0.2.5\n", None).unwrap();
+        assert_eq!(vec![Record::new(Name::parse("printer.", None).unwrap(),
+                                    CLASS_IN,
+                                    30,
+                                    RData::A("192.0.2.5".parse().unwrap()))],
+                   config.llmnr_records);
+        assert!(config.mdns_records.is_empty());
+        assert_eq!(1, Config::parse("llmnr-publish printer 30\n", None).unwrap_err().line);
+    }
+
+    #[test]
+    fn forward() {
+        let config = Config::parse("forward 192.0.2.53 [2001:db8::53]:5353\n", None).unwrap();
+        assert_eq!(vec![Forwarder::Udp("192.0.2.53:53".parse().unwrap()),
+                        Forwarder::Udp("[2001:db8::53]:5353".parse().unwrap())],
+                   config.forwarders);
+        assert_eq!(1, Config::parse("forward\n", None).unwrap_err().line);
+        assert_eq!(1, Config::parse("forward nowhere\n", None).unwrap_err().line);
+    }
+
+    #[test]
+    fn forward_encrypted() {
+        let pin = "pOdRQ0V2YZ1ftJm7kCk1GJnn+Ig8LhGUh5Lm8yfCvV4=";
+        let text = format!("forward https://dns.example #dns.example.net\n\
+                            forward https://[2001:db8::53]:8443/q#dns.example ca ca.pem pin {}\n",
+                           pin);
+        let config = Config::parse(&text, Some(Path::new("/etc/dns"))).unwrap();
+        let server = |host: &str, port| {
+            TlsForwarder {
+                host: host.to_string(),
+                port: port,
+                name: "dns.example".to_string(),
+                roots: None,
+                pins: Vec::new(),
+            }
+        };
+        let mut pinned = server("2001:db8::53", 8443);
+        pinned.roots = Some(PathBuf::from("/etc/dns/ca.pem"));
+        pinned.pins.push(pin.to_string());
+        assert_eq!(vec![Forwarder::Https(server("dns.example", 443), "/dns-query".to_string()),
+                        Forwarder::Https(pinned, "/q".to_string())],
+                   config.forwarders);
+        assert_eq!("https://[2001:db8::53]:8443/q#dns.example",
+                   config.forwarders[1].to_string());
+
+        let text = format!("forward tls://192.0.2.54#dns.example ca ca.pem \
+                            tls://[2001:db8::54]:8853\n\
+                            forward quic://192.0.2.55#dns.example pin {}\n",
+                           pin);
+        let config = Config::parse(&text, Some(Path::new("/etc/dns"))).unwrap();
+        let mut rooted = server("192.0.2.54", 853);
+        rooted.roots = Some(PathBuf::from("/etc/dns/ca.pem"));
+        let mut named = server("2001:db8::54", 8853);
+        named.name = named.host.clone();
+        let mut pinned = server("192.0.2.55", 853);
+        pinned.pins.push(pin.to_string());
+        assert_eq!(vec![Forwarder::Tls(rooted), Forwarder::Tls(named), Forwarder::Quic(pinned)],
+                   config.forwarders);
+        assert_eq!("quic://192.0.2.55:853#dns.example", config.forwarders[2].to_string());
+
+        let stamp = "sdns://AQcAAAAAAAAACjE5Mi4wLjIuNTMgAQIDBAUGBwgJCgsMDQ4PEBESExQVFhcYGRobHB0eHy\
+                     AbMi5kbnNjcnlwdC1jZXJ0LmV4YW1wbGUuY29t";
+        let config = Config::parse(&format!("forward {}\n", stamp), None).unwrap();
+        assert_eq!(vec![Forwarder::Dnscrypt(Stamp::parse(stamp).unwrap())], config.forwarders);
+
+        let text = format!("forward https://proxy.example/proxy pin {} \
+                            target https://target.example:8443 target-key {}\n",
+                           pin,
+                           pin);
+        let config = Config::parse(&text, None).unwrap();
+        let mut proxy = server("proxy.example", 443);
+        proxy.name = proxy.host.clone();
+        proxy.pins.push(pin.to_string());
+        let target = OdohTarget {
+            host: "target.example:8443".to_string(),
+            path: "/dns-query".to_string(),
+            public_key: base64_decode(pin).unwrap(),
+        };
+        assert_eq!(vec![Forwarder::Odoh(proxy, "/proxy".to_string(), target)],
+                   config.forwarders);
+
+        for bad in &["forward https://\n",
+                     "forward https://proxy.example target https://target.example\n",
+                     "forward https://proxy.example target-key AAAA\n",
+                     "forward tls://proxy.example target https://target.example\n",
+                     "forward https://proxy.example target target.example\n",
+                     "forward pin AAAA\n",
+                     "forward sdns://AgAAAAAAAAAA\n",
+                     "forward tls://192.0.2.54/dns-query\n",
+                     "forward quic://:853\n",
+                     "forward https://dns.example:port\n",
+                     "forward https://[dns.example]\n",
+                     "forward https://dns.example# \n",
+                     "forward 192.0.2.53 pin AAAA\n",
+                     "forward https://dns.example pin AAAA\n",
+                     &format!("forward {} ca ca.pem\n", stamp)[..],
+                     "forward https://dns.example ca\n"] {
+            assert_eq!(1, Config::parse(bad, None).unwrap_err().line);
+        }
+    }
+
+    #[test]
+    fn max_connections() {
+        assert_eq!(DEFAULT_MAX_CONNECTIONS, Config::parse("", None).unwrap().max_connections);
+        assert_eq!(64, Config::parse("max-connections 64\n", None).unwrap().max_connections);
+        assert_eq!(1, Config::parse("max-connections 0\n", None).unwrap_err().line);
+        assert_eq!(1, Config::parse("max-connections\n", None).unwrap_err().line);
+    }
+}
@@ -0,0 +1,297 @@
+//! The resolver side of DNSCrypt: certificates for fresh resolver keys,
+//! served in the clear to TXT queries for the provider name, and queries
+//! boxed under those keys opened and answered.
+
+use crypto::{BoxCipher, Ed25519PrivateKey, SecretBox, random_bytes, x25519_keypair};
+use dnscrypt::{Certificate, CLIENT_MAGIC_LEN, HALF_NONCE_LEN, MIN_QUERY_LEN, QUERY_HEADER_LEN,
+               RESOLVER_MAGIC, TAG_LEN, pad, padded_len, unpad};
+use protocol::{HeaderMut, Name, RData, Record};
+use protocol::rdata::{CLASS_IN, TYPE_TXT};
+use super::authority::MAX_UDP_RESPONSE;
+use super::request::Request;
+
+/// Seconds each certificate is good for.
+pub const CERT_LIFETIME: u64 = 86400;
+
+/// Milliseconds between new resolver keys: half a certificate's lifetime,
+/// so clients have time to fetch the new certificates before the old ones
+/// run out.
+pub const KEY_ROTATION_MS: u64 = CERT_LIFETIME * 1000 / 2;
+
+// Certificates are fetched again well before they run out anyway.
+const CERT_TTL: u32 = 3600;
+
+/// The provider's certificates and the resolver keys behind them.
+pub struct DnscryptServer {
+    provider: Name,
+    key: Ed25519PrivateKey,
+    // Newest first; older keys still open queries until their
+    // certificates run out.
+    keys: Vec<(Certificate, Vec<u8>)>,
+}
+
+/// A packet from a client, as the server makes it out.
+pub enum DnscryptRequest {
+    /// A query for the certificates, and its answer to send back as is.
+    Certificates(Vec<u8>),
+    /// A query opened from its box, and what seals the response.
+    Query(Vec<u8>, DnscryptSession),
+}
+
+/// What a response to one query is boxed with.
+pub struct DnscryptSession {
+    secret_box: SecretBox,
+    client_nonce: Vec<u8>,
+}
+
+impl DnscryptSession {
+    /// Boxes a response, padded to a multiple of 64 octets. Over UDP it
+    /// may be no longer than `max_response` of the query's length.
+    pub fn seal(&self, response: &[u8]) -> Vec<u8> {
+        let mut nonce = self.client_nonce.clone();
+        nonce.extend(random_bytes(HALF_NONCE_LEN));
+        let mut packet = RESOLVER_MAGIC.to_vec();
+        packet.extend_from_slice(&nonce);
+        packet.extend(self.secret_box.seal(&nonce, &pad(response, padded_len(response.len(), 0))));
+        packet
+    }
+}
+
+impl DnscryptServer {
+    /// A server for `provider`, conventionally `2.dnscrypt-cert.` and a
+    /// domain, signing its certificates with `key`. The first keys are
+    /// made at once; `time` is seconds since the epoch.
+    pub fn new(provider: Name, key: Ed25519PrivateKey, time: u64) -> DnscryptServer {
+        let mut server = DnscryptServer {
+            provider: provider,
+            key: key,
+            keys: Vec::new(),
+        };
+        server.rotate(time);
+        server
+    }
+
+    pub fn provider(&self) -> &Name {
+        &self.provider
+    }
+
+    /// The provider's public key, for clients to check certificates with.
+    pub fn public_key(&self) -> Vec<u8> {
+        self.key.public_key()
+    }
+
+    /// The certificates handed out: the newest for each box cipher.
+    pub fn certificates(&self) -> Vec<&Certificate> {
+        self.keys.iter().take(2).map(|&(ref certificate, _)| certificate).collect()
+    }
+
+    /// Makes new resolver keys and certificates for both box ciphers, and
+    /// forgets keys whose certificates have run out.
+    pub fn rotate(&mut self, time: u64) {
+        let serial = match self.keys.first() {
+            Some(&(ref newest, _)) if newest.serial >= time as u32 => newest.serial + 1,
+            _ => time as u32,
+        };
+        for &cipher in &[BoxCipher::XSalsa20, BoxCipher::XChaCha20] {
+            let (secret, public) = x25519_keypair();
+            let certificate = Certificate::sign(&self.key,
+                                                cipher,
+                                                &public,
+                                                serial,
+                                                time as u32,
+                                                (time + CERT_LIFETIME) as u32);
+            self.keys.insert(0, (certificate, secret));
+        }
+        self.keys.retain(|&(ref certificate, _)| certificate.is_current(time));
+    }
+
+    /// Makes out a packet from a client: a query boxed for one of the
+    /// resolver keys, or a query in the clear for the certificates. Returns
+    /// None for anything else, which is dropped.
+    pub fn received(&self, packet: &[u8]) -> Option<DnscryptRequest> {
+        if packet.len() >= QUERY_HEADER_LEN {
+            let magic = &packet[..CLIENT_MAGIC_LEN];
+            for &(ref certificate, ref secret) in &self.keys {
+                if magic == &certificate.client_magic[..] {
+                    return self.open(certificate.cipher, secret, packet);
+                }
+            }
+        }
+        let request = match Request::parse(packet) {
+            Some(request) => request,
+            None => return None,
+        };
+        match request.query {
+            Some(ref query) if query.name == self.provider && query.qtype == TYPE_TXT &&
+                               query.qclass == CLASS_IN => {}
+            _ => return None,
+        }
+        let (mut buffer, mut idx) = request.response(MAX_UDP_RESPONSE);
+        let mut count = 0;
+        for certificate in self.certificates() {
+            let record = Record::new(self.provider.clone(),
+                                     CLASS_IN,
+                                     CERT_TTL,
+                                     RData::Txt(vec![certificate.to_wire()]));
+            let at = idx.tell();
+            match record.write_at(&mut idx, &mut buffer) {
+                Some(_) => count += 1,
+                None => idx.rewind(at),
+            }
+        }
+        HeaderMut::at_raw(&mut buffer[..]).set_aa(true).set_an(count);
+        buffer.truncate(idx.tell());
+        Some(DnscryptRequest::Certificates(buffer))
+    }
+
+    /// Like `received`, for a packet that came over UDP: boxed queries
+    /// padded to less than `MIN_QUERY_LEN` are dropped too, as the room
+    /// their responses get would be too small for any answer.
+    pub fn received_datagram(&self, packet: &[u8]) -> Option<DnscryptRequest> {
+        match self.received(packet) {
+            Some(DnscryptRequest::Query(..)) if packet.len() <
+                                                QUERY_HEADER_LEN + MIN_QUERY_LEN + TAG_LEN => None,
+            request => request,
+        }
+    }
+
+    fn open(&self, cipher: BoxCipher, secret: &[u8], packet: &[u8]) -> Option<DnscryptRequest> {
+        let client_key = &packet[CLIENT_MAGIC_LEN..CLIENT_MAGIC_LEN + 32];
+        let client_nonce = &packet[CLIENT_MAGIC_LEN + 32..QUERY_HEADER_LEN];
+        let secret_box = match SecretBox::between(cipher, secret, client_key) {
+            Some(secret_box) => secret_box,
+            None => return None,
+        };
+        let mut nonce = client_nonce.to_vec();
+        nonce.resize(2 * HALF_NONCE_LEN, 0);
+        let query = match secret_box.open(&nonce, &packet[QUERY_HEADER_LEN..]) {
+            Some(padded) => {
+                match unpad(&padded) {
+                    Some(query) => query.to_vec(),
+                    None => return None,
+                }
+            }
+            None => return None,
+        };
+        let session = DnscryptSession {
+            secret_box: secret_box,
+            client_nonce: client_nonce.to_vec(),
+        };
+        Some(DnscryptRequest::Query(query, session))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::{BoxCipher, Ed25519PrivateKey, SecretBox, x25519_keypair};
+    use dnscrypt::{Certificate, QUERY_HEADER_LEN, RESPONSE_HEADER_LEN, pad, unpad};
+    use protocol::{Header, Name, RData, Record};
+    use protocol::rdata::TYPE_TXT;
+    use test_util::{NOW, query};
+
+    fn server() -> DnscryptServer {
+        let provider = Name::parse("2.dnscrypt-cert.example.", None).unwrap();
+        DnscryptServer::new(provider, Ed25519PrivateKey::new(&[7; 32]).unwrap(), NOW)
+    }
+
+    // The certificates in an answer to a certificate query.
+    fn certificates(server: &DnscryptServer, id: u16) -> Vec<Certificate> {
+        let question = query(id, "2.dnscrypt-cert.example.", TYPE_TXT);
+        let response = match server.received(&question) {
+            Some(DnscryptRequest::Certificates(response)) => response,
+            _ => panic!("no certificates"),
+        };
+        let header = Header::at(&response[..]);
+        assert_eq!((Some(id), Some(true)), (header.id(), header.aa()));
+        let (records, _) = Record::read_section(&response, question.len(), header.an().unwrap())
+                               .unwrap();
+        records.iter()
+               .map(|record| {
+                   match record.rdata {
+                       RData::Txt(ref strings) => {
+                           Certificate::parse(&strings[0], &server.public_key()).unwrap()
+                       }
+                       _ => panic!("not TXT"),
+                   }
+               })
+               .collect()
+    }
+
+    #[test]
+    fn certificates_and_queries() {
+        let mut server = server();
+        let certificates = certificates(&server, 5);
+        assert_eq!(2, certificates.len());
+        assert_eq!(BoxCipher::XChaCha20, certificates[0].cipher);
+        assert_eq!(BoxCipher::XSalsa20, certificates[1].cipher);
+        assert!(certificates.iter().all(|c| c.serial == NOW as u32 && c.is_current(NOW)));
+
+        // Other queries in the clear are dropped.
+        assert!(server.received(&query(5, "example.com.", TYPE_TXT)).is_none());
+        assert!(server.received(&query(5, "2.dnscrypt-cert.example.", 1)).is_none());
+
+        let (secret, public) = x25519_keypair();
+        let client_nonce = [9u8; 12];
+        let mut nonce = client_nonce.to_vec();
+        nonce.resize(24, 0);
+        let boxed = |certificate: &Certificate, message: &[u8]| {
+            let secret_box = SecretBox::between(certificate.cipher,
+                                                &secret,
+                                                &certificate.resolver_key)
+                                 .unwrap();
+            let mut packet = certificate.client_magic.clone();
+            packet.extend_from_slice(&public);
+            packet.extend_from_slice(&client_nonce);
+            packet.extend(secret_box.seal(&nonce, &pad(message, 256)));
+            (packet, secret_box)
+        };
+        let question = query(6, "example.com.", 1);
+        for certificate in &certificates {
+            let (packet, secret_box) = boxed(certificate, &question);
+            assert_eq!(QUERY_HEADER_LEN + 256 + 16, packet.len());
+            let session = match server.received(&packet) {
+                Some(DnscryptRequest::Query(query, session)) => {
+                    assert_eq!(question, query);
+                    session
+                }
+                _ => panic!("query not opened"),
+            };
+            let response = session.seal(b"response");
+            assert_eq!(RESPONSE_HEADER_LEN + 64 + 16, response.len());
+            assert_eq!(b"r6fnvWj8", &response[..8]);
+            assert_eq!(&client_nonce[..], &response[8..20]);
+            let opened = secret_box.open(&response[8..32], &response[32..]).unwrap();
+            assert_eq!(Some(&b"response"[..]), unpad(&opened));
+
+            let mut forged = packet.clone();
+            forged[100] ^= 1;
+            assert!(server.received(&forged).is_none());
+        }
+
+        // Old keys keep working until their certificates run out, but only
+        // the newest are handed out.
+        server.rotate(NOW + KEY_ROTATION_MS / 1000);
+        let rotated = self::certificates(&server, 7);
+        assert_eq!(2, rotated.len());
+        assert!(rotated.iter().all(|c| c.serial == (NOW + 43200) as u32));
+        let (packet, _) = boxed(&certificates[0], &question);
+        assert!(server.received(&packet).is_some());
+        server.rotate(NOW + CERT_LIFETIME + 1);
+        assert!(server.received(&packet).is_none());
+        let (packet, _) = boxed(&rotated[1], &question);
+        assert!(server.received(&packet).is_some());
+        assert!(server.received_datagram(&packet).is_some());
+
+        // Over UDP, queries padded short of the minimum are dropped.
+        let secret_box = SecretBox::between(rotated[1].cipher, &secret, &rotated[1].resolver_key)
+                             .unwrap();
+        let mut short = rotated[1].client_magic.clone();
+        short.extend_from_slice(&public);
+        short.extend_from_slice(&client_nonce);
+        short.extend(secret_box.seal(&nonce, &pad(&question, 64)));
+        assert!(server.received(&short).is_some());
+        assert!(server.received_datagram(&short).is_none());
+    }
+}
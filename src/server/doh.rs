@@ -0,0 +1,659 @@
+//! The server side of DNS over HTTPS (RFC 8484). Queries come as requests
+//! for `/dns-query`, by GET with the message in base64url or by POST, over
+//! HTTP/1.1 or HTTP/2, with TLS under them or, behind a proxy, without.
+//!
+//! The same queries can be made and answered in JSON: by GET for
+//! `/resolve?name=...&type=...`, answered in the `application/dns-json`
+//! shape of the common resolver APIs, or with messages in the RFC 8427
+//! representation as `application/dns+json`.
+//!
+//! Given a key, a connection is an Oblivious DoH target as well: sealed
+//! queries POSTed to `/dns-query` are opened, and their responses sealed,
+//! and the key's config is served at `/.well-known/odohconfigs`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use http::{Http1Server, Http2Connection, HttpError, PREFACE, Request, Response};
+use protocol::{Header, HeaderMut, Json, MessageCursor, Name, QuestionMut, base64url_decode,
+               message_from_json, message_to_dns_json, message_to_json, response_ttl};
+use protocol::rdata::{CLASS_IN, TYPE_A, type_from_name};
+use odoh::{ODOH_CONFIGS_PATH, ODOH_MESSAGE, OdohConfig, OdohKey, OdohSession};
+use tls::{ServerConfig, TlsConnection};
+use super::stream::IDLE_TIMEOUT_MS;
+
+pub const DOH_PATH: &'static str = "/dns-query";
+pub const JSON_PATH: &'static str = "/resolve";
+pub const DNS_MESSAGE: &'static str = "application/dns-message";
+pub const DNS_JSON: &'static str = "application/dns-json";
+/// The media type of RFC 8427.
+pub const DNS_PLUS_JSON: &'static str = "application/dns+json";
+
+/// How a query came and how its response goes back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DohFormat {
+    /// The DNS wire format of RFC 8484.
+    Wire,
+    /// The `application/dns-json` shape, for a query by name and type.
+    DnsJson,
+    /// The RFC 8427 representation.
+    Json,
+}
+
+fn refuse(status: u16) -> Response {
+    let mut response = Response::new(status);
+    if status == 405 {
+        response.add_header("allow", "GET, POST");
+    }
+    response
+}
+
+// Undoes the percent-encoding of a query parameter, with `+` for space.
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3).and_then(|hex| ::std::str::from_utf8(hex).ok());
+            match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                Some(byte) => decoded.push(byte),
+                None => return None,
+            }
+            i += 3;
+        } else {
+            decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn parameter(query: &str, key: &str) -> Option<String> {
+    query.split('&')
+         .find(|param| param.starts_with(key) && param[key.len()..].starts_with('='))
+         .and_then(|param| percent_decode(&param[key.len() + 1..]))
+}
+
+// The query for `name` and `type` as the JSON APIs take them: the type by
+// number or mnemonic, A if not given, and `cd` to turn off validation.
+fn json_api_query(query: &str) -> Option<Vec<u8>> {
+    // Names are absolute whether or not they end in a dot.
+    let root = Name::root();
+    let name = match parameter(query, "name").and_then(|name| Name::parse(&name, Some(&root))) {
+        Some(name) => name,
+        None => return None,
+    };
+    let qtype = match parameter(query, "type") {
+        None => TYPE_A,
+        Some(text) => {
+            match text.parse::<u16>().ok().or_else(|| type_from_name(&text)) {
+                Some(qtype) => qtype,
+                None => return None,
+            }
+        }
+    };
+    let cd = match parameter(query, "cd") {
+        Some(ref cd) => cd == "1" || cd == "true",
+        None => false,
+    };
+    let mut message = vec![0u8; 512];
+    let mut idx = MessageCursor::new(message.len());
+    HeaderMut::at(&mut idx, &mut message[..]).unwrap().make_query(0).set_cd(cd).set_qd(1);
+    if QuestionMut::at(&mut idx, &mut message, &name.segments(), qtype, CLASS_IN).is_none() {
+        return None;
+    }
+    message.truncate(idx.tell());
+    Some(message)
+}
+
+fn media_type(value: Option<&str>) -> Option<&str> {
+    value.map(|value| value.split(';').next().unwrap().trim())
+}
+
+/// The DNS query an HTTP request carries and the format to answer it in,
+/// or the response refusing it.
+pub fn doh_query(request: &Request) -> Result<(Vec<u8>, DohFormat), Response> {
+    let (path, query) = match request.path.find('?') {
+        Some(at) => (&request.path[..at], &request.path[at + 1..]),
+        None => (&request.path[..], ""),
+    };
+    let by_name = path == JSON_PATH || parameter(query, "name").is_some();
+    if path != DOH_PATH && path != JSON_PATH {
+        return Err(refuse(404));
+    }
+    let accept_json = request.header("accept")
+                             .map_or(false, |accept| accept.contains(DNS_PLUS_JSON));
+    let mut format = if accept_json {
+        DohFormat::Json
+    } else {
+        DohFormat::Wire
+    };
+    let message = match &request.method[..] {
+        "GET" if by_name => {
+            format = DohFormat::DnsJson;
+            json_api_query(query)
+        }
+        "GET" => parameter(query, "dns").and_then(|dns| base64url_decode(&dns)),
+        "POST" => {
+            match media_type(request.header("content-type")) {
+                Some(DNS_MESSAGE) => Some(request.body.clone()),
+                Some(DNS_PLUS_JSON) => {
+                    format = DohFormat::Json;
+                    ::std::str::from_utf8(&request.body)
+                        .ok()
+                        .and_then(Json::parse)
+                        .and_then(|json| message_from_json(&json).ok())
+                }
+                _ => return Err(refuse(415)),
+            }
+        }
+        _ => return Err(refuse(405)),
+    };
+    match message {
+        Some(ref message) if message.len() >= Header::at(&message[..]).end_offset() => {
+            Ok((message.clone(), format))
+        }
+        _ => Err(refuse(400)),
+    }
+}
+
+/// The query sealed in an Oblivious DoH request and what seals its
+/// response, or the response to send instead, such as the key's config.
+/// None if the request is not for an Oblivious DoH target.
+pub fn odoh_query(key: &OdohKey,
+                  request: &Request)
+    -> Option<Result<(Vec<u8>, OdohSession), Response>> {
+    let content_type = media_type(request.header("content-type"));
+    match (&request.method[..], &request.path[..]) {
+        ("GET", ODOH_CONFIGS_PATH) => {
+            let mut response = Response::new(200);
+            response.body = OdohConfig::list_to_wire(&[key.config().clone()]);
+            response.add_header("content-type", "application/octet-stream");
+            Some(Err(response))
+        }
+        ("POST", DOH_PATH) if content_type == Some(ODOH_MESSAGE) => {
+            match key.open_query(&request.body) {
+                Some((ref query, _)) if query.len() < Header::at(&query[..]).end_offset() => {
+                    Some(Err(refuse(400)))
+                }
+                Some(opened) => Some(Ok(opened)),
+                // Sealed for another key, or tampered with.
+                None => Some(Err(refuse(401))),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The HTTP response carrying a DNS response in `format`, which caches may
+/// keep for its smallest TTL.
+pub fn doh_response(message: &[u8], format: DohFormat) -> Response {
+    let (media_type, json) = match format {
+        DohFormat::Wire => (DNS_MESSAGE, None),
+        DohFormat::DnsJson => (DNS_JSON, Some(message_to_dns_json(message))),
+        DohFormat::Json => (DNS_PLUS_JSON, Some(message_to_json(message))),
+    };
+    let mut response = Response::new(200);
+    response.body = match json {
+        None => message.to_vec(),
+        Some(Some(json)) => json.to_string().into_bytes(),
+        Some(None) => return refuse(500),
+    };
+    let max_age = format!("max-age={}", response_ttl(message).unwrap_or(0));
+    response.add_header("content-type", media_type).add_header("cache-control", &max_age);
+    response
+}
+
+#[derive(Debug)]
+enum Http {
+    // Not yet known to be either version; what has come so far.
+    Unknown(Vec<u8>),
+    One(Http1Server),
+    Two(Http2Connection),
+}
+
+/// One client connection: its TLS if any, its HTTP, and its idle timer.
+/// Reading and writing the socket is up to the caller.
+#[derive(Debug)]
+pub struct DohConnection {
+    tls: Option<TlsConnection>,
+    http: Http,
+    // The format each query waiting for its response came in.
+    formats: HashMap<u32, DohFormat>,
+    odoh_key: Option<Arc<OdohKey>>,
+    // What seals the responses to the oblivious queries.
+    sessions: HashMap<u32, OdohSession>,
+    idle_timeout: Duration,
+    last_active: Instant,
+}
+
+impl DohConnection {
+    /// A connection with no TLS, as from a proxy in front.
+    pub fn new(now: Instant) -> DohConnection {
+        DohConnection {
+            tls: None,
+            http: Http::Unknown(Vec::new()),
+            formats: HashMap::new(),
+            odoh_key: None,
+            sessions: HashMap::new(),
+            idle_timeout: Duration::from_millis(IDLE_TIMEOUT_MS),
+            last_active: now,
+        }
+    }
+
+    /// A connection that starts with a TLS handshake, which should offer
+    /// `h2` and `http/1.1` through ALPN.
+    pub fn with_tls(config: Arc<ServerConfig>, now: Instant) -> DohConnection {
+        let mut connection = DohConnection::new(now);
+        connection.tls = Some(TlsConnection::server(config));
+        connection
+    }
+
+    pub fn set_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Makes the connection an Oblivious DoH target for `key` too.
+    pub fn set_odoh_key(&mut self, key: Arc<OdohKey>) -> &mut Self {
+        self.odoh_key = Some(key);
+        self
+    }
+
+    /// Takes octets read from the client and returns the DNS queries they
+    /// complete, each with the handle to answer it by. Requests that are
+    /// not DNS queries are answered here. An error ends the connection,
+    /// once what is left in `pending` has been written.
+    pub fn received(&mut self,
+                    data: &[u8],
+                    now: Instant)
+                    -> Result<Vec<(u32, Vec<u8>)>, HttpError> {
+        self.last_active = now;
+        let plaintext = match self.tls {
+            Some(ref mut tls) => {
+                try!(tls.received(data));
+                tls.read()
+            }
+            None => data.to_vec(),
+        };
+        let result = self.http_received(&plaintext);
+        let mut queries = Vec::new();
+        if let Ok(requests) = result.as_ref() {
+            for &(handle, ref request) in requests {
+                let oblivious = self.odoh_key.as_ref().and_then(|key| odoh_query(key, request));
+                match oblivious {
+                    Some(Ok((query, session))) => {
+                        self.sessions.insert(handle, session);
+                        queries.push((handle, query));
+                        continue;
+                    }
+                    Some(Err(response)) => {
+                        self.http_respond(handle, &response);
+                        continue;
+                    }
+                    None => {}
+                }
+                match doh_query(request) {
+                    Ok((query, format)) => {
+                        self.formats.insert(handle, format);
+                        queries.push((handle, query));
+                    }
+                    Err(response) => self.http_respond(handle, &response),
+                }
+            }
+        }
+        self.flush();
+        result.map(|_| queries)
+    }
+
+    fn http_received(&mut self, data: &[u8]) -> Result<Vec<(u32, Request)>, HttpError> {
+        let buffered = match self.http {
+            Http::Unknown(ref mut buffered) => {
+                buffered.extend(data.iter().cloned());
+                // HTTP/2 clients start with a preface no HTTP/1.1 request
+                // looks like.
+                if buffered.len() < PREFACE.len() && PREFACE.starts_with(buffered) {
+                    return Ok(Vec::new());
+                }
+                buffered.clone()
+            }
+            Http::One(ref mut http) => return Ok(http.received(data)),
+            Http::Two(ref mut http) => return http.received(data),
+        };
+        if buffered.starts_with(PREFACE) {
+            let mut http = Http2Connection::server();
+            let requests = http.received(&buffered);
+            self.http = Http::Two(http);
+            requests
+        } else {
+            let mut http = Http1Server::new();
+            let requests = http.received(&buffered);
+            self.http = Http::One(http);
+            Ok(requests)
+        }
+    }
+
+    fn http_respond(&mut self, handle: u32, response: &Response) {
+        match self.http {
+            Http::One(ref mut http) => http.respond(handle, response),
+            Http::Two(ref mut http) => http.respond(handle, response),
+            Http::Unknown(_) => {}
+        }
+    }
+
+    // Moves what HTTP has to send into TLS.
+    fn flush(&mut self) {
+        if let Some(ref mut tls) = self.tls {
+            let output = match self.http {
+                Http::One(ref mut http) => {
+                    let output = http.pending().to_vec();
+                    http.written(output.len());
+                    output
+                }
+                Http::Two(ref mut http) => {
+                    let output = http.pending().to_vec();
+                    http.written(output.len());
+                    output
+                }
+                Http::Unknown(_) => return,
+            };
+            if !output.is_empty() {
+                tls.write(&output);
+            }
+        }
+    }
+
+    /// Queues the DNS response to a query.
+    pub fn respond(&mut self, handle: u32, message: &[u8]) {
+        let format = self.formats.remove(&handle).unwrap_or(DohFormat::Wire);
+        let response = match self.sessions.remove(&handle) {
+            Some(session) => {
+                let mut response = Response::new(200);
+                response.body = session.seal(message);
+                response.add_header("content-type", ODOH_MESSAGE);
+                response
+            }
+            None => doh_response(message, format),
+        };
+        self.http_respond(handle, &response);
+        self.flush();
+    }
+
+    /// Answers a query with an HTTP error instead, as when no DNS response
+    /// can be made to it.
+    pub fn refuse(&mut self, handle: u32, status: u16) {
+        self.formats.remove(&handle);
+        self.sessions.remove(&handle);
+        self.http_respond(handle, &refuse(status));
+        self.flush();
+    }
+
+    /// The octets waiting to be written to the client.
+    pub fn pending(&self) -> &[u8] {
+        if let Some(ref tls) = self.tls {
+            return tls.pending();
+        }
+        match self.http {
+            Http::One(ref http) => http.pending(),
+            Http::Two(ref http) => http.pending(),
+            Http::Unknown(_) => &[],
+        }
+    }
+
+    pub fn written(&mut self, n: usize, now: Instant) {
+        if n > 0 {
+            self.last_active = now;
+        }
+        if let Some(ref mut tls) = self.tls {
+            return tls.written(n);
+        }
+        match self.http {
+            Http::One(ref mut http) => http.written(n),
+            Http::Two(ref mut http) => http.written(n),
+            Http::Unknown(_) => {}
+        }
+    }
+
+    /// The TLS the connection runs over, if any.
+    pub fn tls(&self) -> Option<&TlsConnection> {
+        self.tls.as_ref()
+    }
+
+    /// Whether the client is done, so the connection should close once
+    /// `pending` is written.
+    pub fn is_closed(&self) -> bool {
+        let tls_closed = self.tls.as_ref().map_or(false, |tls| tls.is_closed());
+        tls_closed ||
+        match self.http {
+            Http::One(ref http) => http.is_closed(),
+            Http::Two(ref http) => http.is_closed(),
+            Http::Unknown(_) => false,
+        }
+    }
+
+    /// When the connection becomes idle if nothing happens before then.
+    pub fn deadline(&self) -> Instant {
+        self.last_active + self.idle_timeout
+    }
+
+    /// Whether the connection has gone the timeout without anything read
+    /// or written, even if it has output the client does not read.
+    pub fn is_idle(&self, now: Instant) -> bool {
+        now >= self.deadline()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Instant;
+    use http::Request;
+    use odoh::{ODOH_MESSAGE, OdohConfig, OdohKey};
+    use protocol::{Header, HeaderMut, Json, MessageCursor, Name, RData, Record, base64url_encode,
+                   message_from_json, message_to_json};
+    use protocol::rdata::{CLASS_IN, TYPE_A};
+    use test_util::query;
+    use tls::{ClientConfig, ServerConfig, TlsConnection};
+
+    fn answer(query: &[u8]) -> Vec<u8> {
+        let mut message = query.to_vec();
+        message.resize(512, 0);
+        let mut idx = MessageCursor::new(message.len());
+        idx.alloc(query.len()).unwrap();
+        for &ttl in &[300, 60] {
+            Record::new(Name::parse("www.example.com.", None).unwrap(),
+                        CLASS_IN,
+                        ttl,
+                        RData::A("192.0.2.1".parse().unwrap()))
+                .write_at(&mut idx, &mut message)
+                .unwrap();
+        }
+        message.truncate(idx.tell());
+        HeaderMut::at_raw(&mut message[..]).set_qr(true).set_an(2);
+        message
+    }
+
+    #[test]
+    fn requests() {
+        let query = query(0, "www.example.com.", TYPE_A);
+        let path = format!("/dns-query?ct&dns={}", base64url_encode(&query));
+        let mut get = Request::new("GET", &path);
+        assert_eq!(Ok((query.clone(), DohFormat::Wire)), doh_query(&get));
+        get.path = "/dns-query?dns=AAAB".to_string();
+        assert_eq!(400, doh_query(&get).unwrap_err().status);
+        get.path = "/other?dns=AAAB".to_string();
+        assert_eq!(404, doh_query(&get).unwrap_err().status);
+
+        let mut post = Request::new("POST", "/dns-query");
+        post.body = query.clone();
+        assert_eq!(415, doh_query(&post).unwrap_err().status);
+        post.headers.push(("content-type".to_string(), DNS_MESSAGE.to_string()));
+        assert_eq!(Ok((query.clone(), DohFormat::Wire)), doh_query(&post));
+        let put = Request::new("PUT", "/dns-query");
+        assert_eq!(Some("GET, POST"), doh_query(&put).unwrap_err().header("allow"));
+
+        let response = doh_response(&answer(&query), DohFormat::Wire);
+        assert_eq!((200, Some("max-age=60"), Some(DNS_MESSAGE)),
+                   (response.status,
+                    response.header("cache-control"),
+                    response.header("content-type")));
+        assert_eq!(Some("max-age=0"),
+                   doh_response(&query, DohFormat::Wire).header("cache-control"));
+    }
+
+    #[test]
+    fn json() {
+        let query = query(0, "www.example.com.", TYPE_A);
+        let mut get = Request::new("GET", "/resolve?name=www.example.com&type=a");
+        assert_eq!(Ok((query.clone(), DohFormat::DnsJson)), doh_query(&get));
+        get.path = "/dns-query?type=1&name=www%2eexample.com.&cd=1".to_string();
+        let (message, format) = doh_query(&get).unwrap();
+        let header = Header::at(&message[..]);
+        assert_eq!((Some(true), Some(1), DohFormat::DnsJson),
+                   (header.cd(), header.questions().next().and_then(|q| q.qtype()), format));
+        for path in &["/resolve", "/resolve?name=a..b", "/resolve?name=a&type=BOGUS",
+                      "/resolve?name=%zz"] {
+            get.path = path.to_string();
+            assert_eq!(400, doh_query(&get).unwrap_err().status);
+        }
+
+        let response = doh_response(&answer(&query), DohFormat::DnsJson);
+        assert_eq!((Some(DNS_JSON), Some("max-age=60")),
+                   (response.header("content-type"), response.header("cache-control")));
+        let json = Json::parse(&String::from_utf8(response.body).unwrap()).unwrap();
+        let answers = json.get("Answer").and_then(|a| a.as_array()).unwrap();
+        assert_eq!(Some("192.0.2.1"), answers[1].get("data").and_then(|d| d.as_str()));
+
+        // RFC 8427 both ways.
+        let mut post = Request::new("POST", "/dns-query");
+        post.headers.push(("content-type".to_string(), DNS_PLUS_JSON.to_string()));
+        post.body = message_to_json(&query).unwrap().to_string().into_bytes();
+        assert_eq!(Ok((query.clone(), DohFormat::Json)), doh_query(&post));
+        post.body = b"{\"QNAME\": 1}".to_vec();
+        assert_eq!(400, doh_query(&post).unwrap_err().status);
+        get.path = format!("/dns-query?dns={}", base64url_encode(&query));
+        get.headers.push(("accept".to_string(), DNS_PLUS_JSON.to_string()));
+        assert_eq!(Ok((query.clone(), DohFormat::Json)), doh_query(&get));
+        let response = doh_response(&answer(&query), DohFormat::Json);
+        assert_eq!(Some(DNS_PLUS_JSON), response.header("content-type"));
+        let json = Json::parse(&String::from_utf8(response.body).unwrap()).unwrap();
+        let message = message_from_json(&json).unwrap();
+        assert_eq!(Some(json), message_to_json(&message));
+    }
+
+    #[test]
+    fn http1_without_tls() {
+        let query = query(0, "www.example.com.", TYPE_A);
+        let start = Instant::now();
+        let mut connection = DohConnection::new(start);
+        let request = format!("GET /dns-query?dns={} HTTP/1.1\r\nHost: dns.example\r\n\r\n\
+                               GET /robots.txt HTTP/1.1\r\n\r\n\
+                               GET /resolve?name=www.example.com HTTP/1.1\r\n\r\n",
+                              base64url_encode(&query));
+        let queries = connection.received(&request.as_bytes()[..10], start).unwrap();
+        assert!(queries.is_empty() && connection.pending().is_empty());
+        let queries = connection.received(&request.as_bytes()[10..], start).unwrap();
+        assert_eq!(vec![(0, query.clone()), (2, query.clone())], queries);
+        // The second request is answered at once, but waits its turn.
+        assert!(connection.pending().is_empty());
+        connection.respond(0, &answer(&query));
+        let text = String::from_utf8_lossy(connection.pending()).into_owned();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(text.contains("cache-control: max-age=60\r\n"));
+        assert!(text.contains("HTTP/1.1 404 Not Found\r\n"));
+        connection.respond(2, &answer(&query));
+        let text = String::from_utf8_lossy(connection.pending()).into_owned();
+        assert!(text.contains("content-type: application/dns-json\r\n"));
+        assert!(text.ends_with("\"data\":\"192.0.2.1\"}]}"));
+    }
+
+    #[test]
+    fn oblivious_target() {
+        let query = query(0, "www.example.com.", TYPE_A);
+        let start = Instant::now();
+        let key = Arc::new(OdohKey::generate());
+        let mut connection = DohConnection::new(start);
+        connection.set_odoh_key(key.clone());
+        let (sealed, sent) = key.config().seal_query(&query).unwrap();
+        let mut request = format!("GET /.well-known/odohconfigs HTTP/1.1\r\n\r\n\
+                                   POST /dns-query HTTP/1.1\r\ncontent-type: {}\r\n\
+                                   content-length: {}\r\n\r\n",
+                                  ODOH_MESSAGE,
+                                  sealed.len())
+                              .into_bytes();
+        request.extend(&sealed);
+        let queries = connection.received(&request, start).unwrap();
+        assert_eq!(vec![(1, query.clone())], queries);
+        connection.respond(1, &answer(&query));
+
+        let text = connection.pending().to_vec();
+        let configs = OdohConfig::list_to_wire(&[key.config().clone()]);
+        let at = text.windows(configs.len()).position(|w| w == &configs[..]).unwrap();
+        assert!(text[..at].starts_with(b"HTTP/1.1 200 OK\r\n"));
+        let rest = String::from_utf8_lossy(&text[at + configs.len()..]).into_owned();
+        assert!(rest.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(rest.contains("content-type: application/oblivious-dns-message\r\n"));
+        let body = &text[text.len() - (1 + 2 + 16 + 2 + 468 + 16)..];
+        assert_eq!(Some(answer(&query)), sent.open_response(body));
+
+        // Queries sealed for other keys are refused, and plain DoH still
+        // works.
+        let mut request = Request::new("POST", "/dns-query");
+        request.headers.push(("content-type".to_string(), ODOH_MESSAGE.to_string()));
+        request.body = OdohKey::generate().config().seal_query(&query).unwrap().0;
+        assert_eq!(401, odoh_query(&key, &request).unwrap().unwrap_err().status);
+        request.headers[0].1 = DNS_MESSAGE.to_string();
+        assert!(odoh_query(&key, &request).is_none());
+    }
+
+    #[test]
+    fn http2_over_tls() {
+        let query = query(0, "www.example.com.", TYPE_A);
+        let start = Instant::now();
+        let config = ServerConfig::load(Path::new("testdata/tls-server.pem"),
+                                        Path::new("testdata/tls-server.key"))
+                         .unwrap();
+        let mut connection = DohConnection::with_tls(Arc::new(config), start);
+        let mut client_config = ClientConfig::new();
+        client_config.load_roots(Path::new("testdata/tls-ca.pem")).unwrap();
+        let mut client = TlsConnection::client(Arc::new(client_config), "dns.example").unwrap();
+
+        // The preface, empty SETTINGS, and a POST on stream 1, by hand.
+        let mut stream = PREFACE.to_vec();
+        stream.extend(&[0, 0, 0, 4, 0, 0, 0, 0, 0]);
+        let block = [0x83, 0x87, 0x44, 0x0a, b'/', b'd', b'n', b's', b'-', b'q', b'u', b'e', b'r',
+                     b'y', 0x5f, 0x17];
+        stream.extend(&[0, 0, (block.len() + DNS_MESSAGE.len()) as u8, 1, 4, 0, 0, 0, 1]);
+        stream.extend(&block);
+        stream.extend(DNS_MESSAGE.as_bytes());
+        stream.extend(&[0, 0, query.len() as u8, 0, 1, 0, 0, 0, 1]);
+        stream.extend(&query);
+        client.write(&stream);
+
+        let mut queries = Vec::new();
+        let mut from_server = Vec::new();
+        while !client.pending().is_empty() {
+            let to_server = client.pending().to_vec();
+            client.written(to_server.len());
+            for (handle, query) in connection.received(&to_server, start).unwrap() {
+                connection.respond(handle, &answer(&query));
+                queries.push(handle);
+            }
+            let to_client = connection.pending().to_vec();
+            connection.written(to_client.len(), start);
+            client.received(&to_client).unwrap();
+            from_server.extend(client.read());
+        }
+        assert_eq!(vec![1], queries);
+        // SETTINGS, the ACK of ours, a WINDOW_UPDATE, then the response
+        // HEADERS and DATA.
+        let data = &from_server[from_server.len() - 9 - answer(&query).len()..];
+        assert_eq!(&[0, 1, 0, 0, 0, 1], &data[3..9]);
+        assert_eq!(answer(&query), data[9..].to_vec());
+        assert!(!connection.is_closed());
+    }
+}
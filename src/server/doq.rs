@@ -0,0 +1,589 @@
+//! The server side of DNS over QUIC (RFC 9250), on the QUIC of quinn. Each
+//! query comes on a bidirectional stream of its own, with the two octet
+//! length of TCP in front and an ID of zero, and its response goes back on
+//! the same stream.
+//!
+//! Queries may come in 0-RTT data, which an attacker can replay, so only
+//! QUERY and NOTIFY requests are answered before the handshake is over;
+//! others wait for it.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use bytes::{Bytes, BytesMut};
+use quinn_proto::{self, ConnectionHandle, DatagramEvent, Dir, EndpointConfig, Event, ReadError,
+                  StreamEvent, StreamId, TransportConfig, VarInt, WriteError};
+use quinn_proto::crypto::rustls::QuicServerConfig;
+use protocol::{Header, OP_NOTIFY, OP_QUERY, tcp_frame};
+use tls::ServerConfig;
+use super::config::DEFAULT_MAX_CONNECTIONS;
+
+/// The ALPN protocol of DNS over QUIC.
+pub const DOQ_ALPN: &'static [u8] = b"doq";
+
+// Application error codes (RFC 9250 Section 4.3).
+pub const DOQ_NO_ERROR: u64 = 0x0;
+pub const DOQ_INTERNAL_ERROR: u64 = 0x1;
+pub const DOQ_PROTOCOL_ERROR: u64 = 0x2;
+pub const DOQ_REQUEST_CANCELLED: u64 = 0x3;
+pub const DOQ_EXCESSIVE_LOAD: u64 = 0x4;
+
+/// The DNS message framed in a whole stream, if it is one.
+pub fn doq_message(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 2 || data.len() != 2 + ((data[0] as usize) << 8 | data[1] as usize) {
+        return None;
+    }
+    let message = &data[2..];
+    if message.len() < Header::at(message).end_offset() {
+        return None;
+    }
+    Some(message.to_vec())
+}
+
+/// An application error code as QUIC sends it.
+pub fn doq_code(code: u64) -> VarInt {
+    VarInt::from_u64(code).unwrap()
+}
+
+/// What a stream came to, once the peer was done with it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DoqRead {
+    Message(Vec<u8>),
+    /// Something other than one framed DNS message.
+    Malformed,
+    Reset,
+}
+
+/// The DNS messages on the streams of one connection that are part read
+/// or part written, which both ends keep alike.
+#[derive(Debug, Default)]
+pub struct DoqStreams {
+    input: HashMap<StreamId, Vec<u8>>,
+    // What flow control has held back.
+    output: HashMap<StreamId, Vec<u8>>,
+}
+
+impl DoqStreams {
+    pub fn new() -> DoqStreams {
+        DoqStreams::default()
+    }
+
+    /// Reads what has come on stream `id`, and returns what the stream came
+    /// to if the peer is done with it.
+    pub fn read(&mut self, quic: &mut quinn_proto::Connection, id: StreamId) -> Option<DoqRead> {
+        let mut stream = quic.recv_stream(id);
+        let mut chunks = match stream.read(true) {
+            Ok(chunks) => chunks,
+            Err(_) => return None,
+        };
+        let mut done = None;
+        {
+            let input = self.input.entry(id).or_insert_with(Vec::new);
+            loop {
+                match chunks.next(usize::max_value()) {
+                    Ok(Some(chunk)) => input.extend_from_slice(&chunk.bytes),
+                    Ok(None) => {
+                        done = Some(match doq_message(input) {
+                            Some(message) => DoqRead::Message(message),
+                            None => DoqRead::Malformed,
+                        });
+                        break;
+                    }
+                    Err(ReadError::Blocked) => break,
+                    Err(ReadError::Reset(_)) => {
+                        done = Some(DoqRead::Reset);
+                        break;
+                    }
+                }
+                // No DNS message is longer than its two octet length allows.
+                if input.len() > 2 + 65535 {
+                    done = Some(DoqRead::Malformed);
+                    break;
+                }
+            }
+        }
+        let _ = chunks.finalize();
+        if done.is_some() {
+            self.input.remove(&id);
+        }
+        done
+    }
+
+    /// Writes `data` to stream `id` and finishes it, or as much as flow
+    /// control lets through, keeping the rest for `writable`.
+    pub fn write(&mut self, quic: &mut quinn_proto::Connection, id: StreamId, data: Vec<u8>) {
+        self.output.insert(id, data);
+        self.writable(quic, id);
+    }
+
+    /// Writes what stream `id` has held back, now the peer takes more.
+    pub fn writable(&mut self, quic: &mut quinn_proto::Connection, id: StreamId) {
+        let done = match self.output.get_mut(&id) {
+            Some(data) => {
+                let mut stream = quic.send_stream(id);
+                match stream.write(data) {
+                    Ok(n) => {
+                        data.drain(..n);
+                        if data.is_empty() {
+                            let _ = stream.finish();
+                        }
+                        data.is_empty()
+                    }
+                    Err(WriteError::Blocked) => false,
+                    // Stopped by the peer or closed: no one wants the rest.
+                    Err(_) => true,
+                }
+            }
+            None => return,
+        };
+        if done {
+            self.output.remove(&id);
+        }
+    }
+
+    /// Resets stream `id` with `code` and stops reading it, dropping what
+    /// either way has of it.
+    pub fn cancel(&mut self, quic: &mut quinn_proto::Connection, id: StreamId, code: u64) {
+        let _ = quic.send_stream(id).reset(doq_code(code));
+        let _ = quic.recv_stream(id).stop(doq_code(code));
+        self.input.remove(&id);
+        self.output.remove(&id);
+    }
+
+    /// Forgets every stream, as when 0-RTT was rejected and they are gone.
+    pub fn clear(&mut self) {
+        self.input.clear();
+        self.output.clear();
+    }
+}
+
+/// A query taken from a client, to answer it by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DoqHandle {
+    connection: ConnectionHandle,
+    stream: StreamId,
+}
+
+struct Connection {
+    quic: quinn_proto::Connection,
+    streams: DoqStreams,
+    // 0-RTT requests waiting for the handshake.
+    held: Vec<(StreamId, Vec<u8>)>,
+}
+
+/// All the DNS over QUIC connections on one UDP socket. Reading and
+/// writing the socket, and keeping the timer, is up to the caller. quinn
+/// sends no more than three times what an unvalidated address has sent
+/// (RFC 9000 Section 8), validates paths a client moves to (Section 9), and
+/// sends to wherever that leaves the client.
+pub struct DoqServer {
+    endpoint: quinn_proto::Endpoint,
+    connections: HashMap<ConnectionHandle, Connection>,
+    max_connections: usize,
+    // Queries taken and not yet handed over.
+    queries: Vec<(DoqHandle, Vec<u8>)>,
+    // Datagrams for no connection: version negotiation and refusals.
+    output: Vec<(Vec<u8>, SocketAddr)>,
+}
+
+impl DoqServer {
+    /// A server by `config`, which should offer `doq` through ALPN, and may
+    /// take 0-RTT data.
+    pub fn new(config: &ServerConfig) -> DoqServer {
+        let crypto = QuicServerConfig::try_from(config.rustls_config()).expect("TLS 1.3");
+        let mut server = quinn_proto::ServerConfig::with_crypto(Arc::new(crypto));
+        let mut transport = TransportConfig::default();
+        // Queries come on bidirectional streams only (RFC 9250 Section 4.2).
+        transport.max_concurrent_uni_streams(VarInt::from_u32(0));
+        server.transport_config(Arc::new(transport));
+        DoqServer {
+            endpoint: quinn_proto::Endpoint::new(Arc::new(EndpointConfig::default()),
+                                                 Some(Arc::new(server)),
+                                                 false,
+                                                 None),
+            connections: HashMap::new(),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            queries: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Refuses new connections while this many are open.
+    pub fn set_max_connections(&mut self, max_connections: usize) -> &mut Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Takes a datagram from a client and returns the queries it
+    /// completes, each with the handle to answer it by.
+    pub fn received(&mut self,
+                    datagram: &[u8],
+                    from: SocketAddr,
+                    now: Instant)
+        -> Vec<(DoqHandle, Vec<u8>)> {
+        let mut buffer = Vec::new();
+        let datagram = BytesMut::from(datagram);
+        let event = self.endpoint.handle(now, from, None, None, datagram, &mut buffer);
+        let number = match event {
+            Some(DatagramEvent::ConnectionEvent(number, event)) => {
+                if let Some(connection) = self.connections.get_mut(&number) {
+                    connection.quic.handle_event(event);
+                }
+                number
+            }
+            Some(DatagramEvent::NewConnection(incoming)) => {
+                if self.connections.len() >= self.max_connections {
+                    let transmit = self.endpoint.refuse(incoming, &mut buffer);
+                    self.output.push((buffer[..transmit.size].to_vec(), transmit.destination));
+                    return Vec::new();
+                }
+                match self.endpoint.accept(incoming, now, &mut buffer, None) {
+                    Ok((number, quic)) => {
+                        self.connections.insert(number,
+                                                Connection {
+                                                    quic: quic,
+                                                    streams: DoqStreams::new(),
+                                                    held: Vec::new(),
+                                                });
+                        number
+                    }
+                    Err(e) => {
+                        if let Some(transmit) = e.response {
+                            self.output
+                                .push((buffer[..transmit.size].to_vec(), transmit.destination));
+                        }
+                        return Vec::new();
+                    }
+                }
+            }
+            Some(DatagramEvent::Response(transmit)) => {
+                self.output.push((buffer[..transmit.size].to_vec(), transmit.destination));
+                return Vec::new();
+            }
+            None => return Vec::new(),
+        };
+        self.poll(number, now);
+        mem::replace(&mut self.queries, Vec::new())
+    }
+
+    // Acts on what has happened on a connection, taking the queries its
+    // streams complete, and passes its news to the endpoint.
+    fn poll(&mut self, number: ConnectionHandle, now: Instant) {
+        let connection = match self.connections.get_mut(&number) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let mut readable = Vec::new();
+        while let Some(event) = connection.quic.poll() {
+            match event {
+                Event::Stream(StreamEvent::Opened { dir: Dir::Bi }) => {
+                    while let Some(id) = connection.quic.streams().accept(Dir::Bi) {
+                        readable.push(id);
+                    }
+                }
+                Event::Stream(StreamEvent::Readable { id }) => readable.push(id),
+                Event::Stream(StreamEvent::Writable { id }) => {
+                    connection.streams.writable(&mut connection.quic, id);
+                }
+                Event::Connected => {
+                    self.queries.extend(connection.held.drain(..).map(|(id, query)| {
+                        (DoqHandle {
+                            connection: number,
+                            stream: id,
+                        },
+                         query)
+                    }));
+                }
+                _ => {}
+            }
+        }
+        for id in readable {
+            let query = match connection.streams.read(&mut connection.quic, id) {
+                Some(DoqRead::Message(ref query)) if Header::at(&query[..]).id() == Some(0) => {
+                    query.clone()
+                }
+                Some(DoqRead::Reset) => {
+                    connection.held.retain(|&(held, _)| held != id);
+                    continue;
+                }
+                Some(_) => {
+                    connection.quic.close(now,
+                                          doq_code(DOQ_PROTOCOL_ERROR),
+                                          Bytes::from_static(b"bad DNS message on stream"));
+                    break;
+                }
+                None => continue,
+            };
+            let op = Header::at(&query[..]).op();
+            if connection.quic.is_handshaking() && op != Some(OP_QUERY) && op != Some(OP_NOTIFY) {
+                connection.held.push((id, query));
+                continue;
+            }
+            self.queries.push((DoqHandle {
+                connection: number,
+                stream: id,
+            },
+                               query));
+        }
+        while let Some(event) = connection.quic.poll_endpoint_events() {
+            if let Some(event) = self.endpoint.handle_event(number, event) {
+                connection.quic.handle_event(event);
+            }
+        }
+    }
+
+    /// Queues the response to a query. Returns false if it is too long to
+    /// send.
+    pub fn respond(&mut self, handle: DoqHandle, response: &[u8]) -> bool {
+        let frame = match tcp_frame(response) {
+            Some(frame) => frame,
+            None => return false,
+        };
+        if let Some(connection) = self.connections.get_mut(&handle.connection) {
+            connection.streams.write(&mut connection.quic, handle.stream, frame);
+        }
+        true
+    }
+
+    /// Gives up on a query with no response to make, resetting its stream.
+    pub fn fail(&mut self, handle: DoqHandle) {
+        if let Some(connection) = self.connections.get_mut(&handle.connection) {
+            connection.streams.cancel(&mut connection.quic, handle.stream, DOQ_INTERNAL_ERROR);
+        }
+    }
+
+    /// Takes the datagrams to send, each with where it goes, and forgets
+    /// connections that have closed.
+    pub fn datagrams(&mut self, now: Instant) -> Vec<(Vec<u8>, SocketAddr)> {
+        let mut datagrams = mem::replace(&mut self.output, Vec::new());
+        let numbers: Vec<ConnectionHandle> = self.connections.keys().cloned().collect();
+        for number in numbers {
+            if let Some(connection) = self.connections.get_mut(&number) {
+                let mut buffer = Vec::new();
+                while let Some(transmit) = connection.quic.poll_transmit(now, 1, &mut buffer) {
+                    datagrams.push((buffer[..transmit.size].to_vec(), transmit.destination));
+                    buffer.clear();
+                }
+            }
+            self.poll(number, now);
+        }
+        self.connections.retain(|_, connection| !connection.quic.is_drained());
+        datagrams
+    }
+
+    /// When `timeout` next has something to do, if ever.
+    pub fn deadline(&mut self) -> Option<Instant> {
+        self.connections.values_mut().filter_map(|c| c.quic.poll_timeout()).min()
+    }
+
+    /// Acts on the connections' timers; `datagrams` has what they send.
+    pub fn timeout(&mut self, now: Instant) {
+        let numbers: Vec<ConnectionHandle> = self.connections.keys().cloned().collect();
+        for number in numbers {
+            if let Some(connection) = self.connections.get_mut(&number) {
+                if connection.quic.poll_timeout().map_or(true, |deadline| deadline > now) {
+                    continue;
+                }
+                connection.quic.handle_timeout(now);
+            }
+            self.poll(number, now);
+        }
+    }
+
+    /// How many connections are open.
+    pub fn connections(&self) -> usize {
+        self.connections.len()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use bytes::BytesMut;
+    use quinn_proto::{self, ApplicationClose, ConnectionError, Endpoint, EndpointConfig};
+    use quinn_proto::crypto::rustls::QuicClientConfig;
+    use tls::ClientConfig;
+    use client::DoqClient;
+    use protocol::{HeaderMut, OP_UPDATE};
+    use test_util::query;
+
+    fn configs() -> (Arc<ClientConfig>, ServerConfig) {
+        let mut server = ServerConfig::load(Path::new("testdata/tls-server.pem"),
+                                            Path::new("testdata/tls-server.key"))
+                             .unwrap();
+        server.add_alpn(DOQ_ALPN).set_early_data(true);
+        let mut client = ClientConfig::new();
+        client.load_roots(Path::new("testdata/tls-ca.pem")).unwrap();
+        client.add_alpn(DOQ_ALPN);
+        (Arc::new(client), server)
+    }
+
+    // Passes datagrams both ways until neither end has any, and returns
+    // the queries the server took and what the client got back.
+    fn pump(client: &mut DoqClient,
+            server: &mut DoqServer)
+        -> (Vec<(DoqHandle, Vec<u8>)>, Vec<(Vec<u8>, Option<Vec<u8>>)>) {
+        let from: SocketAddr = "192.0.2.1:4433".parse().unwrap();
+        let now = Instant::now();
+        let (mut queries, mut done) = (Vec::new(), Vec::new());
+        loop {
+            let mut quiet = true;
+            while let Some(datagram) = client.next_datagram(now) {
+                quiet = false;
+                queries.extend(server.received(&datagram, from, now));
+            }
+            for (datagram, to) in server.datagrams(now) {
+                assert_eq!(from, to);
+                quiet = false;
+                if let Ok(finished) = client.received(&datagram, now) {
+                    done.extend(finished);
+                }
+            }
+            if quiet {
+                return (queries, done);
+            }
+        }
+    }
+
+    fn client(config: &Arc<ClientConfig>) -> DoqClient {
+        let server: SocketAddr = "192.0.2.53:853".parse().unwrap();
+        DoqClient::new(config, server, "dns.example", Instant::now()).unwrap()
+    }
+
+    #[test]
+    fn queries_and_failures() {
+        let (config, server_config) = configs();
+        let mut server = DoqServer::new(&server_config);
+        let mut client = client(&config);
+        let sent = query(7, "example.com.", 1);
+        assert!(client.query(&sent, Instant::now()));
+        let (queries, _) = pump(&mut client, &mut server);
+        assert_eq!(vec![query(0, "example.com.", 1)],
+                   queries.iter().map(|q| q.1.clone()).collect::<Vec<_>>());
+        let mut answer = query(0, "example.com.", 1);
+        HeaderMut::at_raw(&mut answer[..]).set_qr(true);
+        server.respond(queries[0].0, &answer);
+        let (_, done) = pump(&mut client, &mut server);
+        let mut expected = sent.clone();
+        HeaderMut::at_raw(&mut expected[..]).set_qr(true);
+        assert_eq!(vec![(sent.clone(), Some(expected))], done);
+
+        // A query failed by the server.
+        assert!(client.query(&sent, Instant::now()));
+        let (queries, _) = pump(&mut client, &mut server);
+        server.fail(queries[0].0);
+        let (_, done) = pump(&mut client, &mut server);
+        assert_eq!(vec![(sent.clone(), None)], done);
+        assert_eq!(1, server.connections());
+        client.close(Instant::now());
+        pump(&mut client, &mut server);
+        // Closed connections linger for three probe timeouts.
+        let later = Instant::now() + Duration::from_secs(5);
+        server.timeout(later);
+        server.datagrams(later);
+        assert_eq!(0, server.connections());
+    }
+
+    #[test]
+    fn early_updates_wait() {
+        let (config, server_config) = configs();
+        let mut server = DoqServer::new(&server_config);
+        let mut first = client(&config);
+        assert!(first.query(&query(1, "example.com.", 1), Instant::now()));
+        pump(&mut first, &mut server);
+
+        // Resumed, an UPDATE is not sent in 0-RTT; a QUERY is, and is
+        // answered before the handshake is over.
+        let mut client = client(&config);
+        let mut update = query(2, "example.com.", 1);
+        HeaderMut::at_raw(&mut update[..]).set_op(OP_UPDATE);
+        let plain = query(3, "example.com.", 1);
+        assert!(client.query(&update, Instant::now()) && client.query(&plain, Instant::now()));
+        let from: SocketAddr = "192.0.2.1:4433".parse().unwrap();
+        let mut early = Vec::new();
+        while let Some(datagram) = client.next_datagram(Instant::now()) {
+            early.extend(server.received(&datagram, from, Instant::now()));
+        }
+        assert_eq!(vec![query(0, "example.com.", 1)],
+                   early.iter().map(|q| q.1.clone()).collect::<Vec<_>>());
+        let (queries, _) = pump(&mut client, &mut server);
+        let mut update = query(0, "example.com.", 1);
+        HeaderMut::at_raw(&mut update[..]).set_op(OP_UPDATE);
+        assert_eq!(vec![update], queries.iter().map(|q| q.1.clone()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn protocol_errors() {
+        let (config, server_config) = configs();
+        let mut server = DoqServer::new(&server_config);
+        let from: SocketAddr = "192.0.2.1:4433".parse().unwrap();
+        // A version the server does not speak gets the one it does.
+        let mut packet = vec![0xc0, 0, 0, 0, 2, 1, 9, 1, 8];
+        packet.resize(1200, 0);
+        assert!(server.received(&packet, from, Instant::now()).is_empty());
+        let negotiation = server.datagrams(Instant::now()).remove(0).0;
+        assert_eq!(&[0, 0, 0, 0, 1, 8, 1, 9][..], &negotiation[1..9]);
+        assert!(negotiation[9..].chunks(4).any(|version| version == &[0, 0, 0, 1][..]));
+        assert_eq!(0, server.connections());
+
+        // A query with an ID other than zero closes the connection.
+        assert_eq!(None, doq_message(&[0, 12, 0]));
+        let server_address: SocketAddr = "192.0.2.53:853".parse().unwrap();
+        let mut endpoint = Endpoint::new(Arc::new(EndpointConfig::default()), None, false, None);
+        let crypto = QuicClientConfig::try_from(config.rustls_config()).unwrap();
+        let (_, mut client) = endpoint.connect(Instant::now(),
+                                               quinn_proto::ClientConfig::new(Arc::new(crypto)),
+                                               server_address,
+                                               "dns.example")
+                                      .unwrap();
+        let mut now = Instant::now();
+        let mut id = None;
+        for _ in 0..10 {
+            if id.is_none() {
+                id = client.streams().open(Dir::Bi);
+                if let Some(id) = id {
+                    let frame = tcp_frame(&query(5, "example.com.", 1)).unwrap();
+                    assert_eq!(frame.len(), client.send_stream(id).write(&frame).unwrap());
+                    client.send_stream(id).finish().unwrap();
+                }
+            }
+            let mut buffer = Vec::new();
+            while let Some(transmit) = client.poll_transmit(now, 1, &mut buffer) {
+                server.received(&buffer[..transmit.size], from, now);
+                buffer.clear();
+            }
+            for (datagram, _) in server.datagrams(now) {
+                let event = endpoint.handle(now,
+                                            server_address,
+                                            None,
+                                            None,
+                                            BytesMut::from(&datagram[..]),
+                                            &mut buffer);
+                if let Some(DatagramEvent::ConnectionEvent(_, event)) = event {
+                    client.handle_event(event);
+                }
+            }
+            now += Duration::from_millis(1);
+        }
+        let mut lost = None;
+        while let Some(event) = client.poll() {
+            if let Event::ConnectionLost { reason } = event {
+                lost = Some(reason);
+            }
+        }
+        match lost {
+            Some(ConnectionError::ApplicationClosed(ApplicationClose { error_code, .. })) => {
+                assert_eq!(doq_code(DOQ_PROTOCOL_ERROR), error_code)
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Authoritative serving of loaded zones, and relaying of queries for the rest.
+
+mod authority;
+mod catalog;
+mod config;
+mod dnscrypt;
+mod doh;
+mod doq;
+mod notify;
+mod relay;
+mod request;
+mod secondary;
+mod stream;
+mod transfer;
+mod update;
+
+pub use self::authority::{Authority, EDNS_PAYLOAD, MAX_TCP_RESPONSE, MAX_UDP_RESPONSE};
+pub use self::catalog::{CATALOG_VERSION, CatalogChange, Catalogs, Member, catalog_members};
+pub use self::config::{Client, Config, ConfigError, DEFAULT_LISTEN, DEFAULT_MAX_CONNECTIONS,
+                       DigestConfig, GroupConfig, HTTPS_PORT, HTTP_PORT, NotifyConfig,
+                       SecondaryConfig, TransferConfig, UpdateConfig, ZoneConfig};
+pub use self::dnscrypt::{CERT_LIFETIME, DnscryptRequest, DnscryptServer, DnscryptSession,
+                         KEY_ROTATION_MS};
+pub use self::doh::{DNS_JSON, DNS_MESSAGE, DNS_PLUS_JSON, DOH_PATH, DohConnection, DohFormat,
+                    JSON_PATH, doh_query, doh_response, odoh_query};
+pub use self::doq::{DOQ_ALPN, DOQ_EXCESSIVE_LOAD, DOQ_INTERNAL_ERROR, DOQ_NO_ERROR,
+                    DOQ_PROTOCOL_ERROR, DOQ_REQUEST_CANCELLED, DoqHandle, DoqRead, DoqServer,
+                    DoqStreams, doq_code, doq_message};
+pub use self::notify::{NOTIFY_ATTEMPTS, NOTIFY_INTERVAL_MS, Notifier, notify_message, parse_notify};
+pub use self::relay::{EXPIRE_INTERVAL_MS, MAX_CACHED, MAX_CACHE_TTL, MAX_PENDING, RELAY_TIMEOUT_MS,
+                      Forwarder, OdohTarget, Relay, Relayed, TlsForwarder};
+pub use self::request::{Query, Request};
+pub use self::secondary::{ApplyError, INITIAL_RETRY, MIN_REFRESH, MIN_RETRY, Secondary,
+                          apply_transfer, notify_is_news};
+pub use self::stream::{HIGH_WATER_MARK, IDLE_TIMEOUT_MS, StreamConnection};
+pub use self::transfer::{Responses, client_serial};
+pub use self::update::Update;
@@ -0,0 +1,199 @@
+//! DNS NOTIFY (RFC 1996): telling secondaries that a zone changed.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use protocol::{Header, HeaderMut, MessageCursor, Name, QuestionMut, RData, Record, OP_NOTIFY};
+use protocol::rdata::{CLASS_IN, TYPE_SOA};
+use super::request::Request;
+
+/// How often a NOTIFY is sent before giving up on an answer.
+pub const NOTIFY_ATTEMPTS: u32 = 5;
+/// Milliseconds to wait for an answer before sending again.
+pub const NOTIFY_INTERVAL_MS: u64 = 2000;
+
+/// A NOTIFY for `zone`, with its new SOA in the answer section as a hint.
+pub fn notify_message(zone: &Name, id: u16, soa: Option<&Record>) -> Vec<u8> {
+    let mut buffer = vec![0u8; 512];
+    let mut idx = MessageCursor::new(buffer.len());
+    HeaderMut::at(&mut idx, &mut buffer[..])
+        .unwrap()
+        .make_query(id)
+        .set_op(OP_NOTIFY)
+        .set_aa(true)
+        .set_rd(false)
+        .set_qd(1);
+    QuestionMut::at(&mut idx, &mut buffer, &zone.segments(), TYPE_SOA, CLASS_IN).unwrap();
+    if let Some(soa) = soa {
+        if soa.write_at(&mut idx, &mut buffer).is_some() {
+            HeaderMut::at_raw(&mut buffer[..]).set_an(1);
+        }
+    }
+    buffer.truncate(idx.tell());
+    buffer
+}
+
+/// The zone named by a NOTIFY request, and the serial it announces if it
+/// carried an SOA.
+pub fn parse_notify(message: &[u8]) -> Option<(Name, Option<u32>)> {
+    let request = match Request::parse(message) {
+        Some(ref request) if request.opcode == OP_NOTIFY => request.clone(),
+        _ => return None,
+    };
+    let query = match request.query {
+        Some(query) => query,
+        None => return None,
+    };
+    let answers = Header::at(message).an().unwrap_or(0);
+    let serial = match Record::read_section(message, request.question_end, answers) {
+        Some((records, _)) => {
+            records.iter()
+                   .filter_map(|r| {
+                       match r.rdata {
+                           RData::Soa(ref soa) if r.name == query.name => Some(soa.serial),
+                           _ => None,
+                       }
+                   })
+                   .next()
+        }
+        None => None,
+    };
+    Some((query.name, serial))
+}
+
+#[derive(Debug)]
+struct Pending {
+    target: SocketAddr,
+    message: Vec<u8>,
+    attempts: u32,
+}
+
+/// NOTIFY messages sent and not yet answered.
+#[derive(Debug)]
+pub struct Notifier {
+    pending: HashMap<u16, Pending>,
+    next_id: u16,
+}
+
+impl Notifier {
+    pub fn new(first_id: u16) -> Notifier {
+        Notifier {
+            pending: HashMap::new(),
+            next_id: first_id,
+        }
+    }
+
+    /// Prepares a NOTIFY of `zone` for each target. Returns the messages to
+    /// send now; each should be followed by `retry` with its ID after
+    /// `NOTIFY_INTERVAL_MS`.
+    pub fn notify(&mut self,
+                  zone: &Name,
+                  soa: Option<&Record>,
+                  targets: &[SocketAddr])
+        -> Vec<(u16, SocketAddr, Vec<u8>)> {
+        let mut sends = Vec::new();
+        for target in targets {
+            while self.pending.contains_key(&self.next_id) {
+                self.next_id = self.next_id.wrapping_add(1);
+            }
+            let id = self.next_id;
+            self.next_id = self.next_id.wrapping_add(1);
+            let message = notify_message(zone, id, soa);
+            self.pending.insert(id,
+                                Pending {
+                                    target: *target,
+                                    message: message.clone(),
+                                    attempts: 1,
+                                });
+            sends.push((id, *target, message));
+        }
+        sends
+    }
+
+    /// Takes a response from `from`. Returns true if it answered one of our
+    /// NOTIFY messages, which is then no longer sent.
+    pub fn acknowledge(&mut self, message: &[u8], from: &SocketAddr) -> bool {
+        let header = Header::at(message);
+        if !header.is_response() || header.op() != Some(OP_NOTIFY) {
+            return false;
+        }
+        let id = match header.id() {
+            Some(id) => id,
+            None => return false,
+        };
+        if self.pending.get(&id).map_or(false, |p| p.target == *from) {
+            self.pending.remove(&id);
+            return true;
+        }
+        false
+    }
+
+    /// The NOTIFY to send again for `id`, or None if it was answered or has
+    /// used up its attempts.
+    pub fn retry(&mut self, id: u16) -> Option<(SocketAddr, Vec<u8>)> {
+        let give_up = match self.pending.get_mut(&id) {
+            Some(pending) if pending.attempts < NOTIFY_ATTEMPTS => {
+                pending.attempts += 1;
+                return Some((pending.target, pending.message.clone()));
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if give_up {
+            self.pending.remove(&id);
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use protocol::{HeaderMut, Name};
+    use zone::{Zone, ZoneParser};
+
+    fn soa() -> ::protocol::Record {
+        let origin = Name::parse("example.com.", None).unwrap();
+        let text = "@ 300 SOA ns hostmaster 42 1 1 1 1\n";
+        let records = ZoneParser::new(Cursor::new(text.as_bytes().to_vec()),
+                                      "test.zone",
+                                      Some(origin.clone()))
+                          .map(|r| r.unwrap());
+        Zone::from_records(origin, records).soa_record().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let zone = Name::parse("example.com.", None).unwrap();
+        let message = notify_message(&zone, 7, Some(&soa()));
+        assert_eq!(Some((zone.clone(), Some(42))), parse_notify(&message));
+        let message = notify_message(&zone, 7, None);
+        assert_eq!(Some((zone, None)), parse_notify(&message));
+    }
+
+    #[test]
+    fn retries_until_answered() {
+        let zone = Name::parse("example.com.", None).unwrap();
+        let targets = ["192.0.2.1:53".parse().unwrap(), "192.0.2.2:53".parse().unwrap()];
+        let mut notifier = Notifier::new(100);
+        let sends = notifier.notify(&zone, Some(&soa()), &targets);
+        assert_eq!(2, sends.len());
+        let (id, target, ref message) = sends[0];
+        assert_eq!(targets[0], target);
+
+        let mut answer = message.clone();
+        HeaderMut::at_raw(&mut answer[..]).set_qr(true);
+        // Only from the server it was sent to.
+        assert!(!notifier.acknowledge(&answer, &targets[1]));
+        assert!(notifier.retry(id).is_some());
+        assert!(notifier.acknowledge(&answer, &targets[0]));
+        assert_eq!(None, notifier.retry(id));
+
+        let (id, _, _) = sends[1];
+        for _ in 1..NOTIFY_ATTEMPTS {
+            assert!(notifier.retry(id).is_some());
+        }
+        assert_eq!(None, notifier.retry(id));
+    }
+}
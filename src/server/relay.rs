@@ -0,0 +1,978 @@
+//! Caching forwarding relay: queries outside the loaded zones go to an
+//! upstream resolver under fresh random IDs, and the answers come back to
+//! the client under its own, with a copy kept until its TTLs run out.
+//! Plain DNS upstreams are asked over UDP, and answers that come back
+//! truncated asked for again over TCP; DNS over TLS, HTTPS and QUIC
+//! upstreams, and Oblivious DoH targets through their proxies, are asked
+//! over a connection kept open to them, and DNSCrypt ones by a client that
+//! keeps their certificate.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use client::{DnscryptUpstream, DohUpstream, DoqUpstream, DotUpstream, Upstream};
+use crypto::random_bytes;
+use dnscrypt::Stamp;
+use odoh::OdohConfig;
+use protocol::{Header, HeaderMut, Name, OP_QUERY, Question, RC_NAME_ERROR, RC_OK,
+               OPT_LEN, RC_SERVER_ERROR, RData, Record, append_opt, has_edns,
+               truncate_for_udp};
+use protocol::rdata::TYPE_OPT;
+use tls::{ClientConfig, TlsError};
+use super::authority::EDNS_PAYLOAD;
+use super::doq::DOQ_ALPN;
+use super::request::{Query, Request};
+
+/// Milliseconds to wait for an upstream answer before failing the query.
+pub const RELAY_TIMEOUT_MS: u64 = 2000;
+/// Queries waiting on upstream at once; past this, queries fail at once.
+pub const MAX_PENDING: usize = 4096;
+/// Answers cached at once.
+pub const MAX_CACHED: usize = 10000;
+/// Longest an answer is cached, in seconds, whatever its TTLs say.
+pub const MAX_CACHE_TTL: u32 = 86400;
+/// Milliseconds between sweeps of expired answers out of the cache.
+pub const EXPIRE_INTERVAL_MS: u64 = 60_000;
+
+// Random octets read at a time for query IDs.
+const RANDOM_BATCH: usize = 256;
+
+const HEADER_SIZE: usize = 12;
+
+/// An upstream server spoken to over TLS, and how it is authenticated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsForwarder {
+    /// A host name or an address.
+    pub host: String,
+    pub port: u16,
+    /// The name the server's certificate must be good for.
+    pub name: String,
+    /// A PEM file of the roots to trust, rather than those of the web PKI.
+    pub roots: Option<PathBuf>,
+    /// SPKI pins, one of which the server's chain must have. With pins and
+    /// no roots, a pin match is authentication enough.
+    pub pins: Vec<String>,
+}
+
+impl TlsForwarder {
+    /// The server's address, its host name looked up if it has one.
+    pub fn address(&self) -> io::Result<SocketAddr> {
+        if let Ok(ip) = self.host.parse::<IpAddr>() {
+            return Ok(SocketAddr::new(ip, self.port));
+        }
+        match try!((&self.host[..], self.port).to_socket_addrs()).next() {
+            Some(address) => Ok(address),
+            None => {
+                Err(io::Error::new(io::ErrorKind::NotFound,
+                                   format!("{}: no address", self.host)))
+            }
+        }
+    }
+
+    // The server as the authority of a URI.
+    fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    // The name for the certificate, as the fragment of a URI, if it is not
+    // the host's.
+    fn fragment(&self) -> String {
+        if self.name != self.host {
+            format!("#{}", self.name)
+        } else {
+            String::new()
+        }
+    }
+
+    /// A client configuration that authenticates the server as asked,
+    /// offering `alpn` if given.
+    pub fn client_config(&self, alpn: Option<&[u8]>) -> Result<ClientConfig, TlsError> {
+        let mut config = ClientConfig::new();
+        match self.roots {
+            Some(ref roots) => {
+                try!(config.load_roots(roots));
+            }
+            None if self.pins.is_empty() => {
+                config.add_web_roots();
+            }
+            None => {}
+        }
+        for pin in &self.pins {
+            config.add_pin(pin);
+        }
+        if let Some(alpn) = alpn {
+            config.add_alpn(alpn);
+        }
+        Ok(config)
+    }
+}
+
+/// An Oblivious DoH target, reached through a proxy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OdohTarget {
+    /// The host, and the port if not 443, for the proxy to reach it at.
+    pub host: String,
+    pub path: String,
+    /// The X25519 key queries are sealed for.
+    pub public_key: Vec<u8>,
+}
+
+/// An upstream resolver to relay queries to, and how it is spoken to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Forwarder {
+    /// Plain DNS over UDP, and TCP for truncated answers.
+    Udp(SocketAddr),
+    /// DNS over TLS (RFC 7858).
+    Tls(TlsForwarder),
+    /// DNS over HTTPS (RFC 8484), at a path on the server.
+    Https(TlsForwarder, String),
+    /// DNS over QUIC (RFC 9250).
+    Quic(TlsForwarder),
+    /// DNSCrypt, at the resolver its stamp gives.
+    Dnscrypt(Stamp),
+    /// Oblivious DoH (RFC 9230): the target, through the DNS over HTTPS
+    /// server at the path as a proxy.
+    Odoh(TlsForwarder, String, OdohTarget),
+}
+
+// A DNS over HTTPS client for the server at `path`.
+fn doh_upstream(tls: &TlsForwarder, path: &str) -> io::Result<DohUpstream> {
+    let config = try!(tls.client_config(Some(b"h2"))
+                         .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)));
+    let mut upstream = DohUpstream::new(try!(tls.address()), &tls.name, Arc::new(config));
+    upstream.set_path(path);
+    Ok(upstream)
+}
+
+impl Forwarder {
+    /// A client for the forwarder, to send queries as they come and wait
+    /// for their answers; None for plain DNS, which the event loop asks
+    /// itself. Reads the roots, if they are in a file, and looks the host
+    /// up, but connects only when first asked.
+    pub fn upstream(&self) -> io::Result<Option<Box<Upstream + Send>>> {
+        let invalid = |e: TlsError| io::Error::new(io::ErrorKind::InvalidInput, e);
+        match *self {
+            Forwarder::Udp(_) => Ok(None),
+            Forwarder::Tls(ref tls) => {
+                let config = try!(tls.client_config(None).map_err(invalid));
+                let upstream = DotUpstream::new(try!(tls.address()), &tls.name, Arc::new(config));
+                Ok(Some(Box::new(upstream)))
+            }
+            Forwarder::Https(ref tls, ref path) => {
+                Ok(Some(Box::new(try!(doh_upstream(tls, path)))))
+            }
+            Forwarder::Quic(ref tls) => {
+                let config = try!(tls.client_config(Some(DOQ_ALPN)).map_err(invalid));
+                let upstream = DoqUpstream::new(try!(tls.address()), &tls.name, Arc::new(config));
+                Ok(Some(Box::new(upstream)))
+            }
+            Forwarder::Dnscrypt(ref stamp) => {
+                let upstream = DnscryptUpstream::new(stamp.address,
+                                                     stamp.provider.clone(),
+                                                     &stamp.provider_key);
+                Ok(Some(Box::new(upstream)))
+            }
+            Forwarder::Odoh(ref tls, ref path, ref target) => {
+                let mut upstream = try!(doh_upstream(tls, path));
+                upstream.set_odoh_target(&target.host,
+                                         &target.path,
+                                         OdohConfig::new(&target.public_key));
+                Ok(Some(Box::new(upstream)))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Forwarder {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Forwarder::Udp(ref address) => write!(fmt, "{}", address),
+            Forwarder::Tls(ref tls) => write!(fmt, "tls://{}{}", tls.authority(), tls.fragment()),
+            Forwarder::Https(ref tls, ref path) => {
+                write!(fmt, "https://{}{}{}", tls.authority(), path, tls.fragment())
+            }
+            Forwarder::Quic(ref tls) => {
+                write!(fmt, "quic://{}{}", tls.authority(), tls.fragment())
+            }
+            Forwarder::Dnscrypt(ref stamp) => write!(fmt, "{}", stamp),
+            Forwarder::Odoh(ref tls, ref path, ref target) => {
+                write!(fmt,
+                       "https://{}{}{} target https://{}{}",
+                       tls.authority(),
+                       path,
+                       tls.fragment(),
+                       target.host,
+                       target.path)
+            }
+        }
+    }
+}
+
+/// What to do with a query or an upstream response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Relayed<C> {
+    /// Send this answer back to the client.
+    Answer(C, Vec<u8>),
+    /// Send this message upstream; it waits under this ID, which should be
+    /// passed to `timeout` after `RELAY_TIMEOUT_MS`.
+    Forward(u16, Forwarder, Vec<u8>),
+    /// The answer to this message came back truncated: ask again over TCP
+    /// and pass the answer to `response`. The query has its time again.
+    Retry(u16, SocketAddr, Vec<u8>),
+}
+
+#[derive(Debug)]
+struct Pending<C> {
+    request: Request,
+    client: C,
+    max_len: usize,
+    upstream: Forwarder,
+    // The query as it went upstream, to ask again over TCP.
+    forwarded: Vec<u8>,
+    retried: bool,
+    deadline: Instant,
+}
+
+#[derive(Debug)]
+struct Cached {
+    rc: u8,
+    // Answer, authority and additional records as they came, less OPT.
+    sections: [Vec<Record>; 3],
+    stored: Instant,
+    expires: Instant,
+}
+
+/// Queries forwarded and not yet answered, each with the client `C` that
+/// asked, and the answers cached.
+#[derive(Debug)]
+pub struct Relay<C> {
+    upstreams: Vec<Forwarder>,
+    next_upstream: usize,
+    pending: HashMap<u16, Pending<C>>,
+    cache: HashMap<(Name, u16, u16), Cached>,
+    // Random octets not yet taken for IDs.
+    random: Vec<u8>,
+}
+
+impl<C> Relay<C> {
+    /// A relay to `upstreams`, taken in turn.
+    pub fn new(upstreams: Vec<Forwarder>) -> Relay<C> {
+        Relay {
+            upstreams: upstreams,
+            next_upstream: 0,
+            pending: HashMap::new(),
+            cache: HashMap::new(),
+            random: Vec::new(),
+        }
+    }
+
+    /// Takes a query from `client`, which is handed back with the answer.
+    /// Answers, from the cache or from upstream, are cut down to `max_len`
+    /// octets. Returns None for messages other than standard queries with
+    /// one question, which are not relayed.
+    pub fn query(&mut self,
+                 message: &[u8],
+                 client: C,
+                 max_len: usize,
+                 now: Instant)
+        -> Option<Relayed<C>> {
+        let request = match Request::parse(message) {
+            Some(request) => request,
+            None => return None,
+        };
+        let key = match request.query {
+            Some(ref query) if request.opcode == OP_QUERY => {
+                (query.name.clone(), query.qtype, query.qclass)
+            }
+            _ => return None,
+        };
+        if let Some(cached) = self.cache.get(&key) {
+            if cached.expires > now {
+                // With an OPT record back if the query had one.
+                if !has_edns(message) {
+                    let answer = cached_answer(&request, cached, max_len, now);
+                    return Some(Relayed::Answer(client, answer));
+                }
+                let mut answer = cached_answer(&request,
+                                               cached,
+                                               max_len.saturating_sub(OPT_LEN),
+                                               now);
+                append_opt(&mut answer, EDNS_PAYLOAD as u16);
+                return Some(Relayed::Answer(client, answer));
+            }
+        }
+        if self.upstreams.is_empty() || self.pending.len() >= MAX_PENDING {
+            return Some(Relayed::Answer(client, request.error(RC_SERVER_ERROR, max_len)));
+        }
+        let id = self.fresh_id();
+        let upstream = self.upstreams[self.next_upstream % self.upstreams.len()].clone();
+        self.next_upstream = self.next_upstream.wrapping_add(1);
+        let mut forwarded = message.to_vec();
+        HeaderMut::at_raw(&mut forwarded[..]).set_id(id);
+        self.pending.insert(id,
+                            Pending {
+                                request: request,
+                                client: client,
+                                max_len: max_len,
+                                upstream: upstream.clone(),
+                                forwarded: forwarded.clone(),
+                                retried: false,
+                                deadline: now + Duration::from_millis(RELAY_TIMEOUT_MS),
+                            });
+        Some(Relayed::Forward(id, upstream, forwarded))
+    }
+
+    // An ID no pending query has, so answers cannot be guessed or mixed up.
+    fn fresh_id(&mut self) -> u16 {
+        loop {
+            if self.random.len() < 2 {
+                self.random = random_bytes(RANDOM_BATCH);
+            }
+            let id = (self.random.pop().unwrap() as u16) << 8 | self.random.pop().unwrap() as u16;
+            if !self.pending.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// Takes a response from `from`. If it answers a pending query, from
+    /// the server it went to and for the same question, gives the answer
+    /// for the client under its ID, cut down to the size the client takes,
+    /// and caches it; or, if it came truncated over UDP, the query to ask
+    /// again over TCP. None for anything else, truncated answers among them
+    /// once the query has been asked over TCP.
+    pub fn response(&mut self,
+                    message: &[u8],
+                    from: &Forwarder,
+                    now: Instant)
+        -> Option<Relayed<C>> {
+        let header = Header::at(message);
+        if message.len() < HEADER_SIZE || !header.is_response() {
+            return None;
+        }
+        let id = header.id().unwrap();
+        let matches = match self.pending.get(&id) {
+            Some(pending) => {
+                pending.upstream == *from && question(message) == pending.request.query
+            }
+            None => false,
+        };
+        if !matches {
+            return None;
+        }
+        if let (true, &Forwarder::Udp(upstream)) = (header.is_truncated(), from) {
+            let pending = self.pending.get_mut(&id).unwrap();
+            if pending.retried {
+                return None;
+            }
+            pending.retried = true;
+            pending.deadline = now + Duration::from_millis(RELAY_TIMEOUT_MS);
+            return Some(Relayed::Retry(id, upstream, pending.forwarded.clone()));
+        }
+        let pending = self.pending.remove(&id).unwrap();
+        self.store(&pending.request, message, now);
+        let mut response = match truncate_for_udp(message, pending.max_len) {
+            Some(response) => response,
+            None => pending.request.error(RC_SERVER_ERROR, pending.max_len),
+        };
+        HeaderMut::at_raw(&mut response[..]).set_id(pending.request.id);
+        Some(Relayed::Answer(pending.client, response))
+    }
+
+    // Caches an answer: a truncated one never, an error or referral without
+    // records to time it by neither, and a name error or empty answer only
+    // as long as its SOA says (RFC 2308 Section 5).
+    fn store(&mut self, request: &Request, message: &[u8], now: Instant) {
+        let header = Header::at(message);
+        let rc = header.rc().unwrap();
+        if header.is_truncated() || rc != RC_OK && rc != RC_NAME_ERROR {
+            return;
+        }
+        let counts = [header.an().unwrap(), header.ns().unwrap(), header.ar().unwrap()];
+        let mut at = match question_end(message) {
+            Some(at) => at,
+            None => return,
+        };
+        let mut sections = [Vec::new(), Vec::new(), Vec::new()];
+        for (i, &count) in counts.iter().enumerate() {
+            let (records, next) = match Record::read_section(message, at, count) {
+                Some(section) => section,
+                None => return,
+            };
+            sections[i] = records.into_iter().filter(|r| r.rtype() != TYPE_OPT).collect();
+            at = next;
+        }
+        let mut ttl = sections.iter().flat_map(|s| s.iter()).map(|r| r.ttl).min();
+        if sections[0].is_empty() {
+            let minimum = sections[1].iter()
+                                     .filter_map(|r| {
+                                         match r.rdata {
+                                             RData::Soa(ref soa) => Some(soa.minimum),
+                                             _ => None,
+                                         }
+                                     })
+                                     .next();
+            ttl = match (ttl, minimum) {
+                (Some(ttl), Some(minimum)) if ttl < minimum => Some(ttl),
+                (Some(_), Some(minimum)) => Some(minimum),
+                _ => None,
+            };
+        }
+        let ttl = match ttl {
+            Some(0) | None => return,
+            Some(ttl) if ttl > MAX_CACHE_TTL => MAX_CACHE_TTL,
+            Some(ttl) => ttl,
+        };
+        if self.cache.len() >= MAX_CACHED {
+            self.cache.retain(|_, c| c.expires > now);
+            if self.cache.len() >= MAX_CACHED {
+                return;
+            }
+        }
+        let query = request.query.as_ref().unwrap();
+        self.cache.insert((query.name.clone(), query.qtype, query.qclass),
+                          Cached {
+                              rc: rc,
+                              sections: sections,
+                              stored: now,
+                              expires: now + Duration::from_secs(ttl as u64),
+                          });
+    }
+
+    /// When the query waiting under `id` times out, if it is still waiting.
+    pub fn deadline(&self, id: u16) -> Option<Instant> {
+        self.pending.get(&id).map(|p| p.deadline)
+    }
+
+    /// Gives up on the query waiting under `id` if its time is up by `now`.
+    /// Returns the client and a server failure to send it.
+    pub fn timeout(&mut self, id: u16, now: Instant) -> Option<(C, Vec<u8>)> {
+        match self.pending.get(&id) {
+            Some(pending) if pending.deadline <= now => {}
+            _ => return None,
+        }
+        let pending = self.pending.remove(&id).unwrap();
+        let failure = pending.request.error(RC_SERVER_ERROR, pending.max_len);
+        Some((pending.client, failure))
+    }
+
+    /// Drops the answers that have expired by `now`. Worth calling every
+    /// `EXPIRE_INTERVAL_MS`.
+    pub fn expire(&mut self, now: Instant) {
+        self.cache.retain(|_, c| c.expires > now);
+    }
+}
+
+// The question of a response, read the way `Request` reads a query's.
+fn question(message: &[u8]) -> Option<Query> {
+    if Header::at(message).qd() != Some(1) {
+        return None;
+    }
+    let question = match Question::from_message(message, HEADER_SIZE) {
+        Some(question) => question,
+        None => return None,
+    };
+    let name = question.name().and_then(|n| Name::from_domain_name(message, n));
+    match (name, question.qtype(), question.qclass()) {
+        (Some(name), Some(qtype), Some(qclass)) => {
+            Some(Query {
+                name: name,
+                qtype: qtype,
+                qclass: qclass,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn question_end(message: &[u8]) -> Option<usize> {
+    Question::from_message(message, HEADER_SIZE).map(|q| q.end_offset())
+}
+
+// A cached answer to `request`, its TTLs less the time it has been cached,
+// and truncated if it does not fit `max_len`.
+fn cached_answer(request: &Request, cached: &Cached, max_len: usize, now: Instant) -> Vec<u8> {
+    let age = (now - cached.stored).as_secs() as u32;
+    let (mut buffer, mut idx) = request.response(max_len);
+    let mut counts = [0u16; 3];
+    let mut truncated = false;
+    for (i, section) in cached.sections.iter().enumerate() {
+        for record in section {
+            if truncated {
+                break;
+            }
+            let mut record = record.clone();
+            record.ttl = record.ttl.saturating_sub(age);
+            let at = idx.tell();
+            match record.write_at(&mut idx, &mut buffer) {
+                Some(_) => counts[i] += 1,
+                None => {
+                    idx.rewind(at);
+                    truncated = true;
+                }
+            }
+        }
+    }
+    HeaderMut::at_raw(&mut buffer[..])
+        .set_ra(true)
+        .set_rc(cached.rc)
+        .set_tc(truncated)
+        .set_an(counts[0])
+        .set_ns(counts[1])
+        .set_ar(counts[2]);
+    buffer.truncate(idx.tell());
+    buffer
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, UdpSocket};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crypto::Ed25519PrivateKey;
+    use http::{self, Http2Connection};
+    use odoh::{ODOH_MESSAGE, OdohKey};
+    use protocol::{Header, HeaderMut, Name, RC_NAME_ERROR, RData, Record, Soa};
+    use protocol::rdata::{CLASS_IN, TYPE_A, TYPE_AAAA};
+    use server::{DnscryptRequest, DnscryptServer, DohConnection, DoqServer, Request,
+                 StreamConnection};
+    use tls::{ServerConfig, TlsConnection};
+    use tsig::now;
+    use test_util::query;
+
+    fn response(query: &[u8], rc: u8, answers: &[Record], authorities: &[Record]) -> Vec<u8> {
+        let (mut buffer, mut idx) = Request::parse(query).unwrap().response(512);
+        for record in answers.iter().chain(authorities) {
+            record.write_at(&mut idx, &mut buffer).unwrap();
+        }
+        HeaderMut::at_raw(&mut buffer[..])
+            .set_rc(rc)
+            .set_an(answers.len() as u16)
+            .set_ns(authorities.len() as u16);
+        buffer.truncate(idx.tell());
+        buffer
+    }
+
+    fn record(name: &str, ttl: u32, rdata: RData) -> Record {
+        Record::new(Name::parse(name, None).unwrap(), CLASS_IN, ttl, rdata)
+    }
+
+    // A client address, and the socket the query came in on.
+    type Client = (SocketAddr, usize);
+
+    fn forwarded(relayed: Option<Relayed<Client>>) -> (u16, Forwarder, Vec<u8>) {
+        match relayed {
+            Some(Relayed::Forward(id, upstream, message)) => (id, upstream, message),
+            other => panic!("not forwarded: {:?}", other),
+        }
+    }
+
+    fn answered(relayed: Option<Relayed<Client>>) -> (Client, Vec<u8>) {
+        match relayed {
+            Some(Relayed::Answer(client, message)) => (client, message),
+            other => panic!("not answered: {:?}", other),
+        }
+    }
+
+    fn client() -> SocketAddr {
+        "192.0.2.9:49152".parse().unwrap()
+    }
+
+    fn udp(address: &str) -> Forwarder {
+        Forwarder::Udp(address.parse().unwrap())
+    }
+
+    fn tls_server(port: u16) -> TlsForwarder {
+        TlsForwarder {
+            host: "127.0.0.1".to_string(),
+            port: port,
+            name: "dns.example".to_string(),
+            roots: Some(PathBuf::from("testdata/tls-ca.pem")),
+            pins: Vec::new(),
+        }
+    }
+
+    fn https(port: u16) -> Forwarder {
+        Forwarder::Https(tls_server(port), "/dns-query".to_string())
+    }
+
+    fn server_config() -> ServerConfig {
+        ServerConfig::load(Path::new("testdata/tls-server.pem"),
+                           Path::new("testdata/tls-server.key"))
+            .unwrap()
+    }
+
+    fn www_address() -> Record {
+        record("www.example.com.", 300, RData::A("192.0.2.80".parse().unwrap()))
+    }
+
+    // Relays a query for www.example.com to `forwarder`, served by the
+    // thread `server`, and checks the answer comes back under the client's
+    // ID and is cached.
+    fn relay_through(forwarder: Forwarder, server: thread::JoinHandle<()>) {
+        let mut relay = Relay::new(vec![forwarder.clone()]);
+        let now = Instant::now();
+        let asked = query(7, "www.example.com.", TYPE_A);
+        let (_, to, message) = forwarded(relay.query(&asked, (client(), 0), 512, now));
+        assert_eq!(forwarder, to);
+        let mut upstream = forwarder.upstream().unwrap().unwrap();
+        let answers = upstream.query_all(&[message]).unwrap();
+        let (_, relayed) = answered(relay.response(&answers[0], &forwarder, now));
+        let header = Header::at(&relayed);
+        assert_eq!((Some(7), Some(RC_OK), Some(1)), (header.id(), header.rc(), header.an()));
+        drop(upstream);
+        server.join().unwrap();
+        answered(relay.query(&asked, (client(), 0), 512, now));
+    }
+
+    // A DNS over TLS server answering one query on one connection with the
+    // address of www.example.com.
+    fn serve_dot(listener: TcpListener) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut connection = StreamConnection::with_tls(Arc::new(server_config()), Instant::now());
+        let mut queries = Vec::new();
+        let mut buffer = [0u8; 4096];
+        while queries.is_empty() {
+            let n = stream.read(&mut buffer).unwrap();
+            queries = connection.received(&buffer[..n], Instant::now()).unwrap();
+            stream.write_all(connection.pending()).unwrap();
+            let n = connection.pending().len();
+            connection.written(n, Instant::now());
+        }
+        connection.respond(&response(&queries[0], RC_OK, &[www_address()], &[]));
+        stream.write_all(connection.pending()).unwrap();
+        let _ = stream.read(&mut buffer);
+    }
+
+    // A DNS over HTTPS server answering one query on one connection with
+    // the address of www.example.com.
+    fn serve_doh(listener: TcpListener) {
+        let mut config = server_config();
+        config.add_alpn(b"h2");
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut connection = DohConnection::with_tls(Arc::new(config), Instant::now());
+        let mut queries = Vec::new();
+        let mut buffer = [0u8; 4096];
+        while queries.is_empty() {
+            let n = stream.read(&mut buffer).unwrap();
+            queries = connection.received(&buffer[..n], Instant::now()).unwrap();
+            stream.write_all(connection.pending()).unwrap();
+            let n = connection.pending().len();
+            connection.written(n, Instant::now());
+        }
+        connection.respond(queries[0].0, &response(&queries[0].1, RC_OK, &[www_address()], &[]));
+        stream.write_all(connection.pending()).unwrap();
+        let _ = stream.read(&mut buffer);
+    }
+
+    // A DNS over QUIC server answering one query. It stops once the answer
+    // is sent rather than wait out the connection.
+    fn serve_doq(socket: UdpSocket) {
+        let mut config = server_config();
+        config.add_alpn(DOQ_ALPN);
+        let mut server = DoqServer::new(&config);
+        let mut answered = false;
+        let mut buffer = [0u8; 65536];
+        while !answered {
+            let (n, from) = socket.recv_from(&mut buffer).unwrap();
+            for (handle, query) in server.received(&buffer[..n], from, Instant::now()) {
+                assert!(server.respond(handle, &response(&query, RC_OK, &[www_address()], &[])));
+                answered = true;
+            }
+            for (datagram, to) in server.datagrams(Instant::now()) {
+                socket.send_to(&datagram, to).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn forwards_and_caches() {
+        let upstreams = vec![udp("192.0.2.1:53"), udp("192.0.2.2:53")];
+        let mut relay = Relay::new(upstreams.clone());
+        let start = Instant::now();
+        let asked = query(7, "www.example.com.", TYPE_A);
+        let (id, upstream, message) = forwarded(relay.query(&asked, (client(), 1), 512, start));
+        assert_eq!(upstreams[0], upstream);
+        assert_eq!(Some(id), Header::at(&message).id());
+        assert_eq!(&asked[2..], &message[2..]);
+        assert_eq!(Some(start + Duration::from_millis(RELAY_TIMEOUT_MS)), relay.deadline(id));
+
+        // Only the server asked may answer, and only the question asked.
+        let address = record("www.example.com.", 300, RData::A("192.0.2.80".parse().unwrap()));
+        let answer = response(&message, RC_OK, &[address.clone()], &[]);
+        assert_eq!(None, relay.response(&answer, &upstreams[1], start));
+        let other = query(id, "mail.example.com.", TYPE_A);
+        assert_eq!(None, relay.response(&response(&other, RC_OK, &[], &[]), &upstream, start));
+        assert_eq!(None, relay.response(&message, &upstream, start));
+        let (to, relayed) = answered(relay.response(&answer, &upstream, start));
+        assert_eq!(((client(), 1), Some(7)), (to, Header::at(&relayed).id()));
+        assert_eq!(&answer[2..], &relayed[2..]);
+        assert_eq!(None, relay.deadline(id));
+        assert_eq!(None, relay.response(&answer, &upstream, start));
+
+        // Then from the cache, with the TTL aged, whatever the case.
+        let later = start + Duration::from_secs(100);
+        let asked = query(8, "WWW.example.com.", TYPE_A);
+        let (_, cached) = answered(relay.query(&asked, (client(), 0), 512, later));
+        let header = Header::at(&cached);
+        assert_eq!((Some(8), Some(true), Some(1)), (header.id(), header.ra(), header.an()));
+        let (records, _) = Record::read_section(&cached, asked.len(), 1).unwrap();
+        let mut aged = address.clone();
+        aged.ttl = 200;
+        assert_eq!(vec![aged], records);
+
+        // Until it expires, and the next server is asked.
+        let expired = start + Duration::from_secs(300);
+        let (_, upstream, _) = forwarded(relay.query(&asked, (client(), 0), 512, expired));
+        assert_eq!(upstreams[1], upstream);
+    }
+
+    #[test]
+    fn negative_answers() {
+        let mut relay = Relay::new(vec![udp("192.0.2.1:53")]);
+        let now = Instant::now();
+        let soa = record("example.com.",
+                         3600,
+                         RData::Soa(Soa {
+                             mname: Name::parse("ns.example.com.", None).unwrap(),
+                             rname: Name::parse("hostmaster.example.com.", None).unwrap(),
+                             serial: 1,
+                             refresh: 7200,
+                             retry: 900,
+                             expire: 86400,
+                             minimum: 60,
+                         }));
+        let asked = query(7, "none.example.com.", TYPE_A);
+        let (_, upstream, message) = forwarded(relay.query(&asked, (client(), 0), 512, now));
+        let answer = response(&message, RC_NAME_ERROR, &[], &[soa]);
+        relay.response(&answer, &upstream, now).unwrap();
+        let (_, cached) = answered(relay.query(&asked, (client(), 0), 512, now));
+        assert_eq!((Some(RC_NAME_ERROR), Some(1)),
+                   (Header::at(&cached).rc(), Header::at(&cached).ns()));
+        // For the SOA's minimum rather than its TTL.
+        let later = now + Duration::from_secs(60);
+        forwarded(relay.query(&asked, (client(), 0), 512, later));
+
+        // Failures and answers with nothing to time them by are not kept.
+        let asked = query(8, "none.example.com.", TYPE_AAAA);
+        let (_, _, message) = forwarded(relay.query(&asked, (client(), 0), 512, now));
+        relay.response(&response(&message, RC_SERVER_ERROR, &[], &[]), &upstream, now).unwrap();
+        let (_, _, message) = forwarded(relay.query(&asked, (client(), 0), 512, now));
+        relay.response(&response(&message, RC_OK, &[], &[]), &upstream, now).unwrap();
+        forwarded(relay.query(&asked, (client(), 0), 512, now));
+    }
+
+    #[test]
+    fn truncated_retry() {
+        let server = "192.0.2.1:53".parse().unwrap();
+        let upstream = Forwarder::Udp(server);
+        let mut relay = Relay::new(vec![upstream.clone()]);
+        let start = Instant::now();
+        let asked = query(7, "www.example.com.", TYPE_A);
+        let (id, _, message) = forwarded(relay.query(&asked, (client(), 0), 512, start));
+        let mut truncated = response(&message, RC_OK, &[], &[]);
+        HeaderMut::at_raw(&mut truncated[..]).set_tc(true);
+
+        // Asked again over TCP, with the time that takes.
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(Some(Relayed::Retry(id, server, message.clone())),
+                   relay.response(&truncated, &upstream, later));
+        assert_eq!(Some(later + Duration::from_millis(RELAY_TIMEOUT_MS)), relay.deadline(id));
+        assert_eq!(None, relay.timeout(id, start + Duration::from_millis(RELAY_TIMEOUT_MS)));
+        // Once only.
+        assert_eq!(None, relay.response(&truncated, &upstream, later));
+
+        let address = record("www.example.com.", 300, RData::A("192.0.2.80".parse().unwrap()));
+        let whole = response(&message, RC_OK, &[address], &[]);
+        let (_, relayed) = answered(relay.response(&whole, &upstream, later));
+        assert_eq!(&whole[2..], &relayed[2..]);
+        answered(relay.query(&asked, (client(), 0), 512, later));
+
+        // Over a connection there is no asking again.
+        let mut relay = Relay::new(vec![https(443)]);
+        let (_, upstream, message) = forwarded(relay.query(&asked, (client(), 0), 512, start));
+        let mut truncated = response(&message, RC_OK, &[], &[]);
+        HeaderMut::at_raw(&mut truncated[..]).set_tc(true);
+        let (_, relayed) = answered(relay.response(&truncated, &upstream, later));
+        assert_eq!(Some(true), Header::at(&relayed).tc());
+    }
+
+    #[test]
+    fn client_sizes() {
+        let upstream = udp("192.0.2.1:53");
+        let mut relay = Relay::new(vec![upstream.clone()]);
+        let now = Instant::now();
+        let asked = query(7, "www.example.com.", TYPE_A);
+        let (_, _, message) = forwarded(relay.query(&asked, (client(), 0), 512, now));
+        let addresses: Vec<Record> = (0..40)
+                                         .map(|i| {
+                                             let address = format!("192.0.2.{}", i);
+                                             record("www.example.com.",
+                                                    300,
+                                                    RData::A(address.parse().unwrap()))
+                                         })
+                                         .collect();
+        let (mut answer, mut idx) = Request::parse(&message).unwrap().response(1232);
+        for address in &addresses {
+            address.write_at(&mut idx, &mut answer).unwrap();
+        }
+        HeaderMut::at_raw(&mut answer[..]).set_an(40);
+        answer.truncate(idx.tell());
+
+        // Too big for the client, which is told to ask again over TCP.
+        let (_, relayed) = answered(relay.response(&answer, &upstream, now));
+        let header = Header::at(&relayed);
+        assert!(relayed.len() <= 512);
+        assert_eq!((Some(true), Some(0)), (header.tc(), header.an()));
+
+        // The whole answer is cached all the same, for clients that take it,
+        // and those with EDNS get an OPT record with it.
+        let mut asked = query(8, "www.example.com.", TYPE_A);
+        append_opt(&mut asked, 4096);
+        let (_, cached) = answered(relay.query(&asked, (client(), 0), 4096, now));
+        let header = Header::at(&cached);
+        assert_eq!((Some(false), Some(40), Some(1)), (header.tc(), header.an(), header.ar()));
+        assert!(has_edns(&cached));
+    }
+
+    #[test]
+    fn timeouts() {
+        let mut relay = Relay::new(vec![udp("192.0.2.1:53")]);
+        let now = Instant::now();
+        let asked = query(7, "www.example.com.", TYPE_A);
+        let (id, _, _) = forwarded(relay.query(&asked, (client(), 2), 512, now));
+        assert_eq!(None, relay.timeout(id, now));
+        let deadline = relay.deadline(id).unwrap();
+        let (to, failure) = relay.timeout(id, deadline).unwrap();
+        assert_eq!((client(), 2), to);
+        let header = Header::at(&failure);
+        assert_eq!((Some(7), Some(RC_SERVER_ERROR), Some(1)),
+                   (header.id(), header.rc(), header.qd()));
+        assert_eq!(None, relay.deadline(id));
+        assert_eq!(None, relay.timeout(id, deadline));
+
+        // Nothing but standard queries, and nowhere to send them.
+        let mut update = asked.clone();
+        HeaderMut::at_raw(&mut update[..]).set_op(5);
+        assert_eq!(None, relay.query(&update, (client(), 0), 512, now));
+        assert_eq!(None, relay.query(&asked[..5], (client(), 0), 512, now));
+        let mut nowhere = Relay::new(Vec::new());
+        let (_, failure) = answered(nowhere.query(&asked, (client(), 0), 512, now));
+        assert_eq!(Some(RC_SERVER_ERROR), Header::at(&failure).rc());
+    }
+
+    // A DNSCrypt resolver giving its certificates, then answering one query
+    // with the address of www.example.com.
+    fn serve_dnscrypt(socket: UdpSocket, server: DnscryptServer) {
+        let mut buffer = [0u8; 4096];
+        for _ in 0..2 {
+            let (n, from) = socket.recv_from(&mut buffer).unwrap();
+            let packet = match server.received(&buffer[..n]) {
+                Some(DnscryptRequest::Certificates(response)) => response,
+                Some(DnscryptRequest::Query(query, session)) => {
+                    session.seal(&response(&query, RC_OK, &[www_address()], &[]))
+                }
+                None => panic!("dropped"),
+            };
+            socket.send_to(&packet, from).unwrap();
+        }
+    }
+
+    // An Oblivious DoH proxy and target in one, for one query, which it
+    // opens with `key` rather than pass on.
+    fn serve_odoh(listener: TcpListener, key: OdohKey) {
+        let mut config = server_config();
+        config.add_alpn(b"h2");
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut tls = TlsConnection::server(Arc::new(config));
+        let mut http = Http2Connection::server();
+        let mut requests = Vec::new();
+        let mut buffer = [0u8; 4096];
+        while requests.is_empty() {
+            let n = stream.read(&mut buffer).unwrap();
+            tls.received(&buffer[..n]).unwrap();
+            requests = http.received(&tls.read()).unwrap();
+            tls.write(http.pending());
+            let n = http.pending().len();
+            http.written(n);
+            stream.write_all(tls.pending()).unwrap();
+            let n = tls.pending().len();
+            tls.written(n);
+        }
+        let (handle, request) = requests.remove(0);
+        assert!(request.path.starts_with("/proxy?targethost=target.example&"));
+        let (query, session) = key.open_query(&request.body).unwrap();
+        let mut sealed = http::Response::new(200);
+        sealed.add_header("content-type", ODOH_MESSAGE);
+        sealed.body = session.seal(&response(&query, RC_OK, &[www_address()], &[]));
+        http.respond(handle, &sealed);
+        tls.write(http.pending());
+        stream.write_all(tls.pending()).unwrap();
+        let _ = stream.read(&mut buffer);
+    }
+
+    #[test]
+    fn over_encrypted_transports() {
+        assert!(udp("192.0.2.1:53").upstream().unwrap().is_none());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let forwarder = Forwarder::Tls(tls_server(port));
+        assert_eq!(format!("tls://127.0.0.1:{}#dns.example", port), forwarder.to_string());
+        relay_through(forwarder, thread::spawn(move || serve_dot(listener)));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert_eq!(format!("https://127.0.0.1:{}/dns-query#dns.example", port),
+                   https(port).to_string());
+        relay_through(https(port), thread::spawn(move || serve_doh(listener)));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let forwarder = Forwarder::Quic(tls_server(port));
+        assert_eq!(format!("quic://127.0.0.1:{}#dns.example", port), forwarder.to_string());
+        relay_through(forwarder, thread::spawn(move || serve_doq(socket)));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let provider = Name::parse("2.dnscrypt-cert.example.", None).unwrap();
+        let server = DnscryptServer::new(provider.clone(),
+                                         Ed25519PrivateKey::new(&[7; 32]).unwrap(),
+                                         now());
+        let forwarder = Forwarder::Dnscrypt(Stamp {
+            props: 0,
+            address: socket.local_addr().unwrap(),
+            provider_key: server.public_key(),
+            provider: provider,
+        });
+        assert!(forwarder.to_string().starts_with("sdns://"));
+        relay_through(forwarder, thread::spawn(move || serve_dnscrypt(socket, server)));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let key = OdohKey::generate();
+        let target = OdohTarget {
+            host: "target.example".to_string(),
+            path: "/dns-query".to_string(),
+            public_key: key.config().public_key.clone(),
+        };
+        let forwarder = Forwarder::Odoh(tls_server(port), "/proxy".to_string(), target);
+        assert_eq!(format!("https://127.0.0.1:{}/proxy#dns.example target \
+                            https://target.example/dns-query",
+                           port),
+                   forwarder.to_string());
+        relay_through(forwarder, thread::spawn(move || serve_odoh(listener, key)));
+    }
+}
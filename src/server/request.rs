@@ -0,0 +1,154 @@
+use std::cmp;
+use protocol::{Header, HeaderMut, MessageCursor, Name, Question, QuestionMut};
+
+const HEADER_SIZE: usize = 12;
+
+/// The question of a request, copied out of the message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Query {
+    pub name: Name,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+/// The parts of an incoming message a server needs in order to answer it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub id: u16,
+    pub opcode: u8,
+    pub rd: bool,
+    /// The question, if there was exactly one and it could be read.
+    pub query: Option<Query>,
+    /// Offset just past the question section, or past the header when
+    /// there is no usable question.
+    pub question_end: usize,
+}
+
+impl Request {
+    /// Reads the header and question. Returns None for messages that must
+    /// not be answered at all: responses, and anything too short to carry
+    /// an ID to answer to.
+    pub fn parse(message: &[u8]) -> Option<Request> {
+        if message.len() < HEADER_SIZE {
+            return None;
+        }
+        let header = Header::at(message);
+        if header.is_response() {
+            return None;
+        }
+        let mut request = Request {
+            id: header.id().unwrap(),
+            opcode: header.op().unwrap(),
+            rd: header.rd().unwrap(),
+            query: None,
+            question_end: HEADER_SIZE,
+        };
+        if header.qd() == Some(1) {
+            if let Some(question) = Question::from_message(message, HEADER_SIZE) {
+                let name = question.name().and_then(|n| Name::from_domain_name(message, n));
+                if let (Some(name), Some(qtype), Some(qclass)) = (name,
+                                                                  question.qtype(),
+                                                                  question.qclass()) {
+                    request.question_end = question.end_offset();
+                    request.query = Some(Query {
+                        name: name,
+                        qtype: qtype,
+                        qclass: qclass,
+                    });
+                }
+            }
+        }
+        Some(request)
+    }
+
+    /// Starts a response of at most `max_len` octets: the header with ID,
+    /// opcode and RD copied from the request, and the question echoed if
+    /// there was one. The cursor is left just past the question. There is
+    /// always room for the header.
+    pub fn response(&self, max_len: usize) -> (Vec<u8>, MessageCursor) {
+        let max_len = cmp::max(max_len, HEADER_SIZE);
+        let mut buffer = vec![0u8; max_len];
+        let mut idx = MessageCursor::new(max_len);
+        HeaderMut::at(&mut idx, &mut buffer[..])
+            .unwrap()
+            .set_id(self.id)
+            .set_qr(true)
+            .set_op(self.opcode)
+            .set_rd(self.rd);
+        if let Some(ref query) = self.query {
+            let start = idx.tell();
+            if QuestionMut::at(&mut idx,
+                               &mut buffer,
+                               &query.name.segments(),
+                               query.qtype,
+                               query.qclass)
+                   .is_some() {
+                HeaderMut::at_raw(&mut buffer[..]).set_qd(1);
+            } else {
+                idx.rewind(start);
+            }
+        }
+        (buffer, idx)
+    }
+
+    /// A complete response carrying only `rcode`.
+    pub fn error(&self, rcode: u8, max_len: usize) -> Vec<u8> {
+        let (mut buffer, idx) = self.response(max_len);
+        HeaderMut::at_raw(&mut buffer[..]).set_rc(rcode);
+        buffer.truncate(idx.tell());
+        buffer
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::{Header, Name, HeaderMut, RC_FORMAT_ERROR};
+    use test_util::query;
+
+    #[test]
+    fn parse_query() {
+        let message = query(7, "example.com.", 1);
+        let request = Request::parse(&message).unwrap();
+        assert_eq!(7, request.id);
+        assert!(request.rd);
+        assert_eq!(message.len(), request.question_end);
+        assert_eq!(Some(Query {
+                       name: Name::parse("example.com.", None).unwrap(),
+                       qtype: 1,
+                       qclass: 1,
+                   }),
+                   request.query);
+    }
+
+    #[test]
+    fn ignored_messages() {
+        assert_eq!(None, Request::parse(&[0u8; 11]));
+        let mut message = query(7, "example.com.", 1);
+        HeaderMut::at_raw(&mut message[..]).set_qr(true);
+        assert_eq!(None, Request::parse(&message));
+    }
+
+    #[test]
+    fn error_response() {
+        let mut message = query(9, "example.com.", 1);
+        // Truncated question.
+        message.truncate(20);
+        let request = Request::parse(&message).unwrap();
+        assert_eq!(None, request.query);
+        let response = request.error(RC_FORMAT_ERROR, 512);
+        assert_eq!(12, response.len());
+        let header = Header::at(&response[..]);
+        assert_eq!(Some(9), header.id());
+        assert!(header.is_response());
+        assert_eq!(Some(RC_FORMAT_ERROR), header.rc());
+        assert_eq!(Some(0), header.qd());
+
+        // However little room is left, the header goes out.
+        let request = Request::parse(&query(9, "example.com.", 1)).unwrap();
+        let response = request.error(RC_FORMAT_ERROR, 0);
+        assert_eq!(12, response.len());
+        assert_eq!(Some(0), Header::at(&response[..]).qd());
+    }
+}
@@ -0,0 +1,296 @@
+//! Keeping a secondary copy of a zone current by following the SOA timers
+//! (RFC 1034 Section 4.3.5) and NOTIFY (RFC 1996).
+
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use client::TransferResult;
+use protocol::{Name, Serial, Soa};
+use zone::{Zone, ZonemdError, verify_zonemd};
+
+/// How long to wait before trying again when there are no SOA timers yet,
+/// in seconds.
+pub const INITIAL_RETRY: u64 = 60;
+/// The shortest REFRESH and RETRY followed, in seconds, however small the
+/// SOA has them.
+pub const MIN_REFRESH: u32 = 300;
+pub const MIN_RETRY: u32 = 60;
+
+/// The refresh schedule of one secondary zone. It only decides; running
+/// the transfers and the timers is up to the caller.
+#[derive(Debug)]
+pub struct Secondary {
+    origin: Name,
+    primaries: Vec<SocketAddr>,
+    next_primary: usize,
+    // When the copy was last confirmed current with a primary.
+    confirmed: Option<Instant>,
+    // REFRESH, RETRY and EXPIRE of the copy held, in seconds.
+    timers: Option<(u32, u32, u32)>,
+    refreshing: bool,
+    // A NOTIFY came in while a refresh was already running.
+    again: bool,
+}
+
+impl Secondary {
+    pub fn new(origin: Name, primaries: Vec<SocketAddr>) -> Secondary {
+        Secondary {
+            origin: origin,
+            primaries: primaries,
+            next_primary: 0,
+            confirmed: None,
+            timers: None,
+            refreshing: false,
+            again: false,
+        }
+    }
+
+    pub fn origin(&self) -> &Name {
+        &self.origin
+    }
+
+    pub fn primaries(&self) -> &[SocketAddr] {
+        &self.primaries
+    }
+
+    /// Transfers from other primaries from now on.
+    pub fn set_primaries(&mut self, primaries: Vec<SocketAddr>) {
+        self.primaries = primaries;
+        self.next_primary = 0;
+    }
+
+    /// Starts a refresh and returns the primary to ask, or None if one is
+    /// already running, in which case another follows once it completes.
+    pub fn start_refresh(&mut self) -> Option<SocketAddr> {
+        if self.refreshing || self.primaries.is_empty() {
+            self.again = self.refreshing;
+            return None;
+        }
+        self.refreshing = true;
+        Some(self.primaries[self.next_primary % self.primaries.len()])
+    }
+
+    /// Records the outcome of a refresh, with the SOA now held, and returns
+    /// how long to wait before the next one: REFRESH after a success, RETRY
+    /// after a failure, in which case the next primary is tried. Neither is
+    /// shorter than `MIN_REFRESH` and `MIN_RETRY`.
+    pub fn refreshed(&mut self, now: Instant, success: bool, soa: Option<&Soa>) -> Duration {
+        self.refreshing = false;
+        if let Some(soa) = soa {
+            self.timers = Some((soa.refresh, soa.retry, soa.expire));
+        }
+        if success {
+            self.confirmed = Some(now);
+        } else {
+            self.next_primary += 1;
+        }
+        if self.again {
+            self.again = false;
+            return Duration::from_secs(0);
+        }
+        let delay = match self.timers {
+            Some((refresh, _, _)) if success => cmp::max(refresh, MIN_REFRESH),
+            Some((_, retry, _)) => cmp::max(retry, MIN_RETRY),
+            None => INITIAL_RETRY as u32,
+        };
+        Duration::from_secs(delay as u64)
+    }
+
+    /// Whether the copy has gone too long without being confirmed and must
+    /// no longer be served. A copy never loaded counts as expired.
+    pub fn is_expired(&self, now: Instant) -> bool {
+        match (self.confirmed, self.timers) {
+            (Some(confirmed), Some((_, _, expire))) => {
+                now.duration_since(confirmed) >= Duration::from_secs(expire as u64)
+            }
+            _ => true,
+        }
+    }
+
+    /// How long after a successful refresh the copy expires.
+    pub fn expire_after(&self) -> Option<Duration> {
+        self.timers.map(|(_, _, expire)| Duration::from_secs(expire as u64))
+    }
+}
+
+/// Whether a NOTIFY announcing `serial` calls for a refresh of a copy at
+/// `current`. Without a serial to compare, it always does.
+pub fn notify_is_news(serial: Option<u32>, current: Option<u32>) -> bool {
+    match (serial, current) {
+        (Some(serial), Some(current)) => Serial(serial) > Serial(current),
+        _ => true,
+    }
+}
+
+/// Why a transfer was not applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApplyError {
+    /// An incremental transfer did not fit the copy, and a full one is
+    /// needed instead.
+    Misfit,
+    NoSoa,
+    /// The zone as transferred does not match its ZONEMD digest.
+    Digest(ZonemdError),
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ApplyError::Digest(ref e) => write!(fmt, "{}", e),
+            _ => write!(fmt, "{}", self.description()),
+        }
+    }
+}
+
+impl Error for ApplyError {
+    fn description(&self) -> &str {
+        match *self {
+            ApplyError::Misfit => "incremental transfer does not fit the zone held",
+            ApplyError::NoSoa => "transferred zone has no SOA",
+            ApplyError::Digest(ref e) => e.description(),
+        }
+    }
+}
+
+/// Applies a completed transfer to the copy. Returns whether the zone
+/// changed. A zone whose ZONEMD digest does not match is refused and the
+/// copy is left as it was.
+pub fn apply_transfer(zone: &mut Zone, result: TransferResult) -> Result<bool, ApplyError> {
+    match result {
+        TransferResult::UpToDate => Ok(false),
+        TransferResult::Full(records) => {
+            let newer = Zone::from_records(zone.origin().clone(), records);
+            let (old, new) = (zone.serial(), newer.serial());
+            match (old, new) {
+                (_, None) => Err(ApplyError::NoSoa),
+                (Some(old), Some(new)) if !(Serial(new) > Serial(old)) => Ok(false),
+                _ => {
+                    try!(verify_zonemd(&newer).map_err(ApplyError::Digest));
+                    zone.replace(newer);
+                    Ok(true)
+                }
+            }
+        }
+        TransferResult::Incremental(diffs) => {
+            let mut next = zone.clone();
+            for diff in diffs {
+                if !next.apply(diff) {
+                    return Err(ApplyError::Misfit);
+                }
+            }
+            try!(verify_zonemd(&next).map_err(ApplyError::Digest));
+            *zone = next;
+            Ok(true)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use std::time::{Duration, Instant};
+    use client::TransferResult;
+    use protocol::{Name, RData, Record};
+    use protocol::rdata::CLASS_IN;
+    use zone::{HASH_SHA384, Zone, ZoneParser, ZonemdError, set_zonemd};
+
+    fn zone(serial: u32) -> Zone {
+        let text = format!("@ 300 SOA ns hostmaster {} 3600 600 86400 60\n@ 300 NS ns\n",
+                           serial);
+        let origin = Name::parse("example.com.", None).unwrap();
+        let records = ZoneParser::new(Cursor::new(text.into_bytes()),
+                                      "test.zone",
+                                      Some(origin.clone()))
+                          .map(|r| r.unwrap());
+        Zone::from_records(origin, records)
+    }
+
+    #[test]
+    fn timers() {
+        let primaries = vec!["192.0.2.1:53".parse().unwrap(),
+                             "192.0.2.2:53".parse().unwrap()];
+        let mut secondary = Secondary::new(Name::parse("example.com.", None).unwrap(), primaries);
+        let start = Instant::now();
+        assert!(secondary.is_expired(start));
+
+        // A failure without a copy waits the initial retry, then tries the
+        // other primary.
+        assert_eq!(Some("192.0.2.1:53".parse().unwrap()), secondary.start_refresh());
+        assert_eq!(Duration::from_secs(INITIAL_RETRY), secondary.refreshed(start, false, None));
+        assert_eq!(Some("192.0.2.2:53".parse().unwrap()), secondary.start_refresh());
+        // A NOTIFY arriving meanwhile asks for another go right away.
+        assert_eq!(None, secondary.start_refresh());
+        assert_eq!(Duration::from_secs(0), secondary.refreshed(start, false, None));
+
+        let soa = zone(1).soa().unwrap();
+        secondary.start_refresh();
+        assert_eq!(Duration::from_secs(3600), secondary.refreshed(start, true, Some(&soa)));
+        assert!(!secondary.is_expired(start + Duration::from_secs(86399)));
+        assert!(secondary.is_expired(start + Duration::from_secs(86400)));
+
+        secondary.start_refresh();
+        assert_eq!(Duration::from_secs(600), secondary.refreshed(start, false, Some(&soa)));
+        assert_eq!(Some(Duration::from_secs(86400)), secondary.expire_after());
+
+        // Timers of zero are no reason to ask again and again.
+        let mut soa = soa.clone();
+        soa.refresh = 0;
+        soa.retry = 0;
+        secondary.start_refresh();
+        assert_eq!(Duration::from_secs(MIN_REFRESH as u64),
+                   secondary.refreshed(start, true, Some(&soa)));
+        secondary.start_refresh();
+        assert_eq!(Duration::from_secs(MIN_RETRY as u64),
+                   secondary.refreshed(start, false, Some(&soa)));
+    }
+
+    #[test]
+    fn notify_serials() {
+        assert!(notify_is_news(Some(2), Some(1)));
+        assert!(!notify_is_news(Some(1), Some(1)));
+        assert!(notify_is_news(Some(0), Some(0xffff_fff0)));
+        assert!(notify_is_news(None, Some(1)));
+    }
+
+    #[test]
+    fn applying_transfers() {
+        let mut copy = zone(5);
+        assert_eq!(Ok(false), apply_transfer(&mut copy, TransferResult::UpToDate));
+        // An older full copy is ignored.
+        assert_eq!(Ok(false),
+                   apply_transfer(&mut copy, TransferResult::Full(zone(4).records())));
+        assert_eq!(Ok(true),
+                   apply_transfer(&mut copy, TransferResult::Full(zone(6).records())));
+        assert_eq!(Some(6), copy.serial());
+        assert_eq!(1, copy.journal().len());
+
+        let mut diff = copy.journal().since(5).unwrap()[0].clone();
+        assert!(apply_transfer(&mut copy, TransferResult::Incremental(vec![diff.clone()]))
+                    .is_err());
+        if let RData::Soa(ref mut soa) = diff.to.rdata {
+            soa.serial = 7;
+        }
+        diff.from = copy.soa_record().unwrap();
+        assert_eq!(Ok(true),
+                   apply_transfer(&mut copy, TransferResult::Incremental(vec![diff])));
+        assert_eq!(Some(7), copy.serial());
+
+        // A zone that does not match its digest is refused.
+        let mut signed = zone(8);
+        set_zonemd(&mut signed, &[HASH_SHA384]);
+        let mut records = signed.records();
+        assert_eq!(Ok(true),
+                   apply_transfer(&mut copy.clone(), TransferResult::Full(records.clone())));
+        records.push(Record::new(Name::parse("www.example.com.", None).unwrap(),
+                                 CLASS_IN,
+                                 300,
+                                 RData::A("192.0.2.1".parse().unwrap())));
+        assert_eq!(Err(ApplyError::Digest(ZonemdError::Mismatch)),
+                   apply_transfer(&mut copy, TransferResult::Full(records)));
+        assert_eq!(Some(7), copy.serial());
+    }
+}
@@ -0,0 +1,256 @@
+//! The server side of a DNS connection over TCP (RFC 7766): any number of
+//! queries may arrive back to back, and each is answered as soon as it can
+//! be, in whatever order that turns out to be. The same framing runs inside
+//! TLS for DNS over TLS (RFC 7858).
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use protocol::{FrameReader, FrameWriter, tcp_frame};
+use tls::{ServerConfig, TlsConnection, TlsError};
+use super::transfer::Responses;
+
+/// Milliseconds a connection may go without reading or writing anything
+/// before the server closes it (RFC 7766 Section 6.2.3).
+pub const IDLE_TIMEOUT_MS: u64 = 10_000;
+/// Octets waiting to be written past which a connection stops taking in
+/// queries and packing further responses.
+pub const HIGH_WATER_MARK: usize = 1 << 16;
+
+/// The framing and idle timer of one client connection, and its TLS if it
+/// has any. Reading and writing the socket is up to the caller.
+#[derive(Debug)]
+pub struct StreamConnection {
+    input: FrameReader,
+    output: FrameWriter,
+    tls: Option<TlsConnection>,
+    // Responses to pack into `output` once there is room.
+    queued: VecDeque<Responses>,
+    idle_timeout: Duration,
+    last_active: Instant,
+}
+
+impl StreamConnection {
+    pub fn new(now: Instant) -> StreamConnection {
+        StreamConnection {
+            input: FrameReader::new(),
+            output: FrameWriter::new(),
+            tls: None,
+            queued: VecDeque::new(),
+            idle_timeout: Duration::from_millis(IDLE_TIMEOUT_MS),
+            last_active: now,
+        }
+    }
+
+    /// A connection that starts with a TLS handshake.
+    pub fn with_tls(config: Arc<ServerConfig>, now: Instant) -> StreamConnection {
+        let mut connection = StreamConnection::new(now);
+        connection.tls = Some(TlsConnection::server(config));
+        connection
+    }
+
+    pub fn set_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Takes octets read from the client and returns the queries they
+    /// complete. A TLS error ends the connection, once the alert left in
+    /// `pending` has been written.
+    pub fn received(&mut self, data: &[u8], now: Instant) -> Result<Vec<Vec<u8>>, TlsError> {
+        self.last_active = now;
+        match self.tls {
+            Some(ref mut tls) => {
+                try!(tls.received(data));
+                self.input.push(&tls.read());
+            }
+            None => self.input.push(data),
+        }
+        let mut queries = Vec::new();
+        while let Some(query) = self.input.next_frame() {
+            queries.push(query);
+        }
+        Ok(queries)
+    }
+
+    /// Queues a response. Returns false if it is too long to send.
+    pub fn respond(&mut self, response: &[u8]) -> bool {
+        match self.tls {
+            Some(ref mut tls) => {
+                match tcp_frame(response) {
+                    Some(frame) => {
+                        tls.write(&frame);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            None => self.output.push(response),
+        }
+    }
+
+    /// Queues the responses to a query, which are packed into the output
+    /// only while it is under `HIGH_WATER_MARK`, and more as it drains.
+    pub fn respond_all(&mut self, responses: Responses) {
+        self.queued.push_back(responses);
+        self.fill();
+    }
+
+    fn fill(&mut self) {
+        while self.pending().len() < HIGH_WATER_MARK {
+            let response = match self.queued.front_mut() {
+                Some(responses) => responses.next(),
+                None => return,
+            };
+            match response {
+                Some(response) => {
+                    self.respond(&response);
+                }
+                None => {
+                    self.queued.pop_front();
+                }
+            }
+        }
+    }
+
+    /// The octets waiting to be written to the client.
+    pub fn pending(&self) -> &[u8] {
+        match self.tls {
+            Some(ref tls) => tls.pending(),
+            None => self.output.pending(),
+        }
+    }
+
+    pub fn written(&mut self, n: usize, now: Instant) {
+        if n > 0 {
+            self.last_active = now;
+        }
+        match self.tls {
+            Some(ref mut tls) => tls.written(n),
+            None => self.output.written(n),
+        }
+        self.fill();
+    }
+
+    /// The TLS the connection runs over, if any.
+    pub fn tls(&self) -> Option<&TlsConnection> {
+        self.tls.as_ref()
+    }
+
+    /// When the connection becomes idle if nothing happens before then.
+    pub fn deadline(&self) -> Instant {
+        self.last_active + self.idle_timeout
+    }
+
+    /// Whether the connection has gone the timeout without anything read
+    /// or written. Neither a query left half sent nor output the client
+    /// does not read keeps it open.
+    pub fn is_idle(&self, now: Instant) -> bool {
+        now >= self.deadline()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use protocol::tcp_frame;
+    use server::transfer::Responses;
+    use tls::{ClientConfig, ServerConfig, TlsConnection};
+
+    #[test]
+    fn pipelining() {
+        let start = Instant::now();
+        let mut connection = StreamConnection::new(start);
+        let mut stream = tcp_frame(b"query one").unwrap();
+        stream.extend(tcp_frame(b"query two").unwrap());
+        stream.extend(tcp_frame(b"query three").unwrap());
+        assert_eq!(vec![b"query one".to_vec()], connection.received(&stream[..13], start).unwrap());
+        assert_eq!(vec![b"query two".to_vec(), b"query three".to_vec()],
+                   connection.received(&stream[13..], start).unwrap());
+
+        // Answered in another order than asked.
+        assert!(connection.respond(b"answer three"));
+        assert!(connection.respond(b"answer one"));
+        let mut expected = tcp_frame(b"answer three").unwrap();
+        expected.extend(tcp_frame(b"answer one").unwrap());
+        assert_eq!(&expected[..], connection.pending());
+        connection.written(5, start);
+        assert_eq!(&expected[5..], connection.pending());
+    }
+
+    #[test]
+    fn high_water_mark() {
+        let start = Instant::now();
+        let mut connection = StreamConnection::new(start);
+        for _ in 0..3 {
+            connection.respond_all(Responses::single(vec![0; 40_000]));
+        }
+        // The third waits until the output drains.
+        assert_eq!(2 * 40_002, connection.pending().len());
+        connection.written(40_002, start);
+        assert_eq!(2 * 40_002, connection.pending().len());
+        connection.written(2 * 40_002, start);
+        assert!(connection.pending().is_empty());
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let start = Instant::now();
+        let mut connection = StreamConnection::new(start);
+        connection.set_idle_timeout(Duration::from_secs(5));
+        assert_eq!(start + Duration::from_secs(5), connection.deadline());
+        assert!(!connection.is_idle(start + Duration::from_secs(4)));
+        assert!(connection.is_idle(start + Duration::from_secs(5)));
+
+        // Traffic puts it off, but output the client does not read does not.
+        let later = start + Duration::from_secs(3);
+        connection.received(&tcp_frame(b"query").unwrap(), later).unwrap();
+        connection.respond(b"answer");
+        assert!(!connection.is_idle(later + Duration::from_secs(4)));
+        connection.written(0, later + Duration::from_secs(4));
+        assert!(connection.is_idle(later + Duration::from_secs(5)));
+        let n = connection.pending().len();
+        connection.written(n, later + Duration::from_secs(4));
+        assert!(!connection.is_idle(later + Duration::from_secs(8)));
+        assert!(connection.is_idle(later + Duration::from_secs(9)));
+    }
+
+    #[test]
+    fn over_tls() {
+        let start = Instant::now();
+        let config = ServerConfig::load(Path::new("testdata/tls-server.pem"),
+                                        Path::new("testdata/tls-server.key"))
+                         .unwrap();
+        let mut connection = StreamConnection::with_tls(Arc::new(config), start);
+        let mut client_config = ClientConfig::new();
+        client_config.load_roots(Path::new("testdata/tls-ca.pem")).unwrap();
+        let mut client = TlsConnection::client(Arc::new(client_config), "dns.example").unwrap();
+        client.write(&tcp_frame(b"query").unwrap());
+
+        let mut queries = Vec::new();
+        while !client.pending().is_empty() {
+            let to_server = client.pending().to_vec();
+            client.written(to_server.len());
+            queries.extend(connection.received(&to_server, start).unwrap());
+            for query in &queries {
+                assert!(connection.respond(&[b"answer to ", &query[..]].concat()));
+            }
+            let to_client = connection.pending().to_vec();
+            connection.written(to_client.len(), start);
+            client.received(&to_client).unwrap();
+        }
+        assert_eq!(vec![b"query".to_vec()], queries);
+        assert_eq!(tcp_frame(b"answer to query").unwrap(), client.read());
+        assert!(!connection.tls().unwrap().is_handshaking());
+
+        // Garbage gets an alert back, and nothing more.
+        assert!(connection.pending().is_empty());
+        assert!(connection.received(&[23, 3, 3, 0, 1, 0], start).is_err());
+        assert!(!connection.pending().is_empty());
+        assert!(connection.received(&[], start).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! The serving side of zone transfers: AXFR (RFC 5936) and IXFR (RFC 1995).
+
+use protocol::{Header, HeaderMut, RData, Record, Serial, RC_FORMAT_ERROR, RC_SERVER_ERROR};
+use protocol::rdata::{TYPE_IXFR, TYPE_SOA};
+use tsig::{Signer, now};
+use zone::Zone;
+use super::request::Request;
+
+/// The messages answering one request over a stream transport. A zone
+/// transfer is packed a message at a time as they are taken, rather than
+/// all at once; those answering a signed request are signed in turn.
+#[derive(Debug)]
+pub struct Responses {
+    // A message made already, for anything but a transfer.
+    single: Option<Vec<u8>>,
+    // The transfer request and its records, from `next` on still to pack.
+    request: Option<Request>,
+    records: Vec<Record>,
+    next: usize,
+    max_len: usize,
+    signer: Option<Signer>,
+}
+
+impl Responses {
+    pub fn single(message: Vec<u8>) -> Responses {
+        Responses {
+            single: Some(message),
+            request: None,
+            records: Vec::new(),
+            next: 0,
+            max_len: 0,
+            signer: None,
+        }
+    }
+
+    fn records(request: &Request, records: Vec<Record>, max_len: usize) -> Responses {
+        Responses {
+            single: None,
+            request: Some(request.clone()),
+            records: records,
+            next: 0,
+            max_len: max_len,
+            signer: None,
+        }
+    }
+
+    /// Signs each message with `signer` as it is taken.
+    pub fn sign_with(&mut self, signer: Signer) -> &mut Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Packs as many of the records left as fit in a message. A record too
+    /// big for a message of its own ends the transfer with an error.
+    fn pack(&mut self) -> Option<Vec<u8>> {
+        let request = match self.request {
+            Some(ref request) if self.next < self.records.len() => request,
+            _ => return None,
+        };
+        let (mut buffer, mut idx) = request.response(self.max_len);
+        let mut count = 0;
+        while self.next < self.records.len() {
+            let start = idx.tell();
+            if self.records[self.next].write_at(&mut idx, &mut buffer).is_none() {
+                idx.rewind(start);
+                break;
+            }
+            self.next += 1;
+            count += 1;
+        }
+        if count == 0 {
+            self.next = self.records.len();
+            return Some(request.error(RC_SERVER_ERROR, self.max_len));
+        }
+        HeaderMut::at_raw(&mut buffer[..]).set_aa(true).set_an(count);
+        buffer.truncate(idx.tell());
+        Some(buffer)
+    }
+}
+
+impl Iterator for Responses {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let mut message = match self.single.take().or_else(|| self.pack()) {
+            Some(message) => message,
+            None => return None,
+        };
+        if let Some(ref mut signer) = self.signer {
+            signer.sign(&mut message, now());
+        }
+        Some(message)
+    }
+}
+
+/// The serial of the SOA an IXFR client put in the authority section.
+pub fn client_serial(message: &[u8], request: &Request) -> Option<u32> {
+    let header = Header::at(message);
+    if header.an() != Some(0) || header.ns().map_or(true, |ns| ns < 1) {
+        return None;
+    }
+    match Record::read_section(message, request.question_end, 1) {
+        Some((ref records, _)) if records[0].rtype() == TYPE_SOA => {
+            match records[0].rdata {
+                RData::Soa(ref soa) => Some(soa.serial),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The records of a whole zone bracketed by its SOA.
+fn axfr_records(zone: &Zone, soa: &Record) -> Vec<Record> {
+    let mut records = vec![soa.clone()];
+    records.extend(zone.records().into_iter().filter(|r| r.rtype() != TYPE_SOA));
+    records.push(soa.clone());
+    records
+}
+
+/// The records answering an IXFR from `serial`: the difference sequences
+/// when the journal reaches back that far, a lone SOA when the client is up
+/// to date (or ahead), and the whole zone otherwise.
+fn ixfr_records(zone: &Zone, soa: &Record, serial: u32) -> Vec<Record> {
+    if zone.serial().map_or(false, |ours| !(Serial(ours) > Serial(serial))) {
+        return vec![soa.clone()];
+    }
+    match zone.journal().since(serial) {
+        Some(ref diffs) if !diffs.is_empty() => {
+            let mut records = vec![soa.clone()];
+            for diff in diffs {
+                records.push(diff.from.clone());
+                records.extend(diff.removed.iter().cloned());
+                records.push(diff.to.clone());
+                records.extend(diff.added.iter().cloned());
+            }
+            records.push(soa.clone());
+            records
+        }
+        _ => axfr_records(zone, soa),
+    }
+}
+
+/// The messages answering a transfer request for `zone`, whose apex the
+/// request has already been checked to name.
+pub fn transfer(message: &[u8], request: &Request, zone: &Zone, max_len: usize) -> Responses {
+    let soa = match zone.soa_record() {
+        Some(soa) => soa,
+        None => return Responses::single(request.error(RC_SERVER_ERROR, max_len)),
+    };
+    let ixfr = request.query.as_ref().map_or(false, |q| q.qtype == TYPE_IXFR);
+    let records = if ixfr {
+        match client_serial(message, request) {
+            Some(serial) => ixfr_records(zone, &soa, serial),
+            None => return Responses::single(request.error(RC_FORMAT_ERROR, max_len)),
+        }
+    } else {
+        axfr_records(zone, &soa)
+    };
+    Responses::records(request, records, max_len)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use protocol::{Header, HeaderMut, Name, Question, Record};
+    use protocol::rdata::*;
+    use server::Request;
+    use test_util::query;
+    use zone::{Diff, Zone, ZoneParser};
+
+    fn zone(serial: u32, hosts: usize) -> Zone {
+        let mut text = format!("$TTL 300\n@ SOA ns hostmaster {} 1 1 1 1\n@ NS ns\n", serial);
+        for i in 0..hosts {
+            text.push_str(&format!("host{} A 192.0.2.{}\n", i, i % 250));
+        }
+        let origin = Name::parse("example.com.", None).unwrap();
+        let records = ZoneParser::new(Cursor::new(text.into_bytes()),
+                                      "test.zone",
+                                      Some(origin.clone()))
+                          .map(|r| r.unwrap());
+        Zone::from_records(origin, records)
+    }
+
+    fn answers<I: IntoIterator<Item = Vec<u8>>>(messages: I) -> Vec<Record> {
+        let mut records = Vec::new();
+        for message in messages {
+            let header = Header::at(&message[..]);
+            let question = Question::from_message(&message[..], 12).unwrap();
+            let (section, _) = Record::read_section(&message,
+                                                     question.end_offset(),
+                                                     header.an().unwrap())
+                                    .unwrap();
+            records.extend(section);
+        }
+        records
+    }
+
+    fn ixfr_query(serial: u32) -> Vec<u8> {
+        let mut message = query(1, "example.com.", TYPE_IXFR);
+        message.extend(zone(serial, 0).soa_record().unwrap().to_wire());
+        HeaderMut::at_raw(&mut message[..]).set_ns(1);
+        message
+    }
+
+    #[test]
+    fn axfr_spans_messages() {
+        let zone = zone(5, 100);
+        let message = query(1, "example.com.", TYPE_AXFR);
+        let request = Request::parse(&message).unwrap();
+        let mut responses = transfer(&message, &request, &zone, 512);
+        // Packed only as they are taken.
+        let first = responses.next().unwrap();
+        assert!(responses.next < responses.records.len());
+        let mut messages = vec![first];
+        messages.extend(responses);
+        assert!(messages.len() > 1);
+        for message in &messages {
+            assert!(message.len() <= 512);
+            assert_eq!(Some(true), Header::at(&message[..]).aa());
+        }
+        let records = answers(messages);
+        assert_eq!(103, records.len());
+        assert_eq!(TYPE_SOA, records[0].rtype());
+        assert_eq!(records[0], records[102]);
+    }
+
+    #[test]
+    fn ixfr() {
+        let old = zone(1, 2);
+        let mut current = zone(1, 2);
+        current.replace(zone(2, 3));
+        current.replace(zone(3, 1));
+
+        // Incremental from the start of the journal.
+        let message = ixfr_query(1);
+        let request = Request::parse(&message).unwrap();
+        assert_eq!(Some(1), client_serial(&message, &request));
+        let records = answers(transfer(&message, &request, &current, 65535));
+        let serials: Vec<u32> = records.iter()
+                                       .filter_map(|r| {
+                                           match r.rdata {
+                                               RData::Soa(ref soa) => Some(soa.serial),
+                                               _ => None,
+                                           }
+                                       })
+                                       .collect();
+        assert_eq!(vec![3, 1, 2, 2, 3, 3], serials);
+        let diff = Diff::between(&old, &zone(2, 3)).unwrap();
+        assert_eq!(diff.added, records[3..4].to_vec());
+
+        // Up to date.
+        let message = ixfr_query(3);
+        let request = Request::parse(&message).unwrap();
+        assert_eq!(1, answers(transfer(&message, &request, &current, 65535)).len());
+
+        // Unknown history falls back to the whole zone.
+        let message = ixfr_query(0);
+        let request = Request::parse(&message).unwrap();
+        assert_eq!(4, answers(transfer(&message, &request, &current, 65535)).len());
+
+        // IXFR needs the client's SOA.
+        let message = query(1, "example.com.", TYPE_IXFR);
+        let request = Request::parse(&message).unwrap();
+        let response: Vec<_> = transfer(&message, &request, &current, 65535).collect();
+        assert_eq!(Some(RC_FORMAT_ERROR), Header::at(&response[0][..]).rc());
+    }
+}
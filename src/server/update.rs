@@ -0,0 +1,143 @@
+//! Reading dynamic UPDATE requests (RFC 2136 Section 2).
+
+use protocol::{Header, HeaderMut, MessageCursor, Name, QuestionMut, RData, Record, Resource};
+use protocol::{OP_UPDATE, RC_FORMAT_ERROR};
+use protocol::rdata::TYPE_SOA;
+use super::authority::MAX_TCP_RESPONSE;
+use super::request::Request;
+
+/// The sections of an UPDATE message. They reuse the four header counts:
+/// the zone section in place of the question, then the prerequisites, the
+/// updates and the additional records.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Update {
+    pub zone: Name,
+    pub class: u16,
+    pub prerequisites: Vec<Record>,
+    pub updates: Vec<Record>,
+}
+
+// Like `Record::read_section`, but records without rdata, which UPDATE uses
+// to name whole RRsets, are kept as empty unknown rdata.
+fn read_records(message: &[u8], at: usize, count: u16) -> Option<(Vec<Record>, usize)> {
+    let mut records = Vec::with_capacity(count as usize);
+    let mut next = at;
+    for _ in 0..count {
+        let resource = match Resource::from_message(message, next) {
+            Some(resource) => resource,
+            None => return None,
+        };
+        let record = if resource.data_length() == Some(0) {
+            let name = resource.name().and_then(|n| Name::from_domain_name(message, n));
+            match (name, resource.rtype(), resource.rclass(), resource.ttl()) {
+                (Some(name), Some(rtype), Some(class), Some(ttl)) => {
+                    Record::new(name,
+                                class,
+                                ttl,
+                                RData::Unknown {
+                                    rtype: rtype,
+                                    data: Vec::new(),
+                                })
+                }
+                _ => return None,
+            }
+        } else {
+            match Record::from_resource(message, &resource) {
+                Some(record) => record,
+                None => return None,
+            }
+        };
+        records.push(record);
+        next = resource.end_offset();
+    }
+    Some((records, next))
+}
+
+impl Update {
+    /// Reads an UPDATE request. Returns the RCODE to answer with if it is
+    /// malformed.
+    pub fn parse(message: &[u8], request: &Request) -> Result<Update, u8> {
+        let zone = match request.query {
+            Some(ref query) if query.qtype == TYPE_SOA => query,
+            _ => return Err(RC_FORMAT_ERROR),
+        };
+        let header = Header::at(message);
+        let (prerequisites, next) =
+            match read_records(message, request.question_end, header.an().unwrap_or(0)) {
+                Some(section) => section,
+                None => return Err(RC_FORMAT_ERROR),
+            };
+        let updates = match read_records(message, next, header.ns().unwrap_or(0)) {
+            Some((updates, _)) => updates,
+            None => return Err(RC_FORMAT_ERROR),
+        };
+        Ok(Update {
+            zone: zone.name.clone(),
+            class: zone.qclass,
+            prerequisites: prerequisites,
+            updates: updates,
+        })
+    }
+
+    /// Composes the UPDATE request, as a client would send it.
+    pub fn to_message(&self, id: u16) -> Option<Vec<u8>> {
+        let mut buffer = vec![0u8; MAX_TCP_RESPONSE];
+        let mut idx = MessageCursor::new(buffer.len());
+        HeaderMut::at(&mut idx, &mut buffer[..])
+            .unwrap()
+            .make_query(id)
+            .set_op(OP_UPDATE)
+            .set_rd(false)
+            .set_qd(1)
+            .set_an(self.prerequisites.len() as u16)
+            .set_ns(self.updates.len() as u16);
+        if QuestionMut::at(&mut idx, &mut buffer, &self.zone.segments(), TYPE_SOA, self.class)
+               .is_none() {
+            return None;
+        }
+        for record in self.prerequisites.iter().chain(self.updates.iter()) {
+            if record.write_at(&mut idx, &mut buffer).is_none() {
+                return None;
+            }
+        }
+        buffer.truncate(idx.tell());
+        Some(buffer)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::{Name, RData, Record};
+    use protocol::rdata::{CLASS_ANY, CLASS_IN, TYPE_A};
+    use server::Request;
+
+    #[test]
+    fn round_trip() {
+        let update = Update {
+            zone: Name::parse("example.com.", None).unwrap(),
+            class: CLASS_IN,
+            prerequisites: vec![Record::new(Name::parse("www.example.com.", None).unwrap(),
+                                            CLASS_ANY,
+                                            0,
+                                            RData::Unknown {
+                                                rtype: TYPE_A,
+                                                data: Vec::new(),
+                                            })],
+            updates: vec![Record::new(Name::parse("new.example.com.", None).unwrap(),
+                                      CLASS_IN,
+                                      300,
+                                      RData::A("192.0.2.1".parse().unwrap()))],
+        };
+        let message = update.to_message(9).unwrap();
+        let request = Request::parse(&message).unwrap();
+        assert_eq!(Ok(update), Update::parse(&message, &request));
+
+        // The zone section must name an SOA.
+        let mut message = message;
+        message[request.question_end - 3] = TYPE_A as u8;
+        let request = Request::parse(&message).unwrap();
+        assert_eq!(Err(RC_FORMAT_ERROR), Update::parse(&message, &request));
+    }
+}
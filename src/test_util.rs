@@ -0,0 +1,24 @@
+//! What the tests of several modules share.
+
+use protocol::{HeaderMut, MessageCursor, Name, QuestionMut};
+use protocol::rdata::CLASS_IN;
+
+/// The time the tests run at, 2026-10-18, in seconds since the epoch.
+pub const NOW: u64 = 1792281600;
+
+/// A query asking for recursion, with one question for `name` and `qtype`
+/// in class IN.
+pub fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    class_query(id, name, qtype, CLASS_IN)
+}
+
+/// A query with one question in class `qclass`.
+pub fn class_query(id: u16, name: &str, qtype: u16, qclass: u16) -> Vec<u8> {
+    let mut message = vec![0u8; 512];
+    let mut idx = MessageCursor::new(message.len());
+    HeaderMut::at(&mut idx, &mut message[..]).unwrap().make_query(id).set_qd(1);
+    let name = Name::parse(name, None).unwrap();
+    QuestionMut::at(&mut idx, &mut message, &name.segments(), qtype, qclass).unwrap();
+    message.truncate(idx.tell());
+    message
+}
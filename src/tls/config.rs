@@ -0,0 +1,249 @@
+//! What each end of a connection needs to know ahead of it: the client
+//! what to trust, the server what to present.
+
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::Arc;
+use rustls::{self, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use webpki::EndEntityCert;
+use webpki_roots::TLS_SERVER_ROOTS;
+use crypto::{Digest, Sha256};
+use protocol::base64_encode;
+use super::TlsError;
+use super::pem::{load_certificates, load_private_key};
+
+/// The SHA-256 digest of a certificate's SubjectPublicKeyInfo in base64,
+/// the form of SPKI pins (RFC 7469 Section 2.4).
+pub fn spki_pin(certificate: &CertificateDer) -> Option<String> {
+    EndEntityCert::try_from(certificate)
+        .ok()
+        .map(|certificate| base64_encode(&Sha256::digest(&certificate.subject_public_key_info())))
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+// Checks a server's chain against the roots and pins a client was given.
+#[derive(Debug)]
+struct Verifier {
+    roots: Option<Arc<WebPkiServerVerifier>>,
+    pins: Vec<String>,
+    opportunistic: bool,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(&self,
+                          end_entity: &CertificateDer,
+                          intermediates: &[CertificateDer],
+                          name: &ServerName,
+                          ocsp: &[u8],
+                          now: UnixTime)
+        -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(ref roots) = self.roots {
+            try!(roots.verify_server_cert(end_entity, intermediates, name, ocsp, now));
+        }
+        if !self.pins.is_empty() &&
+           !Some(end_entity).into_iter().chain(intermediates).any(|certificate| {
+            spki_pin(certificate).map_or(false, |pin| self.pins.contains(&pin))
+        }) {
+            return Err(rustls::Error::General("no certificate matches a pin".to_string()));
+        }
+        if self.roots.is_none() && self.pins.is_empty() && !self.opportunistic {
+            return Err(rustls::Error::General("nothing to authenticate the server with"
+                                                  .to_string()));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self,
+                              message: &[u8],
+                              certificate: &CertificateDer,
+                              signature: &DigitallySignedStruct)
+        -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message,
+                                               certificate,
+                                               signature,
+                                               &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self,
+                              message: &[u8],
+                              certificate: &CertificateDer,
+                              signature: &DigitallySignedStruct)
+        -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message,
+                                               certificate,
+                                               signature,
+                                               &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// How a client authenticates servers, and the sessions it can resume.
+/// Shared by every connection made with it.
+#[derive(Debug)]
+pub struct ClientConfig {
+    roots: RootCertStore,
+    pins: Vec<String>,
+    opportunistic: bool,
+    config: Arc<rustls::ClientConfig>,
+}
+
+impl ClientConfig {
+    /// A configuration that trusts nothing yet: give it roots, pins, or
+    /// allow opportunistic connections.
+    pub fn new() -> ClientConfig {
+        let verifier = Verifier {
+            roots: None,
+            pins: Vec::new(),
+            opportunistic: false,
+            provider: provider(),
+        };
+        let mut config = rustls::ClientConfig::builder_with_provider(provider())
+                             .with_protocol_versions(&[&rustls::version::TLS13])
+                             .unwrap()
+                             .dangerous()
+                             .with_custom_certificate_verifier(Arc::new(verifier))
+                             .with_no_client_auth();
+        // Sent only under QUIC, where the application says what is safe.
+        config.enable_early_data = true;
+        ClientConfig {
+            roots: RootCertStore::empty(),
+            pins: Vec::new(),
+            opportunistic: false,
+            config: Arc::new(config),
+        }
+    }
+
+    /// Trusts every certificate in a PEM file as a root.
+    pub fn load_roots(&mut self, path: &Path) -> Result<&mut Self, TlsError> {
+        for root in try!(load_certificates(path)) {
+            try!(self.roots
+                     .add(root)
+                     .map_err(|e| TlsError::File(format!("{}: {}", path.display(), e))));
+        }
+        self.set_verifier();
+        Ok(self)
+    }
+
+    /// Trusts the roots of the web PKI, as Mozilla's root program has them.
+    pub fn add_web_roots(&mut self) -> &mut Self {
+        self.roots.extend(TLS_SERVER_ROOTS.iter().cloned());
+        self.set_verifier();
+        self
+    }
+
+    /// Requires one of the server's certificates to have this SPKI pin,
+    /// the base64 SHA-256 digest of its public key (RFC 7858 Section 4.2).
+    /// With pins and no roots, a pin match is authentication enough.
+    pub fn add_pin(&mut self, pin: &str) -> &mut Self {
+        self.pins.push(pin.to_string());
+        self.set_verifier();
+        self
+    }
+
+    /// Connects even with nothing to authenticate the server by: the
+    /// opportunistic privacy profile of RFC 7858 Section 4.1.
+    pub fn set_opportunistic(&mut self, opportunistic: bool) -> &mut Self {
+        self.opportunistic = opportunistic;
+        self.set_verifier();
+        self
+    }
+
+    /// Offers an application protocol through ALPN (RFC 7301).
+    pub fn add_alpn(&mut self, protocol: &[u8]) -> &mut Self {
+        Arc::make_mut(&mut self.config).alpn_protocols.push(protocol.to_vec());
+        self
+    }
+
+    pub fn alpn(&self) -> &[Vec<u8>] {
+        &self.config.alpn_protocols
+    }
+
+    /// The rustls configuration underneath, sessions and all.
+    pub fn rustls_config(&self) -> Arc<rustls::ClientConfig> {
+        self.config.clone()
+    }
+
+    fn set_verifier(&mut self) {
+        let provider = self.config.crypto_provider().clone();
+        let roots = if self.roots.is_empty() {
+            None
+        } else {
+            WebPkiServerVerifier::builder_with_provider(Arc::new(self.roots.clone()),
+                                                        provider.clone())
+                .build()
+                .ok()
+        };
+        let verifier = Verifier {
+            roots: roots,
+            pins: self.pins.clone(),
+            opportunistic: self.opportunistic,
+            provider: provider,
+        };
+        Arc::make_mut(&mut self.config).dangerous().set_certificate_verifier(Arc::new(verifier));
+    }
+}
+
+/// A server's certificate chain and key, and the sessions it can resume.
+pub struct ServerConfig {
+    config: Arc<rustls::ServerConfig>,
+}
+
+impl ServerConfig {
+    /// Presents `chain`, leaf first, for the private key `key`. Sessions are
+    /// kept in memory, so they do not outlive the process, and each resumes
+    /// once, so 0-RTT data sent with it is taken once.
+    pub fn new(chain: Vec<CertificateDer<'static>>,
+               key: PrivateKeyDer<'static>)
+        -> Result<ServerConfig, TlsError> {
+        let config = try!(rustls::ServerConfig::builder_with_provider(provider())
+                              .with_protocol_versions(&[&rustls::version::TLS13])
+                              .and_then(|builder| {
+                                  builder.with_no_client_auth().with_single_cert(chain, key)
+                              })
+                              .map_err(|e| TlsError::File(e.to_string())));
+        Ok(ServerConfig { config: Arc::new(config) })
+    }
+
+    /// Reads the chain and key from PEM files.
+    pub fn load(certificate: &Path, key: &Path) -> Result<ServerConfig, TlsError> {
+        let chain = try!(load_certificates(certificate));
+        let private_key = try!(load_private_key(key));
+        ServerConfig::new(chain, private_key).map_err(|e| {
+            TlsError::File(format!("{}: {}", key.display(), e))
+        })
+    }
+
+    /// Accepts an application protocol through ALPN; clients offering
+    /// others only are turned away.
+    pub fn add_alpn(&mut self, protocol: &[u8]) -> &mut Self {
+        Arc::make_mut(&mut self.config).alpn_protocols.push(protocol.to_vec());
+        self
+    }
+
+    /// Lets QUIC clients resuming a session send 0-RTT data. It can be
+    /// replayed, so the application must take only what is safe to repeat.
+    pub fn set_early_data(&mut self, early_data: bool) -> &mut Self {
+        Arc::make_mut(&mut self.config).max_early_data_size = if early_data {
+            u32::max_value()
+        } else {
+            0
+        };
+        self
+    }
+
+    /// The rustls configuration underneath.
+    pub fn rustls_config(&self) -> Arc<rustls::ServerConfig> {
+        self.config.clone()
+    }
+}
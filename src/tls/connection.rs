@@ -0,0 +1,255 @@
+//! One TLS connection, a rustls one with the caller doing its I/O.
+
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::mem;
+use std::sync::Arc;
+use rustls::{self, Connection, HandshakeKind};
+use rustls::pki_types::ServerName;
+use super::config::{ClientConfig, ServerConfig};
+use super::TlsError;
+
+/// One TLS connection, either end.
+#[derive(Debug)]
+pub struct TlsConnection {
+    tls: Connection,
+    output: Vec<u8>,
+    plaintext: Vec<u8>,
+    closed: bool,
+    error: Option<TlsError>,
+}
+
+impl TlsConnection {
+    fn new(tls: Connection) -> TlsConnection {
+        let mut connection = TlsConnection {
+            tls: tls,
+            output: Vec::new(),
+            plaintext: Vec::new(),
+            closed: false,
+            error: None,
+        };
+        // What is written before the handshake is done waits for it.
+        connection.tls.set_buffer_limit(None);
+        connection.flush();
+        connection
+    }
+
+    /// Starts a handshake with the server `name`, a host name or an IP
+    /// address, resuming a session saved in `config` if there is one.
+    pub fn client(config: Arc<ClientConfig>, name: &str) -> Result<TlsConnection, TlsError> {
+        let server = try!(ServerName::try_from(name.to_string()).map_err(|_| {
+            TlsError::Protocol(format!("{}: not a server name", name))
+        }));
+        let tls = try!(rustls::ClientConnection::new(config.rustls_config(), server));
+        Ok(TlsConnection::new(Connection::Client(tls)))
+    }
+
+    /// Waits for a client to start a handshake.
+    pub fn server(config: Arc<ServerConfig>) -> TlsConnection {
+        let tls = rustls::ServerConnection::new(config.rustls_config())
+                      .expect("TLS server configuration");
+        TlsConnection::new(Connection::Server(tls))
+    }
+
+    /// Takes octets read from the peer. Application data they complete is
+    /// left for `read`. An error ends the connection; an alert saying why
+    /// may be left in `pending`.
+    pub fn received(&mut self, mut data: &[u8]) -> Result<(), TlsError> {
+        if let Some(ref error) = self.error {
+            return Err(error.clone());
+        }
+        while !data.is_empty() && !self.closed {
+            match self.tls.read_tls(&mut data) {
+                Ok(0) => break,
+                Ok(_) => (),
+                Err(e) => return Err(TlsError::Protocol(e.to_string())),
+            }
+            let state = match self.tls.process_new_packets() {
+                Ok(state) => state,
+                Err(error) => {
+                    self.flush();
+                    let error = TlsError::from(error);
+                    self.error = Some(error.clone());
+                    return Err(error);
+                }
+            };
+            let start = self.plaintext.len();
+            self.plaintext.resize(start + state.plaintext_bytes_to_read(), 0);
+            if let Err(e) = self.tls.reader().read_exact(&mut self.plaintext[start..]) {
+                return Err(TlsError::Protocol(e.to_string()));
+            }
+            self.closed = state.peer_has_closed();
+        }
+        self.flush();
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        while self.tls.wants_write() {
+            if self.tls.write_tls(&mut self.output).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Queues application data, held back until the handshake is done.
+    pub fn write(&mut self, data: &[u8]) {
+        if self.error.is_none() {
+            let _ = self.tls.writer().write_all(data);
+            self.flush();
+        }
+    }
+
+    /// Takes the application data received so far.
+    pub fn read(&mut self) -> Vec<u8> {
+        mem::replace(&mut self.plaintext, Vec::new())
+    }
+
+    /// The octets waiting to be written to the peer.
+    pub fn pending(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn written(&mut self, n: usize) {
+        self.output.drain(..n);
+    }
+
+    /// Tells the peer nothing more will be written.
+    pub fn close(&mut self) {
+        self.tls.send_close_notify();
+        self.flush();
+    }
+
+    pub fn is_handshaking(&self) -> bool {
+        self.tls.is_handshaking()
+    }
+
+    /// Whether the peer has said it will write nothing more.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// The application protocol agreed through ALPN.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.tls.alpn_protocol()
+    }
+
+    /// Whether the handshake resumed an earlier session.
+    pub fn is_resumed(&self) -> bool {
+        self.tls.handshake_kind() == Some(HandshakeKind::Resumed)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+    use tls::{load_certificates, spki_pin};
+
+    fn server_config() -> Arc<ServerConfig> {
+        let mut config = ServerConfig::load(Path::new("testdata/tls-server.pem"),
+                                            Path::new("testdata/tls-server.key"))
+                             .unwrap();
+        config.add_alpn(b"dot");
+        Arc::new(config)
+    }
+
+    fn client_config() -> ClientConfig {
+        let mut config = ClientConfig::new();
+        config.load_roots(Path::new("testdata/tls-ca.pem")).unwrap();
+        config
+    }
+
+    // Passes octets both ways until neither end has any to send.
+    fn pump(client: &mut TlsConnection,
+            server: &mut TlsConnection)
+        -> (Result<(), TlsError>, Result<(), TlsError>) {
+        let mut results = (Ok(()), Ok(()));
+        while !client.pending().is_empty() || !server.pending().is_empty() {
+            let to_server = client.pending().to_vec();
+            client.written(to_server.len());
+            if results.1.is_ok() && !to_server.is_empty() {
+                results.1 = server.received(&to_server);
+            }
+            let to_client = server.pending().to_vec();
+            server.written(to_client.len());
+            if results.0.is_ok() && !to_client.is_empty() {
+                results.0 = client.received(&to_client);
+            }
+        }
+        results
+    }
+
+    #[test]
+    fn handshake_and_resumption() {
+        let server_config = server_config();
+        let mut config = client_config();
+        config.add_alpn(b"dot");
+        let config = Arc::new(config);
+
+        let mut client = TlsConnection::client(config.clone(), "dns.example").unwrap();
+        let mut server = TlsConnection::server(server_config.clone());
+        client.write(b"early query");
+        assert_eq!((Ok(()), Ok(())), pump(&mut client, &mut server));
+        assert!(!client.is_handshaking() && !server.is_handshaking());
+        assert!(!client.is_resumed());
+        assert_eq!(Some(&b"dot"[..]), client.alpn_protocol());
+        assert_eq!(b"early query".to_vec(), server.read());
+
+        // Big enough to take several records.
+        let answer = vec![0x5a; 40000];
+        server.write(&answer);
+        assert_eq!((Ok(()), Ok(())), pump(&mut client, &mut server));
+        assert_eq!(answer, client.read());
+        client.close();
+        assert_eq!((Ok(()), Ok(())), pump(&mut client, &mut server));
+        assert!(server.is_closed());
+
+        // The ticket from the first connection resumes.
+        let mut client = TlsConnection::client(config.clone(), "dns.example").unwrap();
+        let mut server = TlsConnection::server(server_config.clone());
+        assert_eq!((Ok(()), Ok(())), pump(&mut client, &mut server));
+        assert!(client.is_resumed() && server.is_resumed());
+        client.write(b"query");
+        assert_eq!((Ok(()), Ok(())), pump(&mut client, &mut server));
+        assert_eq!(b"query".to_vec(), server.read());
+
+        assert!(TlsConnection::client(config, "not a name").is_err());
+    }
+
+    #[test]
+    fn authentication() {
+        // The wrong name, then the right pin without roots, then a wrong
+        // pin.
+        let mut client = TlsConnection::client(Arc::new(client_config()), "other.example")
+                             .unwrap();
+        let mut server = TlsConnection::server(server_config());
+        let (client_result, server_result) = pump(&mut client, &mut server);
+        assert!(client_result.is_err() && server_result.is_err());
+
+        let chain = load_certificates(Path::new("testdata/tls-server.pem")).unwrap();
+        let mut config = ClientConfig::new();
+        config.add_pin(&spki_pin(&chain[1]).unwrap());
+        let mut client = TlsConnection::client(Arc::new(config), "127.0.0.2").unwrap();
+        let mut server = TlsConnection::server(server_config());
+        assert_eq!((Ok(()), Ok(())), pump(&mut client, &mut server));
+
+        let mut config = client_config();
+        config.add_pin("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=");
+        let mut client = TlsConnection::client(Arc::new(config), "dns.example").unwrap();
+        let mut server = TlsConnection::server(server_config());
+        assert!(pump(&mut client, &mut server).0.is_err());
+
+        // With nothing to go on, only an opportunistic client connects.
+        let mut client = TlsConnection::client(Arc::new(ClientConfig::new()), "dns.example")
+                             .unwrap();
+        let mut server = TlsConnection::server(server_config());
+        assert!(pump(&mut client, &mut server).0.is_err());
+        let mut config = ClientConfig::new();
+        config.set_opportunistic(true);
+        let mut client = TlsConnection::client(Arc::new(config), "dns.example").unwrap();
+        let mut server = TlsConnection::server(server_config());
+        assert_eq!((Ok(()), Ok(())), pump(&mut client, &mut server));
+    }
+}
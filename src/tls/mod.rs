@@ -0,0 +1,48 @@
+//! TLS 1.3 (RFC 8446) for the encrypted DNS transports, on rustls:
+//! certificates checked against roots or SPKI pins, resumption with session
+//! tickets, and 0-RTT only under QUIC. No earlier versions.
+//!
+//! `TlsConnection` works like the stream handlers: octets from the socket
+//! go in, octets for the socket come out, and the caller does the I/O.
+//! QUIC takes the rustls configurations underneath instead.
+
+mod config;
+mod connection;
+mod pem;
+
+use std::error::Error;
+use std::fmt;
+use rustls;
+
+pub use self::config::{ClientConfig, ServerConfig, spki_pin};
+pub use self::connection::TlsConnection;
+pub use self::pem::{load_certificates, load_private_key, pem_decode};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TlsError {
+    /// The handshake or a record failed, at either end.
+    Protocol(String),
+    /// A certificate or key file that cannot be used.
+    File(String),
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(error: rustls::Error) -> TlsError {
+        TlsError::Protocol(error.to_string())
+    }
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.description())
+    }
+}
+
+impl Error for TlsError {
+    fn description(&self) -> &str {
+        match *self {
+            TlsError::Protocol(ref message) => message,
+            TlsError::File(ref message) => message,
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! PEM files (RFC 7468), the way certificates and keys are kept on disk.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs1KeyDer, PrivatePkcs8KeyDer,
+                        PrivateSec1KeyDer};
+use protocol::base64_decode;
+use super::TlsError;
+
+/// The labelled blocks in `text`, decoded, in order. Text outside them is
+/// skipped; None if a block is left open or is not base64.
+pub fn pem_decode(text: &str) -> Option<Vec<(String, Vec<u8>)>> {
+    let mut blocks = Vec::new();
+    let mut open: Option<(String, String)> = None;
+    for line in text.lines() {
+        let line = line.trim();
+        let (label, body) = match open.take() {
+            None => {
+                if line.starts_with("-----BEGIN ") && line.ends_with("-----") {
+                    open = Some((line[11..line.len() - 5].to_string(), String::new()));
+                }
+                continue;
+            }
+            Some(block) => block,
+        };
+        if line == format!("-----END {}-----", label) {
+            match base64_decode(&body) {
+                Some(data) => blocks.push((label, data)),
+                None => return None,
+            }
+        } else {
+            open = Some((label, body + line));
+        }
+    }
+    match open {
+        Some(_) => None,
+        None => Some(blocks),
+    }
+}
+
+fn read_blocks(path: &Path) -> Result<Vec<(String, Vec<u8>)>, TlsError> {
+    let mut text = String::new();
+    try!(File::open(path)
+             .and_then(|mut file| file.read_to_string(&mut text))
+             .map_err(|e| TlsError::File(format!("{}: {}", path.display(), e))));
+    pem_decode(&text).ok_or_else(|| TlsError::File(format!("{}: not a PEM file", path.display())))
+}
+
+/// Reads every certificate in a PEM file: a chain, leaf first, or a set
+/// of trusted roots.
+pub fn load_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certificates: Vec<_> = try!(read_blocks(path))
+                                   .into_iter()
+                                   .filter(|&(ref label, _)| label == "CERTIFICATE")
+                                   .map(|(_, der)| CertificateDer::from(der))
+                                   .collect();
+    if certificates.is_empty() {
+        return Err(TlsError::File(format!("{}: no certificates", path.display())));
+    }
+    Ok(certificates)
+}
+
+/// Reads the first private key in a PEM file, PKCS #8 or the older RSA
+/// and EC forms.
+pub fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    for (label, der) in try!(read_blocks(path)) {
+        match &label[..] {
+            "PRIVATE KEY" => return Ok(PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(der))),
+            "RSA PRIVATE KEY" => return Ok(PrivateKeyDer::Pkcs1(PrivatePkcs1KeyDer::from(der))),
+            "EC PRIVATE KEY" => return Ok(PrivateKeyDer::Sec1(PrivateSec1KeyDer::from(der))),
+            _ => (),
+        }
+    }
+    Err(TlsError::File(format!("{}: no private key", path.display())))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn blocks() {
+        let text = "junk\n-----BEGIN THING-----\nAAEC\nAw==\n-----END THING-----\n\
+                    -----BEGIN OTHER-----\n-----END OTHER-----\n";
+        assert_eq!(Some(vec![("THING".to_string(), vec![0, 1, 2, 3]),
+                             ("OTHER".to_string(), vec![])]),
+                   pem_decode(text));
+        assert_eq!(None, pem_decode("-----BEGIN THING-----\nAAEC\n"));
+        assert_eq!(None, pem_decode("-----BEGIN THING-----\nA\n-----END THING-----\n"));
+    }
+
+    #[test]
+    fn files() {
+        assert_eq!(2, load_certificates(Path::new("testdata/tls-server.pem")).unwrap().len());
+        assert!(load_certificates(Path::new("testdata/tls-server.key")).is_err());
+        assert!(load_certificates(Path::new("testdata/missing.pem")).is_err());
+        match load_private_key(Path::new("testdata/tls-server.key")) {
+            Ok(PrivateKeyDer::Pkcs8(_)) => (),
+            other => panic!("{:?}", other),
+        }
+        match load_private_key(Path::new("testdata/tls-rsa.key")) {
+            Ok(PrivateKeyDer::Pkcs1(_)) => (),
+            other => panic!("{:?}", other),
+        }
+        match load_private_key(Path::new("testdata/tls-server-sec1.key")) {
+            Ok(PrivateKeyDer::Sec1(_)) => (),
+            other => panic!("{:?}", other),
+        }
+        assert!(load_private_key(Path::new("testdata/tls-ca.pem")).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+//! TSIG keys and the BIND key file format they are usually kept in:
+//!
+//! ```text
+//! key "transfer.example.com." {
+//!     algorithm hmac-sha256;
+//!     secret "c2VjcmV0IHNoYXJlZCB3aXRoIHRoZSBwcmltYXJ5";
+//! };
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use crypto::{Hmac, Sha256, Sha384, Sha512};
+use protocol::{Name, base64_decode};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+const ALGORITHMS: &'static [(Algorithm, &'static str)] =
+    &[(Algorithm::HmacSha256, "hmac-sha256"),
+      (Algorithm::HmacSha384, "hmac-sha384"),
+      (Algorithm::HmacSha512, "hmac-sha512")];
+
+impl Algorithm {
+    /// The algorithm's name as it appears in TSIG records.
+    pub fn name(&self) -> Name {
+        let text = ALGORITHMS.iter().find(|&&(a, _)| a == *self).unwrap().1;
+        Name::parse(text, Some(&Name::root())).unwrap()
+    }
+
+    /// Looks an algorithm up by name, with or without the trailing dot.
+    pub fn from_name(name: &Name) -> Option<Algorithm> {
+        ALGORITHMS.iter().find(|&&(a, _)| a.name() == *name).map(|&(a, _)| a)
+    }
+
+    pub fn output_size(&self) -> usize {
+        match *self {
+            Algorithm::HmacSha256 => 32,
+            Algorithm::HmacSha384 => 48,
+            Algorithm::HmacSha512 => 64,
+        }
+    }
+}
+
+/// A MAC being computed with one of the TSIG algorithms.
+pub enum Mac {
+    Sha256(Hmac<Sha256>),
+    Sha384(Hmac<Sha384>),
+    Sha512(Hmac<Sha512>),
+}
+
+impl Mac {
+    pub fn update(&mut self, data: &[u8]) {
+        match *self {
+            Mac::Sha256(ref mut hmac) => hmac.update(data),
+            Mac::Sha384(ref mut hmac) => hmac.update(data),
+            Mac::Sha512(ref mut hmac) => hmac.update(data),
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        match self {
+            Mac::Sha256(hmac) => hmac.finish(),
+            Mac::Sha384(hmac) => hmac.finish(),
+            Mac::Sha512(hmac) => hmac.finish(),
+        }
+    }
+}
+
+/// A shared secret, known to both ends by its name.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key {
+    pub name: Name,
+    pub algorithm: Algorithm,
+    pub secret: Vec<u8>,
+}
+
+impl Key {
+    pub fn mac(&self) -> Mac {
+        match self.algorithm {
+            Algorithm::HmacSha256 => Mac::Sha256(Hmac::new(&self.secret)),
+            Algorithm::HmacSha384 => Mac::Sha384(Hmac::new(&self.secret)),
+            Algorithm::HmacSha512 => Mac::Sha512(Hmac::new(&self.secret)),
+        }
+    }
+}
+
+// Keeps secrets out of logs.
+impl fmt::Debug for Key {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Key")
+           .field("name", &self.name)
+           .field("algorithm", &self.algorithm)
+           .finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyError {
+    /// Zero when the file could not be read at all.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            return write!(fmt, "{}", self.message);
+        }
+        write!(fmt, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for KeyError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+/// Splits key file text into words, quoted strings and the punctuation
+/// `{`, `}` and `;`, each with its line. Comments may be `#`, `//` or
+/// `/* */`.
+fn tokens(text: &str) -> Result<Vec<(usize, String)>, KeyError> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c == b'#' || bytes[i..].starts_with(b"//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+        } else if bytes[i..].starts_with(b"/*") {
+            let start = line;
+            i += 2;
+            while i < bytes.len() && !bytes[i..].starts_with(b"*/") {
+                if bytes[i] == b'\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            if i >= bytes.len() {
+                return Err(KeyError {
+                    line: start,
+                    message: "unterminated comment".to_string(),
+                });
+            }
+            i += 2;
+        } else if c == b'"' {
+            let end = match text[i + 1..].find('"') {
+                Some(end) => i + 1 + end,
+                None => {
+                    return Err(KeyError {
+                        line: line,
+                        message: "unterminated string".to_string(),
+                    })
+                }
+            };
+            tokens.push((line, text[i + 1..end].to_string()));
+            line += text[i + 1..end].matches('\n').count();
+            i = end + 1;
+        } else if c == b'{' || c == b'}' || c == b';' {
+            tokens.push((line, (c as char).to_string()));
+            i += 1;
+        } else {
+            let start = i;
+            while i < bytes.len() && !bytes[i].is_ascii_whitespace() &&
+                  !b"{};\"".contains(&bytes[i]) {
+                i += 1;
+            }
+            tokens.push((line, text[start..i].to_string()));
+        }
+    }
+    Ok(tokens)
+}
+
+// The token at `i`, or an error naming what was expected there.
+fn token<'a>(tokens: &'a [(usize, String)], i: usize, what: &str) -> Result<&'a str, KeyError> {
+    match tokens.get(i) {
+        Some(&(_, ref token)) => Ok(token),
+        None => {
+            Err(KeyError {
+                line: tokens.last().map_or(1, |&(line, _)| line),
+                message: format!("expected {} at end of file", what),
+            })
+        }
+    }
+}
+
+/// Reads the `key` statements of a BIND style key file. Other statements
+/// are not allowed.
+pub fn parse_keys(text: &str) -> Result<Vec<Key>, KeyError> {
+    let tokens = try!(tokens(text));
+    let mut keys = Vec::new();
+    let mut i = 0;
+    let error = |i: usize, message: String| {
+        KeyError {
+            line: tokens.get(i).or(tokens.last()).map_or(1, |&(line, _)| line),
+            message: message,
+        }
+    };
+    while i < tokens.len() {
+        if try!(token(&tokens, i, "key")) != "key" {
+            return Err(error(i, format!("unknown statement {}", tokens[i].1)));
+        }
+        let name = try!(token(&tokens, i + 1, "key name"));
+        let name = match Name::parse(name, Some(&Name::root())) {
+            Some(name) => name,
+            None => return Err(error(i + 1, format!("bad key name {}", tokens[i + 1].1))),
+        };
+        if try!(token(&tokens, i + 2, "{")) != "{" {
+            return Err(error(i + 2, "expected {".to_string()));
+        }
+        let start = i;
+        i += 3;
+        let (mut algorithm, mut secret) = (None, None);
+        while try!(token(&tokens, i, "}")) != "}" {
+            let value = try!(token(&tokens, i + 1, "a value"));
+            match tokens[i].1.as_str() {
+                "algorithm" => {
+                    let name = Name::parse(value, Some(&Name::root()));
+                    match name.as_ref().and_then(Algorithm::from_name) {
+                        Some(a) => algorithm = Some(a),
+                        None => {
+                            return Err(error(i + 1, format!("unsupported algorithm {}", value)))
+                        }
+                    }
+                }
+                "secret" => {
+                    match base64_decode(value) {
+                        Some(s) => secret = Some(s),
+                        None => return Err(error(i + 1, "secret is not base64".to_string())),
+                    }
+                }
+                other => return Err(error(i, format!("unknown key option {}", other))),
+            }
+            if try!(token(&tokens, i + 2, ";")) != ";" {
+                return Err(error(i + 2, "expected ;".to_string()));
+            }
+            i += 3;
+        }
+        if try!(token(&tokens, i + 1, ";")) != ";" {
+            return Err(error(i + 1, "expected ;".to_string()));
+        }
+        i += 2;
+        match (algorithm, secret) {
+            (Some(algorithm), Some(secret)) => {
+                keys.push(Key {
+                    name: name,
+                    algorithm: algorithm,
+                    secret: secret,
+                })
+            }
+            _ => return Err(error(start, format!("key {} needs an algorithm and a secret", name))),
+        }
+    }
+    Ok(keys)
+}
+
+/// Reads a key file.
+pub fn load_keys<P: AsRef<Path>>(path: P) -> Result<Vec<Key>, KeyError> {
+    let path = path.as_ref();
+    let mut text = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+        return Err(KeyError {
+            line: 0,
+            message: format!("{}: {}", path.display(), e),
+        });
+    }
+    parse_keys(&text)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::Name;
+
+    #[test]
+    fn key_files() {
+        let text = "# generated by tsig-keygen\n\
+                    key \"transfer.example.com\" {\n\
+                    \talgorithm hmac-sha256;\n\
+                    \tsecret \"c2VjcmV0\";\n\
+                    };\n\
+                    /* a second\n   key */\n\
+                    key update. { algorithm HMAC-SHA512.; secret \"AQID\"; };\n";
+        let keys = parse_keys(text).unwrap();
+        assert_eq!(vec![Key {
+                            name: Name::parse("transfer.example.com.", None).unwrap(),
+                            algorithm: Algorithm::HmacSha256,
+                            secret: b"secret".to_vec(),
+                        },
+                        Key {
+                            name: Name::parse("update.", None).unwrap(),
+                            algorithm: Algorithm::HmacSha512,
+                            secret: vec![1, 2, 3],
+                        }],
+                   keys);
+        assert_eq!(Some(Algorithm::HmacSha384),
+                   Algorithm::from_name(&Name::parse("hmac-sha384.", None).unwrap()));
+    }
+
+    #[test]
+    fn key_file_errors() {
+        assert_eq!(2,
+                   parse_keys("\nkey a { algorithm hmac-md5; secret \"AA==\"; };")
+                       .unwrap_err()
+                       .line);
+        assert_eq!(1, parse_keys("key a { algorithm hmac-sha256; };").unwrap_err().line);
+        assert_eq!(1, parse_keys("options { };").unwrap_err().line);
+        assert_eq!(3,
+                   parse_keys("key a {\nsecret \"AA==\";\nalgorithm hmac-sha256; }")
+                       .unwrap_err()
+                       .line);
+        assert!(parse_keys("key \"a { };").is_err());
+        // Words are split at ASCII whitespace only, never inside a character.
+        assert!(parse_keys("key \"k.\" {\u{160} a").is_err());
+        assert_eq!(Ok(Vec::new()), parse_keys("// nothing\n"));
+    }
+}
@@ -0,0 +1,9 @@
+//! Transaction signatures (TSIG, RFC 8945): shared secret keys that
+//! authenticate requests and responses between two hosts.
+
+mod key;
+mod sign;
+
+pub use self::key::{Algorithm, Key, KeyError, Mac, load_keys, parse_keys};
+pub use self::sign::{BADKEY, BADSIG, BADTIME, BADTRUNC, FUDGE, MAX_UNSIGNED, RequestError,
+                     SignedRequest, Signer, TsigError, Verifier, now, verify_request};
@@ -0,0 +1,522 @@
+//! Signing messages and checking their signatures (RFC 8945 Section 5).
+//!
+//! A stream of messages, such as the answer to a zone transfer, is signed as
+//! a chain: every MAC also covers the one before it, starting from the MAC
+//! of the request, and any unsigned messages since.
+
+use std::error::Error;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crypto::constant_time_eq;
+use protocol::{BEU48Field, Header, TsigRecord, RC_FORMAT_ERROR, RC_NOT_AUTH};
+use super::key::Key;
+
+pub const BADSIG: u16 = 16;
+pub const BADKEY: u16 = 17;
+pub const BADTIME: u16 = 18;
+pub const BADTRUNC: u16 = 22;
+
+/// Seconds either side of the signing time within which a signature is
+/// accepted.
+pub const FUDGE: u16 = 300;
+/// Unsigned messages a stream may have in a row (RFC 8945 Section 5.3.1).
+pub const MAX_UNSIGNED: usize = 99;
+
+/// The current time in seconds since the epoch, as TSIG counts it.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TsigError {
+    /// A TSIG record that cannot be used at all; answered with FORMERR.
+    Malformed,
+    /// No signature where one is required.
+    Unsigned,
+    BadSig,
+    BadKey,
+    BadTime,
+    /// The other end answered with this TSIG error.
+    Reported(u16),
+}
+
+impl TsigError {
+    /// The code for the TSIG error field.
+    pub fn code(&self) -> u16 {
+        match *self {
+            TsigError::Malformed | TsigError::Unsigned => 0,
+            TsigError::BadSig => BADSIG,
+            TsigError::BadKey => BADKEY,
+            TsigError::BadTime => BADTIME,
+            TsigError::Reported(code) => code,
+        }
+    }
+
+    /// The RCODE of a response reporting the error.
+    pub fn rcode(&self) -> u8 {
+        match *self {
+            TsigError::Malformed => RC_FORMAT_ERROR,
+            _ => RC_NOT_AUTH,
+        }
+    }
+}
+
+impl fmt::Display for TsigError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TsigError::Reported(BADSIG) => write!(fmt, "TSIG rejected: bad signature"),
+            TsigError::Reported(BADKEY) => write!(fmt, "TSIG rejected: unknown key"),
+            TsigError::Reported(BADTIME) => write!(fmt, "TSIG rejected: clocks differ"),
+            TsigError::Reported(code) => write!(fmt, "TSIG rejected with error {}", code),
+            _ => write!(fmt, "{}", self.description()),
+        }
+    }
+}
+
+impl Error for TsigError {
+    fn description(&self) -> &str {
+        match *self {
+            TsigError::Malformed => "malformed TSIG record",
+            TsigError::Unsigned => "message is not signed",
+            TsigError::BadSig => "TSIG signature does not match",
+            TsigError::BadKey => "unknown TSIG key",
+            TsigError::BadTime => "TSIG signing time out of range",
+            TsigError::Reported(_) => "TSIG rejected",
+        }
+    }
+}
+
+/// The state shared by both ends of a signed exchange.
+#[derive(Debug)]
+struct Chain {
+    key: Key,
+    // The MAC the next one builds on.
+    prior: Option<Vec<u8>>,
+    // Later messages of a stream only cover the timers.
+    first: bool,
+    // Unsigned messages since the prior MAC, which the next one covers.
+    between: Vec<u8>,
+}
+
+impl Chain {
+    fn digest(&self, unsigned: &[u8], tsig: &TsigRecord) -> Vec<u8> {
+        let mut mac = self.key.mac();
+        if let Some(ref prior) = self.prior {
+            mac.update(&[(prior.len() >> 8) as u8, prior.len() as u8]);
+            mac.update(prior);
+        }
+        mac.update(&self.between);
+        mac.update(unsigned);
+        mac.update(&tsig.variables(!self.first));
+        mac.finish()
+    }
+
+    fn advance(&mut self, mac: Vec<u8>) {
+        self.prior = Some(mac);
+        self.first = false;
+        self.between.clear();
+    }
+
+    /// Checks the MAC and time of `tsig` (RFC 8945 Section 5.2), and
+    /// returns the MAC on success.
+    fn check(&self, unsigned: &[u8], tsig: &TsigRecord, now: u64) -> Result<Vec<u8>, TsigError> {
+        if tsig.key_name != self.key.name || tsig.algorithm != self.key.algorithm.name() {
+            return Err(TsigError::BadKey);
+        }
+        let size = self.key.algorithm.output_size();
+        if tsig.mac.len() > size || tsig.mac.len() < ::std::cmp::max(10, size / 2) {
+            return Err(TsigError::Malformed);
+        }
+        let expected = self.digest(unsigned, tsig);
+        if !constant_time_eq(&expected[..tsig.mac.len()], &tsig.mac) {
+            return Err(TsigError::BadSig);
+        }
+        let fudge = tsig.fudge as u64;
+        if now > tsig.time_signed + fudge || now + fudge < tsig.time_signed {
+            return Err(TsigError::BadTime);
+        }
+        Ok(tsig.mac.clone())
+    }
+}
+
+/// Signs a request, or the one or more messages answering a signed request.
+#[derive(Debug)]
+pub struct Signer {
+    chain: Chain,
+}
+
+impl Signer {
+    /// Signs with `key`. Responses take the MAC of the request they answer.
+    pub fn new(key: Key, request_mac: Option<Vec<u8>>) -> Signer {
+        Signer {
+            chain: Chain {
+                key: key,
+                prior: request_mac,
+                first: true,
+                between: Vec::new(),
+            },
+        }
+    }
+
+    /// The most a signature adds to a message.
+    pub fn overhead(&self) -> usize {
+        let key = &self.chain.key;
+        // Owner, type, class, TTL and length; the rdata fields; six octets
+        // of other data for BADTIME.
+        key.name.wire_len() + 10 + key.algorithm.name().wire_len() + 16 +
+        key.algorithm.output_size() + 6
+    }
+
+    /// Appends a TSIG record to `message`. Returns the MAC, which signs the
+    /// request a response must be checked against.
+    pub fn sign(&mut self, message: &mut Vec<u8>, now: u64) -> Vec<u8> {
+        self.sign_error(message, now, 0, Vec::new())
+    }
+
+    /// Signs with an error code and other data in the record, as a BADTIME
+    /// response is.
+    pub fn sign_error(&mut self,
+                      message: &mut Vec<u8>,
+                      time_signed: u64,
+                      error: u16,
+                      other: Vec<u8>)
+        -> Vec<u8> {
+        let mut tsig = TsigRecord {
+            key_name: self.chain.key.name.clone(),
+            algorithm: self.chain.key.algorithm.name(),
+            time_signed: time_signed,
+            fudge: FUDGE,
+            mac: Vec::new(),
+            original_id: Header::at(&message[..]).id().unwrap_or(0),
+            error: error,
+            other: other,
+        };
+        tsig.mac = self.chain.digest(message, &tsig);
+        tsig.append_to(message);
+        self.chain.advance(tsig.mac.clone());
+        tsig.mac
+    }
+
+    /// Sends `message` unsigned, for the next signature to cover. At most
+    /// `MAX_UNSIGNED` may go in a row, and never the first or the last.
+    pub fn skip(&mut self, message: &[u8]) {
+        self.chain.between.extend_from_slice(message);
+    }
+}
+
+/// Checks the signatures of the messages answering a signed request.
+#[derive(Debug)]
+pub struct Verifier {
+    chain: Chain,
+    // Unsigned messages since the last signed one.
+    skipped: usize,
+}
+
+impl Verifier {
+    pub fn new(key: Key, request_mac: Vec<u8>) -> Verifier {
+        Verifier {
+            chain: Chain {
+                key: key,
+                prior: Some(request_mac),
+                first: true,
+                between: Vec::new(),
+            },
+            skipped: 0,
+        }
+    }
+
+    /// Checks the next message, and returns it as it was before signing.
+    /// Up to `MAX_UNSIGNED` messages in a row after the first may come
+    /// unsigned, to be covered by the next signature; `finish` checks that
+    /// the stream did not end on one.
+    pub fn verify(&mut self, message: &[u8], now: u64) -> Result<Vec<u8>, TsigError> {
+        let (tsig, start) = match TsigRecord::find(message) {
+            Ok(Some(found)) => found,
+            Ok(None) if !self.chain.first && self.skipped < MAX_UNSIGNED => {
+                self.skipped += 1;
+                self.chain.between.extend_from_slice(message);
+                return Ok(message.to_vec());
+            }
+            Ok(None) => return Err(TsigError::Unsigned),
+            Err(()) => return Err(TsigError::Malformed),
+        };
+        if tsig.error != 0 {
+            return Err(TsigError::Reported(tsig.error));
+        }
+        let unsigned = TsigRecord::unsigned(message, &tsig, start);
+        let mac = try!(self.chain.check(&unsigned, &tsig, now));
+        self.chain.advance(mac);
+        self.skipped = 0;
+        Ok(unsigned)
+    }
+
+    /// Checks that the last message was signed, as a stream must end.
+    pub fn finish(&self) -> Result<(), TsigError> {
+        if self.skipped > 0 {
+            return Err(TsigError::Unsigned);
+        }
+        Ok(())
+    }
+}
+
+/// A request whose signature checked out.
+#[derive(Debug)]
+pub struct SignedRequest {
+    pub key: Key,
+    pub mac: Vec<u8>,
+    /// The request as it was before signing.
+    pub message: Vec<u8>,
+}
+
+impl SignedRequest {
+    /// A signer for the response messages.
+    pub fn signer(&self) -> Signer {
+        Signer::new(self.key.clone(), Some(self.mac.clone()))
+    }
+}
+
+/// Why a signed request was not accepted, with what is needed to say so.
+#[derive(Debug)]
+pub struct RequestError {
+    pub error: TsigError,
+    tsig: Option<TsigRecord>,
+    // The key, when the signature was good but the time was not.
+    key: Option<Key>,
+}
+
+impl RequestError {
+    /// Adds the TSIG record reporting the error to a response carrying
+    /// `self.error.rcode()` (RFC 8945 Section 5.3.2). Only a BADTIME response
+    /// can be signed; the others say which key failed and no more.
+    pub fn sign_response(&self, response: &mut Vec<u8>, now: u64) {
+        let tsig = match self.tsig {
+            Some(ref tsig) => tsig,
+            None => return,
+        };
+        if let Some(ref key) = self.key {
+            let mut other = vec![0u8; 6];
+            BEU48Field { index: 0 }.set(&mut other[..], now);
+            Signer::new(key.clone(), Some(tsig.mac.clone()))
+                .sign_error(response, tsig.time_signed, self.error.code(), other);
+            return;
+        }
+        TsigRecord {
+                mac: Vec::new(),
+                error: self.error.code(),
+                other: Vec::new(),
+                ..tsig.clone()
+            }
+            .append_to(response);
+    }
+}
+
+/// Checks the signature of a request against the keys known. Returns None
+/// for an unsigned request.
+pub fn verify_request(message: &[u8],
+                      keys: &[Key],
+                      now: u64)
+    -> Result<Option<SignedRequest>, RequestError> {
+    let (tsig, start) = match TsigRecord::find(message) {
+        Ok(Some(found)) => found,
+        Ok(None) => return Ok(None),
+        Err(()) => {
+            return Err(RequestError {
+                error: TsigError::Malformed,
+                tsig: None,
+                key: None,
+            })
+        }
+    };
+    let fail = |error: TsigError, tsig: TsigRecord, key: Option<Key>| {
+        Err(RequestError {
+            error: error,
+            tsig: Some(tsig),
+            key: key,
+        })
+    };
+    let key = match keys.iter().find(|k| k.name == tsig.key_name) {
+        Some(key) => key,
+        None => return fail(TsigError::BadKey, tsig, None),
+    };
+    let chain = Chain {
+        key: key.clone(),
+        prior: None,
+        first: true,
+        between: Vec::new(),
+    };
+    let unsigned = TsigRecord::unsigned(message, &tsig, start);
+    match chain.check(&unsigned, &tsig, now) {
+        Ok(mac) => {
+            Ok(Some(SignedRequest {
+                key: key.clone(),
+                mac: mac,
+                message: unsigned,
+            }))
+        }
+        Err(TsigError::BadTime) => fail(TsigError::BadTime, tsig, Some(key.clone())),
+        Err(TsigError::Malformed) => {
+            Err(RequestError {
+                error: TsigError::Malformed,
+                tsig: None,
+                key: None,
+            })
+        }
+        Err(error) => fail(error, tsig, None),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::{Header, HeaderMut, MessageCursor, Name, QuestionMut, RC_NOT_AUTH,
+                   TsigRecord};
+    use tsig::{Algorithm, Key};
+
+    fn key(name: &str, algorithm: Algorithm) -> Key {
+        Key {
+            name: Name::parse(name, None).unwrap(),
+            algorithm: algorithm,
+            secret: b"0123456789abcdef".to_vec(),
+        }
+    }
+
+    fn message(id: u16) -> Vec<u8> {
+        let mut message = vec![0u8; 512];
+        let mut idx = MessageCursor::new(message.len());
+        HeaderMut::at(&mut idx, &mut message[..]).unwrap().make_query(id).set_qd(1);
+        QuestionMut::at(&mut idx,
+                        &mut message,
+                        &Name::parse("example.com.", None).unwrap().segments(),
+                        252,
+                        1)
+            .unwrap();
+        message.truncate(idx.tell());
+        message
+    }
+
+    #[test]
+    fn request_and_responses() {
+        let now = 1_500_000_000;
+        let keys = vec![key("other.", Algorithm::HmacSha512),
+                        key("transfer.example.", Algorithm::HmacSha384)];
+        let mut request = message(7);
+        let request_mac = Signer::new(keys[1].clone(), None).sign(&mut request, now);
+        assert_eq!(48, request_mac.len());
+
+        let signed = verify_request(&request, &keys, now + 10).unwrap().unwrap();
+        assert_eq!(keys[1], signed.key);
+        assert_eq!(request_mac, signed.mac);
+        assert_eq!(message(7), signed.message);
+
+        // Three response messages, signed as a chain.
+        let mut signer = signed.signer();
+        let mut responses = Vec::new();
+        for _ in 0..3 {
+            let mut response = message(7);
+            HeaderMut::at_raw(&mut response[..]).set_qr(true);
+            signer.sign(&mut response, now + 20);
+            responses.push(response);
+        }
+        let mut verifier = Verifier::new(keys[1].clone(), request_mac.clone());
+        for response in &responses {
+            assert!(verifier.verify(response, now + 30).is_ok());
+        }
+        // Out of order breaks the chain.
+        let mut verifier = Verifier::new(keys[1].clone(), request_mac.clone());
+        assert!(verifier.verify(&responses[0], now).is_ok());
+        assert_eq!(Err(TsigError::BadSig), verifier.verify(&responses[2], now));
+        // The first message must be signed.
+        let mut verifier = Verifier::new(keys[1].clone(), request_mac);
+        assert_eq!(Err(TsigError::Unsigned), verifier.verify(&message(7), now));
+    }
+
+    #[test]
+    fn unsigned_between() {
+        let now = 1_500_000_000;
+        let key = key("transfer.example.", Algorithm::HmacSha256);
+        let mut request = message(7);
+        let request_mac = Signer::new(key.clone(), None).sign(&mut request, now);
+        let mut response = message(7);
+        HeaderMut::at_raw(&mut response[..]).set_qr(true);
+
+        // Signed, then as many unsigned as allowed, then signed again over
+        // them all.
+        let mut signer = Signer::new(key.clone(), Some(request_mac.clone()));
+        let mut responses = Vec::new();
+        for i in 0..MAX_UNSIGNED + 2 {
+            let mut message = response.clone();
+            if i == 0 || i == MAX_UNSIGNED + 1 {
+                signer.sign(&mut message, now);
+            } else {
+                signer.skip(&message);
+            }
+            responses.push(message);
+        }
+        let mut verifier = Verifier::new(key.clone(), request_mac.clone());
+        for (i, message) in responses.iter().enumerate() {
+            assert!(verifier.verify(message, now).is_ok());
+            assert_eq!(i == 0 || i == MAX_UNSIGNED + 1, verifier.finish().is_ok());
+        }
+
+        // An unsigned message changed on the way breaks the next signature.
+        let mut verifier = Verifier::new(key.clone(), request_mac.clone());
+        let mut tampered = responses[1].clone();
+        HeaderMut::at_raw(&mut tampered[..]).set_rd(false);
+        assert!(verifier.verify(&responses[0], now).is_ok());
+        assert!(verifier.verify(&tampered, now).is_ok());
+        for message in &responses[2..MAX_UNSIGNED + 1] {
+            assert!(verifier.verify(message, now).is_ok());
+        }
+        assert_eq!(Err(TsigError::BadSig),
+                   verifier.verify(&responses[MAX_UNSIGNED + 1], now));
+
+        // One more unsigned in a row is too many.
+        let mut verifier = Verifier::new(key, request_mac);
+        for message in &responses[..MAX_UNSIGNED + 1] {
+            assert!(verifier.verify(message, now).is_ok());
+        }
+        assert_eq!(Err(TsigError::Unsigned), verifier.verify(&response, now));
+    }
+
+    #[test]
+    fn rejected_requests() {
+        let now = 1_500_000_000;
+        let good = key("k.", Algorithm::HmacSha256);
+        let mut request = message(9);
+        Signer::new(good.clone(), None).sign(&mut request, now);
+
+        let error = verify_request(&request, &[key("unknown.", Algorithm::HmacSha256)], now)
+                        .unwrap_err();
+        assert_eq!(TsigError::BadKey, error.error);
+        let wrong_secret = Key { secret: b"guess".to_vec(), ..good.clone() };
+        let error = verify_request(&request, &[wrong_secret], now).unwrap_err();
+        assert_eq!(TsigError::BadSig, error.error);
+        // The reply names the problem and is not signed.
+        let mut response = message(9);
+        HeaderMut::at_raw(&mut response[..]).set_qr(true).set_rc(RC_NOT_AUTH);
+        error.sign_response(&mut response, now);
+        let (tsig, _) = TsigRecord::find(&response).unwrap().unwrap();
+        assert_eq!((BADSIG, 0), (tsig.error, tsig.mac.len()));
+
+        // BADTIME replies are signed, with our clock in the other data.
+        let error = verify_request(&request, &[good.clone()], now + 301).unwrap_err();
+        assert_eq!(TsigError::BadTime, error.error);
+        let mut response = message(9);
+        HeaderMut::at_raw(&mut response[..]).set_qr(true).set_rc(RC_NOT_AUTH);
+        error.sign_response(&mut response, now + 301);
+        let request_mac = TsigRecord::find(&request).unwrap().unwrap().0.mac;
+        let mut verifier = Verifier::new(good.clone(), request_mac);
+        assert_eq!(Err(TsigError::Reported(BADTIME)), verifier.verify(&response, now));
+        let (tsig, _) = TsigRecord::find(&response).unwrap().unwrap();
+        assert_eq!(vec![0, 0, 0x59, 0x68, 0x30, 0x2d], tsig.other);
+
+        // Tampering after signing.
+        let mut tampered = request.clone();
+        HeaderMut::at_raw(&mut tampered[..]).set_rd(false);
+        assert_eq!(TsigError::BadSig,
+                   verify_request(&tampered, &[good.clone()], now).unwrap_err().error);
+        assert_eq!(Some(9), Header::at(&request[..]).id());
+        assert!(verify_request(&message(9), &[good], now).unwrap().is_none());
+    }
+}
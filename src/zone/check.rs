@@ -0,0 +1,458 @@
+//! Zone integrity checks, in the spirit of BIND's `named-checkzone`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use protocol::{Name, RData, Record};
+use protocol::rdata::{TYPE_A, TYPE_AAAA, TYPE_CNAME, TYPE_DS, TYPE_NS, TYPE_NSEC, TYPE_RRSIG,
+                      TYPE_SOA, type_name};
+use super::parser::{Location, ZoneParser};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Severity::Warning => write!(fmt, "warning"),
+            Severity::Error => write!(fmt, "error"),
+        }
+    }
+}
+
+/// One problem found in a zone. Problems with the zone as a whole, like a
+/// missing SOA, have no location.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref location) = self.location {
+            try!(write!(fmt, "{}: ", location));
+        }
+        write!(fmt, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Whether any finding is severe enough to refuse loading the zone.
+pub fn has_errors(findings: &[Finding]) -> bool {
+    findings.iter().any(|f| f.severity == Severity::Error)
+}
+
+/// Parses and checks a zone file. Syntax errors are reported as findings
+/// alongside the semantic checks of `check_zone`.
+pub fn check_zone_file<P: AsRef<Path>>(path: P, origin: &Name) -> Vec<Finding> {
+    let mut parser = match ZoneParser::open(path, Some(origin.clone())) {
+        Ok(parser) => parser,
+        Err(e) => {
+            return vec![Finding {
+                            severity: Severity::Error,
+                            location: Some(e.location),
+                            message: e.message,
+                        }]
+        }
+    };
+    let mut findings = Vec::new();
+    let mut records = Vec::new();
+    while let Some(result) = parser.next() {
+        match result {
+            Ok(record) => records.push((record, parser.location().clone())),
+            Err(e) => {
+                findings.push(Finding {
+                    severity: Severity::Error,
+                    location: Some(e.location),
+                    message: e.message,
+                })
+            }
+        }
+    }
+    findings.extend(check_zone(origin, &records));
+    findings
+}
+
+struct Checker<'a> {
+    origin: &'a Name,
+    records: &'a [(Record, Location)],
+    findings: Vec<Finding>,
+    // Indexes into `records`, by owner, for in-zone records only.
+    owners: HashMap<&'a Name, Vec<usize>>,
+    cuts: HashSet<&'a Name>,
+}
+
+/// Checks a parsed zone. Each record comes with the location it was read
+/// from, as reported by `ZoneParser::location`.
+pub fn check_zone(origin: &Name, records: &[(Record, Location)]) -> Vec<Finding> {
+    let mut checker = Checker {
+        origin: origin,
+        records: records,
+        findings: Vec::new(),
+        owners: HashMap::new(),
+        cuts: HashSet::new(),
+    };
+    checker.index();
+    checker.check_apex();
+    checker.check_cnames();
+    checker.check_delegations();
+    checker.check_targets();
+    checker.check_ttls();
+    let mut findings = checker.findings;
+    findings.sort_by(|a, b| {
+        let key = |f: &Finding| f.location.as_ref().map(|l| (l.file.clone(), l.line, l.column));
+        key(a).cmp(&key(b))
+    });
+    findings
+}
+
+impl<'a> Checker<'a> {
+    fn report(&mut self, severity: Severity, index: Option<usize>, message: String) {
+        self.findings.push(Finding {
+            severity: severity,
+            location: index.map(|i| self.records[i].1.clone()),
+            message: message,
+        });
+    }
+
+    fn types_at(&self, name: &Name) -> Vec<u16> {
+        match self.owners.get(name) {
+            Some(indexes) => indexes.iter().map(|&i| self.records[i].0.rtype()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn has_address(&self, name: &Name) -> bool {
+        self.types_at(name).iter().any(|&t| t == TYPE_A || t == TYPE_AAAA)
+    }
+
+    /// The zone cut at or above `name`, if any, not counting the apex.
+    fn enclosing_cut(&self, name: &Name) -> Option<&'a Name> {
+        let mut current = Some(name.clone());
+        while let Some(n) = current {
+            if &n == self.origin {
+                return None;
+            }
+            if let Some(cut) = self.cuts.get(&n) {
+                return Some(*cut);
+            }
+            current = n.parent();
+        }
+        None
+    }
+
+    fn index(&mut self) {
+        let records = self.records;
+        for (i, &(ref record, _)) in records.iter().enumerate() {
+            if !record.name.is_subdomain_of(self.origin) {
+                self.report(Severity::Error,
+                            Some(i),
+                            format!("{} is out of zone {}", record.name, self.origin));
+                continue;
+            }
+            self.owners.entry(&record.name).or_insert(Vec::new()).push(i);
+            if record.rtype() == TYPE_NS && &record.name != self.origin {
+                self.cuts.insert(&record.name);
+            }
+        }
+    }
+
+    fn check_apex(&mut self) {
+        let records = self.records;
+        let mut soa_count = 0;
+        for (i, &(ref record, _)) in records.iter().enumerate() {
+            if !record.name.is_subdomain_of(self.origin) {
+                continue;
+            }
+            match record.rtype() {
+                TYPE_SOA if &record.name == self.origin => {
+                    soa_count += 1;
+                    if soa_count > 1 {
+                        self.report(Severity::Error, Some(i), "multiple SOA records".to_string());
+                    }
+                }
+                TYPE_SOA => {
+                    self.report(Severity::Error,
+                                Some(i),
+                                format!("SOA record at {} is not at the zone apex", record.name));
+                }
+                TYPE_DS if &record.name == self.origin => {
+                    self.report(Severity::Error,
+                                Some(i),
+                                "DS record at the zone apex belongs in the parent zone"
+                                    .to_string());
+                }
+                _ => {}
+            }
+        }
+        if soa_count == 0 {
+            self.report(Severity::Error, None, format!("{} has no SOA record", self.origin));
+        }
+        if !self.types_at(self.origin).contains(&TYPE_NS) {
+            self.report(Severity::Error, None, format!("{} has no NS records", self.origin));
+        }
+    }
+
+    fn check_cnames(&mut self) {
+        let mut owners: Vec<(&Name, Vec<usize>)> = self.owners
+                                                       .iter()
+                                                       .map(|(n, i)| (*n, i.clone()))
+                                                       .collect();
+        owners.sort();
+        for (name, indexes) in owners {
+            let cnames: Vec<usize> = indexes.iter()
+                                            .cloned()
+                                            .filter(|&i| self.records[i].0.rtype() == TYPE_CNAME)
+                                            .collect();
+            if cnames.is_empty() {
+                continue;
+            }
+            if cnames.len() > 1 {
+                self.report(Severity::Error,
+                            Some(cnames[1]),
+                            format!("{} has more than one CNAME", name));
+            }
+            for &i in &indexes {
+                let rtype = self.records[i].0.rtype();
+                if rtype != TYPE_CNAME && rtype != TYPE_RRSIG && rtype != TYPE_NSEC {
+                    self.report(Severity::Error,
+                                Some(i),
+                                format!("CNAME and other data at {}", name));
+                }
+            }
+        }
+    }
+
+    /// Finds data hidden below zone cuts, and glue that nothing refers to.
+    fn check_delegations(&mut self) {
+        let records = self.records;
+        let mut ns_targets = HashSet::new();
+        for &(ref record, _) in records {
+            if let RData::Ns(ref target) = record.rdata {
+                ns_targets.insert(target);
+            }
+        }
+        for (i, &(ref record, _)) in records.iter().enumerate() {
+            if !record.name.is_subdomain_of(self.origin) {
+                continue;
+            }
+            let cut = match self.enclosing_cut(&record.name) {
+                Some(cut) => cut,
+                None => continue,
+            };
+            let rtype = record.rtype();
+            let at_cut = &record.name == cut;
+            match rtype {
+                TYPE_NS | TYPE_DS | TYPE_RRSIG | TYPE_NSEC if at_cut => {}
+                TYPE_A | TYPE_AAAA => {
+                    if !ns_targets.contains(&record.name) {
+                        self.report(Severity::Warning,
+                                    Some(i),
+                                    format!("unneeded glue {} {} below delegation {}",
+                                            record.name,
+                                            type_name(rtype),
+                                            cut));
+                    }
+                }
+                _ => {
+                    self.report(Severity::Warning,
+                                Some(i),
+                                format!("{} {} is occluded by the delegation at {}",
+                                        record.name,
+                                        type_name(rtype),
+                                        cut));
+                }
+            }
+        }
+    }
+
+    /// Checks where in-zone NS and MX targets lead.
+    fn check_targets(&mut self) {
+        let records = self.records;
+        for (i, &(ref record, _)) in records.iter().enumerate() {
+            if !record.name.is_subdomain_of(self.origin) {
+                continue;
+            }
+            let (target, what) = match record.rdata {
+                RData::Ns(ref target) => (target, "NS"),
+                RData::Mx { ref exchange, .. } => (exchange, "MX"),
+                _ => continue,
+            };
+            if !target.is_subdomain_of(self.origin) {
+                continue;
+            }
+            if self.types_at(target).contains(&TYPE_CNAME) {
+                self.report(Severity::Error,
+                            Some(i),
+                            format!("{} target {} is a CNAME", what, target));
+            } else if !self.has_address(target) {
+                let message = if what == "NS" && self.enclosing_cut(target).is_some() {
+                    format!("missing glue for NS target {}", target)
+                } else {
+                    format!("{} target {} has no address records (A or AAAA)", what, target)
+                };
+                self.report(Severity::Error, Some(i), message);
+            }
+        }
+    }
+
+    /// All records of an RRset should share one TTL (RFC 2181 Section 5.2).
+    fn check_ttls(&mut self) {
+        let records = self.records;
+        let mut first_ttl: HashMap<(&Name, u16, u16), u32> = HashMap::new();
+        for (i, &(ref record, _)) in records.iter().enumerate() {
+            if !record.name.is_subdomain_of(self.origin) {
+                continue;
+            }
+            let key = (&record.name, record.class, record.rtype());
+            let ttl = *first_ttl.entry(key).or_insert(record.ttl);
+            if ttl != record.ttl {
+                self.report(Severity::Warning,
+                            Some(i),
+                            format!("TTL {} differs from {} for the {} {} RRset",
+                                    record.ttl,
+                                    ttl,
+                                    record.name,
+                                    type_name(record.rtype())));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use protocol::Name;
+    use zone::ZoneParser;
+
+    fn name(text: &str) -> Name {
+        Name::parse(text, None).unwrap()
+    }
+
+    fn check(text: &str) -> Vec<Finding> {
+        let origin = name("example.com.");
+        let mut parser = ZoneParser::new(Cursor::new(text.as_bytes().to_vec()),
+                                         "test.zone",
+                                         Some(origin.clone()));
+        let mut records = Vec::new();
+        while let Some(result) = parser.next() {
+            records.push((result.unwrap(), parser.location().clone()));
+        }
+        check_zone(&origin, &records)
+    }
+
+    const APEX: &'static str = "$TTL 300\n\
+                                @ SOA ns1 hostmaster 1 7200 3600 1209600 300\n\
+                                @ NS ns1\n\
+                                ns1 A 192.0.2.53\n";
+
+    fn messages(findings: &[Finding]) -> Vec<String> {
+        findings.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn clean_zone() {
+        let findings = check(&format!("{}\
+                                       @ MX 10 mail\n\
+                                       mail A 192.0.2.25\n\
+                                       sub NS ns.sub\n\
+                                       ns.sub A 192.0.2.99\n\
+                                       sub DS 1 8 2 abcd\n",
+                                      APEX));
+        assert_eq!(Vec::<String>::new(), messages(&findings));
+    }
+
+    #[test]
+    fn missing_soa_and_ns() {
+        let findings = check("$TTL 300\nwww A 192.0.2.1\n");
+        assert_eq!(vec!["error: example.com. has no SOA record".to_string(),
+                        "error: example.com. has no NS records".to_string()],
+                   messages(&findings));
+        assert!(has_errors(&findings));
+    }
+
+    #[test]
+    fn cname_and_other_data() {
+        let findings = check(&format!("{}www CNAME other\nwww TXT hi\n", APEX));
+        assert_eq!(vec!["test.zone:6:1: error: CNAME and other data at www.example.com."
+                            .to_string()],
+                   messages(&findings));
+    }
+
+    #[test]
+    fn glue() {
+        let findings = check(&format!("{}\
+                                       sub NS ns.sub\n\
+                                       sub NS ns.elsewhere.net.\n\
+                                       stray.sub A 192.0.2.1\n\
+                                       www.sub TXT hidden\n",
+                                      APEX));
+        assert_eq!(vec!["test.zone:5:1: error: missing glue for NS target ns.sub.example.com."
+                            .to_string(),
+                        "test.zone:7:1: warning: unneeded glue stray.sub.example.com. A below \
+                         delegation sub.example.com."
+                            .to_string(),
+                        "test.zone:8:1: warning: www.sub.example.com. TXT is occluded by the \
+                         delegation at sub.example.com."
+                            .to_string()],
+                   messages(&findings));
+    }
+
+    #[test]
+    fn targets_are_cnames() {
+        let findings = check(&format!("{}\
+                                       @ NS alias\n\
+                                       @ MX 10 alias\n\
+                                       alias CNAME ns1\n\
+                                       @ MX 20 nowhere\n",
+                                      APEX));
+        assert_eq!(vec!["test.zone:5:1: error: NS target alias.example.com. is a CNAME"
+                            .to_string(),
+                        "test.zone:6:1: error: MX target alias.example.com. is a CNAME"
+                            .to_string(),
+                        "test.zone:8:1: error: MX target nowhere.example.com. has no address \
+                         records (A or AAAA)"
+                            .to_string()],
+                   messages(&findings));
+    }
+
+    #[test]
+    fn out_of_zone() {
+        let findings = check(&format!("{}www.example.net. A 192.0.2.1\n", APEX));
+        assert_eq!(vec!["test.zone:5:1: error: www.example.net. is out of zone example.com."
+                            .to_string()],
+                   messages(&findings));
+    }
+
+    #[test]
+    fn ttl_mismatch() {
+        let findings = check(&format!("{}www 60 A 192.0.2.1\nwww 120 A 192.0.2.2\n", APEX));
+        assert_eq!(vec!["test.zone:6:1: warning: TTL 120 differs from 60 for the \
+                         www.example.com. A RRset"
+                            .to_string()],
+                   messages(&findings));
+        assert!(!has_errors(&findings));
+    }
+
+    #[test]
+    fn ds_at_apex() {
+        let findings = check(&format!("{}@ DS 1 8 2 abcd\n", APEX));
+        assert_eq!(vec!["test.zone:5:1: error: DS record at the zone apex belongs in the \
+                         parent zone"
+                            .to_string()],
+                   messages(&findings));
+    }
+
+    #[test]
+    fn syntax_errors_are_findings() {
+        let findings = check_zone_file("testdata/missing.zone", &name("example.com."));
+        assert_eq!(1, findings.len());
+        assert_eq!(Severity::Error, findings[0].severity);
+    }
+}
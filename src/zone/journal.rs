@@ -0,0 +1,182 @@
+//! History of zone changes, kept so that secondaries can catch up with an
+//! incremental transfer (RFC 1995) instead of copying the whole zone.
+
+use std::collections::{HashSet, VecDeque};
+use protocol::{RData, Record};
+use protocol::rdata::TYPE_SOA;
+use super::store::Zone;
+
+/// How many changes a journal keeps before forgetting the oldest.
+pub const DEFAULT_JOURNAL_SIZE: usize = 64;
+
+/// The changes taking a zone from one SOA serial to the next, in the shape of
+/// one IXFR difference sequence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diff {
+    /// The SOA before the change.
+    pub from: Record,
+    /// The SOA after the change.
+    pub to: Record,
+    pub removed: Vec<Record>,
+    pub added: Vec<Record>,
+}
+
+fn serial(soa: &Record) -> u32 {
+    match soa.rdata {
+        RData::Soa(ref soa) => soa.serial,
+        _ => 0,
+    }
+}
+
+impl Diff {
+    /// The differences between two versions of a zone, or None if either
+    /// lacks an SOA.
+    pub fn between(old: &Zone, new: &Zone) -> Option<Diff> {
+        let (from, to) = match (old.soa_record(), new.soa_record()) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return None,
+        };
+        let old_records: HashSet<Record> = old.records()
+                                              .into_iter()
+                                              .filter(|r| r.rtype() != TYPE_SOA)
+                                              .collect();
+        let new_records: HashSet<Record> = new.records()
+                                              .into_iter()
+                                              .filter(|r| r.rtype() != TYPE_SOA)
+                                              .collect();
+        // Walk the canonical lists so the diff comes out in a stable order.
+        let removed = old.records()
+                         .into_iter()
+                         .filter(|r| r.rtype() != TYPE_SOA && !new_records.contains(r))
+                         .collect();
+        let added = new.records()
+                       .into_iter()
+                       .filter(|r| r.rtype() != TYPE_SOA && !old_records.contains(r))
+                       .collect();
+        Some(Diff {
+            from: from,
+            to: to,
+            removed: removed,
+            added: added,
+        })
+    }
+
+    pub fn from_serial(&self) -> u32 {
+        serial(&self.from)
+    }
+
+    pub fn to_serial(&self) -> u32 {
+        serial(&self.to)
+    }
+}
+
+/// A bounded list of consecutive diffs, oldest first.
+#[derive(Clone, Debug)]
+pub struct Journal {
+    diffs: VecDeque<Diff>,
+    capacity: usize,
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Journal {
+        Journal {
+            diffs: VecDeque::new(),
+            capacity: capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.diffs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diffs.is_empty()
+    }
+
+    /// Appends a diff. A diff that does not continue from the last one
+    /// breaks the history, so everything before it is dropped.
+    pub fn push(&mut self, diff: Diff) {
+        let continues = self.diffs.back().map(|last| last.to_serial() == diff.from_serial());
+        if continues == Some(false) {
+            self.diffs.clear();
+        }
+        self.diffs.push_back(diff);
+        while self.diffs.len() > self.capacity {
+            self.diffs.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.diffs.clear();
+    }
+
+    /// The diffs leading from `serial` to the newest version, or None if
+    /// the history no longer reaches back that far.
+    pub fn since(&self, serial: u32) -> Option<Vec<&Diff>> {
+        match self.diffs.iter().position(|diff| diff.from_serial() == serial) {
+            Some(start) => Some(self.diffs.iter().skip(start).collect()),
+            None => None,
+        }
+    }
+}
+
+impl Default for Journal {
+    fn default() -> Journal {
+        Journal::new(DEFAULT_JOURNAL_SIZE)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use protocol::Name;
+    use zone::{Zone, ZoneParser};
+
+    fn zone(serial: u32, extra: &str) -> Zone {
+        let text = format!("$TTL 300\n\
+                            @ SOA ns hostmaster {} 1 1 1 1\n\
+                            @ NS ns\n\
+                            ns A 192.0.2.1\n{}",
+                           serial,
+                           extra);
+        let origin = Name::parse("example.com.", None).unwrap();
+        let records = ZoneParser::new(Cursor::new(text.into_bytes()),
+                                      "test.zone",
+                                      Some(origin.clone()))
+                          .map(|r| r.unwrap());
+        Zone::from_records(origin, records)
+    }
+
+    #[test]
+    fn between() {
+        let old = zone(1, "a A 192.0.2.2\nb A 192.0.2.3\n");
+        let new = zone(2, "a A 192.0.2.2\nb 600 A 192.0.2.3\nc A 192.0.2.4\n");
+        let diff = Diff::between(&old, &new).unwrap();
+        assert_eq!((1, 2), (diff.from_serial(), diff.to_serial()));
+        assert_eq!(vec!["b.example.com. 300 IN A 192.0.2.3".to_string()],
+                   diff.removed.iter().map(|r| r.to_string()).collect::<Vec<_>>());
+        assert_eq!(vec!["b.example.com. 600 IN A 192.0.2.3".to_string(),
+                        "c.example.com. 300 IN A 192.0.2.4".to_string()],
+                   diff.added.iter().map(|r| r.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn history() {
+        let zones = [zone(1, ""), zone(2, ""), zone(3, ""), zone(4, "")];
+        let mut journal = Journal::new(2);
+        for pair in zones.windows(2) {
+            journal.push(Diff::between(&pair[0], &pair[1]).unwrap());
+        }
+        assert_eq!(2, journal.len());
+        assert!(journal.since(1).is_none());
+        assert_eq!(vec![2, 3],
+                   journal.since(2).unwrap().iter().map(|d| d.from_serial()).collect::<Vec<_>>());
+        assert_eq!(0, journal.since(4).map_or(0, |d| d.len()));
+
+        // A gap starts the history over.
+        journal.push(Diff::between(&zones[0], &zones[1]).unwrap());
+        assert_eq!(1, journal.len());
+    }
+}
@@ -0,0 +1,19 @@
+//! Master file (zone file) handling.
+
+mod check;
+mod journal;
+mod parser;
+mod store;
+mod update;
+mod writer;
+mod zonemd;
+
+pub use self::check::{Finding, Severity, check_zone, check_zone_file, has_errors};
+pub use self::journal::{DEFAULT_JOURNAL_SIZE, Diff, Journal};
+pub use self::parser::{Location, MAX_GENERATE, ParseError, ZoneParser};
+pub use self::store::{Lookup, RRset, Zone};
+pub use self::update::{apply_update, check_prerequisites, prescan};
+pub use self::writer::ZoneWriter;
+pub use self::zonemd::{HASH_SHA384, HASH_SHA512, SCHEME_SIMPLE, ZonemdError,
+                       hash_algorithm_from_name, refresh_zonemd, set_zonemd, verify_zonemd,
+                       zone_digest};
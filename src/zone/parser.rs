@@ -0,0 +1,929 @@
+//! Streaming parser for RFC 1035 Section 5 master files, with the BIND
+//! extensions `$TTL` (RFC 2308), `$GENERATE` and TTL unit suffixes.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use protocol::Name;
+use protocol::RData;
+use protocol::Record;
+use protocol::rdata::{CLASS_IN, TYPE_SOA, class_from_name, parse_ttl, type_from_name};
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Most records one `$GENERATE` may expand to, as in BIND.
+pub const MAX_GENERATE: u64 = 65535;
+
+/// Where in a master file something was found. Lines and columns count from 1.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub location: Location,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}: {}", self.location, self.message)
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    quoted: bool,
+    line: usize,
+    column: usize,
+}
+
+/// One logical entry: the tokens of a line, or of several lines joined by
+/// parentheses.
+#[derive(Clone, Debug)]
+struct Entry {
+    tokens: Vec<Token>,
+    // The entry began with whitespace, so it inherits the previous owner.
+    blank_owner: bool,
+    file: String,
+}
+
+impl Entry {
+    fn location(&self, token: usize) -> Location {
+        match self.tokens.get(token).or(self.tokens.last()) {
+            Some(t) => {
+                Location {
+                    file: self.file.clone(),
+                    line: t.line,
+                    column: t.column,
+                }
+            }
+            None => {
+                Location {
+                    file: self.file.clone(),
+                    line: 0,
+                    column: 0,
+                }
+            }
+        }
+    }
+
+    fn error(&self, token: usize, message: String) -> ParseError {
+        ParseError {
+            location: self.location(token),
+            message: message,
+        }
+    }
+}
+
+// A `$GENERATE` being expanded one record at a time.
+struct Generate {
+    entry: Entry,
+    // Token of the rdata template.
+    rhs: usize,
+    rtype: u16,
+    ttl: u32,
+    class: u16,
+    // The value of the next record, if there is one.
+    next: Option<u64>,
+    stop: u64,
+    step: u64,
+}
+
+struct Source {
+    reader: Box<BufRead>,
+    file: String,
+    line: usize,
+    // Origin of the including file, restored when this one is exhausted.
+    parent_origin: Option<Name>,
+}
+
+/// Reads records from a master file one at a time.
+///
+/// Errors do not end the stream: after an `Err` the parser resumes with the
+/// next entry, so callers can collect every problem in a file at once.
+pub struct ZoneParser {
+    sources: Vec<Source>,
+    origin: Option<Name>,
+    default_ttl: Option<u32>,
+    last_owner: Option<Name>,
+    last_ttl: Option<u32>,
+    last_class: u16,
+    generating: Option<Generate>,
+    location: Location,
+}
+
+impl ZoneParser {
+    /// Parses from any reader. `file` is only used in error locations.
+    pub fn new<R: BufRead + 'static>(reader: R, file: &str, origin: Option<Name>) -> ZoneParser {
+        ZoneParser {
+            sources: vec![Source {
+                              reader: Box::new(reader),
+                              file: file.to_string(),
+                              line: 0,
+                              parent_origin: None,
+                          }],
+            origin: origin,
+            default_ttl: None,
+            last_owner: None,
+            last_ttl: None,
+            last_class: CLASS_IN,
+            generating: None,
+            location: Location {
+                file: file.to_string(),
+                line: 0,
+                column: 0,
+            },
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, origin: Option<Name>) -> Result<ZoneParser, ParseError> {
+        let file = path.as_ref().to_string_lossy().into_owned();
+        match File::open(path.as_ref()) {
+            Ok(f) => Ok(ZoneParser::new(BufReader::new(f), &file, origin)),
+            Err(e) => {
+                Err(ParseError {
+                    location: Location {
+                        file: file,
+                        line: 0,
+                        column: 0,
+                    },
+                    message: format!("cannot open: {}", e),
+                })
+            }
+        }
+    }
+
+    /// The current `$ORIGIN`.
+    pub fn origin(&self) -> Option<&Name> {
+        self.origin.as_ref()
+    }
+
+    /// Location of the entry that produced the most recently returned record.
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    /// Reads the next logical entry, joining parenthesized continuation lines
+    /// and dropping comments.
+    fn next_entry(&mut self) -> Option<Result<Entry, ParseError>> {
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut blank_owner = false;
+        let mut depth = 0;
+        loop {
+            let mut line = String::new();
+            let (read, file, line_number) = match self.sources.last_mut() {
+                Some(source) => {
+                    let read = source.reader.read_line(&mut line);
+                    if let Ok(n) = read {
+                        if n > 0 {
+                            source.line += 1;
+                        }
+                    }
+                    (read, source.file.clone(), source.line)
+                }
+                None => return None,
+            };
+            let eof_error = |message: &str| {
+                ParseError {
+                    location: Location {
+                        file: file.clone(),
+                        line: line_number,
+                        column: 0,
+                    },
+                    message: message.to_string(),
+                }
+            };
+            match read {
+                Ok(0) => {
+                    self.pop_source();
+                    if depth > 0 {
+                        return Some(Err(eof_error("unbalanced parenthesis at end of file")));
+                    }
+                    continue;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.pop_source();
+                    return Some(Err(eof_error(&format!("read error: {}", e))));
+                }
+            }
+
+            let starts_blank = line.starts_with(' ') || line.starts_with('\t');
+            let had_tokens = !tokens.is_empty();
+            if let Err(e) = tokenize_line(&line, &file, line_number, &mut depth, &mut tokens) {
+                return Some(Err(e));
+            }
+            if !had_tokens && !tokens.is_empty() {
+                blank_owner = starts_blank;
+            }
+            if depth == 0 && !tokens.is_empty() {
+                return Some(Ok(Entry {
+                    tokens: tokens,
+                    blank_owner: blank_owner,
+                    file: file,
+                }));
+            }
+        }
+    }
+
+    fn pop_source(&mut self) {
+        if let Some(source) = self.sources.pop() {
+            if !self.sources.is_empty() {
+                self.origin = source.parent_origin;
+            }
+        }
+    }
+
+    fn process(&mut self, entry: Entry) -> Result<Option<Record>, ParseError> {
+        if !entry.blank_owner && !entry.tokens[0].quoted && entry.tokens[0].text.starts_with('$') {
+            try!(self.directive(&entry));
+            return Ok(None);
+        }
+        self.record(&entry).map(Some)
+    }
+
+    fn directive(&mut self, entry: &Entry) -> Result<(), ParseError> {
+        let args = &entry.tokens[1..];
+        match &entry.tokens[0].text.to_uppercase()[..] {
+            "$ORIGIN" => {
+                if args.len() != 1 {
+                    return Err(entry.error(0, "$ORIGIN takes exactly one name".to_string()));
+                }
+                match Name::parse(&args[0].text, self.origin.as_ref()) {
+                    Some(origin) => self.origin = Some(origin),
+                    None => return Err(entry.error(1, format!("invalid origin '{}'", args[0].text))),
+                }
+            }
+            "$TTL" => {
+                if args.len() != 1 {
+                    return Err(entry.error(0, "$TTL takes exactly one TTL".to_string()));
+                }
+                match parse_ttl(&args[0].text) {
+                    Some(ttl) => self.default_ttl = Some(ttl),
+                    None => return Err(entry.error(1, format!("invalid TTL '{}'", args[0].text))),
+                }
+            }
+            "$INCLUDE" => {
+                if args.is_empty() || args.len() > 2 {
+                    return Err(entry.error(0,
+                                           "$INCLUDE takes a file name and an optional origin"
+                                               .to_string()));
+                }
+                try!(self.include(entry));
+            }
+            "$GENERATE" => try!(self.generate(entry)),
+            other => return Err(entry.error(0, format!("unknown directive {}", other))),
+        }
+        Ok(())
+    }
+
+    fn include(&mut self, entry: &Entry) -> Result<(), ParseError> {
+        if self.sources.len() >= MAX_INCLUDE_DEPTH {
+            return Err(entry.error(0, "$INCLUDE nested too deeply".to_string()));
+        }
+        let mut path = PathBuf::from(&entry.tokens[1].text);
+        if path.is_relative() {
+            if let Some(dir) = Path::new(&entry.file).parent() {
+                path = dir.join(path);
+            }
+        }
+        let new_origin = match entry.tokens.get(2) {
+            Some(token) => {
+                match Name::parse(&token.text, self.origin.as_ref()) {
+                    Some(origin) => Some(origin),
+                    None => return Err(entry.error(2, format!("invalid origin '{}'", token.text))),
+                }
+            }
+            None => self.origin.clone(),
+        };
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                return Err(entry.error(1, format!("cannot open {}: {}", path.display(), e)));
+            }
+        };
+        self.sources.push(Source {
+            reader: Box::new(BufReader::new(file)),
+            file: path.to_string_lossy().into_owned(),
+            line: 0,
+            parent_origin: self.origin.clone(),
+        });
+        self.origin = new_origin;
+        Ok(())
+    }
+
+    /// Parses the optional TTL and class that may appear in either order
+    /// before the type, starting at token `i`. Returns the index of the type.
+    fn ttl_and_class(&self,
+                     entry: &Entry,
+                     mut i: usize)
+        -> Result<(Option<u32>, Option<u16>, usize), ParseError> {
+        let mut ttl = None;
+        let mut class = None;
+        for _ in 0..2 {
+            let text = match entry.tokens.get(i) {
+                Some(token) => &token.text,
+                None => return Err(entry.error(i, "missing type".to_string())),
+            };
+            if ttl.is_none() && text.starts_with(|c: char| c.is_digit(10)) {
+                match parse_ttl(text) {
+                    Some(value) => ttl = Some(value),
+                    None => return Err(entry.error(i, format!("invalid TTL '{}'", text))),
+                }
+                i += 1;
+            } else if class.is_none() && class_from_name(text).is_some() &&
+                      type_from_name(text).is_none() {
+                class = class_from_name(text);
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        Ok((ttl, class, i))
+    }
+
+    fn record(&mut self, entry: &Entry) -> Result<Record, ParseError> {
+        let mut i = 0;
+        let owner = if entry.blank_owner {
+            match self.last_owner {
+                Some(ref owner) => owner.clone(),
+                None => return Err(entry.error(0, "no previous owner to inherit".to_string())),
+            }
+        } else {
+            i += 1;
+            try!(self.name(entry, 0))
+        };
+        self.last_owner = Some(owner.clone());
+
+        let (ttl, class, i) = try!(self.ttl_and_class(entry, i));
+        let rtype = match entry.tokens.get(i).and_then(|t| type_from_name(&t.text)) {
+            Some(rtype) => rtype,
+            None => {
+                let text = entry.tokens.get(i).map(|t| &t.text[..]).unwrap_or("");
+                return Err(entry.error(i, format!("unknown type '{}'", text)));
+            }
+        };
+        let class = class.unwrap_or(self.last_class);
+        let fields: Vec<&str> = entry.tokens[i + 1..].iter().map(|t| &t.text[..]).collect();
+        let rdata = match RData::parse(rtype, &fields, self.origin.as_ref()) {
+            Ok(rdata) => rdata,
+            Err(message) => return Err(entry.error(i + 1, message)),
+        };
+
+        let ttl = match (ttl, self.default_ttl, self.last_ttl, &rdata) {
+            (Some(ttl), _, _, _) => ttl,
+            (None, Some(ttl), _, _) => ttl,
+            (None, None, Some(ttl), _) => ttl,
+            // Without any TTL in sight, BIND falls back to the SOA minimum.
+            (None, None, None, &RData::Soa(ref soa)) => soa.minimum,
+            _ => return Err(entry.error(0, "no TTL specified and no $TTL default".to_string())),
+        };
+        if rtype == TYPE_SOA && self.last_ttl.is_none() && self.default_ttl.is_none() {
+            self.default_ttl = Some(ttl);
+        }
+        self.last_ttl = Some(ttl);
+        self.last_class = class;
+        self.location = entry.location(0);
+        Ok(Record::new(owner, class, ttl, rdata))
+    }
+
+    fn name(&self, entry: &Entry, token: usize) -> Result<Name, ParseError> {
+        let text = &entry.tokens[token].text;
+        match Name::parse(text, self.origin.as_ref()) {
+            Some(name) => Ok(name),
+            None if self.origin.is_none() && !text.ends_with('.') => {
+                Err(entry.error(token, format!("relative name '{}' without $ORIGIN", text)))
+            }
+            None => Err(entry.error(token, format!("invalid name '{}'", text))),
+        }
+    }
+
+    /// Expands `$GENERATE range lhs [ttl] [class] type rhs`.
+    fn generate(&mut self, entry: &Entry) -> Result<(), ParseError> {
+        if entry.tokens.len() < 5 {
+            return Err(entry.error(0,
+                                   "$GENERATE needs a range, owner, type and rdata".to_string()));
+        }
+        let (start, stop, step) = match parse_range(&entry.tokens[1].text) {
+            Some(range) => range,
+            None => {
+                return Err(entry.error(1, format!("invalid range '{}'", entry.tokens[1].text)))
+            }
+        };
+        if (stop - start) / step >= MAX_GENERATE {
+            return Err(entry.error(1,
+                                   format!("$GENERATE makes more than {} records", MAX_GENERATE)));
+        }
+        let (ttl, class, i) = try!(self.ttl_and_class(entry, 3));
+        let rtype = match entry.tokens.get(i).and_then(|t| type_from_name(&t.text)) {
+            Some(rtype) => rtype,
+            None => return Err(entry.error(i, "unknown type".to_string())),
+        };
+        if entry.tokens.len() != i + 2 {
+            return Err(entry.error(i + 1, "$GENERATE takes a single rdata field".to_string()));
+        }
+        let ttl = match ttl.or(self.default_ttl).or(self.last_ttl) {
+            Some(ttl) => ttl,
+            None => return Err(entry.error(0, "no TTL specified and no $TTL default".to_string())),
+        };
+        let class = class.unwrap_or(self.last_class);
+        self.location = entry.location(0);
+        self.generating = Some(Generate {
+            entry: entry.clone(),
+            rhs: i + 1,
+            rtype: rtype,
+            ttl: ttl,
+            class: class,
+            next: Some(start),
+            stop: stop,
+            step: step,
+        });
+        Ok(())
+    }
+
+    /// The next record of the `$GENERATE` being expanded, if any. An error
+    /// ends the expansion.
+    fn next_generated(&mut self) -> Option<Result<Record, ParseError>> {
+        let generate = match self.generating {
+            Some(ref mut generate) => generate,
+            None => return None,
+        };
+        let value = match generate.next {
+            Some(value) => value,
+            None => return None,
+        };
+        generate.next = match value.checked_add(generate.step) {
+            Some(next) if next <= generate.stop => Some(next),
+            _ => None,
+        };
+        let entry = &generate.entry;
+        let owner_text = match substitute(&entry.tokens[2].text, value) {
+            Ok(text) => text,
+            Err(message) => {
+                generate.next = None;
+                return Some(Err(entry.error(2, message)));
+            }
+        };
+        let owner = match Name::parse(&owner_text, self.origin.as_ref()) {
+            Some(owner) => owner,
+            None => {
+                generate.next = None;
+                return Some(Err(entry.error(2, format!("invalid name '{}'", owner_text))));
+            }
+        };
+        let origin = self.origin.as_ref();
+        let rtype = generate.rtype;
+        let rdata = substitute(&entry.tokens[generate.rhs].text, value)
+                        .and_then(|text| RData::parse(rtype, &[&text], origin));
+        match rdata {
+            Ok(rdata) => Some(Ok(Record::new(owner, generate.class, generate.ttl, rdata))),
+            Err(message) => {
+                generate.next = None;
+                Some(Err(entry.error(generate.rhs, message)))
+            }
+        }
+    }
+}
+
+impl Iterator for ZoneParser {
+    type Item = Result<Record, ParseError>;
+
+    fn next(&mut self) -> Option<Result<Record, ParseError>> {
+        loop {
+            match self.next_generated() {
+                Some(result) => return Some(result),
+                None => self.generating = None,
+            }
+            let entry = match self.next_entry() {
+                Some(Ok(entry)) => entry,
+                Some(Err(e)) => return Some(Err(e)),
+                None => return None,
+            };
+            match self.process(entry) {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Splits one physical line into tokens, tracking parenthesis depth across
+/// lines.
+fn tokenize_line(line: &str,
+                 file: &str,
+                 line_number: usize,
+                 depth: &mut usize,
+                 tokens: &mut Vec<Token>)
+    -> Result<(), ParseError> {
+    let error = |column: usize, message: &str| {
+        ParseError {
+            location: Location {
+                file: file.to_string(),
+                line: line_number,
+                column: column,
+            },
+            message: message.to_string(),
+        }
+    };
+    let mut current: Option<Token> = None;
+    let mut quoted = false;
+    let mut chars = line.trim_right_matches(|c| c == '\n' || c == '\r').chars().enumerate();
+    while let Some((index, c)) = chars.next() {
+        let column = index + 1;
+        if quoted {
+            let token = current.as_mut().unwrap();
+            match c {
+                '"' => quoted = false,
+                '\\' => {
+                    token.text.push(c);
+                    if let Some((_, escaped)) = chars.next() {
+                        token.text.push(escaped);
+                    }
+                }
+                _ => token.text.push(c),
+            }
+            continue;
+        }
+        match c {
+            ';' => break,
+            ' ' | '\t' | '(' | ')' | '"' => {
+                if let Some(token) = current.take() {
+                    tokens.push(token);
+                }
+                match c {
+                    '(' => *depth += 1,
+                    ')' => {
+                        if *depth == 0 {
+                            return Err(error(column, "unbalanced ')'"));
+                        }
+                        *depth -= 1;
+                    }
+                    '"' => {
+                        quoted = true;
+                        current = Some(Token {
+                            text: String::new(),
+                            quoted: true,
+                            line: line_number,
+                            column: column,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            _ => {
+                let token = current.get_or_insert(Token {
+                    text: String::new(),
+                    quoted: false,
+                    line: line_number,
+                    column: column,
+                });
+                token.text.push(c);
+                if c == '\\' {
+                    if let Some((_, escaped)) = chars.next() {
+                        token.text.push(escaped);
+                    }
+                }
+            }
+        }
+    }
+    if quoted {
+        let column = current.as_ref().map(|t| t.column).unwrap_or(0);
+        return Err(error(column, "unterminated quoted string"));
+    }
+    if let Some(token) = current.take() {
+        tokens.push(token);
+    }
+    Ok(())
+}
+
+/// Parses a `$GENERATE` range of the form `start-stop[/step]`.
+fn parse_range(text: &str) -> Option<(u64, u64, u64)> {
+    let (range, step) = match text.find('/') {
+        Some(slash) => (&text[..slash], text[slash + 1..].parse::<u64>().ok()),
+        None => (text, Some(1)),
+    };
+    let dash = match range.find('-') {
+        Some(dash) => dash,
+        None => return None,
+    };
+    match (range[..dash].parse::<u64>(), range[dash + 1..].parse::<u64>(), step) {
+        (Ok(start), Ok(stop), Some(step)) if start <= stop && step > 0 => {
+            Some((start, stop, step))
+        }
+        _ => None,
+    }
+}
+
+/// Replaces `$` and `${offset[,width[,base]]}` in a `$GENERATE` template.
+/// `\$` produces a literal dollar sign; other escapes are left for the name
+/// and rdata parsers.
+fn substitute(template: &str, value: u64) -> Result<String, String> {
+    let mut result = String::with_capacity(template.len() + 8);
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                match chars.next() {
+                    Some('$') => result.push('$'),
+                    Some(escaped) => {
+                        result.push('\\');
+                        result.push(escaped);
+                    }
+                    None => result.push('\\'),
+                }
+            }
+            '$' => {
+                if chars.peek() != Some(&'{') {
+                    result.push_str(&value.to_string());
+                    continue;
+                }
+                chars.next();
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err("unterminated ${...} modifier".to_string()),
+                    }
+                }
+                result.push_str(&try!(format_modifier(&spec, value)));
+            }
+            _ => result.push(c),
+        }
+    }
+    Ok(result)
+}
+
+fn format_modifier(spec: &str, value: u64) -> Result<String, String> {
+    let parts: Vec<&str> = spec.split(',').collect();
+    if parts.len() > 3 {
+        return Err(format!("invalid modifier '{}'", spec));
+    }
+    let offset = match parts[0].parse::<i64>() {
+        Ok(offset) => offset,
+        Err(_) => return Err(format!("invalid offset in '{}'", spec)),
+    };
+    let width = match parts.get(1).map(|w| w.parse::<usize>()) {
+        Some(Ok(width)) => width,
+        Some(Err(_)) => return Err(format!("invalid width in '{}'", spec)),
+        None => 0,
+    };
+    let base = parts.get(2).cloned().unwrap_or("d");
+    let n = if offset < 0 {
+        value.checked_sub(offset.wrapping_neg() as u64)
+    } else {
+        value.checked_add(offset as u64)
+    };
+    let n = match n {
+        Some(n) => n,
+        None => return Err(format!("offset in '{}' takes the value out of range", spec)),
+    };
+    Ok(match base {
+        "d" => format!("{:01$}", n, width),
+        "o" => format!("{:01$o}", n, width),
+        "x" => format!("{:01$x}", n, width),
+        "X" => format!("{:01$X}", n, width),
+        "n" | "N" => {
+            // Nibble format for reverse IPv6 zones: least significant nibble
+            // first, separated by dots, padded to at least `width` characters.
+            let digits = if base == "n" {
+                format!("{:x}", n)
+            } else {
+                format!("{:X}", n)
+            };
+            let mut nibbles: Vec<String> = digits.chars().rev().map(|c| c.to_string()).collect();
+            while nibbles.len() * 2 - 1 < width {
+                nibbles.push("0".to_string());
+            }
+            nibbles.join(".")
+        }
+        _ => return Err(format!("invalid base in '{}'", spec)),
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use protocol::{Name, RData, Record};
+    use protocol::rdata::{CLASS_CH, CLASS_IN};
+
+    fn name(text: &str) -> Name {
+        Name::parse(text, None).unwrap()
+    }
+
+    fn parse(text: &str) -> Vec<Result<Record, ParseError>> {
+        ZoneParser::new(Cursor::new(text.as_bytes().to_vec()),
+                        "test.zone",
+                        Some(name("example.com.")))
+            .collect()
+    }
+
+    fn parse_ok(text: &str) -> Vec<Record> {
+        parse(text).into_iter().map(|r| r.unwrap()).collect()
+    }
+
+    #[test]
+    fn soa_with_parentheses_and_comments() {
+        let records = parse_ok("$TTL 1h\n\
+                                @ IN SOA ns1 hostmaster ( ; comment\n\
+                                \t2015110801 ; serial\n\
+                                \t2h 1h 2w\n\
+                                \t5m )\n");
+        assert_eq!(1, records.len());
+        assert_eq!(name("example.com."), records[0].name);
+        assert_eq!(3600, records[0].ttl);
+        match records[0].rdata {
+            RData::Soa(ref soa) => {
+                assert_eq!(name("ns1.example.com."), soa.mname);
+                assert_eq!(2015110801, soa.serial);
+                assert_eq!(7200, soa.refresh);
+                assert_eq!(1209600, soa.expire);
+                assert_eq!(300, soa.minimum);
+            }
+            ref other => panic!("not an SOA: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn inheritance() {
+        let records = parse_ok("www 300 IN A 192.0.2.1\n\
+                                \tA 192.0.2.2\n\
+                                \t600 AAAA 2001:db8::1\n\
+                                mail CH TXT \"x\"\n\
+                                \tTXT y\n");
+        assert_eq!(5, records.len());
+        assert_eq!(name("www.example.com."), records[1].name);
+        assert_eq!(300, records[1].ttl);
+        assert_eq!(name("www.example.com."), records[2].name);
+        assert_eq!(600, records[2].ttl);
+        assert_eq!(CLASS_IN, records[2].class);
+        assert_eq!(600, records[3].ttl);
+        assert_eq!(CLASS_CH, records[3].class);
+        assert_eq!(name("mail.example.com."), records[4].name);
+        assert_eq!(CLASS_CH, records[4].class);
+    }
+
+    #[test]
+    fn class_before_ttl() {
+        let records = parse_ok("a IN 42 A 192.0.2.1\n");
+        assert_eq!(42, records[0].ttl);
+        assert_eq!(CLASS_IN, records[0].class);
+    }
+
+    #[test]
+    fn origin_directive() {
+        let records = parse_ok("$TTL 60\n\
+                                $ORIGIN sub\n\
+                                a A 192.0.2.1\n\
+                                $ORIGIN other.test.\n\
+                                @ NS ns.example.com.\n");
+        assert_eq!(name("a.sub.example.com."), records[0].name);
+        assert_eq!(name("other.test."), records[1].name);
+    }
+
+    #[test]
+    fn quoted_strings() {
+        let records = parse_ok("$TTL 60\n\
+                                t TXT \"semi;colon\" \"paren (\" \"a \\\"quote\\\"\"\n");
+        assert_eq!(RData::Txt(vec![b"semi;colon".to_vec(),
+                                   b"paren (".to_vec(),
+                                   b"a \"quote\"".to_vec()]),
+                   records[0].rdata);
+    }
+
+    #[test]
+    fn generate() {
+        let records = parse_ok("$TTL 60\n\
+                                $GENERATE 1-3 host-$ A 10.0.0.$\n\
+                                $GENERATE 0-8/4 ${10,3} CNAME ${0,2,x}.hex\n\
+                                $GENERATE 1-1 x\\$$ TXT a\n");
+        assert_eq!(7, records.len());
+        assert_eq!(name("host-1.example.com."), records[0].name);
+        assert_eq!(RData::A("10.0.0.3".parse().unwrap()), records[2].rdata);
+        assert_eq!(name("014.example.com."), records[4].name);
+        assert_eq!(RData::Cname(name("08.hex.example.com.")), records[5].rdata);
+        assert_eq!(name("x$1.example.com."), records[6].name);
+    }
+
+    #[test]
+    fn generate_limits() {
+        let text = "$TTL 60\n$GENERATE 18446744073709551613-18446744073709551615 h$ A 10.0.0.1\n";
+        let records = parse_ok(text);
+        assert_eq!(name("h18446744073709551615.example.com."), records[2].name);
+
+        let text = "$TTL 60\n$GENERATE 0-65535 h$ A 10.0.0.1\nafter A 10.0.0.2\n";
+        let results: Vec<_> = ZoneParser::new(Cursor::new(text.as_bytes().to_vec()),
+                                              "test.zone",
+                                              Some(name("example.com.")))
+                                  .collect();
+        assert_eq!(2, results.len());
+        assert_eq!(2, results[0].as_ref().unwrap_err().location.line);
+
+        // Expanded as read, and ended by the first bad record.
+        let text = "$TTL 60\n$GENERATE 0-65534 h$ A 10.0.${-1}\n";
+        let mut parser = ZoneParser::new(Cursor::new(text.as_bytes().to_vec()),
+                                         "test.zone",
+                                         Some(name("example.com.")));
+        assert!(parser.next().unwrap().is_err());
+        assert!(parser.next().is_none());
+        let text = "$TTL 60\n$GENERATE 0-65534 h$ A 10.0.0.1\n";
+        let mut parser = ZoneParser::new(Cursor::new(text.as_bytes().to_vec()),
+                                         "test.zone",
+                                         Some(name("example.com.")));
+        assert_eq!(name("h0.example.com."), parser.next().unwrap().unwrap().name);
+        assert_eq!(65534, parser.count());
+    }
+
+    #[test]
+    fn nibble_modifier() {
+        assert_eq!(Ok("f.0.0".to_string()), format_modifier("0,5,n", 0xf));
+        assert_eq!(Ok("A.B".to_string()), format_modifier("0,0,N", 0xba));
+        assert!(format_modifier("-5", 1).is_err());
+    }
+
+    #[test]
+    fn include() {
+        let mut parser = ZoneParser::open("testdata/include-parent.zone",
+                                          Some(name("example.com.")))
+                             .unwrap();
+        let records: Vec<Record> = parser.by_ref().map(|r| r.unwrap()).collect();
+        let names: Vec<String> = records.iter().map(|r| r.name.to_string()).collect();
+        assert_eq!(vec!["before.example.com.",
+                        "child.sub.example.com.",
+                        "sub.example.com.",
+                        "after.example.com."],
+                   names);
+        assert_eq!(Some(&name("example.com.")), parser.origin());
+    }
+
+    #[test]
+    fn error_locations() {
+        let results = parse("$TTL 60\n\
+                             ok A 192.0.2.1\n\
+                             bad A 192.0.2.300\n\
+                             \n\
+                             worse (\n  \
+                             BOGUS x )\n\
+                             fine A 192.0.2.2\n");
+        assert_eq!(4, results.len());
+        assert!(results[0].is_ok());
+        let e = results[1].clone().unwrap_err();
+        assert_eq!(Location {
+                       file: "test.zone".to_string(),
+                       line: 3,
+                       column: 7,
+                   },
+                   e.location);
+        let e = results[2].clone().unwrap_err();
+        assert_eq!((6, 3), (e.location.line, e.location.column));
+        assert_eq!("test.zone:6:3: unknown type 'BOGUS'", e.to_string());
+        assert!(results[3].is_ok());
+    }
+
+    #[test]
+    fn missing_ttl() {
+        let results = parse("a A 192.0.2.1\n");
+        assert!(results[0].is_err());
+    }
+
+    #[test]
+    fn unbalanced() {
+        let results = parse("$TTL 60\na A ( 192.0.2.1\n");
+        assert_eq!("unbalanced parenthesis at end of file",
+                   results[0].clone().unwrap_err().message);
+        let results = parse("$TTL 60\na A 192.0.2.1 )\n");
+        assert!(results[0].is_err());
+    }
+
+    #[test]
+    fn relative_without_origin() {
+        let results: Vec<_> = ZoneParser::new(Cursor::new(b"a 60 A 192.0.2.1\n".to_vec()),
+                                              "t",
+                                              None)
+                                  .collect();
+        assert_eq!("relative name 'a' without $ORIGIN",
+                   results[0].clone().unwrap_err().message);
+    }
+}
@@ -0,0 +1,809 @@
+//! In-memory authoritative data for one zone, answering queries with the
+//! algorithm of RFC 1034 Section 4.3.2.
+//!
+//! Names are kept in a tree keyed by label, starting at the zone apex and
+//! descending right to left, so a lookup costs one map probe per label no
+//! matter how many names the zone holds.
+
+use std::ascii::AsciiExt;
+use std::collections::BTreeMap;
+use std::path::Path;
+use protocol::{HeaderMut, MessageCursor, Name, RData, Record, Soa};
+use protocol::{RC_NAME_ERROR, RC_OK, RC_REFUSED, RC_SERVER_ERROR};
+use protocol::rdata::{CLASS_IN, TYPE_A, TYPE_AAAA, TYPE_ANY, TYPE_CNAME, TYPE_DS, TYPE_NS,
+                      TYPE_SOA};
+use super::journal::{Diff, Journal};
+use super::parser::{Location, ParseError, ZoneParser};
+
+const MAX_CNAME_CHAIN: usize = 16;
+const WILDCARD: &'static [u8] = b"*";
+
+/// The records of one owner and type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RRset {
+    pub ttl: u32,
+    pub rdatas: Vec<RData>,
+}
+
+#[derive(Clone)]
+struct Node {
+    name: Name,
+    // Keyed by lowercased label, which also keeps children in canonical order.
+    children: BTreeMap<Vec<u8>, Node>,
+    rrsets: BTreeMap<u16, RRset>,
+}
+
+impl Node {
+    fn new(name: Name) -> Node {
+        Node {
+            name: name,
+            children: BTreeMap::new(),
+            rrsets: BTreeMap::new(),
+        }
+    }
+
+    fn records(&self, owner: &Name, class: u16, rtype: u16) -> Vec<Record> {
+        match self.rrsets.get(&rtype) {
+            Some(rrset) => {
+                rrset.rdatas
+                     .iter()
+                     .map(|rdata| Record::new(owner.clone(), class, rrset.ttl, rdata.clone()))
+                     .collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn is_cut(&self) -> bool {
+        self.rrsets.contains_key(&TYPE_NS)
+    }
+}
+
+/// What the tree holds for a name, before CNAME and negative processing.
+enum Found<'a> {
+    // The node for the name itself, or the wildcard that matched it.
+    Node(&'a Node),
+    // A zone cut at or above the name.
+    Referral(&'a Node),
+    NxDomain,
+}
+
+/// The outcome of a lookup, ready to be composed into a response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lookup {
+    pub rcode: u8,
+    pub authoritative: bool,
+    pub answer: Vec<Record>,
+    pub authority: Vec<Record>,
+    pub additional: Vec<Record>,
+}
+
+impl Lookup {
+    fn new() -> Lookup {
+        Lookup {
+            rcode: RC_OK,
+            authoritative: true,
+            answer: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+        }
+    }
+
+    /// Whether this is a referral to a child zone rather than an answer.
+    pub fn is_referral(&self) -> bool {
+        !self.authoritative && self.rcode == RC_OK && self.answer.is_empty() &&
+        self.authority.iter().any(|r| r.rtype() == TYPE_NS)
+    }
+
+    /// Composes the answer, authority and additional sections at the cursor,
+    /// which must be just past the question, and fills in the header's
+    /// counts, AA and RCODE. Records that do not fit are left out; if that
+    /// affects the answer or authority sections TC is set and false returned.
+    pub fn write_sections(&self, idx: &mut MessageCursor, data: &mut [u8]) -> bool {
+        let (an, complete) = write_section(&self.answer, idx, data);
+        let (ns, complete) = if complete {
+            write_section(&self.authority, idx, data)
+        } else {
+            (0, false)
+        };
+        // Missing additional data does not call for truncation (RFC 2181
+        // Section 9).
+        let ar = if complete {
+            write_section(&self.additional, idx, data).0
+        } else {
+            0
+        };
+        HeaderMut::at_raw(data)
+            .set_aa(self.authoritative)
+            .set_rc(self.rcode)
+            .set_tc(!complete)
+            .set_an(an)
+            .set_ns(ns)
+            .set_ar(ar);
+        complete
+    }
+}
+
+fn write_section(records: &[Record], idx: &mut MessageCursor, data: &mut [u8]) -> (u16, bool) {
+    let mut count = 0;
+    for record in records {
+        let start = idx.tell();
+        if record.write_at(idx, data).is_none() {
+            idx.rewind(start);
+            return (count, false);
+        }
+        count += 1;
+    }
+    (count, true)
+}
+
+fn lowercase(label: &[u8]) -> Vec<u8> {
+    label.iter().map(|c| c.to_ascii_lowercase()).collect()
+}
+
+#[derive(Clone)]
+pub struct Zone {
+    origin: Name,
+    class: u16,
+    apex: Node,
+    journal: Journal,
+}
+
+impl Zone {
+    /// An empty IN class zone.
+    pub fn new(origin: Name) -> Zone {
+        Zone {
+            apex: Node::new(origin.clone()),
+            origin: origin,
+            class: CLASS_IN,
+            journal: Journal::default(),
+        }
+    }
+
+    /// Builds a zone from records, skipping any outside of it.
+    pub fn from_records<I: IntoIterator<Item = Record>>(origin: Name, records: I) -> Zone {
+        let mut zone = Zone::new(origin);
+        for record in records {
+            zone.insert(record);
+        }
+        zone
+    }
+
+    /// Loads a zone file. Out-of-zone records and a missing SOA are errors.
+    pub fn load<P: AsRef<Path>>(path: P, origin: &Name) -> Result<Zone, ParseError> {
+        let mut parser = try!(ZoneParser::open(path.as_ref(), Some(origin.clone())));
+        let mut zone = Zone::new(origin.clone());
+        while let Some(result) = parser.next() {
+            let record = try!(result);
+            if !record.name.is_subdomain_of(origin) || record.class != zone.class {
+                return Err(ParseError {
+                    location: parser.location().clone(),
+                    message: format!("{} is out of zone {}", record.name, origin),
+                });
+            }
+            zone.insert(record);
+        }
+        if zone.soa().is_none() {
+            return Err(ParseError {
+                location: Location {
+                    file: path.as_ref().to_string_lossy().into_owned(),
+                    line: 0,
+                    column: 0,
+                },
+                message: format!("{} has no SOA record", origin),
+            });
+        }
+        Ok(zone)
+    }
+
+    pub fn origin(&self) -> &Name {
+        &self.origin
+    }
+
+    pub fn class(&self) -> u16 {
+        self.class
+    }
+
+    pub fn soa(&self) -> Option<Soa> {
+        match self.apex.rrsets.get(&TYPE_SOA).and_then(|rrset| rrset.rdatas.first()) {
+            Some(&RData::Soa(ref soa)) => Some(soa.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn soa_record(&self) -> Option<Record> {
+        self.apex.records(&self.origin, self.class, TYPE_SOA).pop()
+    }
+
+    pub fn serial(&self) -> Option<u32> {
+        self.soa().map(|soa| soa.serial)
+    }
+
+    /// Lowercased labels of `name` below the apex, nearest the apex first.
+    fn path(&self, name: &Name) -> Option<Vec<Vec<u8>>> {
+        if !name.is_subdomain_of(&self.origin) {
+            return None;
+        }
+        let depth = name.label_count() - self.origin.label_count();
+        Some(name.labels()[..depth].iter().rev().map(|l| lowercase(l)).collect())
+    }
+
+    /// The node for exactly `name`, looking through zone cuts.
+    fn node(&self, name: &Name) -> Option<&Node> {
+        let path = match self.path(name) {
+            Some(path) => path,
+            None => return None,
+        };
+        let mut node = &self.apex;
+        for label in path {
+            match node.children.get(&label) {
+                Some(child) => node = child,
+                None => return None,
+            }
+        }
+        Some(node)
+    }
+
+    fn node_mut(&mut self, name: &Name) -> Option<&mut Node> {
+        let path = match self.path(name) {
+            Some(path) => path,
+            None => return None,
+        };
+        let depth = path.len();
+        let mut node = &mut self.apex;
+        for (i, label) in path.into_iter().enumerate() {
+            let child_name = name.labels()[depth - i - 1].clone();
+            let parent = node.name.clone();
+            node = node.children
+                       .entry(label)
+                       .or_insert_with(|| Node::new(parent.prepend(&child_name).unwrap()));
+        }
+        Some(node)
+    }
+
+    /// Adds a record. Returns false if it is outside the zone or already
+    /// present.
+    pub fn insert(&mut self, record: Record) -> bool {
+        if record.class != self.class {
+            return false;
+        }
+        let rtype = record.rtype();
+        let node = match self.node_mut(&record.name) {
+            Some(node) => node,
+            None => return false,
+        };
+        let rrset = node.rrsets.entry(rtype).or_insert(RRset {
+            ttl: record.ttl,
+            rdatas: Vec::new(),
+        });
+        if rrset.rdatas.contains(&record.rdata) {
+            return false;
+        }
+        if record.ttl < rrset.ttl {
+            rrset.ttl = record.ttl;
+        }
+        rrset.rdatas.push(record.rdata);
+        true
+    }
+
+    /// Applies `edit` to the RRsets of an existing name, then drops the
+    /// name's node, and any parents left empty, if nothing remains there.
+    fn edit<F>(&mut self, name: &Name, edit: F) -> bool
+        where F: FnOnce(&mut BTreeMap<u16, RRset>) -> bool
+    {
+        fn descend<F>(node: &mut Node, path: &[Vec<u8>], edit: F) -> bool
+            where F: FnOnce(&mut BTreeMap<u16, RRset>) -> bool
+        {
+            if path.is_empty() {
+                return edit(&mut node.rrsets);
+            }
+            let (changed, empty) = match node.children.get_mut(&path[0]) {
+                Some(child) => {
+                    let changed = descend(child, &path[1..], edit);
+                    (changed, child.rrsets.is_empty() && child.children.is_empty())
+                }
+                None => return false,
+            };
+            if empty {
+                node.children.remove(&path[0]);
+            }
+            changed
+        }
+        match self.path(name) {
+            Some(path) => descend(&mut self.apex, &path, edit),
+            None => false,
+        }
+    }
+
+    /// Removes a record, ignoring its TTL. Returns false if it was not there.
+    pub fn remove(&mut self, record: &Record) -> bool {
+        if record.class != self.class {
+            return false;
+        }
+        let rtype = record.rtype();
+        self.edit(&record.name, |rrsets| {
+            let (removed, empty) = match rrsets.get_mut(&rtype) {
+                Some(rrset) => {
+                    let before = rrset.rdatas.len();
+                    rrset.rdatas.retain(|rdata| *rdata != record.rdata);
+                    (rrset.rdatas.len() < before, rrset.rdatas.is_empty())
+                }
+                None => return false,
+            };
+            if empty {
+                rrsets.remove(&rtype);
+            }
+            removed
+        })
+    }
+
+    /// Removes every record of one type at `name`. Returns false if there
+    /// were none.
+    pub fn remove_rrset(&mut self, name: &Name, rtype: u16) -> bool {
+        self.edit(name, |rrsets| rrsets.remove(&rtype).is_some())
+    }
+
+    /// The types of the records at exactly `name`; empty when the name is
+    /// not in use.
+    pub fn rtypes(&self, name: &Name) -> Vec<u16> {
+        match self.node(name) {
+            Some(node) => node.rrsets.keys().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub fn journal_mut(&mut self) -> &mut Journal {
+        &mut self.journal
+    }
+
+    /// Applies a diff made against the current serial and records it in the
+    /// journal. Returns false, changing nothing, if the serial does not match.
+    pub fn apply(&mut self, diff: Diff) -> bool {
+        if self.serial() != Some(diff.from_serial()) {
+            return false;
+        }
+        for record in &diff.removed {
+            self.remove(record);
+        }
+        for record in &diff.added {
+            self.insert(record.clone());
+        }
+        self.apex.rrsets.remove(&TYPE_SOA);
+        self.insert(diff.to.clone());
+        self.journal.push(diff);
+        true
+    }
+
+    /// Replaces the contents with those of a newer version of the zone,
+    /// keeping the journal so the change can be served incrementally.
+    pub fn replace(&mut self, newer: Zone) {
+        match Diff::between(self, &newer) {
+            Some(ref diff) if diff.from_serial() != diff.to_serial() => {
+                self.journal.push(diff.clone());
+            }
+            _ => self.journal.clear(),
+        }
+        self.apex = newer.apex;
+    }
+
+    pub fn rrset(&self, name: &Name, rtype: u16) -> Option<&RRset> {
+        self.node(name).and_then(|node| node.rrsets.get(&rtype))
+    }
+
+    /// Every record in the zone, owners in canonical order.
+    pub fn records(&self) -> Vec<Record> {
+        let mut records = Vec::new();
+        let mut stack = vec![&self.apex];
+        while let Some(node) = stack.pop() {
+            for &rtype in node.rrsets.keys() {
+                records.extend(node.records(&node.name, self.class, rtype));
+            }
+            // Reversed so that the first child is visited first.
+            stack.extend(node.children.values().rev());
+        }
+        records
+    }
+
+    /// Walks down towards `name`, stopping at zone cuts and falling back to
+    /// a wildcard at the closest encloser.
+    fn find(&self, name: &Name, qtype: u16) -> Found {
+        let path = match self.path(name) {
+            Some(path) => path,
+            None => return Found::NxDomain,
+        };
+        let mut node = &self.apex;
+        for (i, label) in path.iter().enumerate() {
+            match node.children.get(label) {
+                Some(child) => {
+                    node = child;
+                    // The DS at a cut belongs to this (the parent) side.
+                    let at_name = i + 1 == path.len();
+                    if node.is_cut() && !(at_name && qtype == TYPE_DS) {
+                        return Found::Referral(node);
+                    }
+                }
+                None => {
+                    return match node.children.get(WILDCARD) {
+                        Some(wildcard) => Found::Node(wildcard),
+                        None => Found::NxDomain,
+                    };
+                }
+            }
+        }
+        Found::Node(node)
+    }
+
+    fn negative(&self, result: &mut Lookup) {
+        if let Some(mut soa) = self.soa_record() {
+            // RFC 2308 Section 3: the lesser of the SOA TTL and minimum.
+            if let RData::Soa(ref data) = soa.rdata {
+                soa.ttl = soa.ttl.min(data.minimum);
+            }
+            result.authority.push(soa);
+        }
+    }
+
+    /// Adds the addresses of in-zone `targets` to the additional section,
+    /// including glue below zone cuts.
+    fn add_addresses(&self, result: &mut Lookup, targets: Vec<Name>) {
+        for target in targets {
+            if let Some(node) = self.node(&target) {
+                for &rtype in &[TYPE_A, TYPE_AAAA] {
+                    for record in node.records(&target, self.class, rtype) {
+                        if !result.additional.contains(&record) &&
+                           !result.answer.contains(&record) {
+                            result.additional.push(record);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Answers a query for `qname` and `qtype` from this zone.
+    pub fn lookup(&self, qname: &Name, qtype: u16) -> Lookup {
+        let mut result = Lookup::new();
+        if !qname.is_subdomain_of(&self.origin) {
+            result.rcode = RC_REFUSED;
+            result.authoritative = false;
+            return result;
+        }
+        let mut name = qname.clone();
+        for _ in 0..MAX_CNAME_CHAIN {
+            let node = match self.find(&name, qtype) {
+                Found::Node(node) => node,
+                Found::Referral(cut) => {
+                    // Only the part of a CNAME chain already answered is ours.
+                    result.authoritative = !result.answer.is_empty();
+                    let ns = cut.records(&cut.name, self.class, TYPE_NS);
+                    let targets = ns.iter()
+                                    .filter_map(|r| {
+                                        match r.rdata {
+                                            RData::Ns(ref target) => Some(target.clone()),
+                                            _ => None,
+                                        }
+                                    })
+                                    .collect();
+                    result.authority.extend(ns);
+                    self.add_addresses(&mut result, targets);
+                    return result;
+                }
+                Found::NxDomain => {
+                    result.rcode = RC_NAME_ERROR;
+                    self.negative(&mut result);
+                    return result;
+                }
+            };
+
+            if qtype != TYPE_CNAME && qtype != TYPE_ANY {
+                if let Some(&RData::Cname(ref target)) = node.rrsets
+                                                             .get(&TYPE_CNAME)
+                                                             .and_then(|r| r.rdatas.first()) {
+                    result.answer.extend(node.records(&name, self.class, TYPE_CNAME));
+                    if !target.is_subdomain_of(&self.origin) {
+                        return result;
+                    }
+                    name = target.clone();
+                    continue;
+                }
+            }
+
+            let found = if qtype == TYPE_ANY {
+                node.rrsets
+                    .keys()
+                    .flat_map(|&rtype| node.records(&name, self.class, rtype))
+                    .collect::<Vec<Record>>()
+            } else {
+                node.records(&name, self.class, qtype)
+            };
+            if found.is_empty() {
+                self.negative(&mut result);
+                return result;
+            }
+            let targets = found.iter()
+                               .filter_map(|r| {
+                                   match r.rdata {
+                                       RData::Ns(ref target) |
+                                       RData::Mx { exchange: ref target, .. } |
+                                       RData::Srv { ref target, .. } => Some(target.clone()),
+                                       _ => None,
+                                   }
+                               })
+                               .collect();
+            result.answer.extend(found);
+            self.add_addresses(&mut result, targets);
+            return result;
+        }
+        // CNAME chain too long or looping.
+        result.rcode = RC_SERVER_ERROR;
+        result
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use protocol::{Header, MessageCursor, Name, QuestionMut, RData, Record, Resource};
+    use protocol::{RC_NAME_ERROR, RC_OK, RC_REFUSED};
+    use protocol::rdata::*;
+    use zone::{Diff, ZoneParser};
+
+    fn name(text: &str) -> Name {
+        Name::parse(text, None).unwrap()
+    }
+
+    const ZONE: &'static str = "$TTL 300\n\
+                                @ SOA ns1 hostmaster 1 7200 3600 1209600 60\n\
+                                @ NS ns1\n\
+                                @ MX 10 mail\n\
+                                ns1 A 192.0.2.53\n\
+                                mail A 192.0.2.25\n\
+                                mail AAAA 2001:db8::25\n\
+                                www CNAME web\n\
+                                web A 192.0.2.80\n\
+                                ext CNAME www.example.net.\n\
+                                loop1 CNAME loop2\n\
+                                loop2 CNAME loop1\n\
+                                a.b.c TXT deep\n\
+                                *.wild TXT wildcard\n\
+                                *.wild MX 5 mail\n\
+                                exists.wild A 192.0.2.9\n\
+                                sub NS ns.sub\n\
+                                sub NS ns.example.net.\n\
+                                sub DS 1 8 2 abcd\n\
+                                ns.sub A 192.0.2.99\n";
+
+    fn zone() -> Zone {
+        let origin = name("example.com.");
+        let records = ZoneParser::new(Cursor::new(ZONE.as_bytes().to_vec()),
+                                      "test.zone",
+                                      Some(origin.clone()))
+                          .map(|r| r.unwrap());
+        Zone::from_records(origin, records)
+    }
+
+    fn owners_and_types(records: &[Record]) -> Vec<(String, u16)> {
+        records.iter().map(|r| (r.name.to_string(), r.rtype())).collect()
+    }
+
+    #[test]
+    fn exact_match_with_additional() {
+        let result = zone().lookup(&name("Example.COM."), TYPE_MX);
+        assert_eq!(RC_OK, result.rcode);
+        assert!(result.authoritative);
+        assert_eq!(vec![("Example.COM.".to_string(), TYPE_MX)],
+                   owners_and_types(&result.answer));
+        assert_eq!(vec![("mail.example.com.".to_string(), TYPE_A),
+                        ("mail.example.com.".to_string(), TYPE_AAAA)],
+                   owners_and_types(&result.additional));
+    }
+
+    #[test]
+    fn cname_chain() {
+        let result = zone().lookup(&name("www.example.com."), TYPE_A);
+        assert_eq!(vec![("www.example.com.".to_string(), TYPE_CNAME),
+                        ("web.example.com.".to_string(), TYPE_A)],
+                   owners_and_types(&result.answer));
+
+        let result = zone().lookup(&name("www.example.com."), TYPE_CNAME);
+        assert_eq!(vec![("www.example.com.".to_string(), TYPE_CNAME)],
+                   owners_and_types(&result.answer));
+
+        let result = zone().lookup(&name("ext.example.com."), TYPE_A);
+        assert_eq!(RC_OK, result.rcode);
+        assert_eq!(1, result.answer.len());
+        assert!(result.authority.is_empty());
+
+        let result = zone().lookup(&name("loop1.example.com."), TYPE_A);
+        assert_eq!(RC_SERVER_ERROR, result.rcode);
+    }
+
+    #[test]
+    fn negative_answers() {
+        let result = zone().lookup(&name("nope.example.com."), TYPE_A);
+        assert_eq!(RC_NAME_ERROR, result.rcode);
+        assert!(result.answer.is_empty());
+        assert_eq!(vec![("example.com.".to_string(), TYPE_SOA)],
+                   owners_and_types(&result.authority));
+        // Negative TTL is the SOA minimum here.
+        assert_eq!(60, result.authority[0].ttl);
+
+        let result = zone().lookup(&name("web.example.com."), TYPE_AAAA);
+        assert_eq!(RC_OK, result.rcode);
+        assert!(result.answer.is_empty());
+        assert_eq!(1, result.authority.len());
+
+        // Empty non-terminal.
+        let result = zone().lookup(&name("b.c.example.com."), TYPE_A);
+        assert_eq!(RC_OK, result.rcode);
+        assert!(result.answer.is_empty());
+    }
+
+    #[test]
+    fn referral() {
+        let result = zone().lookup(&name("host.deeper.sub.example.com."), TYPE_A);
+        assert_eq!(RC_OK, result.rcode);
+        assert!(!result.authoritative);
+        assert!(result.is_referral());
+        assert!(result.answer.is_empty());
+        assert_eq!(vec![("sub.example.com.".to_string(), TYPE_NS),
+                        ("sub.example.com.".to_string(), TYPE_NS)],
+                   owners_and_types(&result.authority));
+        assert_eq!(vec![("ns.sub.example.com.".to_string(), TYPE_A)],
+                   owners_and_types(&result.additional));
+
+        // The DS lives on the parent side of the cut.
+        let result = zone().lookup(&name("sub.example.com."), TYPE_DS);
+        assert!(result.authoritative);
+        assert_eq!(vec![("sub.example.com.".to_string(), TYPE_DS)],
+                   owners_and_types(&result.answer));
+    }
+
+    #[test]
+    fn wildcards() {
+        let result = zone().lookup(&name("anything.wild.example.com."), TYPE_TXT);
+        assert_eq!(RC_OK, result.rcode);
+        assert_eq!(Record::new(name("anything.wild.example.com."),
+                               CLASS_IN,
+                               300,
+                               RData::Txt(vec![b"wildcard".to_vec()])),
+                   result.answer[0]);
+
+        let result = zone().lookup(&name("x.wild.example.com."), TYPE_MX);
+        assert_eq!(vec![("mail.example.com.".to_string(), TYPE_A),
+                        ("mail.example.com.".to_string(), TYPE_AAAA)],
+                   owners_and_types(&result.additional));
+
+        // Existing names are not covered by the wildcard.
+        let result = zone().lookup(&name("exists.wild.example.com."), TYPE_TXT);
+        assert!(result.answer.is_empty());
+        // Nor are names below them.
+        let result = zone().lookup(&name("a.exists.wild.example.com."), TYPE_TXT);
+        assert_eq!(RC_NAME_ERROR, result.rcode);
+    }
+
+    #[test]
+    fn any_and_out_of_zone() {
+        let result = zone().lookup(&name("mail.example.com."), TYPE_ANY);
+        assert_eq!(2, result.answer.len());
+
+        let result = zone().lookup(&name("example.net."), TYPE_A);
+        assert_eq!(RC_REFUSED, result.rcode);
+    }
+
+    #[test]
+    fn records_in_canonical_order() {
+        let records = zone().records();
+        let mut sorted: Vec<Name> = records.iter().map(|r| r.name.clone()).collect();
+        sorted.sort();
+        assert_eq!(sorted, records.iter().map(|r| r.name.clone()).collect::<Vec<Name>>());
+        assert_eq!(19, records.len());
+        assert_eq!(Some(1), zone().serial());
+    }
+
+    #[test]
+    fn remove_prunes_empty_names() {
+        let mut zone = zone();
+        let record = Record::new(name("a.b.c.example.com."),
+                                 CLASS_IN,
+                                 0,
+                                 RData::Txt(vec![b"deep".to_vec()]));
+        assert!(zone.remove(&record));
+        assert!(!zone.remove(&record));
+        // No longer an empty non-terminal.
+        assert_eq!(RC_NAME_ERROR, zone.lookup(&name("b.c.example.com."), TYPE_A).rcode);
+
+        assert_eq!(vec![TYPE_A, TYPE_AAAA], zone.rtypes(&name("mail.example.com.")));
+        assert!(zone.remove_rrset(&name("mail.example.com."), TYPE_A));
+        assert!(!zone.remove_rrset(&name("mail.example.com."), TYPE_A));
+        assert!(zone.remove_rrset(&name("mail.example.com."), TYPE_AAAA));
+        assert!(zone.rtypes(&name("mail.example.com.")).is_empty());
+    }
+
+    #[test]
+    fn apply_and_replace() {
+        let mut records = zone().records();
+        records.retain(|r| r.name != name("web.example.com."));
+        for record in records.iter_mut() {
+            if let RData::Soa(ref mut soa) = record.rdata {
+                soa.serial = 2;
+            }
+        }
+        records.push(Record::new(name("new.example.com."),
+                                 CLASS_IN,
+                                 300,
+                                 RData::A("192.0.2.1".parse().unwrap())));
+        let newer = Zone::from_records(name("example.com."), records);
+        let diff = Diff::between(&zone(), &newer).unwrap();
+        assert_eq!(1, diff.removed.len());
+        assert_eq!(1, diff.added.len());
+
+        let mut applied = zone();
+        assert!(applied.apply(diff.clone()));
+        assert_eq!(newer.records(), applied.records());
+        assert_eq!(Some(2), applied.serial());
+        // Only applies on top of the serial it was made against.
+        assert!(!applied.apply(diff.clone()));
+        assert_eq!(1, applied.journal().len());
+
+        let mut replaced = zone();
+        replaced.replace(newer);
+        assert_eq!(applied.records(), replaced.records());
+        assert_eq!(vec![&diff], replaced.journal().since(1).unwrap());
+    }
+
+    #[test]
+    fn load_file() {
+        let zone = Zone::load("testdata/example.com.zone", &name("example.com.")).unwrap();
+        assert_eq!(Some(2015110801), zone.serial());
+        assert!(Zone::load("testdata/include-parent.zone", &name("example.com.")).is_err());
+    }
+
+    fn compose(result: &Lookup, len: usize) -> (Vec<u8>, bool) {
+        let mut buffer = vec![0u8; len];
+        let mut idx = MessageCursor::new(len);
+        idx.alloc(12);
+        let qname = name("example.com.");
+        QuestionMut::at(&mut idx, &mut buffer, &qname.segments(), TYPE_MX, CLASS_IN).unwrap();
+        let complete = result.write_sections(&mut idx, &mut buffer);
+        buffer.truncate(idx.tell());
+        (buffer, complete)
+    }
+
+    #[test]
+    fn write_sections() {
+        let result = zone().lookup(&name("example.com."), TYPE_MX);
+        let (message, complete) = compose(&result, 512);
+        assert!(complete);
+        let header = Header::at(&message[..]);
+        assert_eq!(Some(true), header.aa());
+        assert_eq!(Some(false), header.tc());
+        assert_eq!((Some(1), Some(0), Some(2)), (header.an(), header.ns(), header.ar()));
+
+        // The answer's owner is compressed against the question.
+        let answer = Resource::from_message(&message[..], 12 + 13 + 4).unwrap();
+        assert_eq!(12 + 13 + 4 + 2 + 10 + 20, answer.end_offset());
+        assert_eq!(result.answer[0], Record::from_resource(&message, &answer).unwrap());
+
+        // Too small for everything: additional data is dropped silently.
+        let (message, complete) = compose(&result, answer.end_offset() + 4);
+        assert!(complete);
+        let header = Header::at(&message[..]);
+        assert_eq!((Some(1), Some(0)), (header.an(), header.ar()));
+        assert_eq!(Some(false), header.tc());
+
+        // Too small for the answer.
+        let (message, complete) = compose(&result, answer.end_offset() - 1);
+        assert!(!complete);
+        let header = Header::at(&message[..]);
+        assert_eq!(Some(0), header.an());
+        assert_eq!(Some(true), header.tc());
+        assert_eq!(12 + 13 + 4, message.len());
+    }
+}
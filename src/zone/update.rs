@@ -0,0 +1,451 @@
+//! Dynamic update (RFC 2136): checking prerequisites and applying changes to
+//! a zone all at once.
+
+use std::collections::HashMap;
+use protocol::{Name, RData, Record, Serial};
+use protocol::{RC_FORMAT_ERROR, RC_NAME_ERROR, RC_NOT_ZONE, RC_NX_RRSET, RC_YX_DOMAIN,
+               RC_YX_RRSET};
+use protocol::rdata::{CLASS_ANY, CLASS_NONE, TYPE_ANY, TYPE_CNAME, TYPE_NS, TYPE_OPT, TYPE_SOA,
+                      TYPE_ZONEMD};
+use super::journal::Diff;
+use super::store::Zone;
+use super::zonemd::refresh_zonemd;
+
+// Types that only make sense in queries or as message options.
+fn is_meta(rtype: u16) -> bool {
+    rtype == TYPE_OPT || (rtype >= 128 && rtype <= 255)
+}
+
+// RDLENGTH zero, which is how an UPDATE says "no rdata".
+fn is_empty(rdata: &RData) -> bool {
+    match *rdata {
+        RData::Unknown { ref data, .. } => data.is_empty(),
+        _ => false,
+    }
+}
+
+/// Evaluates the prerequisite section against the zone (RFC 2136 Section
+/// 3.2), returning the RCODE of the first one that fails.
+pub fn check_prerequisites(zone: &Zone, prerequisites: &[Record]) -> Result<(), u8> {
+    // RRsets that must exist with exactly these records.
+    let mut exact: HashMap<(Name, u16), Vec<RData>> = HashMap::new();
+    for record in prerequisites {
+        if record.ttl != 0 {
+            return Err(RC_FORMAT_ERROR);
+        }
+        if !record.name.is_subdomain_of(zone.origin()) {
+            return Err(RC_NOT_ZONE);
+        }
+        let rtype = record.rtype();
+        let in_use = !zone.rtypes(&record.name).is_empty();
+        if record.class == CLASS_ANY || record.class == CLASS_NONE {
+            if !is_empty(&record.rdata) {
+                return Err(RC_FORMAT_ERROR);
+            }
+            let exists = if rtype == TYPE_ANY {
+                in_use
+            } else {
+                zone.rrset(&record.name, rtype).is_some()
+            };
+            match (record.class == CLASS_ANY, rtype == TYPE_ANY, exists) {
+                (true, true, false) => return Err(RC_NAME_ERROR),
+                (true, false, false) => return Err(RC_NX_RRSET),
+                (false, true, true) => return Err(RC_YX_DOMAIN),
+                (false, false, true) => return Err(RC_YX_RRSET),
+                _ => {}
+            }
+        } else if record.class == zone.class() {
+            let rdatas = exact.entry((record.name.clone(), rtype)).or_insert(Vec::new());
+            if !rdatas.contains(&record.rdata) {
+                rdatas.push(record.rdata.clone());
+            }
+        } else {
+            return Err(RC_FORMAT_ERROR);
+        }
+    }
+    for (&(ref name, rtype), rdatas) in &exact {
+        let matches = match zone.rrset(name, rtype) {
+            Some(rrset) => {
+                rrset.rdatas.len() == rdatas.len() &&
+                rrset.rdatas.iter().all(|rdata| rdatas.contains(rdata))
+            }
+            None => false,
+        };
+        if !matches {
+            return Err(RC_NX_RRSET);
+        }
+    }
+    Ok(())
+}
+
+/// Checks the update section for records that could not be applied (RFC
+/// 2136 Section 3.4.1), so that nothing is changed unless all of it can be.
+pub fn prescan(zone: &Zone, updates: &[Record]) -> Result<(), u8> {
+    for record in updates {
+        if !record.name.is_subdomain_of(zone.origin()) {
+            return Err(RC_NOT_ZONE);
+        }
+        let rtype = record.rtype();
+        let valid = if record.class == zone.class() {
+            !is_meta(rtype) && !is_empty(&record.rdata)
+        } else if record.class == CLASS_ANY {
+            record.ttl == 0 && is_empty(&record.rdata) &&
+            (rtype == TYPE_ANY || !is_meta(rtype))
+        } else if record.class == CLASS_NONE {
+            record.ttl == 0 && !is_meta(rtype)
+        } else {
+            false
+        };
+        if !valid {
+            return Err(RC_FORMAT_ERROR);
+        }
+    }
+    Ok(())
+}
+
+// The records of one RRset, as the zone holds them.
+fn rrset_records(zone: &Zone, name: &Name, rtype: u16) -> Vec<Record> {
+    match zone.rrset(name, rtype) {
+        Some(rrset) => {
+            rrset.rdatas
+                 .iter()
+                 .map(|rdata| Record::new(name.clone(), zone.class(), rrset.ttl, rdata.clone()))
+                 .collect()
+        }
+        None => Vec::new(),
+    }
+}
+
+/// What an update has removed from and added to a zone so far, the SOA
+/// aside, in the shape of a journal diff. A change undoing an earlier one
+/// cancels it out.
+#[derive(Debug, Default)]
+struct Changes {
+    removed: Vec<Record>,
+    added: Vec<Record>,
+}
+
+impl Changes {
+    fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty()
+    }
+
+    fn added(&mut self, record: Record) {
+        if record.rtype() == TYPE_SOA {
+            return;
+        }
+        match self.removed.iter().position(|r| *r == record) {
+            Some(i) => {
+                self.removed.remove(i);
+            }
+            None => self.added.push(record),
+        }
+    }
+
+    fn removed(&mut self, record: Record) {
+        if record.rtype() == TYPE_SOA {
+            return;
+        }
+        match self.added.iter().position(|r| *r == record) {
+            Some(i) => {
+                self.added.remove(i);
+            }
+            None => self.removed.push(record),
+        }
+    }
+
+    fn insert(&mut self, zone: &mut Zone, record: &Record) {
+        if zone.insert(record.clone()) {
+            self.added(record.clone());
+        }
+    }
+
+    /// Removes one record, whatever TTL it is given.
+    fn remove(&mut self, zone: &mut Zone, record: &Record) {
+        let ttl = match zone.rrset(&record.name, record.rtype()) {
+            Some(rrset) => rrset.ttl,
+            None => return,
+        };
+        let record = Record::new(record.name.clone(), zone.class(), ttl, record.rdata.clone());
+        if zone.remove(&record) {
+            self.removed(record);
+        }
+    }
+
+    fn remove_rrset(&mut self, zone: &mut Zone, name: &Name, rtype: u16) {
+        for record in rrset_records(zone, name, rtype) {
+            self.removed(record);
+        }
+        zone.remove_rrset(name, rtype);
+    }
+}
+
+fn add(zone: &mut Zone, changes: &mut Changes, record: &Record) {
+    let rtype = record.rtype();
+    let origin = zone.origin().clone();
+    if rtype == TYPE_SOA {
+        // Only a newer serial replaces the SOA.
+        let newer = match (zone.serial(), &record.rdata) {
+            (Some(current), &RData::Soa(ref soa)) => Serial(soa.serial) > Serial(current),
+            _ => false,
+        };
+        if record.name == origin && newer {
+            zone.remove_rrset(&origin, TYPE_SOA);
+            zone.insert(record.clone());
+        }
+        return;
+    }
+    let rtypes = zone.rtypes(&record.name);
+    if rtype == TYPE_CNAME {
+        // A CNAME may not join other data, and replaces an existing one.
+        if rtypes.iter().any(|&t| t != TYPE_CNAME) {
+            return;
+        }
+        changes.remove_rrset(zone, &record.name, TYPE_CNAME);
+    } else if rtypes.contains(&TYPE_CNAME) {
+        return;
+    }
+    // The RRset takes on the TTL of the latest addition.
+    let existing = match zone.rrset(&record.name, rtype) {
+        Some(rrset) if rrset.ttl != record.ttl => Some(rrset.rdatas.clone()),
+        _ => None,
+    };
+    if let Some(rdatas) = existing {
+        changes.remove_rrset(zone, &record.name, rtype);
+        for rdata in rdatas {
+            let record = Record::new(record.name.clone(), record.class, record.ttl, rdata);
+            changes.insert(zone, &record);
+        }
+    }
+    changes.insert(zone, record);
+}
+
+fn delete(zone: &mut Zone, changes: &mut Changes, record: &Record) {
+    let rtype = record.rtype();
+    let at_apex = record.name == *zone.origin();
+    if record.class == CLASS_ANY {
+        let rtypes = if rtype == TYPE_ANY {
+            zone.rtypes(&record.name)
+        } else {
+            vec![rtype]
+        };
+        for rtype in rtypes {
+            // The apex keeps its SOA and NS records.
+            if !(at_apex && (rtype == TYPE_SOA || rtype == TYPE_NS)) {
+                changes.remove_rrset(zone, &record.name, rtype);
+            }
+        }
+        return;
+    }
+    if rtype == TYPE_SOA {
+        return;
+    }
+    if at_apex && rtype == TYPE_NS &&
+       zone.rrset(&record.name, TYPE_NS).map_or(false, |ns| ns.rdatas.len() == 1) {
+        return;
+    }
+    changes.remove(zone, record);
+}
+
+/// Applies an UPDATE to the zone: the prerequisites are checked, then
+/// either every change is made or none is. A change to the zone bumps the
+/// SOA serial, unless the update itself raised it, and is recorded in the
+/// journal, as are new ZONEMD digests if the zone has them. Returns
+/// whether the zone changed, or the RCODE to answer with.
+///
+/// The changes are made in place and journaled as they are made, so an
+/// update costs in proportion to its size, apart from recomputing any
+/// ZONEMD digests. They are held in memory only: the zone file is left as
+/// it was.
+pub fn apply_update(zone: &mut Zone,
+                    prerequisites: &[Record],
+                    updates: &[Record])
+    -> Result<bool, u8> {
+    try!(check_prerequisites(zone, prerequisites));
+    try!(prescan(zone, updates));
+    // Past the prescan nothing can fail, so nothing is left half done.
+    let (from, serial) = (zone.soa_record(), zone.serial());
+    let mut changes = Changes::default();
+    for record in updates {
+        if record.class == zone.class() {
+            add(zone, &mut changes, record);
+        } else {
+            delete(zone, &mut changes, record);
+        }
+    }
+    if changes.is_empty() && zone.soa_record() == from {
+        return Ok(false);
+    }
+    let origin = zone.origin().clone();
+    if zone.serial() == serial {
+        if let Some(mut soa) = zone.soa_record() {
+            if let RData::Soa(ref mut rdata) = soa.rdata {
+                rdata.serial = Serial(rdata.serial).add(1).unwrap().0;
+            }
+            zone.remove_rrset(&origin, TYPE_SOA);
+            zone.insert(soa);
+        }
+    }
+    let digests = rrset_records(zone, &origin, TYPE_ZONEMD);
+    refresh_zonemd(zone);
+    let refreshed = rrset_records(zone, &origin, TYPE_ZONEMD);
+    if refreshed != digests {
+        for record in digests {
+            changes.removed(record);
+        }
+        for record in refreshed {
+            changes.added(record);
+        }
+    }
+    match (from, zone.soa_record()) {
+        (Some(from), Some(to)) => {
+            zone.journal_mut().push(Diff {
+                from: from,
+                to: to,
+                removed: changes.removed,
+                added: changes.added,
+            })
+        }
+        _ => zone.journal_mut().clear(),
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use protocol::{Name, RData, Record};
+    use protocol::{RC_FORMAT_ERROR, RC_NAME_ERROR, RC_NOT_ZONE, RC_NX_RRSET, RC_YX_DOMAIN,
+                   RC_YX_RRSET};
+    use protocol::rdata::*;
+    use zone::{HASH_SHA384, Zone, ZoneParser, set_zonemd, verify_zonemd};
+
+    fn name(text: &str) -> Name {
+        Name::parse(text, None).unwrap()
+    }
+
+    fn zone() -> Zone {
+        let text = "$TTL 300\n\
+                    @ SOA ns hostmaster 10 3600 600 86400 60\n\
+                    @ NS ns\n\
+                    ns A 192.0.2.53\n\
+                    www A 192.0.2.80\n\
+                    www A 192.0.2.81\n\
+                    ftp CNAME www\n";
+        let records = ZoneParser::new(Cursor::new(text.as_bytes().to_vec()),
+                                      "test.zone",
+                                      Some(name("example.com.")))
+                          .map(|r| r.unwrap());
+        Zone::from_records(name("example.com."), records)
+    }
+
+    fn a(owner: &str, class: u16, ttl: u32, address: &str) -> Record {
+        Record::new(name(owner), class, ttl, RData::A(address.parse().unwrap()))
+    }
+
+    fn empty(owner: &str, class: u16, rtype: u16) -> Record {
+        Record::new(name(owner),
+                    class,
+                    0,
+                    RData::Unknown {
+                        rtype: rtype,
+                        data: Vec::new(),
+                    })
+    }
+
+    #[test]
+    fn prerequisites() {
+        let zone = zone();
+        let check = |records: Vec<Record>| check_prerequisites(&zone, &records);
+        assert_eq!(Ok(()), check(vec![empty("www.example.com.", CLASS_ANY, TYPE_ANY)]));
+        assert_eq!(Err(RC_NAME_ERROR),
+                   check(vec![empty("new.example.com.", CLASS_ANY, TYPE_ANY)]));
+        assert_eq!(Err(RC_NX_RRSET),
+                   check(vec![empty("www.example.com.", CLASS_ANY, TYPE_AAAA)]));
+        assert_eq!(Err(RC_YX_DOMAIN),
+                   check(vec![empty("www.example.com.", CLASS_NONE, TYPE_ANY)]));
+        assert_eq!(Err(RC_YX_RRSET),
+                   check(vec![empty("www.example.com.", CLASS_NONE, TYPE_A)]));
+        assert_eq!(Ok(()), check(vec![empty("new.example.com.", CLASS_NONE, TYPE_ANY)]));
+        // Value dependent: the whole RRset must match.
+        assert_eq!(Ok(()),
+                   check(vec![a("www.example.com.", CLASS_IN, 0, "192.0.2.81"),
+                              a("www.example.com.", CLASS_IN, 0, "192.0.2.80")]));
+        assert_eq!(Err(RC_NX_RRSET),
+                   check(vec![a("www.example.com.", CLASS_IN, 0, "192.0.2.80")]));
+        assert_eq!(Err(RC_FORMAT_ERROR),
+                   check(vec![a("www.example.com.", CLASS_IN, 300, "192.0.2.80")]));
+        assert_eq!(Err(RC_NOT_ZONE),
+                   check(vec![empty("www.example.net.", CLASS_ANY, TYPE_ANY)]));
+    }
+
+    #[test]
+    fn updates() {
+        let original = zone();
+        let mut zone = original.clone();
+        let updates = vec![a("new.example.com.", CLASS_IN, 60, "192.0.2.1"),
+                           empty("www.example.com.", CLASS_ANY, TYPE_A),
+                           a("ns.example.com.", CLASS_NONE, 0, "192.0.2.53"),
+                           // Ignored: the apex keeps its SOA and NS.
+                           empty("example.com.", CLASS_ANY, TYPE_ANY),
+                           // Ignored: a CNAME cannot have other data.
+                           a("ftp.example.com.", CLASS_IN, 60, "192.0.2.2")];
+        assert_eq!(Ok(true), apply_update(&mut zone, &[], &updates));
+        assert_eq!(Some(11), zone.serial());
+        assert_eq!(1, zone.journal().len());
+        let records: Vec<String> = zone.records().iter().map(|r| r.to_string()).collect();
+        assert_eq!(vec!["example.com. 300 IN NS ns.example.com.",
+                        "example.com. 300 IN SOA ns.example.com. hostmaster.example.com. 11 \
+                         3600 600 86400 60",
+                        "ftp.example.com. 300 IN CNAME www.example.com.",
+                        "new.example.com. 60 IN A 192.0.2.1"],
+                   records);
+        // The journal holds just what changed.
+        let diff = zone.journal().since(10).unwrap()[0].clone();
+        assert_eq!((3, 1), (diff.removed.len(), diff.added.len()));
+        let mut copy = original.clone();
+        assert!(copy.apply(diff));
+        assert_eq!(zone.records(), copy.records());
+
+        // Nothing to do changes nothing.
+        assert_eq!(Ok(false),
+                   apply_update(&mut zone, &[], &[empty("www.example.com.", CLASS_ANY, TYPE_A)]));
+        assert_eq!(Some(11), zone.serial());
+        // Nor does a change undone in the same update.
+        let updates = vec![a("www.example.com.", CLASS_IN, 60, "192.0.2.80"),
+                           a("www.example.com.", CLASS_NONE, 0, "192.0.2.80")];
+        assert_eq!(Ok(false), apply_update(&mut zone, &[], &updates));
+        assert_eq!(1, zone.journal().len());
+    }
+
+    #[test]
+    fn digests() {
+        let mut zone = zone();
+        set_zonemd(&mut zone, &[HASH_SHA384]);
+        let original = zone.clone();
+        let updates = vec![a("new.example.com.", CLASS_IN, 60, "192.0.2.1")];
+        assert_eq!(Ok(true), apply_update(&mut zone, &[], &updates));
+        assert_eq!(Ok(true), verify_zonemd(&zone));
+        let diff = zone.journal().since(10).unwrap()[0].clone();
+        assert_eq!((1, 2), (diff.removed.len(), diff.added.len()));
+        let mut copy = original;
+        assert!(copy.apply(diff));
+        assert_eq!(zone.records(), copy.records());
+    }
+
+    #[test]
+    fn atomic() {
+        let mut zone = zone();
+        let before = zone.records();
+        // The second record is malformed, so the first is not applied either.
+        let updates = vec![a("new.example.com.", CLASS_IN, 60, "192.0.2.1"),
+                           a("www.example.com.", CLASS_ANY, 60, "192.0.2.80")];
+        assert_eq!(Err(RC_FORMAT_ERROR), apply_update(&mut zone, &[], &updates));
+        let prerequisites = vec![empty("www.example.com.", CLASS_NONE, TYPE_ANY)];
+        assert_eq!(Err(RC_YX_DOMAIN),
+                   apply_update(&mut zone, &prerequisites, &updates[..1]));
+        assert_eq!(before, zone.records());
+        assert!(zone.journal().is_empty());
+    }
+}
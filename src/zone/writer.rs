@@ -0,0 +1,204 @@
+//! Serializes records back to master file format.
+//!
+//! The output is canonical: records are sorted, grouped by owner and laid
+//! out in fixed-width columns, so that writing the same set of records twice
+//! produces identical text and a one record change is a one line diff.
+
+use std::cmp::Ordering;
+use std::io::{self, Write};
+use protocol::{Name, Record};
+use protocol::rdata::{TYPE_SOA, class_name, type_name};
+
+const OWNER_WIDTH: usize = 24;
+const TTL_WIDTH: usize = 8;
+const CLASS_WIDTH: usize = 4;
+const TYPE_WIDTH: usize = 8;
+
+pub struct ZoneWriter {
+    origin: Option<Name>,
+    default_ttl: Option<u32>,
+}
+
+impl ZoneWriter {
+    pub fn new() -> ZoneWriter {
+        ZoneWriter {
+            origin: None,
+            default_ttl: None,
+        }
+    }
+
+    /// Emits `$ORIGIN` and writes names at or below it relative to it.
+    pub fn set_origin(&mut self, origin: Name) -> &mut Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    /// Emits `$TTL` and leaves out TTLs that match it.
+    pub fn set_default_ttl(&mut self, ttl: u32) -> &mut Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W, records: &[Record]) -> io::Result<()> {
+        let origin = self.origin.as_ref();
+        if let Some(origin) = origin {
+            try!(writeln!(out, "$ORIGIN {}", origin));
+        }
+        if let Some(ttl) = self.default_ttl {
+            try!(writeln!(out, "$TTL {}", ttl));
+        }
+
+        let mut sorted: Vec<&Record> = records.iter().collect();
+        sorted.sort_by(|a, b| canonical_order(a, b));
+        sorted.dedup();
+
+        let mut previous: Option<&Name> = None;
+        for record in sorted {
+            let owner = if previous == Some(&record.name) {
+                String::new()
+            } else {
+                record.name.relative(origin).to_string()
+            };
+            previous = Some(&record.name);
+            let ttl = if self.default_ttl == Some(record.ttl) {
+                String::new()
+            } else {
+                record.ttl.to_string()
+            };
+            let mut line = String::with_capacity(80);
+            push_column(&mut line, &owner, OWNER_WIDTH);
+            push_column(&mut line, &ttl, TTL_WIDTH);
+            push_column(&mut line, &class_name(record.class), CLASS_WIDTH);
+            push_column(&mut line, &type_name(record.rtype()), TYPE_WIDTH);
+            line.push_str(&record.rdata.presentation(origin).to_string());
+            try!(writeln!(out, "{}", line));
+        }
+        Ok(())
+    }
+
+    pub fn write_to_string(&self, records: &[Record]) -> String {
+        let mut out = Vec::new();
+        self.write(&mut out, records).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+}
+
+/// Pads `text` to `width`, always leaving at least one separating space.
+fn push_column(line: &mut String, text: &str, width: usize) {
+    line.push_str(text);
+    let pad = if text.len() < width {
+        width - text.len()
+    } else {
+        1
+    };
+    for _ in 0..pad {
+        line.push(' ');
+    }
+}
+
+/// Owners in canonical order, the SOA first at its owner, then by class,
+/// type and canonical rdata (RFC 4034 Section 6.3).
+fn canonical_order(a: &Record, b: &Record) -> Ordering {
+    a.name
+     .cmp(&b.name)
+     .then((a.rtype() != TYPE_SOA).cmp(&(b.rtype() != TYPE_SOA)))
+     .then(a.class.cmp(&b.class))
+     .then(a.rtype().cmp(&b.rtype()))
+     .then_with(|| a.rdata.to_canonical_wire().cmp(&b.rdata.to_canonical_wire()))
+     .then(a.ttl.cmp(&b.ttl))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use protocol::{Name, RData, Record};
+    use protocol::rdata::CLASS_IN;
+    use zone::ZoneParser;
+
+    fn name(text: &str) -> Name {
+        Name::parse(text, None).unwrap()
+    }
+
+    fn parse(text: &str) -> Vec<Record> {
+        ZoneParser::new(Cursor::new(text.as_bytes().to_vec()),
+                        "test.zone",
+                        Some(name("example.com.")))
+            .map(|r| r.unwrap())
+            .collect()
+    }
+
+    const ZONE: &'static str = "$TTL 3600\n\
+                                www A 192.0.2.2\n\
+                                @ MX 10 mail\n\
+                                www A 192.0.2.1\n\
+                                @ NS ns1.example.net.\n\
+                                @ SOA ns1 hostmaster 1 7200 3600 1209600 300\n\
+                                txt 300 TXT \"quoted \\\"text\\\"\" \"semi;colon\"\n\
+                                odd\\.label TYPE65280 \\# 2 beef\n\
+                                Mail A 192.0.2.25\n";
+
+    #[test]
+    fn canonical_output() {
+        let mut writer = ZoneWriter::new();
+        writer.set_origin(name("example.com.")).set_default_ttl(3600);
+        let text = writer.write_to_string(&parse(ZONE));
+        let expected = ["$ORIGIN example.com.",
+                        "$TTL 3600",
+                        "@                               IN  SOA     ns1 hostmaster 1 7200 3600 \
+                         1209600 300",
+                        "                                IN  NS      ns1.example.net.",
+                        "                                IN  MX      10 mail",
+                        "Mail                            IN  A       192.0.2.25",
+                        "odd\\.label                      IN  TYPE65280 \\# 2 beef",
+                        "txt                     300     IN  TXT     \"quoted \\\"text\\\"\" \
+                         \"semi;colon\"",
+                        "www                             IN  A       192.0.2.1",
+                        "                                IN  A       192.0.2.2",
+                        ""];
+        assert_eq!(expected.join("\n"), text);
+    }
+
+    #[test]
+    fn round_trip() {
+        let records = parse(ZONE);
+        let mut writer = ZoneWriter::new();
+        writer.set_origin(name("example.com."));
+        let text = writer.write_to_string(&records);
+        let mut reparsed = parse(&text);
+        let mut original = records.clone();
+        reparsed.sort_by(|a, b| canonical_order(a, b));
+        original.sort_by(|a, b| canonical_order(a, b));
+        assert_eq!(original, reparsed);
+    }
+
+    #[test]
+    fn stable_regardless_of_input_order() {
+        let mut records = parse(ZONE);
+        let writer = ZoneWriter::new();
+        let first = writer.write_to_string(&records);
+        records.reverse();
+        assert_eq!(first, writer.write_to_string(&records));
+    }
+
+    #[test]
+    fn absolute_without_origin() {
+        let records = vec![Record::new(name("a.example."),
+                                       CLASS_IN,
+                                       60,
+                                       RData::Cname(name("b.example.")))];
+        assert_eq!("a.example.              60      IN  CNAME   b.example.\n",
+                   ZoneWriter::new().write_to_string(&records));
+    }
+
+    #[test]
+    fn duplicates_removed() {
+        let record = Record::new(name("a.example."),
+                                 CLASS_IN,
+                                 60,
+                                 RData::A("192.0.2.1".parse().unwrap()));
+        let text = ZoneWriter::new().write_to_string(&[record.clone(), record]);
+        assert_eq!(1, text.lines().count());
+    }
+}
@@ -0,0 +1,295 @@
+//! Zone digests (RFC 8976): a ZONEMD record at the apex holds a hash of
+//! every other record of the zone, so a copy can be checked end to end no
+//! matter how it was transferred.
+
+use std::error::Error;
+use std::fmt;
+use crypto::{Digest, Sha384, Sha512};
+use protocol::{RData, Record};
+use protocol::rdata::{TYPE_RRSIG, TYPE_ZONEMD};
+use super::store::Zone;
+
+/// The only digest scheme: the zone hashed as one stream of records.
+pub const SCHEME_SIMPLE: u8 = 1;
+pub const HASH_SHA384: u8 = 1;
+pub const HASH_SHA512: u8 = 2;
+
+const HASH_NAMES: &'static [(u8, &'static str)] = &[(HASH_SHA384, "SHA384"),
+                                                    (HASH_SHA512, "SHA512")];
+
+fn is_supported(hash_algorithm: u8) -> bool {
+    HASH_NAMES.iter().any(|&(n, _)| n == hash_algorithm)
+}
+
+/// The hash algorithm of a mnemonic such as `SHA384`.
+pub fn hash_algorithm_from_name(text: &str) -> Option<u8> {
+    HASH_NAMES.iter().find(|&&(_, name)| name.eq_ignore_ascii_case(text)).map(|&(n, _)| n)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZonemdError {
+    /// The digests are for another version of the zone.
+    Serial,
+    /// Two digests of the same scheme and hash algorithm.
+    Duplicate,
+    /// The zone does not match its digest.
+    Mismatch,
+}
+
+impl fmt::Display for ZonemdError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.description())
+    }
+}
+
+impl Error for ZonemdError {
+    fn description(&self) -> &str {
+        match *self {
+            ZonemdError::Serial => "ZONEMD serial does not match the SOA",
+            ZonemdError::Duplicate => "ZONEMD has two digests of the same kind",
+            ZonemdError::Mismatch => "zone does not match its ZONEMD digest",
+        }
+    }
+}
+
+// The apex ZONEMD RRset and the signature over it are left out of the
+// digest (RFC 8976 Section 3.3.1).
+fn excluded(zone: &Zone, record: &Record) -> bool {
+    if record.name != *zone.origin() {
+        return false;
+    }
+    match record.rdata {
+        RData::Zonemd { .. } => true,
+        RData::Unknown { rtype: TYPE_RRSIG, ref data } if data.len() >= 2 => {
+            (data[0] as u16) << 8 | data[1] as u16 == TYPE_ZONEMD
+        }
+        _ => false,
+    }
+}
+
+// Hashes the records in canonical form and order: owners in canonical
+// order, then types, then the rdata of each RRset sorted, less duplicates.
+fn hash<D: Digest>(zone: &Zone) -> Vec<u8> {
+    let mut hash = D::new();
+    let records: Vec<Record> = zone.records()
+                                   .into_iter()
+                                   .filter(|r| !excluded(zone, r))
+                                   .collect();
+    let mut start = 0;
+    while start < records.len() {
+        let first = &records[start];
+        let end = start +
+                  records[start..]
+                      .iter()
+                      .take_while(|r| r.name == first.name && r.rtype() == first.rtype())
+                      .count();
+        let mut rdatas: Vec<Vec<u8>> = records[start..end]
+                                           .iter()
+                                           .map(|r| r.rdata.to_canonical_wire())
+                                           .collect();
+        rdatas.sort();
+        rdatas.dedup();
+        let mut prefix = first.name.to_canonical_wire();
+        let rtype = first.rtype();
+        prefix.extend([(rtype >> 8) as u8,
+                       rtype as u8,
+                       (first.class >> 8) as u8,
+                       first.class as u8,
+                       (first.ttl >> 24) as u8,
+                       (first.ttl >> 16) as u8,
+                       (first.ttl >> 8) as u8,
+                       first.ttl as u8]
+                          .iter()
+                          .cloned());
+        for rdata in rdatas {
+            hash.update(&prefix);
+            hash.update(&[(rdata.len() >> 8) as u8, rdata.len() as u8]);
+            hash.update(&rdata);
+        }
+        start = end;
+    }
+    hash.finish()
+}
+
+/// The simple scheme digest of a zone, or None for an unknown hash
+/// algorithm.
+pub fn zone_digest(zone: &Zone, hash_algorithm: u8) -> Option<Vec<u8>> {
+    match hash_algorithm {
+        HASH_SHA384 => Some(hash::<Sha384>(zone)),
+        HASH_SHA512 => Some(hash::<Sha512>(zone)),
+        _ => None,
+    }
+}
+
+/// Replaces the apex ZONEMD RRset with digests of the zone as it is now,
+/// one per hash algorithm. Does nothing to a zone without an SOA.
+pub fn set_zonemd(zone: &mut Zone, hash_algorithms: &[u8]) {
+    let soa = match zone.soa_record() {
+        Some(soa) => soa,
+        None => return,
+    };
+    let origin = zone.origin().clone();
+    zone.remove_rrset(&origin, TYPE_ZONEMD);
+    let serial = zone.serial().unwrap();
+    let mut records = Vec::new();
+    for &hash_algorithm in hash_algorithms {
+        if let Some(digest) = zone_digest(zone, hash_algorithm) {
+            records.push(Record::new(origin.clone(),
+                                     soa.class,
+                                     soa.ttl,
+                                     RData::Zonemd {
+                                         serial: serial,
+                                         scheme: SCHEME_SIMPLE,
+                                         hash_algorithm: hash_algorithm,
+                                         digest: digest,
+                                     }));
+        }
+    }
+    for record in records {
+        zone.insert(record);
+    }
+}
+
+/// Recomputes the digests of a zone that has them, after it changed.
+pub fn refresh_zonemd(zone: &mut Zone) {
+    let algorithms: Vec<u8> = match zone.rrset(zone.origin(), TYPE_ZONEMD) {
+        Some(rrset) => {
+            rrset.rdatas
+                 .iter()
+                 .filter_map(|rdata| {
+                     match *rdata {
+                         RData::Zonemd { scheme: SCHEME_SIMPLE, hash_algorithm, .. } => {
+                             Some(hash_algorithm)
+                         }
+                         _ => None,
+                     }
+                 })
+                 .collect()
+        }
+        None => return,
+    };
+    set_zonemd(zone, &algorithms);
+}
+
+/// Checks a zone against its ZONEMD records (RFC 8976 Section 4). Returns
+/// whether a digest was checked: false when the zone has none of a kind
+/// supported.
+pub fn verify_zonemd(zone: &Zone) -> Result<bool, ZonemdError> {
+    let rdatas = match zone.rrset(zone.origin(), TYPE_ZONEMD) {
+        Some(rrset) => &rrset.rdatas,
+        None => return Ok(false),
+    };
+    let mut supported = Vec::new();
+    for rdata in rdatas {
+        if let RData::Zonemd { serial, scheme, hash_algorithm, ref digest } = *rdata {
+            if scheme != SCHEME_SIMPLE || !is_supported(hash_algorithm) {
+                continue;
+            }
+            if supported.iter().any(|&(_, alg, _)| alg == hash_algorithm) {
+                return Err(ZonemdError::Duplicate);
+            }
+            supported.push((serial, hash_algorithm, digest));
+        }
+    }
+    if supported.is_empty() {
+        return Ok(false);
+    }
+    let mut error = ZonemdError::Serial;
+    for (serial, hash_algorithm, digest) in supported {
+        if Some(serial) != zone.serial() {
+            continue;
+        }
+        if zone_digest(zone, hash_algorithm).as_ref() == Some(digest) {
+            return Ok(true);
+        }
+        error = ZonemdError::Mismatch;
+    }
+    Err(error)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use protocol::{Name, RData, Record, hex_decode};
+    use protocol::rdata::{CLASS_IN, TYPE_ZONEMD};
+    use zone::{Zone, ZoneParser};
+
+    // RFC 8976 Appendix A.1.
+    const SIMPLE: &'static str = "\
+example.      86400  IN  SOA     ns1 admin 2018031900 1800 900 604800 86400
+              86400  IN  NS      ns1
+              86400  IN  NS      ns2
+              86400  IN  ZONEMD  2018031900 1 1 (
+                                 c68090d90a7aed716bc459f9340e3d7c1370d4d24b7e2fc3
+                                 a1ddc0b9a87153b9a9713b3c9ae5cc27777f98b8e730044c )
+ns1           3600   IN  A       203.0.113.63
+NS2           3600   IN  AAAA    2001:db8::63
+";
+
+    fn zone(text: &str) -> Zone {
+        let origin = Name::parse("example.", None).unwrap();
+        let records = ZoneParser::new(Cursor::new(text.as_bytes().to_vec()),
+                                      "example.zone",
+                                      Some(origin.clone()))
+                          .map(|r| r.unwrap());
+        Zone::from_records(origin, records)
+    }
+
+    #[test]
+    fn rfc8976_example() {
+        let mut zone = zone(SIMPLE);
+        assert_eq!(Ok(true), verify_zonemd(&zone));
+        assert_eq!(hex_decode("c68090d90a7aed716bc459f9340e3d7c1370d4d24b7e2fc3a1ddc0b9a87153b9\
+                               a9713b3c9ae5cc27777f98b8e730044c"),
+                   zone_digest(&zone, HASH_SHA384));
+
+        // Any change to the records breaks it.
+        zone.insert(Record::new(Name::parse("ns3.example.", None).unwrap(),
+                                CLASS_IN,
+                                3600,
+                                RData::A("203.0.113.64".parse().unwrap())));
+        assert_eq!(Err(ZonemdError::Mismatch), verify_zonemd(&zone));
+        refresh_zonemd(&mut zone);
+        assert_eq!(Ok(true), verify_zonemd(&zone));
+        assert_eq!(1, zone.rrset(zone.origin(), TYPE_ZONEMD).unwrap().rdatas.len());
+    }
+
+    #[test]
+    fn generating_and_verifying() {
+        let mut zone = zone(&SIMPLE.replace("ZONEMD  2018031900", "ZONEMD  2018031800"));
+        assert_eq!(Err(ZonemdError::Serial), verify_zonemd(&zone));
+
+        set_zonemd(&mut zone, &[HASH_SHA384, HASH_SHA512]);
+        assert_eq!(Ok(true), verify_zonemd(&zone));
+        let rrset = zone.rrset(zone.origin(), TYPE_ZONEMD).unwrap().clone();
+        assert_eq!(2, rrset.rdatas.len());
+        assert_eq!(86400, rrset.ttl);
+
+        // Unsupported digests are not checked; duplicate ones are refused.
+        let origin = zone.origin().clone();
+        zone.remove_rrset(&origin, TYPE_ZONEMD);
+        let unsupported = RData::Zonemd {
+            serial: 2018031900,
+            scheme: 1,
+            hash_algorithm: 240,
+            digest: vec![0; 48],
+        };
+        zone.insert(Record::new(origin.clone(), CLASS_IN, 86400, unsupported));
+        assert_eq!(Ok(false), verify_zonemd(&zone));
+        for rdata in rrset.rdatas {
+            if let RData::Zonemd { hash_algorithm: HASH_SHA384, .. } = rdata {
+                let mut other = rdata.clone();
+                if let RData::Zonemd { ref mut digest, .. } = other {
+                    digest[0] ^= 1;
+                }
+                zone.insert(Record::new(origin.clone(), CLASS_IN, 86400, rdata));
+                zone.insert(Record::new(origin.clone(), CLASS_IN, 86400, other));
+            }
+        }
+        assert_eq!(Err(ZonemdError::Duplicate), verify_zonemd(&zone));
+        assert_eq!(Some(HASH_SHA512), hash_algorithm_from_name("sha512"));
+        assert_eq!(None, hash_algorithm_from_name("SHA1"));
+    }
+}